@@ -0,0 +1,191 @@
+//! Host NUMA topology discovery from sysfs
+//!
+//! Builds a `ClusterTopology` from the nodes the host kernel reports under
+//! `/sys/devices/system/node`, so SLIT distances need not be hand-coded:
+//!
+//! - `nodeN/cpumap`: hex CPU bitmask, 32-bit groups separated by commas
+//! - `nodeN/meminfo`: `Node N MemTotal: <kB> kB`
+//! - `nodeN/distance`: distances to every online node, already in SLIT units
+//!
+//! Guest CPUs and memory are numbered contiguously in node order, whatever
+//! the host numbering: node 0 gets the first CPUs and the lowest memory.
+
+use crate::{ClusterTopology, NodeConfig};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+
+/// Where the kernel lists NUMA nodes
+const SYSFS_NODE_DIR: &str = "/sys/devices/system/node";
+
+/// Discover the host's NUMA topology
+pub fn discover_numa_topology() -> Result<ClusterTopology> {
+    discover_numa_topology_in(Path::new(SYSFS_NODE_DIR))
+}
+
+/// Discover the NUMA topology described by the `node*` directories in `root`
+fn discover_numa_topology_in(root: &Path) -> Result<ClusterTopology> {
+    let mut node_ids: Vec<u32> = fs::read_dir(root)
+        .with_context(|| format!("Failed to read {}", root.display()))?
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()
+        })
+        .collect();
+    node_ids.sort_unstable();
+    if node_ids.is_empty() {
+        return Err(anyhow!("No NUMA nodes found in {}", root.display()));
+    }
+
+    let mut nodes = Vec::with_capacity(node_ids.len());
+    let mut cpu_start = 0;
+    let mut mem_start = 0;
+    for node_id in node_ids {
+        let dir = root.join(format!("node{}", node_id));
+        let read = |file: &str| {
+            fs::read_to_string(dir.join(file))
+                .with_context(|| format!("Failed to read {}", dir.join(file).display()))
+        };
+
+        let cpu_count = parse_cpumap(&read("cpumap")?)
+            .with_context(|| format!("Bad cpumap for node {}", node_id))?;
+        let mem_size = parse_mem_total(&read("meminfo")?)
+            .with_context(|| format!("Bad meminfo for node {}", node_id))?;
+        let latencies = parse_distance(&read("distance")?)
+            .with_context(|| format!("Bad distance for node {}", node_id))?;
+
+        info!(
+            "Discovered node {}: {} CPUs, {} MiB, distances {:?}",
+            node_id,
+            cpu_count,
+            mem_size >> 20,
+            latencies
+        );
+        nodes.push(NodeConfig {
+            node_id,
+            cpu_start,
+            cpu_count,
+            mem_start,
+            mem_size,
+            latencies,
+            // sysfs only reports bandwidth read back from firmware's HMAT
+            bandwidth_read_mbs: vec![],
+            bandwidth_write_mbs: vec![],
+        });
+        cpu_start += cpu_count;
+        mem_start += mem_size;
+    }
+
+    let count = nodes.len();
+    if let Some(node) = nodes.iter().find(|node| node.latencies.len() != count) {
+        return Err(anyhow!(
+            "Node {} lists {} distances for {} nodes",
+            node.node_id,
+            node.latencies.len(),
+            count
+        ));
+    }
+
+    Ok(ClusterTopology { nodes })
+}
+
+/// Number of CPUs set in a `cpumap` bitmask (e.g. `00000000,0000000f`)
+fn parse_cpumap(cpumap: &str) -> Result<u32> {
+    cpumap
+        .trim()
+        .split(',')
+        .map(|group| {
+            u32::from_str_radix(group, 16)
+                .map(u32::count_ones)
+                .map_err(|e| anyhow!("Invalid CPU mask group {:?}: {}", group, e))
+        })
+        .sum()
+}
+
+/// Node memory in bytes from the `MemTotal` line of a node's `meminfo`
+fn parse_mem_total(meminfo: &str) -> Result<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.contains("MemTotal:"))
+        .ok_or_else(|| anyhow!("No MemTotal line"))?;
+    let kb: u64 = line
+        .split_whitespace()
+        .rev()
+        .nth(1) // "<kB> kB"
+        .and_then(|kb| kb.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid MemTotal line {:?}", line))?;
+    Ok(kb << 10)
+}
+
+/// SLIT distances from a node's `distance` file
+fn parse_distance(distance: &str) -> Result<Vec<u32>> {
+    distance
+        .split_whitespace()
+        .map(|d| {
+            d.parse()
+                .map_err(|e| anyhow!("Invalid distance {:?}: {}", d, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_node(root: &Path, id: u32, cpumap: &str, mem_kb: u64, distance: &str) {
+        let dir = root.join(format!("node{}", id));
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("cpumap"), format!("{}\n", cpumap)).unwrap();
+        fs::write(
+            dir.join("meminfo"),
+            format!("Node {id} MemTotal:       {mem_kb} kB\nNode {id} MemFree:        1024 kB\n"),
+        )
+        .unwrap();
+        fs::write(dir.join("distance"), format!("{}\n", distance)).unwrap();
+    }
+
+    #[test]
+    fn test_discover_two_node_topology() {
+        let root = tempfile::tempdir().unwrap();
+        // Host CPUs 0-3 and 8-11 on node 0, 4-7 on node 1
+        write_node(root.path(), 0, "00000000,00000f0f", 2 << 20, "10 21");
+        write_node(root.path(), 1, "00000000,000000f0", 1 << 20, "21 10");
+        // Not nodes
+        fs::write(root.path().join("online"), "0-1\n").unwrap();
+        fs::create_dir(root.path().join("power")).unwrap();
+
+        let topology = discover_numa_topology_in(root.path()).unwrap();
+        assert_eq!(topology.nodes.len(), 2);
+
+        let node0 = &topology.nodes[0];
+        assert_eq!(node0.node_id, 0);
+        assert_eq!((node0.cpu_start, node0.cpu_count), (0, 8));
+        assert_eq!((node0.mem_start, node0.mem_size), (0, 2 << 30));
+        assert_eq!(node0.latencies, vec![10, 21]);
+
+        let node1 = &topology.nodes[1];
+        assert_eq!(node1.node_id, 1);
+        assert_eq!((node1.cpu_start, node1.cpu_count), (8, 4));
+        assert_eq!((node1.mem_start, node1.mem_size), (2 << 30, 1 << 30));
+        assert_eq!(node1.latencies, vec![21, 10]);
+    }
+
+    #[test]
+    fn test_mismatched_distance_matrix_is_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        write_node(root.path(), 0, "1", 1 << 20, "10 20");
+        assert!(discover_numa_topology_in(root.path()).is_err());
+    }
+
+    #[test]
+    fn test_no_nodes_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(discover_numa_topology_in(root.path()).is_err());
+    }
+}
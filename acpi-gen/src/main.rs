@@ -1,4 +1,8 @@
-use anyhow::Result;
+mod discovery;
+mod patch;
+mod tables;
+
+use anyhow::{bail, Result};
 use log::info;
 use serde::{Deserialize, Serialize};
 
@@ -19,24 +23,70 @@ struct NodeConfig {
     mem_size: u64,
     /// Estimated latency to other nodes (in 10ns units for SLIT)
     latencies: Vec<u32>,
+    /// Bandwidth from this node to each node's memory (in MB/s for HMAT);
+    /// empty if unknown
+    #[serde(default)]
+    bandwidth_read_mbs: Vec<u64>,
+    #[serde(default)]
+    bandwidth_write_mbs: Vec<u64>,
+}
+
+/// Check every node lists one `what` per node
+fn check_per_node(
+    topology: &ClusterTopology,
+    what: &str,
+    listed: impl Fn(&NodeConfig) -> usize,
+) -> Result<()> {
+    let count = topology.nodes.len();
+    if let Some(node) = topology.nodes.iter().find(|node| listed(node) != count) {
+        bail!(
+            "Node {} lists {} {} for {} nodes",
+            node.node_id,
+            listed(node),
+            what,
+            count
+        );
+    }
+    Ok(())
+}
+
+/// Generate ACPI MADT (Multiple APIC Description Table)
+fn generate_madt(topology: &ClusterTopology) -> Result<Vec<u8>> {
+    info!("Generating ACPI MADT for {} nodes", topology.nodes.len());
+
+    // MADT contains:
+    // - Processor Local APIC Structure (for each CPU)
+    // - I/O APIC Structure (one, at the standard address)
+    // - Interrupt Source Override: ISA IRQ 0 (the PIT) arrives on GSI 2
+    let mut entries = Vec::new();
+    for node in &topology.nodes {
+        for cpu in node.cpu_start..node.cpu_start + node.cpu_count {
+            // APIC ID 0xff is the broadcast address
+            let Ok(apic_id @ 0..=0xfe) = u8::try_from(cpu) else {
+                bail!(
+                    "Node {} CPU {} does not fit in a local APIC ID",
+                    node.node_id,
+                    cpu
+                );
+            };
+            entries.extend_from_slice(&tables::madt_local_apic(apic_id, apic_id));
+        }
+    }
+    entries.extend_from_slice(&tables::madt_io_apic(0, tables::IO_APIC_ADDRESS, 0));
+    entries.extend_from_slice(&tables::madt_source_override(0, 2));
+
+    let madt_data = tables::madt(&entries);
+    info!("MADT generation complete");
+    Ok(madt_data)
 }
 
 /// Generate ACPI SRAT (System Resource Affinity Table)
 fn generate_srat(topology: &ClusterTopology) -> Result<Vec<u8>> {
     info!("Generating ACPI SRAT for {} nodes", topology.nodes.len());
 
-    // TODO M4: Implement ACPI SRAT generation using acpi_tables crate
     // SRAT contains:
-    // - Processor Local APIC/SAPIC Affinity Structure (for each CPU)
+    // - Processor Local x2APIC Affinity Structure (for each CPU)
     // - Memory Affinity Structure (for each memory range)
-
-    // Structure:
-    // - Header (signature "SRAT", length, revision, checksum, etc.)
-    // - Reserved fields
-    // - Affinity structures for CPUs and memory
-
-    let mut srat_data = Vec::new();
-
     for node in &topology.nodes {
         info!(
             "  Node {}: CPUs {}-{}, Memory 0x{:x}-0x{:x}",
@@ -46,19 +96,10 @@ fn generate_srat(topology: &ClusterTopology) -> Result<Vec<u8>> {
             node.mem_start,
             node.mem_start + node.mem_size
         );
-
-        // Add processor affinity structures
-        for cpu in node.cpu_start..(node.cpu_start + node.cpu_count) {
-            // Type 0: Processor Local APIC/SAPIC Affinity
-            // TODO: Add proper ACPI structure
-        }
-
-        // Add memory affinity structure
-        // Type 1: Memory Affinity
-        // TODO: Add proper ACPI structure
     }
 
-    info!("SRAT generation complete (stub)");
+    let srat_data = tables::srat(&topology.nodes);
+    info!("SRAT generation complete");
     Ok(srat_data)
 }
 
@@ -70,13 +111,10 @@ fn generate_slit(topology: &ClusterTopology) -> Result<Vec<u8>> {
     // - Distance from node to itself = 10
     // - Distance to remote nodes based on latency measurements
 
+    check_per_node(topology, "latencies", |node| node.latencies.len())?;
     let num_nodes = topology.nodes.len() as u64;
 
-    // TODO M4: Implement SLIT generation
-    // Structure:
-    // - Header
-    // - Number of System Localities (u64)
-    // - Matrix of distances (num_nodes x num_nodes, each u8)
+    let mut matrix = Vec::with_capacity((num_nodes * num_nodes) as usize);
 
     info!("SLIT matrix ({}x{}):", num_nodes, num_nodes);
     for i in 0..num_nodes {
@@ -85,20 +123,16 @@ fn generate_slit(topology: &ClusterTopology) -> Result<Vec<u8>> {
             let distance = if i == j {
                 10 // Local
             } else {
-                // Use configured latency or default to 20 for remote
-                topology.nodes[i as usize]
-                    .latencies
-                    .get(j as usize)
-                    .copied()
-                    .unwrap_or(20)
+                topology.nodes[i as usize].latencies[j as usize]
             };
             row.push_str(&format!("{:3} ", distance));
+            matrix.push(distance.min(u8::MAX as u32) as u8);
         }
         info!("  [{}]", row);
     }
 
-    let slit_data = Vec::new();
-    info!("SLIT generation complete (stub)");
+    let slit_data = tables::slit(num_nodes as usize, &matrix);
+    info!("SLIT generation complete");
     Ok(slit_data)
 }
 
@@ -109,13 +143,60 @@ fn generate_hmat(topology: &ClusterTopology) -> Result<Vec<u8>> {
     // HMAT provides detailed memory characteristics:
     // - Latency (read/write)
     // - Bandwidth (read/write)
-    // - Memory side cache information
-
-    // TODO M4: Implement HMAT generation (optional for MVP)
-    // This is more detailed than SLIT and provides bandwidth info
+    // - Memory side cache information (not described; there is none)
+
+    check_per_node(topology, "latencies", |node| node.latencies.len())?;
+    let domains: Vec<u32> = topology.nodes.iter().map(|node| node.node_id).collect();
+
+    // Reads and writes are assumed to take as long
+    let latencies_ps: Vec<u64> = topology
+        .nodes
+        .iter()
+        .flat_map(|node| node.latencies.iter())
+        .map(|&latency| u64::from(latency) * 10_000)
+        .collect();
+    let mut structures = vec![
+        tables::hmat_locality(tables::HMAT_READ_LATENCY, &domains, &latencies_ps),
+        tables::hmat_locality(tables::HMAT_WRITE_LATENCY, &domains, &latencies_ps),
+    ];
+
+    let bandwidth_known = topology
+        .nodes
+        .iter()
+        .any(|node| !node.bandwidth_read_mbs.is_empty() || !node.bandwidth_write_mbs.is_empty());
+    if bandwidth_known {
+        check_per_node(topology, "read bandwidths", |node| {
+            node.bandwidth_read_mbs.len()
+        })?;
+        check_per_node(topology, "write bandwidths", |node| {
+            node.bandwidth_write_mbs.len()
+        })?;
+        let read: Vec<u64> = topology
+            .nodes
+            .iter()
+            .flat_map(|node| node.bandwidth_read_mbs.iter().copied())
+            .collect();
+        let write: Vec<u64> = topology
+            .nodes
+            .iter()
+            .flat_map(|node| node.bandwidth_write_mbs.iter().copied())
+            .collect();
+        structures.push(tables::hmat_locality(
+            tables::HMAT_READ_BANDWIDTH,
+            &domains,
+            &read,
+        ));
+        structures.push(tables::hmat_locality(
+            tables::HMAT_WRITE_BANDWIDTH,
+            &domains,
+            &write,
+        ));
+    } else {
+        info!("No bandwidths configured; HMAT describes latency only");
+    }
 
-    let hmat_data = Vec::new();
-    info!("HMAT generation complete (stub)");
+    let hmat_data = tables::hmat(&structures);
+    info!("HMAT generation complete");
     Ok(hmat_data)
 }
 
@@ -123,10 +204,19 @@ fn generate_hmat(topology: &ClusterTopology) -> Result<Vec<u8>> {
 fn generate_acpi_tables(topology: &ClusterTopology) -> Result<()> {
     info!("=== ACPI Table Generation (M4) ===");
 
+    let madt = generate_madt(topology)?;
     let srat = generate_srat(topology)?;
     let slit = generate_slit(topology)?;
     let hmat = generate_hmat(topology)?;
 
+    info!(
+        "Table sizes: MADT={} bytes, SRAT={} bytes, SLIT={} bytes, HMAT={} bytes",
+        madt.len(),
+        srat.len(),
+        slit.len(),
+        hmat.len()
+    );
+
     // TODO M4: Write tables to files or integrate with OVMF
     // - Tables should be loaded by UEFI firmware
     // - Guest OS will parse these to understand NUMA topology
@@ -144,6 +234,12 @@ fn main() -> Result<()> {
 
     info!("SSI-HV ACPI Generator (M4)");
 
+    // --discover: describe the host's own NUMA nodes
+    if std::env::args().skip(1).any(|arg| arg == "--discover") {
+        let topology = discovery::discover_numa_topology()?;
+        return generate_acpi_tables(&topology);
+    }
+
     // Example 2-node cluster topology
     let topology = ClusterTopology {
         nodes: vec![
@@ -154,6 +250,9 @@ fn main() -> Result<()> {
                 mem_start: 0,
                 mem_size: 2 << 30,       // 2 GiB
                 latencies: vec![10, 20], // Local=10, Remote=20
+                // Local DRAM, then RDMA to the other node
+                bandwidth_read_mbs: vec![20_000, 11_000],
+                bandwidth_write_mbs: vec![18_000, 10_000],
             },
             NodeConfig {
                 node_id: 1,
@@ -162,6 +261,8 @@ fn main() -> Result<()> {
                 mem_start: 2 << 30,
                 mem_size: 2 << 30, // 2 GiB
                 latencies: vec![20, 10],
+                bandwidth_read_mbs: vec![11_000, 20_000],
+                bandwidth_write_mbs: vec![10_000, 18_000],
             },
         ],
     };
@@ -184,6 +285,8 @@ mod tests {
             mem_start: 0,
             mem_size: 2 << 30,
             latencies: vec![10, 20],
+            bandwidth_read_mbs: vec![],
+            bandwidth_write_mbs: vec![],
         };
         assert_eq!(node.node_id, 0);
         assert_eq!(node.cpu_count, 4);
@@ -200,6 +303,8 @@ mod tests {
                     mem_start: 0,
                     mem_size: 2 << 30,
                     latencies: vec![10, 20],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
                 },
                 NodeConfig {
                     node_id: 1,
@@ -208,6 +313,8 @@ mod tests {
                     mem_start: 2 << 30,
                     mem_size: 2 << 30,
                     latencies: vec![20, 10],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
                 },
             ],
         };
@@ -216,6 +323,67 @@ mod tests {
         assert_eq!(topology.nodes[1].node_id, 1);
     }
 
+    #[test]
+    fn test_generate_madt_two_nodes() {
+        let topology = ClusterTopology {
+            nodes: (0..2)
+                .map(|node_id| NodeConfig {
+                    node_id,
+                    cpu_start: node_id * 4,
+                    cpu_count: 4,
+                    mem_start: u64::from(node_id) << 31,
+                    mem_size: 2 << 30,
+                    latencies: vec![10, 20],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
+                })
+                .collect(),
+        };
+        let madt = generate_madt(&topology).unwrap();
+        assert_eq!(&madt[..4], b"APIC");
+        assert_eq!(madt[8], 4);
+        assert_eq!(tables::table_length(&madt) as usize, madt.len());
+        assert!(tables::acpi_validate_checksum(&madt));
+        assert_eq!(tables::read_u32(&madt, tables::HEADER_LEN), 0xfee0_0000);
+
+        let mut entries = Vec::new();
+        let mut offset = tables::MADT_ENTRIES_OFFSET;
+        while offset < madt.len() {
+            entries.push((madt[offset], offset));
+            offset += madt[offset + 1] as usize;
+        }
+        assert_eq!(offset, madt.len());
+        let apic_ids: Vec<u8> = entries
+            .iter()
+            .filter(|&&(kind, _)| kind == tables::MADT_LOCAL_APIC)
+            .map(|&(_, offset)| madt[offset + 3])
+            .collect();
+        assert_eq!(apic_ids, (0..8).collect::<Vec<u8>>());
+        let kinds: Vec<u8> = entries[8..].iter().map(|&(kind, _)| kind).collect();
+        assert_eq!(kinds, [tables::MADT_IO_APIC, tables::MADT_SOURCE_OVERRIDE]);
+        // IRQ 0 to GSI 2
+        let (_, offset) = entries[9];
+        assert_eq!(madt[offset + 3], 0);
+        assert_eq!(tables::read_u32(&madt, offset + 4), 2);
+    }
+
+    #[test]
+    fn test_generate_madt_rejects_wide_apic_ids() {
+        let topology = ClusterTopology {
+            nodes: vec![NodeConfig {
+                node_id: 0,
+                cpu_start: 250,
+                cpu_count: 8,
+                mem_start: 0,
+                mem_size: 1 << 30,
+                latencies: vec![10],
+                bandwidth_read_mbs: vec![],
+                bandwidth_write_mbs: vec![],
+            }],
+        };
+        assert!(generate_madt(&topology).is_err());
+    }
+
     #[test]
     fn test_generate_srat() {
         let topology = ClusterTopology {
@@ -226,10 +394,55 @@ mod tests {
                 mem_start: 0,
                 mem_size: 1 << 30,
                 latencies: vec![10],
+                bandwidth_read_mbs: vec![],
+                bandwidth_write_mbs: vec![],
             }],
         };
-        let result = generate_srat(&topology);
-        assert!(result.is_ok());
+        let srat = generate_srat(&topology).unwrap();
+        // Two processor structures and one memory structure
+        assert_eq!(tables::srat_entries(&srat).unwrap().len(), 3);
+        assert!(tables::acpi_validate_checksum(&srat));
+    }
+
+    #[test]
+    fn test_generate_srat_two_nodes() {
+        let topology = ClusterTopology {
+            nodes: (0..2)
+                .map(|node_id| NodeConfig {
+                    node_id,
+                    cpu_start: node_id * 4,
+                    cpu_count: 4,
+                    mem_start: u64::from(node_id) << 31,
+                    mem_size: 2 << 30,
+                    latencies: vec![10, 20],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
+                })
+                .collect(),
+        };
+        let srat = generate_srat(&topology).unwrap();
+        assert_eq!(&srat[..4], b"SRAT");
+        assert_eq!(tables::table_length(&srat) as usize, srat.len());
+        assert_eq!(srat.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)), 0);
+
+        let entries = tables::srat_entries(&srat).unwrap();
+        let processors: Vec<_> = entries
+            .iter()
+            .filter(|&&(kind, _, _)| kind == tables::SRAT_PROCESSOR)
+            .collect();
+        assert_eq!(processors.len(), 8);
+        for (cpu, &&(_, offset, len)) in processors.iter().enumerate() {
+            assert_eq!(len, tables::SRAT_PROCESSOR_LEN);
+            assert_eq!(tables::read_u32(&srat, offset + 4), cpu as u32 / 4);
+            assert_eq!(tables::read_u32(&srat, offset + 8), cpu as u32);
+        }
+        let memory = entries
+            .iter()
+            .filter(|&&(kind, _, len)| {
+                kind == tables::SRAT_MEMORY && len == tables::SRAT_MEMORY_LEN
+            })
+            .count();
+        assert_eq!(memory, 2);
     }
 
     #[test]
@@ -242,12 +455,74 @@ mod tests {
                 mem_start: 0,
                 mem_size: 1 << 30,
                 latencies: vec![10],
+                bandwidth_read_mbs: vec![],
+                bandwidth_write_mbs: vec![],
             }],
         };
         let result = generate_slit(&topology);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_generate_slit_three_nodes() {
+        let latencies = [vec![10, 20, 30], vec![20, 10, 25], vec![30, 25, 10]];
+        let topology = ClusterTopology {
+            nodes: latencies
+                .iter()
+                .zip(0..)
+                .map(|(latencies, node_id)| NodeConfig {
+                    node_id,
+                    cpu_start: node_id * 2,
+                    cpu_count: 2,
+                    mem_start: u64::from(node_id) << 30,
+                    mem_size: 1 << 30,
+                    latencies: latencies.clone(),
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
+                })
+                .collect(),
+        };
+        let slit = generate_slit(&topology).unwrap();
+        assert_eq!(&slit[..4], b"SLIT");
+        assert_eq!(tables::read_u32(&slit, 4) as usize, 44 + 9);
+        assert_eq!(slit.len(), 44 + 9);
+        assert!(tables::acpi_validate_checksum(&slit));
+        assert_eq!(tables::slit_localities(&slit).unwrap(), 3);
+        let matrix = &slit[tables::SLIT_MATRIX_OFFSET..];
+        assert!((0..3).all(|i| matrix[i * 3 + i] == 10));
+        assert_eq!(matrix, &[10, 20, 30, 20, 10, 25, 30, 25, 10]);
+    }
+
+    #[test]
+    fn test_generate_slit_rejects_missing_latencies() {
+        let topology = ClusterTopology {
+            nodes: vec![
+                NodeConfig {
+                    node_id: 0,
+                    cpu_start: 0,
+                    cpu_count: 1,
+                    mem_start: 0,
+                    mem_size: 1 << 30,
+                    latencies: vec![10, 20],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
+                },
+                NodeConfig {
+                    node_id: 1,
+                    cpu_start: 1,
+                    cpu_count: 1,
+                    mem_start: 1 << 30,
+                    mem_size: 1 << 30,
+                    latencies: vec![10],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
+                },
+            ],
+        };
+        let err = generate_slit(&topology).unwrap_err();
+        assert!(err.to_string().contains("Node 1"));
+    }
+
     #[test]
     fn test_generate_hmat() {
         let topology = ClusterTopology {
@@ -258,12 +533,96 @@ mod tests {
                 mem_start: 0,
                 mem_size: 1 << 30,
                 latencies: vec![10],
+                bandwidth_read_mbs: vec![],
+                bandwidth_write_mbs: vec![],
             }],
         };
         let result = generate_hmat(&topology);
         assert!(result.is_ok());
     }
 
+    /// Data type and decoded matrix of each HMAT locality structure
+    fn hmat_localities(hmat: &[u8]) -> Vec<(u8, Vec<u64>)> {
+        let mut localities = Vec::new();
+        let mut offset = tables::HMAT_STRUCTURES_OFFSET;
+        while offset < hmat.len() {
+            let length = tables::read_u32(hmat, offset + 4) as usize;
+            let domains = tables::read_u32(hmat, offset + 12) as usize;
+            let base = u64::from_le_bytes(hmat[offset + 24..offset + 32].try_into().unwrap());
+            let entries = offset + tables::HMAT_LOCALITY_HEADER_LEN + 8 * domains;
+            let matrix = hmat[entries..offset + length]
+                .chunks(2)
+                .map(|entry| u64::from(u16::from_le_bytes([entry[0], entry[1]])) * base)
+                .collect();
+            localities.push((hmat[offset + 9], matrix));
+            offset += length;
+        }
+        localities
+    }
+
+    #[test]
+    fn test_generate_hmat_two_nodes() {
+        let bandwidths = [vec![20_000, 11_000], vec![11_000, 20_000]];
+        let topology = ClusterTopology {
+            nodes: (0..2)
+                .map(|node_id| NodeConfig {
+                    node_id,
+                    cpu_start: node_id * 4,
+                    cpu_count: 4,
+                    mem_start: u64::from(node_id) << 31,
+                    mem_size: 2 << 30,
+                    latencies: if node_id == 0 {
+                        vec![10, 20]
+                    } else {
+                        vec![20, 10]
+                    },
+                    bandwidth_read_mbs: bandwidths[node_id as usize].clone(),
+                    bandwidth_write_mbs: vec![9_000, 9_000],
+                })
+                .collect(),
+        };
+        let hmat = generate_hmat(&topology).unwrap();
+        assert_eq!(&hmat[..4], b"HMAT");
+        assert_eq!(tables::table_length(&hmat) as usize, hmat.len());
+        assert!(tables::acpi_validate_checksum(&hmat));
+
+        let localities = hmat_localities(&hmat);
+        let kinds: Vec<u8> = localities.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                tables::HMAT_READ_LATENCY,
+                tables::HMAT_WRITE_LATENCY,
+                tables::HMAT_READ_BANDWIDTH,
+                tables::HMAT_WRITE_BANDWIDTH
+            ]
+        );
+        assert_eq!(localities[0].1, [100_000, 200_000, 200_000, 100_000]);
+        let read = &localities[2].1;
+        assert!(read[0] > read[1] && read[3] > read[2]);
+        assert_eq!(read, &[20_000, 11_000, 11_000, 20_000]);
+    }
+
+    #[test]
+    fn test_generate_hmat_rejects_partial_bandwidths() {
+        let topology = ClusterTopology {
+            nodes: (0..2)
+                .map(|node_id| NodeConfig {
+                    node_id,
+                    cpu_start: node_id,
+                    cpu_count: 1,
+                    mem_start: u64::from(node_id) << 30,
+                    mem_size: 1 << 30,
+                    latencies: vec![10, 20],
+                    bandwidth_read_mbs: if node_id == 0 { vec![100, 50] } else { vec![] },
+                    bandwidth_write_mbs: vec![100, 50],
+                })
+                .collect(),
+        };
+        let err = generate_hmat(&topology).unwrap_err();
+        assert!(err.to_string().contains("Node 1 lists 0 read bandwidths"));
+    }
+
     #[test]
     fn test_generate_acpi_tables() {
         let topology = ClusterTopology {
@@ -274,6 +633,8 @@ mod tests {
                 mem_start: 0,
                 mem_size: 1 << 30,
                 latencies: vec![10],
+                bandwidth_read_mbs: vec![],
+                bandwidth_write_mbs: vec![],
             }],
         };
         let result = generate_acpi_tables(&topology);
@@ -291,6 +652,8 @@ mod tests {
                     mem_start: 0,
                     mem_size: 2 << 30,
                     latencies: vec![10, 20],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
                 },
                 NodeConfig {
                     node_id: 1,
@@ -299,6 +662,8 @@ mod tests {
                     mem_start: 2 << 30,
                     mem_size: 2 << 30,
                     latencies: vec![20, 10],
+                    bandwidth_read_mbs: vec![],
+                    bandwidth_write_mbs: vec![],
                 },
             ],
         };
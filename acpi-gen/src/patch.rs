@@ -0,0 +1,194 @@
+//! Growing the SRAT and SLIT when a node joins
+//!
+//! A node joining a running cluster needs to appear in the tables the guest
+//! already holds, without regenerating them: existing entries keep their
+//! offsets, so only the new node's affinity structures are appended to the
+//! SRAT and the SLIT gains one row and one column. Both tables get their
+//! `length` field and checksum recomputed.
+
+use crate::tables::{self, SLIT_MATRIX_OFFSET, SRAT_MEMORY};
+use crate::NodeConfig;
+use anyhow::{anyhow, Result};
+
+/// Distance of a locality to itself
+const LOCAL_DISTANCE: u8 = 10;
+
+/// Adds nodes to an existing SRAT
+pub struct SratPatcher;
+
+#[allow(dead_code)] // Not yet called; node join will patch the tables the guest holds
+impl SratPatcher {
+    /// Append `new_node`'s processor and memory affinity structures to
+    /// `existing_srat`
+    ///
+    /// Fails, leaving the table as it was, if it is not a well-formed SRAT or
+    /// already has memory in `new_node`'s proximity domain.
+    pub fn add_node(new_node: &NodeConfig, existing_srat: &mut Vec<u8>) -> Result<()> {
+        for (kind, offset, _) in tables::srat_entries(existing_srat)? {
+            if kind == SRAT_MEMORY
+                && tables::read_u32(existing_srat, offset + 2) == new_node.node_id
+            {
+                return Err(anyhow!(
+                    "SRAT already has memory in proximity domain {}",
+                    new_node.node_id
+                ));
+            }
+        }
+
+        existing_srat.extend_from_slice(&tables::srat_node_entries(new_node));
+        let length = existing_srat.len();
+        tables::set_table_length(existing_srat, length);
+        tables::acpi_set_checksum(existing_srat, tables::CHECKSUM_OFFSET);
+        Ok(())
+    }
+}
+
+/// Adds localities to an existing SLIT
+pub struct SlitPatcher;
+
+#[allow(dead_code)] // Not yet called; node join will patch the tables the guest holds
+impl SlitPatcher {
+    /// Insert a locality at `new_node_idx` into `slit`, `distances[i]` away
+    /// from locality `i` of the grown matrix in both directions
+    ///
+    /// `distances` has one entry per locality including the new one, whose
+    /// own entry must be the local distance (10).
+    pub fn expand(slit: &mut Vec<u8>, new_node_idx: usize, distances: &[u8]) -> Result<()> {
+        let old = tables::slit_localities(slit)?;
+        let new = old + 1;
+        if new_node_idx > old {
+            return Err(anyhow!(
+                "Locality {} would leave a gap after {} localities",
+                new_node_idx,
+                old
+            ));
+        }
+        if distances.len() != new {
+            return Err(anyhow!(
+                "{} distances given for {} localities",
+                distances.len(),
+                new
+            ));
+        }
+        if distances[new_node_idx] != LOCAL_DISTANCE {
+            return Err(anyhow!(
+                "Distance of locality {} to itself must be {}",
+                new_node_idx,
+                LOCAL_DISTANCE
+            ));
+        }
+
+        let matrix = &slit[SLIT_MATRIX_OFFSET..];
+        let mut grown = Vec::with_capacity(new * new);
+        for row in 0..new {
+            for column in 0..new {
+                grown.push(if row == new_node_idx {
+                    distances[column]
+                } else if column == new_node_idx {
+                    distances[row]
+                } else {
+                    // Skip over the inserted row and column
+                    let old_row = row - usize::from(row > new_node_idx);
+                    let old_column = column - usize::from(column > new_node_idx);
+                    matrix[old_row * old + old_column]
+                });
+            }
+        }
+        *slit = tables::slit(new, &grown);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::{SRAT_MEMORY_LEN, SRAT_PROCESSOR, SRAT_PROCESSOR_LEN};
+
+    fn node(node_id: u32, latencies: Vec<u32>) -> NodeConfig {
+        NodeConfig {
+            node_id,
+            cpu_start: node_id * 4,
+            cpu_count: 4,
+            mem_start: u64::from(node_id) << 31,
+            mem_size: 2 << 30,
+            latencies,
+            bandwidth_read_mbs: vec![],
+            bandwidth_write_mbs: vec![],
+        }
+    }
+
+    fn count(entries: &[(u8, usize, usize)], kind: u8) -> usize {
+        entries.iter().filter(|&&(k, _, _)| k == kind).count()
+    }
+
+    #[test]
+    fn test_srat_grows_from_two_to_three_nodes() {
+        let mut srat = tables::srat(&[node(0, vec![10, 20]), node(1, vec![20, 10])]);
+        let before = srat.len();
+        assert!(tables::acpi_validate_checksum(&srat));
+
+        SratPatcher::add_node(&node(2, vec![30, 30, 10]), &mut srat).unwrap();
+
+        let entries = tables::srat_entries(&srat).unwrap();
+        assert_eq!(count(&entries, SRAT_PROCESSOR), 12);
+        assert_eq!(count(&entries, SRAT_MEMORY), 3);
+        assert_eq!(
+            srat.len(),
+            before + 4 * SRAT_PROCESSOR_LEN + SRAT_MEMORY_LEN
+        );
+        assert_eq!(tables::table_length(&srat) as usize, srat.len());
+        assert!(tables::acpi_validate_checksum(&srat));
+        // Node 2's memory is where the new structures start
+        let (_, offset, _) = entries[14];
+        assert_eq!(tables::read_u32(&srat, offset + 2), 2);
+        assert_eq!(&srat[offset + 8..offset + 16], &(4u64 << 30).to_le_bytes());
+        // CPU 8, the first new one, is in domain 2
+        let (_, offset, _) = entries[10];
+        assert_eq!(tables::read_u32(&srat, offset + 4), 2);
+        assert_eq!(tables::read_u32(&srat, offset + 8), 8);
+    }
+
+    #[test]
+    fn test_srat_rejects_known_domain_and_damaged_table() {
+        let mut srat = tables::srat(&[node(0, vec![10])]);
+        let original = srat.clone();
+        assert!(SratPatcher::add_node(&node(0, vec![10]), &mut srat).is_err());
+        assert_eq!(srat, original);
+
+        srat[tables::HEADER_LEN] ^= 1;
+        assert!(SratPatcher::add_node(&node(1, vec![20, 10]), &mut srat).is_err());
+        srat.push(0);
+        assert!(SratPatcher::add_node(&node(1, vec![20, 10]), &mut srat).is_err());
+    }
+
+    #[test]
+    fn test_slit_expands_with_new_row_and_column() {
+        let mut slit = tables::slit(2, &[10, 20, 20, 10]);
+        SlitPatcher::expand(&mut slit, 2, &[30, 25, 10]).unwrap();
+
+        assert_eq!(tables::slit_localities(&slit).unwrap(), 3);
+        assert_eq!(tables::table_length(&slit) as usize, slit.len());
+        assert!(tables::acpi_validate_checksum(&slit));
+        assert_eq!(
+            &slit[SLIT_MATRIX_OFFSET..],
+            &[10, 20, 30, 20, 10, 25, 30, 25, 10]
+        );
+
+        // In the middle, existing localities move over
+        let mut slit = tables::slit(2, &[10, 20, 20, 10]);
+        SlitPatcher::expand(&mut slit, 1, &[15, 10, 15]).unwrap();
+        assert_eq!(
+            &slit[SLIT_MATRIX_OFFSET..],
+            &[10, 15, 20, 15, 10, 15, 20, 15, 10]
+        );
+    }
+
+    #[test]
+    fn test_slit_rejects_bad_distances() {
+        let mut slit = tables::slit(2, &[10, 20, 20, 10]);
+        assert!(SlitPatcher::expand(&mut slit, 3, &[20, 20, 10]).is_err());
+        assert!(SlitPatcher::expand(&mut slit, 2, &[20, 10]).is_err());
+        assert!(SlitPatcher::expand(&mut slit, 2, &[20, 20, 20]).is_err());
+        assert_eq!(tables::slit_localities(&slit).unwrap(), 2);
+    }
+}
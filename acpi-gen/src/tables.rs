@@ -0,0 +1,351 @@
+//! Binary layout of the MADT, SRAT, SLIT and HMAT
+//!
+//! Field offsets follow the ACPI 6.5 specification (sections 5.2.12,
+//! 5.2.16, 5.2.17 and 5.2.28). Every table starts with the common 36-byte header, whose bytes,
+//! checksum included, sum to zero.
+
+use crate::NodeConfig;
+use anyhow::{anyhow, Result};
+
+/// Common table header
+pub const HEADER_LEN: usize = 36;
+/// Offset of the header's `length` field
+pub const LENGTH_OFFSET: usize = 4;
+/// Offset of the header's `checksum` field
+pub const CHECKSUM_OFFSET: usize = 9;
+
+/// MADT header plus the local interrupt controller address and flags
+pub const MADT_ENTRIES_OFFSET: usize = HEADER_LEN + 8;
+/// Processor Local APIC Structure
+pub const MADT_LOCAL_APIC: u8 = 0;
+pub const MADT_LOCAL_APIC_LEN: usize = 8;
+/// I/O APIC Structure
+pub const MADT_IO_APIC: u8 = 1;
+pub const MADT_IO_APIC_LEN: usize = 12;
+/// Interrupt Source Override Structure
+pub const MADT_SOURCE_OVERRIDE: u8 = 2;
+pub const MADT_SOURCE_OVERRIDE_LEN: usize = 10;
+/// Where local APICs and the I/O APIC sit in physical memory
+pub const LOCAL_APIC_ADDRESS: u32 = 0xfee0_0000;
+pub const IO_APIC_ADDRESS: u32 = 0xfec0_0000;
+/// MADT flag: the system also has dual 8259s
+const PCAT_COMPAT: u32 = 1;
+
+/// SRAT header plus its reserved fields
+pub const SRAT_ENTRIES_OFFSET: usize = HEADER_LEN + 12;
+/// Processor Local x2APIC Affinity Structure
+pub const SRAT_PROCESSOR: u8 = 2;
+pub const SRAT_PROCESSOR_LEN: usize = 24;
+/// Memory Affinity Structure
+pub const SRAT_MEMORY: u8 = 1;
+pub const SRAT_MEMORY_LEN: usize = 40;
+
+/// SLIT header plus its locality count
+pub const SLIT_MATRIX_OFFSET: usize = HEADER_LEN + 8;
+
+/// HMAT header plus its reserved field
+pub const HMAT_STRUCTURES_OFFSET: usize = HEADER_LEN + 4;
+/// System Locality Latency and Bandwidth Information Structure
+pub const HMAT_LOCALITY: u16 = 1;
+/// Locality structure fields before its domain lists
+pub const HMAT_LOCALITY_HEADER_LEN: usize = 32;
+/// Locality structure data types; latencies are in picoseconds and
+/// bandwidths in MB/s
+pub const HMAT_READ_LATENCY: u8 = 1;
+pub const HMAT_WRITE_LATENCY: u8 = 2;
+pub const HMAT_READ_BANDWIDTH: u8 = 4;
+pub const HMAT_WRITE_BANDWIDTH: u8 = 5;
+
+/// Affinity structure flag: the entry is in use
+const ENABLED: u32 = 1;
+
+/// Header for a table of `length` bytes, checksum left zero
+fn header(signature: &[u8; 4], revision: u8, length: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(length);
+    header.extend_from_slice(signature);
+    header.extend_from_slice(&(length as u32).to_le_bytes());
+    header.push(revision);
+    header.push(0);
+    header.extend_from_slice(b"SSIHV ");
+    header.extend_from_slice(b"SSIHVTOP");
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(b"SSIH");
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header
+}
+
+fn byte_sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// The byte that, added to `data`, makes it sum to zero
+pub fn acpi_checksum(data: &[u8]) -> u8 {
+    byte_sum(data).wrapping_neg()
+}
+
+/// Write the checksum at `offset` so all of `data` sums to zero
+///
+/// Tables keep theirs at `CHECKSUM_OFFSET`; call this once the rest of the
+/// table is encoded.
+pub fn acpi_set_checksum(data: &mut [u8], offset: usize) {
+    data[offset] = 0;
+    data[offset] = acpi_checksum(data);
+}
+
+/// Whether `data`'s bytes, checksum included, sum to zero
+pub fn acpi_validate_checksum(data: &[u8]) -> bool {
+    byte_sum(data) == 0
+}
+
+/// `table`'s `length` field
+pub fn table_length(table: &[u8]) -> u32 {
+    read_u32(table, LENGTH_OFFSET)
+}
+
+pub fn set_table_length(table: &mut [u8], length: usize) {
+    table[LENGTH_OFFSET..LENGTH_OFFSET + 4].copy_from_slice(&(length as u32).to_le_bytes());
+}
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Check `table` is a whole, intact `signature` table
+pub fn validate(table: &[u8], signature: &[u8; 4]) -> Result<()> {
+    if table.len() < HEADER_LEN || &table[..4] != signature {
+        return Err(anyhow!(
+            "Not a {} table",
+            String::from_utf8_lossy(signature)
+        ));
+    }
+    let length = table_length(table) as usize;
+    if length != table.len() {
+        return Err(anyhow!(
+            "{} length field says {} bytes, table holds {}",
+            String::from_utf8_lossy(signature),
+            length,
+            table.len()
+        ));
+    }
+    if !acpi_validate_checksum(table) {
+        return Err(anyhow!(
+            "{} checksum does not match its contents",
+            String::from_utf8_lossy(signature)
+        ));
+    }
+    Ok(())
+}
+
+/// Processor Local APIC Structure for one CPU
+pub fn madt_local_apic(processor_uid: u8, apic_id: u8) -> [u8; MADT_LOCAL_APIC_LEN] {
+    let mut entry = [0; MADT_LOCAL_APIC_LEN];
+    entry[0] = MADT_LOCAL_APIC;
+    entry[1] = MADT_LOCAL_APIC_LEN as u8;
+    entry[2] = processor_uid;
+    entry[3] = apic_id;
+    entry[4..8].copy_from_slice(&ENABLED.to_le_bytes());
+    entry
+}
+
+/// I/O APIC Structure
+pub fn madt_io_apic(id: u8, address: u32, gsi_base: u32) -> [u8; MADT_IO_APIC_LEN] {
+    let mut entry = [0; MADT_IO_APIC_LEN];
+    entry[0] = MADT_IO_APIC;
+    entry[1] = MADT_IO_APIC_LEN as u8;
+    entry[2] = id;
+    entry[4..8].copy_from_slice(&address.to_le_bytes());
+    entry[8..12].copy_from_slice(&gsi_base.to_le_bytes());
+    entry
+}
+
+/// Interrupt Source Override Structure routing ISA `irq` to `gsi`, with
+/// the bus's default polarity and trigger mode
+pub fn madt_source_override(irq: u8, gsi: u32) -> [u8; MADT_SOURCE_OVERRIDE_LEN] {
+    let mut entry = [0; MADT_SOURCE_OVERRIDE_LEN];
+    entry[0] = MADT_SOURCE_OVERRIDE;
+    entry[1] = MADT_SOURCE_OVERRIDE_LEN as u8;
+    // Bus 0 is ISA
+    entry[3] = irq;
+    entry[4..8].copy_from_slice(&gsi.to_le_bytes());
+    entry
+}
+
+/// MADT holding `entries`
+pub fn madt(entries: &[u8]) -> Vec<u8> {
+    let mut table = header(b"APIC", 4, MADT_ENTRIES_OFFSET + entries.len());
+    table.extend_from_slice(&LOCAL_APIC_ADDRESS.to_le_bytes());
+    table.extend_from_slice(&PCAT_COMPAT.to_le_bytes());
+    table.extend_from_slice(entries);
+    acpi_set_checksum(&mut table, CHECKSUM_OFFSET);
+    table
+}
+
+/// Processor Local x2APIC Affinity Structure for one CPU
+///
+/// The x2APIC form carries a 32-bit APIC ID, so CPUs past 255 keep their
+/// affinity; the local APIC form only has room for 8 bits.
+pub fn srat_processor(x2apic_id: u32, domain: u32) -> [u8; SRAT_PROCESSOR_LEN] {
+    let mut entry = [0; SRAT_PROCESSOR_LEN];
+    entry[0] = SRAT_PROCESSOR;
+    entry[1] = SRAT_PROCESSOR_LEN as u8;
+    entry[4..8].copy_from_slice(&domain.to_le_bytes());
+    entry[8..12].copy_from_slice(&x2apic_id.to_le_bytes());
+    entry[12..16].copy_from_slice(&ENABLED.to_le_bytes());
+    entry
+}
+
+/// Memory Affinity Structure for one node's range
+pub fn srat_memory(base: u64, length: u64, domain: u32) -> [u8; SRAT_MEMORY_LEN] {
+    let mut entry = [0; SRAT_MEMORY_LEN];
+    entry[0] = SRAT_MEMORY;
+    entry[1] = SRAT_MEMORY_LEN as u8;
+    entry[2..6].copy_from_slice(&domain.to_le_bytes());
+    entry[8..16].copy_from_slice(&base.to_le_bytes());
+    entry[16..24].copy_from_slice(&length.to_le_bytes());
+    entry[28..32].copy_from_slice(&ENABLED.to_le_bytes());
+    entry
+}
+
+/// A node's affinity structures: one per CPU, then its memory range
+pub fn srat_node_entries(node: &NodeConfig) -> Vec<u8> {
+    let mut entries = Vec::new();
+    for cpu in node.cpu_start..node.cpu_start + node.cpu_count {
+        entries.extend_from_slice(&srat_processor(cpu, node.node_id));
+    }
+    entries.extend_from_slice(&srat_memory(node.mem_start, node.mem_size, node.node_id));
+    entries
+}
+
+/// SRAT describing `nodes`
+pub fn srat(nodes: &[NodeConfig]) -> Vec<u8> {
+    let entries: Vec<u8> = nodes.iter().flat_map(srat_node_entries).collect();
+    let mut table = header(b"SRAT", 3, SRAT_ENTRIES_OFFSET + entries.len());
+    // Reserved, 1 for compatibility
+    table.extend_from_slice(&1u32.to_le_bytes());
+    table.extend_from_slice(&[0; 8]);
+    table.extend_from_slice(&entries);
+    acpi_set_checksum(&mut table, CHECKSUM_OFFSET);
+    table
+}
+
+/// Type, offset and length of each SRAT affinity structure
+pub fn srat_entries(srat: &[u8]) -> Result<Vec<(u8, usize, usize)>> {
+    validate(srat, b"SRAT")?;
+    let mut entries = Vec::new();
+    let mut offset = SRAT_ENTRIES_OFFSET;
+    while offset < srat.len() {
+        let (kind, length) = (srat[offset], *srat.get(offset + 1).unwrap_or(&0) as usize);
+        if length < 2 || offset + length > srat.len() {
+            return Err(anyhow!("Malformed SRAT entry at offset {}", offset));
+        }
+        entries.push((kind, offset, length));
+        offset += length;
+    }
+    Ok(entries)
+}
+
+/// SLIT for a `localities` x `localities` distance matrix, row by row
+pub fn slit(localities: usize, matrix: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(matrix.len(), localities * localities);
+    let mut table = header(b"SLIT", 1, SLIT_MATRIX_OFFSET + matrix.len());
+    table.extend_from_slice(&(localities as u64).to_le_bytes());
+    table.extend_from_slice(matrix);
+    acpi_set_checksum(&mut table, CHECKSUM_OFFSET);
+    table
+}
+
+/// The SLIT's locality count
+pub fn slit_localities(slit: &[u8]) -> Result<usize> {
+    validate(slit, b"SLIT")?;
+    if slit.len() < SLIT_MATRIX_OFFSET {
+        return Err(anyhow!("SLIT too short for its locality count"));
+    }
+    let localities =
+        u64::from_le_bytes(slit[HEADER_LEN..SLIT_MATRIX_OFFSET].try_into().unwrap()) as usize;
+    if SLIT_MATRIX_OFFSET + localities * localities != slit.len() {
+        return Err(anyhow!(
+            "SLIT matrix does not match {} localities",
+            localities
+        ));
+    }
+    Ok(localities)
+}
+
+/// Locality structure of `data_type` between every pair of `domains`
+///
+/// `matrix` holds one value per initiator, target pair, row by row. The
+/// structure stores 16-bit entries times a common base unit, so the base is
+/// picked to fit the largest value; nonzero values never round down to
+/// zero, which would mean unreachable.
+pub fn hmat_locality(data_type: u8, domains: &[u32], matrix: &[u64]) -> Vec<u8> {
+    debug_assert_eq!(matrix.len(), domains.len() * domains.len());
+    let max = matrix.iter().copied().max().unwrap_or(0);
+    let base = max.div_ceil(u64::from(u16::MAX)).max(1);
+    let length = HMAT_LOCALITY_HEADER_LEN + 8 * domains.len() + 2 * matrix.len();
+
+    let mut structure = Vec::with_capacity(length);
+    structure.extend_from_slice(&HMAT_LOCALITY.to_le_bytes());
+    structure.extend_from_slice(&[0; 2]);
+    structure.extend_from_slice(&(length as u32).to_le_bytes());
+    // Flags: memory, not a memory side cache
+    structure.push(0);
+    structure.push(data_type);
+    structure.extend_from_slice(&[0; 2]);
+    // The same domains initiate and are targeted
+    structure.extend_from_slice(&(domains.len() as u32).to_le_bytes());
+    structure.extend_from_slice(&(domains.len() as u32).to_le_bytes());
+    structure.extend_from_slice(&[0; 4]);
+    structure.extend_from_slice(&base.to_le_bytes());
+    for _ in 0..2 {
+        for domain in domains {
+            structure.extend_from_slice(&domain.to_le_bytes());
+        }
+    }
+    for &value in matrix {
+        let entry = if value == 0 { 0 } else { (value / base).max(1) };
+        structure.extend_from_slice(&(entry as u16).to_le_bytes());
+    }
+    structure
+}
+
+/// HMAT holding `structures`
+pub fn hmat(structures: &[Vec<u8>]) -> Vec<u8> {
+    let structures = structures.concat();
+    let mut table = header(b"HMAT", 2, HMAT_STRUCTURES_OFFSET + structures.len());
+    table.extend_from_slice(&[0; 4]);
+    table.extend_from_slice(&structures);
+    acpi_set_checksum(&mut table, CHECKSUM_OFFSET);
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One-locality SLIT, checksum computed by hand
+    const SLIT_ONE_NODE: [u8; 45] = [
+        0x53, 0x4c, 0x49, 0x54, 0x2d, 0x00, 0x00, 0x00, 0x01, 0x25, 0x53, 0x53, 0x49, 0x48, 0x56,
+        0x20, 0x53, 0x53, 0x49, 0x48, 0x56, 0x54, 0x4f, 0x50, 0x01, 0x00, 0x00, 0x00, 0x53, 0x53,
+        0x49, 0x48, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a,
+    ];
+
+    #[test]
+    fn test_checksum_of_known_table() {
+        assert!(acpi_validate_checksum(&SLIT_ONE_NODE));
+        assert_eq!(slit(1, &[10]), SLIT_ONE_NODE);
+
+        let mut table = SLIT_ONE_NODE;
+        table[CHECKSUM_OFFSET] = 0;
+        assert_eq!(acpi_checksum(&table), 0x25);
+        acpi_set_checksum(&mut table, CHECKSUM_OFFSET);
+        assert_eq!(table, SLIT_ONE_NODE);
+    }
+
+    #[test]
+    fn test_checksum_detects_flipped_byte() {
+        let mut table = SLIT_ONE_NODE;
+        table[SLIT_MATRIX_OFFSET] ^= 0x01;
+        assert!(!acpi_validate_checksum(&table));
+        assert!(validate(&table, b"SLIT").is_err());
+    }
+}
@@ -7,13 +7,22 @@ Exposes REST API for cluster management and metrics.
 """
 
 import asyncio
+import base64
 import logging
-from typing import Dict, List, Optional
+import os
+import time
+from typing import Dict, List, Optional, Tuple
 from dataclasses import dataclass, field
 from datetime import datetime
 import json
 
-from fastapi import FastAPI, HTTPException
+from cryptography.exceptions import InvalidSignature
+from cryptography.hazmat.primitives.asymmetric.ed25519 import (
+    Ed25519PrivateKey,
+    Ed25519PublicKey,
+)
+from cryptography.hazmat.primitives.serialization import Encoding, PublicFormat
+from fastapi import Depends, FastAPI, Header, HTTPException
 from pydantic import BaseModel
 import uvicorn
 
@@ -30,11 +39,13 @@ logger = logging.getLogger("ssi-hv-coordinator")
 
 
 class TransportEndpoint(BaseModel):
-    """Transport endpoint information (TCP or RDMA)"""
-    transport_type: str  # "tcp" or "rdma"
-    # TCP fields
+    """Transport endpoint information (TCP, QUIC or RDMA)"""
+    transport_type: str  # "tcp", "quic" or "rdma"
+    # TCP fields (QUIC endpoints use them for their UDP address)
     tcp_addr: Optional[str] = None
     tcp_port: Optional[int] = None
+    # QUIC node certificate (DER, hex)
+    quic_cert: Optional[str] = None
     # RDMA fields (optional)
     rdma_qpn: Optional[int] = None
     rdma_lid: Optional[int] = None
@@ -42,6 +53,54 @@ class TransportEndpoint(BaseModel):
     rdma_psn: Optional[int] = None
 
 
+class PageReference(BaseModel):
+    """Location of a page with known contents (dedup index entry)"""
+    fingerprint: str  # xxHash3-128 of the page, hex
+    gpa: int
+    node_id: int
+
+
+class LoadMetrics(BaseModel):
+    """Page-serving load reported by a node's pager"""
+    pages_owned: int
+    fault_rate_per_sec: float
+    transport_bw_utilized_pct: float
+    cpu_utilization_pct: float
+
+
+class BalloonStats(BaseModel):
+    """Where a node sees the guest's pages; local + remote + unknown = total"""
+    total_pages: int
+    local_pages: int
+    remote_pages: int
+    unknown_pages: int
+    compressed_pages: int
+    dlq_pages: int
+
+
+# A node holding more of the guest than this calls for another node
+SCALE_OUT_FILL_RATIO = 0.9
+
+
+class NodeRegistration(BaseModel):
+    """Node public key, signed by the node over `{node_id}:{timestamp}`"""
+    node_id: int
+    public_key: str  # Ed25519, hex
+    timestamp: int  # Unix seconds
+    signature: str  # hex
+    # Hash of the node's machine ID, MAC and hostname; not covered by the
+    # signature
+    hardware_fingerprint: Optional[str] = None
+
+
+class AuthToken(BaseModel):
+    """Coordinator-signed binding of a node ID to its public key"""
+    node_id: int
+    public_key: str
+    expiry_ts: int
+    signature: str  # Coordinator's, over "{node_id}:{public_key}:{expiry_ts}"
+
+
 class NodeInfo(BaseModel):
     """Node information for cluster membership"""
     node_id: int
@@ -93,6 +152,16 @@ class ClusterState:
         default_factory=dict)  # node_id -> endpoint
     created_at: datetime = field(default_factory=datetime.now)
     vm_running: bool = False
+    # (node_id, gpa) -> content fingerprint, for page deduplication
+    dedup_pages: Dict[Tuple[int, int], str] = field(default_factory=dict)
+    # node_id -> public key of the identity the endpoint was registered with
+    endpoint_keys: Dict[int, str] = field(default_factory=dict)
+    # node_id -> latest load report, for page placement
+    node_load: Dict[int, LoadMetrics] = field(default_factory=dict)
+    # node_id -> latest balloon stats, for scale-out decisions
+    node_balloon: Dict[int, BalloonStats] = field(default_factory=dict)
+    # page number -> node whose claim was recorded first
+    page_owners: Dict[int, int] = field(default_factory=dict)
 
     def add_node(self, node: NodeInfo):
         """Add node to cluster"""
@@ -138,6 +207,133 @@ app = FastAPI(
 current_cluster: Optional[ClusterState] = None
 
 
+# ============================================================================
+# Node Authentication
+# ============================================================================
+
+TOKEN_TTL_SECONDS = 24 * 3600
+# How old a registration signature may be
+REGISTRATION_MAX_AGE_SECONDS = 300
+# Reject requests without a token (otherwise only invalid tokens are rejected)
+REQUIRE_AUTH = os.environ.get("SSI_HV_REQUIRE_AUTH") == "1"
+
+coordinator_key = Ed25519PrivateKey.generate()
+# node_id -> public key (hex), pinned on first registration
+node_public_keys: Dict[int, str] = {}
+# hardware fingerprint -> node_id, so one host can't register two node IDs
+node_fingerprints: Dict[str, int] = {}
+
+
+def public_key_hex(key: Ed25519PrivateKey) -> str:
+    return key.public_key().public_bytes(Encoding.Raw, PublicFormat.Raw).hex()
+
+
+def verify_signature(public_key: str, signature: str, message: str) -> bool:
+    try:
+        key = Ed25519PublicKey.from_public_bytes(bytes.fromhex(public_key))
+        key.verify(bytes.fromhex(signature), message.encode())
+        return True
+    except (ValueError, InvalidSignature):
+        return False
+
+
+def issue_token(node_id: int, public_key: str) -> AuthToken:
+    expiry_ts = int(time.time()) + TOKEN_TTL_SECONDS
+    message = f"{node_id}:{public_key}:{expiry_ts}".encode()
+    return AuthToken(
+        node_id=node_id,
+        public_key=public_key,
+        expiry_ts=expiry_ts,
+        signature=coordinator_key.sign(message).hex(),
+    )
+
+
+def verify_bearer(bearer: str) -> AuthToken:
+    """Decode and check a bearer token, raising 401 if it is not valid"""
+    try:
+        token = AuthToken.model_validate_json(base64.b64decode(bearer))
+    except ValueError:
+        raise HTTPException(status_code=401, detail="Malformed auth token")
+
+    message = f"{token.node_id}:{token.public_key}:{token.expiry_ts}"
+    if not verify_signature(public_key_hex(coordinator_key), token.signature, message):
+        raise HTTPException(status_code=401, detail="Invalid auth token")
+    if token.expiry_ts <= time.time():
+        raise HTTPException(status_code=401, detail="Auth token expired")
+    if node_public_keys.get(token.node_id) != token.public_key:
+        raise HTTPException(status_code=401, detail="Auth token key is not registered")
+    return token
+
+
+async def authenticated_node(
+    authorization: Optional[str] = Header(None),
+) -> Optional[AuthToken]:
+    """Token of the calling node, or None for unauthenticated callers"""
+    if authorization is None:
+        if REQUIRE_AUTH:
+            raise HTTPException(status_code=401, detail="Authentication required")
+        return None
+
+    scheme, _, bearer = authorization.partition(" ")
+    if scheme.lower() != "bearer" or not bearer:
+        raise HTTPException(status_code=401, detail="Expected a bearer token")
+    return verify_bearer(bearer)
+
+
+@app.post("/auth/register")
+async def register_identity(registration: NodeRegistration) -> dict:
+    """
+    Register a node's public key and issue an auth token.
+
+    The first key registered for a node ID is pinned; registering the same
+    node ID with a different key is rejected. Likewise a hardware fingerprint
+    belongs to the first node ID registering it.
+    """
+    age = abs(time.time() - registration.timestamp)
+    message = f"{registration.node_id}:{registration.timestamp}"
+    if age > REGISTRATION_MAX_AGE_SECONDS or not verify_signature(
+        registration.public_key, registration.signature, message
+    ):
+        raise HTTPException(status_code=401, detail="Invalid registration signature")
+
+    fingerprint = registration.hardware_fingerprint
+    owner = node_fingerprints.get(fingerprint) if fingerprint else None
+    if owner is not None and owner != registration.node_id:
+        logger.warning(
+            f"Rejected node {registration.node_id}: its host is already "
+            f"registered as node {owner}")
+        raise HTTPException(
+            status_code=409,
+            detail=f"Node {owner} is already registered from this host"
+        )
+
+    pinned = node_public_keys.setdefault(
+        registration.node_id, registration.public_key)
+    if pinned != registration.public_key:
+        logger.warning(
+            f"Rejected node {registration.node_id}: public key does not match")
+        raise HTTPException(
+            status_code=403,
+            detail=f"Node {registration.node_id} is registered with a different key"
+        )
+    if fingerprint:
+        node_fingerprints[fingerprint] = registration.node_id
+
+    token = issue_token(registration.node_id, registration.public_key)
+    logger.info(f"Issued auth token for node {registration.node_id}")
+    return {
+        "token": base64.b64encode(token.model_dump_json().encode()).decode(),
+        "coordinator_key": public_key_hex(coordinator_key),
+        "expiry_ts": token.expiry_ts,
+    }
+
+
+@app.get("/auth/public_key")
+async def get_coordinator_key() -> dict:
+    """Coordinator public key, for verifying node tokens"""
+    return {"coordinator_key": public_key_hex(coordinator_key)}
+
+
 @app.post("/cluster", status_code=201)
 async def create_cluster(request: ClusterCreateRequest) -> dict:
     """
@@ -263,6 +459,9 @@ async def remove_node(node_id: int) -> dict:
     # TODO: Stop VMM on node
 
     current_cluster.remove_node(node_id)
+    # Peers must not keep discovering a node that left
+    current_cluster.endpoints.pop(node_id, None)
+    current_cluster.endpoint_keys.pop(node_id, None)
 
     return {
         "status": "removed",
@@ -300,6 +499,71 @@ async def get_metrics() -> MetricsResponse:
     )
 
 
+class PageClaim(BaseModel):
+    """A node claiming a page it touched first"""
+    node_id: int
+
+
+# Declared before /pages/{gpa:path}, which would otherwise match these paths
+@app.get("/pages/{page_num}/owner")
+async def get_page_owner(page_num: int) -> dict:
+    """Node recorded as owning a page, or null if nobody has claimed it"""
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+    return {
+        "page_num": page_num,
+        "owner_node": current_cluster.page_owners.get(page_num),
+    }
+
+
+@app.put("/pages/{page_num}/owner")
+async def claim_page(
+    page_num: int,
+    claim: PageClaim,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
+    """
+    Claim a page for a node unless another node claimed it first.
+
+    Returns the owner either way; pagers that claimed speculatively abort
+    when it is not them.
+    """
+    if caller is not None and caller.node_id != claim.node_id:
+        raise HTTPException(
+            status_code=403,
+            detail=f"Node {caller.node_id} cannot claim pages for node {claim.node_id}"
+        )
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    owner = current_cluster.page_owners.setdefault(page_num, claim.node_id)
+    return {"page_num": page_num, "owner_node": owner}
+
+
+@app.get("/pages/{page_num}/placement")
+async def get_page_placement(page_num: int) -> dict:
+    """
+    Node a first-touched page should be placed on, or null to leave it to
+    the faulting node.
+
+    A page already claimed stays with its owner; otherwise the node with
+    the lowest reported fault rate is preferred.
+    """
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    owner = current_cluster.page_owners.get(page_num)
+    if owner is not None:
+        return {"page_num": page_num, "preferred_node": owner, "reason": "owner"}
+    if current_cluster.node_load:
+        node_id = min(
+            current_cluster.node_load,
+            key=lambda node: (current_cluster.node_load[node].fault_rate_per_sec, node),
+        )
+        return {"page_num": page_num, "preferred_node": node_id, "reason": "least loaded"}
+    return {"page_num": page_num, "preferred_node": None, "reason": "no load reported"}
+
+
 @app.get("/pages/{gpa:path}")
 async def get_page_info(gpa: str) -> dict:
     """
@@ -327,19 +591,86 @@ async def get_page_info(gpa: str) -> dict:
         "heat": 0,
         "access_count": 0,
         "migration_count": 0,
+        # Where RDMA readers find the page; nodes do not report their
+        # memory regions yet
+        "rdma_addr": None,
+        "rdma_rkey": None,
     }
 
 
+@app.post("/dedup", status_code=201)
+async def register_page_fingerprint(
+    ref: PageReference,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
+    """
+    Record the content fingerprint of a page.
+
+    Pagers register every page they fetch so that other nodes can skip
+    transferring contents they already hold.
+    """
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    current_cluster.dedup_pages[(ref.node_id, ref.gpa)] = ref.fingerprint
+    return {"status": "registered", "fingerprint": ref.fingerprint}
+
+
+@app.get("/dedup/nodes/{node_id}/pages/{gpa}")
+async def lookup_page_fingerprint(
+    node_id: int,
+    gpa: int,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> PageReference:
+    """Get the content fingerprint registered for a page"""
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    fingerprint = current_cluster.dedup_pages.get((node_id, gpa))
+    if fingerprint is None:
+        raise HTTPException(
+            status_code=404,
+            detail=f"No fingerprint for GPA {hex(gpa)} on node {node_id}"
+        )
+
+    return PageReference(fingerprint=fingerprint, gpa=gpa, node_id=node_id)
+
+
+@app.get("/dedup/{fingerprint}")
+async def list_pages_with_fingerprint(fingerprint: str) -> dict:
+    """List every registered page with the given contents"""
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    pages = [
+        {"gpa": gpa, "node_id": node_id}
+        for (node_id, gpa), fp in current_cluster.dedup_pages.items()
+        if fp == fingerprint
+    ]
+    return {"fingerprint": fingerprint, "pages": pages}
+
+
 @app.post("/nodes/{node_id}/endpoint", status_code=201)
-async def register_endpoint(node_id: int, endpoint: TransportEndpoint) -> dict:
+async def register_endpoint(
+    node_id: int,
+    endpoint: TransportEndpoint,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
     """
     Register or update transport endpoint for a node.
 
     This allows nodes to advertise their TCP or RDMA endpoints
-    for other nodes to discover and connect to.
+    for other nodes to discover and connect to. Authenticated nodes may
+    only register their own endpoint.
     """
     global current_cluster
 
+    if caller is not None and caller.node_id != node_id:
+        raise HTTPException(
+            status_code=403,
+            detail=f"Node {caller.node_id} cannot register an endpoint for node {node_id}"
+        )
+
     # Auto-create cluster if it doesn't exist (for testing/development)
     if current_cluster is None:
         logger.warning("No cluster exists, auto-creating for testing")
@@ -360,10 +691,14 @@ async def register_endpoint(node_id: int, endpoint: TransportEndpoint) -> dict:
     # Store endpoint
     current_cluster.endpoints[node_id] = endpoint
     current_cluster.nodes[node_id].endpoint = endpoint
+    if caller is not None:
+        current_cluster.endpoint_keys[node_id] = caller.public_key
+    else:
+        current_cluster.endpoint_keys.pop(node_id, None)
 
     logger.info(
         f"Node {node_id} registered {endpoint.transport_type.upper()} endpoint: "
-        f"{endpoint.tcp_addr}:{endpoint.tcp_port}" if endpoint.transport_type == "tcp"
+        f"{endpoint.tcp_addr}:{endpoint.tcp_port}" if endpoint.transport_type in ("tcp", "quic")
         else f"QPN={endpoint.rdma_qpn}"
     )
 
@@ -374,6 +709,79 @@ async def register_endpoint(node_id: int, endpoint: TransportEndpoint) -> dict:
     }
 
 
+@app.put("/nodes/{node_id}/load")
+async def report_load(
+    node_id: int,
+    load: LoadMetrics,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
+    """
+    Record a node's current page-serving load.
+
+    Pagers report every few seconds; placement prefers lightly loaded nodes.
+    """
+    if caller is not None and caller.node_id != node_id:
+        raise HTTPException(
+            status_code=403,
+            detail=f"Node {caller.node_id} cannot report load for node {node_id}"
+        )
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    current_cluster.node_load[node_id] = load
+    return {"status": "recorded", "node_id": node_id}
+
+
+@app.get("/nodes/{node_id}/load")
+async def get_load(node_id: int) -> LoadMetrics:
+    """Get the latest load reported by a node"""
+    if current_cluster is None or node_id not in current_cluster.node_load:
+        raise HTTPException(
+            status_code=404,
+            detail=f"No load reported for node {node_id}"
+        )
+    return current_cluster.node_load[node_id]
+
+
+@app.put("/nodes/{node_id}/balloon_stats")
+async def report_balloon_stats(
+    node_id: int,
+    stats: BalloonStats,
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
+    """Record where a node sees the guest's pages (sent every 10 s)"""
+    if caller is not None and caller.node_id != node_id:
+        raise HTTPException(
+            status_code=403,
+            detail=f"Node {caller.node_id} cannot report balloon stats for node {node_id}"
+        )
+    if current_cluster is None:
+        raise HTTPException(status_code=404, detail="No active cluster")
+
+    current_cluster.node_balloon[node_id] = stats
+    return {"status": "recorded", "node_id": node_id}
+
+
+@app.get("/nodes/{node_id}/balloon_stats")
+async def get_balloon_stats(node_id: int) -> dict:
+    """
+    Latest balloon stats of a node, with its fill ratio (local / total
+    pages) and whether that calls for another node to join.
+    """
+    if current_cluster is None or node_id not in current_cluster.node_balloon:
+        raise HTTPException(
+            status_code=404,
+            detail=f"No balloon stats reported for node {node_id}"
+        )
+    stats = current_cluster.node_balloon[node_id]
+    fill_ratio = stats.local_pages / stats.total_pages if stats.total_pages else 0.0
+    return {
+        **stats.model_dump(),
+        "fill_ratio": fill_ratio,
+        "scale_out": fill_ratio > SCALE_OUT_FILL_RATIO,
+    }
+
+
 @app.get("/nodes/{node_id}/endpoint")
 async def get_endpoint(node_id: int) -> TransportEndpoint:
     """
@@ -395,14 +803,18 @@ async def get_endpoint(node_id: int) -> TransportEndpoint:
 
 
 @app.get("/endpoints")
-async def list_all_endpoints() -> dict:
+async def list_all_endpoints(
+    caller: Optional[AuthToken] = Depends(authenticated_node),
+) -> dict:
     """
     Get all registered transport endpoints in the cluster.
 
-    Useful for nodes to discover all peers at once.
+    Useful for nodes to discover all peers at once. `public_keys` holds the
+    key of the identity each endpoint was registered with; peers prove they
+    hold it when they connect. Bearer tokens are never handed out.
     """
     if current_cluster is None:
-        return {"cluster_name": "none", "endpoints": {}}
+        return {"cluster_name": "none", "endpoints": {}, "public_keys": {}}
 
     return {
         "cluster_name": current_cluster.name,
@@ -410,6 +822,10 @@ async def list_all_endpoints() -> dict:
             str(node_id): endpoint.model_dump()
             for node_id, endpoint in current_cluster.endpoints.items()
         },
+        "public_keys": {
+            str(node_id): public_key
+            for node_id, public_key in current_cluster.endpoint_keys.items()
+        },
     }
 
 
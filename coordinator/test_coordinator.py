@@ -3,8 +3,12 @@ Unit tests for SSI-HV Coordinator
 Following TDD principles with comprehensive coverage
 """
 
-from main import app, ClusterState, NodeInfo
+from main import app, ClusterState, NodeInfo, node_fingerprints, node_public_keys, public_key_hex, verify_signature
+from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PrivateKey
+import base64
+import json
 import pytest
+import time
 from fastapi.testclient import TestClient
 import sys
 import os
@@ -272,6 +276,15 @@ class TestNodeManagement:
             },
         )
 
+        client.post(
+            "/nodes/1/endpoint",
+            json={
+                "transport_type": "tcp",
+                "tcp_addr": "192.168.1.11",
+                "tcp_port": 50051,
+            },
+        )
+
         # Remove node
         response = client.delete("/nodes/1")
         assert response.status_code == 200
@@ -280,6 +293,10 @@ class TestNodeManagement:
         assert data["node_id"] == 1
         assert data["remaining_nodes"] == 1
 
+        # Its endpoint is no longer handed out
+        assert "1" not in client.get("/endpoints").json()["endpoints"]
+        assert client.get("/nodes/1/endpoint").status_code == 404
+
         # Cleanup
         client.delete("/cluster")
 
@@ -623,6 +640,45 @@ class TestTransportEndpoints:
         # Cleanup
         client.delete("/cluster")
 
+    def test_register_quic_endpoint_keeps_certificate(self):
+        # Create cluster with node
+        client.post(
+            "/cluster",
+            json={
+                "name": "test-cluster",
+                "nodes": [
+                    {
+                        "node_id": 0,
+                        "hostname": "node0",
+                        "ip_address": "192.168.1.10",
+                        "cpu_count": 4,
+                        "memory_mb": 8192,
+                        "status": "active",
+                    }
+                ],
+            },
+        )
+
+        # Register QUIC endpoint
+        response = client.post(
+            "/nodes/0/endpoint",
+            json={
+                "transport_type": "quic",
+                "tcp_addr": "192.168.1.10",
+                "tcp_port": 50051,
+                "quic_cert": "3082",
+            },
+        )
+        assert response.status_code == 201
+
+        # Peers need the certificate to connect
+        data = client.get("/nodes/0/endpoint").json()
+        assert data["transport_type"] == "quic"
+        assert data["quic_cert"] == "3082"
+
+        # Cleanup
+        client.delete("/cluster")
+
     def test_get_endpoint(self):
         # Create cluster
         client.post(
@@ -841,5 +897,298 @@ class TestTransportEndpoints:
         client.delete("/cluster")
 
 
+
+class TestDeduplication:
+    """Test the page deduplication index"""
+
+    ZERO_PAGE = "0" * 31 + "1"
+
+    def setup_method(self):
+        client.post("/cluster", json={"name": "test-cluster", "nodes": []})
+
+    def teardown_method(self):
+        client.delete("/cluster")
+
+    def test_register_and_lookup(self):
+        response = client.post(
+            "/dedup",
+            json={"fingerprint": self.ZERO_PAGE, "gpa": 4096, "node_id": 1},
+        )
+        assert response.status_code == 201
+
+        response = client.get("/dedup/nodes/1/pages/4096")
+        assert response.status_code == 200
+        data = response.json()
+        assert data["fingerprint"] == self.ZERO_PAGE
+        assert data["gpa"] == 4096
+        assert data["node_id"] == 1
+
+    def test_lookup_unknown_page(self):
+        response = client.get("/dedup/nodes/1/pages/8192")
+        assert response.status_code == 404
+
+    def test_ten_identical_pages(self):
+        for i in range(10):
+            client.post(
+                "/dedup",
+                json={"fingerprint": self.ZERO_PAGE, "gpa": i * 4096, "node_id": 0},
+            )
+
+        response = client.get(f"/dedup/{self.ZERO_PAGE}")
+        assert response.status_code == 200
+        assert len(response.json()["pages"]) == 10
+
+    def test_reregister_replaces_fingerprint(self):
+        client.post("/dedup", json={"fingerprint": "aa", "gpa": 0, "node_id": 0})
+        client.post("/dedup", json={"fingerprint": "bb", "gpa": 0, "node_id": 0})
+
+        response = client.get("/dedup/nodes/0/pages/0")
+        assert response.json()["fingerprint"] == "bb"
+        assert client.get("/dedup/aa").json()["pages"] == []
+
+class TestLoadReporting:
+    """Test node load reports"""
+
+    LOAD = {
+        "pages_owned": 1024,
+        "fault_rate_per_sec": 12.5,
+        "transport_bw_utilized_pct": 3.0,
+        "cpu_utilization_pct": 41.2,
+    }
+
+    def setup_method(self):
+        client.post("/cluster", json={"name": "test-cluster", "nodes": []})
+
+    def teardown_method(self):
+        client.delete("/cluster")
+
+    def test_report_and_get_load(self):
+        response = client.put("/nodes/1/load", json=self.LOAD)
+        assert response.status_code == 200
+
+        response = client.get("/nodes/1/load")
+        assert response.status_code == 200
+        assert response.json() == self.LOAD
+
+    def test_latest_report_wins(self):
+        client.put("/nodes/1/load", json=self.LOAD)
+        client.put("/nodes/1/load", json={**self.LOAD, "pages_owned": 7})
+        assert client.get("/nodes/1/load").json()["pages_owned"] == 7
+
+    def test_unknown_node_load(self):
+        assert client.get("/nodes/9/load").status_code == 404
+
+    def test_malformed_report_rejected(self):
+        response = client.put("/nodes/1/load", json={"pages_owned": 1})
+        assert response.status_code == 422
+
+
+class TestBalloonStats:
+    """Test balloon stats reports"""
+
+    STATS = {
+        "total_pages": 1000,
+        "local_pages": 950,
+        "remote_pages": 30,
+        "unknown_pages": 20,
+        "compressed_pages": 100,
+        "dlq_pages": 1,
+    }
+
+    def setup_method(self):
+        client.post("/cluster", json={"name": "test-cluster", "nodes": []})
+
+    def teardown_method(self):
+        client.delete("/cluster")
+
+    def test_full_node_calls_for_scale_out(self):
+        response = client.put("/nodes/1/balloon_stats", json=self.STATS)
+        assert response.status_code == 200
+
+        stats = client.get("/nodes/1/balloon_stats").json()
+        assert stats["local_pages"] == 950
+        assert stats["fill_ratio"] == 0.95
+        assert stats["scale_out"] is True
+
+        client.put("/nodes/1/balloon_stats", json={**self.STATS, "local_pages": 500})
+        assert client.get("/nodes/1/balloon_stats").json()["scale_out"] is False
+
+    def test_unknown_node_balloon_stats(self):
+        assert client.get("/nodes/9/balloon_stats").status_code == 404
+
+
+class TestPageOwnership:
+    """Test page claims for speculative fault resolution"""
+
+    def setup_method(self):
+        client.post("/cluster", json={"name": "test-cluster", "nodes": []})
+
+    def teardown_method(self):
+        client.delete("/cluster")
+
+    def test_unclaimed_page_has_no_owner(self):
+        response = client.get("/pages/5/owner")
+        assert response.status_code == 200
+        assert response.json() == {"page_num": 5, "owner_node": None}
+
+    def test_first_claim_wins(self):
+        response = client.put("/pages/5/owner", json={"node_id": 1})
+        assert response.json() == {"page_num": 5, "owner_node": 1}
+
+        response = client.put("/pages/5/owner", json={"node_id": 2})
+        assert response.status_code == 200
+        assert response.json()["owner_node"] == 1
+        assert client.get("/pages/5/owner").json()["owner_node"] == 1
+
+    def test_page_info_still_served(self):
+        assert client.get("/pages/0x1000").json()["gpa"] == "0x1000"
+
+    def test_placement_prefers_least_loaded_node(self):
+        response = client.get("/pages/5/placement")
+        assert response.status_code == 200
+        assert response.json()["preferred_node"] is None
+
+        load = {
+            "pages_owned": 0,
+            "fault_rate_per_sec": 10.0,
+            "transport_bw_utilized_pct": 0.0,
+            "cpu_utilization_pct": 0.0,
+        }
+        client.put("/nodes/1/load", json=load)
+        client.put("/nodes/2/load", json={**load, "fault_rate_per_sec": 2.0})
+        assert client.get("/pages/5/placement").json() == {
+            "page_num": 5,
+            "preferred_node": 2,
+            "reason": "least loaded",
+        }
+
+        # Claimed pages stay put
+        client.put("/pages/5/owner", json={"node_id": 1})
+        assert client.get("/pages/5/placement").json()["preferred_node"] == 1
+
+
+class TestAuthentication:
+    """Test node identity registration and bearer tokens"""
+
+    @staticmethod
+    def registration(node_id, key, timestamp=None):
+        timestamp = int(time.time()) if timestamp is None else timestamp
+        return {
+            "node_id": node_id,
+            "public_key": public_key_hex(key),
+            "timestamp": timestamp,
+            "signature": key.sign(f"{node_id}:{timestamp}".encode()).hex(),
+        }
+
+    def register(self, node_id, key):
+        response = client.post(
+            "/auth/register", json=self.registration(node_id, key))
+        assert response.status_code == 200
+        return {"Authorization": f"Bearer {response.json()['token']}"}
+
+    def setup_method(self):
+        node_public_keys.clear()
+        node_fingerprints.clear()
+        client.post("/cluster", json={"name": "test-cluster", "nodes": []})
+
+    def teardown_method(self):
+        client.delete("/cluster")
+        node_public_keys.clear()
+        node_fingerprints.clear()
+
+    def test_register_issues_verifiable_token(self):
+        key = Ed25519PrivateKey.generate()
+        response = client.post("/auth/register", json=self.registration(1, key))
+        assert response.status_code == 200
+        data = response.json()
+        assert data["coordinator_key"] == client.get(
+            "/auth/public_key").json()["coordinator_key"]
+
+        token = json.loads(base64.b64decode(data["token"]))
+        assert token["node_id"] == 1
+        assert token["public_key"] == public_key_hex(key)
+        assert verify_signature(
+            data["coordinator_key"],
+            token["signature"],
+            f"{token['node_id']}:{token['public_key']}:{token['expiry_ts']}",
+        )
+
+    def test_wrong_key_rejected(self):
+        self.register(1, Ed25519PrivateKey.generate())
+
+        response = client.post(
+            "/auth/register",
+            json=self.registration(1, Ed25519PrivateKey.generate()))
+        assert response.status_code == 403
+
+    def test_bad_or_stale_signature_rejected(self):
+        key = Ed25519PrivateKey.generate()
+        forged = self.registration(1, key)
+        forged["public_key"] = public_key_hex(Ed25519PrivateKey.generate())
+        assert client.post("/auth/register", json=forged).status_code == 401
+
+        stale = self.registration(1, key, timestamp=int(time.time()) - 3600)
+        assert client.post("/auth/register", json=stale).status_code == 401
+
+    def test_second_node_on_host_rejected(self):
+        key = Ed25519PrivateKey.generate()
+        first = self.registration(1, key)
+        first["hardware_fingerprint"] = "ab" * 32
+        assert client.post("/auth/register", json=first).status_code == 200
+
+        # The same node may register again, e.g. after a restart
+        again = self.registration(1, key)
+        again["hardware_fingerprint"] = "ab" * 32
+        assert client.post("/auth/register", json=again).status_code == 200
+
+        second = self.registration(2, Ed25519PrivateKey.generate())
+        second["hardware_fingerprint"] = "ab" * 32
+        response = client.post("/auth/register", json=second)
+        assert response.status_code == 409
+        assert 2 not in node_public_keys
+
+        # Without a fingerprint the coordinator can't tell
+        del second["hardware_fingerprint"]
+        assert client.post("/auth/register", json=second).status_code == 200
+
+    def test_endpoint_registration_with_token(self):
+        key = Ed25519PrivateKey.generate()
+        headers = self.register(1, key)
+        endpoint = {"transport_type": "tcp",
+                    "tcp_addr": "10.0.0.1", "tcp_port": 50051}
+
+        response = client.post(
+            "/nodes/1/endpoint", json=endpoint, headers=headers)
+        assert response.status_code == 201
+
+        # A node may not register endpoints for other nodes
+        response = client.post(
+            "/nodes/2/endpoint", json=endpoint, headers=headers)
+        assert response.status_code == 403
+
+        # Peers learn the node's public key, never its bearer token
+        data = client.get("/endpoints", headers=headers).json()
+        assert data["public_keys"]["1"] == public_key_hex(key)
+        assert "tokens" not in data
+
+    def test_forged_token_rejected(self):
+        impostor = Ed25519PrivateKey.generate()
+        token = {
+            "node_id": 1,
+            "public_key": public_key_hex(impostor),
+            "expiry_ts": int(time.time()) + 60,
+        }
+        message = f"1:{token['public_key']}:{token['expiry_ts']}".encode()
+        token["signature"] = impostor.sign(message).hex()
+        bearer = base64.b64encode(json.dumps(token).encode()).decode()
+
+        response = client.get(
+            "/endpoints", headers={"Authorization": f"Bearer {bearer}"})
+        assert response.status_code == 401
+        response = client.get(
+            "/endpoints", headers={"Authorization": "Bearer not-a-token"})
+        assert response.status_code == 401
+
+
 if __name__ == "__main__":
     pytest.main([__file__, "-v"])
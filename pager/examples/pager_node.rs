@@ -8,9 +8,16 @@
 //! 4. Listens for remote page requests
 //!
 //! Usage: pager_node <node_id> <total_nodes> <coordinator_url>
+//!        pager_node migrate <api_url> <target_node> --dry-run
 //!
 //! Example: pager_node 0 2 http://100.119.10.82:8000
+//!
+//! `migrate --dry-run` asks a running pager's management API how long
+//! migrating its pages to `target_node` would pause the guest. The estimate
+//! probes the target, so the API wants a credential for the pager's node in
+//! `SSI_HV_MANAGEMENT_CREDENTIAL` (see `pager::identity::ManagementCredential`).
 
+use pager::migration::MigrationEstimate;
 use pager::start_pager;
 use std::env;
 use std::io::Write;
@@ -22,11 +29,19 @@ use std::time::Duration;
 fn main() {
     // Parse arguments
     let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        migrate(&args);
+        return;
+    }
     if args.len() != 4 {
         eprintln!(
             "Usage: {} <node_id> <total_nodes> <coordinator_url>",
             args[0]
         );
+        eprintln!(
+            "       {} migrate <api_url> <target_node> --dry-run",
+            args[0]
+        );
         eprintln!("Example: {} 0 2 http://100.119.10.82:8000", args[0]);
         process::exit(1);
     }
@@ -79,7 +94,13 @@ fn main() {
     // Start the pager
     println!("✓ Starting pager with transport...");
 
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        eprintln!("❌ Failed to create async runtime: {}", e);
+        process::exit(1);
+    });
+
     match start_pager(
+        runtime.handle(),
         base_ptr as *mut u8,
         memory_size,
         node_id,
@@ -121,7 +142,11 @@ fn main() {
                 }
             }
 
-            println!("\n✅ Pager stopped cleanly");
+            if let Err(e) = handle.stop() {
+                eprintln!("❌ Pager did not stop cleanly: {:#}", e);
+            } else {
+                println!("\n✅ Pager stopped cleanly");
+            }
 
             // Clean up memory
             unsafe {
@@ -140,3 +165,63 @@ fn main() {
         }
     }
 }
+
+/// `migrate <api_url> <target_node> --dry-run`
+fn migrate(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let positional: Vec<&String> = args[2..].iter().filter(|arg| *arg != "--dry-run").collect();
+    if positional.len() != 2 {
+        eprintln!(
+            "Usage: {} migrate <api_url> <target_node> --dry-run",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} migrate http://127.0.0.1:9090 1 --dry-run",
+            args[0]
+        );
+        process::exit(1);
+    }
+
+    let api_url = positional[0].trim_end_matches('/');
+    let target_node: u32 = positional[1].parse().unwrap_or_else(|_| {
+        eprintln!("Error: target_node must be a number");
+        process::exit(1);
+    });
+    if !dry_run {
+        eprintln!("❌ Live migration is not implemented yet; pass --dry-run for an estimate");
+        process::exit(1);
+    }
+
+    let credential = env::var("SSI_HV_MANAGEMENT_CREDENTIAL").unwrap_or_else(|_| {
+        eprintln!("Error: set SSI_HV_MANAGEMENT_CREDENTIAL to a management credential");
+        process::exit(1);
+    });
+
+    let url = format!("{}/api/v1/migration/estimate/{}", api_url, target_node);
+    let response = reqwest::blocking::Client::new()
+        .get(&url)
+        .bearer_auth(credential)
+        .send()
+        .unwrap_or_else(|e| {
+            eprintln!("❌ Failed to reach pager API: {}", e);
+            process::exit(1);
+        });
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        eprintln!("❌ Estimate failed ({}): {}", status, body);
+        process::exit(1);
+    }
+    let estimate: MigrationEstimate = response.json().unwrap_or_else(|e| {
+        eprintln!("❌ Invalid estimate from pager API: {}", e);
+        process::exit(1);
+    });
+
+    println!("📊 Migration to node {} (dry run)", target_node);
+    println!("   Dirty pages:       {}", estimate.dirty_pages);
+    println!("   Transfer time:     {} ms", estimate.transfer_time_ms);
+    println!(
+        "   Estimated downtime: {} ms",
+        estimate.total_downtime_estimate_ms
+    );
+}
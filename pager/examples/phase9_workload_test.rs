@@ -358,7 +358,9 @@ fn main() -> Result<()> {
 
     // Start pager
     println!("\n⚙️  Starting pager...");
+    let runtime = tokio::runtime::Runtime::new().context("Failed to create async runtime")?;
     let _pager_handle = start_pager(
+        runtime.handle(),
         base,
         config.mem_size,
         config.node_id,
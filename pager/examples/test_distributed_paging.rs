@@ -70,10 +70,8 @@ fn main() -> Result<()> {
 
     println!("   ✓ Created userfaultfd");
 
-    unsafe {
-        uffd.register(base_ptr, MEMORY_SIZE)
-            .context("Failed to register userfaultfd")?;
-    }
+    uffd.register(base_ptr, MEMORY_SIZE)
+        .context("Failed to register userfaultfd")?;
 
     println!("   ✓ Registered {} bytes with userfaultfd", MEMORY_SIZE);
     println!();
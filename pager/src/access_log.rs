@@ -0,0 +1,177 @@
+//! Logging guest accesses to chosen address ranges
+//!
+//! Regulated deployments have to record every access to some of the
+//! guest's memory. Ranges registered with the `AccessLogger` (see
+//! `Pager::access_logger`) have each fault on them, once resolved, passed to
+//! the range's `AccessHandler`, which can write it to a file
+//! (`FileAccessLogger`), forward it to a SIEM or raise an alert.
+//!
+//! The pager only sees faults, so an access is logged the first time the
+//! guest touches a page after it was fetched, claimed or restored, not on
+//! every load and store.
+
+use anyhow::{Context, Result};
+use log::warn;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How a logged fault was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultType {
+    /// The page was already owned by this node
+    Local,
+    /// The page was fetched from its owner
+    Remote,
+    /// The page was untouched and claimed by this node
+    FirstTouch,
+}
+
+/// Receives the accesses to a registered range
+pub trait AccessHandler: Send + Sync {
+    /// The guest faulted on `gpa` (an offset into the pager's region), which
+    /// was resolved as `fault_type` at `timestamp`
+    fn on_access(&self, gpa: u64, fault_type: FaultType, timestamp: SystemTime) -> Result<()>;
+}
+
+/// Handlers for guest address ranges
+#[derive(Default)]
+pub struct AccessLogger {
+    /// Handlers by `[start, end)` range of guest addresses
+    ranges: RwLock<HashMap<(u64, u64), Arc<dyn AccessHandler>>>,
+}
+
+impl AccessLogger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass accesses to `[start, end)` to `handler`, replacing any handler
+    /// registered for exactly that range
+    pub fn register_range(&self, start: u64, end: u64, handler: Arc<dyn AccessHandler>) {
+        self.ranges.write().insert((start, end), handler);
+    }
+
+    /// Stop logging `[start, end)`, returning whether it was registered
+    pub fn unregister_range(&self, start: u64, end: u64) -> bool {
+        self.ranges.write().remove(&(start, end)).is_some()
+    }
+
+    /// Whether any range is registered; faults skip the lookup if not
+    pub fn is_empty(&self) -> bool {
+        self.ranges.read().is_empty()
+    }
+
+    /// Pass the access to the handler of every range containing `gpa`
+    ///
+    /// A failing handler is logged and does not fail the fault.
+    pub fn log(&self, gpa: u64, fault_type: FaultType, timestamp: SystemTime) {
+        let ranges = self.ranges.read();
+        for (&(start, end), handler) in ranges.iter() {
+            if (start..end).contains(&gpa) {
+                if let Err(e) = handler.on_access(gpa, fault_type, timestamp) {
+                    warn!(
+                        "Access handler for 0x{:x}-0x{:x} failed: {:#}",
+                        start, end, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// One line of a `FileAccessLogger` file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRecord {
+    pub gpa: u64,
+    pub fault_type: FaultType,
+    /// Microseconds since the Unix epoch
+    pub timestamp_us: u64,
+}
+
+/// Appends accesses to a file as JSON lines (`AccessRecord`s)
+pub struct FileAccessLogger {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileAccessLogger {
+    /// Append to `path`, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open access log {}", path.display()))?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl AccessHandler for FileAccessLogger {
+    fn on_access(&self, gpa: u64, fault_type: FaultType, timestamp: SystemTime) -> Result<()> {
+        let record = AccessRecord {
+            gpa,
+            fault_type,
+            timestamp_us: timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_micros() as u64)
+                .unwrap_or(0),
+        };
+        let mut writer = self.writer.lock();
+        serde_json::to_writer(&mut *writer, &record)?;
+        writer.write_all(b"\n")?;
+        // Each record reaches the file before the guest goes on
+        writer.flush().context("Failed to write access log")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_logger_writes_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let logger = AccessLogger::new();
+        logger.register_range(
+            0x1000,
+            0x3000,
+            Arc::new(FileAccessLogger::open(&path).unwrap()),
+        );
+
+        let timestamp = UNIX_EPOCH + std::time::Duration::from_micros(42);
+        logger.log(0x1000, FaultType::FirstTouch, timestamp);
+        logger.log(0x3000, FaultType::Local, timestamp);
+        logger.log(0x2fff, FaultType::Remote, timestamp);
+
+        let records: Vec<AccessRecord> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                AccessRecord {
+                    gpa: 0x1000,
+                    fault_type: FaultType::FirstTouch,
+                    timestamp_us: 42,
+                },
+                AccessRecord {
+                    gpa: 0x2fff,
+                    fault_type: FaultType::Remote,
+                    timestamp_us: 42,
+                },
+            ]
+        );
+    }
+}
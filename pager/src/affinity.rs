@@ -0,0 +1,205 @@
+//! Which node a first-touched page lives on
+//!
+//! By default a page lives on the node that first touches it, so a node
+//! that touches most of the guest holds most of it. `PlacementPolicy::
+//! RoundRobin` spreads first touches over the cluster instead, at the cost
+//! of making most later faults remote. `PlacementPolicy::AffinityRoundRobin`
+//! spreads only the first page of each 2 MiB range: the rest of the range
+//! goes to the node that faulted in it most recently, since guests tend to
+//! work through contiguous memory.
+//!
+//! Each pager's `AffinityTracker` hears of the faults it handles only, so a
+//! range is spread until this node has faulted in it. The coordinator sees
+//! the whole cluster's load, so with placement hints on, a pager asks it
+//! first (`GET /pages/{page_num}/placement`) and only falls back to its own
+//! policy when the coordinator has no preference or does not answer.
+
+use crate::HUGE_PAGE_PAGES;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How long a placement hint from the coordinator is reused
+pub const PLACEMENT_HINT_TTL: Duration = Duration::from_secs(10);
+
+/// Placement queries are made on the fault path, so give up quickly
+pub const PLACEMENT_HINT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The coordinator's choice of node for a first-touched page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlacementHint {
+    /// None leaves the page to the local policy
+    pub preferred_node: Option<u32>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Where first-touched pages are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlacementPolicy {
+    /// On the node that touched them
+    #[default]
+    FirstTouch,
+    /// On each node in turn
+    RoundRobin,
+    /// On the node that last faulted in the same 2 MiB range, or on each
+    /// node in turn for ranges not faulted in yet
+    AffinityRoundRobin,
+}
+
+/// Places first-touched pages, remembering which node last faulted in each
+/// 2 MiB range
+#[derive(Debug, Default)]
+pub struct AffinityTracker {
+    policy: PlacementPolicy,
+    /// Range number to the node that last faulted in it
+    last_accessor: Mutex<HashMap<u64, u32>>,
+    /// Round-robin position
+    next: AtomicUsize,
+}
+
+impl AffinityTracker {
+    pub fn new(policy: PlacementPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    pub fn policy(&self) -> PlacementPolicy {
+        self.policy
+    }
+
+    /// `node` faulted on `page_num`
+    ///
+    /// Only `AffinityRoundRobin` keeps track.
+    pub fn record_access(&self, page_num: u64, node: u32) {
+        if self.policy == PlacementPolicy::AffinityRoundRobin {
+            self.last_accessor
+                .lock()
+                .insert(page_num / HUGE_PAGE_PAGES, node);
+        }
+    }
+
+    /// Node that last faulted in the 2 MiB range holding `page_num`
+    pub fn last_accessor(&self, page_num: u64) -> Option<u32> {
+        self.last_accessor
+            .lock()
+            .get(&(page_num / HUGE_PAGE_PAGES))
+            .copied()
+    }
+
+    /// Choose which of `nodes` holds `page_num`, first touched by `node`,
+    /// and record the access
+    ///
+    /// `nodes` should include `node` and list the nodes in the same order
+    /// on every call.
+    pub fn place(&self, page_num: u64, node: u32, nodes: &[u32]) -> u32 {
+        let home = match self.policy {
+            PlacementPolicy::FirstTouch => node,
+            PlacementPolicy::RoundRobin => self.next_node(node, nodes),
+            PlacementPolicy::AffinityRoundRobin => self
+                .last_accessor(page_num)
+                .filter(|accessor| nodes.contains(accessor))
+                .unwrap_or_else(|| self.next_node(node, nodes)),
+        };
+        self.record_access(page_num, node);
+        home
+    }
+
+    fn next_node(&self, node: u32, nodes: &[u32]) -> u32 {
+        if nodes.is_empty() {
+            return node;
+        }
+        nodes[self.next.fetch_add(1, Ordering::Relaxed) % nodes.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_touch_stays_local() {
+        let tracker = AffinityTracker::default();
+        assert_eq!(tracker.place(0, 1, &[0, 1, 2]), 1);
+        assert_eq!(tracker.place(1, 2, &[0, 1, 2]), 2);
+        assert_eq!(tracker.last_accessor(0), None);
+    }
+
+    #[test]
+    fn test_round_robin_ignores_accessor() {
+        let tracker = AffinityTracker::new(PlacementPolicy::RoundRobin);
+        let homes: Vec<u32> = (0..4).map(|page| tracker.place(page, 0, &[0, 1])).collect();
+        assert_eq!(homes, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_affinity_follows_last_accessor_of_range() {
+        let tracker = AffinityTracker::new(PlacementPolicy::AffinityRoundRobin);
+        let nodes = [0, 1, 2];
+        // A new range goes round-robin; the first home is node 0
+        assert_eq!(tracker.place(10, 2, &nodes), 0);
+        assert_eq!(tracker.place(11, 2, &nodes), 2);
+        assert_eq!(tracker.place(HUGE_PAGE_PAGES - 1, 2, &nodes), 2);
+
+        tracker.record_access(12, 1);
+        assert_eq!(tracker.place(13, 2, &nodes), 1);
+        // The next range is new again
+        assert_eq!(tracker.place(HUGE_PAGE_PAGES, 2, &nodes), 1);
+
+        assert_eq!(tracker.last_accessor(5), Some(2));
+        // A node that left is not chosen
+        tracker.record_access(0, 7);
+        assert_eq!(tracker.place(1, 2, &nodes), 2);
+    }
+
+    /// Share of faults that are remote when node 0 works on the first
+    /// 128 MiB and node 1 on the second, each with its own tracker, in a
+    /// cluster of `nodes` nodes
+    fn remote_fault_share(policy: PlacementPolicy, nodes: u32) -> f64 {
+        const PAGES_PER_NODE: u64 = (128 << 20) / crate::PAGE_SIZE as u64;
+        const PASSES: usize = 4;
+
+        let cluster: Vec<u32> = (0..nodes).collect();
+        let trackers = [AffinityTracker::new(policy), AffinityTracker::new(policy)];
+        let mut homes = HashMap::new();
+        let (mut faults, mut remote) = (0u64, 0u64);
+        for _ in 0..PASSES {
+            for offset in 0..PAGES_PER_NODE {
+                // Both nodes run at once
+                for (node, tracker) in trackers.iter().enumerate() {
+                    let page_num = node as u64 * PAGES_PER_NODE + offset;
+                    let home = *homes
+                        .entry(page_num)
+                        .or_insert_with(|| tracker.place(page_num, node as u32, &cluster));
+                    tracker.record_access(page_num, node as u32);
+                    faults += 1;
+                    if home != node as u32 {
+                        remote += 1;
+                    }
+                }
+            }
+        }
+        remote as f64 / faults as f64
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_affinity_vs_round_robin_placement() {
+        for nodes in [2, 4] {
+            let round_robin = remote_fault_share(PlacementPolicy::RoundRobin, nodes);
+            let affinity = remote_fault_share(PlacementPolicy::AffinityRoundRobin, nodes);
+            println!(
+                "{} nodes, 2 x 128 MiB disjoint working sets: remote faults {:.1}% round-robin, {:.2}% affinity",
+                nodes,
+                round_robin * 100.0,
+                affinity * 100.0
+            );
+            assert!(affinity < 0.05);
+            assert!(round_robin >= 0.5);
+        }
+    }
+}
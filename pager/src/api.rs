@@ -0,0 +1,942 @@
+//! HTTP management API for the pager
+//!
+//! Routes (JSON unless noted):
+//! - `GET    /api/v1/stats` - current `PagerStats`
+//! - `GET    /api/v1/stats/hdr?base_time=S` - fault latencies as an HDR histogram log
+//!   (binary; see `PagerStats::hdr_log`)
+//! - `GET    /api/v1/stats/directory_shards` - page directory entries per shard
+//! - `GET    /api/v1/stats/network` - transport counters and host interface statistics
+//! - `GET    /api/v1/directory/{page_num}` - owner of a page
+//! - `POST   /api/v1/directory/{page_num}/migrate` - move a local page to `{"target_node": N}`
+//! - `POST   /api/v1/migrate/batch` - move `{"migrations": [[page_num, target_node], ...]}`
+//! - `DELETE /api/v1/directory/{page_num}` - release a page back to `Unknown`
+//! - `GET    /api/v1/peers` - connected nodes with measured latency
+//! - `GET    /api/v1/dlq` - faults given up on, oldest first
+//! - `GET    /api/v1/migration/estimate/{target_node}` - dry run of migrating this node's pages
+//! - `POST   /api/v1/shutdown` - stop the fault loop and this server
+//!
+//! The routes that change anything (`DELETE`, migrate and shutdown) or send
+//! traffic to peers (the migration estimate) need an `Authorization: Bearer`
+//! credential a cluster node signed for this node (see
+//! `identity::ManagementCredential`).
+
+use crate::allocator::PageAllocator;
+use crate::dlq::{DeadLetterQueue, DlqEntry};
+use crate::identity::ManagementAuth;
+use crate::migration::{BatchMigrationResult, LocalPages, MigrationCoordinator, MigrationEstimate};
+use crate::{
+    discard_page, stats_snapshot, PageDirectory, PageOwner, PagerStats, ShardStat, ShutdownSignal,
+    PAGE_SIZE,
+};
+use anyhow::{anyhow, Context, Result};
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{Stream, StreamExt};
+use log::{info, warn};
+use parking_lot::RwLock;
+use rdma_transport::monitor::{self, InterfaceStats};
+use rdma_transport::{Endpoint as TransportEndpoint, TransportManager, TransportStats};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Shared state handed to every route handler
+#[derive(Clone)]
+pub struct ApiState {
+    pub stats: Arc<RwLock<PagerStats>>,
+    pub directory: Arc<PageDirectory>,
+    /// Fault buffer pool, whose hits and misses `GET /api/v1/stats` reports
+    pub allocator: Arc<PageAllocator>,
+    pub transport: Arc<RwLock<TransportManager>>,
+    pub shutdown: Arc<ShutdownSignal>,
+    pub dead_letters: Arc<DeadLetterQueue>,
+    pub migration: MigrationCoordinator,
+    /// Base address of the registered region (for reading local pages on migrate)
+    pub base: u64,
+    pub len: usize,
+    /// Checks the credentials of the routes that change anything
+    pub auth: Arc<ManagementAuth>,
+}
+
+/// Page ownership as reported by the API
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DirectoryEntry {
+    pub page_num: u64,
+    pub owner: PageOwner,
+}
+
+/// Body of `POST /api/v1/directory/{page_num}/migrate`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrateRequest {
+    pub target_node: u32,
+}
+
+/// Body of `POST /api/v1/migrate/batch`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchMigrateRequest {
+    /// `(page_num, target_node)` pairs
+    pub migrations: Vec<(u64, u32)>,
+}
+
+/// One entry of `GET /api/v1/peers`
+#[derive(Debug, Serialize)]
+pub struct PeerInfo {
+    pub node_id: u32,
+    pub endpoint: TransportEndpoint,
+    /// Round-trip latency measured when the request was served (None if unreachable)
+    pub latency_us: Option<u64>,
+}
+
+/// Body of `GET /api/v1/stats/network`
+#[derive(Debug, Serialize)]
+pub struct NetworkStats {
+    #[serde(flatten)]
+    pub transport: TransportStats,
+    pub interfaces: Vec<InterfaceStats>,
+}
+
+/// Query of `GET /api/v1/stats/hdr`
+#[derive(Debug, Deserialize)]
+pub struct HdrLogQuery {
+    /// Start of the log's intervals, in seconds since the Unix epoch
+    #[serde(default)]
+    pub base_time: u64,
+}
+
+/// Error response: status code plus `{"error": "..."}` body
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// A cluster node that signed a credential for this request
+///
+/// Requests without a valid, unused `Authorization: Bearer` credential are
+/// turned away with 401 before the handler runs.
+struct ClusterCaller {
+    node_id: u32,
+}
+
+impl FromRequestParts<ApiState> for ClusterCaller {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ApiState) -> Result<Self, ApiError> {
+        let unauthorized = |message: String| ApiError(StatusCode::UNAUTHORIZED, message);
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("missing bearer credential".to_string()))?;
+        let node_id = state
+            .auth
+            .verify(bearer)
+            .map_err(|e| unauthorized(format!("{:#}", e)))?;
+        Ok(Self { node_id })
+    }
+}
+
+/// Build the management router
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/api/v1/stats", get(get_stats))
+        .route("/api/v1/stats/hdr", get(get_hdr_log))
+        .route("/api/v1/stats/directory_shards", get(get_directory_shards))
+        .route("/api/v1/stats/network", get(get_network_stats))
+        .route(
+            "/api/v1/directory/{page_num}",
+            get(get_directory_entry).delete(release_page),
+        )
+        .route("/api/v1/directory/{page_num}/migrate", post(migrate_page))
+        .route("/api/v1/migrate/batch", post(batch_migrate))
+        .route("/api/v1/migrate/progress", get(migration_progress))
+        .route("/api/v1/peers", get(list_peers))
+        .route("/api/v1/dlq", get(get_dead_letters))
+        .route(
+            "/api/v1/migration/estimate/{target_node}",
+            get(estimate_migration),
+        )
+        .route("/api/v1/shutdown", post(shutdown))
+        .with_state(state)
+}
+
+/// Start the management server on `addr` in a background thread
+///
+/// Returns the server thread and the address it listens on. The server
+/// exits when `state.shutdown` is triggered.
+pub fn serve(state: ApiState, addr: SocketAddr) -> Result<(JoinHandle<()>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind(addr)
+        .with_context(|| format!("Failed to bind management API on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+
+    info!("Management API listening on {}", local_addr);
+
+    let server = thread::Builder::new()
+        .name("pager-api".to_string())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(e) => {
+                    warn!("Failed to create management API runtime: {}", e);
+                    return;
+                }
+            };
+
+            runtime.block_on(async move {
+                let listener = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(l) => l,
+                    Err(e) => {
+                        warn!("Failed to register management listener: {}", e);
+                        return;
+                    }
+                };
+                let shutdown = Arc::clone(&state.shutdown);
+                let app = router(state);
+                if let Err(e) = axum::serve(listener, app)
+                    .with_graceful_shutdown(async move { shutdown.wait().await })
+                    .await
+                {
+                    warn!("Management API error: {}", e);
+                }
+                info!("Management API stopped");
+            });
+        })
+        .context("Failed to spawn management API thread")?;
+    Ok((server, local_addr))
+}
+
+async fn get_stats(State(state): State<ApiState>) -> Json<PagerStats> {
+    Json(stats_snapshot(
+        &state.stats,
+        &state.directory,
+        &state.allocator,
+    ))
+}
+
+async fn get_hdr_log(
+    State(state): State<ApiState>,
+    Query(query): Query<HdrLogQuery>,
+) -> Result<Response, ApiError> {
+    let log = state
+        .stats
+        .read()
+        .hdr_log(query.base_time)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], log).into_response())
+}
+
+async fn get_directory_shards(State(state): State<ApiState>) -> Json<Vec<ShardStat>> {
+    Json(state.directory.shard_stats())
+}
+
+async fn get_network_stats(State(state): State<ApiState>) -> Result<Json<NetworkStats>, ApiError> {
+    let interfaces = monitor::interface_stats()
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(Json(NetworkStats {
+        transport: state.transport.read().stats(),
+        interfaces,
+    }))
+}
+
+async fn get_directory_entry(
+    State(state): State<ApiState>,
+    Path(page_num): Path<u64>,
+) -> Json<DirectoryEntry> {
+    Json(DirectoryEntry {
+        page_num,
+        owner: state.directory.get_owner(page_num),
+    })
+}
+
+async fn release_page(
+    State(state): State<ApiState>,
+    caller: ClusterCaller,
+    Path(page_num): Path<u64>,
+) -> Result<Json<DirectoryEntry>, ApiError> {
+    if (page_num as usize).saturating_mul(PAGE_SIZE) >= state.len {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("page {} outside registered region", page_num),
+        ));
+    }
+    info!("Node {} released page {}", caller.node_id, page_num);
+    state.directory.set_owner(page_num, PageOwner::Unknown);
+    // The next access faults as a first touch instead of reading a copy
+    // nobody owns
+    discard_page(state.base, page_num)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(Json(DirectoryEntry {
+        page_num,
+        owner: PageOwner::Unknown,
+    }))
+}
+
+async fn migrate_page(
+    State(state): State<ApiState>,
+    _caller: ClusterCaller,
+    Path(page_num): Path<u64>,
+    Json(req): Json<MigrateRequest>,
+) -> Result<Json<DirectoryEntry>, ApiError> {
+    if req.target_node == state.directory.local_node() {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "target_node is the local node".to_string(),
+        ));
+    }
+    if (page_num as usize).saturating_mul(PAGE_SIZE) >= state.len {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("page {} outside registered region", page_num),
+        ));
+    }
+    let owner = state.directory.get_owner(page_num);
+    if owner != PageOwner::Local {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            format!("page {} is not local (owner: {:?})", page_num, owner),
+        ));
+    }
+
+    // Transport calls block on their own runtime, so keep them off this one
+    let result = tokio::task::spawn_blocking(move || -> Result<DirectoryEntry> {
+        // Sent at its address, where faults on other nodes fetch it
+        let addr = state.base + page_num * PAGE_SIZE as u64;
+        // SAFETY: the page is owned locally, so it is resident in the registered region
+        let data = unsafe { std::slice::from_raw_parts(addr as *const u8, PAGE_SIZE) };
+        state
+            .transport
+            .read()
+            .send_page(addr, data, req.target_node)
+            .context("Failed to send page to target node")?;
+
+        // The target acknowledged the page, so the next access fetches it
+        // from there
+        let owner = PageOwner::Remote(req.target_node);
+        state.directory.set_owner(page_num, owner);
+        state.page_moved(page_num)?;
+        info!("Migrated page {} to node {}", page_num, req.target_node);
+        Ok(DirectoryEntry { page_num, owner })
+    })
+    .await
+    .map_err(|e| anyhow!("Migration task failed: {}", e));
+
+    match result.and_then(|r| r) {
+        Ok(entry) => Ok(Json(entry)),
+        Err(e) => Err(ApiError(StatusCode::BAD_GATEWAY, format!("{:#}", e))),
+    }
+}
+
+async fn batch_migrate(
+    State(state): State<ApiState>,
+    _caller: ClusterCaller,
+    Json(req): Json<BatchMigrateRequest>,
+) -> Result<Json<BatchMigrationResult>, ApiError> {
+    // Transport calls block on their own runtime, so keep them off this one
+    tokio::task::spawn_blocking(move || state.migration.batch_migrate(req.migrations, &state))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+}
+
+/// Server-sent `MigrationEvent`s of the running or next batch migration,
+/// ending after its final event
+async fn migration_progress(
+    State(state): State<ApiState>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    let events = state
+        .migration
+        .progress()
+        .subscribe()
+        .map(|event| serde_json::to_string(&event).map(|data| Event::default().data(data)));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Pages as `migrate_page` treats them: read straight from the region and
+/// unmapped once sent
+impl LocalPages for ApiState {
+    fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn page_count(&self) -> u64 {
+        (self.len / PAGE_SIZE) as u64
+    }
+
+    fn read_page(&self, page_num: u64) -> Result<Vec<u8>> {
+        let addr = self.base + page_num * PAGE_SIZE as u64;
+        // SAFETY: only called for locally owned pages, which are resident
+        Ok(unsafe { std::slice::from_raw_parts(addr as *const u8, PAGE_SIZE) }.to_vec())
+    }
+
+    fn page_moved(&self, page_num: u64) -> Result<()> {
+        discard_page(self.base, page_num)
+    }
+}
+
+async fn list_peers(State(state): State<ApiState>) -> Result<Json<Vec<PeerInfo>>, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let transport = state.transport.read();
+        transport
+            .peers()
+            .into_iter()
+            .map(|(node_id, endpoint)| PeerInfo {
+                node_id,
+                endpoint,
+                latency_us: transport
+                    .measure_latency(node_id)
+                    .ok()
+                    .map(|d| d.as_micros() as u64),
+            })
+            .collect()
+    })
+    .await
+    .map(Json)
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn get_dead_letters(State(state): State<ApiState>) -> Json<Vec<DlqEntry>> {
+    Json(state.dead_letters.entries())
+}
+
+async fn estimate_migration(
+    State(state): State<ApiState>,
+    _caller: ClusterCaller,
+    Path(target_node): Path<u32>,
+) -> Result<Json<MigrationEstimate>, ApiError> {
+    if target_node == state.directory.local_node() {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "target_node is the local node".to_string(),
+        ));
+    }
+
+    // The bandwidth probe blocks on the transport's runtime
+    tokio::task::spawn_blocking(move || state.migration.estimate_downtime(target_node))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .map_err(|e| ApiError(StatusCode::BAD_GATEWAY, format!("{:#}", e)))
+}
+
+async fn shutdown(State(state): State<ApiState>, caller: ClusterCaller) -> StatusCode {
+    info!(
+        "Shutdown requested via management API by node {}",
+        caller.node_id
+    );
+    state.shutdown.trigger();
+    StatusCode::ACCEPTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{AuthToken, ManagementCredential, NodeIdentity};
+    use crate::migration::MigrationEvent;
+    use crate::tests::loopback;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use http_body_util::BodyExt;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::OnceLock;
+    use tower::ServiceExt;
+
+    /// Token expiry far enough out for any test run
+    const TOKEN_EXPIRY: u64 = 4_000_000_000;
+
+    /// Coordinator that issues the tokens `test_state` accepts
+    fn coordinator() -> &'static NodeIdentity {
+        static COORDINATOR: OnceLock<NodeIdentity> = OnceLock::new();
+        COORDINATOR.get_or_init(NodeIdentity::generate)
+    }
+
+    /// Fresh credential of cluster node 1 for managing node 0 (whose API
+    /// `test_state` serves), with a token signed by `issuer`
+    fn bearer(issuer: &NodeIdentity) -> String {
+        let identity = NodeIdentity::generate();
+        let token = AuthToken::issue(issuer, 1, &identity.public_key_hex(), TOKEN_EXPIRY);
+        ManagementCredential::issue(&identity, &token, 0).to_bearer()
+    }
+
+    /// `ApiState` over a region of real memory, unmapped on drop
+    struct TestState(ApiState);
+
+    impl std::ops::Deref for TestState {
+        type Target = ApiState;
+
+        fn deref(&self) -> &ApiState {
+            &self.0
+        }
+    }
+
+    impl Drop for TestState {
+        fn drop(&mut self) {
+            unsafe { libc::munmap(self.0.base as *mut libc::c_void, self.0.len) };
+        }
+    }
+
+    fn test_state() -> TestState {
+        let len = 16 * PAGE_SIZE;
+        // Local pages are read from the region, so back it with real memory
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        let directory = Arc::new(PageDirectory::new(0));
+        let transport = Arc::new(RwLock::new(TransportManager::new(0).unwrap()));
+        TestState(ApiState {
+            stats: Arc::new(RwLock::new(PagerStats::default())),
+            allocator: Arc::new(PageAllocator::new(1)),
+            migration: MigrationCoordinator::new(
+                Arc::clone(&directory),
+                Arc::clone(&transport),
+                crate::DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            ),
+            directory,
+            transport,
+            shutdown: Arc::new(ShutdownSignal::default()),
+            dead_letters: Arc::new(DeadLetterQueue::default()),
+            base: base as u64,
+            len,
+            auth: Arc::new(ManagementAuth::new(0, coordinator().verifying_key())),
+        })
+    }
+
+    /// Run a request future; `ApiState` owns a runtime, so it must be dropped outside one
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    async fn send(
+        state: &ApiState,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req = Request::builder().method(method).uri(uri).header(
+            header::AUTHORIZATION,
+            format!("Bearer {}", bearer(coordinator())),
+        );
+        let body = match body {
+            Some(json) => {
+                req = req.header("content-type", "application/json");
+                Body::from(json.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = router(state.clone())
+            .oneshot(req.body(body).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let json = if bytes.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        };
+        (status, json)
+    }
+
+    #[test]
+    fn test_get_stats() {
+        let state = test_state();
+        state.stats.write().local_faults = 7;
+        state.stats.write().remote_faults = 3;
+        {
+            // The pool holds one page: the second checkout misses
+            let _held = state.allocator.alloc();
+            let _extra = state.allocator.alloc();
+        }
+
+        let (status, json) = block_on(send(&state, Method::GET, "/api/v1/stats", None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["local_faults"], 7);
+        assert_eq!(json["remote_faults"], 3);
+        assert_eq!(json["pool_hits"], 1);
+        assert_eq!(json["pool_misses"], 1);
+    }
+
+    #[test]
+    fn test_get_hdr_log() {
+        let state = test_state();
+        state.stats.write().remote_fetch_latency_us.record(250);
+
+        let response = block_on(
+            router(state.clone()).oneshot(
+                Request::builder()
+                    .uri("/api/v1/stats/hdr?base_time=1700000000")
+                    .body(Body::empty())
+                    .unwrap(),
+            ),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        let bytes = block_on(response.into_body().collect()).unwrap().to_bytes();
+        let log = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(log.starts_with("#[BaseTime: 1700000000.000"));
+        assert_eq!(log.matches("Tag=").count(), 3);
+        assert!(log.contains("Tag=first_touch,"));
+    }
+
+    #[test]
+    fn test_get_directory_shards() {
+        let state = test_state();
+        // One page in each of 100 regions
+        for region in 0..100 {
+            state.directory.claim_page(region * crate::REGION_PAGES);
+        }
+
+        let (status, json) = block_on(send(
+            &state,
+            Method::GET,
+            "/api/v1/stats/directory_shards",
+            None,
+        ));
+        assert_eq!(status, StatusCode::OK);
+        let shards = json.as_array().unwrap();
+        assert_eq!(shards[0]["shard_id"], 0);
+        let total: u64 = shards
+            .iter()
+            .map(|s| s["entry_count"].as_u64().unwrap())
+            .sum();
+        assert_eq!(total, 100);
+
+        let (_, stats) = block_on(send(&state, Method::GET, "/api/v1/stats", None));
+        assert!(stats["max_shard_occupancy"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_get_network_stats() {
+        let state = test_state();
+        let (status, json) = block_on(send(&state, Method::GET, "/api/v1/stats/network", None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["congestion_events"], 0);
+        assert_eq!(json["throttled"], false);
+        let interfaces = json["interfaces"].as_array().unwrap();
+        assert!(interfaces.iter().any(|i| i["name"] == "lo"));
+    }
+
+    #[test]
+    fn test_get_directory_entry() {
+        let state = test_state();
+        state.directory.set_owner(5, PageOwner::Remote(2));
+
+        let (status, json) = block_on(send(&state, Method::GET, "/api/v1/directory/5", None));
+        assert_eq!(status, StatusCode::OK);
+        let entry: DirectoryEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            entry,
+            DirectoryEntry {
+                page_num: 5,
+                owner: PageOwner::Remote(2)
+            }
+        );
+
+        let (_, json) = block_on(send(&state, Method::GET, "/api/v1/directory/6", None));
+        assert_eq!(json["owner"], "unknown");
+    }
+
+    #[test]
+    fn test_migrate_non_local_page_rejected() {
+        let state = test_state();
+        state.directory.set_owner(5, PageOwner::Remote(2));
+
+        for (page_num, owner) in [(3, PageOwner::Unknown), (5, PageOwner::Remote(2))] {
+            let (status, json) = block_on(send(
+                &state,
+                Method::POST,
+                &format!("/api/v1/directory/{}/migrate", page_num),
+                Some(serde_json::json!({ "target_node": 1 })),
+            ));
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert!(json["error"].as_str().unwrap().contains("not local"));
+            assert_eq!(state.directory.get_owner(page_num), owner);
+        }
+    }
+
+    #[test]
+    fn test_migrate_local_page_unmaps_it() {
+        let state = test_state();
+        let peer = TransportManager::new(1).unwrap();
+        peer.set_directory_epoch(Arc::new(AtomicU64::new(u64::MAX)));
+        state
+            .transport
+            .write()
+            .connect_peer(1, loopback(peer.local_endpoint()))
+            .unwrap();
+        state.directory.claim_page(2);
+        let addr = state.base + 2 * PAGE_SIZE as u64;
+        unsafe { std::ptr::write_bytes(addr as *mut u8, 0x5a, PAGE_SIZE) };
+
+        let (status, json) = block_on(send(
+            &state,
+            Method::POST,
+            "/api/v1/directory/2/migrate",
+            Some(serde_json::json!({ "target_node": 1 })),
+        ));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["owner"]["remote"], 1);
+        assert_eq!(state.directory.get_owner(2), PageOwner::Remote(1));
+        assert!(!crate::is_resident(addr).unwrap());
+        let migrated = state.transport.read().fetch_page(addr, 1).unwrap();
+        assert_eq!(migrated, vec![0x5a; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_migrate_to_local_node_rejected() {
+        let state = test_state();
+
+        let (status, _) = block_on(send(
+            &state,
+            Method::POST,
+            "/api/v1/directory/3/migrate",
+            Some(serde_json::json!({ "target_node": 0 })),
+        ));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_migrate_local_page_without_peer_fails() {
+        let state = test_state();
+        state.directory.claim_page(1);
+
+        let (status, json) = block_on(send(
+            &state,
+            Method::POST,
+            "/api/v1/directory/1/migrate",
+            Some(serde_json::json!({ "target_node": 4 })),
+        ));
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(json["error"].as_str().unwrap().contains("send page"));
+        assert_eq!(state.directory.get_owner(1), PageOwner::Local);
+    }
+
+    #[test]
+    fn test_batch_migrate_reports_each_page() {
+        let state = test_state();
+        state.directory.set_owner(5, PageOwner::Remote(2));
+        state.directory.set_owner(7, PageOwner::Remote(3));
+
+        let (status, json) = block_on(send(
+            &state,
+            Method::POST,
+            "/api/v1/migrate/batch",
+            Some(serde_json::json!({ "migrations": [[5, 2], [3, 0], [99, 1], [7, 1]] })),
+        ));
+        assert_eq!(status, StatusCode::OK);
+        let result: BatchMigrationResult = serde_json::from_value(json).unwrap();
+        assert_eq!(result.succeeded, 1);
+        assert_eq!(result.failed, 3);
+        let errors: std::collections::HashMap<u64, String> = result.errors.into_iter().collect();
+        assert!(errors[&3].contains("local node"));
+        assert!(errors[&99].contains("outside registered region"));
+        assert!(errors[&7].contains("fetch from node 3"));
+        assert_eq!(state.directory.get_owner(7), PageOwner::Remote(3));
+    }
+
+    #[test]
+    fn test_migration_progress_streams_events() {
+        let state = test_state();
+
+        let events = block_on(async {
+            let response = router(state.clone())
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/v1/migrate/progress")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "text/event-stream"
+            );
+            let body = tokio::spawn(response.into_body().collect());
+
+            // A group per target, each failing to send as there are no peers
+            let (status, _) = send(
+                &state,
+                Method::POST,
+                "/api/v1/migrate/batch",
+                Some(serde_json::json!({ "migrations": [[0, 1], [1, 2], [2, 3]] })),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+
+            // The stream ends after the migration's final event
+            let bytes = body.await.unwrap().unwrap().to_bytes();
+            String::from_utf8(bytes.to_vec())
+                .unwrap()
+                .lines()
+                .filter_map(|line| line.strip_prefix("data: "))
+                .map(|data| serde_json::from_str::<MigrationEvent>(data).unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert!(events.len() >= 3, "{:?}", events);
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].pages_completed <= pair[1].pages_completed));
+        let last = events.last().unwrap();
+        assert_eq!((last.pages_completed, last.pages_total), (3, 3));
+        assert_eq!(last.bytes_transferred, 0);
+        assert_eq!(last.eta_ms, Some(0));
+    }
+
+    #[test]
+    fn test_release_page() {
+        let state = test_state();
+        state.directory.claim_page(9);
+        let addr = state.base + 9 * PAGE_SIZE as u64;
+        unsafe { *(addr as *mut u8) = 0xaa };
+
+        let (status, json) = block_on(send(&state, Method::DELETE, "/api/v1/directory/9", None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["owner"], "unknown");
+        assert_eq!(state.directory.get_owner(9), PageOwner::Unknown);
+        // Unmapped along with it
+        assert!(!crate::is_resident(addr).unwrap());
+
+        let (status, _) = block_on(send(&state, Method::DELETE, "/api/v1/directory/16", None));
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_list_peers_empty() {
+        let state = test_state();
+
+        let (status, json) = block_on(send(&state, Method::GET, "/api/v1/peers", None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json, serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_estimate_migration_needs_remote_target() {
+        let state = test_state();
+
+        let (status, _) = block_on(send(
+            &state,
+            Method::GET,
+            "/api/v1/migration/estimate/0",
+            None,
+        ));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // No peer to probe
+        let (status, json) = block_on(send(
+            &state,
+            Method::GET,
+            "/api/v1/migration/estimate/4",
+            None,
+        ));
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(json["error"].as_str().unwrap().contains("bandwidth"));
+    }
+
+    #[test]
+    fn test_get_dead_letters() {
+        let state = test_state();
+        state
+            .dead_letters
+            .push(3 * PAGE_SIZE as u64, "checksum mismatch".to_string());
+
+        let (status, json) = block_on(send(&state, Method::GET, "/api/v1/dlq", None));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json[0]["gpa"], 3 * PAGE_SIZE);
+        assert_eq!(json[0]["error"], "checksum mismatch");
+    }
+
+    #[test]
+    fn test_mutating_routes_need_cluster_token() {
+        let state = test_state();
+        state.directory.claim_page(9);
+        let forged = bearer(&NodeIdentity::generate());
+        let replayed = bearer(coordinator());
+        state.auth.verify(&replayed).unwrap();
+        // What every node sends the coordinator, so anyone it reaches has it
+        let identity = NodeIdentity::generate();
+        let bare_token =
+            AuthToken::issue(coordinator(), 1, &identity.public_key_hex(), TOKEN_EXPIRY)
+                .to_bearer();
+
+        for (method, uri) in [
+            (Method::DELETE, "/api/v1/directory/9"),
+            (Method::POST, "/api/v1/directory/9/migrate"),
+            (Method::POST, "/api/v1/migrate/batch"),
+            (Method::POST, "/api/v1/shutdown"),
+            (Method::GET, "/api/v1/migration/estimate/4"),
+        ] {
+            let mut authorizations = vec![None];
+            for credential in [&forged, &replayed, &bare_token] {
+                authorizations.push(Some(format!("Bearer {}", credential)));
+            }
+            for authorization in authorizations {
+                let mut req = Request::builder()
+                    .method(method.clone())
+                    .uri(uri)
+                    .header("content-type", "application/json");
+                if let Some(authorization) = &authorization {
+                    req = req.header(header::AUTHORIZATION, authorization);
+                }
+                let response =
+                    block_on(router(state.clone()).oneshot(req.body(Body::from("{}")).unwrap()))
+                        .unwrap();
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            }
+        }
+        assert_eq!(state.directory.get_owner(9), PageOwner::Local);
+        assert!(!state.shutdown.is_triggered());
+
+        // Reads stay open
+        let response = block_on(
+            router(state.clone()).oneshot(
+                Request::builder()
+                    .uri("/api/v1/directory/9")
+                    .body(Body::empty())
+                    .unwrap(),
+            ),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let state = test_state();
+        assert!(!state.shutdown.is_triggered());
+
+        let (status, _) = block_on(send(&state, Method::POST, "/api/v1/shutdown", None));
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(state.shutdown.is_triggered());
+    }
+}
@@ -0,0 +1,266 @@
+//! Cluster-wide page count balancing
+//!
+//! A node holding far more local pages than its peers does most of the
+//! page serving. With `PagerConfig::auto_balance` set, a `BalancingAgent`
+//! reads every node's `LoadMetrics` from the coordinator each
+//! `BALANCE_INTERVAL` and, if this node holds more than its share of the
+//! cluster's pages, asks the fault loop to migrate the excess to nodes
+//! holding less than theirs. Each node only ever moves its own pages, so the
+//! agents on different nodes do not need to agree on anything.
+//!
+//! A node's share is the cluster's page count divided by the number of
+//! reporting nodes, rounded up so that every page fits somewhere.
+
+use crate::coordinator::CoordinatorClient;
+use crate::metrics::LoadMetrics;
+use crate::reload::ControlMessage;
+use crate::ShutdownSignal;
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::Sender;
+use log::{debug, info, warn};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+/// How often the agent looks for imbalance
+pub const BALANCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Load requests give up after this long
+const LOAD_FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the agent learns how loaded each node is
+pub trait LoadSource: Send {
+    /// Latest load of every node that has reported one, by node ID
+    fn cluster_load(&self) -> Result<BTreeMap<u32, LoadMetrics>>;
+}
+
+/// Loads as reported to the coordinator (`GET /nodes/{id}/load`)
+pub struct CoordinatorLoad {
+    coordinator: Arc<CoordinatorClient>,
+    /// Runs the requests for the agent's thread
+    runtime: Handle,
+    total_nodes: u32,
+}
+
+impl CoordinatorLoad {
+    pub fn new(coordinator: Arc<CoordinatorClient>, runtime: Handle, total_nodes: u32) -> Self {
+        Self {
+            coordinator,
+            runtime,
+            total_nodes,
+        }
+    }
+
+    async fn fetch_loads(&self) -> Result<BTreeMap<u32, LoadMetrics>> {
+        let bearer = self.coordinator.bearer()?;
+        let mut loads = BTreeMap::new();
+        for node_id in 0..self.total_nodes {
+            let response = self
+                .coordinator
+                .send(|http, url| {
+                    http.get(format!("{}/nodes/{}/load", url, node_id))
+                        .bearer_auth(bearer)
+                        .timeout(LOAD_FETCH_TIMEOUT)
+                })
+                .await
+                .with_context(|| format!("Failed to fetch load of node {}", node_id))?;
+            match response.status() {
+                // Not reported yet
+                StatusCode::NOT_FOUND => continue,
+                status if !status.is_success() => {
+                    return Err(anyhow!(
+                        "Failed to fetch load of node {}: {}",
+                        node_id,
+                        status
+                    ));
+                }
+                _ => {}
+            }
+            let load = response
+                .json()
+                .await
+                .with_context(|| format!("Failed to parse load of node {}", node_id))?;
+            loads.insert(node_id, load);
+        }
+        Ok(loads)
+    }
+}
+
+impl LoadSource for CoordinatorLoad {
+    fn cluster_load(&self) -> Result<BTreeMap<u32, LoadMetrics>> {
+        self.runtime.block_on(self.fetch_loads())
+    }
+}
+
+/// Pages for this node to migrate to `target_node`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rebalance {
+    pub target_node: u32,
+    pub pages: usize,
+}
+
+/// Moves this node's excess pages to less loaded nodes
+pub struct BalancingAgent {
+    node_id: u32,
+    source: Box<dyn LoadSource>,
+    interval: Duration,
+}
+
+impl BalancingAgent {
+    pub fn new(node_id: u32, source: Box<dyn LoadSource>) -> Self {
+        Self {
+            node_id,
+            source,
+            interval: BALANCE_INTERVAL,
+        }
+    }
+
+    /// Look for imbalance this often (default `BALANCE_INTERVAL`)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Migrations that bring this node down to its share of `loads`
+    ///
+    /// The most underloaded nodes are filled first; none is given more than
+    /// it needs to reach its own share.
+    pub fn plan(&self, loads: &BTreeMap<u32, LoadMetrics>) -> Vec<Rebalance> {
+        let Some(local) = loads.get(&self.node_id) else {
+            return Vec::new();
+        };
+        let total: u64 = loads.values().map(|load| load.pages_owned).sum();
+        let share = total.div_ceil(loads.len() as u64);
+        let mut excess = local.pages_owned.saturating_sub(share);
+
+        let mut underloaded: Vec<(u32, u64)> = loads
+            .iter()
+            .filter(|(_, load)| load.pages_owned < share)
+            .map(|(&node_id, load)| (node_id, share - load.pages_owned))
+            .collect();
+        underloaded.sort_by_key(|&(node_id, room)| (std::cmp::Reverse(room), node_id));
+
+        let mut plan = Vec::new();
+        for (target_node, room) in underloaded {
+            if excess == 0 {
+                break;
+            }
+            let pages = room.min(excess);
+            excess -= pages;
+            plan.push(Rebalance {
+                target_node,
+                pages: pages as usize,
+            });
+        }
+        plan
+    }
+
+    /// Plan every `interval` on a background thread, handing each migration
+    /// to the fault loop through `control`
+    ///
+    /// Stops on shutdown, or once the fault loop is gone.
+    pub fn start(
+        self,
+        control: Arc<Sender<ControlMessage>>,
+        shutdown: Arc<ShutdownSignal>,
+    ) -> Result<JoinHandle<()>> {
+        info!(
+            "Balancing pages of node {} every {:?}",
+            self.node_id, self.interval
+        );
+        // Only waits; load requests block outside it
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .context("Failed to create balancing runtime")?;
+
+        thread::Builder::new()
+            .name("pager-balance".to_string())
+            .spawn(move || loop {
+                let stopped = runtime.block_on(async {
+                    tokio::select! {
+                        _ = shutdown.wait() => true,
+                        _ = tokio::time::sleep(self.interval) => false,
+                    }
+                });
+                if stopped {
+                    break;
+                }
+
+                let loads = match self.source.cluster_load() {
+                    Ok(loads) => loads,
+                    Err(e) => {
+                        debug!("No cluster load to balance on: {:#}", e);
+                        continue;
+                    }
+                };
+                for rebalance in self.plan(&loads) {
+                    debug!(
+                        "Migrating {} pages to node {}",
+                        rebalance.pages, rebalance.target_node
+                    );
+                    if control.send(ControlMessage::Rebalance(rebalance)).is_err() {
+                        warn!("Fault loop gone; stopping page balancing");
+                        return;
+                    }
+                }
+            })
+            .context("Failed to spawn balancing thread")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoLoad;
+
+    impl LoadSource for NoLoad {
+        fn cluster_load(&self) -> Result<BTreeMap<u32, LoadMetrics>> {
+            Ok(BTreeMap::new())
+        }
+    }
+
+    fn loads(pages: &[u64]) -> BTreeMap<u32, LoadMetrics> {
+        pages
+            .iter()
+            .enumerate()
+            .map(|(node_id, &pages_owned)| {
+                let load = LoadMetrics {
+                    pages_owned,
+                    fault_rate_per_sec: 0.0,
+                    transport_bw_utilized_pct: 0.0,
+                    cpu_utilization_pct: 0.0,
+                };
+                (node_id as u32, load)
+            })
+            .collect()
+    }
+
+    fn plan(node_id: u32, pages: &[u64]) -> Vec<(u32, usize)> {
+        BalancingAgent::new(node_id, Box::new(NoLoad))
+            .plan(&loads(pages))
+            .into_iter()
+            .map(|rebalance| (rebalance.target_node, rebalance.pages))
+            .collect()
+    }
+
+    #[test]
+    fn test_excess_fills_most_underloaded_first() {
+        // Share is 10
+        assert_eq!(plan(0, &[20, 8, 2]), vec![(2, 8), (1, 2)]);
+        // Share rounds up to 7
+        assert_eq!(plan(0, &[12, 4, 4]), vec![(1, 3), (2, 2)]);
+    }
+
+    #[test]
+    fn test_nodes_at_or_below_share_stay_put() {
+        assert!(plan(1, &[20, 8, 2]).is_empty());
+        assert!(plan(0, &[7, 7, 6]).is_empty());
+        // Not reporting yet
+        assert!(plan(3, &[20, 8, 2]).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! Page cache between the pager and the transport
+//!
+//! Remote pages are kept in a shared memory mapping of an anonymous `memfd`,
+//! so a page that faults again (e.g. after the guest dropped it) is installed
+//! straight from the cache with `UFFDIO_COPY` instead of crossing the network.
+//! The mapping is sparse: only slots that have held a page use memory.
+//!
+//! Entries are keyed by GPA and evicted least recently used first. They hold
+//! the page as it was fetched; whoever learns that a remote page changed must
+//! `invalidate` it.
+//!
+//! Fault threads and the prefaulter share the cache, so a slot is only read
+//! inside `with_page`, which keeps it from being evicted or overwritten
+//! until the reader is done.
+
+use crate::PAGE_SIZE;
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+
+/// Default cache size (65536 pages)
+pub const DEFAULT_PAGE_CACHE_SIZE: usize = 256 << 20;
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    /// GPA -> (slot, last use)
+    entries: HashMap<u64, (usize, u64)>,
+    /// Last use -> GPA, oldest first
+    lru: BTreeMap<u64, u64>,
+    /// Slots never used or freed by `invalidate`
+    free: Vec<usize>,
+    /// Slots handed out so far (the rest are implicitly free)
+    used: usize,
+    clock: u64,
+}
+
+impl CacheIndex {
+    fn touch(&mut self, gpa: u64) -> Option<usize> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (slot, last_use) = self.entries.get_mut(&gpa)?;
+        self.lru.remove(last_use);
+        *last_use = clock;
+        self.lru.insert(clock, gpa);
+        Some(*slot)
+    }
+}
+
+/// LRU cache of page contents in a `memfd` mapping
+pub struct PageCache {
+    base: *mut u8,
+    capacity: usize,
+    memfd: libc::c_int,
+    index: Mutex<CacheIndex>,
+}
+
+// SAFETY: the mapping is owned by the cache and slot access is serialized by
+// `index`; pointers are only handed out while it is held
+unsafe impl Send for PageCache {}
+unsafe impl Sync for PageCache {}
+
+impl PageCache {
+    /// Cache of `size` bytes (rounded down to whole pages)
+    pub fn new(size: usize) -> Result<Self> {
+        let capacity = size / PAGE_SIZE;
+        if capacity == 0 {
+            return Err(anyhow!("Page cache of {} bytes holds no pages", size));
+        }
+        let len = capacity * PAGE_SIZE;
+
+        // SAFETY: plain syscalls; every failure path closes what was opened
+        unsafe {
+            let memfd = libc::memfd_create(c"ssi-hv-page-cache".as_ptr(), libc::MFD_CLOEXEC);
+            if memfd < 0 {
+                return Err(anyhow!(
+                    "memfd_create failed: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            if libc::ftruncate(memfd, len as libc::off_t) != 0 {
+                let err = std::io::Error::last_os_error();
+                libc::close(memfd);
+                return Err(anyhow!("Failed to size page cache: {}", err));
+            }
+            let base = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                memfd,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                let err = std::io::Error::last_os_error();
+                libc::close(memfd);
+                return Err(anyhow!("Failed to map page cache: {}", err));
+            }
+
+            debug!("Page cache: {} pages at {:p}", capacity, base);
+            Ok(Self {
+                base: base as *mut u8,
+                capacity,
+                memfd,
+                index: Mutex::new(CacheIndex::default()),
+            })
+        }
+    }
+
+    /// Maximum number of cached pages
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of cached pages
+    pub fn len(&self) -> usize {
+        self.index.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether `gpa` is cached
+    pub fn contains(&self, gpa: u64) -> bool {
+        self.index.lock().entries.contains_key(&gpa)
+    }
+
+    /// Run `f` on the cached contents of `gpa`, marking it most recently used
+    ///
+    /// The pointer covers one page. Inserts and invalidations wait until `f`
+    /// returns, so the slot holds `gpa` for as long as `f` runs; `f` must not
+    /// use the cache itself.
+    pub fn with_page<R>(&self, gpa: u64, f: impl FnOnce(*const u8) -> R) -> Option<R> {
+        let mut index = self.index.lock();
+        let slot = index.touch(gpa)?;
+        Some(f(self.slot_ptr(slot) as *const u8))
+    }
+
+    /// Copy `data` into the cache as the contents of `gpa`
+    ///
+    /// Evicts the least recently used page if the cache is full.
+    pub fn insert(&self, gpa: u64, data: &[u8]) -> Result<()> {
+        if data.len() != PAGE_SIZE {
+            return Err(anyhow!(
+                "Invalid page size: expected {}, got {}",
+                PAGE_SIZE,
+                data.len()
+            ));
+        }
+
+        let mut index = self.index.lock();
+        let slot = match index.touch(gpa) {
+            Some(slot) => slot,
+            None => {
+                let slot = if let Some(slot) = index.free.pop() {
+                    slot
+                } else if index.used < self.capacity {
+                    index.used += 1;
+                    index.used - 1
+                } else {
+                    let (_, victim) = index.lru.pop_first().expect("full cache has an LRU entry");
+                    index
+                        .entries
+                        .remove(&victim)
+                        .expect("LRU entry is cached")
+                        .0
+                };
+                index.clock += 1;
+                let clock = index.clock;
+                index.entries.insert(gpa, (slot, clock));
+                index.lru.insert(clock, gpa);
+                slot
+            }
+        };
+
+        // SAFETY: `slot` is in bounds and the index lock keeps other users out
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.slot_ptr(slot), PAGE_SIZE) };
+        Ok(())
+    }
+
+    /// Drop the cached copy of `gpa`, if any
+    pub fn invalidate(&self, gpa: u64) {
+        let mut index = self.index.lock();
+        if let Some((slot, last_use)) = index.entries.remove(&gpa) {
+            index.lru.remove(&last_use);
+            index.free.push(slot);
+        }
+    }
+
+    fn slot_ptr(&self, slot: usize) -> *mut u8 {
+        debug_assert!(slot < self.capacity);
+        // SAFETY: slots are within the `capacity * PAGE_SIZE` mapping
+        unsafe { self.base.add(slot * PAGE_SIZE) }
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        // SAFETY: we own the mapping and the fd
+        unsafe {
+            if libc::munmap(self.base as *mut libc::c_void, self.capacity * PAGE_SIZE) != 0 {
+                warn!("Failed to unmap page cache");
+            }
+            libc::close(self.memfd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::loopback;
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; PAGE_SIZE]
+    }
+
+    fn read(cache: &PageCache, gpa: u64) -> Option<Vec<u8>> {
+        cache.with_page(gpa, |ptr| {
+            unsafe { std::slice::from_raw_parts(ptr, PAGE_SIZE) }.to_vec()
+        })
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let cache = PageCache::new(4 * PAGE_SIZE).unwrap();
+        assert_eq!(cache.capacity(), 4);
+        assert!(!cache.contains(0x1000));
+
+        cache.insert(0x1000, &page(1)).unwrap();
+        cache.insert(0x2000, &page(2)).unwrap();
+        assert_eq!(read(&cache, 0x1000).unwrap(), page(1));
+        assert_eq!(read(&cache, 0x2000).unwrap(), page(2));
+
+        // Re-inserting overwrites in place
+        cache.insert(0x1000, &page(3)).unwrap();
+        assert_eq!(read(&cache, 0x1000).unwrap(), page(3));
+        assert_eq!(cache.len(), 2);
+
+        assert!(cache.insert(0x3000, &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = PageCache::new(2 * PAGE_SIZE).unwrap();
+        cache.insert(0, &page(0)).unwrap();
+        cache.insert(1 << 12, &page(1)).unwrap();
+
+        // Touch page 0 so page 1 is the eviction victim
+        assert!(read(&cache, 0).is_some());
+        cache.insert(2 << 12, &page(2)).unwrap();
+
+        assert!(!cache.contains(1 << 12));
+        assert_eq!(read(&cache, 0).unwrap(), page(0));
+        assert_eq!(read(&cache, 2 << 12).unwrap(), page(2));
+    }
+
+    #[test]
+    fn test_invalidate_frees_slot() {
+        let cache = PageCache::new(PAGE_SIZE).unwrap();
+        cache.insert(0, &page(0)).unwrap();
+        cache.invalidate(0);
+        assert!(cache.is_empty());
+
+        cache.insert(1 << 12, &page(1)).unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_cached_vs_uncached_fetch() {
+        use rdma_transport::TransportManager;
+        use std::time::Instant;
+
+        const PAGES: u64 = 64;
+        const ROUNDS: usize = 20;
+
+        let owner = TransportManager::new(0).unwrap();
+
+        let mut fetcher = TransportManager::new(1).unwrap();
+        fetcher
+            .connect_peer(0, loopback(owner.local_endpoint()))
+            .unwrap();
+        let gpas: Vec<u64> = (0..PAGES).map(|i| i * PAGE_SIZE as u64).collect();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for &gpa in &gpas {
+                let data = fetcher.fetch_page(gpa, 0).unwrap();
+                std::hint::black_box(&data);
+            }
+        }
+        let uncached = start.elapsed();
+
+        let cache = PageCache::new(PAGES as usize * PAGE_SIZE).unwrap();
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for &gpa in &gpas {
+                if cache.with_page(gpa, std::hint::black_box).is_none() {
+                    cache
+                        .insert(gpa, &fetcher.fetch_page(gpa, 0).unwrap())
+                        .unwrap();
+                }
+            }
+        }
+        let cached = start.elapsed();
+
+        let fetches = PAGES as u32 * ROUNDS as u32;
+        println!(
+            "{} fetches: uncached {:?} ({:?}/page), cached {:?} ({:?}/page), {:.1}x",
+            fetches,
+            uncached,
+            uncached / fetches,
+            cached,
+            cached / fetches,
+            uncached.as_secs_f64() / cached.as_secs_f64()
+        );
+    }
+}
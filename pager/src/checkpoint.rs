@@ -0,0 +1,386 @@
+//! Incremental checkpoints of the page directory
+//!
+//! Writing out the whole directory takes seconds once it holds millions of
+//! entries, so only the first checkpoint of a run is complete (the base).
+//! Later checkpoints append a delta of the pages whose owner changed since
+//! the previous one. `restore` replays the base and then every delta in order.
+//!
+//! The file is a sequence of sections, each a header followed by records:
+//!
+//! ```text
+//! header: b"SSIC" | kind: u8 (0 base, 1 delta) | records: u64
+//! record: first page: u64 | end page: u64 | owner: u8 | node: u32
+//! ```
+//!
+//! All integers are little endian. A record covers `first..end` with one
+//! owner, so a base written from coalesced runs stays small. A section cut
+//! short (e.g. by a crash mid-append) is ignored along with anything after
+//! it.
+
+use crate::{PageDirectory, PageOwner, ShutdownSignal};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"SSIC";
+
+const KIND_BASE: u8 = 0;
+const KIND_DELTA: u8 = 1;
+
+const OWNER_UNKNOWN: u8 = 0;
+const OWNER_LOCAL: u8 = 1;
+const OWNER_REMOTE: u8 = 2;
+const OWNER_SPECULATIVE: u8 = 3;
+
+/// Pages whose owner changed, shared between a directory and its checkpointer
+pub(crate) type ChangedPages = Arc<Mutex<HashSet<u64>>>;
+
+/// What `IncrementalCheckpointer::checkpoint` wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checkpoint {
+    /// The whole directory, as this many runs
+    Base(usize),
+    /// This many pages changed since the previous checkpoint
+    Delta(usize),
+}
+
+/// Writes a base checkpoint, then deltas, of one page directory
+pub struct IncrementalCheckpointer {
+    directory: Arc<PageDirectory>,
+    path: PathBuf,
+    dirty_since_checkpoint: ChangedPages,
+    /// `path` holds a base this run's deltas extend
+    has_base: bool,
+}
+
+impl IncrementalCheckpointer {
+    /// Checkpoint `directory` to `path`, recording its changes from now on
+    ///
+    /// Fails if the directory already has a checkpointer.
+    pub fn new(directory: Arc<PageDirectory>, path: impl Into<PathBuf>) -> Result<Self> {
+        let dirty_since_checkpoint = ChangedPages::default();
+        directory.record_changes(Arc::clone(&dirty_since_checkpoint))?;
+        Ok(Self {
+            directory,
+            path: path.into(),
+            dirty_since_checkpoint,
+            has_base: false,
+        })
+    }
+
+    /// Write the pages changed since the last checkpoint, or a base first
+    pub fn checkpoint(&mut self) -> Result<Checkpoint> {
+        if !self.has_base {
+            let runs = self.write_base()?;
+            self.has_base = true;
+            return Ok(Checkpoint::Base(runs));
+        }
+
+        let mut changed: Vec<u64> = std::mem::take(&mut *self.dirty_since_checkpoint.lock())
+            .into_iter()
+            .collect();
+        if changed.is_empty() {
+            return Ok(Checkpoint::Delta(0));
+        }
+        changed.sort_unstable();
+        // Owners as of now; pages changing again are recorded for next time
+        let records: Vec<(u64, u64, PageOwner)> = changed
+            .iter()
+            .map(|&page_num| (page_num, page_num + 1, self.directory.get_owner(page_num)))
+            .collect();
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open checkpoint {}", self.path.display()))?;
+        write_section(file, KIND_DELTA, &records)
+            .with_context(|| format!("Failed to append to checkpoint {}", self.path.display()))?;
+        debug!("Checkpointed {} changed pages", records.len());
+        Ok(Checkpoint::Delta(records.len()))
+    }
+
+    /// Replace the checkpoint file with the whole directory
+    fn write_base(&self) -> Result<usize> {
+        // Changes from here on belong in the next delta
+        self.dirty_since_checkpoint.lock().clear();
+        let runs = self.directory.coalesced_regions();
+
+        // Written aside and renamed, so a crash leaves the old checkpoint
+        let partial = self.path.with_extension("partial");
+        let file = File::create(&partial)
+            .with_context(|| format!("Failed to create {}", partial.display()))?;
+        write_section(file, KIND_BASE, &runs)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        fs::rename(&partial, &self.path)
+            .with_context(|| format!("Failed to replace checkpoint {}", self.path.display()))?;
+
+        info!(
+            "Wrote base checkpoint of {} pages ({} runs) to {}",
+            self.directory.page_count(),
+            runs.len(),
+            self.path.display()
+        );
+        Ok(runs.len())
+    }
+}
+
+/// Replay the checkpoint at `path` into `directory`, returning the number of
+/// sections applied
+pub fn restore(path: &Path, directory: &PageDirectory) -> Result<usize> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open checkpoint {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let mut sections = 0;
+    loop {
+        let records = match read_section(&mut reader, sections == 0) {
+            Ok(Some(records)) => records,
+            Ok(None) => break,
+            Err(e) if is_truncated(&e) => {
+                warn!(
+                    "Checkpoint {} ends in a partial section; ignoring it",
+                    path.display()
+                );
+                break;
+            }
+            Err(e) => return Err(e.context(format!("Corrupt checkpoint {}", path.display()))),
+        };
+        for (first, end, owner) in records {
+            for page_num in first..end {
+                directory.set_owner(page_num, owner);
+            }
+        }
+        sections += 1;
+    }
+
+    info!(
+        "Restored {} pages from {} checkpoint sections in {}",
+        directory.page_count(),
+        sections,
+        path.display()
+    );
+    Ok(sections)
+}
+
+/// Checkpoint every `interval` on a background thread
+///
+/// A last checkpoint is written on shutdown; without an interval that is the
+/// only one.
+pub fn start_checkpointing(
+    mut checkpointer: IncrementalCheckpointer,
+    interval: Option<Duration>,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<JoinHandle<()>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("Failed to create checkpoint runtime")?;
+
+    thread::Builder::new()
+        .name("pager-checkpoint".to_string())
+        .spawn(move || loop {
+            let stopped = runtime.block_on(async {
+                tokio::select! {
+                    _ = shutdown.wait() => true,
+                    _ = async {
+                        match interval {
+                            Some(interval) => tokio::time::sleep(interval).await,
+                            None => std::future::pending().await,
+                        }
+                    } => false,
+                }
+            });
+            if let Err(e) = checkpointer.checkpoint() {
+                warn!("Checkpoint failed: {:#}", e);
+            }
+            if stopped {
+                break;
+            }
+        })
+        .context("Failed to spawn checkpoint thread")
+}
+
+fn write_section(file: File, kind: u8, records: &[(u64, u64, PageOwner)]) -> Result<()> {
+    let mut out = BufWriter::new(file);
+    out.write_all(MAGIC)?;
+    out.write_all(&[kind])?;
+    out.write_all(&(records.len() as u64).to_le_bytes())?;
+    for &(first, end, owner) in records {
+        let (tag, node) = match owner {
+            PageOwner::Unknown => (OWNER_UNKNOWN, 0),
+            PageOwner::Local | PageOwner::LocalHuge => (OWNER_LOCAL, 0),
+            PageOwner::Remote(node) => (OWNER_REMOTE, node),
+            PageOwner::Speculative(node) => (OWNER_SPECULATIVE, node),
+        };
+        out.write_all(&first.to_le_bytes())?;
+        out.write_all(&end.to_le_bytes())?;
+        out.write_all(&[tag])?;
+        out.write_all(&node.to_le_bytes())?;
+    }
+    let file = out.into_inner().map_err(|e| e.into_error())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Next section's records, or None at the end of the file
+fn read_section(
+    reader: &mut impl Read,
+    expect_base: bool,
+) -> Result<Option<Vec<(u64, u64, PageOwner)>>> {
+    let mut magic = [0u8; 4];
+    match reader.read_exact(&mut magic) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if &magic != MAGIC {
+        return Err(anyhow!("Bad section magic {:02x?}", magic));
+    }
+    let kind = read_u8(reader)?;
+    match (kind, expect_base) {
+        (KIND_BASE, true) | (KIND_DELTA, false) => {}
+        (KIND_BASE | KIND_DELTA, _) => {
+            return Err(anyhow!("Checkpoint does not start with exactly one base"));
+        }
+        _ => return Err(anyhow!("Unknown section kind {}", kind)),
+    }
+
+    let count = read_u64(reader)?;
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let first = read_u64(reader)?;
+        let end = read_u64(reader)?;
+        let tag = read_u8(reader)?;
+        let node = read_u32(reader)?;
+        let owner = match tag {
+            OWNER_UNKNOWN => PageOwner::Unknown,
+            OWNER_LOCAL => PageOwner::Local,
+            OWNER_REMOTE => PageOwner::Remote(node),
+            OWNER_SPECULATIVE => PageOwner::Speculative(node),
+            _ => return Err(anyhow!("Unknown owner tag {}", tag)),
+        };
+        records.push((first, end, owner));
+    }
+    Ok(Some(records))
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8> {
+    let mut bytes = [0u8; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Whether `error` is the file ending inside a section
+fn is_truncated(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_PAGES: u64 = 10_000;
+
+    fn owners(directory: &PageDirectory, pages: u64) -> Vec<PageOwner> {
+        (0..pages).map(|page| directory.get_owner(page)).collect()
+    }
+
+    #[test]
+    fn test_restore_replays_base_and_deltas() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pager.ckpt");
+
+        let directory = Arc::new(PageDirectory::new(0));
+        for page in 0..BASE_PAGES {
+            match page % 3 {
+                0 => directory.claim_page(page),
+                owner => directory.set_owner(page, PageOwner::Remote(owner as u32)),
+            }
+        }
+        let mut checkpointer = IncrementalCheckpointer::new(Arc::clone(&directory), &path).unwrap();
+        assert!(matches!(
+            checkpointer.checkpoint().unwrap(),
+            Checkpoint::Base(_)
+        ));
+
+        // 100 changes over two deltas, including releases and new pages
+        for page in (0..50).map(|i| i * 97) {
+            directory.set_owner(page, PageOwner::Remote(7));
+        }
+        assert_eq!(checkpointer.checkpoint().unwrap(), Checkpoint::Delta(50));
+        for page in 0..25 {
+            directory.set_owner(page * 13, PageOwner::Unknown);
+        }
+        for page in BASE_PAGES..BASE_PAGES + 25 {
+            directory.set_owner(page, PageOwner::Speculative(0));
+        }
+        assert_eq!(checkpointer.checkpoint().unwrap(), Checkpoint::Delta(50));
+        assert_eq!(checkpointer.checkpoint().unwrap(), Checkpoint::Delta(0));
+
+        let restored = PageDirectory::new(0);
+        // The empty delta wrote nothing
+        assert_eq!(restore(&path, &restored).unwrap(), 3);
+        assert_eq!(
+            owners(&restored, BASE_PAGES + 100),
+            owners(&directory, BASE_PAGES + 100)
+        );
+        assert_eq!(restored.page_count(), directory.page_count());
+    }
+
+    #[test]
+    fn test_partial_trailing_section_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pager.ckpt");
+
+        let directory = Arc::new(PageDirectory::new(0));
+        directory.claim_page(1);
+        let mut checkpointer = IncrementalCheckpointer::new(Arc::clone(&directory), &path).unwrap();
+        checkpointer.checkpoint().unwrap();
+        directory.set_owner(2, PageOwner::Remote(1));
+        checkpointer.checkpoint().unwrap();
+
+        // Cut the delta short
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let restored = PageDirectory::new(0);
+        assert_eq!(restore(&path, &restored).unwrap(), 1);
+        assert_eq!(restored.get_owner(1), PageOwner::Local);
+        assert_eq!(restored.get_owner(2), PageOwner::Unknown);
+    }
+
+    #[test]
+    fn test_one_checkpointer_per_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = Arc::new(PageDirectory::new(0));
+        let _first =
+            IncrementalCheckpointer::new(Arc::clone(&directory), dir.path().join("a")).unwrap();
+        assert!(IncrementalCheckpointer::new(directory, dir.path().join("b")).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Periodic compaction of the page directory
+//!
+//! Ownership changes leave entries behind that no longer say anything:
+//! regions whose pages all went back to `Unknown`, pages owned by nodes
+//! that have left the cluster, and per-page overrides a different dominant
+//! owner would make unnecessary. `PageDirectory::compact` drops them; with
+//! a `compaction_interval` configured, the pager runs it on a background
+//! thread, once a day by default.
+
+use crate::{PageDirectory, PagerStats, ShutdownSignal};
+use anyhow::{Context, Result};
+use log::info;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the pager compacts its directory unless configured otherwise
+pub const DEFAULT_COMPACTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What one `PageDirectory::compact` achieved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CompactionStats {
+    /// Regions plus per-page overrides before compacting
+    pub entries_before: usize,
+    pub entries_removed: usize,
+    /// Approximate memory given back by the removed entries
+    pub bytes_freed: usize,
+}
+
+/// Compact `directory` every `interval` until shutdown, forgetting the
+/// pages of nodes in `dead_nodes` as of each run
+pub(crate) fn start_compacting(
+    directory: Arc<PageDirectory>,
+    dead_nodes: Arc<RwLock<HashSet<u32>>>,
+    stats: Arc<RwLock<PagerStats>>,
+    interval: Duration,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<JoinHandle<()>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("Failed to create compaction runtime")?;
+
+    thread::Builder::new()
+        .name("pager-compaction".to_string())
+        .spawn(move || loop {
+            let stopped = runtime.block_on(async {
+                tokio::select! {
+                    _ = shutdown.wait() => true,
+                    _ = tokio::time::sleep(interval) => false,
+                }
+            });
+            if stopped {
+                break;
+            }
+            let dead = dead_nodes.read().clone();
+            let compaction = directory.compact(&dead);
+            stats.write().gc_entries_freed += compaction.entries_removed as u64;
+            info!(
+                "Compacted page directory: {} of {} entries removed, {} bytes freed",
+                compaction.entries_removed, compaction.entries_before, compaction.bytes_freed
+            );
+        })
+        .context("Failed to spawn compaction thread")
+}
@@ -0,0 +1,261 @@
+//! Compressing cold local pages
+//!
+//! Local pages the guest has left alone for a while still take a full page
+//! of host memory each. With compression enabled, a background thread
+//! compresses every `COLD_PAGE_INTERVAL` the local pages not faulted on
+//! since the previous pass (see `PageHeatmap`), keeps the compressed bytes,
+//! and drops the page from the mapping. The guest's next access faults, and
+//! the fault loop decompresses the page back into place.
+//!
+//! A page is write-protected through userfaultfd while it is compressed, so
+//! a guest write racing with the compression blocks on a write-protect
+//! fault instead of being lost; the fault loop resolves it from the
+//! compressed copy like any other access.
+//!
+//! Pages that do not shrink are left alone, as are huge pages.
+
+use crate::{is_resident, PageDirectory, PageOwner, PagerStats, ShutdownSignal, PAGE_SIZE};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use userfaultfd::Uffd;
+
+/// How long a local page goes without a fault before it is compressed
+pub const COLD_PAGE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// zstd level; compression is on the background thread, but decompression
+/// is on the fault path, and both are fastest at low levels
+const COMPRESSION_LEVEL: i32 = 1;
+
+/// Pages faulted on since the last reset
+///
+/// The pager only sees faults, not accesses to pages already present, so a
+/// page counts as hot if it was faulted on.
+#[derive(Debug, Default)]
+pub struct PageHeatmap {
+    touched: Mutex<HashSet<u64>>,
+}
+
+impl PageHeatmap {
+    pub fn record(&self, page_num: u64) {
+        self.touched.lock().insert(page_num);
+    }
+
+    pub fn is_hot(&self, page_num: u64) -> bool {
+        self.touched.lock().contains(&page_num)
+    }
+
+    /// Start a new interval with every page cold
+    pub fn reset(&self) {
+        self.touched.lock().clear();
+    }
+}
+
+/// Keeps cold local pages compressed (see the module docs)
+pub struct ColdPageCompressor {
+    heatmap: PageHeatmap,
+    /// Compressed contents of pages dropped from the mapping, by page number
+    pages: Mutex<HashMap<u64, Vec<u8>>>,
+    stats: Arc<RwLock<PagerStats>>,
+}
+
+impl ColdPageCompressor {
+    pub fn new(stats: Arc<RwLock<PagerStats>>) -> Self {
+        Self {
+            heatmap: PageHeatmap::default(),
+            pages: Mutex::new(HashMap::new()),
+            stats,
+        }
+    }
+
+    /// The guest faulted on `page_num`
+    pub fn record_access(&self, page_num: u64) {
+        self.heatmap.record(page_num);
+    }
+
+    pub fn is_compressed(&self, page_num: u64) -> bool {
+        self.pages.lock().contains_key(&page_num)
+    }
+
+    /// Compress the local page `page_num` of the region at `base` and drop
+    /// it from the mapping
+    ///
+    /// Returns false if the page is not local and resident, or would not
+    /// shrink.
+    pub(crate) fn compress(
+        &self,
+        uffd: &Uffd,
+        base: u64,
+        directory: &PageDirectory,
+        page_num: u64,
+    ) -> Result<bool> {
+        let addr = base + page_num * PAGE_SIZE as u64;
+        // Held until the page is gone, so a fault on it, or a migration of
+        // it, waits for the compressed copy
+        let mut pages = self.pages.lock();
+        if pages.contains_key(&page_num)
+            || directory.get_owner(page_num) != PageOwner::Local
+            || !is_resident(addr)?
+        {
+            return Ok(false);
+        }
+
+        let ptr = addr as *mut libc::c_void;
+        uffd.write_protect(ptr, PAGE_SIZE)
+            .with_context(|| format!("Failed to write-protect page {}", page_num))?;
+        // SAFETY: the page is resident, and write-protected so the guest
+        // cannot change it under us
+        let data = unsafe { std::slice::from_raw_parts(addr as *const u8, PAGE_SIZE) };
+        let compressed = match zstd::bulk::compress(data, COMPRESSION_LEVEL) {
+            Ok(compressed) if compressed.len() < PAGE_SIZE => compressed,
+            result => {
+                uffd.remove_write_protection(ptr, PAGE_SIZE, true)?;
+                return result
+                    .map(|_| false)
+                    .with_context(|| format!("Failed to compress page {}", page_num));
+            }
+        };
+
+        // SAFETY: the page is inside the registered region
+        if unsafe { libc::madvise(ptr, PAGE_SIZE, libc::MADV_DONTNEED) } != 0 {
+            let error = std::io::Error::last_os_error();
+            uffd.remove_write_protection(ptr, PAGE_SIZE, true)?;
+            return Err(anyhow!("Failed to drop page {}: {}", page_num, error));
+        }
+
+        let mut stats = self.stats.write();
+        stats.compressed_pages += 1;
+        stats.bytes_saved += (PAGE_SIZE - compressed.len()) as u64;
+        pages.insert(page_num, compressed);
+        Ok(true)
+    }
+
+    /// Decompress `page_num` if it is compressed, forgetting the compressed
+    /// copy
+    pub(crate) fn take(&self, page_num: u64) -> Result<Option<Vec<u8>>> {
+        let compressed = self.pages.lock().remove(&page_num);
+        compressed
+            .map(|compressed| self.decompress(page_num, &compressed))
+            .transpose()
+    }
+
+    /// Contents of the local page `page_num` of the region at `base`,
+    /// whether it is compressed or mapped
+    ///
+    /// Must not be called for a page that is neither, which would fault.
+    pub(crate) fn read_local(&self, base: u64, page_num: u64) -> Result<Vec<u8>> {
+        // Keeps the page from being compressed while it is read
+        let mut pages = self.pages.lock();
+        if let Some(compressed) = pages.remove(&page_num) {
+            drop(pages);
+            return self.decompress(page_num, &compressed);
+        }
+        let addr = base + page_num * PAGE_SIZE as u64;
+        // SAFETY: the page is local and not compressed, so it is mapped
+        Ok(unsafe { std::slice::from_raw_parts(addr as *const u8, PAGE_SIZE) }.to_vec())
+    }
+
+    /// Drop the compressed copy of `page_num`, which is no longer local
+    pub(crate) fn forget(&self, page_num: u64) {
+        let compressed = self.pages.lock().remove(&page_num);
+        if let Some(compressed) = compressed {
+            self.uncount(&compressed);
+        }
+    }
+
+    /// Decompress a copy just removed from `pages`
+    fn decompress(&self, page_num: u64, compressed: &[u8]) -> Result<Vec<u8>> {
+        self.uncount(compressed);
+        zstd::bulk::decompress(compressed, PAGE_SIZE)
+            .with_context(|| format!("Failed to decompress page {}", page_num))
+    }
+
+    /// Take a copy removed from `pages` out of the stats
+    fn uncount(&self, compressed: &[u8]) {
+        let mut stats = self.stats.write();
+        stats.compressed_pages -= 1;
+        stats.bytes_saved -= (PAGE_SIZE - compressed.len()) as u64;
+    }
+
+    /// Compress the local pages of the region at `base` not faulted on since
+    /// the last pass, returning how many were compressed
+    pub(crate) fn compress_cold(
+        &self,
+        uffd: &Uffd,
+        base: u64,
+        directory: &PageDirectory,
+    ) -> Result<u64> {
+        let mut compressed = 0;
+        for (first, end, owner) in directory.coalesced_regions() {
+            if owner != PageOwner::Local {
+                continue;
+            }
+            for page_num in first..end {
+                if self.heatmap.is_hot(page_num) || directory.is_huge(page_num) {
+                    continue;
+                }
+                if self.compress(uffd, base, directory, page_num)? {
+                    compressed += 1;
+                }
+            }
+        }
+        self.heatmap.reset();
+        Ok(compressed)
+    }
+}
+
+/// Compress cold pages every `COLD_PAGE_INTERVAL` until shutdown
+pub(crate) fn start_compressing(
+    compressor: Arc<ColdPageCompressor>,
+    uffd: Arc<Uffd>,
+    base: u64,
+    directory: Arc<PageDirectory>,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<JoinHandle<()>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("Failed to create compression runtime")?;
+
+    thread::Builder::new()
+        .name("pager-compressor".to_string())
+        .spawn(move || loop {
+            let stopped = runtime.block_on(async {
+                tokio::select! {
+                    _ = shutdown.wait() => true,
+                    _ = tokio::time::sleep(COLD_PAGE_INTERVAL) => false,
+                }
+            });
+            if stopped {
+                break;
+            }
+            match compressor.compress_cold(&uffd, base, &directory) {
+                Ok(0) => debug!("No cold pages to compress"),
+                Ok(pages) => info!("Compressed {} cold pages", pages),
+                Err(e) => {
+                    warn!("Cold page compression stopped: {:#}", e);
+                    break;
+                }
+            }
+        })
+        .context("Failed to spawn compressor thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_reset_cools_pages() {
+        let heatmap = PageHeatmap::default();
+        heatmap.record(7);
+        assert!(heatmap.is_hot(7));
+        assert!(!heatmap.is_hot(8));
+        heatmap.reset();
+        assert!(!heatmap.is_hot(7));
+    }
+}
@@ -0,0 +1,993 @@
+//! Client for a replicated coordinator
+//!
+//! A single coordinator is a single point of failure, so a node can be given
+//! several replica URLs. Each request goes to one replica and fails over to
+//! the next on a network error or a 5xx response; any other response
+//! (including 4xx) is returned to the caller as is. `LbStrategy` picks the
+//! order replicas are tried in.
+//!
+//! Replicas must share cluster state and the coordinator signing key: a node
+//! may authenticate with one replica and send its next request to another.
+//!
+//! In Kubernetes the replicas can instead be discovered from a DNS SRV
+//! record, by configuring a single `srv://` URL such as
+//! `srv://_ssi._tcp.coordinator.svc.cluster.local` (see
+//! `CoordinatorDiscovery`).
+//!
+//! A client speaks for one node, and once `authorize`d sends that node's
+//! auth token with its requests. Requests are async; code outside async
+//! context runs them on the pager's runtime with `Handle::block_on`.
+
+use crate::affinity::{PlacementHint, PLACEMENT_HINT_TIMEOUT};
+use crate::{CoordinatorEndpoint, EndpointsResponse};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
+use parking_lot::Mutex;
+use rdma_transport::rediscovery::{PageLocator, RemotePageInfo};
+use rdma_transport::Endpoint as TransportEndpoint;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Health checks give up after this long
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Prefix of a coordinator URL naming a DNS SRV record to discover the
+/// replicas from (e.g. "srv://_ssi._tcp.coordinator.svc.cluster.local")
+pub const SRV_SCHEME: &str = "srv://";
+
+/// How long discovered replicas are used before the SRV record is resolved
+/// again
+pub const SRV_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Order in which coordinator replicas are tried
+///
+/// Replicas discovered through DNS SRV are always tried in weighted
+/// round-robin order (see `CoordinatorDiscovery`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LbStrategy {
+    /// Start each request at the next replica in turn
+    #[default]
+    RoundRobin,
+    /// Start at the replica that answered fastest last time; replicas whose
+    /// last request failed go last
+    HealthFirst,
+}
+
+/// Coordinator replicas and how to spread requests over them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordinatorConfig {
+    /// Replica base URLs (e.g. "http://10.0.0.1:8000"), or a single
+    /// `SRV_SCHEME` URL to discover them from
+    pub urls: Vec<String>,
+    pub strategy: LbStrategy,
+}
+
+impl CoordinatorConfig {
+    /// A single coordinator
+    pub fn single(url: &str) -> Self {
+        Self {
+            urls: vec![url.to_string()],
+            strategy: LbStrategy::default(),
+        }
+    }
+}
+
+/// One target of an SRV record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+impl SrvTarget {
+    /// Base URL of the coordinator at this target
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.host.trim_end_matches('.'), self.port)
+    }
+}
+
+/// Looks up SRV records
+pub trait SrvResolver: Send + Sync {
+    fn lookup_srv(&self, service_name: &str) -> Result<Vec<SrvTarget>>;
+}
+
+/// The host's DNS configuration (`/etc/resolv.conf`)
+pub struct SystemResolver;
+
+impl SrvResolver for SystemResolver {
+    fn lookup_srv(&self, service_name: &str) -> Result<Vec<SrvTarget>> {
+        // The resolver owns a runtime, so it is not kept around to be
+        // dropped in async context
+        let resolver = hickory_resolver::Resolver::from_system_conf()
+            .context("Failed to read DNS configuration")?;
+        let lookup = resolver
+            .srv_lookup(service_name)
+            .with_context(|| format!("Failed to look up SRV record {}", service_name))?;
+        Ok(lookup
+            .iter()
+            .map(|srv| SrvTarget {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                host: srv.target().to_utf8(),
+                port: srv.port(),
+            })
+            .collect())
+    }
+}
+
+/// Coordinator replicas found through a DNS SRV record
+///
+/// Targets are resolved at most every `SRV_CACHE_TTL`, keeping the old ones
+/// if resolving fails. Requests start at a target of the best (lowest)
+/// priority, picked by smooth weighted round-robin (a weight of 0 counts as
+/// 1), and fail over to the others by priority and then weight.
+pub struct CoordinatorDiscovery {
+    service_name: String,
+    resolver: Arc<dyn SrvResolver>,
+    state: Mutex<DiscoveryState>,
+}
+
+#[derive(Default)]
+struct DiscoveryState {
+    /// By priority, then weight, highest first
+    targets: Vec<SrvTarget>,
+    resolved_at: Option<Instant>,
+    /// Each target's weighted round-robin credit
+    credit: Vec<i64>,
+}
+
+impl CoordinatorDiscovery {
+    pub fn new(service_name: &str, resolver: Arc<dyn SrvResolver>) -> Self {
+        Self {
+            service_name: service_name.to_string(),
+            resolver,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Coordinator URLs of `service_name`'s targets, by priority and then
+    /// weight, from the system resolver
+    pub fn from_dns_srv(service_name: &str) -> Result<Vec<String>> {
+        let discovery = Self::new(service_name, Arc::new(SystemResolver));
+        discovery.refresh()?;
+        Ok(discovery.urls())
+    }
+
+    /// URLs of the targets last resolved, by priority and then weight
+    pub fn urls(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .targets
+            .iter()
+            .map(SrvTarget::url)
+            .collect()
+    }
+
+    /// Whether the targets must be resolved (again) before use
+    fn is_stale(&self) -> bool {
+        self.state
+            .lock()
+            .resolved_at
+            .is_none_or(|resolved| resolved.elapsed() >= SRV_CACHE_TTL)
+    }
+
+    /// Resolve the targets if they are stale
+    fn refresh(&self) -> Result<()> {
+        if !self.is_stale() {
+            return Ok(());
+        }
+        let resolved =
+            self.resolver
+                .lookup_srv(&self.service_name)
+                .and_then(|targets| match targets.is_empty() {
+                    true => Err(anyhow!("SRV record {} has no targets", self.service_name)),
+                    false => Ok(targets),
+                });
+
+        let mut state = self.state.lock();
+        match resolved {
+            Ok(mut targets) => {
+                targets.sort_by_key(|target| (target.priority, Reverse(target.weight)));
+                if targets != state.targets {
+                    debug!("Discovered coordinators {:?}", targets);
+                    state.credit = vec![0; targets.len()];
+                    state.targets = targets;
+                }
+            }
+            Err(e) if !state.targets.is_empty() => {
+                warn!("{:#}; keeping the coordinators found before", e);
+            }
+            Err(e) => return Err(e),
+        }
+        state.resolved_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// URLs in the order a request tries them
+    fn order(&self) -> Vec<String> {
+        let mut state = self.state.lock();
+        let DiscoveryState {
+            targets, credit, ..
+        } = &mut *state;
+        let Some(best) = targets.first().map(|target| target.priority) else {
+            return Vec::new();
+        };
+        let candidates = targets
+            .iter()
+            .take_while(|target| target.priority == best)
+            .count();
+
+        let mut total = 0;
+        for (target, credit) in targets.iter().zip(credit.iter_mut()).take(candidates) {
+            let weight = target.weight.max(1) as i64;
+            *credit += weight;
+            total += weight;
+        }
+        // First of the highest credit, so ties go to the heavier target
+        let picked = (0..candidates)
+            .min_by_key(|&i| Reverse(credit[i]))
+            .expect("targets are never empty");
+        credit[picked] -= total;
+
+        std::iter::once(picked)
+            .chain((0..targets.len()).filter(|&i| i != picked))
+            .map(|i| targets[i].url())
+            .collect()
+    }
+}
+
+/// What the last request to a replica showed
+#[derive(Debug, Clone, Copy, Default)]
+struct ReplicaHealth {
+    last_response: Option<Duration>,
+    failed: bool,
+}
+
+/// HTTP client that fails over between coordinator replicas
+pub struct CoordinatorClient {
+    client: reqwest::Client,
+    /// Coordinator as configured: the first replica, or the `srv://` URL
+    /// the replicas are discovered from
+    base_url: String,
+    /// Node the client sends requests for
+    node_id: u32,
+    /// The node's auth token, once `authorize` gave it
+    bearer: OnceLock<String>,
+    /// Configured replicas; empty if they are discovered
+    urls: Vec<String>,
+    discovery: Option<Arc<CoordinatorDiscovery>>,
+    strategy: LbStrategy,
+    /// Per-request timeout (`PagerConfig::coordinator_timeout_secs`)
+    timeout: Duration,
+    /// Replica the next round-robin request starts at
+    next: AtomicUsize,
+    /// By replica URL
+    health: Mutex<HashMap<String, ReplicaHealth>>,
+}
+
+impl CoordinatorClient {
+    /// Client for `node_id`'s requests to the coordinator in `config`
+    pub fn new(config: CoordinatorConfig, node_id: u32) -> Result<Self> {
+        if config.urls.is_empty() {
+            return Err(anyhow!("No coordinator URLs configured"));
+        }
+        if let Some(service_name) = config.urls[0].strip_prefix(SRV_SCHEME) {
+            if config.urls.len() > 1 {
+                return Err(anyhow!(
+                    "An {} coordinator URL must be the only one",
+                    SRV_SCHEME
+                ));
+            }
+            let discovery = CoordinatorDiscovery::new(service_name, Arc::new(SystemResolver));
+            return Ok(Self::discovered(discovery, node_id));
+        }
+        let urls: Vec<String> = config
+            .urls
+            .iter()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .collect();
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            base_url: urls[0].clone(),
+            node_id,
+            bearer: OnceLock::new(),
+            urls,
+            discovery: None,
+            strategy: config.strategy,
+            timeout: crate::COORDINATOR_TIMEOUT,
+            next: AtomicUsize::new(0),
+            health: Mutex::default(),
+        })
+    }
+
+    /// Client for `node_id`'s requests to the replicas `discovery` finds
+    pub fn discovered(discovery: CoordinatorDiscovery, node_id: u32) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: format!("{}{}", SRV_SCHEME, discovery.service_name),
+            node_id,
+            bearer: OnceLock::new(),
+            urls: Vec::new(),
+            discovery: Some(Arc::new(discovery)),
+            strategy: LbStrategy::default(),
+            timeout: crate::COORDINATOR_TIMEOUT,
+            next: AtomicUsize::new(0),
+            health: Mutex::default(),
+        }
+    }
+
+    /// Coordinator as configured: the first replica, or the `srv://` URL
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Node the client sends requests for
+    pub fn node_id(&self) -> u32 {
+        self.node_id
+    }
+
+    /// Send the node's auth token `bearer` with its requests from now on
+    ///
+    /// A node authenticates once, so only the first token given is kept.
+    pub fn authorize(&self, bearer: String) {
+        if self.bearer.set(bearer).is_err() {
+            warn!("Node {} is already authorized", self.node_id);
+        }
+    }
+
+    /// The node's auth token
+    pub fn bearer(&self) -> Result<&str> {
+        self.bearer
+            .get()
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("Node {} has not authenticated yet", self.node_id))
+    }
+
+    /// Give up on each request after `timeout` rather than 5 seconds
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long each request waits for a replica
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Replica base URLs, in configured order or as last discovered
+    pub fn urls(&self) -> Vec<String> {
+        match &self.discovery {
+            Some(discovery) => discovery.urls(),
+            None => self.urls.clone(),
+        }
+    }
+
+    /// Send a request built by `request` from a replica's base URL, failing
+    /// over to the other replicas as needed
+    pub async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client, &str) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        self.discover().await?;
+        let order = self.order();
+        let mut last_error = None;
+        for url in &order {
+            let start = Instant::now();
+            let result = request(&self.client, url).send().await;
+            match self.check(url, start, result) {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(self.all_failed(order.len(), last_error))
+    }
+
+    /// Where the coordinator would place `page_num` (`GET
+    /// /pages/{page_num}/placement`)
+    pub async fn placement_hint(&self, page_num: u64) -> Result<PlacementHint> {
+        self.send(|client, url| {
+            client
+                .get(format!("{}/pages/{}/placement", url, page_num))
+                .timeout(PLACEMENT_HINT_TIMEOUT)
+        })
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid placement hint response")
+    }
+
+    /// Where page `gpa` can be RDMA-read from (`GET /pages/{gpa}`)
+    pub async fn locate_page(&self, gpa: u64) -> Result<RemotePageInfo> {
+        let info: PageInfoResponse = self
+            .send(|client, url| {
+                client
+                    .get(format!("{}/pages/0x{:x}", url, gpa))
+                    .timeout(self.timeout)
+            })
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid page info response")?;
+        match (info.rdma_addr, info.rdma_rkey) {
+            (Some(addr), Some(rkey)) => Ok(RemotePageInfo {
+                node_id: info.owner_node,
+                addr,
+                rkey,
+            }),
+            _ => Err(anyhow!(
+                "Coordinator has no remote key for page 0x{:x}",
+                gpa
+            )),
+        }
+    }
+
+    /// Advertise the node's transport endpoint to its peers (`POST
+    /// /nodes/{node_id}/endpoint`)
+    pub async fn register(&self, endpoint: &TransportEndpoint) -> Result<()> {
+        let body = CoordinatorEndpoint::from(endpoint);
+        body.validate()
+            .context("Refusing to register invalid endpoint")?;
+        let bearer = self.bearer()?;
+        let response = self
+            .send(|client, url| {
+                client
+                    .post(format!("{}/nodes/{}/endpoint", url, self.node_id))
+                    .bearer_auth(bearer)
+                    .json(&body)
+                    .timeout(self.timeout)
+            })
+            .await
+            .context("Failed to send endpoint registration")?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to register endpoint: {}",
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every node's endpoint (`GET /endpoints`)
+    pub async fn fetch_endpoints(&self) -> Result<EndpointsResponse> {
+        let bearer = self.bearer()?;
+        let response = self
+            .send(|client, url| {
+                client
+                    .get(format!("{}/endpoints", url))
+                    .bearer_auth(bearer)
+                    .timeout(self.timeout)
+            })
+            .await
+            .context("Failed to fetch endpoints")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to fetch endpoints: {}", response.status()));
+        }
+        response
+            .json()
+            .await
+            .context("Failed to parse endpoints response")
+    }
+
+    /// Take the node out of the cluster, endpoint and all (`DELETE
+    /// /nodes/{node_id}`)
+    pub async fn deregister(&self) -> Result<()> {
+        let bearer = self.bearer()?;
+        self.send(|client, url| {
+            client
+                .delete(format!("{}/nodes/{}", url, self.node_id))
+                .bearer_auth(bearer)
+                .timeout(self.timeout)
+        })
+        .await
+        .context("Failed to send deregistration")?
+        .error_for_status()
+        .with_context(|| format!("Failed to deregister node {}", self.node_id))?;
+        Ok(())
+    }
+
+    /// Ping `GET /health` on every replica; true for those that answer 2xx
+    pub async fn health_check_all(&self) -> HashMap<String, bool> {
+        if let Err(e) = self.discover().await {
+            warn!("{:#}", e);
+        }
+        let urls = self.urls();
+        let mut healthy = HashMap::with_capacity(urls.len());
+        for url in urls {
+            let start = Instant::now();
+            let result = self
+                .client
+                .get(format!("{}/health", url))
+                .timeout(HEALTH_CHECK_TIMEOUT)
+                .send()
+                .await;
+            let ok = matches!(&result, Ok(response) if response.status().is_success());
+            self.record(&url, ok.then(|| start.elapsed()));
+            healthy.insert(url, ok);
+        }
+        healthy
+    }
+
+    /// Resolve discovered replicas if they are stale, off the async runtime
+    async fn discover(&self) -> Result<()> {
+        match &self.discovery {
+            Some(discovery) if discovery.is_stale() => {
+                let discovery = Arc::clone(discovery);
+                tokio::task::spawn_blocking(move || discovery.refresh())
+                    .await
+                    .context("Coordinator discovery task failed")?
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Replica URLs in the order to try them
+    fn order(&self) -> Vec<String> {
+        if let Some(discovery) = &self.discovery {
+            return discovery.order();
+        }
+        let count = self.urls.len();
+        match self.strategy {
+            LbStrategy::RoundRobin => {
+                let first = self.next.fetch_add(1, Ordering::Relaxed) % count;
+                (0..count)
+                    .map(|i| self.urls[(first + i) % count].clone())
+                    .collect()
+            }
+            LbStrategy::HealthFirst => {
+                let health = self.health.lock();
+                let mut order = self.urls.clone();
+                // Stable, so untried replicas keep their configured order
+                order.sort_by_key(|url| {
+                    let health = health.get(url).copied().unwrap_or_default();
+                    (health.failed, health.last_response.unwrap_or_default())
+                });
+                order
+            }
+        }
+    }
+
+    /// Note a replica's response time, or `None` if it failed
+    fn record(&self, url: &str, response_time: Option<Duration>) {
+        let mut health = self.health.lock();
+        let health = health.entry(url.to_string()).or_default();
+        *health = ReplicaHealth {
+            last_response: response_time.or(health.last_response),
+            failed: response_time.is_none(),
+        };
+    }
+
+    /// The response if it should go to the caller, or the error to fail over on
+    fn check(
+        &self,
+        url: &str,
+        start: Instant,
+        result: reqwest::Result<reqwest::Response>,
+    ) -> Result<reqwest::Response> {
+        let error = match result {
+            Ok(response) if !response.status().is_server_error() => {
+                self.record(url, Some(start.elapsed()));
+                return Ok(response);
+            }
+            Ok(response) => anyhow!("Coordinator {} returned {}", url, response.status()),
+            Err(e) => anyhow::Error::new(e).context(format!("Coordinator {} unreachable", url)),
+        };
+        self.record(url, None);
+        debug!("{:#}; trying next replica", error);
+        Err(error)
+    }
+
+    fn all_failed(&self, tried: usize, last_error: Option<anyhow::Error>) -> anyhow::Error {
+        let error = last_error.expect("at least one replica is configured");
+        if tried == 1 {
+            return error;
+        }
+        warn!("All {} coordinator replicas failed", tried);
+        error.context("All coordinator replicas failed")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PageInfoResponse {
+    owner_node: u32,
+    #[serde(default)]
+    rdma_addr: Option<u64>,
+    #[serde(default)]
+    rdma_rkey: Option<u32>,
+}
+
+/// `CoordinatorClient::locate_page` for RDMA rediscovery, which looks pages
+/// up outside async context
+pub struct CoordinatorPageLocator {
+    coordinator: Arc<CoordinatorClient>,
+    runtime: Handle,
+}
+
+impl CoordinatorPageLocator {
+    /// Look pages up through `coordinator` on `runtime`
+    pub fn new(coordinator: Arc<CoordinatorClient>, runtime: Handle) -> Self {
+        Self {
+            coordinator,
+            runtime,
+        }
+    }
+}
+
+impl PageLocator for CoordinatorPageLocator {
+    fn locate(&self, gpa: u64) -> Result<RemotePageInfo> {
+        self.runtime.block_on(self.coordinator.locate_page(gpa))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn client(servers: &[&mockito::Server], strategy: LbStrategy) -> CoordinatorClient {
+        CoordinatorClient::new(
+            CoordinatorConfig {
+                urls: servers.iter().map(|s| s.url()).collect(),
+                strategy,
+            },
+            0,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_on_server_error() {
+        let mut failing = mockito::Server::new_async().await;
+        let mut healthy = mockito::Server::new_async().await;
+        let first = failing
+            .mock("GET", "/endpoints")
+            .with_status(500)
+            .create_async()
+            .await;
+        let second = healthy
+            .mock("GET", "/endpoints")
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let coordinator = client(&[&failing, &healthy], LbStrategy::HealthFirst);
+        let response = coordinator
+            .send(|client, url| client.get(format!("{}/endpoints", url)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        first.assert_async().await;
+        second.assert_async().await;
+
+        // The failed replica is now tried last
+        assert_eq!(coordinator.order(), vec![healthy.url(), failing.url()]);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let mut first = mockito::Server::new_async().await;
+        let mut second = mockito::Server::new_async().await;
+        first
+            .mock("GET", "/endpoints")
+            .with_status(401)
+            .create_async()
+            .await;
+        let untouched = second
+            .mock("GET", "/endpoints")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let coordinator = client(&[&first, &second], LbStrategy::HealthFirst);
+        let response = coordinator
+            .send(|client, url| client.get(format!("{}/endpoints", url)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        untouched.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_all_replicas_failing_is_an_error() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/endpoints")
+            .with_status(503)
+            .create_async()
+            .await;
+        let coordinator = client(&[&server, &server], LbStrategy::RoundRobin);
+        let result = coordinator
+            .send(|client, url| client.get(format!("{}/endpoints", url)))
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_round_robin_rotates_start() {
+        let coordinator = CoordinatorClient::new(
+            CoordinatorConfig {
+                urls: vec!["http://a".into(), "http://b".into(), "http://c/".into()],
+                strategy: LbStrategy::RoundRobin,
+            },
+            0,
+        )
+        .unwrap();
+        assert_eq!(coordinator.base_url(), "http://a");
+        assert_eq!(coordinator.urls()[2], "http://c");
+        assert_eq!(coordinator.order(), ["http://a", "http://b", "http://c"]);
+        assert_eq!(coordinator.order(), ["http://b", "http://c", "http://a"]);
+        assert_eq!(coordinator.order(), ["http://c", "http://a", "http://b"]);
+        assert_eq!(coordinator.order(), ["http://a", "http://b", "http://c"]);
+
+        assert!(CoordinatorClient::new(
+            CoordinatorConfig {
+                urls: vec![],
+                strategy: LbStrategy::RoundRobin,
+            },
+            0,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_locates_page_for_rdma_read() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/pages/0x2000")
+            .with_body(
+                r#"{"gpa": "0x2000", "owner_node": 2, "rdma_addr": 140000000, "rdma_rkey": 77}"#,
+            )
+            .create();
+        server
+            .mock("GET", "/pages/0x3000")
+            .with_body(r#"{"gpa": "0x3000", "owner_node": 0, "heat": 0}"#)
+            .create();
+
+        // RDMA rediscovery looks pages up outside async context
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = CoordinatorPageLocator::new(
+            Arc::new(client(&[&server], LbStrategy::RoundRobin)),
+            runtime.handle().clone(),
+        );
+        assert_eq!(
+            client.locate(0x2000).unwrap(),
+            RemotePageInfo {
+                node_id: 2,
+                addr: 140_000_000,
+                rkey: 77,
+            }
+        );
+        assert!(client.locate(0x3000).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_health_check_all() {
+        let mut up = mockito::Server::new_async().await;
+        let mut down = mockito::Server::new_async().await;
+        up.mock("GET", "/health")
+            .with_status(200)
+            .create_async()
+            .await;
+        down.mock("GET", "/health")
+            .with_status(503)
+            .create_async()
+            .await;
+
+        let coordinator = client(&[&down, &up], LbStrategy::HealthFirst);
+        let health = coordinator.health_check_all().await;
+        assert!(health[&up.url()]);
+        assert!(!health[&down.url()]);
+        assert_eq!(coordinator.order(), vec![up.url(), down.url()]);
+    }
+
+    /// Serves fixed SRV targets, counting lookups
+    struct MockResolver {
+        targets: Vec<SrvTarget>,
+        lookups: AtomicUsize,
+    }
+
+    impl MockResolver {
+        fn new(targets: Vec<SrvTarget>) -> Arc<Self> {
+            Arc::new(Self {
+                targets,
+                lookups: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl SrvResolver for MockResolver {
+        fn lookup_srv(&self, _service_name: &str) -> Result<Vec<SrvTarget>> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(self.targets.clone())
+        }
+    }
+
+    fn target(host: &str, port: u16, priority: u16, weight: u16) -> SrvTarget {
+        SrvTarget {
+            priority,
+            weight,
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_discovery_orders_targets_by_weight() {
+        let resolver = MockResolver::new(vec![
+            target("light.coordinator.", 8000, 10, 1),
+            target("backup.coordinator.", 8000, 20, 100),
+            target("heavy.coordinator.", 8000, 10, 3),
+        ]);
+        let discovery = CoordinatorDiscovery::new("_ssi._tcp.coordinator", resolver.clone());
+        discovery.refresh().unwrap();
+        assert_eq!(
+            discovery.urls(),
+            [
+                "http://heavy.coordinator:8000",
+                "http://light.coordinator:8000",
+                "http://backup.coordinator:8000",
+            ]
+        );
+
+        // Requests start at the priority 10 targets, 3:1, and fail over by
+        // priority then weight
+        let firsts: Vec<String> = (0..8).map(|_| discovery.order().swap_remove(0)).collect();
+        assert_eq!(
+            firsts
+                .iter()
+                .filter(|url| url.starts_with("http://heavy"))
+                .count(),
+            6
+        );
+        assert_eq!(
+            firsts
+                .iter()
+                .filter(|url| url.starts_with("http://light"))
+                .count(),
+            2
+        );
+        assert_eq!(
+            discovery.order()[1..],
+            [
+                "http://light.coordinator:8000",
+                "http://backup.coordinator:8000",
+            ]
+        );
+
+        // Resolved again only once the cache expires
+        discovery.refresh().unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::Relaxed), 1);
+        discovery.state.lock().resolved_at = Instant::now().checked_sub(SRV_CACHE_TTL);
+        discovery.refresh().unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_spreads_requests_over_discovered_coordinators() {
+        let mut heavy = mockito::Server::new_async().await;
+        let mut light = mockito::Server::new_async().await;
+        let port = |server: &mockito::Server| server.socket_address().port();
+        let resolver = MockResolver::new(vec![
+            target("127.0.0.1", port(&heavy), 0, 3),
+            target("127.0.0.1", port(&light), 0, 1),
+        ]);
+        let heavy_hits = heavy
+            .mock("GET", "/endpoints")
+            .expect(3)
+            .create_async()
+            .await;
+        let light_hits = light
+            .mock("GET", "/endpoints")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let coordinator = CoordinatorClient::discovered(
+            CoordinatorDiscovery::new("_ssi._tcp.coordinator", resolver.clone()),
+            0,
+        );
+        assert_eq!(coordinator.base_url(), "srv://_ssi._tcp.coordinator");
+        for _ in 0..4 {
+            let response = coordinator
+                .send(|client, url| client.get(format!("{}/endpoints", url)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        heavy_hits.assert_async().await;
+        light_hits.assert_async().await;
+        assert_eq!(resolver.lookups.load(Ordering::Relaxed), 1);
+
+        assert!(CoordinatorClient::new(
+            CoordinatorConfig {
+                urls: vec!["srv://_ssi._tcp.coordinator".into(), heavy.url()],
+                strategy: LbStrategy::RoundRobin,
+            },
+            0,
+        )
+        .is_err());
+    }
+
+    /// Coordinator that keeps the endpoints nodes register, as the real one
+    /// does
+    fn endpoint_registry() -> axum::Router {
+        use axum::extract::{Path, State};
+        use axum::http::{header, HeaderMap, StatusCode};
+        use axum::routing::{delete, get, post};
+
+        type Endpoints = Arc<Mutex<HashMap<String, serde_json::Value>>>;
+        axum::Router::new()
+            .route(
+                "/nodes/{node_id}/endpoint",
+                post(
+                    |State(endpoints): State<Endpoints>,
+                     Path(node_id): Path<u32>,
+                     headers: HeaderMap,
+                     axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        if headers[header::AUTHORIZATION] != "Bearer token" {
+                            return StatusCode::UNAUTHORIZED;
+                        }
+                        endpoints.lock().insert(node_id.to_string(), body);
+                        StatusCode::CREATED
+                    },
+                ),
+            )
+            .route(
+                "/endpoints",
+                get(|State(endpoints): State<Endpoints>| async move {
+                    axum::Json(serde_json::json!({ "endpoints": &*endpoints.lock() }))
+                }),
+            )
+            .route(
+                "/nodes/{node_id}",
+                delete(
+                    |State(endpoints): State<Endpoints>, Path(node_id): Path<u32>| async move {
+                        match endpoints.lock().remove(&node_id.to_string()) {
+                            Some(_) => StatusCode::OK,
+                            None => StatusCode::NOT_FOUND,
+                        }
+                    },
+                ),
+            )
+            .with_state(Endpoints::default())
+    }
+
+    #[tokio::test]
+    async fn test_register_discover_and_deregister() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, endpoint_registry()).await });
+        let coordinator = CoordinatorClient::new(CoordinatorConfig::single(&url), 3).unwrap();
+        let forger = CoordinatorClient::new(CoordinatorConfig::single(&url), 4).unwrap();
+
+        let endpoint = TransportEndpoint::tcp(([127, 0, 0, 1], 50051).into());
+        // Not authenticated yet
+        assert!(coordinator.register(&endpoint).await.is_err());
+        coordinator.authorize("token".to_string());
+        coordinator.register(&endpoint).await.unwrap();
+        forger.authorize("forged".to_string());
+        assert!(forger.register(&endpoint).await.is_err());
+
+        let listed = coordinator.fetch_endpoints().await.unwrap();
+        assert_eq!(listed.endpoints.len(), 1);
+        assert_eq!(
+            listed.endpoints["3"].to_transport_endpoint().unwrap(),
+            endpoint
+        );
+
+        coordinator.deregister().await.unwrap();
+        let listed = coordinator.fetch_endpoints().await.unwrap();
+        assert!(listed.endpoints.is_empty());
+        assert!(coordinator.deregister().await.is_err());
+    }
+}
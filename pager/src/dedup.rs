@@ -0,0 +1,370 @@
+//! Content-based page deduplication
+//!
+//! Many pages in a cluster hold identical data (zero pages, shared library
+//! text). `DeduplicationLayer` wraps the transport: every page fetched from a
+//! remote node is fingerprinted with xxHash3 and registered in a cluster-wide
+//! index kept by the coordinator. Before a later remote fetch, the index is
+//! asked for the page's fingerprint; if this node already holds data with that
+//! fingerprint, the owner is asked for the hash of its current copy, and only
+//! if the two match is the page installed from the local copy without the
+//! data crossing the network.
+//!
+//! The index is filled by fetchers, so an entry goes stale as soon as the
+//! owner writes the page; the owner's hash is what makes a hit safe.
+//! Fingerprints are 128-bit so accidental collisions are not a practical
+//! concern.
+
+use crate::coordinator::CoordinatorClient;
+use crate::PAGE_SIZE;
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use parking_lot::{Mutex, RwLock};
+use rdma_transport::TransportManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use xxhash_rust::xxh3::xxh3_128;
+
+/// Distinct page contents kept locally for dedup hits (16 MiB of 4K pages)
+const CONTENT_CACHE_PAGES: usize = 4096;
+
+/// Coordinator requests must not hold up the fault path for long
+const COORDINATOR_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// xxHash3-128 of a page's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageFingerprint(pub u128);
+
+impl PageFingerprint {
+    pub fn of(data: &[u8]) -> Self {
+        Self(xxh3_128(data))
+    }
+
+    /// Hex form used by the coordinator
+    pub fn to_hex(self) -> String {
+        format!("{:032x}", self.0)
+    }
+
+    pub fn from_hex(s: &str) -> Result<Self> {
+        u128::from_str_radix(s, 16)
+            .map(Self)
+            .with_context(|| format!("Invalid page fingerprint {:?}", s))
+    }
+}
+
+/// Cluster-wide map of page contents to `(gpa, node_id)` locations
+pub trait DedupIndex: Send + Sync {
+    /// Fingerprint last registered for `gpa` on `node_id`, if any
+    fn lookup(&self, node_id: u32, gpa: u64) -> Result<Option<PageFingerprint>>;
+
+    /// Record that `gpa` on `node_id` holds data with `fingerprint`
+    fn register(&self, fingerprint: PageFingerprint, gpa: u64, node_id: u32) -> Result<()>;
+}
+
+/// Body of `POST /dedup` and response of `GET /dedup/nodes/{node_id}/pages/{gpa}`
+#[derive(Debug, Serialize, Deserialize)]
+struct PageReference {
+    fingerprint: String,
+    gpa: u64,
+    node_id: u32,
+}
+
+/// `DedupIndex` stored in the coordinator
+pub struct CoordinatorDedupIndex {
+    coordinator: Arc<CoordinatorClient>,
+    /// Runs the requests for the fetching thread
+    runtime: Handle,
+}
+
+impl CoordinatorDedupIndex {
+    pub fn new(coordinator: Arc<CoordinatorClient>, runtime: Handle) -> Self {
+        Self {
+            coordinator,
+            runtime,
+        }
+    }
+
+    async fn lookup_async(&self, node_id: u32, gpa: u64) -> Result<Option<PageFingerprint>> {
+        let bearer = self.coordinator.bearer()?;
+        let response = self
+            .coordinator
+            .send(|http, url| {
+                http.get(format!("{}/dedup/nodes/{}/pages/{}", url, node_id, gpa))
+                    .bearer_auth(bearer)
+                    .timeout(COORDINATOR_TIMEOUT)
+            })
+            .await
+            .context("Failed to query dedup index")?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!("Dedup lookup failed: {}", response.status()));
+        }
+
+        let reference: PageReference = response
+            .json()
+            .await
+            .context("Failed to parse dedup lookup response")?;
+        PageFingerprint::from_hex(&reference.fingerprint).map(Some)
+    }
+
+    async fn register_async(&self, reference: &PageReference) -> Result<()> {
+        let bearer = self.coordinator.bearer()?;
+        let response = self
+            .coordinator
+            .send(|http, url| {
+                http.post(format!("{}/dedup", url))
+                    .bearer_auth(bearer)
+                    .json(reference)
+                    .timeout(COORDINATOR_TIMEOUT)
+            })
+            .await
+            .context("Failed to register page fingerprint")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Dedup registration failed: {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+impl DedupIndex for CoordinatorDedupIndex {
+    fn lookup(&self, node_id: u32, gpa: u64) -> Result<Option<PageFingerprint>> {
+        self.runtime.block_on(self.lookup_async(node_id, gpa))
+    }
+
+    fn register(&self, fingerprint: PageFingerprint, gpa: u64, node_id: u32) -> Result<()> {
+        let reference = PageReference {
+            fingerprint: fingerprint.to_hex(),
+            gpa,
+            node_id,
+        };
+        self.runtime.block_on(self.register_async(&reference))
+    }
+}
+
+/// A page returned by `DeduplicationLayer::fetch_page`
+pub struct FetchedPage {
+    pub data: Vec<u8>,
+    /// Installed from a local copy instead of transferred
+    pub dedup_hit: bool,
+}
+
+/// Transport wrapper that skips transfers of already-known page contents
+pub struct DeduplicationLayer {
+    transport: Arc<RwLock<TransportManager>>,
+    index: Box<dyn DedupIndex>,
+    /// Page contents this node has seen, by fingerprint
+    contents: Mutex<HashMap<PageFingerprint, Arc<[u8]>>>,
+    zero_page: PageFingerprint,
+    hits: AtomicU64,
+}
+
+impl DeduplicationLayer {
+    pub fn new(transport: Arc<RwLock<TransportManager>>, index: Box<dyn DedupIndex>) -> Self {
+        Self {
+            transport,
+            index,
+            contents: Mutex::new(HashMap::new()),
+            zero_page: PageFingerprint::of(&[0u8; PAGE_SIZE]),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Fetch `gpa` from `remote_node`, avoiding the transfer on a dedup hit
+    ///
+    /// A local copy is used only if the owner's current hash of the page
+    /// matches the index. Index or hash errors are not fatal: the page is
+    /// then fetched in full, as of directory `epoch` (see
+    /// `TransportManager::fetch_page_at_epoch`).
+    pub fn fetch_page(&self, gpa: u64, remote_node: u32, epoch: u64) -> Result<FetchedPage> {
+        match self.index.lookup(remote_node, gpa) {
+            Ok(Some(fingerprint)) => {
+                if let Some(data) = self.local_copy(fingerprint) {
+                    let current = self
+                        .transport
+                        .read()
+                        .fetch_page_hash(gpa, remote_node, epoch);
+                    match current {
+                        Ok(hash) if PageFingerprint(hash) == fingerprint => {
+                            self.hits.fetch_add(1, Ordering::Relaxed);
+                            debug!(
+                                "Dedup hit for GPA 0x{:x} on node {}: {}",
+                                gpa,
+                                remote_node,
+                                fingerprint.to_hex()
+                            );
+                            return Ok(FetchedPage {
+                                data,
+                                dedup_hit: true,
+                            });
+                        }
+                        Ok(_) => debug!(
+                            "GPA 0x{:x} on node {} changed since it was indexed",
+                            gpa, remote_node
+                        ),
+                        Err(e) => debug!(
+                            "Failed to confirm GPA 0x{:x} with node {}: {:#}",
+                            gpa, remote_node, e
+                        ),
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => debug!("Dedup lookup for GPA 0x{:x} failed: {:#}", gpa, e),
+        }
+
+        let data = self
+            .transport
+            .read()
+            .fetch_page_at_epoch(gpa, remote_node, epoch)
+            .context("Failed to fetch page via transport")?;
+
+        let fingerprint = PageFingerprint::of(&data);
+        self.remember(fingerprint, &data);
+        if let Err(e) = self.index.register(fingerprint, gpa, remote_node) {
+            debug!(
+                "Failed to register GPA 0x{:x} with dedup index: {:#}",
+                gpa, e
+            );
+        }
+
+        Ok(FetchedPage {
+            data,
+            dedup_hit: false,
+        })
+    }
+
+    /// Pages served from local copies so far
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn local_copy(&self, fingerprint: PageFingerprint) -> Option<Vec<u8>> {
+        if fingerprint == self.zero_page {
+            return Some(vec![0u8; PAGE_SIZE]);
+        }
+        self.contents.lock().get(&fingerprint).map(|d| d.to_vec())
+    }
+
+    fn remember(&self, fingerprint: PageFingerprint, data: &[u8]) {
+        if fingerprint == self.zero_page {
+            return;
+        }
+        let mut contents = self.contents.lock();
+        if contents.len() < CONTENT_CACHE_PAGES {
+            contents.entry(fingerprint).or_insert_with(|| data.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::loopback;
+
+    /// In-process stand-in for the coordinator's index
+    #[derive(Default)]
+    struct MemoryIndex {
+        pages: Mutex<HashMap<(u32, u64), PageFingerprint>>,
+    }
+
+    impl DedupIndex for Arc<MemoryIndex> {
+        fn lookup(&self, node_id: u32, gpa: u64) -> Result<Option<PageFingerprint>> {
+            Ok(self.pages.lock().get(&(node_id, gpa)).copied())
+        }
+
+        fn register(&self, fingerprint: PageFingerprint, gpa: u64, node_id: u32) -> Result<()> {
+            self.pages.lock().insert((node_id, gpa), fingerprint);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_hex_round_trip() {
+        let fp = PageFingerprint::of(b"guest page");
+        assert_eq!(PageFingerprint::from_hex(&fp.to_hex()).unwrap(), fp);
+        assert!(PageFingerprint::from_hex("not hex").is_err());
+    }
+
+    #[test]
+    fn test_ten_identical_zero_pages() {
+        // Node 0 serves (zero) pages over TCP
+        let owner = TransportManager::new(0).unwrap();
+
+        let mut fetcher = TransportManager::new(1).unwrap();
+        fetcher
+            .connect_peer(0, loopback(owner.local_endpoint()))
+            .unwrap();
+
+        let index = Arc::new(MemoryIndex::default());
+        let gpas: Vec<u64> = (0..10).map(|i| i * PAGE_SIZE as u64).collect();
+
+        // First node to touch the pages transfers them and fills the index
+        let first =
+            DeduplicationLayer::new(Arc::new(RwLock::new(fetcher)), Box::new(Arc::clone(&index)));
+        for &gpa in &gpas {
+            let page = first.fetch_page(gpa, 0, 0).unwrap();
+            assert!(!page.dedup_hit);
+        }
+        assert_eq!(first.hits(), 0);
+        assert_eq!(index.pages.lock().len(), 10);
+
+        // A second node gets all ten by reference, once node 0 confirms them
+        let mut transport = TransportManager::new(2).unwrap();
+        transport
+            .connect_peer(0, loopback(owner.local_endpoint()))
+            .unwrap();
+        let second = DeduplicationLayer::new(
+            Arc::new(RwLock::new(transport)),
+            Box::new(Arc::clone(&index)),
+        );
+        for &gpa in &gpas {
+            let page = second.fetch_page(gpa, 0, 0).unwrap();
+            assert!(page.dedup_hit);
+            assert_eq!(page.data, vec![0u8; PAGE_SIZE]);
+        }
+        assert_eq!(second.hits(), 10);
+
+        // Unknown pages still need a real fetch
+        assert!(!second.fetch_page(0x10_0000, 0, 0).unwrap().dedup_hit);
+    }
+
+    #[test]
+    fn test_owner_write_invalidates_fingerprint() {
+        let owner = TransportManager::new(0).unwrap();
+        let mut transport = TransportManager::new(1).unwrap();
+        transport
+            .connect_peer(0, loopback(owner.local_endpoint()))
+            .unwrap();
+
+        let index = Arc::new(MemoryIndex::default());
+        let layer = DeduplicationLayer::new(
+            Arc::new(RwLock::new(transport)),
+            Box::new(Arc::clone(&index)),
+        );
+        assert!(!layer.fetch_page(0x1000, 0, 0).unwrap().dedup_hit);
+        assert!(layer.fetch_page(0x1000, 0, 0).unwrap().dedup_hit);
+
+        // The owner's copy changes after it was indexed as a zero page
+        let mut writer = TransportManager::new(2).unwrap();
+        writer
+            .connect_peer(0, loopback(owner.local_endpoint()))
+            .unwrap();
+        writer.send_page(0x1000, &[7u8; PAGE_SIZE], 0).unwrap();
+
+        let page = layer.fetch_page(0x1000, 0, 0).unwrap();
+        assert!(!page.dedup_hit);
+        assert_eq!(page.data, vec![7u8; PAGE_SIZE]);
+        assert_eq!(
+            index.pages.lock()[&(0, 0x1000)],
+            PageFingerprint::of(&[7u8; PAGE_SIZE])
+        );
+        assert_eq!(layer.hits(), 1);
+    }
+}
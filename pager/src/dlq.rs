@@ -0,0 +1,103 @@
+//! Faults given up on
+//!
+//! A remote fault that keeps failing (corrupted data on the owner, a
+//! hardware error) would otherwise leave its vCPU blocked for good. After
+//! `DEFAULT_MAX_FAULT_RETRIES` retries `handle_pagefault` resolves the page
+//! with zeros, forgets its owner and records it in the `DeadLetterQueue`,
+//! where operators can find it (`GET /api/v1/dlq`).
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Retries of a failing remote fault before it is dead-lettered
+pub const DEFAULT_MAX_FAULT_RETRIES: u32 = 5;
+
+/// Entries kept; the oldest go first
+pub const DLQ_CAPACITY: usize = 1000;
+
+/// One fault given up on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DlqEntry {
+    /// Guest address, as an offset into the pager's region
+    pub gpa: u64,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Last error the fault failed with
+    pub error: String,
+}
+
+/// The most recent `DLQ_CAPACITY` faults given up on
+#[derive(Debug)]
+pub struct DeadLetterQueue {
+    entries: Mutex<VecDeque<DlqEntry>>,
+    capacity: usize,
+}
+
+impl Default for DeadLetterQueue {
+    fn default() -> Self {
+        Self::new(DLQ_CAPACITY)
+    }
+}
+
+impl DeadLetterQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Record the fault at `gpa`, dropping the oldest entry if full
+    pub fn push(&self, gpa: u64, error: String) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let mut entries = self.entries.lock();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(DlqEntry {
+            gpa,
+            timestamp,
+            error,
+        });
+    }
+
+    /// Entries, oldest first
+    pub fn entries(&self) -> Vec<DlqEntry> {
+        self.entries.lock().iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_entries_evicted_at_capacity() {
+        let dlq = DeadLetterQueue::new(3);
+        for gpa in 0..5 {
+            dlq.push(gpa * 4096, format!("failure {}", gpa));
+        }
+        let entries = dlq.entries();
+        assert_eq!(dlq.len(), 3);
+        assert_eq!(
+            entries.iter().map(|entry| entry.gpa).collect::<Vec<_>>(),
+            vec![2 * 4096, 3 * 4096, 4 * 4096]
+        );
+        assert_eq!(entries[2].error, "failure 4");
+        assert!(entries[0].timestamp > 0);
+        assert_eq!(DeadLetterQueue::default().capacity, DLQ_CAPACITY);
+    }
+}
@@ -0,0 +1,284 @@
+//! Page ownership gossip over UDP multicast
+//!
+//! Learning every claim from the coordinator makes it a bottleneck as the
+//! cluster grows. With gossip enabled, each node multicasts a
+//! `GossipMsg::PageClaimed` whenever its directory claims a page, and every
+//! other node in the group records the claimer as the page's owner.
+//!
+//! Gossip is best effort: datagrams may be lost, and broadcasts beyond
+//! `MAX_BROADCASTS_PER_SEC` are dropped, so the coordinator stays the
+//! authority on ownership. A claim is applied if its epoch (the claimer's
+//! directory epoch) is newer than the last one applied for the page, and
+//! never over a page this node owns itself. Multicast loops back to the
+//! sender, whose own messages are ignored.
+
+use crate::{PageDirectory, PageOwner, PagerStats, ShutdownSignal};
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Multicast group gossip is sent to unless configured otherwise
+pub const DEFAULT_GOSSIP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 0, 0, 1);
+
+pub const DEFAULT_GOSSIP_PORT: u16 = 7946;
+
+/// Broadcasts a node sends per second at most
+pub const MAX_BROADCASTS_PER_SEC: u32 = 1000;
+
+/// How often the receiver checks for shutdown
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where gossip is exchanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GossipConfig {
+    pub group: Ipv4Addr,
+    pub port: u16,
+    /// Local interface address to join the group on; unspecified lets the
+    /// kernel choose
+    pub interface: Ipv4Addr,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            group: DEFAULT_GOSSIP_GROUP,
+            port: DEFAULT_GOSSIP_PORT,
+            interface: Ipv4Addr::UNSPECIFIED,
+        }
+    }
+}
+
+/// One datagram of gossip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GossipMsg {
+    PageClaimed {
+        page_num: u64,
+        node_id: u32,
+        epoch: u64,
+    },
+}
+
+/// Broadcasts left in the current one-second window
+#[derive(Debug)]
+struct BroadcastBudget {
+    window_start: Instant,
+    sent: u32,
+}
+
+impl BroadcastBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            sent: 0,
+        }
+    }
+
+    /// Use up one broadcast, if any are left
+    fn take(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            *self = Self::new(now);
+        }
+        if self.sent >= MAX_BROADCASTS_PER_SEC {
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+}
+
+/// This node's membership of the gossip group
+pub struct OwnershipGossip {
+    node_id: u32,
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    budget: Mutex<BroadcastBudget>,
+    /// Epoch of the last claim applied, by page
+    applied: Mutex<HashMap<u64, u64>>,
+    stats: Arc<RwLock<PagerStats>>,
+}
+
+impl OwnershipGossip {
+    /// Join the group in `config`
+    pub fn join(
+        node_id: u32,
+        config: GossipConfig,
+        stats: Arc<RwLock<PagerStats>>,
+    ) -> Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+            .context("Failed to create gossip socket")?;
+        // Other nodes on this host listen on the same port
+        socket.set_reuse_address(true)?;
+        socket
+            .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.port).into())
+            .with_context(|| format!("Failed to bind gossip port {}", config.port))?;
+        socket
+            .join_multicast_v4(&config.group, &config.interface)
+            .with_context(|| format!("Failed to join multicast group {}", config.group))?;
+        if !config.interface.is_unspecified() {
+            socket.set_multicast_if_v4(&config.interface)?;
+        }
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+
+        info!(
+            "Node {} gossiping ownership on {}:{}",
+            node_id, config.group, config.port
+        );
+        Ok(Self {
+            node_id,
+            socket: socket.into(),
+            group: SocketAddrV4::new(config.group, config.port),
+            budget: Mutex::new(BroadcastBudget::new(Instant::now())),
+            applied: Mutex::new(HashMap::new()),
+            stats,
+        })
+    }
+
+    /// Tell the group this node claimed `page_num` at directory `epoch`
+    ///
+    /// Dropped if this second's broadcasts are used up or the send fails.
+    pub fn announce_claim(&self, page_num: u64, epoch: u64) {
+        if !self.budget.lock().take(Instant::now()) {
+            debug!("Gossip budget used up; not announcing page {}", page_num);
+            return;
+        }
+        let msg = GossipMsg::PageClaimed {
+            page_num,
+            node_id: self.node_id,
+            epoch,
+        };
+        let bytes = serde_json::to_vec(&msg).expect("gossip messages serialize");
+        match self.socket.send_to(&bytes, self.group) {
+            Ok(_) => self.stats.write().gossip_msgs_sent += 1,
+            Err(e) => debug!("Failed to announce page {}: {}", page_num, e),
+        }
+    }
+
+    /// Apply the group's claims to `directory` on a background thread
+    /// until shutdown
+    pub fn start_receiving(
+        self: &Arc<Self>,
+        directory: Arc<PageDirectory>,
+        shutdown: Arc<ShutdownSignal>,
+    ) -> Result<JoinHandle<()>> {
+        let gossip = Arc::clone(self);
+        thread::Builder::new()
+            .name("pager-gossip".to_string())
+            .spawn(move || {
+                let mut buf = [0u8; 512];
+                while !shutdown.is_triggered() {
+                    let len = match gossip.socket.recv(&mut buf) {
+                        Ok(len) => len,
+                        Err(e)
+                            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                        {
+                            continue
+                        }
+                        Err(e) => {
+                            warn!("Gossip receiver stopped: {}", e);
+                            return;
+                        }
+                    };
+                    match serde_json::from_slice(&buf[..len]) {
+                        Ok(msg) => {
+                            gossip.apply(msg, &directory);
+                        }
+                        Err(e) => debug!("Ignoring malformed gossip: {}", e),
+                    }
+                }
+            })
+            .context("Failed to spawn gossip thread")
+    }
+
+    /// Record a claim in `directory`, returning whether it changed anything
+    fn apply(&self, msg: GossipMsg, directory: &PageDirectory) -> bool {
+        let GossipMsg::PageClaimed {
+            page_num,
+            node_id,
+            epoch,
+        } = msg;
+        if node_id == self.node_id {
+            return false;
+        }
+        self.stats.write().gossip_msgs_received += 1;
+
+        let mut applied = self.applied.lock();
+        if applied.get(&page_num).is_some_and(|&seen| seen >= epoch) {
+            return false;
+        }
+        if matches!(
+            directory.get_owner(page_num),
+            PageOwner::Local | PageOwner::Speculative(_)
+        ) {
+            debug!(
+                "Node {} claims page {}, which is ours; leaving it to the coordinator",
+                node_id, page_num
+            );
+            return false;
+        }
+        applied.insert(page_num, epoch);
+        directory.set_owner(page_num, PageOwner::Remote(node_id));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unused_port() -> u16 {
+        UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn claim(page_num: u64, node_id: u32, epoch: u64) -> GossipMsg {
+        GossipMsg::PageClaimed {
+            page_num,
+            node_id,
+            epoch,
+        }
+    }
+
+    #[test]
+    fn test_broadcasts_limited_per_second() {
+        let start = Instant::now();
+        let mut budget = BroadcastBudget::new(start);
+        let sent = (0..1500).filter(|_| budget.take(start)).count();
+        assert_eq!(sent, MAX_BROADCASTS_PER_SEC as usize);
+        assert!(budget.take(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_newer_claims_applied() {
+        let config = GossipConfig {
+            port: unused_port(),
+            ..Default::default()
+        };
+        let stats = Arc::new(RwLock::new(PagerStats::default()));
+        let gossip = OwnershipGossip::join(0, config, Arc::clone(&stats)).unwrap();
+        let directory = PageDirectory::new(0);
+
+        assert!(gossip.apply(claim(1, 1, 5), &directory));
+        assert_eq!(directory.get_owner(1), PageOwner::Remote(1));
+        // Older news, our own echo, and pages we own are ignored
+        assert!(!gossip.apply(claim(1, 2, 4), &directory));
+        assert!(!gossip.apply(claim(2, 0, 9), &directory));
+        directory.claim_page(3);
+        assert!(!gossip.apply(claim(3, 2, 9), &directory));
+        assert!(gossip.apply(claim(1, 2, 6), &directory));
+        assert_eq!(directory.get_owner(1), PageOwner::Remote(2));
+        assert_eq!(stats.read().gossip_msgs_received, 4);
+    }
+}
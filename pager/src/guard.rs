@@ -0,0 +1,300 @@
+//! Guard pages around the pager's memory region
+//!
+//! A guest access that runs past its memory would otherwise land in whatever
+//! the host has mapped next to the region. `GuardPages` maps an inaccessible
+//! (`PROT_NONE`) page directly before and/or after the region, so such an
+//! access faults immediately. A `SIGSEGV` handler recognizes faults on guard
+//! pages and reports them before letting the signal kill the process as usual.
+//!
+//! Guard pages lie outside the range registered with userfaultfd, so they
+//! never produce userfaults.
+
+use crate::PAGE_SIZE;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Guard pages that can be registered process-wide at once
+const MAX_GUARD_PAGES: usize = 16;
+
+/// Start addresses of live guard pages (0 = free slot)
+///
+/// Atomics rather than a lock so the signal handler can read them.
+static GUARD_PAGES: [AtomicU64; MAX_GUARD_PAGES] = [const { AtomicU64::new(0) }; MAX_GUARD_PAGES];
+
+/// `SIGSEGV` disposition before ours, for faults that are not ours
+static PREVIOUS_HANDLER: OnceLock<SigAction> = OnceLock::new();
+static INSTALL_LOCK: Mutex<()> = Mutex::new(());
+
+/// `PROT_NONE` pages bracketing a memory region; unmapped on drop
+#[derive(Debug)]
+pub struct GuardPages {
+    pages: Vec<u64>,
+}
+
+impl GuardPages {
+    /// Map guard pages before and/or after `base..base+len`
+    ///
+    /// Fails if the neighbouring page is already mapped.
+    pub fn new(base: u64, len: usize, before: bool, after: bool) -> Result<Self> {
+        if !base.is_multiple_of(PAGE_SIZE as u64) || !len.is_multiple_of(PAGE_SIZE) {
+            return Err(anyhow!(
+                "Region 0x{:x}+0x{:x} is not page aligned",
+                base,
+                len
+            ));
+        }
+        install_sigsegv_handler()?;
+
+        let mut guard = Self { pages: Vec::new() };
+        if before {
+            let addr = base
+                .checked_sub(PAGE_SIZE as u64)
+                .ok_or_else(|| anyhow!("No room for a guard page below 0x{:x}", base))?;
+            guard.map(addr)?;
+        }
+        if after {
+            guard.map(base + len as u64)?;
+        }
+
+        info!(
+            "Guard pages for 0x{:x}+0x{:x}: {:x?}",
+            base, len, guard.pages
+        );
+        Ok(guard)
+    }
+
+    /// Start addresses of the guard pages
+    pub fn pages(&self) -> &[u64] {
+        &self.pages
+    }
+
+    fn map(&mut self, addr: u64) -> Result<()> {
+        // SAFETY: MAP_FIXED_NOREPLACE never replaces an existing mapping
+        let mapped = unsafe {
+            libc::mmap(
+                addr as *mut libc::c_void,
+                PAGE_SIZE,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+        if mapped == libc::MAP_FAILED {
+            return Err(anyhow!(
+                "Failed to map guard page at 0x{:x}: {}",
+                addr,
+                std::io::Error::last_os_error()
+            ));
+        }
+        if mapped as u64 != addr {
+            // Kernels before 4.17 treat MAP_FIXED_NOREPLACE as a hint
+            unsafe { libc::munmap(mapped, PAGE_SIZE) };
+            return Err(anyhow!("Address 0x{:x} is already mapped", addr));
+        }
+
+        let registered = GUARD_PAGES.iter().any(|slot| {
+            slot.compare_exchange(0, addr, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        });
+        if !registered {
+            unsafe { libc::munmap(mapped, PAGE_SIZE) };
+            return Err(anyhow!("Too many guard pages (max {})", MAX_GUARD_PAGES));
+        }
+
+        self.pages.push(addr);
+        Ok(())
+    }
+}
+
+impl Drop for GuardPages {
+    fn drop(&mut self) {
+        for &addr in &self.pages {
+            for slot in &GUARD_PAGES {
+                let _ = slot.compare_exchange(addr, 0, Ordering::AcqRel, Ordering::Acquire);
+            }
+            // SAFETY: we mapped this page and nothing else refers to it
+            if unsafe { libc::munmap(addr as *mut libc::c_void, PAGE_SIZE) } != 0 {
+                warn!("Failed to unmap guard page at 0x{:x}", addr);
+            }
+        }
+    }
+}
+
+fn is_guard_page(addr: u64) -> bool {
+    let page = addr & !(PAGE_SIZE as u64 - 1);
+    page != 0
+        && GUARD_PAGES
+            .iter()
+            .any(|slot| slot.load(Ordering::Acquire) == page)
+}
+
+fn install_sigsegv_handler() -> Result<()> {
+    // Serialize installs so the saved action is never our own handler
+    let _installing = INSTALL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if PREVIOUS_HANDLER.get().is_some() {
+        return Ok(());
+    }
+
+    let action = SigAction::new(
+        SigHandler::SigAction(handle_sigsegv),
+        SaFlags::SA_SIGINFO | SaFlags::SA_ONSTACK,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only touches atomics and calls async-signal-safe functions
+    let previous = unsafe { sigaction(Signal::SIGSEGV, &action) }
+        .map_err(|e| anyhow!("Failed to install SIGSEGV handler: {}", e))?;
+    let _ = PREVIOUS_HANDLER.set(previous);
+    Ok(())
+}
+
+extern "C" fn handle_sigsegv(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    // SAFETY: the kernel passes a valid siginfo for SA_SIGINFO handlers
+    let addr = unsafe { (*info).si_addr() } as u64;
+
+    if is_guard_page(addr) {
+        report_violation(addr);
+        // Returning re-executes the access, which now kills the process
+        reset_to_default();
+        return;
+    }
+
+    match PREVIOUS_HANDLER.get().map(|action| action.handler()) {
+        Some(SigHandler::SigAction(handler)) => handler(signal, info, context),
+        Some(SigHandler::Handler(handler)) => handler(signal),
+        Some(SigHandler::SigIgn) => {}
+        Some(SigHandler::SigDfl) | None => reset_to_default(),
+    }
+}
+
+fn reset_to_default() {
+    // SAFETY: restoring the default disposition is async-signal-safe
+    unsafe { libc::signal(libc::SIGSEGV, libc::SIG_DFL) };
+}
+
+/// Write `Guard page violation: addr=0x...` to stderr without allocating
+fn report_violation(addr: u64) {
+    const PREFIX: &[u8] = b"Guard page violation: addr=0x";
+
+    let mut buf = [0u8; PREFIX.len() + 16 + 1];
+    buf[..PREFIX.len()].copy_from_slice(PREFIX);
+    let mut len = PREFIX.len();
+
+    let digits = (64 - addr.leading_zeros()).div_ceil(4).max(1);
+    for i in (0..digits).rev() {
+        buf[len] = b"0123456789abcdef"[((addr >> (i * 4)) & 0xf) as usize];
+        len += 1;
+    }
+    buf[len] = b'\n';
+    len += 1;
+
+    // SAFETY: write(2) is async-signal-safe and `buf` outlives the call
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            buf.as_ptr() as *const libc::c_void,
+            len,
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_region(len: usize) -> *mut u8 {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        base as *mut u8
+    }
+
+    #[test]
+    fn test_guard_pages_registered_and_released() {
+        // Reserve 4 pages and free the outer two so the guards have room
+        let outer = map_region(4 * PAGE_SIZE);
+        let base = unsafe { outer.add(PAGE_SIZE) };
+        unsafe {
+            libc::munmap(outer as *mut libc::c_void, PAGE_SIZE);
+            libc::munmap(base.add(2 * PAGE_SIZE) as *mut libc::c_void, PAGE_SIZE);
+        }
+
+        let guard = GuardPages::new(base as u64, 2 * PAGE_SIZE, true, true).unwrap();
+        let before = base as u64 - PAGE_SIZE as u64;
+        let after = base as u64 + 2 * PAGE_SIZE as u64;
+        assert_eq!(guard.pages(), &[before, after]);
+        assert!(is_guard_page(before + 10));
+        assert!(is_guard_page(after));
+        assert!(!is_guard_page(base as u64));
+
+        drop(guard);
+        assert!(!is_guard_page(after));
+        unsafe { libc::munmap(base as *mut libc::c_void, 2 * PAGE_SIZE) };
+    }
+
+    #[test]
+    fn test_guard_page_rejects_mapped_neighbour() {
+        let base = map_region(3 * PAGE_SIZE);
+        // The page after the first one is still mapped
+        assert!(GuardPages::new(base as u64, PAGE_SIZE, false, true).is_err());
+        unsafe { libc::munmap(base as *mut libc::c_void, 3 * PAGE_SIZE) };
+    }
+
+    #[test]
+    fn test_write_past_end_hits_guard_page() {
+        let len = 2 * PAGE_SIZE;
+        let outer = map_region(len + PAGE_SIZE);
+        unsafe { libc::munmap(outer.add(len) as *mut libc::c_void, PAGE_SIZE) };
+        let guard = GuardPages::new(outer as u64, len, false, true).unwrap();
+
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+
+        // The write kills the process, so do it in a child
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            unsafe {
+                libc::dup2(pipe[1], libc::STDERR_FILENO);
+                std::ptr::write_volatile(outer.add(len), 1);
+                libc::_exit(0);
+            }
+        }
+        unsafe { libc::close(pipe[1]) };
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFSIGNALED(status), "child exited normally");
+        assert_eq!(libc::WTERMSIG(status), libc::SIGSEGV);
+
+        let mut output = [0u8; 128];
+        let n = unsafe { libc::read(pipe[0], output.as_mut_ptr() as *mut libc::c_void, 128) };
+        unsafe { libc::close(pipe[0]) };
+        let output = std::str::from_utf8(&output[..n.max(0) as usize]).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "Guard page violation: addr=0x{:x}\n",
+                outer as u64 + len as u64
+            )
+        );
+
+        drop(guard);
+        unsafe { libc::munmap(outer as *mut libc::c_void, len) };
+    }
+}
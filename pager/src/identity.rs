@@ -0,0 +1,653 @@
+//! Node identity and coordinator-issued auth tokens
+//!
+//! Each node holds an Ed25519 key pair (`NodeIdentity`). At startup it
+//! registers the public key with the coordinator, signing the request to prove
+//! it holds the private key. The coordinator pins the first key it sees for a
+//! node ID and answers with an `AuthToken` signed by the coordinator's own
+//! key. The token goes into the `Authorization: Bearer` header of every later
+//! coordinator request. Peers never see each other's bearer tokens: as a
+//! connection opens, each side presents its token with a fresh signature by
+//! its own key (`PeerAuth`), and the other checks both.
+//!
+//! Signed messages are plain strings so the Python coordinator can produce
+//! and check them byte for byte:
+//! - registration: `"{node_id}:{timestamp}"`
+//! - token: `"{node_id}:{public_key}:{expiry_ts}"` (public key in hex)
+//! - peer hello: `"{node_id}:{remote_node_id}:{timestamp}"`
+//! - management request: `"manage:{node_id}:{target_node_id}:{timestamp}:{nonce}"`
+//!
+//! The management API never takes a bare bearer token either, since every
+//! node sends its token to the coordinator. A caller signs a
+//! `ManagementCredential` addressed to the node it is managing, and that node
+//! takes each one only once (`ManagementAuth`).
+//!
+//! A registration can also carry the host's `hardware_fingerprint`. The
+//! coordinator turns away a node ID whose fingerprint another node ID
+//! already registered, which catches two pagers on one host given the same
+//! configuration by mistake.
+
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::info;
+use parking_lot::Mutex;
+use rand::RngCore;
+use rdma_transport::PeerAuthenticator;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Ed25519 key pair identifying this node to the cluster
+#[derive(Clone)]
+pub struct NodeIdentity {
+    key: SigningKey,
+}
+
+impl std::fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeIdentity")
+            .field("public_key", &self.public_key_hex())
+            .finish()
+    }
+}
+
+impl NodeIdentity {
+    /// Fresh random key pair
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+
+    /// Load the key pair stored at `path`, or generate one and save it there
+    ///
+    /// The file holds the hex-encoded secret key and is created mode 0600.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let hex_key = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read identity key {}", path.display()))?;
+            let secret: [u8; 32] = hex::decode(hex_key.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow!("Invalid identity key in {}", path.display()))?;
+            return Ok(Self {
+                key: SigningKey::from_bytes(&secret),
+            });
+        }
+
+        let identity = Self::generate();
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to create identity key {}", path.display()))?;
+        writeln!(file, "{}", hex::encode(identity.key.to_bytes()))?;
+        info!(
+            "Generated node identity {} in {}",
+            identity.public_key_hex(),
+            path.display()
+        );
+        Ok(identity)
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Public key as the coordinator stores it
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.key.verifying_key().as_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.key.sign(message)
+    }
+
+    /// Signed body for the coordinator's `POST /auth/register`
+    pub fn registration(&self, node_id: u32, hardware_fingerprint: Option<String>) -> Registration {
+        let timestamp = unix_now();
+        let signature = self.sign(format!("{}:{}", node_id, timestamp).as_bytes());
+        Registration {
+            node_id,
+            public_key: self.public_key_hex(),
+            timestamp,
+            signature: hex::encode(signature.to_bytes()),
+            hardware_fingerprint,
+        }
+    }
+}
+
+/// SHA-256, in hex, of the machine ID, first NIC's MAC address and hostname
+/// of the system whose root file system is at `root`
+///
+/// The machine ID is read from `etc/machine-id`, or `var/lib/dbus/machine-id`
+/// as systemd falls back to; the NIC is the first in name order under
+/// `sys/class/net` with a non-zero address, if any; the hostname is
+/// `proc/sys/kernel/hostname`.
+pub fn hardware_fingerprint(root: &Path) -> Result<String> {
+    let read = |path: &str| fs::read_to_string(root.join(path)).map(|s| s.trim().to_string());
+    let machine_id = read("etc/machine-id")
+        .or_else(|_| read("var/lib/dbus/machine-id"))
+        .context("Failed to read machine ID")?;
+    let hostname = read("proc/sys/kernel/hostname").context("Failed to read hostname")?;
+
+    let mut nics: Vec<_> = fs::read_dir(root.join("sys/class/net"))
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    nics.sort();
+    let mac = nics
+        .iter()
+        .filter_map(|nic| fs::read_to_string(nic.join("address")).ok())
+        .map(|address| address.trim().to_string())
+        .find(|address| !address.is_empty() && address != "00:00:00:00:00:00")
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    for part in [&machine_id, &mac, &hostname] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Body of `POST /auth/register`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub node_id: u32,
+    pub public_key: String,
+    pub timestamp: u64,
+    /// Node's signature over `"{node_id}:{timestamp}"`, hex
+    pub signature: String,
+    /// Host's `hardware_fingerprint`, for the coordinator to check is not
+    /// registered by another node ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware_fingerprint: Option<String>,
+}
+
+/// Response of `POST /auth/register`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationResponse {
+    /// Bearer form of the issued `AuthToken`
+    pub token: String,
+    /// Coordinator's public key, hex
+    pub coordinator_key: String,
+}
+
+/// Coordinator's statement that `public_key` belongs to `node_id`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthToken {
+    pub node_id: u32,
+    /// Node's public key, hex
+    pub public_key: String,
+    /// Unix time after which the token is no longer valid
+    pub expiry_ts: u64,
+    /// Coordinator's signature, hex
+    pub signature: String,
+}
+
+impl AuthToken {
+    /// Sign a token for `node_id` (what the coordinator does on registration)
+    pub fn issue(issuer: &NodeIdentity, node_id: u32, public_key: &str, expiry_ts: u64) -> Self {
+        let signature = issuer.sign(&Self::signed_message(node_id, public_key, expiry_ts));
+        Self {
+            node_id,
+            public_key: public_key.to_string(),
+            expiry_ts,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+
+    fn signed_message(node_id: u32, public_key: &str, expiry_ts: u64) -> Vec<u8> {
+        format!("{}:{}:{}", node_id, public_key, expiry_ts).into_bytes()
+    }
+
+    /// Value for the `Authorization: Bearer` header (base64 of the JSON token)
+    pub fn to_bearer(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).expect("token serializes"))
+    }
+
+    pub fn from_bearer(bearer: &str) -> Result<Self> {
+        let json = BASE64
+            .decode(bearer.trim())
+            .context("Auth token is not base64")?;
+        serde_json::from_slice(&json).context("Auth token is not valid JSON")
+    }
+
+    /// Check that `coordinator_key` signed this token for `node_id` and it has
+    /// not expired
+    pub fn verify(&self, node_id: u32, coordinator_key: &VerifyingKey) -> Result<()> {
+        if self.node_id != node_id {
+            return Err(anyhow!(
+                "Auth token is for node {}, not node {}",
+                self.node_id,
+                node_id
+            ));
+        }
+        if self.expiry_ts <= unix_now() {
+            return Err(anyhow!("Auth token for node {} has expired", node_id));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("Malformed auth token signature"))?;
+        coordinator_key
+            .verify(
+                &Self::signed_message(self.node_id, &self.public_key, self.expiry_ts),
+                &signature,
+            )
+            .map_err(|_| anyhow!("Auth token for node {} has a bad signature", node_id))
+    }
+}
+
+/// How far a peer hello's timestamp may be from our clock
+const HELLO_MAX_SKEW_SECS: u64 = 60;
+
+/// Credentials a node presents to a peer (see `PeerAuth`)
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerHello {
+    token: AuthToken,
+    timestamp: u64,
+    /// Node's signature over `"{node_id}:{remote_node_id}:{timestamp}"`, hex
+    signature: String,
+}
+
+/// Authenticates transport connections with this node's identity
+///
+/// A peer is accepted if the coordinator signed its token and it holds the
+/// token's private key, shown by signing a hello addressed to this node.
+pub struct PeerAuth {
+    node_id: u32,
+    identity: NodeIdentity,
+    token: AuthToken,
+    coordinator_key: VerifyingKey,
+}
+
+impl PeerAuth {
+    pub fn new(
+        node_id: u32,
+        identity: NodeIdentity,
+        token: AuthToken,
+        coordinator_key: VerifyingKey,
+    ) -> Self {
+        Self {
+            node_id,
+            identity,
+            token,
+            coordinator_key,
+        }
+    }
+
+    fn hello_message(node_id: u32, remote_node_id: u32, timestamp: u64) -> Vec<u8> {
+        format!("{}:{}:{}", node_id, remote_node_id, timestamp).into_bytes()
+    }
+}
+
+impl PeerAuthenticator for PeerAuth {
+    fn credentials(&self, remote_node_id: u32) -> Result<Vec<u8>> {
+        let timestamp = unix_now();
+        let signature = self.identity.sign(&Self::hello_message(
+            self.node_id,
+            remote_node_id,
+            timestamp,
+        ));
+        let hello = PeerHello {
+            token: self.token.clone(),
+            timestamp,
+            signature: hex::encode(signature.to_bytes()),
+        };
+        Ok(serde_json::to_vec(&hello)?)
+    }
+
+    fn verify(&self, credentials: &[u8]) -> Result<u32> {
+        let hello: PeerHello =
+            serde_json::from_slice(credentials).context("Peer hello is not valid JSON")?;
+        let node_id = hello.token.node_id;
+        hello.token.verify(node_id, &self.coordinator_key)?;
+        if unix_now().abs_diff(hello.timestamp) > HELLO_MAX_SKEW_SECS {
+            return Err(anyhow!("Hello from node {} is not recent", node_id));
+        }
+
+        let signature = hex::decode(&hello.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("Malformed peer hello signature"))?;
+        parse_public_key(&hello.token.public_key)?
+            .verify(
+                &Self::hello_message(node_id, self.node_id, hello.timestamp),
+                &signature,
+            )
+            .map_err(|_| anyhow!("Hello from node {} has a bad signature", node_id))?;
+        Ok(node_id)
+    }
+}
+
+/// A node's signed request to manage `target_node_id` through its API
+///
+/// Good for `HELLO_MAX_SKEW_SECS` and, thanks to the nonce, only once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagementCredential {
+    pub token: AuthToken,
+    pub target_node_id: u32,
+    pub timestamp: u64,
+    /// Random, hex
+    pub nonce: String,
+    /// Node's signature over
+    /// `"manage:{node_id}:{target_node_id}:{timestamp}:{nonce}"`, hex
+    pub signature: String,
+}
+
+impl ManagementCredential {
+    /// Sign a request to manage `target_node_id` as the holder of `token`
+    pub fn issue(identity: &NodeIdentity, token: &AuthToken, target_node_id: u32) -> Self {
+        let timestamp = unix_now();
+        let mut nonce = [0u8; 16];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+        let signature = identity.sign(&Self::signed_message(
+            token.node_id,
+            target_node_id,
+            timestamp,
+            &nonce,
+        ));
+        Self {
+            token: token.clone(),
+            target_node_id,
+            timestamp,
+            nonce,
+            signature: hex::encode(signature.to_bytes()),
+        }
+    }
+
+    fn signed_message(node_id: u32, target_node_id: u32, timestamp: u64, nonce: &str) -> Vec<u8> {
+        format!(
+            "manage:{}:{}:{}:{}",
+            node_id, target_node_id, timestamp, nonce
+        )
+        .into_bytes()
+    }
+
+    /// Value for the `Authorization: Bearer` header (base64 of the JSON
+    /// credential)
+    pub fn to_bearer(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).expect("credential serializes"))
+    }
+
+    pub fn from_bearer(bearer: &str) -> Result<Self> {
+        let json = BASE64
+            .decode(bearer.trim())
+            .context("Management credential is not base64")?;
+        serde_json::from_slice(&json).context("Management credential is not valid JSON")
+    }
+}
+
+/// Checks `ManagementCredential`s sent to this node's management API
+pub struct ManagementAuth {
+    node_id: u32,
+    coordinator_key: VerifyingKey,
+    /// Nonces already taken, with their credential's timestamp
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ManagementAuth {
+    pub fn new(node_id: u32, coordinator_key: VerifyingKey) -> Self {
+        Self {
+            node_id,
+            coordinator_key,
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Check a bearer credential and return the node that sent it
+    ///
+    /// Accepts a credential addressed to this node, recent, signed by the key
+    /// of a token the coordinator issued, and not seen before.
+    pub fn verify(&self, bearer: &str) -> Result<u32> {
+        let credential = ManagementCredential::from_bearer(bearer)?;
+        let node_id = credential.token.node_id;
+        credential.token.verify(node_id, &self.coordinator_key)?;
+        if credential.target_node_id != self.node_id {
+            return Err(anyhow!(
+                "Management credential is for node {}, not node {}",
+                credential.target_node_id,
+                self.node_id
+            ));
+        }
+        let now = unix_now();
+        if now.abs_diff(credential.timestamp) > HELLO_MAX_SKEW_SECS {
+            return Err(anyhow!(
+                "Management credential from node {} is not recent",
+                node_id
+            ));
+        }
+
+        let signature = hex::decode(&credential.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("Malformed management credential signature"))?;
+        parse_public_key(&credential.token.public_key)?
+            .verify(
+                &ManagementCredential::signed_message(
+                    node_id,
+                    self.node_id,
+                    credential.timestamp,
+                    &credential.nonce,
+                ),
+                &signature,
+            )
+            .map_err(|_| {
+                anyhow!(
+                    "Management credential from node {} has a bad signature",
+                    node_id
+                )
+            })?;
+
+        // Nonces older than the skew window can go: their credentials are
+        // turned away as stale anyway
+        let mut seen = self.seen.lock();
+        seen.retain(|_, timestamp| now.abs_diff(*timestamp) <= HELLO_MAX_SKEW_SECS);
+        if seen
+            .insert(credential.nonce, credential.timestamp)
+            .is_some()
+        {
+            return Err(anyhow!(
+                "Management credential from node {} was already used",
+                node_id
+            ));
+        }
+        Ok(node_id)
+    }
+}
+
+/// Parse a hex-encoded Ed25519 public key
+pub fn parse_public_key(hex_key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("Invalid public key {:?}", hex_key))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid Ed25519 public key")
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expiry() -> u64 {
+        unix_now() + 3600
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let coordinator = NodeIdentity::generate();
+        let node = NodeIdentity::generate();
+        let token = AuthToken::issue(&coordinator, 3, &node.public_key_hex(), expiry());
+
+        let parsed = AuthToken::from_bearer(&token.to_bearer()).unwrap();
+        assert_eq!(parsed, token);
+        parsed.verify(3, &coordinator.verifying_key()).unwrap();
+    }
+
+    #[test]
+    fn test_token_signed_with_wrong_key_rejected() {
+        let coordinator = NodeIdentity::generate();
+        let impostor = NodeIdentity::generate();
+        let node = NodeIdentity::generate();
+
+        // Token forged by a node instead of the coordinator
+        let forged = AuthToken::issue(&impostor, 3, &node.public_key_hex(), expiry());
+        assert!(forged.verify(3, &coordinator.verifying_key()).is_err());
+
+        // Genuine token with the public key swapped for another node's
+        let mut token = AuthToken::issue(&coordinator, 3, &node.public_key_hex(), expiry());
+        token.public_key = impostor.public_key_hex();
+        assert!(token.verify(3, &coordinator.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_token_for_other_node_or_expired_rejected() {
+        let coordinator = NodeIdentity::generate();
+        let key = coordinator.verifying_key();
+        let node = NodeIdentity::generate().public_key_hex();
+
+        assert!(AuthToken::issue(&coordinator, 3, &node, expiry())
+            .verify(4, &key)
+            .is_err());
+        assert!(AuthToken::issue(&coordinator, 3, &node, unix_now() - 1)
+            .verify(3, &key)
+            .is_err());
+    }
+
+    fn peer(coordinator: &NodeIdentity, node_id: u32) -> PeerAuth {
+        let identity = NodeIdentity::generate();
+        let token = AuthToken::issue(coordinator, node_id, &identity.public_key_hex(), expiry());
+        PeerAuth::new(node_id, identity, token, coordinator.verifying_key())
+    }
+
+    #[test]
+    fn test_peer_hello_checked() {
+        let coordinator = NodeIdentity::generate();
+        let (first, second) = (peer(&coordinator, 1), peer(&coordinator, 2));
+        assert_eq!(second.verify(&first.credentials(2).unwrap()).unwrap(), 1);
+
+        // A hello addressed to another node is not accepted here
+        let third = peer(&coordinator, 3);
+        assert!(third.verify(&first.credentials(2).unwrap()).is_err());
+
+        // Nor one signed by a key other than the token's
+        let mut stolen: PeerHello = serde_json::from_slice(&first.credentials(2).unwrap()).unwrap();
+        stolen.token = second.token.clone();
+        assert!(second
+            .verify(&serde_json::to_vec(&stolen).unwrap())
+            .is_err());
+
+        // Nor a token from another coordinator
+        let outsider = peer(&NodeIdentity::generate(), 4);
+        assert!(second.verify(&outsider.credentials(2).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_management_credential_used_once_by_its_target() {
+        let coordinator = NodeIdentity::generate();
+        let caller = NodeIdentity::generate();
+        let token = AuthToken::issue(&coordinator, 1, &caller.public_key_hex(), expiry());
+        let auth = ManagementAuth::new(2, coordinator.verifying_key());
+
+        let credential = ManagementCredential::issue(&caller, &token, 2).to_bearer();
+        assert_eq!(auth.verify(&credential).unwrap(), 1);
+        // Replayed
+        assert!(auth.verify(&credential).is_err());
+
+        // The bare token every node sends the coordinator
+        assert!(auth.verify(&token.to_bearer()).is_err());
+
+        // Addressed to another node
+        let other = ManagementCredential::issue(&caller, &token, 3);
+        assert!(auth.verify(&other.to_bearer()).is_err());
+
+        // Signed by a key other than the token's
+        let stolen = ManagementCredential::issue(&NodeIdentity::generate(), &token, 2);
+        assert!(auth.verify(&stolen.to_bearer()).is_err());
+
+        // Stale
+        let mut stale = ManagementCredential::issue(&caller, &token, 2);
+        stale.timestamp -= 2 * HELLO_MAX_SKEW_SECS;
+        stale.signature = hex::encode(
+            caller
+                .sign(&ManagementCredential::signed_message(
+                    1,
+                    2,
+                    stale.timestamp,
+                    &stale.nonce,
+                ))
+                .to_bytes(),
+        );
+        assert!(auth.verify(&stale.to_bearer()).is_err());
+    }
+
+    #[test]
+    fn test_registration_signature() {
+        let node = NodeIdentity::generate();
+        let registration = node.registration(7, None);
+
+        let key = parse_public_key(&registration.public_key).unwrap();
+        let signature =
+            Signature::from_slice(&hex::decode(&registration.signature).unwrap()).unwrap();
+        let message = format!("7:{}", registration.timestamp);
+        assert!(key.verify(message.as_bytes(), &signature).is_ok());
+    }
+
+    #[test]
+    fn test_hardware_fingerprint_follows_machine_id() {
+        let machine = |machine_id: &str| {
+            let root = tempfile::tempdir().unwrap();
+            fs::create_dir_all(root.path().join("etc")).unwrap();
+            fs::write(root.path().join("etc/machine-id"), machine_id).unwrap();
+            fs::create_dir_all(root.path().join("proc/sys/kernel")).unwrap();
+            fs::write(root.path().join("proc/sys/kernel/hostname"), "node-a\n").unwrap();
+            for (nic, address) in [("lo", "00:00:00:00:00:00"), ("eth0", "52:54:00:12:34:56")] {
+                let dir = root.path().join("sys/class/net").join(nic);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("address"), format!("{}\n", address)).unwrap();
+            }
+            root
+        };
+        let first = machine("0123456789abcdef0123456789abcdef\n");
+        let other = machine("fedcba9876543210fedcba9876543210\n");
+
+        let fingerprint = hardware_fingerprint(first.path()).unwrap();
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(hardware_fingerprint(first.path()).unwrap(), fingerprint);
+        assert_ne!(hardware_fingerprint(other.path()).unwrap(), fingerprint);
+
+        fs::remove_file(first.path().join("etc/machine-id")).unwrap();
+        assert!(hardware_fingerprint(first.path()).is_err());
+    }
+
+    #[test]
+    fn test_key_file_persists_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.key");
+
+        let first = NodeIdentity::load_or_generate(&path).unwrap();
+        let second = NodeIdentity::load_or_generate(&path).unwrap();
+        assert_eq!(first.public_key_hex(), second.public_key_hex());
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
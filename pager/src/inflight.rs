@@ -0,0 +1,195 @@
+//! De-duplication of concurrent remote fetches
+//!
+//! When several threads fault on the same remote page at once, only the
+//! first (the leader) should fetch it. `InFlightTracker` records a `Notify`
+//! per GPA being fetched; later threads (followers) wait on it and, once the
+//! leader is done, resolve their fault from the page cache. The leader's
+//! `UFFDIO_COPY` has usually installed the page already, in which case the
+//! followers' copies fail with `EEXIST`, which counts as success.
+//!
+//! Fault handler threads are plain OS threads, so followers block on the
+//! `Notify` with a minimal thread-parking executor rather than a runtime.
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+use tokio::sync::Notify;
+
+/// Remote fetches in progress, by GPA
+#[derive(Debug, Default)]
+pub struct InFlightTracker {
+    fetches: DashMap<u64, Arc<Notify>>,
+}
+
+/// Role of a thread faulting on a GPA
+pub enum InFlight<'a> {
+    /// No fetch was running; this thread must fetch the page
+    Leader(LeaderGuard<'a>),
+    /// Another thread is fetching the page
+    Follower(Waiter<'a>),
+}
+
+impl InFlightTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Join or start the fetch of `gpa`
+    pub fn begin(&self, gpa: u64) -> InFlight<'_> {
+        match self.fetches.entry(gpa) {
+            Entry::Occupied(entry) => InFlight::Follower(Waiter {
+                tracker: self,
+                gpa,
+                notify: Arc::clone(entry.get()),
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(Notify::new()));
+                InFlight::Leader(LeaderGuard { tracker: self, gpa })
+            }
+        }
+    }
+
+    /// Number of fetches in progress
+    pub fn len(&self) -> usize {
+        self.fetches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fetches.is_empty()
+    }
+}
+
+/// Held by the leader while it fetches; wakes followers when dropped, whether
+/// or not the fetch succeeded
+pub struct LeaderGuard<'a> {
+    tracker: &'a InFlightTracker,
+    gpa: u64,
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        // Remove first so followers that check afterwards do not wait
+        if let Some((_, notify)) = self.tracker.fetches.remove(&self.gpa) {
+            notify.notify_waiters();
+        }
+    }
+}
+
+/// A follower's handle on the leader's fetch
+pub struct Waiter<'a> {
+    tracker: &'a InFlightTracker,
+    gpa: u64,
+    notify: Arc<Notify>,
+}
+
+impl Waiter<'_> {
+    /// Block until the leader's fetch has finished
+    pub fn wait(self) {
+        let mut notified = pin!(self.notify.notified());
+        // Register before checking, so a leader finishing in between still
+        // wakes us
+        notified.as_mut().enable();
+
+        let still_running = self
+            .tracker
+            .fetches
+            .get(&self.gpa)
+            .is_some_and(|current| Arc::ptr_eq(&current, &self.notify));
+        if still_running {
+            block_on(notified);
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll `future` to completion on the current thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn test_eight_threads_one_fetch() {
+        const THREADS: usize = 8;
+
+        let tracker = InFlightTracker::new();
+        let fetches = AtomicU64::new(0);
+        let waited = AtomicU64::new(0);
+        let barrier = Barrier::new(THREADS);
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    barrier.wait();
+                    match tracker.begin(0x1000) {
+                        InFlight::Leader(_guard) => {
+                            // Slow fetch, so every other thread arrives meanwhile
+                            thread::sleep(Duration::from_millis(200));
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                        }
+                        InFlight::Follower(waiter) => {
+                            waiter.wait();
+                            // The leader finished before we were released
+                            assert_eq!(fetches.load(Ordering::SeqCst), 1);
+                            waited.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(waited.load(Ordering::SeqCst), THREADS as u64 - 1);
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn test_follower_after_leader_finished_does_not_block() {
+        let tracker = InFlightTracker::new();
+        let InFlight::Leader(guard) = tracker.begin(0) else {
+            panic!("first thread must lead");
+        };
+        let InFlight::Follower(waiter) = tracker.begin(0) else {
+            panic!("second thread must follow");
+        };
+
+        drop(guard);
+        waiter.wait();
+        assert!(matches!(tracker.begin(0), InFlight::Leader(_)));
+    }
+
+    #[test]
+    fn test_different_pages_fetch_independently() {
+        let tracker = InFlightTracker::new();
+        let first = tracker.begin(0);
+        let second = tracker.begin(0x1000);
+        assert!(matches!(first, InFlight::Leader(_)));
+        assert!(matches!(second, InFlight::Leader(_)));
+        assert_eq!(tracker.len(), 2);
+    }
+}
@@ -6,40 +6,301 @@
 //! 3. Fetching from remote node via RDMA if needed
 //! 4. Resolving fault with UFFDIO_COPY/WAKE
 
+pub mod access_log;
+pub mod affinity;
+pub mod allocator;
+pub mod api;
+pub mod balancing;
+pub mod cache;
+pub mod checkpoint;
+pub mod compaction;
+pub mod compressor;
+pub mod coordinator;
+pub mod dedup;
+pub mod dlq;
+pub mod gossip;
+pub mod guard;
+pub mod identity;
+pub mod inflight;
+pub mod metrics;
+pub mod migration;
+pub mod optimistic;
+pub mod page_size;
+pub mod pattern;
+pub mod policy;
+pub mod prefault;
+pub mod prefetch;
+pub mod pressure;
+pub mod reload;
+#[cfg(test)]
+mod simulation;
+pub mod speculation;
+pub mod stats;
+pub mod workers;
+
+use access_log::{AccessLogger, FaultType};
+use affinity::{AffinityTracker, PlacementHint, PlacementPolicy, PLACEMENT_HINT_TTL};
+use allocator::{PageAllocator, DEFAULT_POOL_PAGES};
 use anyhow::{anyhow, Context, Result};
-use crossbeam_channel::{bounded, Receiver, Sender};
-use log::{debug, info, warn};
-use parking_lot::RwLock;
-use rdma_transport::{Endpoint as TransportEndpoint, TransportManager};
+use balancing::{BalancingAgent, CoordinatorLoad};
+use cache::{PageCache, DEFAULT_PAGE_CACHE_SIZE};
+use checkpoint::{ChangedPages, IncrementalCheckpointer};
+use compaction::{CompactionStats, DEFAULT_COMPACTION_INTERVAL};
+use compressor::ColdPageCompressor;
+use coordinator::{CoordinatorClient, CoordinatorConfig};
+use crossbeam_channel::{Receiver, Sender};
+use dashmap::DashMap;
+use dedup::{CoordinatorDedupIndex, DeduplicationLayer, FetchedPage};
+use dlq::{DeadLetterQueue, DlqEntry, DEFAULT_MAX_FAULT_RETRIES};
+use ed25519_dalek::VerifyingKey;
+use gossip::{GossipConfig, OwnershipGossip};
+use guard::GuardPages;
+use hdrhistogram::serialization::interval_log::{IntervalLogWriterBuilder, Tag};
+use hdrhistogram::serialization::V2DeflateSerializer;
+use identity::{AuthToken, ManagementAuth, NodeIdentity, PeerAuth, RegistrationResponse};
+use inflight::{InFlight, InFlightTracker};
+use log::{debug, error, info, warn};
+use metrics::{
+    BalloonStats, LatencyHistogram, LoadSampler, PushGatewayConfig, BALLOON_STATS_INTERVAL,
+    LOAD_REPORT_INTERVAL,
+};
+use migration::{BatchMigrationResult, FetchCancelled, LocalPages, MigrationCoordinator};
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::{global, KeyValue};
+use optimistic::OptimisticLock;
+use page_size::{GuestPageWalker, PageSizeClass, PageSizeConfig};
+use parking_lot::{Mutex, RwLock};
+use pattern::AccessPatternDetector;
+use policy::{OvercommitPolicy, PageReplacementPolicy};
+use prefault::{PrefaultContext, Prefaulter};
+use prefetch::{PrefetchContext, SequentialPrefetcher};
+use pressure::{LogPressure, MemoryWatermarks, PressureCallback};
+use rdma_transport::reconnect::{PeerLocator, ReconnectPolicy};
+use rdma_transport::{Endpoint as TransportEndpoint, TransportError, TransportManager};
+use reload::{ConfigReloader, ControlMessage, ReloadableConfig};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
+use stats::SamplingHistogram;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::thread::{self, JoinHandle};
-use userfaultfd::{Event, RegisterMode, Uffd, UffdBuilder};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use userfaultfd::{Event, FaultKind, RegisterMode, Uffd, UffdBuilder};
+use workers::WorkerPool;
 
 const PAGE_SIZE: usize = 4096;
 
+/// How long the fault loop waits for an event before re-checking for shutdown
+const FAULT_POLL_TIMEOUT_MS: i32 = 100;
+
+/// Default timeout for coordinator requests (see
+/// `PagerConfig::coordinator_timeout_secs`)
+const COORDINATOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Load reports are sent from the fault loop, so they must give up quickly
+const LOAD_REPORT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long shutdown waits for peers' in-flight page requests
+const TRANSPORT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `PagerHandle::stop` waits for the fault loop to finish
+const PAGER_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Times a remote fetch is retried after the owner reports a stale epoch
+const STALE_EPOCH_RETRIES: u32 = 5;
+
+/// Remote fetches a node may have outstanding with one peer by default
+pub const DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE: usize = 32;
+
+/// Page groups a batch migration moves at once by default
+pub const DEFAULT_MAX_CONCURRENT_MIGRATIONS: usize = 8;
+
+/// Pause before retry `n` is `n` times this, giving the migration that made
+/// the owner's directory stale time to land
+const STALE_EPOCH_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Pause before retry `n` of a failed or cancelled remote fault is `n` times
+/// this, so a flapping owner or repeated cancellations do not keep the vCPU
+/// spinning
+const FAULT_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
 /// Coordinator endpoint model (matches Python API)
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct CoordinatorEndpoint {
-    transport_type: String,
-    tcp_addr: Option<String>,
-    tcp_port: Option<u16>,
-    rdma_qpn: Option<u32>,
-    rdma_lid: Option<u16>,
-    rdma_gid: Option<String>,
-    rdma_psn: Option<u32>,
+///
+/// Build one with `CoordinatorEndpointBuilder` to have it validated up front.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoordinatorEndpoint {
+    pub transport_type: String,
+    pub tcp_addr: Option<String>,
+    pub tcp_port: Option<u16>,
+    pub rdma_qpn: Option<u32>,
+    pub rdma_lid: Option<u16>,
+    /// 16-byte GID in hex, optionally `0x`-prefixed
+    pub rdma_gid: Option<String>,
+    pub rdma_psn: Option<u32>,
+    /// QUIC nodes' certificate (DER) in hex; QUIC endpoints use the `tcp_`
+    /// address fields for their UDP address
+    #[serde(default)]
+    pub quic_cert: Option<String>,
+}
+
+/// Validated constructors for `CoordinatorEndpoint`
+pub struct CoordinatorEndpointBuilder;
+
+impl CoordinatorEndpointBuilder {
+    /// TCP endpoint; `addr` must be an IPv4 or IPv6 address
+    pub fn tcp(addr: &str, port: u16) -> Result<CoordinatorEndpoint> {
+        let endpoint = CoordinatorEndpoint {
+            tcp_addr: Some(addr.to_string()),
+            tcp_port: Some(port),
+            ..CoordinatorEndpoint::empty("tcp")
+        };
+        endpoint.validate()?;
+        Ok(endpoint)
+    }
+
+    /// QUIC endpoint with the node's certificate (DER)
+    pub fn quic(addr: &str, port: u16, cert: &[u8]) -> Result<CoordinatorEndpoint> {
+        let endpoint = CoordinatorEndpoint {
+            tcp_addr: Some(addr.to_string()),
+            tcp_port: Some(port),
+            quic_cert: Some(hex::encode(cert)),
+            ..CoordinatorEndpoint::empty("quic")
+        };
+        endpoint.validate()?;
+        Ok(endpoint)
+    }
+
+    /// RDMA endpoint; `gid_hex` must encode 16 bytes
+    pub fn rdma(qpn: u32, lid: u16, gid_hex: &str, psn: u32) -> Result<CoordinatorEndpoint> {
+        let endpoint = CoordinatorEndpoint {
+            rdma_qpn: Some(qpn),
+            rdma_lid: Some(lid),
+            rdma_gid: Some(gid_hex.to_string()),
+            rdma_psn: Some(psn),
+            ..CoordinatorEndpoint::empty("rdma")
+        };
+        endpoint.validate()?;
+        Ok(endpoint)
+    }
+}
+
+impl From<&TransportEndpoint> for CoordinatorEndpoint {
+    fn from(endpoint: &TransportEndpoint) -> Self {
+        match endpoint {
+            TransportEndpoint::Tcp { addr, port } => Self {
+                tcp_addr: Some(addr.to_string()),
+                tcp_port: Some(*port),
+                ..Self::empty("tcp")
+            },
+            #[cfg(feature = "rdma-transport")]
+            TransportEndpoint::Rdma { qpn, lid, gid, psn } => Self {
+                rdma_qpn: Some(*qpn),
+                rdma_lid: Some(*lid),
+                rdma_gid: Some(format!("0x{}", hex::encode(gid))),
+                rdma_psn: Some(*psn),
+                ..Self::empty("rdma")
+            },
+            #[cfg(feature = "quic-transport")]
+            TransportEndpoint::Quic { addr, port, cert } => Self {
+                tcp_addr: Some(addr.to_string()),
+                tcp_port: Some(*port),
+                quic_cert: Some(hex::encode(cert)),
+                ..Self::empty("quic")
+            },
+        }
+    }
 }
 
 impl CoordinatorEndpoint {
+    fn empty(transport_type: &str) -> Self {
+        Self {
+            transport_type: transport_type.to_string(),
+            tcp_addr: None,
+            tcp_port: None,
+            rdma_qpn: None,
+            rdma_lid: None,
+            rdma_gid: None,
+            rdma_psn: None,
+            quic_cert: None,
+        }
+    }
+
+    /// Check that the fields for `transport_type` are present and well-formed
+    pub fn validate(&self) -> Result<()> {
+        match self.transport_type.as_str() {
+            "tcp" => self.validate_socket_addr(),
+            "quic" => {
+                self.validate_socket_addr()?;
+                self.quic_cert_bytes().map(|_| ())
+            }
+            "rdma" => {
+                match self.rdma_qpn {
+                    Some(0) => return Err(anyhow!("rdma_qpn must be non-zero")),
+                    Some(_) => {}
+                    None => return Err(anyhow!("Missing rdma_qpn")),
+                }
+                self.rdma_lid.ok_or_else(|| anyhow!("Missing rdma_lid"))?;
+                let psn = self.rdma_psn.ok_or_else(|| anyhow!("Missing rdma_psn"))?;
+                if psn > 0xff_ffff {
+                    return Err(anyhow!("rdma_psn 0x{:x} exceeds 24 bits", psn));
+                }
+                self.rdma_gid_bytes().map(|_| ())
+            }
+            other => Err(anyhow!("Unknown transport type: {}", other)),
+        }
+    }
+
+    /// `tcp_addr` and `tcp_port`, shared by TCP and QUIC endpoints
+    fn validate_socket_addr(&self) -> Result<()> {
+        let addr = self
+            .tcp_addr
+            .as_deref()
+            .filter(|addr| !addr.is_empty())
+            .ok_or_else(|| anyhow!("Missing tcp_addr"))?;
+        addr.parse::<std::net::IpAddr>()
+            .with_context(|| format!("Invalid tcp_addr {:?}", addr))?;
+        match self.tcp_port {
+            Some(0) => Err(anyhow!("tcp_port must be non-zero")),
+            Some(_) => Ok(()),
+            None => Err(anyhow!("Missing tcp_port")),
+        }
+    }
+
+    fn quic_cert_bytes(&self) -> Result<Vec<u8>> {
+        let cert = self
+            .quic_cert
+            .as_deref()
+            .filter(|cert| !cert.is_empty())
+            .ok_or_else(|| anyhow!("Missing quic_cert"))?;
+        hex::decode(cert).map_err(|e| anyhow!("Invalid quic_cert: {}", e))
+    }
+
+    fn rdma_gid_bytes(&self) -> Result<[u8; 16]> {
+        let gid_str = self
+            .rdma_gid
+            .as_deref()
+            .ok_or_else(|| anyhow!("Missing rdma_gid"))?;
+        let gid = hex::decode(gid_str.trim_start_matches("0x"))
+            .map_err(|e| anyhow!("Invalid GID format: {}", e))?;
+        gid.try_into()
+            .map_err(|gid: Vec<u8>| anyhow!("GID must be 16 bytes, got {}", gid.len()))
+    }
+
     /// Convert to TransportEndpoint for use with TransportManager
     fn to_transport_endpoint(&self) -> Result<TransportEndpoint> {
         match self.transport_type.as_str() {
             "tcp" => {
                 let addr = self
                     .tcp_addr
-                    .clone()
+                    .as_deref()
                     .ok_or_else(|| anyhow!("Missing tcp_addr"))?;
+                let addr = addr
+                    .parse()
+                    .with_context(|| format!("Invalid tcp_addr {:?}", addr))?;
                 let port = self.tcp_port.ok_or_else(|| anyhow!("Missing tcp_port"))?;
                 Ok(TransportEndpoint::Tcp { addr, port })
             }
@@ -48,92 +309,852 @@ impl CoordinatorEndpoint {
                 {
                     let qpn = self.rdma_qpn.ok_or_else(|| anyhow!("Missing rdma_qpn"))?;
                     let lid = self.rdma_lid.ok_or_else(|| anyhow!("Missing rdma_lid"))?;
-                    let gid_str = self
-                        .rdma_gid
-                        .clone()
-                        .ok_or_else(|| anyhow!("Missing rdma_gid"))?;
+                    let gid = self.rdma_gid_bytes()?;
                     let psn = self.rdma_psn.ok_or_else(|| anyhow!("Missing rdma_psn"))?;
 
-                    // Parse GID from hex string
-                    let gid = hex::decode(gid_str.trim_start_matches("0x"))
-                        .map_err(|e| anyhow!("Invalid GID format: {}", e))?;
-                    if gid.len() != 16 {
-                        return Err(anyhow!("GID must be 16 bytes"));
-                    }
-                    let mut gid_arr = [0u8; 16];
-                    gid_arr.copy_from_slice(&gid);
-
-                    Ok(TransportEndpoint::Rdma {
-                        qpn,
-                        lid,
-                        gid: gid_arr,
-                        psn,
-                    })
+                    Ok(TransportEndpoint::Rdma { qpn, lid, gid, psn })
                 }
                 #[cfg(not(feature = "rdma-transport"))]
                 {
                     Err(anyhow!("RDMA transport not compiled in"))
                 }
             }
+            "quic" => {
+                #[cfg(feature = "quic-transport")]
+                {
+                    self.validate()?;
+                    let addr = self.tcp_addr.as_deref().unwrap_or_default().parse()?;
+                    let port = self.tcp_port.unwrap_or_default();
+                    let cert = self.quic_cert_bytes()?;
+                    Ok(TransportEndpoint::Quic { addr, port, cert })
+                }
+                #[cfg(not(feature = "quic-transport"))]
+                {
+                    Err(anyhow!("QUIC transport not compiled in"))
+                }
+            }
             _ => Err(anyhow!("Unknown transport type: {}", self.transport_type)),
         }
     }
 }
 
+/// Response of the coordinator's `GET /endpoints`
+#[derive(Debug, Deserialize)]
+pub struct EndpointsResponse {
+    /// By node ID
+    pub endpoints: HashMap<String, CoordinatorEndpoint>,
+    /// Public keys (hex) of the identities the nodes registered their
+    /// endpoints with
+    #[serde(default)]
+    pub public_keys: HashMap<String, String>,
+}
+
+impl EndpointsResponse {
+    /// Key `node_id` registered its endpoint with
+    ///
+    /// The peer proves it holds the key as a connection opens (see
+    /// `identity::PeerAuth`).
+    pub fn public_key(&self, node_id: u32) -> Result<VerifyingKey> {
+        let key = self
+            .public_keys
+            .get(&node_id.to_string())
+            .ok_or_else(|| anyhow!("no registered identity"))?;
+        identity::parse_public_key(key)
+    }
+}
+
+/// Finds peers to reconnect to in the coordinator's `GET /endpoints`
+struct CoordinatorPeers {
+    coordinator: Arc<CoordinatorClient>,
+    /// Runs the request for the reconnecting thread
+    runtime: tokio::runtime::Handle,
+}
+
+impl PeerLocator for CoordinatorPeers {
+    fn endpoint(&self, node_id: u32) -> Result<TransportEndpoint> {
+        let endpoints = self.runtime.block_on(self.coordinator.fetch_endpoints())?;
+        endpoints
+            .public_key(node_id)
+            .with_context(|| format!("Node {} is not authenticated", node_id))?;
+        endpoints
+            .endpoints
+            .get(&node_id.to_string())
+            .ok_or_else(|| anyhow!("Coordinator has no endpoint for node {}", node_id))?
+            .to_transport_endpoint()
+    }
+}
+
+/// Sends this node's `LoadMetrics` and `BalloonStats` to the coordinator
+struct NodeReporter {
+    coordinator: Arc<CoordinatorClient>,
+    sampler: Mutex<LoadSampler>,
+    /// Current stats and number of pages owned
+    snapshot: Box<dyn Fn() -> (PagerStats, u64) + Send + Sync>,
+    /// Current `BalloonStats`
+    balloon: Box<dyn Fn() -> BalloonStats + Send + Sync>,
+}
+
+impl NodeReporter {
+    async fn report_load(&self) -> Result<()> {
+        let (stats, pages_owned) = (self.snapshot)();
+        let load = self.sampler.lock().sample_current(&stats, pages_owned);
+
+        let bearer = self.coordinator.bearer()?;
+        let node_id = self.coordinator.node_id();
+        let response = self
+            .coordinator
+            .send(|http, url| {
+                http.put(format!("{}/nodes/{}/load", url, node_id))
+                    .bearer_auth(bearer)
+                    .json(&load)
+                    .timeout(LOAD_REPORT_TIMEOUT)
+            })
+            .await
+            .context("Failed to send load report")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!("Failed to report load: {}", response.status()));
+        }
+        debug!("Reported load: {:?}", load);
+        Ok(())
+    }
+
+    async fn report_balloon_stats(&self) -> Result<()> {
+        let balloon = (self.balloon)();
+        let bearer = self.coordinator.bearer()?;
+        let node_id = self.coordinator.node_id();
+        let response = self
+            .coordinator
+            .send(|http, url| {
+                http.put(format!("{}/nodes/{}/balloon_stats", url, node_id))
+                    .bearer_auth(bearer)
+                    .json(&balloon)
+                    .timeout(LOAD_REPORT_TIMEOUT)
+            })
+            .await
+            .context("Failed to send balloon stats")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to report balloon stats: {}",
+                response.status()
+            ));
+        }
+        debug!("Reported balloon stats: {:?}", balloon);
+        Ok(())
+    }
+}
+
+/// Where the pages of a `len`-byte guest region are (see `BalloonStats`)
+fn balloon_stats(
+    len: usize,
+    compressed_pages: u64,
+    dead_letters: &DeadLetterQueue,
+    directory: &PageDirectory,
+) -> BalloonStats {
+    let mut stats = BalloonStats {
+        total_pages: (len / PAGE_SIZE) as u64,
+        compressed_pages,
+        dlq_pages: dead_letters
+            .entries()
+            .iter()
+            .map(|entry| entry.gpa / PAGE_SIZE as u64)
+            .collect::<HashSet<_>>()
+            .len() as u64,
+        ..BalloonStats::default()
+    };
+    for (first, end, owner) in directory.coalesced_regions() {
+        let end = end.min(stats.total_pages);
+        let pages = end.saturating_sub(first);
+        match owner {
+            PageOwner::Local | PageOwner::LocalHuge | PageOwner::Speculative(_) => {
+                stats.local_pages += pages
+            }
+            PageOwner::Remote(_) => stats.remote_pages += pages,
+            PageOwner::Unknown => {}
+        }
+    }
+    stats.unknown_pages = stats.total_pages - stats.local_pages - stats.remote_pages;
+    stats
+}
+
+/// This node's standing with the coordinator (see `identity`)
+struct ClusterAuth {
+    token: AuthToken,
+    coordinator_key: VerifyingKey,
+}
+
+impl ClusterAuth {
+    /// Check a `POST /auth/register` response issued for `node_id`
+    fn from_registration(node_id: u32, response: RegistrationResponse) -> Result<Self> {
+        let coordinator_key = identity::parse_public_key(&response.coordinator_key)
+            .context("Invalid coordinator key")?;
+        let token = AuthToken::from_bearer(&response.token)?;
+        token
+            .verify(node_id, &coordinator_key)
+            .context("Coordinator issued an invalid token")?;
+        Ok(Self {
+            token,
+            coordinator_key,
+        })
+    }
+
+    fn bearer(&self) -> String {
+        self.token.to_bearer()
+    }
+
+    /// Proves to peers, with `identity`, that this node is `node_id`
+    fn peer_auth(&self, node_id: u32, identity: NodeIdentity) -> PeerAuth {
+        PeerAuth::new(node_id, identity, self.token.clone(), self.coordinator_key)
+    }
+}
+
 /// Page ownership state
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PageOwner {
     Local,
     Remote(u32), // node_id
     Unknown,
+    /// Claimed by this node (node_id) pending coordinator confirmation
+    Speculative(u32),
+    /// Local, and backed by one 2 MiB huge page with its neighbours
+    ///
+    /// Only held by directory regions; lookups report `Local`.
+    LocalHuge,
+}
+
+/// Pages per directory region (1 MiB)
+const REGION_PAGES: u64 = 256;
+
+/// Pages per huge page (2 MiB)
+const HUGE_PAGE_PAGES: u64 = 512;
+
+/// Bytes per huge page
+const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Slots of `PageDirectory`'s owner cache, each holding one region
+const OWNER_CACHE_SLOTS: u64 = 1024;
+
+/// Owner cache slot holding no region
+const NO_REGION: u64 = u64::MAX;
+
+/// Ownership of one 1 MiB region of guest memory
+///
+/// Pages follow the region's dominant owner unless they have an override, so
+/// a region owned entirely by one node costs a single entry.
+#[derive(Debug, Clone)]
+struct Region {
+    owner: PageOwner,
+    /// Per-page exceptions, by page offset within the region
+    overrides: HashMap<u8, PageOwner>,
+}
+
+impl Region {
+    fn new() -> Self {
+        Self {
+            owner: PageOwner::Unknown,
+            overrides: HashMap::new(),
+        }
+    }
+
+    fn owner_of(&self, offset: u8) -> PageOwner {
+        self.overrides
+            .get(&offset)
+            .copied()
+            .unwrap_or(self.dominant())
+    }
+
+    /// Owner of pages without an override
+    fn dominant(&self) -> PageOwner {
+        match self.owner {
+            PageOwner::LocalHuge => PageOwner::Local,
+            owner => owner,
+        }
+    }
+
+    fn is_huge(&self) -> bool {
+        self.owner == PageOwner::LocalHuge
+    }
+
+    fn set(&mut self, offset: u8, owner: PageOwner) {
+        if self.is_huge() {
+            if owner == PageOwner::Local {
+                return;
+            }
+            // Part of the huge page is leaving
+            self.owner = PageOwner::Local;
+        }
+        if owner == self.owner {
+            self.overrides.remove(&offset);
+        } else {
+            self.overrides.insert(offset, owner);
+        }
+        if self.overrides.len() as u64 > REGION_PAGES / 2 {
+            self.reelect();
+        }
+    }
+
+    /// Make the most common owner dominant and rebuild the overrides
+    fn reelect(&mut self) {
+        let owners: Vec<PageOwner> = (0..REGION_PAGES)
+            .map(|offset| self.owner_of(offset as u8))
+            .collect();
+
+        let mut counts: HashMap<PageOwner, u64> = HashMap::new();
+        for owner in &owners {
+            *counts.entry(*owner).or_default() += 1;
+        }
+        let dominant = counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(owner, _)| owner)
+            .unwrap_or(PageOwner::Unknown);
+
+        self.owner = dominant;
+        self.overrides = owners
+            .into_iter()
+            .enumerate()
+            .filter(|(_, owner)| *owner != dominant)
+            .map(|(offset, owner)| (offset as u8, owner))
+            .collect();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.owner == PageOwner::Unknown && self.overrides.is_empty()
+    }
+
+    /// Pages owned by `owner`
+    fn pages_owned_by(&self, owner: PageOwner) -> usize {
+        let matching = self.overrides.values().filter(|o| **o == owner).count();
+        if self.dominant() == owner {
+            REGION_PAGES as usize - (self.overrides.len() - matching)
+        } else {
+            matching
+        }
+    }
+
+    /// Pages with a known owner
+    fn known_pages(&self) -> usize {
+        let unknown_overrides = self
+            .overrides
+            .values()
+            .filter(|owner| **owner == PageOwner::Unknown)
+            .count();
+        if self.owner == PageOwner::Unknown {
+            self.overrides.len() - unknown_overrides
+        } else {
+            REGION_PAGES as usize - unknown_overrides
+        }
+    }
 }
 
 /// Page directory tracking ownership across the cluster
+///
+/// Two levels: each 1 MiB region records a dominant owner, and only pages
+/// owned by someone else get an entry of their own. Lookups in homogeneous
+/// regions never reach the second level.
 pub struct PageDirectory {
-    /// Map guest physical page number to owner node
-    ownership: RwLock<HashMap<u64, PageOwner>>,
+    /// Region number (page number / `REGION_PAGES`) to region ownership
+    ///
+    /// Sharded so fault-handler threads touching different regions rarely
+    /// contend on the same lock.
+    regions: DashMap<u64, Region>,
     local_node: u32,
+    /// Bumped on every ownership change; peers refuse fetches from nodes
+    /// whose directory is newer than theirs
+    epoch: Arc<AtomicU64>,
+    /// Pages changed since the last checkpoint, if checkpointing
+    changed: OnceLock<ChangedPages>,
+    /// Announces this node's claims to its peers, if gossiping
+    gossip: OnceLock<Arc<OwnershipGossip>>,
+    /// Owners of recently read regions with no overrides, by region number
+    /// modulo `OWNER_CACHE_SLOTS`
+    ///
+    /// Lookups that hit it skip the shard lock. A region's slot is cleared
+    /// after every change to it, and filled only if it was not written
+    /// since the lookup started (see `optimistic`).
+    owner_cache: Box<[OptimisticLock<(u64, PageOwner), 2>]>,
+}
+
+/// Region count of one `PageDirectory` shard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ShardStat {
+    pub shard_id: u32,
+    pub entry_count: usize,
 }
 
 impl PageDirectory {
     fn new(local_node: u32) -> Self {
         Self {
-            ownership: RwLock::new(HashMap::new()),
+            regions: DashMap::new(),
             local_node,
+            epoch: Arc::new(AtomicU64::new(0)),
+            changed: OnceLock::new(),
+            gossip: OnceLock::new(),
+            owner_cache: (0..OWNER_CACHE_SLOTS)
+                .map(|_| OptimisticLock::new((NO_REGION, PageOwner::Unknown)))
+                .collect(),
         }
     }
 
+    /// Add every page whose owner changes from now on to `changed`
+    fn record_changes(&self, changed: ChangedPages) -> Result<()> {
+        self.changed
+            .set(changed)
+            .map_err(|_| anyhow!("Directory changes are already recorded"))
+    }
+
     /// Get page owner (first-touch policy for M3)
     fn get_owner(&self, page_num: u64) -> PageOwner {
-        self.ownership
-            .read()
-            .get(&page_num)
-            .copied()
-            .unwrap_or(PageOwner::Unknown)
+        let region_num = page_num / REGION_PAGES;
+        let slot = self.cache_slot(region_num);
+        let ((cached, owner), version) = slot.read();
+        if cached == region_num {
+            return owner;
+        }
+
+        let (owner, uniform) = match self.regions.get(&region_num) {
+            Some(region) => (
+                region.owner_of((page_num % REGION_PAGES) as u8),
+                region.overrides.is_empty(),
+            ),
+            None => (PageOwner::Unknown, true),
+        };
+        if uniform {
+            slot.compare_and_write(version, (region_num, owner));
+        }
+        owner
+    }
+
+    fn cache_slot(&self, region_num: u64) -> &OptimisticLock<(u64, PageOwner), 2> {
+        &self.owner_cache[(region_num % OWNER_CACHE_SLOTS) as usize]
+    }
+
+    /// Drop any cached owner of `region_num`; call after changing it
+    fn forget_cached(&self, region_num: u64) {
+        self.cache_slot(region_num)
+            .write((NO_REGION, PageOwner::Unknown));
+    }
+
+    /// Owner of a page and the directory epoch it was read at
+    ///
+    /// The epoch is re-read until no ownership change lands during the
+    /// lookup, so the owner is at least as new as the epoch.
+    pub fn read_with_epoch(&self, page_num: u64) -> (PageOwner, u64) {
+        loop {
+            let epoch = self.epoch();
+            let owner = self.get_owner(page_num);
+            if self.epoch() == epoch {
+                return (owner, epoch);
+            }
+        }
+    }
+
+    /// Number of ownership changes so far
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Announce every page claimed from now on through `gossip`
+    fn gossip_claims(&self, gossip: Arc<OwnershipGossip>) -> Result<()> {
+        self.gossip
+            .set(gossip)
+            .map_err(|_| anyhow!("Directory claims are already gossiped"))
     }
 
     /// Claim ownership of a page (first touch)
     pub fn claim_page(&self, page_num: u64) {
-        self.ownership.write().insert(page_num, PageOwner::Local);
+        self.set_owner(page_num, PageOwner::Local);
+        if let Some(gossip) = self.gossip.get() {
+            gossip.announce_claim(page_num, self.epoch());
+        }
     }
 
     /// Set page owner explicitly (for testing and migration)
     pub fn set_owner(&self, page_num: u64, owner: PageOwner) {
-        self.ownership.write().insert(page_num, owner);
+        let region_num = page_num / REGION_PAGES;
+        let (empty, demoted) = {
+            let mut region = self.regions.entry(region_num).or_insert_with(Region::new);
+            let huge = region.is_huge();
+            region.set((page_num % REGION_PAGES) as u8, owner);
+            (region.is_empty(), huge && !region.is_huge())
+        };
+        if empty {
+            self.regions
+                .remove_if(&region_num, |_, region| region.is_empty());
+        }
+        if demoted {
+            // The rest of the huge page is local pages again
+            self.set_huge(page_num - page_num % HUGE_PAGE_PAGES, false);
+        }
+        self.forget_cached(region_num);
+        if let Some(changed) = self.changed.get() {
+            changed.lock().insert(page_num);
+        }
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Mark the 2 MiB range from `start_page_2m` as one huge page, if this
+    /// node owns every page in it
+    ///
+    /// Owners read the same afterwards; changing any page's owner splits
+    /// the huge page again. Returns false if `start_page_2m` is not 2 MiB
+    /// aligned, a page is not local, or the range is already huge.
+    pub fn promote_region(&self, start_page_2m: u64) -> bool {
+        if !start_page_2m.is_multiple_of(HUGE_PAGE_PAGES) {
+            return false;
+        }
+        let all_local = self.huge_regions(start_page_2m).all(|region_num| {
+            self.regions.get(&region_num).is_some_and(|region| {
+                region.owner == PageOwner::Local && region.overrides.is_empty()
+            })
+        });
+        if !all_local {
+            return false;
+        }
+        // Regions are locked one at a time; a page changing owner meanwhile
+        // undoes the promotion
+        if !self.set_huge(start_page_2m, true) {
+            self.set_huge(start_page_2m, false);
+            return false;
+        }
+        true
+    }
+
+    /// Whether `page_num` is part of a promoted huge page
+    pub fn is_huge(&self, page_num: u64) -> bool {
+        self.regions
+            .get(&(page_num / REGION_PAGES))
+            .is_some_and(|region| region.is_huge())
+    }
+
+    /// Owner of the whole `size_class` page holding guest address `gpa`
+    ///
+    /// Every 4 KiB page it covers has to have that owner; a huge page whose
+    /// pages are owned by different nodes reads as `Unknown`, as does one
+    /// untouched anywhere.
+    pub fn get_owner_huge(&self, gpa: u64, size_class: PageSizeClass) -> PageOwner {
+        let first = size_class.align_page(gpa / PAGE_SIZE as u64);
+        if size_class.pages() < REGION_PAGES {
+            return self.get_owner(first);
+        }
+        let mut owner = None;
+        for region_num in first / REGION_PAGES..(first + size_class.pages()) / REGION_PAGES {
+            // Overrides always differ from the region's own owner
+            let region_owner = match self.regions.get(&region_num) {
+                Some(region) if region.overrides.is_empty() => region.dominant(),
+                _ => return PageOwner::Unknown,
+            };
+            if *owner.get_or_insert(region_owner) != region_owner {
+                return PageOwner::Unknown;
+            }
+        }
+        owner.unwrap_or(PageOwner::Unknown)
+    }
+
+    /// Regions of the huge page starting at `start_page_2m`
+    fn huge_regions(&self, start_page_2m: u64) -> std::ops::Range<u64> {
+        let first = start_page_2m / REGION_PAGES;
+        first..first + HUGE_PAGE_PAGES / REGION_PAGES
+    }
+
+    /// Promote or demote every region of a huge page, returning whether all
+    /// of them were wholly local (for promotion) or huge (for demotion)
+    fn set_huge(&self, start_page_2m: u64, huge: bool) -> bool {
+        let (from, to) = match huge {
+            true => (PageOwner::Local, PageOwner::LocalHuge),
+            false => (PageOwner::LocalHuge, PageOwner::Local),
+        };
+        let mut all = true;
+        for region_num in self.huge_regions(start_page_2m) {
+            match self.regions.get_mut(&region_num) {
+                Some(mut region) if region.owner == from && region.overrides.is_empty() => {
+                    region.owner = to;
+                }
+                _ => all = false,
+            }
+        }
+        all
+    }
+
+    /// Forget the owners of the pages at guest addresses `gpas`, e.g. memory
+    /// the guest gave back
+    ///
+    /// Returns how many of them had a known owner.
+    pub fn release_pages(&self, gpas: &[u64]) -> usize {
+        gpas.iter()
+            .map(|gpa| gpa / PAGE_SIZE as u64)
+            .filter(|&page_num| {
+                let known = self.get_owner(page_num) != PageOwner::Unknown;
+                if known {
+                    self.set_owner(page_num, PageOwner::Unknown);
+                }
+                known
+            })
+            .count()
+    }
+
+    /// Drop regions left with no known page and give back spare override
+    /// capacity, returning how many regions were dropped
+    ///
+    /// `set_owner` drops a region as its last page is released, but only
+    /// if no other thread changed it in between; this catches the rest.
+    /// Unknown pages inside owned regions are kept, since they are what
+    /// marks those pages released.
+    pub fn gc_unknown_entries(&self) -> usize {
+        let before = self.regions.len();
+        self.regions.retain(|_, region| {
+            region.overrides.shrink_to_fit();
+            !region.is_empty()
+        });
+        before.saturating_sub(self.regions.len())
+    }
+
+    /// Drop entries that no longer say anything (see `compaction`)
+    ///
+    /// Pages owned by a node in `dead_nodes` become `Unknown`, overrides
+    /// matching their region's owner are dropped, each region's most common
+    /// owner is made dominant, and regions left empty are removed. Huge
+    /// regions keep their owner.
+    pub fn compact(&self, dead_nodes: &HashSet<u32>) -> CompactionStats {
+        let is_dead = |owner: PageOwner| matches!(owner, PageOwner::Remote(node) if dead_nodes.contains(&node));
+        let entries = |region: &Region| 1 + region.overrides.len();
+
+        let mut stats = CompactionStats::default();
+        let mut forgotten = Vec::new();
+        for mut region in self.regions.iter_mut() {
+            let region_num = *region.key();
+            let region = region.value_mut();
+            stats.entries_before += entries(region);
+            let overrides_before = region.overrides.len();
+
+            if is_dead(region.owner) || region.overrides.values().any(|&owner| is_dead(owner)) {
+                let mut owners: Vec<PageOwner> = (0..REGION_PAGES)
+                    .map(|offset| region.owner_of(offset as u8))
+                    .collect();
+                for (offset, owner) in owners.iter_mut().enumerate() {
+                    if is_dead(*owner) {
+                        *owner = PageOwner::Unknown;
+                        forgotten.push(region_num * REGION_PAGES + offset as u64);
+                    }
+                }
+                if is_dead(region.owner) {
+                    region.owner = PageOwner::Unknown;
+                }
+                region.overrides = owners
+                    .into_iter()
+                    .enumerate()
+                    .map(|(offset, owner)| (offset as u8, owner))
+                    .collect();
+            }
+            let dominant = region.dominant();
+            region.overrides.retain(|_, owner| *owner != dominant);
+            if !region.is_huge() && !region.overrides.is_empty() {
+                region.reelect();
+            }
+            region.overrides.shrink_to_fit();
+            self.forget_cached(region_num);
+
+            let removed = overrides_before.saturating_sub(region.overrides.len());
+            stats.entries_removed += removed;
+            stats.bytes_freed += removed * std::mem::size_of::<(u8, PageOwner)>();
+        }
+
+        let regions_before = self.regions.len();
+        self.regions.retain(|_, region| !region.is_empty());
+        let regions_removed = regions_before.saturating_sub(self.regions.len());
+        stats.entries_removed += regions_removed;
+        stats.bytes_freed += regions_removed * std::mem::size_of::<(u64, Region)>();
+
+        if !forgotten.is_empty() {
+            if let Some(changed) = self.changed.get() {
+                changed.lock().extend(forgotten);
+            }
+            self.epoch.fetch_add(1, Ordering::SeqCst);
+        }
+        stats
     }
 
     /// Get total pages tracked
     pub fn page_count(&self) -> usize {
-        self.ownership.read().len()
+        self.regions.iter().map(|region| region.known_pages()).sum()
+    }
+
+    /// Pages tracked, a promoted huge page counting as one mapping
+    pub fn mapping_count(&self) -> usize {
+        let mut huge_regions = 0;
+        let small_pages: usize = self
+            .regions
+            .iter()
+            .map(|region| match region.is_huge() {
+                true => {
+                    huge_regions += 1;
+                    0
+                }
+                false => region.known_pages(),
+            })
+            .sum();
+        small_pages + huge_regions / (HUGE_PAGE_PAGES / REGION_PAGES) as usize
+    }
+
+    /// Pages owned by this node
+    pub fn local_page_count(&self) -> usize {
+        self.regions
+            .iter()
+            .map(|region| region.pages_owned_by(PageOwner::Local))
+            .sum()
+    }
+
+    /// Contiguous runs of pages with the same known owner
+    ///
+    /// Returns `(first_page, end_page, owner)` with `end_page` exclusive,
+    /// sorted by page number.
+    pub fn coalesced_regions(&self) -> Vec<(u64, u64, PageOwner)> {
+        let mut regions: Vec<(u64, Region)> = self
+            .regions
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        regions.sort_by_key(|(region_num, _)| *region_num);
+
+        let mut runs: Vec<(u64, u64, PageOwner)> = Vec::new();
+        let mut push = |start: u64, end: u64, owner: PageOwner| {
+            if owner == PageOwner::Unknown {
+                return;
+            }
+            match runs.last_mut() {
+                Some((_, last_end, last_owner)) if *last_end == start && *last_owner == owner => {
+                    *last_end = end;
+                }
+                _ => runs.push((start, end, owner)),
+            }
+        };
+
+        for (region_num, region) in regions {
+            let base = region_num * REGION_PAGES;
+            if region.overrides.is_empty() {
+                push(base, base + REGION_PAGES, region.dominant());
+                continue;
+            }
+            for offset in 0..REGION_PAGES {
+                push(
+                    base + offset,
+                    base + offset + 1,
+                    region.owner_of(offset as u8),
+                );
+            }
+        }
+        runs
+    }
+
+    /// Get the node ID this directory treats as local
+    pub fn local_node(&self) -> u32 {
+        self.local_node
+    }
+
+    /// Regions per shard, for spotting hot shards and hash skew
+    ///
+    /// Read-locks each shard in turn, so this is a debugging aid rather than
+    /// something to call on the fault path.
+    pub fn shard_stats(&self) -> Vec<ShardStat> {
+        self.regions
+            .shards()
+            .iter()
+            .enumerate()
+            .map(|(shard_id, shard)| ShardStat {
+                shard_id: shard_id as u32,
+                entry_count: shard.read().len(),
+            })
+            .collect()
+    }
+
+    /// Region count of the fullest shard
+    pub fn max_shard_occupancy(&self) -> usize {
+        self.shard_stats()
+            .iter()
+            .map(|shard| shard.entry_count)
+            .max()
+            .unwrap_or(0)
     }
 }
 
 /// Statistics for observability (NFR-observability)
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct PagerStats {
     pub local_faults: u64,
     pub remote_faults: u64,
+    /// Service time of every fault `fault_service_sample` recorded (all of
+    /// them at the default sample rate), as the fault loop measured it
     pub fault_service_time_us: Vec<u64>,
+    /// Service times as the fault loop measured them, sampled (see `stats`)
+    pub fault_service_sample: SamplingHistogram,
+    /// Time `handle_pagefault` took to claim and zero-fill untouched pages
+    pub first_touch_latency_us: LatencyHistogram,
+    /// Time `handle_pagefault` took on pages this node already owned
+    pub local_resolved_latency_us: LatencyHistogram,
+    /// Time `handle_pagefault` took to fetch pages from their owner
+    pub remote_fetch_latency_us: LatencyHistogram,
+    /// Times the fault stream's access pattern classification changed
+    pub pattern_changes: u64,
+    /// Prefetch depth currently chosen by access pattern detection
+    pub prefetch_depth: usize,
+    /// Entries in the fullest page directory shard (filled in on read)
+    pub max_shard_occupancy: usize,
+    /// Remote faults served from a local copy of identical content
+    pub dedup_hits: u64,
+    /// Remote faults served from the page cache without a fetch
+    pub cache_hits: u64,
+    /// Remote faults that waited for another thread's fetch of the same page
+    pub deduplicated_faults: u64,
+    /// Remote fetches retried because the owner's directory epoch was stale
+    pub stale_epoch_retries: u64,
+    /// Remote faults resolved with zeros because the owner was unreachable
+    pub timeout_faults: u64,
+    /// Remote fetches given up on after `ReloadableConfig::fetch_timeout`
+    pub fetch_timeouts: u64,
+    /// Local pages sent to a peer to stay within `OvercommitPolicy::Lazy`
+    pub evictions: u64,
+    /// Speculative claims dropped because another node claimed first
+    pub speculation_aborts: u64,
+    /// Remote fetches abandoned because their migration was cancelled
+    pub cancelled_fetches: u64,
+    /// Local pages migrated to a less loaded node (see `balancing`)
+    pub balance_migrations: u64,
+    /// 2 MiB ranges promoted to huge pages (see `Pager::try_promote_huge`)
+    pub huge_page_promotions: u64,
+    /// Directory entries dropped for pages the guest gave back, and by
+    /// `PageDirectory::gc_unknown_entries` and `PageDirectory::compact`
+    pub gc_entries_freed: u64,
+    /// First-touched pages placed on a peer (see `affinity`)
+    pub remote_placements: u64,
+    /// First-touched pages placed where the coordinator suggested
+    pub coordinator_hint_hits: u64,
+    /// Remote pages installed before the guest faulted on them (see
+    /// `prefault`)
+    pub proactive_installs: u64,
+    /// Untouched pages zero-filled ahead of sequential first touches (see
+    /// `prefetch`)
+    pub prefetched_pages: u64,
+    /// Remote faults given up on after repeated failures (see `dlq`)
+    pub dead_lettered_faults: u64,
+    /// Remote fetches that waited for a slot with their peer (see
+    /// `PagerConfig::max_concurrent_fetches_per_node`)
+    pub fetch_semaphore_waits: u64,
+    /// Ownership changes announced to peers (see `gossip`)
+    pub gossip_msgs_sent: u64,
+    /// Peers' ownership announcements received
+    pub gossip_msgs_received: u64,
+    /// Cold local pages currently held compressed (see `compressor`)
+    pub compressed_pages: u64,
+    /// Memory the compressed pages save over keeping them mapped
+    pub bytes_saved: u64,
+    /// Writes to write-protected pages
+    pub write_protect_faults: u64,
+    /// Fault buffers taken from the pre-allocated pool (see `allocator`)
+    pub pool_hits: u64,
+    /// Fault buffers allocated because the pool was empty
+    pub pool_misses: u64,
 }
 
 impl PagerStats {
@@ -145,7 +1166,7 @@ impl PagerStats {
         let mut sorted = self.fault_service_time_us.clone();
         sorted.sort_unstable();
         let len = sorted.len();
-        if len % 2 == 0 {
+        if len.is_multiple_of(2) {
             // Even number of elements: average the two middle values
             Some((sorted[len / 2 - 1] + sorted[len / 2]) / 2)
         } else {
@@ -154,52 +1175,415 @@ impl PagerStats {
         }
     }
 
+    /// Calculate p95 fault service time
+    pub fn p95_latency_us(&self) -> Option<u64> {
+        self.percentile_latency_us(0.95)
+    }
+
     /// Calculate p99 fault service time
     pub fn p99_latency_us(&self) -> Option<u64> {
+        self.percentile_latency_us(0.99)
+    }
+
+    /// Calculate p99.9 fault service time
+    pub fn p999_latency_us(&self) -> Option<u64> {
+        self.percentile_latency_us(0.999)
+    }
+
+    /// Calculate the `p` quantile (0.0 to 1.0, clamped) of fault service time
+    pub fn percentile_latency_us(&self, p: f64) -> Option<u64> {
         if self.fault_service_time_us.is_empty() {
             return None;
         }
         let mut sorted = self.fault_service_time_us.clone();
         sorted.sort_unstable();
-        let idx = (sorted.len() as f64 * 0.99) as usize;
+        let idx = (sorted.len() as f64 * p.clamp(0.0, 1.0)) as usize;
         Some(sorted[idx.min(sorted.len() - 1)])
     }
 
-    /// Calculate remote miss ratio
-    pub fn remote_miss_ratio(&self) -> f64 {
-        let total = self.local_faults + self.remote_faults;
-        if total == 0 {
-            0.0
-        } else {
-            self.remote_faults as f64 / total as f64
-        }
+    /// Faults the fault loop handled
+    ///
+    /// While the loop runs, faults since the last sampled one are not
+    /// counted yet (fewer than `latency_sample_rate`).
+    pub fn actual_fault_count(&self) -> u64 {
+        self.fault_service_sample.total_faults()
     }
-}
 
-/// Main pager structure
-pub struct Pager {
-    uffd: Uffd,
-    base: u64,
+    /// Faults whose service time `fault_service_sample` recorded
+    pub fn sampled_fault_count(&self) -> u64 {
+        self.fault_service_sample.samples_recorded()
+    }
+
+    pub fn first_touch_p99_us(&self) -> Option<u64> {
+        self.first_touch_latency_us.p99_us()
+    }
+
+    pub fn local_resolved_p99_us(&self) -> Option<u64> {
+        self.local_resolved_latency_us.p99_us()
+    }
+
+    pub fn remote_fetch_p99_us(&self) -> Option<u64> {
+        self.remote_fetch_latency_us.p99_us()
+    }
+
+    /// Latency histograms by the `fault_type` label they are exported with
+    pub fn fault_latencies(&self) -> [(&'static str, &LatencyHistogram); 3] {
+        [
+            ("first_touch", &self.first_touch_latency_us),
+            ("local_resolved", &self.local_resolved_latency_us),
+            ("remote_fetch", &self.remote_fetch_latency_us),
+        ]
+    }
+
+    /// Fault latencies as an HDR histogram interval log (compressed V2
+    /// encoding), one interval per kind of fault tagged `local`, `remote`
+    /// and `first_touch`
+    ///
+    /// Each interval runs from `base_time_sec` (seconds since the Unix
+    /// epoch; e.g. when the pager started) to now.
+    pub fn hdr_log(&self, base_time_sec: u64) -> Result<Vec<u8>> {
+        let base_time = UNIX_EPOCH + Duration::from_secs(base_time_sec);
+        let elapsed = SystemTime::now()
+            .duration_since(base_time)
+            .unwrap_or_default();
+        let mut log = Vec::new();
+        let mut serializer = V2DeflateSerializer::new();
+        let mut writer = IntervalLogWriterBuilder::new()
+            .with_base_time(base_time)
+            .begin_log_with(&mut log, &mut serializer)
+            .context("Failed to start HDR log")?;
+        for (tag, latencies) in [
+            ("local", &self.local_resolved_latency_us),
+            ("remote", &self.remote_fetch_latency_us),
+            ("first_touch", &self.first_touch_latency_us),
+        ] {
+            writer
+                .write_histogram(
+                    latencies.histogram(),
+                    Duration::ZERO,
+                    elapsed,
+                    Tag::new(tag),
+                )
+                .map_err(|e| anyhow!("Failed to write {} latencies: {:?}", tag, e))?;
+        }
+        Ok(log)
+    }
+
+    /// Write `hdr_log` to `path`
+    pub fn export_hdr_log(&self, path: &Path, base_time_sec: u64) -> Result<()> {
+        std::fs::write(path, self.hdr_log(base_time_sec)?)
+            .with_context(|| format!("Failed to write HDR log {}", path.display()))
+    }
+
+    /// Calculate remote miss ratio
+    pub fn remote_miss_ratio(&self) -> f64 {
+        let total = self.local_faults + self.remote_faults;
+        if total == 0 {
+            0.0
+        } else {
+            self.remote_faults as f64 / total as f64
+        }
+    }
+}
+
+/// Shutdown flag shared by the fault loop and the management API
+#[derive(Debug, Default)]
+pub struct ShutdownSignal {
+    triggered: AtomicBool,
+    notify: tokio::sync::Notify,
+}
+
+impl ShutdownSignal {
+    /// Request shutdown and wake anything waiting on it
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Whether shutdown has been requested
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Wait until shutdown is requested
+    pub async fn wait(&self) {
+        let notified = self.notify.notified();
+        if self.is_triggered() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Main pager structure
+pub struct Pager {
+    uffd: Arc<Uffd>,
+    base: u64,
     len: usize,
     directory: Arc<PageDirectory>,
     stats: Arc<RwLock<PagerStats>>,
     node_id: u32,
     total_nodes: u32,
     transport: Arc<RwLock<TransportManager>>,
-    coordinator_url: String,
+    coordinator: Arc<CoordinatorClient>,
+    auth: ClusterAuth,
+    shutdown: Arc<ShutdownSignal>,
+    api_server: Option<JoinHandle<()>>,
+    management_addr: Option<SocketAddr>,
+    metrics_pusher: Option<JoinHandle<()>>,
+    metrics_server: Option<JoinHandle<()>>,
+    metrics_addr: Option<SocketAddr>,
+    /// Threads `handle_faults` resolves faults on
+    fault_threads: usize,
+    pressure_monitor: Option<JoinHandle<()>>,
+    pressure_callback: Arc<RwLock<Box<dyn PressureCallback>>>,
+    balancer: Option<JoinHandle<()>>,
+    checkpointer: Option<JoinHandle<()>>,
+    compactor: Option<JoinHandle<()>>,
+    /// Nodes whose pages the next compaction forgets
+    dead_nodes: Arc<RwLock<HashSet<u32>>>,
+    gossip_receiver: Option<JoinHandle<()>>,
+    config: Arc<RwLock<ReloadableConfig>>,
+    reloader: Option<ConfigReloader>,
+    workers: Mutex<WorkerPool>,
+    control_tx: Arc<Sender<ControlMessage>>,
+    control_rx: Receiver<ControlMessage>,
+    /// Stops the fault loop; `PagerHandle` holds a clone of the sender
+    stop_tx: Sender<()>,
+    stop_rx: Receiver<()>,
+    dedup: Option<Arc<DeduplicationLayer>>,
+    compressor: Option<Arc<ColdPageCompressor>>,
+    compressor_thread: Option<JoinHandle<()>>,
+    /// Pages protected by `write_protect_page`, with their contents from
+    /// before the first write once written
+    write_tracked: Mutex<HashMap<u64, Option<Vec<u8>>>>,
+    access_logger: Arc<AccessLogger>,
+    guard_pages: Option<GuardPages>,
+    /// `SCHED_FIFO` priority for the fault handling thread
+    realtime_priority: Option<u8>,
+    reporter: Arc<NodeReporter>,
+    /// How often `start_reporting` sends load to the coordinator
+    load_report_interval: Duration,
+    /// How often `start_reporting` sends balloon stats to the coordinator
+    balloon_report_interval: Duration,
+    cache: Arc<PageCache>,
+    /// Staging buffers for pages being installed
+    allocator: Arc<PageAllocator>,
+    inflight: InFlightTracker,
+    overcommit: OvercommitPolicy,
+    replacement_policy: Arc<dyn PageReplacementPolicy>,
+    placement: AffinityTracker,
+    /// Ask the coordinator where to place first-touched pages
+    placement_hints: bool,
+    /// Coordinator placement hints by page, with when they were fetched
+    hint_cache: Mutex<HashMap<u64, (PlacementHint, Instant)>>,
+    /// Page sizes the guest maps its memory with, if they can be read
+    page_walker: Option<Arc<dyn GuestPageWalker>>,
+    /// Claim first-touched pages before the coordinator confirms them
+    speculative_claims: bool,
+    /// Size of the pages first touches are resolved with
+    page_size: PageSizeConfig,
+    prefetcher: Option<SequentialPrefetcher>,
+    /// Retries of a failing remote fault before it is dead-lettered
+    max_fault_retries: u32,
+    dead_letters: Arc<DeadLetterQueue>,
+    migration: MigrationCoordinator,
+    /// The caller's runtime, which coordinator requests and waits on remote
+    /// fetches (so they can be cancelled) run on. It must outlive the pager.
+    runtime: tokio::runtime::Handle,
+    max_concurrent_fetches_per_node: usize,
+    /// Fetch slots of each peer, created on first fetch
+    fetch_limits: DashMap<u32, Arc<Semaphore>>,
 }
 
 impl Pager {
-    fn new(
-        base: *mut u8,
-        len: usize,
-        node_id: u32,
-        total_nodes: u32,
-        coordinator_url: &str,
-    ) -> Result<Self> {
+    /// Create the pager, reaching the coordinator on `runtime`
+    ///
+    /// Must be called outside `runtime`'s async context.
+    fn new(config: PagerConfig, runtime: tokio::runtime::Handle) -> Result<Self> {
+        config.validate()?;
+        let uffd = Self::register_uffd(config.base, config.len, config.mode)?;
+        let identity = Self::load_identity(&config)?;
+
+        // Initialize transport manager
+        info!(
+            "Initializing transport layer for node {}...",
+            config.node_id
+        );
+        let mut transport =
+            TransportManager::new(config.node_id).context("Failed to create transport manager")?;
+        let coordinator = CoordinatorClient::new(config.coordinator.clone(), config.node_id)?
+            .with_timeout(Duration::from_secs(config.coordinator_timeout_secs));
+
+        // Authenticate, then register endpoint with coordinator
+        let fingerprint = config
+            .require_unique_fingerprint
+            .then(Self::compute_hardware_fingerprint)
+            .transpose()?;
+        let auth = runtime
+            .block_on(Self::authenticate(&coordinator, &identity, fingerprint))
+            .context("Failed to authenticate with coordinator")?;
+        let local_endpoint = transport.local_endpoint();
+        runtime
+            .block_on(coordinator.register(&local_endpoint))
+            .context("Failed to register with coordinator")?;
+        info!(
+            "✅ Registered endpoint with coordinator: {:?}",
+            local_endpoint
+        );
+
+        // Discover and connect to all peer nodes
+        transport.set_authenticator(Arc::new(auth.peer_auth(config.node_id, identity)));
+        let endpoints = runtime
+            .block_on(coordinator.fetch_endpoints())
+            .context("Failed to discover peers")?;
+        Self::connect_peers(config.node_id, &mut transport, endpoints)
+            .context("Failed to discover peers")?;
+
+        Self::from_parts(config, uffd, transport, auth, coordinator, runtime)
+    }
+
+    /// Create the pager without blocking the async runtime
+    ///
+    /// userfaultfd registration, transport setup and peer connection run on
+    /// the blocking pool; coordinator requests use the async HTTP client.
+    /// The pager keeps using the current runtime, so it must outlive the pager.
+    /// It owns a transport runtime, so drop it outside async context (e.g.
+    /// in `spawn_blocking`) or hand it to `spawn`.
+    pub async fn new_async(config: PagerConfig) -> Result<Self> {
+        config.validate()?;
+        let PagerConfig {
+            base,
+            len,
+            node_id,
+            total_nodes,
+            coordinator,
+            identity_key_path,
+            push_gateway,
+            overcommit,
+            replacement_policy,
+            placement,
+            watermarks,
+            auto_balance,
+            checkpoint_path,
+            checkpoint_interval,
+            compaction_interval,
+            placement_hints,
+            max_concurrent_fetches_per_node,
+            max_concurrent_migrations,
+            require_unique_fingerprint,
+            mode,
+            page_size,
+            reconnect,
+            prefetch_pages,
+            pool_capacity,
+            fault_threads,
+            coordinator_timeout_secs,
+            metrics_port,
+        } = config;
+        let base = base as usize;
+        let client = CoordinatorClient::new(coordinator.clone(), node_id)?
+            .with_timeout(Duration::from_secs(coordinator_timeout_secs));
+
+        let key_path = identity_key_path.clone();
+        let (uffd, identity, fingerprint) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let uffd = Self::register_uffd(base as *mut u8, len, mode)?;
+            let identity = match key_path {
+                Some(path) => NodeIdentity::load_or_generate(&path)?,
+                None => NodeIdentity::generate(),
+            };
+            let fingerprint = require_unique_fingerprint
+                .then(Self::compute_hardware_fingerprint)
+                .transpose()?;
+            Ok((uffd, identity, fingerprint))
+        })
+        .await
+        .context("Pager initialization task failed")??;
+
+        let auth = Self::authenticate(&client, &identity, fingerprint).await?;
+
+        // Created once the coordinator has accepted the node, as its runtime
+        // can't be dropped here if registration fails
+        let transport = tokio::task::spawn_blocking(move || {
+            info!("Initializing transport layer for node {}...", node_id);
+            TransportManager::new(node_id).context("Failed to create transport manager")
+        })
+        .await
+        .context("Pager initialization task failed")??;
+
+        transport.set_authenticator(Arc::new(auth.peer_auth(node_id, identity)));
+        let local_endpoint = transport.local_endpoint();
+        client.register(&local_endpoint).await?;
+        info!(
+            "✅ Registered endpoint with coordinator: {:?}",
+            local_endpoint
+        );
+        let endpoints = client.fetch_endpoints().await?;
+
+        // Connecting measures peer latency synchronously
+        let transport = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut transport = transport;
+            Self::connect_peers(node_id, &mut transport, endpoints)?;
+            Ok(transport)
+        })
+        .await
+        .context("Peer connection task failed")??;
+
+        let config = PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id,
+            total_nodes,
+            coordinator,
+            identity_key_path,
+            push_gateway,
+            overcommit,
+            replacement_policy,
+            placement,
+            watermarks,
+            auto_balance,
+            checkpoint_path,
+            checkpoint_interval,
+            compaction_interval,
+            placement_hints,
+            max_concurrent_fetches_per_node,
+            max_concurrent_migrations,
+            require_unique_fingerprint,
+            mode,
+            page_size,
+            reconnect,
+            prefetch_pages,
+            pool_capacity,
+            fault_threads,
+            coordinator_timeout_secs,
+            metrics_port,
+        };
+        Self::from_parts(
+            config,
+            uffd,
+            transport,
+            auth,
+            client,
+            tokio::runtime::Handle::current(),
+        )
+    }
+
+    /// Node key pair from `identity_key_path`, or a fresh one
+    fn load_identity(config: &PagerConfig) -> Result<NodeIdentity> {
+        match &config.identity_key_path {
+            Some(path) => NodeIdentity::load_or_generate(path),
+            None => Ok(NodeIdentity::generate()),
+        }
+    }
+
+    /// Create a userfaultfd and register `base..base+len` for the faults of
+    /// `mode`
+    fn register_uffd(base: *mut u8, len: usize, mode: PagerMode) -> Result<Uffd> {
         let uffd = UffdBuilder::new()
             .close_on_exec(true)
-            .non_blocking(false)
+            // Non-blocking: poll() on a blocking userfaultfd always reports an
+            // error, so the fault loop could not wait with a timeout
+            .non_blocking(true)
             .create()
             .context("Failed to create userfaultfd")?;
 
@@ -208,7 +1592,24 @@ impl Pager {
             "Attempting to register memory: base={:p}, len=0x{:x}",
             base, len
         );
-        match unsafe { uffd.register(base as *mut libc::c_void, len) } {
+        let registered = match mode {
+            PagerMode::Missing => uffd.register(base as *mut libc::c_void, len),
+            PagerMode::WriteProtect => {
+                uffd.register_with_mode(base as *mut libc::c_void, len, RegisterMode::WRITE_PROTECT)
+            }
+            PagerMode::MissingAndWriteProtect => {
+                let wp_mode = RegisterMode::MISSING | RegisterMode::WRITE_PROTECT;
+                uffd.register_with_mode(base as *mut libc::c_void, len, wp_mode)
+                    .or_else(|e| {
+                        info!(
+                            "Write-protect faults unavailable ({}); registering for missing faults only",
+                            e
+                        );
+                        uffd.register(base as *mut libc::c_void, len)
+                    })
+            }
+        };
+        match registered {
             Ok(_) => info!("Successfully registered memory with userfaultfd"),
             Err(e) => {
                 eprintln!("Failed to register userfaultfd: {:?}", e);
@@ -217,368 +1618,4133 @@ impl Pager {
         }
 
         info!("Userfaultfd registered: base={:p}, len=0x{:x}", base, len);
+        Ok(uffd)
+    }
 
-        // Initialize transport manager
-        info!("Initializing transport layer for node {}...", node_id);
-        let mut transport =
-            TransportManager::new(node_id).context("Failed to create transport manager")?;
+    fn from_parts(
+        config: PagerConfig,
+        uffd: Uffd,
+        mut transport: TransportManager,
+        auth: ClusterAuth,
+        coordinator: CoordinatorClient,
+        runtime: tokio::runtime::Handle,
+    ) -> Result<Self> {
+        if config.max_concurrent_fetches_per_node == 0 {
+            return Err(anyhow!("At least one concurrent fetch per node is needed"));
+        }
+        if config.max_concurrent_migrations == 0 {
+            return Err(anyhow!("At least one concurrent migration is needed"));
+        }
+        let reloadable = ReloadableConfig::default();
+        let workers = WorkerPool::new(reloadable.worker_threads)?;
+        let (control_tx, control_rx) = crossbeam_channel::unbounded();
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded(1);
+
+        let stats = Arc::new(RwLock::new(PagerStats::default()));
+        let directory = Arc::new(PageDirectory::new(config.node_id));
+        transport.set_directory_epoch(Arc::clone(&directory.epoch));
+        let shutdown = Arc::new(ShutdownSignal::default());
+        let allocator = Arc::new(PageAllocator::new(config.pool_capacity));
+        let metrics_pusher = match config.push_gateway {
+            Some(gateway) => {
+                let stats = Arc::clone(&stats);
+                let directory = Arc::clone(&directory);
+                let allocator = Arc::clone(&allocator);
+                let snapshot = move || stats_snapshot(&stats, &directory, &allocator);
+                Some(metrics::start_pushing(
+                    gateway,
+                    config.node_id,
+                    snapshot,
+                    Arc::clone(&shutdown),
+                )?)
+            }
+            None => None,
+        };
+        let (metrics_server, metrics_addr) = match config.metrics_port {
+            Some(port) => {
+                let stats = Arc::clone(&stats);
+                let directory = Arc::clone(&directory);
+                let allocator = Arc::clone(&allocator);
+                let snapshot = move || stats_snapshot(&stats, &directory, &allocator);
+                let (server, addr) = metrics::serve_metrics(
+                    (Ipv4Addr::UNSPECIFIED, port).into(),
+                    config.node_id,
+                    snapshot,
+                    Arc::clone(&shutdown),
+                )?;
+                (Some(server), Some(addr))
+            }
+            None => (None, None),
+        };
+        let pressure_callback: Arc<RwLock<Box<dyn PressureCallback>>> =
+            Arc::new(RwLock::new(Box::new(LogPressure)));
+        let pressure_monitor = match config.watermarks {
+            Some(watermarks) => {
+                let directory = Arc::downgrade(&directory);
+                Some(pressure::start_monitor(
+                    watermarks,
+                    move || Some(directory.upgrade()?.local_page_count()),
+                    Arc::clone(&pressure_callback),
+                    Arc::clone(&shutdown),
+                )?)
+            }
+            None => None,
+        };
+        let checkpointer = match &config.checkpoint_path {
+            Some(path) => {
+                if path.exists() {
+                    checkpoint::restore(path, &directory)?;
+                }
+                let checkpointer = IncrementalCheckpointer::new(Arc::clone(&directory), path)?;
+                Some(checkpoint::start_checkpointing(
+                    checkpointer,
+                    config.checkpoint_interval,
+                    Arc::clone(&shutdown),
+                )?)
+            }
+            None if config.checkpoint_interval.is_some() => {
+                return Err(anyhow!("checkpoint_interval needs a checkpoint_path"));
+            }
+            None => None,
+        };
+        let dead_nodes = Arc::new(RwLock::new(HashSet::new()));
+        let compactor = match config.compaction_interval {
+            Some(interval) => Some(compaction::start_compacting(
+                Arc::clone(&directory),
+                Arc::clone(&dead_nodes),
+                Arc::clone(&stats),
+                interval,
+                Arc::clone(&shutdown),
+            )?),
+            None => None,
+        };
+        let uffd = Arc::new(uffd);
+        let prefetcher = match config.prefetch_pages {
+            0 => None,
+            depth => Some(SequentialPrefetcher::start(
+                depth,
+                PrefetchContext {
+                    uffd: Arc::clone(&uffd),
+                    base: config.base as u64,
+                    len: config.len,
+                    directory: Arc::clone(&directory),
+                    allocator: Arc::clone(&allocator),
+                    replacement_policy: Arc::clone(&config.replacement_policy),
+                    stats: Arc::clone(&stats),
+                },
+            )?),
+        };
+        let coordinator = Arc::new(coordinator);
+        transport.set_reconnect_policy(config.reconnect);
+        transport.set_peer_locator(Arc::new(CoordinatorPeers {
+            coordinator: Arc::clone(&coordinator),
+            runtime: runtime.clone(),
+        }));
+        let dead_letters = Arc::new(DeadLetterQueue::default());
+        let snapshot = {
+            let stats = Arc::clone(&stats);
+            let directory = Arc::clone(&directory);
+            move || (stats.read().clone(), directory.local_page_count() as u64)
+        };
+        let balloon = {
+            let stats = Arc::clone(&stats);
+            let directory = Arc::clone(&directory);
+            let dead_letters = Arc::clone(&dead_letters);
+            let len = config.len;
+            move || {
+                let compressed_pages = stats.read().compressed_pages;
+                balloon_stats(len, compressed_pages, &dead_letters, &directory)
+            }
+        };
+        let reporter = Arc::new(NodeReporter {
+            coordinator: Arc::clone(&coordinator),
+            sampler: Mutex::new(LoadSampler::new()),
+            snapshot: Box::new(snapshot),
+            balloon: Box::new(balloon),
+        });
+        let transport = Arc::new(RwLock::new(transport));
+        let migration = MigrationCoordinator::new(
+            Arc::clone(&directory),
+            Arc::clone(&transport),
+            config.max_concurrent_migrations,
+        );
+
+        let mut pager = Self {
+            uffd,
+            base: config.base as u64,
+            len: config.len,
+            directory,
+            stats,
+            node_id: config.node_id,
+            total_nodes: config.total_nodes,
+            transport,
+            coordinator: Arc::clone(&coordinator),
+            auth,
+            shutdown,
+            api_server: None,
+            management_addr: None,
+            metrics_pusher,
+            metrics_server,
+            metrics_addr,
+            fault_threads: config.fault_threads,
+            pressure_monitor,
+            pressure_callback,
+            balancer: None,
+            checkpointer,
+            compactor,
+            dead_nodes,
+            gossip_receiver: None,
+            config: Arc::new(RwLock::new(reloadable)),
+            reloader: None,
+            workers: Mutex::new(workers),
+            control_tx: Arc::new(control_tx),
+            control_rx,
+            stop_tx,
+            stop_rx,
+            dedup: None,
+            compressor: None,
+            compressor_thread: None,
+            write_tracked: Mutex::new(HashMap::new()),
+            access_logger: Arc::new(AccessLogger::new()),
+            guard_pages: None,
+            realtime_priority: None,
+            reporter,
+            load_report_interval: LOAD_REPORT_INTERVAL,
+            balloon_report_interval: BALLOON_STATS_INTERVAL,
+            cache: Arc::new(PageCache::new(DEFAULT_PAGE_CACHE_SIZE)?),
+            allocator,
+            inflight: InFlightTracker::new(),
+            overcommit: config.overcommit,
+            replacement_policy: config.replacement_policy,
+            placement: AffinityTracker::new(config.placement),
+            placement_hints: config.placement_hints,
+            hint_cache: Mutex::new(HashMap::new()),
+            page_walker: None,
+            speculative_claims: false,
+            page_size: config.page_size,
+            prefetcher,
+            max_fault_retries: DEFAULT_MAX_FAULT_RETRIES,
+            dead_letters,
+            migration,
+            runtime,
+            max_concurrent_fetches_per_node: config.max_concurrent_fetches_per_node,
+            fetch_limits: DashMap::new(),
+        };
+        if config.auto_balance {
+            let loads =
+                CoordinatorLoad::new(coordinator, pager.runtime.clone(), config.total_nodes);
+            pager.start_balancing(BalancingAgent::new(config.node_id, Box::new(loads)))?;
+        }
+        Ok(pager)
+    }
+
+    /// Run `agent` until the pager shuts down
+    fn start_balancing(&mut self, agent: BalancingAgent) -> Result<()> {
+        self.balancer =
+            Some(agent.start(Arc::clone(&self.control_tx), Arc::clone(&self.shutdown))?);
+        Ok(())
+    }
+
+    /// Exchange ownership changes with peers in the multicast group in
+    /// `config` (see `gossip`)
+    fn start_gossip(&mut self, config: GossipConfig) -> Result<()> {
+        let gossip = Arc::new(OwnershipGossip::join(
+            self.node_id,
+            config,
+            Arc::clone(&self.stats),
+        )?);
+        self.gossip_receiver =
+            Some(gossip.start_receiving(Arc::clone(&self.directory), Arc::clone(&self.shutdown))?);
+        self.directory.gossip_claims(gossip)
+    }
+
+    /// Run the calling thread under `SCHED_FIFO` at `priority` (1-99)
+    ///
+    /// Without `CAP_SYS_NICE` the thread keeps its normal scheduling and a
+    /// warning is logged.
+    pub fn set_realtime_priority(priority: u8) -> Result<()> {
+        if !(1..=99).contains(&priority) {
+            return Err(anyhow!("Real-time priority {} out of range 1-99", priority));
+        }
+
+        let param = libc::sched_param {
+            sched_priority: priority as i32,
+        };
+        // SAFETY: pthread_self() is always a valid thread handle
+        let ret = unsafe {
+            libc::pthread_setschedparam(nix::sys::pthread::pthread_self(), libc::SCHED_FIFO, &param)
+        };
+        match ret {
+            0 => {
+                info!(
+                    "Fault handler running with SCHED_FIFO priority {}",
+                    priority
+                );
+                Ok(())
+            }
+            libc::EPERM => {
+                warn!(
+                    "No permission for SCHED_FIFO priority {} (needs CAP_SYS_NICE), keeping normal scheduling",
+                    priority
+                );
+                Ok(())
+            }
+            err => Err(anyhow!(
+                "Failed to set SCHED_FIFO priority {}: {}",
+                priority,
+                std::io::Error::from_raw_os_error(err)
+            )),
+        }
+    }
+
+    /// Map inaccessible pages around the region to catch out-of-bounds access
+    fn enable_guard_pages(&mut self, before: bool, after: bool) -> Result<()> {
+        self.guard_pages = Some(GuardPages::new(self.base, self.len, before, after)?);
+        Ok(())
+    }
+
+    /// Compress cold local pages in the background (see `compressor`)
+    fn enable_compression(&mut self) -> Result<()> {
+        let compressor = Arc::new(ColdPageCompressor::new(Arc::clone(&self.stats)));
+        self.compressor_thread = Some(compressor::start_compressing(
+            Arc::clone(&compressor),
+            Arc::clone(&self.uffd),
+            self.base,
+            Arc::clone(&self.directory),
+            Arc::clone(&self.shutdown),
+        )?);
+        self.compressor = Some(compressor);
+        info!("Cold page compression enabled");
+        Ok(())
+    }
+
+    /// Route remote fetches through the coordinator's dedup index
+    fn enable_deduplication(&mut self) -> Result<()> {
+        let index = CoordinatorDedupIndex::new(Arc::clone(&self.coordinator), self.runtime.clone());
+        self.dedup = Some(Arc::new(DeduplicationLayer::new(
+            Arc::clone(&self.transport),
+            Box::new(index),
+        )));
+        info!("Page deduplication enabled");
+        Ok(())
+    }
+
+    /// Load live settings from `path` and re-read them on SIGHUP
+    fn enable_config_reload(&mut self, path: PathBuf) -> Result<()> {
+        let reloader = ConfigReloader::new(path)?;
+        self.config = reloader.config();
+        self.workers
+            .lock()
+            .resize(self.config.read().worker_threads)?;
+        self.transport
+            .write()
+            .set_bandwidth_limit(self.config.read().bandwidth_limit_mbps);
+        reload::install_sighup_handler(Arc::clone(&self.control_tx))?;
+
+        info!(
+            "Pager config loaded from {} (send SIGHUP to reload)",
+            reloader.path().display()
+        );
+        self.reloader = Some(reloader);
+        Ok(())
+    }
+
+    /// Apply control messages queued since the last fault
+    fn process_control_messages(&self) {
+        while let Ok(msg) = self.control_rx.try_recv() {
+            match msg {
+                ControlMessage::ConfigReload => self.apply_config_reload(),
+                ControlMessage::AbortSpeculative { page_num, owner } => {
+                    if let Err(e) = self.abort_speculative(page_num, owner) {
+                        warn!(
+                            "Failed to abort speculative claim of page {}: {:#}",
+                            page_num, e
+                        );
+                    }
+                }
+                ControlMessage::Prefault(pages) => {
+                    if let Err(e) = self.prefault_pages(&pages) {
+                        warn!("Failed to prefault {} pages: {:#}", pages.len(), e);
+                    }
+                }
+                ControlMessage::Rebalance(rebalance) => {
+                    if let Err(e) = self.rebalance(rebalance.target_node, rebalance.pages) {
+                        warn!(
+                            "Failed to migrate pages to node {}: {:#}",
+                            rebalance.target_node, e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_config_reload(&self) {
+        let Some(reloader) = &self.reloader else {
+            warn!("Config reload requested but no config file was given");
+            return;
+        };
+
+        let old = match reloader.reload() {
+            Ok(old) => old,
+            Err(e) => {
+                warn!("Config reload failed, keeping current settings: {:#}", e);
+                return;
+            }
+        };
+
+        let config = self.config.read().clone();
+        if config.worker_threads != old.worker_threads {
+            info!(
+                "Resizing pager workers: {} -> {}",
+                old.worker_threads, config.worker_threads
+            );
+            let mut workers = self.workers.lock();
+            if let Err(e) = workers.resize(config.worker_threads) {
+                warn!(
+                    "Running {} of {} pager workers: {:#}",
+                    workers.size(),
+                    config.worker_threads,
+                    e
+                );
+            }
+        }
+        if config.bandwidth_limit_mbps != old.bandwidth_limit_mbps {
+            self.transport
+                .write()
+                .set_bandwidth_limit(config.bandwidth_limit_mbps);
+        }
+    }
+
+    /// Start the HTTP management API on `addr`
+    fn start_management_api(&mut self, addr: SocketAddr) -> Result<()> {
+        let state = api::ApiState {
+            stats: Arc::clone(&self.stats),
+            directory: Arc::clone(&self.directory),
+            allocator: Arc::clone(&self.allocator),
+            transport: Arc::clone(&self.transport),
+            shutdown: Arc::clone(&self.shutdown),
+            dead_letters: Arc::clone(&self.dead_letters),
+            migration: self.migration.clone(),
+            base: self.base,
+            len: self.len,
+            auth: Arc::new(ManagementAuth::new(self.node_id, self.auth.coordinator_key)),
+        };
+        let (server, addr) = api::serve(state, addr)?;
+        self.api_server = Some(server);
+        self.management_addr = Some(addr);
+        Ok(())
+    }
+
+    /// Hash of this host's machine ID, first NIC's MAC address and hostname
+    /// (see `identity::hardware_fingerprint`)
+    pub fn compute_hardware_fingerprint() -> Result<String> {
+        identity::hardware_fingerprint(Path::new("/"))
+    }
+
+    /// Register the node's public key, and the host's fingerprint if given,
+    /// and obtain an auth token, which `coordinator` sends from then on
+    async fn authenticate(
+        coordinator: &CoordinatorClient,
+        identity: &NodeIdentity,
+        fingerprint: Option<String>,
+    ) -> Result<ClusterAuth> {
+        let node_id = coordinator.node_id();
+        let registration = identity.registration(node_id, fingerprint);
+        let response = coordinator
+            .send(|http, url| {
+                http.post(format!("{}/auth/register", url))
+                    .json(&registration)
+                    .timeout(coordinator.timeout())
+            })
+            .await
+            .context("Failed to send identity registration")?;
+
+        if !response.status().is_success() {
+            return Err(registration_rejected(node_id, response.status()));
+        }
+
+        let registration = response
+            .json()
+            .await
+            .context("Failed to parse identity registration response")?;
+        let auth = ClusterAuth::from_registration(node_id, registration)?;
+        coordinator.authorize(auth.bearer());
+        Ok(auth)
+    }
+
+    /// Send this node's current `LoadMetrics` to the coordinator
+    pub fn report_load_to_coordinator(&self) -> Result<()> {
+        self.runtime.block_on(self.reporter.report_load())
+    }
+
+    /// Report load to the coordinator every `load_report_interval`, and
+    /// balloon stats every `balloon_report_interval`, until shutdown
+    ///
+    /// Runs on its own thread so a slow coordinator never holds up the
+    /// fault loop.
+    fn start_reporting(&self) -> Result<JoinHandle<()>> {
+        let reporter = Arc::clone(&self.reporter);
+        let shutdown = Arc::clone(&self.shutdown);
+        let (load_interval, balloon_interval) =
+            (self.load_report_interval, self.balloon_report_interval);
+        let runtime = self.runtime.clone();
+
+        thread::Builder::new()
+            .name("pager-report".to_string())
+            .spawn(move || {
+                runtime.block_on(async {
+                    let mut next_load_report = Instant::now() + load_interval;
+                    let mut next_balloon_report = Instant::now() + balloon_interval;
+                    loop {
+                        let next = next_load_report.min(next_balloon_report);
+                        tokio::select! {
+                            _ = shutdown.wait() => break,
+                            _ = tokio::time::sleep_until(next.into()) => {}
+                        }
+                        if Instant::now() >= next_load_report {
+                            if let Err(e) = reporter.report_load().await {
+                                debug!("Load report failed: {:#}", e);
+                            }
+                            next_load_report = Instant::now() + load_interval;
+                        }
+                        if Instant::now() >= next_balloon_report {
+                            if let Err(e) = reporter.report_balloon_stats().await {
+                                debug!("Balloon stats report failed: {:#}", e);
+                            }
+                            next_balloon_report = Instant::now() + balloon_interval;
+                        }
+                    }
+                })
+            })
+            .context("Failed to spawn report thread")
+    }
+
+    /// Where the guest's pages are (see `BalloonStats`)
+    pub fn balloon_stats(&self) -> BalloonStats {
+        (self.reporter.balloon)()
+    }
+
+    /// Send this node's current `BalloonStats` to the coordinator
+    pub fn report_balloon_stats_to_coordinator(&self) -> Result<()> {
+        self.runtime.block_on(self.reporter.report_balloon_stats())
+    }
+
+    /// Connect to every endpoint the coordinator listed except our own
+    ///
+    /// Peers that registered without an identity, or fail to authenticate
+    /// as the connection opens, are skipped.
+    fn connect_peers(
+        local_node_id: u32,
+        transport: &mut TransportManager,
+        endpoints_resp: EndpointsResponse,
+    ) -> Result<()> {
+        info!(
+            "📋 Discovered {} peer nodes",
+            endpoints_resp.endpoints.len()
+        );
+
+        // Connect to all peers except self
+        for (node_id_str, coord_endpoint) in &endpoints_resp.endpoints {
+            let peer_node_id: u32 = node_id_str.parse().context("Invalid node ID in response")?;
+
+            if peer_node_id == local_node_id {
+                continue; // Skip self
+            }
+
+            if let Err(e) = endpoints_resp.public_key(peer_node_id) {
+                warn!(
+                    "Not connecting to unauthenticated node {}: {:#}",
+                    peer_node_id, e
+                );
+                continue;
+            }
+
+            let transport_endpoint = coord_endpoint
+                .to_transport_endpoint()
+                .context("Failed to convert endpoint")?;
+
+            match transport.connect_peer(peer_node_id, transport_endpoint) {
+                Err(e)
+                    if e.downcast_ref::<TransportError>()
+                        == Some(&TransportError::AuthenticationFailed) =>
+                {
+                    warn!(
+                        "Not connecting to unauthenticated node {}: {:#}",
+                        peer_node_id, e
+                    );
+                }
+                result => result.context(format!("Failed to connect to node {}", peer_node_id))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The next userfaultfd event, or `None` if none arrives within
+    /// `timeout_ms`
+    fn next_event(&self, timeout_ms: i32) -> Result<Option<Event>> {
+        let mut pfd = libc::pollfd {
+            fd: self.uffd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `pfd` is a single valid pollfd
+        let ready = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+        if ready < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(error).context("poll on userfaultfd failed");
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+        // A woken fault may already be gone, which the non-blocking read
+        // reports as no event
+        self.uffd
+            .read_event()
+            .context("Failed to read userfaultfd event")
+    }
+
+    /// Main fault handling loop
+    ///
+    /// Runs until `PagerHandle::stop` or a shutdown request (e.g. via
+    /// `POST /api/v1/shutdown`), then stops the pager's threads and
+    /// deregisters from the coordinator. A fault already read is always
+    /// resolved before the loop checks for either.
+    fn handle_faults(mut self) -> Result<()> {
+        info!(
+            "Pager: fault handling loop started on node {}",
+            self.node_id
+        );
+
+        if let Some(priority) = self.realtime_priority {
+            Self::set_realtime_priority(priority)?;
+        }
+
+        let reporter = self.start_reporting()?;
+
+        // Every fault thread waits on the uffd, which hands each event to
+        // one of them
+        let pager = &self;
+        thread::scope(|scope| -> Result<()> {
+            for index in 1..pager.fault_threads {
+                let spawned = thread::Builder::new()
+                    .name(format!("pager-node{}-fault{}", pager.node_id, index))
+                    .spawn_scoped(scope, move || {
+                        if let Some(priority) = pager.realtime_priority {
+                            if let Err(e) = Self::set_realtime_priority(priority) {
+                                warn!("{:#}", e);
+                            }
+                        }
+                        pager.serve_faults(false)
+                    });
+                if let Err(e) = spawned {
+                    // Lets the fault threads already started finish
+                    pager.shutdown.trigger();
+                    return Err(e).context("Failed to spawn fault thread");
+                }
+            }
+            pager.serve_faults(true);
+            // Stopped through the handle: the other threads watch the signal
+            pager.shutdown.trigger();
+            Ok(())
+        })?;
+
+        info!(
+            "Pager: fault handling loop stopped on node {}",
+            self.node_id
+        );
+        if let Err(e) = self
+            .transport
+            .read()
+            .shutdown_gracefully(TRANSPORT_DRAIN_TIMEOUT)
+        {
+            warn!("Transport did not shut down cleanly: {:#}", e);
+        }
+        if let Some(server) = self.api_server.take() {
+            let _ = server.join();
+        }
+        let _ = reporter.join();
+        if let Some(pusher) = self.metrics_pusher.take() {
+            let _ = pusher.join();
+        }
+        if let Some(server) = self.metrics_server.take() {
+            let _ = server.join();
+        }
+        if let Some(monitor) = self.pressure_monitor.take() {
+            let _ = monitor.join();
+        }
+        if let Some(balancer) = self.balancer.take() {
+            let _ = balancer.join();
+        }
+        if let Some(checkpointer) = self.checkpointer.take() {
+            let _ = checkpointer.join();
+        }
+        if let Some(compactor) = self.compactor.take() {
+            let _ = compactor.join();
+        }
+        if let Some(compressor) = self.compressor_thread.take() {
+            let _ = compressor.join();
+        }
+        if let Some(receiver) = self.gossip_receiver.take() {
+            let _ = receiver.join();
+        }
+        // Once nothing reports for this node any more
+        if let Err(e) = self.runtime.block_on(self.coordinator.deregister()) {
+            warn!("{:#}", e);
+        }
+        Ok(())
+    }
+
+    /// Resolve faults until the pager stops
+    ///
+    /// The `primary` fault thread also applies control messages and watches
+    /// the stop channel; the others stop on the shutdown signal it triggers.
+    fn serve_faults(&self, primary: bool) {
+        let mut sampler = self.stats.read().fault_service_sample.sampler();
+        // This thread's faults, from which its prefetch depth is tuned
+        let mut pattern = AccessPatternDetector::new();
+
+        loop {
+            if primary {
+                crossbeam_channel::select! {
+                    recv(self.stop_rx) -> _ => break,
+                    default => {}
+                }
+                self.process_control_messages();
+            }
+            if self.shutdown.is_triggered() {
+                break;
+            }
+
+            // Wait for a fault, waking periodically to observe shutdown
+            let event = match self.next_event(FAULT_POLL_TIMEOUT_MS) {
+                Ok(Some(event)) => event,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to read uffd event: {:#}", e);
+                    continue;
+                }
+            };
+
+            match event {
+                Event::Pagefault {
+                    kind: FaultKind::WriteProtected,
+                    addr,
+                    ..
+                } => {
+                    let fault_addr = addr as u64;
+                    if let Err(e) = self.handle_write_protect_fault(fault_addr) {
+                        warn!(
+                            "Failed to handle write-protect fault at 0x{:x}: {:#}",
+                            fault_addr, e
+                        );
+                    }
+                }
+                Event::Pagefault { addr, .. } => {
+                    let start = std::time::Instant::now();
+                    let fault_addr = addr as u64;
+
+                    if let Err(e) = self.handle_pagefault(fault_addr, &mut pattern) {
+                        warn!("Failed to handle page fault at 0x{:x}: {}", fault_addr, e);
+                    }
+
+                    // Only sampled faults take the stats lock
+                    let elapsed = start.elapsed().as_micros() as u64;
+                    if let Some(faults) = sampler.sample() {
+                        let mut stats = self.stats.write();
+                        stats.fault_service_time_us.push(elapsed);
+                        stats.fault_service_sample.record_sampled(elapsed, faults);
+                    }
+
+                    debug!(
+                        "Fault serviced: addr=0x{:x}, time={}µs",
+                        fault_addr, elapsed
+                    );
+                }
+                Event::Fork { .. } => {
+                    info!("Fork event (unhandled)");
+                }
+                Event::Remap { .. } => {
+                    info!("Remap event (unhandled)");
+                }
+                Event::Remove { .. } => {
+                    info!("Remove event (unhandled)");
+                }
+                Event::Unmap { .. } => {
+                    info!("Unmap event (unhandled)");
+                }
+            }
+        }
+        self.stats
+            .write()
+            .fault_service_sample
+            .count_unsampled(sampler.take_pending());
+    }
+
+    /// Handle a single page fault
+    ///
+    /// `pattern` holds the recent faults of the calling fault thread.
+    fn handle_pagefault(&self, fault_addr: u64, pattern: &mut AccessPatternDetector) -> Result<()> {
+        if fault_addr < self.base || fault_addr >= self.base + self.len as u64 {
+            return Err(anyhow!(
+                "Fault address 0x{:x} outside registered region",
+                fault_addr
+            ));
+        }
+
+        let page_num = (fault_addr - self.base) / PAGE_SIZE as u64;
+        let start = Instant::now();
+        let prefetch_depth = self.track_access_pattern(pattern, page_num);
+
+        debug!("Page fault: addr=0x{:x}, page_num={}", fault_addr, page_num);
+        // Remote fetches carry this span to the owner (see
+        // `rdma_transport::trace`)
+        let tracer = global::tracer("pager");
+        let span = tracer
+            .span_builder("handle_pagefault")
+            .with_attributes([KeyValue::new("gpa", (fault_addr - self.base) as i64)])
+            .start(&tracer);
+        let _trace = opentelemetry::Context::current_with_span(span).attach();
+        if let Some(compressor) = &self.compressor {
+            compressor.record_access(page_num);
+        }
+
+        let mut stale_retries = 0;
+        let mut failures = 0;
+        loop {
+            // Check ownership
+            let (owner, epoch) = self.directory.read_with_epoch(page_num);
+
+            match owner {
+                PageOwner::Local | PageOwner::LocalHuge | PageOwner::Speculative(_) => {
+                    // Already local: a page dropped when it was compressed,
+                    // else zero-fill (shouldn't happen in normal operation)
+                    self.replacement_policy.record_access(page_num);
+                    self.placement.record_access(page_num, self.node_id);
+                    if !self.restore_compressed(page_num)? {
+                        let size_class = self.page_size_class(fault_addr - self.base);
+                        self.resolve_with_zeros_sized(fault_addr, size_class)?;
+                    }
+                    self.log_access(fault_addr, FaultType::Local);
+                    let mut stats = self.stats.write();
+                    stats.local_faults += 1;
+                    stats
+                        .local_resolved_latency_us
+                        .record(start.elapsed().as_micros() as u64);
+                }
+                PageOwner::Remote(node) => {
+                    self.placement.record_access(page_num, self.node_id);
+                    // Fetch from remote node via RDMA, once per page however many
+                    // threads fault on it
+                    let fetched = match self.inflight.begin(fault_addr - self.base) {
+                        InFlight::Leader(_fetching) => {
+                            self.fetch_remote_page(fault_addr, node, epoch)
+                        }
+                        InFlight::Follower(waiter) => {
+                            waiter.wait();
+                            self.stats.write().deduplicated_faults += 1;
+                            // Served from the cache the leader filled
+                            self.fetch_remote_page(fault_addr, node, epoch)
+                        }
+                    };
+                    match fetched {
+                        // The owner has not caught up with a migration we
+                        // know of; the page may have moved, so look again
+                        Err(e) if is_stale_epoch(&e) && stale_retries < STALE_EPOCH_RETRIES => {
+                            stale_retries += 1;
+                            self.stats.write().stale_epoch_retries += 1;
+                            debug!(
+                                "Stale epoch fetching 0x{:x} from node {}, retry {}: {:#}",
+                                fault_addr, node, stale_retries, e
+                            );
+                            thread::sleep(STALE_EPOCH_BACKOFF * stale_retries);
+                            continue;
+                        }
+                        // E.g. a network partition; zeros keep the guest
+                        // running, at the cost of the page's contents
+                        Err(e) if is_unreachable(&e) => {
+                            warn!(
+                                "Node {} unreachable; resolving 0x{:x} with zeros: {:#}",
+                                node, fault_addr, e
+                            );
+                            self.resolve_with_zeros(fault_addr)?;
+                            self.stats.write().timeout_faults += 1;
+                            self.log_access(fault_addr, FaultType::Remote);
+                            return Ok(());
+                        }
+                        // A cancelled fetch (the owner may have changed back)
+                        // counts as a failure, else repeated cancellations
+                        // would retry forever
+                        Err(e) if failures < self.max_fault_retries => {
+                            failures += 1;
+                            if migration::is_cancelled(&e) {
+                                debug!(
+                                    "Fetch of 0x{:x} from node {} cancelled, retry {}",
+                                    fault_addr, node, failures
+                                );
+                            } else {
+                                debug!(
+                                    "Fetching 0x{:x} from node {} failed, retry {}: {:#}",
+                                    fault_addr, node, failures, e
+                                );
+                            }
+                            thread::sleep(FAULT_RETRY_BACKOFF * failures);
+                            continue;
+                        }
+                        Err(e) => {
+                            self.dead_letter(fault_addr, e)?;
+                            self.log_access(fault_addr, FaultType::Remote);
+                            return Ok(());
+                        }
+                        Ok(()) => {}
+                    }
+                    self.log_access(fault_addr, FaultType::Remote);
+                    {
+                        let mut stats = self.stats.write();
+                        stats.remote_faults += 1;
+                        stats
+                            .remote_fetch_latency_us
+                            .record(start.elapsed().as_micros() as u64);
+                    }
+                    if let Err(e) = self.prefetch_remote(page_num, prefetch_depth) {
+                        warn!("Failed to prefetch after page {}: {:#}", page_num, e);
+                    }
+                }
+                PageOwner::Unknown => {
+                    let home = self.place(page_num);
+                    if home != self.node_id {
+                        // Then fetch it from there like any remote page
+                        match self.place_on_peer(page_num, home) {
+                            Ok(()) => continue,
+                            Err(e) => warn!("Keeping page {} local: {:#}", page_num, e),
+                        }
+                    }
+
+                    // First touch - claim ownership and zero-fill
+                    if !self.first_touch_huge(fault_addr)? {
+                        self.make_room()?;
+                        if self.speculative_claims {
+                            self.directory
+                                .set_owner(page_num, PageOwner::Speculative(self.node_id));
+                        } else {
+                            self.directory.claim_page(page_num);
+                        }
+                        self.replacement_policy.record_claim(page_num);
+                        self.resolve_with_zeros(fault_addr)?;
+                    }
+                    self.log_access(fault_addr, FaultType::FirstTouch);
+                    {
+                        let mut stats = self.stats.write();
+                        stats.local_faults += 1;
+                        stats
+                            .first_touch_latency_us
+                            .record(start.elapsed().as_micros() as u64);
+                    }
+                    // This may have been the last page of its 2 MiB range
+                    let huge_start = page_num - page_num % HUGE_PAGE_PAGES;
+                    self.try_promote_huge(huge_start * PAGE_SIZE as u64);
+                    if self.speculative_claims {
+                        self.confirm_speculation(page_num);
+                    }
+                    self.prefetch_following(page_num);
+                }
+            }
+
+            return Ok(());
+        }
+    }
+
+    /// Claim and zero-fill, in one go, the 2 MiB page holding the first
+    /// touch at `addr` if pages are `PageSizeConfig::Huge2M`
+    ///
+    /// The whole huge page becomes local, whatever `placement` would do
+    /// with its other 4 KiB pages. Returns false, for the fault to be
+    /// resolved 4 KiB at a time, if some of the huge page was touched
+    /// before, it does not fit the region, or claims are capped or
+    /// speculative.
+    fn first_touch_huge(&self, addr: u64) -> Result<bool> {
+        if self.page_size.size_class() != PageSizeClass::Huge2M
+            || self.speculative_claims
+            || self.overcommit != OvercommitPolicy::Strict
+        {
+            return Ok(false);
+        }
+        let start = PageSizeClass::Huge2M.align_page((addr - self.base) / PAGE_SIZE as u64);
+        let pages = start..start + HUGE_PAGE_PAGES;
+        if pages.end * PAGE_SIZE as u64 > self.len as u64
+            || pages
+                .clone()
+                .any(|page_num| self.directory.get_owner(page_num) != PageOwner::Unknown)
+        {
+            return Ok(false);
+        }
+
+        for page_num in pages {
+            self.directory.claim_page(page_num);
+            self.replacement_policy.record_claim(page_num);
+        }
+        self.resolve_with_zeros_sized(addr, PageSizeClass::Huge2M)?;
+        self.try_promote_huge(start * PAGE_SIZE as u64);
+        Ok(true)
+    }
+
+    /// Pass the resolved fault at `addr` to the access logger
+    fn log_access(&self, addr: u64, fault_type: FaultType) {
+        if !self.access_logger.is_empty() {
+            self.access_logger
+                .log(addr - self.base, fault_type, SystemTime::now());
+        }
+    }
+
+    /// Give up on the remote fault at `addr`: resolve it with zeros so the
+    /// guest can go on, forget the page's owner and record it in the
+    /// dead-letter queue
+    fn dead_letter(&self, addr: u64, e: anyhow::Error) -> Result<()> {
+        let gpa = addr - self.base;
+        error!(
+            "Giving up on 0x{:x} after {} retries; resolving with zeros: {:#}",
+            addr, self.max_fault_retries, e
+        );
+        self.dead_letters.push(gpa, format!("{:#}", e));
+        self.cache.invalidate(gpa);
+        self.directory
+            .set_owner(gpa / PAGE_SIZE as u64, PageOwner::Unknown);
+        self.stats.write().dead_lettered_faults += 1;
+        let zero_page = self.allocator.alloc_zeroed();
+        Self::install_page(&self.uffd, addr, zero_page.as_ptr())
+    }
+
+    /// Faults given up on, oldest first (see `dlq`)
+    pub fn dead_letter_queue(&self) -> Vec<DlqEntry> {
+        self.dead_letters.entries()
+    }
+
+    /// Back the 2 MiB range at guest address `start_addr` with a huge page if
+    /// this node owns all of it
+    ///
+    /// Guest addresses are offsets into the pager's region. Returns whether
+    /// the range was promoted.
+    pub fn try_promote_huge(&self, start_addr: u64) -> bool {
+        let huge_page_size = HUGE_PAGE_SIZE as u64;
+        if start_addr + huge_page_size > self.len as u64
+            || !self.directory.promote_region(start_addr / PAGE_SIZE as u64)
+        {
+            return false;
+        }
+
+        let addr = (self.base + start_addr) as *mut libc::c_void;
+        // SAFETY: the range lies within the registered region
+        let ret = unsafe { libc::madvise(addr, huge_page_size as usize, libc::MADV_HUGEPAGE) };
+        if ret != 0 {
+            warn!(
+                "Failed to mark 0x{:x} for huge pages: {}",
+                start_addr,
+                std::io::Error::last_os_error()
+            );
+        }
+        debug!("Promoted 0x{:x} to a huge page", start_addr);
+        self.stats.write().huge_page_promotions += 1;
+        true
+    }
+
+    /// Forget the pages at guest addresses `gpas`, which the guest has given
+    /// back, so their next access faults as a first touch
+    ///
+    /// Guest addresses are offsets into the pager's region. Whatever copy
+    /// is mapped here is unmapped, whoever owned the page; other nodes'
+    /// directories are not told.
+    pub fn handle_balloon_deflate(&self, gpas: &[u64]) -> Result<()> {
+        if let Some(gpa) = gpas.iter().find(|&&gpa| gpa >= self.len as u64) {
+            return Err(anyhow!("Released address 0x{:x} outside the region", gpa));
+        }
+
+        let released = self.directory.release_pages(gpas);
+        for &gpa in gpas {
+            self.cache.invalidate(gpa);
+            if let Some(compressor) = &self.compressor {
+                compressor.forget(gpa / PAGE_SIZE as u64);
+            }
+            self.discard_local_copy(gpa / PAGE_SIZE as u64)?;
+        }
+        let collected = self.directory.gc_unknown_entries();
+        self.stats.write().gc_entries_freed += (released + collected) as u64;
+        debug!(
+            "Released {} of {} pages returned by the guest",
+            released,
+            gpas.len()
+        );
+        Ok(())
+    }
+
+    /// Install the remote pages in the guest range `gpa_start..gpa_end`
+    /// before the guest faults on them, returning how many were installed
+    ///
+    /// Guest addresses are offsets into the pager's region; the range is
+    /// widened to whole pages. Pages that are local, untouched or already
+    /// present are left alone, as are pages whose owner cannot be reached.
+    pub fn prefault_region(&self, gpa_start: u64, gpa_end: u64) -> Result<u64> {
+        if gpa_start > gpa_end || gpa_end > self.len as u64 {
+            return Err(anyhow!(
+                "Prefault range 0x{:x}-0x{:x} outside the region",
+                gpa_start,
+                gpa_end
+            ));
+        }
+        let first = gpa_start / PAGE_SIZE as u64;
+        let last = gpa_end.div_ceil(PAGE_SIZE as u64);
+        self.prefault_pages(&(first..last).collect::<Vec<_>>())
+    }
+
+    /// `prefault_region` for the pages numbered `pages`
+    fn prefault_pages(&self, pages: &[u64]) -> Result<u64> {
+        let mut remote = Vec::new();
+        for &page_num in pages {
+            if let PageOwner::Remote(owner) = self.directory.get_owner(page_num) {
+                if !self.is_present(page_num)? {
+                    remote.push((page_num, owner));
+                }
+            }
+        }
+        prefault::fetch_into_cache(&self.transport, &self.cache, self.base, &remote)?;
+
+        let mut installed = 0;
+        for (page_num, _) in remote {
+            let gpa = page_num * PAGE_SIZE as u64;
+            // Evicted again if the cache is smaller than the range
+            let copied = self.cache.with_page(gpa, |cached| {
+                Self::copy_page(&self.uffd, self.base + gpa, cached)
+            });
+            if let Some(true) = copied.transpose()? {
+                installed += 1;
+            }
+        }
+        self.stats.write().proactive_installs += installed;
+        debug!("Prefaulted {} of {} pages", installed, pages.len());
+        Ok(installed)
+    }
+
+    /// Whether `page_num` is mapped in this node's region
+    fn is_present(&self, page_num: u64) -> Result<bool> {
+        is_resident(self.base + page_num * PAGE_SIZE as u64)
+    }
+
+    /// Install `page_num` from its compressed copy, if it has one
+    fn restore_compressed(&self, page_num: u64) -> Result<bool> {
+        let Some(compressor) = &self.compressor else {
+            return Ok(false);
+        };
+        let Some(data) = compressor.take(page_num)? else {
+            return Ok(false);
+        };
+        Self::install_page(
+            &self.uffd,
+            self.base + page_num * PAGE_SIZE as u64,
+            data.as_ptr(),
+        )?;
+        debug!("Decompressed page {}", page_num);
+        Ok(true)
+    }
+
+    /// Resolve a write to a write-protected page
+    ///
+    /// A page write-protected while it was compressed has been dropped by
+    /// the time the fault is read, or was left in place because it did not
+    /// compress. A page protected by `write_protect_page` is copied first,
+    /// so `take_written_pages` has its contents from before the write.
+    fn handle_write_protect_fault(&self, fault_addr: u64) -> Result<()> {
+        self.stats.write().write_protect_faults += 1;
+        let page_addr = fault_addr & !(PAGE_SIZE as u64 - 1);
+        let page_num = (page_addr - self.base) / PAGE_SIZE as u64;
+        if self.restore_compressed(page_num)? {
+            self.log_access(fault_addr, FaultType::Local);
+            return Ok(());
+        }
+        if let Some(copy @ None) = self.write_tracked.lock().get_mut(&page_num) {
+            // SAFETY: the page is present, and still write-protected so the
+            // write has not happened yet
+            let data = unsafe { std::slice::from_raw_parts(page_addr as *const u8, PAGE_SIZE) };
+            *copy = Some(data.to_vec());
+        }
+        self.uffd
+            .remove_write_protection(page_addr as *mut libc::c_void, PAGE_SIZE, true)
+            .context("Failed to remove write protection")
+    }
+
+    /// Fault on the next write to local `page_num`, keeping a copy of its
+    /// contents before the write (see `take_written_pages`)
+    ///
+    /// For pre-copy migration: protect a page once it has been sent, and
+    /// send it again if it was written.
+    pub fn write_protect_page(&self, page_num: u64) -> Result<()> {
+        if !matches!(
+            self.directory.get_owner(page_num),
+            PageOwner::Local | PageOwner::LocalHuge
+        ) || !self.is_present(page_num)?
+        {
+            return Err(anyhow!("Page {} is not present locally", page_num));
+        }
+        let page_addr = self.base + page_num * PAGE_SIZE as u64;
+        let mut tracked = self.write_tracked.lock();
+        self.uffd
+            .write_protect(page_addr as *mut libc::c_void, PAGE_SIZE)
+            .with_context(|| format!("Failed to write-protect page {}", page_num))?;
+        tracked.insert(page_num, None);
+        Ok(())
+    }
+
+    /// Pages written since `write_protect_page`, with their contents from
+    /// before the write; they are no longer protected or tracked
+    pub fn take_written_pages(&self) -> HashMap<u64, Vec<u8>> {
+        let mut tracked = self.write_tracked.lock();
+        let written: Vec<u64> = tracked
+            .iter()
+            .filter(|(_, copy)| copy.is_some())
+            .map(|(&page_num, _)| page_num)
+            .collect();
+        written
+            .into_iter()
+            .filter_map(|page_num| Some((page_num, tracked.remove(&page_num)??)))
+            .collect()
+    }
+
+    /// Prefault pages the VMM hints at from a background thread (see
+    /// `prefault`)
+    pub fn prefaulter(&self) -> Result<Prefaulter> {
+        Prefaulter::start(PrefaultContext {
+            base: self.base,
+            len: self.len,
+            directory: Arc::clone(&self.directory),
+            transport: Arc::clone(&self.transport),
+            cache: Arc::clone(&self.cache),
+            control_tx: Arc::clone(&self.control_tx),
+        })
+    }
+
+    /// Evict a local page if claiming one more would exceed the overcommit
+    /// limit
+    fn make_room(&self) -> Result<()> {
+        let OvercommitPolicy::Lazy { max_local_pages } = self.overcommit else {
+            return Ok(());
+        };
+        if self.directory.local_page_count() < max_local_pages {
+            return Ok(());
+        }
+        match self.replacement_policy.select_victim(&self.directory) {
+            Some(victim) => self.evict_page(victim),
+            None => {
+                warn!(
+                    "{} local pages but no eviction victim; exceeding the limit",
+                    max_local_pages
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Hand a local page to a peer and unmap it, so the next access fetches
+    /// it back
+    fn evict_page(&self, page_num: u64) -> Result<()> {
+        let transport = self.transport.read();
+        let peers = transport.peers();
+        if peers.is_empty() {
+            return Err(anyhow!("No peer to evict page {} to", page_num));
+        }
+        // Spread evicted pages over the peers
+        let (target, _) = peers[(page_num % peers.len() as u64) as usize];
+        drop(transport);
+
+        self.migrate_page(page_num, target)
+            .with_context(|| format!("Failed to evict page {}", page_num))?;
+        self.stats.write().evictions += 1;
+        debug!("Evicted page {} to node {}", page_num, target);
+        Ok(())
+    }
+
+    /// Migrate up to `pages` local pages, chosen by the replacement policy,
+    /// to `target_node`
+    fn rebalance(&self, target_node: u32, pages: usize) -> Result<()> {
+        for _ in 0..pages {
+            let Some(victim) = self.replacement_policy.select_victim(&self.directory) else {
+                debug!("No local page left to migrate to node {}", target_node);
+                break;
+            };
+            self.migrate_page(victim, target_node)?;
+            self.stats.write().balance_migrations += 1;
+        }
+        Ok(())
+    }
+
+    /// Send a local page to `target` and unmap it, so the next access
+    /// fetches it back
+    fn migrate_page(&self, page_num: u64, target: u32) -> Result<()> {
+        let addr = self.base + page_num * PAGE_SIZE as u64;
+        let data = self.read_page(page_num)?;
+        self.transport
+            .read()
+            .send_page(addr, &data, target)
+            .with_context(|| format!("Failed to send page {} to node {}", page_num, target))?;
+
+        self.directory
+            .set_owner(page_num, PageOwner::Remote(target));
+        self.page_moved(page_num)
+    }
+
+    /// Move each `(page_num, target_node)` page to its target node,
+    /// `PagerConfig::max_concurrent_migrations` groups at a time (see
+    /// `MigrationCoordinator::batch_migrate`)
+    pub fn batch_migrate(&self, migrations: Vec<(u64, u32)>) -> Result<BatchMigrationResult> {
+        let requested = migrations.len();
+        let result = self.migration.batch_migrate(migrations, self)?;
+        info!(
+            "Batch migration of {} pages: {} moved, {} failed",
+            requested, result.succeeded, result.failed
+        );
+        Ok(result)
+    }
+
+    /// Make `target` the home of the untouched page `page_num`
+    fn place_on_peer(&self, page_num: u64, target: u32) -> Result<()> {
+        let addr = self.base + page_num * PAGE_SIZE as u64;
+        self.transport
+            .read()
+            .send_page(addr, &[0; PAGE_SIZE], target)
+            .with_context(|| format!("Failed to place page {} on node {}", page_num, target))?;
+        self.directory
+            .set_owner(page_num, PageOwner::Remote(target));
+        self.stats.write().remote_placements += 1;
+        debug!("Placed page {} on node {}", page_num, target);
+        Ok(())
+    }
+
+    /// Choose the node a page first touched here lives on: the
+    /// coordinator's choice if placement hints are on and it has one, else
+    /// the placement policy's
+    fn place(&self, page_num: u64) -> u32 {
+        let nodes = self.nodes();
+        if self.placement_hints {
+            match self.query_placement_hint(page_num) {
+                Ok(PlacementHint {
+                    preferred_node: Some(node),
+                    ..
+                }) if nodes.contains(&node) => {
+                    self.placement.record_access(page_num, self.node_id);
+                    self.stats.write().coordinator_hint_hits += 1;
+                    return node;
+                }
+                Ok(hint) => debug!("No usable placement hint for page {}: {:?}", page_num, hint),
+                Err(e) => debug!("No placement hint for page {}: {:#}", page_num, e),
+            }
+        }
+        self.placement.place(page_num, self.node_id, &nodes)
+    }
+
+    /// Where the coordinator would place `page_num`, reusing its answer for
+    /// `PLACEMENT_HINT_TTL`
+    pub fn query_placement_hint(&self, page_num: u64) -> Result<PlacementHint> {
+        if let Some((hint, fetched)) = self.hint_cache.lock().get(&page_num) {
+            if fetched.elapsed() < PLACEMENT_HINT_TTL {
+                return Ok(hint.clone());
+            }
+        }
+        let hint = self
+            .runtime
+            .block_on(self.coordinator.placement_hint(page_num))?;
+        let mut cache = self.hint_cache.lock();
+        cache.retain(|_, (_, fetched)| fetched.elapsed() < PLACEMENT_HINT_TTL);
+        cache.insert(page_num, (hint.clone(), Instant::now()));
+        Ok(hint)
+    }
+
+    /// This node and its peers, in node order
+    fn nodes(&self) -> Vec<u32> {
+        let mut nodes: Vec<u32> = self
+            .transport
+            .read()
+            .peers()
+            .into_iter()
+            .map(|(node, _)| node)
+            .collect();
+        nodes.push(self.node_id);
+        nodes.sort_unstable();
+        nodes
+    }
+
+    /// Unmap a page so the next access faults again
+    fn discard_local_copy(&self, page_num: u64) -> Result<()> {
+        discard_page(self.base, page_num)
+    }
+
+    /// Check a speculative claim with the coordinator on a worker thread
+    ///
+    /// A confirmed claim becomes `Local`; a lost one is aborted by the fault
+    /// loop. If the coordinator cannot be reached the page stays speculative.
+    fn confirm_speculation(&self, page_num: u64) {
+        let coordinator = Arc::clone(&self.coordinator);
+        let runtime = self.runtime.clone();
+        let directory = Arc::clone(&self.directory);
+        let control_tx = Arc::clone(&self.control_tx);
+        let node_id = self.node_id;
+
+        self.workers.lock().execute(move || {
+            match runtime.block_on(speculation::confirm_claim(&coordinator, page_num)) {
+                Ok(owner) if owner == node_id => {
+                    if directory.get_owner(page_num) == PageOwner::Speculative(node_id) {
+                        directory.set_owner(page_num, PageOwner::Local);
+                    }
+                }
+                Ok(owner) => {
+                    debug!("Page {} was claimed first by node {}", page_num, owner);
+                    let _ = control_tx.send(ControlMessage::AbortSpeculative { page_num, owner });
+                }
+                Err(e) => warn!(
+                    "Cannot confirm speculative claim of page {}: {:#}",
+                    page_num, e
+                ),
+            }
+        });
+    }
+
+    /// Give up a speculative claim that lost to `owner`'s
+    ///
+    /// Drops the local copy, including anything written to it since the
+    /// claim, and installs the owner's copy instead.
+    pub fn abort_speculative(&self, page_num: u64, owner: u32) -> Result<()> {
+        if self.directory.get_owner(page_num) != PageOwner::Speculative(self.node_id) {
+            // Already resolved, e.g. migrated meanwhile
+            return Ok(());
+        }
+        info!(
+            "Aborting speculative claim of page {} in favour of node {}",
+            page_num, owner
+        );
+
+        self.directory.set_owner(page_num, PageOwner::Remote(owner));
+        self.cache.invalidate(page_num * PAGE_SIZE as u64);
+        self.discard_local_copy(page_num)?;
+        self.stats.write().speculation_aborts += 1;
+
+        let (_, epoch) = self.directory.read_with_epoch(page_num);
+        let addr = self.base + page_num * PAGE_SIZE as u64;
+        self.fetch_remote_page(addr, owner, epoch)
+    }
+
+    /// Feed a fault into a fault thread's access pattern detector and
+    /// retune its prefetch depth, returning the new depth
+    fn track_access_pattern(&self, detector: &mut AccessPatternDetector, page_num: u64) -> usize {
+        let changed = detector.record(page_num);
+        let depth = detector.prefetch_depth(self.config.read().prefetch_depth);
+
+        let mut stats = self.stats.write();
+        if changed {
+            stats.pattern_changes += 1;
+            debug!(
+                "Access pattern now {:?}, prefetch depth {}",
+                detector.pattern(),
+                depth
+            );
+        }
+        stats.prefetch_depth = depth;
+        depth
+    }
+
+    /// Fetch the remote pages among the `depth` after a remote fault on
+    /// `page_num` into the page cache
+    fn prefetch_remote(&self, page_num: u64, depth: usize) -> Result<()> {
+        let pages = (self.len / PAGE_SIZE) as u64;
+        let mut remote = Vec::new();
+        for next in (page_num + 1..=page_num + depth as u64).take_while(|&next| next < pages) {
+            if let PageOwner::Remote(owner) = self.directory.get_owner(next) {
+                if !self.is_present(next)? {
+                    remote.push((next, owner));
+                }
+            }
+        }
+        if !remote.is_empty() {
+            prefault::fetch_into_cache(&self.transport, &self.cache, self.base, &remote)?;
+        }
+        Ok(())
+    }
+
+    /// Queue the untouched pages after a local first touch of `page_num` for
+    /// the prefetcher
+    ///
+    /// Only where they would be claimed locally, 4 KiB at a time, anyway.
+    fn prefetch_following(&self, page_num: u64) {
+        let Some(prefetcher) = &self.prefetcher else {
+            return;
+        };
+        if self.placement.policy() == PlacementPolicy::FirstTouch
+            && !self.placement_hints
+            && !self.speculative_claims
+            && self.overcommit == OvercommitPolicy::Strict
+            && self.page_size == PageSizeConfig::Small4K
+        {
+            prefetcher.first_touched(page_num);
+        }
+    }
+
+    /// Resolve fault with zero-filled page (local allocation)
+    fn resolve_with_zeros(&self, addr: u64) -> Result<()> {
+        let zero_page = self.allocator.alloc_zeroed();
+
+        let copied = unsafe {
+            self.uffd.copy(
+                zero_page.as_ptr() as *const libc::c_void,
+                addr as *mut libc::c_void,
+                PAGE_SIZE,
+                true,
+            )
+        };
+        match copied {
+            Ok(_) => debug!("Resolved with zeros: addr=0x{:x}", addr),
+            // The prefetcher zero-filled it first; the fault still waits
+            Err(userfaultfd::Error::CopyFailed(errno)) if errno as i32 == libc::EEXIST => {
+                self.uffd
+                    .wake(addr as *mut libc::c_void, PAGE_SIZE)
+                    .context("Failed to wake faulting thread")?;
+            }
+            Err(e) => return Err(e).context("Failed to copy zero page"),
+        }
+        Ok(())
+    }
+
+    /// Resolve the fault at `addr` by zero-filling the whole `size_class`
+    /// page holding it
+    ///
+    /// Falls back to the faulting 4 KiB page when the huge page is not all
+    /// local, does not fit the region, or is partly present already.
+    fn resolve_with_zeros_sized(&self, addr: u64, size_class: PageSizeClass) -> Result<()> {
+        if size_class == PageSizeClass::Small4K {
+            return self.resolve_with_zeros(addr);
+        }
+
+        let gpa = addr - self.base;
+        let start = size_class.align_page(gpa / PAGE_SIZE as u64) * PAGE_SIZE as u64;
+        if start + size_class.bytes() as u64 <= self.len as u64
+            && self.directory.get_owner_huge(gpa, size_class) == PageOwner::Local
+        {
+            // SAFETY: the range lies within the registered region
+            let zeroed = unsafe {
+                self.uffd.zeropage(
+                    (self.base + start) as *mut libc::c_void,
+                    size_class.bytes(),
+                    true,
+                )
+            };
+            match zeroed {
+                Ok(_) => {
+                    debug!("Resolved {:?} page at 0x{:x} with zeros", size_class, start);
+                    return Ok(());
+                }
+                Err(e) => debug!(
+                    "Zero-filling {:?} page at 0x{:x} failed, resolving 4 KiB: {}",
+                    size_class, start, e
+                ),
+            }
+        }
+        let zero_page = self.allocator.alloc_zeroed();
+        Self::install_page(&self.uffd, addr, zero_page.as_ptr())
+    }
+
+    /// Fetch page from remote node via transport layer
+    ///
+    /// `epoch` is the directory epoch `remote_node` was looked up at.
+    fn fetch_remote_page(&self, addr: u64, remote_node: u32, epoch: u64) -> Result<()> {
+        debug!(
+            "Fetching remote page: addr=0x{:x}, from node {}",
+            addr, remote_node
+        );
+
+        let gpa = addr - self.base;
+        let cached = self
+            .cache
+            .with_page(gpa, |cached| Self::install_page(&self.uffd, addr, cached));
+        if let Some(installed) = cached {
+            installed?;
+            self.stats.write().cache_hits += 1;
+            return Ok(());
+        }
+
+        // Use TransportManager to fetch page (works with TCP or RDMA)
+        let transport = Arc::clone(&self.transport);
+        let dedup = self.dedup.clone();
+        let permit = self.fetch_permit(remote_node)?;
+        // The slot is held until the fetch ends, even if it is abandoned
+        let page = self.cancellable_fetch(move || {
+            let _permit = permit;
+            match dedup {
+                Some(dedup) => dedup.fetch_page(addr, remote_node, epoch),
+                None => transport
+                    .read()
+                    .fetch_page_at_epoch(addr, remote_node, epoch)
+                    .map(|data| FetchedPage {
+                        data,
+                        dedup_hit: false,
+                    })
+                    .context("Failed to fetch page via transport"),
+            }
+        })?;
+        if page.dedup_hit {
+            self.stats.write().dedup_hits += 1;
+        }
+
+        self.cache.insert(gpa, &page.data)?;
+        Self::install_page(&self.uffd, addr, page.data.as_ptr())
+    }
+
+    /// Wait for one of `node`'s fetch slots
+    fn fetch_permit(&self, node: u32) -> Result<OwnedSemaphorePermit> {
+        let semaphore = Arc::clone(
+            &self
+                .fetch_limits
+                .entry(node)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrent_fetches_per_node))),
+        );
+        match Arc::clone(&semaphore).try_acquire_owned() {
+            Ok(permit) => Ok(permit),
+            Err(TryAcquireError::NoPermits) => {
+                self.stats.write().fetch_semaphore_waits += 1;
+                self.runtime
+                    .block_on(semaphore.acquire_owned())
+                    .with_context(|| format!("Fetch slots of node {} closed", node))
+            }
+            Err(TryAcquireError::Closed) => Err(anyhow!("Fetch slots of node {} closed", node)),
+        }
+    }
+
+    /// Run `fetch` until it finishes, runs out of time
+    /// (`ReloadableConfig::fetch_timeout`) or the migration coordinator
+    /// cancels it
+    ///
+    /// A timed out or cancelled fetch is abandoned, not interrupted: it runs
+    /// on in the background and its result is dropped.
+    fn cancellable_fetch<T: Send + 'static>(
+        &self,
+        fetch: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let token = self.migration.token();
+        // The fetch runs on another thread, but belongs to the fault's span
+        let trace = opentelemetry::Context::current();
+        let fetch = move || {
+            let _trace = trace.attach();
+            fetch()
+        };
+        let timeout = self.config.read().fetch_timeout;
+        let fetched = self.runtime.block_on(async {
+            tokio::time::timeout(timeout, async {
+                tokio::select! {
+                    result = tokio::task::spawn_blocking(fetch) => Some(result),
+                    _ = token.cancelled() => None,
+                }
+            })
+            .await
+        });
+        match fetched {
+            Ok(Some(result)) => result.context("Page fetch panicked")?,
+            Ok(None) => {
+                self.stats.write().cancelled_fetches += 1;
+                Err(FetchCancelled.into())
+            }
+            Err(_) => {
+                self.stats.write().fetch_timeouts += 1;
+                Err(anyhow!("Page fetch timed out after {:?}", timeout))
+            }
+        }
+    }
+
+    /// Resolve the fault at `addr` with the page at `data`
+    ///
+    /// A page that is already present (`EEXIST`, e.g. installed by another
+    /// thread faulting on it) counts as resolved.
+    fn install_page(uffd: &Uffd, addr: u64, data: *const u8) -> Result<()> {
+        Self::copy_page(uffd, addr, data).map(|_| ())
+    }
+
+    /// `install_page`, returning false if the page was already present
+    fn copy_page(uffd: &Uffd, addr: u64, data: *const u8) -> Result<bool> {
+        // SAFETY: `data` points at a full page that stays valid for the call
+        let copied = unsafe {
+            uffd.copy(
+                data as *const libc::c_void,
+                addr as *mut libc::c_void,
+                PAGE_SIZE,
+                true,
+            )
+        };
+        match copied {
+            Ok(_) => Ok(true),
+            Err(userfaultfd::Error::CopyFailed(errno)) if errno as i32 == libc::EEXIST => Ok(false),
+            Err(e) => Err(e).context("Failed to copy remote page"),
+        }
+    }
+
+    /// Size of the page the guest maps guest address `gpa` with
+    ///
+    /// Asks the `GuestPageWalker` if there is one; otherwise only 2 MiB
+    /// ranges promoted by `try_promote_huge` are known to be huge.
+    pub fn page_size_class(&self, gpa: u64) -> PageSizeClass {
+        if let Some(size_class) = self
+            .page_walker
+            .as_ref()
+            .and_then(|walker| walker.page_size_class(gpa))
+        {
+            return size_class;
+        }
+        if self.directory.is_huge(gpa / PAGE_SIZE as u64) {
+            PageSizeClass::Huge2M
+        } else {
+            PageSizeClass::Small4K
+        }
+    }
+
+    /// Get the page cache in front of the transport
+    pub fn page_cache(&self) -> &PageCache {
+        &self.cache
+    }
+
+    /// Get statistics for observability
+    pub fn get_stats(&self) -> PagerStats {
+        stats_snapshot(&self.stats, &self.directory, &self.allocator)
+    }
+
+    /// Get cluster size this pager was configured with
+    pub fn total_nodes(&self) -> u32 {
+        self.total_nodes
+    }
+
+    /// Get the coordinator client used for registration and discovery
+    pub fn coordinator(&self) -> &CoordinatorClient {
+        &self.coordinator
+    }
+
+    /// Get page directory for testing
+    pub fn directory(&self) -> &Arc<PageDirectory> {
+        &self.directory
+    }
+
+    /// Ranges of guest addresses whose accesses are logged (see
+    /// `access_log`)
+    pub fn access_logger(&self) -> Arc<AccessLogger> {
+        Arc::clone(&self.access_logger)
+    }
+
+    /// Get the coordinator that cancels this pager's in-flight fetches
+    pub fn migration_coordinator(&self) -> &MigrationCoordinator {
+        &self.migration
+    }
+
+    /// Forget the pages of `node_id`, which has left the cluster, at the
+    /// next directory compaction
+    pub fn mark_node_dead(&self, node_id: u32) {
+        self.dead_nodes.write().insert(node_id);
+    }
+
+    /// Get transport manager for testing
+    pub fn transport(&self) -> Arc<RwLock<TransportManager>> {
+        Arc::clone(&self.transport)
+    }
+
+    /// Address the management API listens on, if it was started
+    pub fn management_addr(&self) -> Option<SocketAddr> {
+        self.management_addr
+    }
+
+    /// Address Prometheus scrapes `GET /metrics` on, if
+    /// `PagerConfig::metrics_port` is set
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// Get the shutdown signal observed by the fault loop
+    pub fn shutdown_signal(&self) -> Arc<ShutdownSignal> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// Run the fault loop in a background thread
+    ///
+    /// Grab any shared handles (`transport()`, `directory()`, ...) before
+    /// calling this, since the pager moves into the thread.
+    pub fn spawn(self) -> Result<JoinHandle<Result<()>>> {
+        thread::Builder::new()
+            .name(format!("pager-node{}", self.node_id))
+            .spawn(move || self.handle_faults())
+            .context("Failed to spawn pager thread")
+    }
+
+    /// Get the live (reloadable) settings
+    pub fn config(&self) -> Arc<RwLock<ReloadableConfig>> {
+        Arc::clone(&self.config)
+    }
+}
+
+impl LocalPages for Pager {
+    fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn page_count(&self) -> u64 {
+        (self.len / PAGE_SIZE) as u64
+    }
+
+    fn read_page(&self, page_num: u64) -> Result<Vec<u8>> {
+        match &self.compressor {
+            Some(compressor) => compressor.read_local(self.base, page_num),
+            // SAFETY: local pages are present in the registered region
+            None => Ok(unsafe {
+                std::slice::from_raw_parts(
+                    (self.base + page_num * PAGE_SIZE as u64) as *const u8,
+                    PAGE_SIZE,
+                )
+            }
+            .to_vec()),
+        }
+    }
+
+    fn page_moved(&self, page_num: u64) -> Result<()> {
+        self.cache.invalidate(page_num * PAGE_SIZE as u64);
+        if let Some(compressor) = &self.compressor {
+            // Compressed after it was read
+            compressor.forget(page_num);
+        }
+        // The next access faults and fetches it back
+        self.discard_local_copy(page_num)
+    }
+}
+
+/// Faults the pager registers its region for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PagerMode {
+    /// Faults on pages not yet present, which the pager fills
+    Missing,
+    /// Writes to write-protected pages only, for a region populated by
+    /// other means (see `Pager::write_protect_page`)
+    WriteProtect,
+    /// Both, falling back to `Missing` where the kernel cannot
+    /// write-protect the region; without write protection cold pages are
+    /// not compressed
+    #[default]
+    MissingAndWriteProtect,
+}
+
+/// Memory region and cluster membership of a pager
+#[derive(Debug, Clone)]
+pub struct PagerConfig {
+    /// Base address of guest memory region
+    pub base: *mut u8,
+    /// Length of memory region
+    pub len: usize,
+    /// Local node identifier
+    pub node_id: u32,
+    /// Total nodes in cluster
+    pub total_nodes: u32,
+    /// Coordinator URL (e.g., "http://localhost:8000")
+    pub coordinator: CoordinatorConfig,
+    /// Where the node's key pair is kept (see `identity`); a fresh key pair
+    /// is generated on every start if unset
+    pub identity_key_path: Option<PathBuf>,
+    /// Push metrics to this Prometheus Pushgateway (see `metrics`)
+    pub push_gateway: Option<PushGatewayConfig>,
+    /// Cap on local pages (see `policy`)
+    pub overcommit: OvercommitPolicy,
+    /// Picks pages to evict under `OvercommitPolicy::Lazy`
+    pub replacement_policy: Arc<dyn PageReplacementPolicy>,
+    /// Where first-touched pages go (see `affinity`)
+    pub placement: PlacementPolicy,
+    /// Ask the coordinator before placing by `placement`
+    pub placement_hints: bool,
+    /// Signal memory pressure at these local page counts (see `pressure`)
+    pub watermarks: Option<MemoryWatermarks>,
+    /// Migrate pages to less loaded nodes (see `balancing`)
+    pub auto_balance: bool,
+    /// Restore the page directory from this file on start and checkpoint it
+    /// there (see `checkpoint`)
+    pub checkpoint_path: Option<PathBuf>,
+    /// Checkpoint this often rather than only on shutdown
+    pub checkpoint_interval: Option<Duration>,
+    /// Compact the page directory this often (see `compaction`)
+    pub compaction_interval: Option<Duration>,
+    /// Fetches outstanding with any one peer at a time; faults beyond it
+    /// wait for a slot
+    pub max_concurrent_fetches_per_node: usize,
+    /// Page groups a batch migration moves in parallel (see
+    /// `MigrationCoordinator::batch_migrate`)
+    pub max_concurrent_migrations: usize,
+    /// Register the host's hardware fingerprint, so the coordinator turns
+    /// the node away if another node ID runs on the same host
+    pub require_unique_fingerprint: bool,
+    /// Faults to register the region for
+    pub mode: PagerMode,
+    /// Size of the pages first touches are resolved with
+    pub page_size: PageSizeConfig,
+    /// Reconnect to peers whose connection broke, at the endpoint the
+    /// coordinator lists (see `rdma_transport::reconnect`)
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Untouched pages after each local first touch to zero-fill ahead of
+    /// the guest (see `prefetch`); 0 turns prefetching off
+    pub prefetch_pages: usize,
+    /// Page buffers pre-allocated for resolving faults (see `allocator`);
+    /// faults beyond them allocate from the heap
+    pub pool_capacity: usize,
+    /// Threads resolving faults; each waits on the userfaultfd, which hands
+    /// every fault to one of them. They share the page cache, whose slots
+    /// are only read under its lock (see `cache::PageCache::with_page`)
+    pub fault_threads: usize,
+    /// How long a coordinator request waits for a replica
+    pub coordinator_timeout_secs: u64,
+    /// Serve `GET /metrics` for Prometheus on this port of every interface
+    /// (see `metrics::serve_metrics`)
+    pub metrics_port: Option<u16>,
+}
+
+// SAFETY: `base` is only an address here; the pager accesses the region
+// through userfaultfd, never by dereferencing the pointer
+unsafe impl Send for PagerConfig {}
+
+/// No region yet; a single node with the local coordinator and every
+/// option at the `PagerBuilder` default
+impl Default for PagerConfig {
+    fn default() -> Self {
+        Self {
+            base: std::ptr::null_mut(),
+            len: 0,
+            node_id: 0,
+            total_nodes: 1,
+            coordinator: CoordinatorConfig::single("http://127.0.0.1:8000"),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: Some(DEFAULT_COMPACTION_INTERVAL),
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: COORDINATOR_TIMEOUT.as_secs(),
+            metrics_port: None,
+        }
+    }
+}
+
+impl PagerConfig {
+    /// Check the region and cluster membership make sense before anything
+    /// is registered or contacted
+    pub fn validate(&self) -> Result<()> {
+        if self.len == 0 || !self.len.is_multiple_of(PAGE_SIZE) {
+            return Err(anyhow!(
+                "Region length 0x{:x} must be a nonzero multiple of the {} byte page size",
+                self.len,
+                PAGE_SIZE
+            ));
+        }
+        if self.node_id >= self.total_nodes {
+            return Err(anyhow!(
+                "Node {} is outside a cluster of {} nodes",
+                self.node_id,
+                self.total_nodes
+            ));
+        }
+        if self.fault_threads == 0 {
+            return Err(anyhow!("At least one fault thread is needed"));
+        }
+        if self.coordinator_timeout_secs == 0 {
+            return Err(anyhow!("Coordinator timeout must be at least a second"));
+        }
+        for url in &self.coordinator.urls {
+            if url.starts_with(coordinator::SRV_SCHEME) {
+                continue;
+            }
+            let parsed = reqwest::Url::parse(url)
+                .with_context(|| format!("Invalid coordinator URL {:?}", url))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(anyhow!("Coordinator URL {:?} is not HTTP(S)", url));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builder for a validated `PagerConfig`
+///
+/// Fields without a setter here keep their `PagerConfig::default()`; hand
+/// the result to `PagerBuilder::from_config` to set them and the optional
+/// services.
+pub struct PagerConfigBuilder {
+    config: PagerConfig,
+}
+
+impl PagerConfigBuilder {
+    /// Start a config for the memory region at `base..base+len`
+    pub fn new(base: *mut u8, len: usize) -> Self {
+        Self {
+            config: PagerConfig {
+                base,
+                len,
+                ..PagerConfig::default()
+            },
+        }
+    }
+
+    pub fn node_id(mut self, node_id: u32) -> Self {
+        self.config.node_id = node_id;
+        self
+    }
+
+    pub fn total_nodes(mut self, total_nodes: u32) -> Self {
+        self.config.total_nodes = total_nodes;
+        self
+    }
+
+    pub fn coordinator_url(mut self, url: &str) -> Self {
+        self.config.coordinator = CoordinatorConfig::single(url);
+        self
+    }
+
+    pub fn fault_threads(mut self, threads: usize) -> Self {
+        self.config.fault_threads = threads;
+        self
+    }
+
+    pub fn prefetch_pages(mut self, pages: usize) -> Self {
+        self.config.prefetch_pages = pages;
+        self
+    }
+
+    pub fn coordinator_timeout_secs(mut self, secs: u64) -> Self {
+        self.config.coordinator_timeout_secs = secs;
+        self
+    }
+
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.config.metrics_port = Some(port);
+        self
+    }
+
+    /// The config, if `PagerConfig::validate` accepts it
+    pub fn build(self) -> Result<PagerConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Builder for `Pager` with optional services
+pub struct PagerBuilder {
+    config: PagerConfig,
+    management_addr: Option<SocketAddr>,
+    config_file: Option<PathBuf>,
+    deduplication: bool,
+    cold_page_compression: bool,
+    guard_pages: (bool, bool),
+    realtime_priority: Option<u8>,
+    page_cache_size: Option<usize>,
+    page_walker: Option<Arc<dyn GuestPageWalker>>,
+    speculative_claims: bool,
+    max_fault_retries: Option<u32>,
+    pressure_callback: Option<Box<dyn PressureCallback>>,
+    gossip: Option<GossipConfig>,
+    latency_sample_rate: Option<u32>,
+    runtime: Option<tokio::runtime::Handle>,
+}
+
+impl PagerBuilder {
+    /// Start building a pager for the memory region at `base..base+len`
+    pub fn new(base: *mut u8, len: usize) -> Self {
+        Self::from_config(PagerConfig {
+            base,
+            len,
+            ..PagerConfig::default()
+        })
+    }
+
+    /// Start building a pager from `config`, e.g. from `PagerConfigBuilder`
+    pub fn from_config(config: PagerConfig) -> Self {
+        Self {
+            config,
+            management_addr: None,
+            config_file: None,
+            deduplication: false,
+            cold_page_compression: false,
+            guard_pages: (false, false),
+            realtime_priority: None,
+            page_cache_size: None,
+            page_walker: None,
+            speculative_claims: false,
+            max_fault_retries: None,
+            pressure_callback: None,
+            gossip: None,
+            latency_sample_rate: None,
+            runtime: None,
+        }
+    }
+
+    pub fn node_id(mut self, node_id: u32) -> Self {
+        self.config.node_id = node_id;
+        self
+    }
+
+    pub fn total_nodes(mut self, total_nodes: u32) -> Self {
+        self.config.total_nodes = total_nodes;
+        self
+    }
+
+    /// A single coordinator, or with an `srv://` URL the replicas of a DNS SRV
+    /// record (see `coordinator::CoordinatorDiscovery`)
+    pub fn coordinator_url(mut self, url: &str) -> Self {
+        self.config.coordinator = CoordinatorConfig::single(url);
+        self
+    }
+
+    /// Spread coordinator requests over several replicas (see `coordinator`)
+    pub fn coordinator(mut self, config: CoordinatorConfig) -> Self {
+        self.config.coordinator = config;
+        self
+    }
+
+    /// Load the node key pair from this file, creating it on first start
+    pub fn identity_key_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.identity_key_path = Some(path.into());
+        self
+    }
+
+    /// Push metrics to a Prometheus Pushgateway (see `metrics`)
+    pub fn push_gateway(mut self, config: PushGatewayConfig) -> Self {
+        self.config.push_gateway = Some(config);
+        self
+    }
+
+    /// Limit local pages, evicting to peers beyond the limit (see `policy`)
+    pub fn overcommit(mut self, policy: OvercommitPolicy) -> Self {
+        self.config.overcommit = policy;
+        self
+    }
+
+    /// Signal memory pressure at these local page counts (see `pressure`)
+    pub fn watermarks(mut self, watermarks: MemoryWatermarks) -> Self {
+        self.config.watermarks = Some(watermarks);
+        self
+    }
+
+    /// Migrate pages to less loaded nodes (see `balancing`)
+    pub fn auto_balance(mut self, enabled: bool) -> Self {
+        self.config.auto_balance = enabled;
+        self
+    }
+
+    /// Keep the page directory in this file across restarts (see `checkpoint`)
+    pub fn checkpoint_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Checkpoint the page directory this often
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.config.checkpoint_interval = Some(interval);
+        self
+    }
+
+    /// Compact the page directory this often, or never with `None`
+    pub fn compaction_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.compaction_interval = interval;
+        self
+    }
+
+    /// Tell `callback` about memory pressure instead of logging it
+    pub fn pressure_callback(mut self, callback: Box<dyn PressureCallback>) -> Self {
+        self.pressure_callback = Some(callback);
+        self
+    }
+
+    /// Multicast this node's page claims to, and learn its peers' from,
+    /// the group in `config` (see `gossip`)
+    pub fn gossip(mut self, config: GossipConfig) -> Self {
+        self.gossip = Some(config);
+        self
+    }
+
+    /// Choose evictions with this policy (default `LruPolicy`)
+    pub fn replacement_policy(mut self, policy: impl PageReplacementPolicy + 'static) -> Self {
+        self.config.replacement_policy = Arc::new(policy);
+        self
+    }
+
+    /// Place first-touched pages with this policy (see `affinity`)
+    pub fn placement(mut self, policy: PlacementPolicy) -> Self {
+        self.config.placement = policy;
+        self
+    }
+
+    /// Place first-touched pages where the coordinator suggests, falling
+    /// back to `placement`
+    pub fn placement_hints(mut self, enabled: bool) -> Self {
+        self.config.placement_hints = enabled;
+        self
+    }
+
+    /// Have at most `limit` fetches outstanding with any one peer
+    ///
+    /// Defaults to 32.
+    pub fn max_concurrent_fetches_per_node(mut self, limit: usize) -> Self {
+        self.config.max_concurrent_fetches_per_node = limit;
+        self
+    }
+
+    /// Have batch migrations move at most `limit` page groups at once
+    ///
+    /// Defaults to 8.
+    pub fn max_concurrent_migrations(mut self, limit: usize) -> Self {
+        self.config.max_concurrent_migrations = limit;
+        self
+    }
+
+    /// Whether to have the coordinator refuse this node if another node ID
+    /// registered from the same host (see `Pager::compute_hardware_fingerprint`)
+    ///
+    /// On by default; turn it off to run several nodes on one host.
+    pub fn require_unique_fingerprint(mut self, required: bool) -> Self {
+        self.config.require_unique_fingerprint = required;
+        self
+    }
+
+    /// Faults to register the region for (see `PagerMode`)
+    pub fn mode(mut self, mode: PagerMode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    /// Resolve first touches a whole page of this size at a time (see
+    /// `PageSizeConfig`)
+    pub fn page_size(mut self, page_size: PageSizeConfig) -> Self {
+        self.config.page_size = page_size;
+        self
+    }
+
+    /// Reconnect to peers whose connection broke (see
+    /// `rdma_transport::reconnect`)
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// Zero-fill this many untouched pages after each local first touch
+    /// (see `prefetch`)
+    pub fn prefetch_pages(mut self, pages: usize) -> Self {
+        self.config.prefetch_pages = pages;
+        self
+    }
+
+    /// Pre-allocate this many page buffers for resolving faults (see
+    /// `allocator`)
+    pub fn pool_capacity(mut self, pages: usize) -> Self {
+        self.config.pool_capacity = pages;
+        self
+    }
+
+    /// Resolve faults on this many threads
+    ///
+    /// Defaults to 1.
+    pub fn fault_threads(mut self, threads: usize) -> Self {
+        self.config.fault_threads = threads;
+        self
+    }
+
+    /// Give up on a coordinator replica after this many seconds
+    ///
+    /// Defaults to 5.
+    pub fn coordinator_timeout_secs(mut self, secs: u64) -> Self {
+        self.config.coordinator_timeout_secs = secs;
+        self
+    }
+
+    /// Serve `GET /metrics` for Prometheus on this port (see
+    /// `metrics::serve_metrics`)
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.config.metrics_port = Some(port);
+        self
+    }
+
+    /// Resolve first touches before the coordinator confirms the claim (see
+    /// `speculation`)
+    pub fn speculative_claims(mut self, enabled: bool) -> Self {
+        self.speculative_claims = enabled;
+        self
+    }
+
+    /// Retry a failing remote fault this many times before giving up on it
+    /// (see `dlq`)
+    ///
+    /// Defaults to 5.
+    pub fn max_fault_retries(mut self, retries: u32) -> Self {
+        self.max_fault_retries = Some(retries);
+        self
+    }
+
+    /// Record the service time of 1 in `rate` faults (see `stats`)
+    pub fn latency_sample_rate(mut self, rate: u32) -> Self {
+        self.latency_sample_rate = Some(rate);
+        self
+    }
+
+    /// Serve the HTTP management API (see `api`) on this port of the
+    /// loopback interface
+    pub fn management_port(mut self, port: u16) -> Self {
+        self.management_addr = Some((Ipv4Addr::LOCALHOST, port).into());
+        self
+    }
+
+    /// Serve the HTTP management API on `addr`, e.g. to reach it from other
+    /// hosts
+    pub fn management_addr(mut self, addr: SocketAddr) -> Self {
+        self.management_addr = Some(addr);
+        self
+    }
+
+    /// Read live settings from this TOML file and reload it on SIGHUP
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Skip transfers of page contents this node already holds (see `dedup`)
+    ///
+    /// Adds a coordinator lookup to every remote fault.
+    pub fn deduplication(mut self, enabled: bool) -> Self {
+        self.deduplication = enabled;
+        self
+    }
+
+    /// Compress local pages left alone for 5 minutes (see `compressor`)
+    ///
+    /// Needs userfaultfd write-protect support (Linux 5.7).
+    pub fn cold_page_compression(mut self, enabled: bool) -> Self {
+        self.cold_page_compression = enabled;
+        self
+    }
+
+    /// Put a `PROT_NONE` guard page before and/or after the region
+    ///
+    /// Out-of-bounds accesses then fault immediately and are reported as
+    /// `Guard page violation` (see `guard`). The neighbouring pages must be
+    /// unmapped.
+    pub fn with_guard_pages(mut self, before: bool, after: bool) -> Self {
+        self.guard_pages = (before, after);
+        self
+    }
+
+    /// Run the fault handling thread under `SCHED_FIFO` at this priority (1-99)
+    ///
+    /// Applied when the thread starts; see `Pager::set_realtime_priority`.
+    pub fn realtime_priority(mut self, priority: u8) -> Self {
+        self.realtime_priority = Some(priority);
+        self
+    }
+
+    /// Keep up to `bytes` of fetched remote pages for re-faults (see `cache`)
+    ///
+    /// Defaults to 256 MiB.
+    pub fn page_cache_size(mut self, bytes: usize) -> Self {
+        self.page_cache_size = Some(bytes);
+        self
+    }
+
+    /// Read the guest's page sizes with `walker` (see `page_size`)
+    pub fn page_walker(mut self, walker: impl GuestPageWalker + 'static) -> Self {
+        self.page_walker = Some(Arc::new(walker));
+        self
+    }
+
+    /// Reach the coordinator on `runtime` (see `Pager::new`)
+    ///
+    /// Required by `build`; `build_async` uses the runtime it runs on.
+    pub fn runtime(mut self, runtime: tokio::runtime::Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Create the pager (registers memory and connects to peers)
+    pub fn build(self) -> Result<Pager> {
+        self.config.validate()?;
+        let runtime = self.runtime.clone().ok_or_else(|| {
+            anyhow!("No runtime to reach the coordinator on: call PagerBuilder::runtime or use build_async")
+        })?;
+        let pager = Pager::new(self.config.clone(), runtime)?;
+        self.finish(pager)
+    }
+
+    /// Like `build`, but without blocking the async runtime (see `Pager::new_async`)
+    pub async fn build_async(self) -> Result<Pager> {
+        let pager = Pager::new_async(self.config.clone()).await?;
+        self.finish(pager)
+    }
+
+    /// Enable the optional services on a freshly created pager
+    fn finish(self, mut pager: Pager) -> Result<Pager> {
+        if let Some(priority) = self.realtime_priority {
+            if !(1..=99).contains(&priority) {
+                return Err(anyhow!("Real-time priority {} out of range 1-99", priority));
+            }
+            pager.realtime_priority = Some(priority);
+        }
+
+        if let Some(size) = self.page_cache_size {
+            pager.cache = Arc::new(PageCache::new(size)?);
+        }
+        pager.page_walker = self.page_walker;
+        pager.speculative_claims = self.speculative_claims;
+        if let Some(retries) = self.max_fault_retries {
+            pager.max_fault_retries = retries;
+        }
+        if let Some(rate) = self.latency_sample_rate {
+            if rate == 0 {
+                return Err(anyhow!("Latency sample rate must be at least 1"));
+            }
+            pager.stats.write().fault_service_sample = SamplingHistogram::new(rate);
+        }
+        if let Some(callback) = self.pressure_callback {
+            *pager.pressure_callback.write() = callback;
+        }
+        if let Some(config) = self.gossip {
+            pager.start_gossip(config)?;
+        }
+
+        if let Some(path) = self.config_file {
+            pager.enable_config_reload(path)?;
+        }
+
+        if self.deduplication {
+            pager.enable_deduplication()?;
+        }
+
+        if self.cold_page_compression {
+            pager.enable_compression()?;
+        }
+
+        let (before, after) = self.guard_pages;
+        if before || after {
+            pager.enable_guard_pages(before, after)?;
+        }
+
+        if let Some(addr) = self.management_addr {
+            pager.start_management_api(addr)?;
+        }
+
+        Ok(pager)
+    }
+
+    /// Build the pager and run its fault loop in a background thread
+    pub fn start(self) -> Result<JoinHandle<Result<()>>> {
+        self.build()?.spawn()
+    }
+}
+
+/// Whether `error` is the owner refusing a fetch over a stale directory epoch
+fn is_stale_epoch(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TransportError>(),
+        Some(TransportError::StaleEpoch { .. })
+    )
+}
+
+/// Whether `error` is the owner being unreachable
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TransportError>(),
+        Some(TransportError::ConnectionFailed)
+    )
+}
+
+/// Error for the coordinator turning down this node's registration
+fn registration_rejected(node_id: u32, status: reqwest::StatusCode) -> anyhow::Error {
+    if status == reqwest::StatusCode::CONFLICT {
+        anyhow!(
+            "Coordinator rejected node {}: another node ID is registered from this host \
+             (set require_unique_fingerprint to false to run several on one host)",
+            node_id
+        )
+    } else {
+        anyhow!("Coordinator rejected node identity: {}", status)
+    }
+}
+
+/// Unmap page `page_num` of the region at `base`, so the next access to it
+/// faults
+pub(crate) fn discard_page(base: u64, page_num: u64) -> Result<()> {
+    let addr = base + page_num * PAGE_SIZE as u64;
+    let ret = unsafe { libc::madvise(addr as *mut libc::c_void, PAGE_SIZE, libc::MADV_DONTNEED) };
+    if ret != 0 {
+        return Err(anyhow!(
+            "Failed to unmap page {}: {}",
+            page_num,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// `stats` with the counters kept outside it filled in, as every view
+/// outside the fault loop reports them
+pub(crate) fn stats_snapshot(
+    stats: &RwLock<PagerStats>,
+    directory: &PageDirectory,
+    allocator: &PageAllocator,
+) -> PagerStats {
+    let mut stats = stats.read().clone();
+    stats.max_shard_occupancy = directory.max_shard_occupancy();
+    stats.pool_hits = allocator.hits();
+    stats.pool_misses = allocator.misses();
+    stats
+}
+
+/// Whether the page at `addr` is mapped
+pub(crate) fn is_resident(addr: u64) -> Result<bool> {
+    let mut vec = 0u8;
+    // SAFETY: mincore only reads the page tables, and `vec` has room for
+    // the one page asked about
+    let ret = unsafe { libc::mincore(addr as *mut libc::c_void, PAGE_SIZE, &mut vec) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("mincore of 0x{:x} failed", addr));
+    }
+    Ok(vec & 1 != 0)
+}
+
+/// A pager running in its own thread (see `start_pager`)
+pub struct PagerHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<Result<()>>,
+    stats: Arc<RwLock<PagerStats>>,
+    directory: Arc<PageDirectory>,
+    allocator: Arc<PageAllocator>,
+}
+
+impl PagerHandle {
+    /// Wrap a pager's fault loop, started with `spawn`
+    pub fn spawn(pager: Pager) -> Result<Self> {
+        let stop = pager.stop_tx.clone();
+        let stats = Arc::clone(&pager.stats);
+        let directory = Arc::clone(pager.directory());
+        let allocator = Arc::clone(&pager.allocator);
+        let thread = pager.spawn()?;
+        Ok(Self {
+            stop,
+            thread,
+            stats,
+            directory,
+            allocator,
+        })
+    }
+
+    /// Stop the fault loop and return how it ended
+    ///
+    /// The loop finishes the fault it is handling first. Gives up after
+    /// `PAGER_STOP_TIMEOUT`, leaving the thread to finish on its own.
+    pub fn stop(self) -> Result<()> {
+        // Full only if already asked to stop
+        let _ = self.stop.try_send(());
+        let deadline = Instant::now() + PAGER_STOP_TIMEOUT;
+        while !self.thread.is_finished() {
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "Pager did not stop within {:?}",
+                    PAGER_STOP_TIMEOUT
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("Pager thread panicked"))?
+    }
+
+    /// A snapshot of the pager's statistics
+    pub fn stats(&self) -> PagerStats {
+        stats_snapshot(&self.stats, &self.directory, &self.allocator)
+    }
+
+    pub fn directory(&self) -> Arc<PageDirectory> {
+        Arc::clone(&self.directory)
+    }
+
+    /// Whether the fault loop has ended, on its own or through `stop`
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+/// Start pager in background thread
+///
+/// Initialization runs on `runtime` via `PagerBuilder::build_async`, as do
+/// the pager's coordinator requests afterwards; the fault loop gets its own
+/// thread, which the returned handle stops. Must not be called from inside
+/// `runtime`'s async context.
+///
+/// # Arguments
+/// * `runtime` - Tokio runtime the pager reaches the coordinator on; must
+///   outlive the pager
+/// * `base` - Base address of guest memory region
+/// * `len` - Length of memory region
+/// * `node_id` - Local node identifier
+/// * `total_nodes` - Total nodes in cluster
+/// * `coordinator_url` - Coordinator URL (e.g., "http://localhost:8000")
+pub fn start_pager(
+    runtime: &tokio::runtime::Handle,
+    base: *mut u8,
+    len: usize,
+    node_id: u32,
+    total_nodes: u32,
+    coordinator_url: &str,
+) -> Result<PagerHandle> {
+    info!(
+        "Starting pager: base={:p}, len=0x{:x}, node={}/{}",
+        base, len, node_id, total_nodes
+    );
+    info!("Coordinator: {}", coordinator_url);
+
+    let builder = PagerBuilder::new(base, len)
+        .node_id(node_id)
+        .total_nodes(total_nodes)
+        .coordinator_url(coordinator_url);
+    PagerHandle::spawn(runtime.block_on(builder.build_async())?)
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+
+    /// Resolve the fault at `addr` as a fault thread's first fault
+    fn fault(pager: &Pager, addr: u64) -> Result<()> {
+        pager.handle_pagefault(addr, &mut AccessPatternDetector::new())
+    }
+
+    #[test]
+    fn test_page_directory_new() {
+        let dir = PageDirectory::new(0);
+        assert_eq!(dir.local_node, 0);
+        assert_eq!(dir.page_count(), 0);
+    }
+
+    #[test]
+    fn test_page_directory_claim() {
+        let dir = PageDirectory::new(0);
+
+        // Initially unknown
+        assert_eq!(dir.get_owner(0), PageOwner::Unknown);
+
+        // Claim page
+        dir.claim_page(0);
+        assert_eq!(dir.get_owner(0), PageOwner::Local);
+        assert_eq!(dir.page_count(), 1);
+    }
+
+    #[test]
+    fn test_page_directory_set_owner() {
+        let dir = PageDirectory::new(0);
+
+        // Set remote owner
+        dir.set_owner(0, PageOwner::Remote(1));
+        assert_eq!(dir.get_owner(0), PageOwner::Remote(1));
+
+        // Change to local
+        dir.set_owner(0, PageOwner::Local);
+        assert_eq!(dir.get_owner(0), PageOwner::Local);
+    }
+
+    #[test]
+    fn test_page_directory_multiple_pages() {
+        let dir = PageDirectory::new(0);
+
+        dir.claim_page(0);
+        dir.set_owner(1, PageOwner::Remote(1));
+        dir.set_owner(2, PageOwner::Remote(2));
+
+        assert_eq!(dir.get_owner(0), PageOwner::Local);
+        assert_eq!(dir.get_owner(1), PageOwner::Remote(1));
+        assert_eq!(dir.get_owner(2), PageOwner::Remote(2));
+        assert_eq!(dir.get_owner(3), PageOwner::Unknown);
+        assert_eq!(dir.page_count(), 3);
+    }
+
+    #[test]
+    fn test_pager_config_validation() {
+        let config = PagerConfig {
+            len: 16 * PAGE_SIZE,
+            ..PagerConfig::default()
+        };
+        config.validate().unwrap();
+        let srv = PagerConfig {
+            coordinator: CoordinatorConfig::single("srv://_coordinator._tcp.example"),
+            ..config.clone()
+        };
+        srv.validate().unwrap();
+
+        let invalid = [
+            PagerConfig::default(),
+            PagerConfig {
+                len: 16 * PAGE_SIZE + 1,
+                ..config.clone()
+            },
+            PagerConfig {
+                node_id: 1,
+                ..config.clone()
+            },
+            PagerConfig {
+                coordinator: CoordinatorConfig::single("127.0.0.1:8000"),
+                ..config.clone()
+            },
+            PagerConfig {
+                coordinator: CoordinatorConfig::single("not a url"),
+                ..config.clone()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?} accepted", config);
+        }
+
+        // Rejected before the region is registered
+        let error = PagerBuilder::new(std::ptr::null_mut(), 0)
+            .build()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Region length"));
+    }
+
+    #[test]
+    fn test_pager_config_builder() {
+        let base = 0x1000_0000 as *mut u8;
+        let config = PagerConfigBuilder::new(base, 16 * PAGE_SIZE)
+            .node_id(1)
+            .total_nodes(2)
+            .coordinator_url("http://10.0.0.1:8000")
+            .fault_threads(4)
+            .metrics_port(9100)
+            .build()
+            .unwrap();
+        assert_eq!(config.coordinator.urls, ["http://10.0.0.1:8000"]);
+        assert_eq!(config.fault_threads, 4);
+        assert_eq!(config.prefetch_pages, 0);
+        assert_eq!(config.coordinator_timeout_secs, 5);
+        assert_eq!(config.metrics_port, Some(9100));
+
+        let builder = || PagerConfigBuilder::new(base, 16 * PAGE_SIZE);
+        assert!(builder().build().is_ok());
+        assert!(PagerConfigBuilder::new(base, 0).build().is_err());
+        assert!(PagerConfigBuilder::new(base, 100).build().is_err());
+        assert!(builder().node_id(1).build().is_err());
+        assert!(builder().coordinator_url("not a url").build().is_err());
+        assert!(builder().fault_threads(0).build().is_err());
+        assert!(builder().coordinator_timeout_secs(0).build().is_err());
+    }
+
+    #[test]
+    fn test_page_directory_homogeneous_regions_coalesce() {
+        let dir = PageDirectory::new(0);
+        for page in 0..16 * REGION_PAGES {
+            dir.claim_page(page);
+        }
+
+        // One entry per 1 MiB region instead of one per page
+        assert_eq!(dir.regions.len(), 16);
+        assert!(dir.regions.iter().all(|r| r.overrides.is_empty()));
+        assert_eq!(dir.page_count(), 16 * REGION_PAGES as usize);
+        assert_eq!(
+            dir.coalesced_regions(),
+            vec![(0, 16 * REGION_PAGES, PageOwner::Local)]
+        );
+    }
+
+    #[test]
+    fn test_page_directory_overrides() {
+        let dir = PageDirectory::new(0);
+        for page in 0..REGION_PAGES {
+            dir.claim_page(page);
+        }
+        dir.set_owner(10, PageOwner::Remote(2));
+        dir.set_owner(11, PageOwner::Remote(2));
+
+        assert_eq!(dir.get_owner(9), PageOwner::Local);
+        assert_eq!(dir.get_owner(10), PageOwner::Remote(2));
+        assert_eq!(dir.regions.get(&0).unwrap().overrides.len(), 2);
+        assert_eq!(
+            dir.coalesced_regions(),
+            vec![
+                (0, 10, PageOwner::Local),
+                (10, 12, PageOwner::Remote(2)),
+                (12, REGION_PAGES, PageOwner::Local),
+            ]
+        );
+
+        // Migrating most of the region away flips its dominant owner
+        for page in 0..200 {
+            dir.set_owner(page, PageOwner::Remote(2));
+        }
+        let region = dir.regions.get(&0).unwrap();
+        assert_eq!(region.owner, PageOwner::Remote(2));
+        assert_eq!(region.overrides.len(), (REGION_PAGES - 200) as usize);
+        drop(region);
+
+        // Invalidating every page drops the region
+        for page in 0..REGION_PAGES {
+            dir.set_owner(page, PageOwner::Unknown);
+        }
+        assert_eq!(dir.regions.len(), 0);
+        assert_eq!(dir.page_count(), 0);
+    }
+
+    #[test]
+    fn test_page_directory_promotes_local_huge_pages() {
+        let dir = PageDirectory::new(0);
+        for page in 0..HUGE_PAGE_PAGES - 1 {
+            dir.claim_page(page);
+        }
+        assert!(!dir.promote_region(0));
+
+        dir.claim_page(HUGE_PAGE_PAGES - 1);
+        assert!(!dir.promote_region(REGION_PAGES));
+        assert!(dir.promote_region(0));
+        assert!(!dir.promote_region(0));
+        assert!(dir.is_huge(0) && dir.is_huge(HUGE_PAGE_PAGES - 1));
+        assert_eq!(dir.get_owner(300), PageOwner::Local);
+        assert_eq!(dir.local_page_count(), HUGE_PAGE_PAGES as usize);
+        assert_eq!(dir.page_count(), HUGE_PAGE_PAGES as usize);
+        assert_eq!(dir.mapping_count(), 1);
+        assert_eq!(
+            dir.coalesced_regions(),
+            vec![(0, HUGE_PAGE_PAGES, PageOwner::Local)]
+        );
+
+        // Reclaiming keeps it; losing a page splits the whole huge page
+        dir.claim_page(5);
+        assert!(dir.is_huge(5));
+        dir.set_owner(300, PageOwner::Remote(1));
+        assert!(!dir.is_huge(0) && !dir.is_huge(300));
+        assert_eq!(dir.get_owner(5), PageOwner::Local);
+        assert_eq!(dir.get_owner(300), PageOwner::Remote(1));
+        assert_eq!(dir.local_page_count(), HUGE_PAGE_PAGES as usize - 1);
+        assert_eq!(dir.page_count(), HUGE_PAGE_PAGES as usize);
+        assert_eq!(dir.mapping_count(), HUGE_PAGE_PAGES as usize);
+    }
+
+    #[test]
+    fn test_page_directory_owner_of_whole_huge_page() {
+        let dir = PageDirectory::new(0);
+        let huge_bytes = PageSizeClass::Huge2M.bytes() as u64;
+        for page in HUGE_PAGE_PAGES..2 * HUGE_PAGE_PAGES {
+            dir.set_owner(page, PageOwner::Remote(2));
+        }
+        // Any address inside the huge page, and only its keys
+        for gpa in [huge_bytes, huge_bytes + 12345, 2 * huge_bytes - 1] {
+            assert_eq!(
+                dir.get_owner_huge(gpa, PageSizeClass::Huge2M),
+                PageOwner::Remote(2)
+            );
+        }
+        assert_eq!(
+            dir.get_owner_huge(0, PageSizeClass::Huge2M),
+            PageOwner::Unknown
+        );
+        assert_eq!(
+            dir.get_owner_huge(huge_bytes + 4096, PageSizeClass::Small4K),
+            PageOwner::Remote(2)
+        );
+        // Not all of the 1 GiB page
+        assert_eq!(
+            dir.get_owner_huge(huge_bytes, PageSizeClass::Huge1G),
+            PageOwner::Unknown
+        );
+
+        // One page elsewhere splits the ownership
+        dir.set_owner(HUGE_PAGE_PAGES + 7, PageOwner::Local);
+        assert_eq!(
+            dir.get_owner_huge(huge_bytes, PageSizeClass::Huge2M),
+            PageOwner::Unknown
+        );
+
+        for page in 0..HUGE_PAGE_PAGES {
+            dir.claim_page(page);
+        }
+        assert!(dir.promote_region(0));
+        assert_eq!(
+            dir.get_owner_huge(0, PageSizeClass::Huge2M),
+            PageOwner::Local
+        );
+    }
+
+    #[test]
+    fn test_page_directory_releases_and_collects_unknown_entries() {
+        let dir = PageDirectory::new(0);
+        for page in 0..200 {
+            dir.claim_page(page);
+        }
+        // 100 regions left with nothing but Unknown pages
+        for region_num in 10..110 {
+            dir.regions.insert(region_num, Region::new());
+        }
+        let entries = |dir: &PageDirectory| -> usize {
+            dir.shard_stats().iter().map(|s| s.entry_count).sum()
+        };
+        assert_eq!(entries(&dir), 101);
+
+        assert_eq!(dir.gc_unknown_entries(), 100);
+        assert_eq!(entries(&dir), 1);
+        assert_eq!(dir.page_count(), 200);
+
+        let gpas: Vec<u64> = (150..250).map(|page| page * PAGE_SIZE as u64).collect();
+        // Pages 200..250 were never claimed
+        assert_eq!(dir.release_pages(&gpas), 50);
+        assert_eq!(dir.page_count(), 150);
+        assert_eq!(dir.get_owner(160), PageOwner::Unknown);
+        assert_eq!(dir.get_owner(149), PageOwner::Local);
+        // Still marking the released pages
+        assert_eq!(dir.gc_unknown_entries(), 0);
+        assert_eq!(dir.page_count(), 150);
+    }
+
+    #[test]
+    fn test_page_directory_compaction_removes_unknown_entries() {
+        let dir = PageDirectory::new(0);
+        for region_num in 0..1000 {
+            dir.regions.insert(region_num, Region::new());
+        }
+
+        let compaction = dir.compact(&HashSet::new());
+        assert_eq!(compaction.entries_before, 1000);
+        assert_eq!(compaction.entries_removed, 1000);
+        assert!(compaction.bytes_freed >= 1000 * std::mem::size_of::<Region>());
+        assert_eq!(dir.regions.len(), 0);
+    }
+
+    #[test]
+    fn test_page_directory_compaction_forgets_dead_nodes() {
+        let dir = PageDirectory::new(0);
+        // Region 0: local with two pages on node 2; region 1: all node 2
+        for page in 0..REGION_PAGES {
+            dir.claim_page(page);
+            dir.set_owner(REGION_PAGES + page, PageOwner::Remote(2));
+        }
+        dir.set_owner(3, PageOwner::Remote(2));
+        dir.set_owner(4, PageOwner::Remote(2));
+        dir.set_owner(5, PageOwner::Remote(1));
+        // Overrides a different dominant owner would make redundant
+        let mut region = Region::new();
+        for offset in 0..10 {
+            region.overrides.insert(offset, PageOwner::Remote(1));
+        }
+        dir.regions.insert(2, region);
+        let epoch = dir.epoch();
+
+        let compaction = dir.compact(&HashSet::from([2]));
+        assert_eq!(compaction.entries_before, 3 + 3 + 10);
+        assert_eq!(dir.get_owner(3), PageOwner::Unknown);
+        assert_eq!(dir.get_owner(5), PageOwner::Remote(1));
+        assert_eq!(dir.get_owner(6), PageOwner::Local);
+        assert_eq!(dir.get_owner(REGION_PAGES + 7), PageOwner::Unknown);
+        assert_eq!(dir.get_owner(2 * REGION_PAGES + 9), PageOwner::Remote(1));
+        assert_eq!(dir.get_owner(2 * REGION_PAGES + 10), PageOwner::Unknown);
+        assert!(dir.regions.get(&1).is_none());
+        assert_eq!(dir.regions.get(&2).unwrap().overrides.len(), 10);
+        assert!(dir.epoch() > epoch);
+    }
+
+    #[test]
+    fn test_page_directory_cached_owners_follow_changes() {
+        let dir = PageDirectory::new(0);
+        // Regions 1 and 1 + OWNER_CACHE_SLOTS share a cache slot
+        let aliased = (1 + OWNER_CACHE_SLOTS) * REGION_PAGES;
+        assert_eq!(dir.get_owner(REGION_PAGES), PageOwner::Unknown);
+        dir.set_owner(REGION_PAGES + 1, PageOwner::Remote(2));
+        assert_eq!(dir.get_owner(REGION_PAGES + 1), PageOwner::Remote(2));
+        assert_eq!(dir.get_owner(aliased), PageOwner::Unknown);
+        assert_eq!(dir.get_owner(REGION_PAGES + 1), PageOwner::Remote(2));
+
+        // Uniform regions are served from the cache until changed
+        for page in 2 * REGION_PAGES..3 * REGION_PAGES {
+            dir.set_owner(page, PageOwner::Remote(3));
+        }
+        assert_eq!(dir.get_owner(2 * REGION_PAGES), PageOwner::Remote(3));
+        let slot = dir.cache_slot(2).read().0;
+        assert_eq!(slot, (2, PageOwner::Remote(3)));
+
+        dir.compact(&HashSet::from([3]));
+        assert_eq!(dir.get_owner(2 * REGION_PAGES + 5), PageOwner::Unknown);
+        assert_eq!(dir.get_owner(REGION_PAGES + 1), PageOwner::Remote(2));
+    }
+
+    #[test]
+    fn test_page_directory_shard_stats() {
+        let dir = PageDirectory::new(0);
+        // One page in each of 1000 regions
+        for region in 0..1000 {
+            dir.claim_page(region * REGION_PAGES);
+        }
+
+        let shards = dir.shard_stats();
+        assert!(shards.len() > 1);
+        assert_eq!(shards.iter().map(|s| s.entry_count).sum::<usize>(), 1000);
+        assert!(shards
+            .iter()
+            .enumerate()
+            .all(|(i, s)| s.shard_id == i as u32));
+        assert_eq!(
+            dir.max_shard_occupancy(),
+            shards.iter().map(|s| s.entry_count).max().unwrap()
+        );
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_page_directory_shard_distribution() {
+        const PAGES: u64 = 100_000;
+
+        // Page number = i * multiplier
+        let distributions = [
+            ("sequential", 1u64),
+            ("stride 512 (2 MiB)", 512),
+            ("stride 262144 (1 GiB)", 1 << 18),
+            ("high bits only", 1 << 40),
+        ];
+
+        for (name, multiplier) in distributions {
+            let dir = PageDirectory::new(0);
+            let start = std::time::Instant::now();
+            for i in 0..PAGES {
+                dir.claim_page(i * multiplier);
+            }
+            let elapsed = start.elapsed();
+
+            let counts: Vec<f64> = dir
+                .shard_stats()
+                .iter()
+                .map(|s| s.entry_count as f64)
+                .collect();
+            let mean = counts.iter().sum::<f64>() / counts.len() as f64;
+            let variance =
+                counts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / counts.len() as f64;
+
+            println!(
+                "{:>22}: {} shards, mean {:.0}, max {}, stddev {:.1} ({:.1}% of mean), insert {:?}",
+                name,
+                counts.len(),
+                mean,
+                dir.max_shard_occupancy(),
+                variance.sqrt(),
+                variance.sqrt() / mean * 100.0,
+                elapsed
+            );
+        }
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_rwlock_hashmap_vs_page_directory() {
+        const THREADS: u64 = 8;
+        const OPS: u64 = 200_000;
+        const PAGES: u64 = 1 << 16;
+
+        // Each thread mostly looks owners up, claiming a page every 16th fault
+        fn throughput(get_owner: impl Fn(u64) + Sync, claim_page: impl Fn(u64) + Sync) -> f64 {
+            let start = std::time::Instant::now();
+            std::thread::scope(|s| {
+                for thread in 0..THREADS {
+                    let (get_owner, claim_page) = (&get_owner, &claim_page);
+                    s.spawn(move || {
+                        for op in 0..OPS {
+                            let page = (op * 7919 + thread * PAGES / THREADS) % PAGES;
+                            if op % 16 == 0 {
+                                claim_page(page);
+                            } else {
+                                get_owner(page);
+                            }
+                        }
+                    });
+                }
+            });
+            (THREADS * OPS) as f64 / start.elapsed().as_secs_f64()
+        }
+
+        // The single-lock map the directory used to be
+        let locked = RwLock::new(HashMap::<u64, PageOwner>::new());
+        let rwlock = throughput(
+            |page| {
+                std::hint::black_box(locked.read().get(&page).copied());
+            },
+            |page| {
+                locked.write().insert(page, PageOwner::Local);
+            },
+        );
+
+        let dir = PageDirectory::new(0);
+        let sharded = throughput(
+            |page| {
+                std::hint::black_box(dir.get_owner(page));
+            },
+            |page| {
+                dir.claim_page(page);
+            },
+        );
+
+        println!(
+            "{} threads, 1 claim per 16 lookups: RwLock<HashMap> {:.1}M ops/s, \
+             PageDirectory {:.1}M ops/s ({:.1}x)",
+            THREADS,
+            rwlock / 1e6,
+            sharded / 1e6,
+            sharded / rwlock
+        );
+    }
+
+    /// A test `TransportManager`'s `endpoint`, at the loopback address
+    ///
+    /// The manager's transport depends on the features compiled in.
+    pub(crate) fn loopback(endpoint: TransportEndpoint) -> TransportEndpoint {
+        let localhost = std::net::IpAddr::from([127, 0, 0, 1]);
+        match endpoint {
+            TransportEndpoint::Tcp { port, .. } => TransportEndpoint::Tcp {
+                addr: localhost,
+                port,
+            },
+            #[cfg(feature = "quic-transport")]
+            TransportEndpoint::Quic { port, cert, .. } => TransportEndpoint::Quic {
+                addr: localhost,
+                port,
+                cert,
+            },
+            #[allow(unreachable_patterns)]
+            other => panic!("{} is not reachable over loopback", other),
+        }
+    }
+
+    /// Identity `mock_coordinator` signs tokens with
+    fn mock_coordinator_identity() -> &'static NodeIdentity {
+        static IDENTITY: OnceLock<NodeIdentity> = OnceLock::new();
+        IDENTITY.get_or_init(NodeIdentity::generate)
+    }
+
+    /// Transport for `node_id` that pagers registered with `mock_coordinator`
+    /// accept as a peer
+    fn authenticated_peer(node_id: u32) -> TransportManager {
+        let coordinator = mock_coordinator_identity();
+        let identity = NodeIdentity::generate();
+        let token = AuthToken::issue(
+            coordinator,
+            node_id,
+            &identity.public_key_hex(),
+            4_000_000_000,
+        );
+        let transport = TransportManager::new(node_id).unwrap();
+        transport.set_authenticator(Arc::new(PeerAuth::new(
+            node_id,
+            identity,
+            token,
+            coordinator.verifying_key(),
+        )));
+        transport
+    }
+
+    /// Minimal coordinator: issue a token, accept registration, report no peers
+    fn mock_coordinator() -> axum::Router {
+        use axum::routing::{get, post};
+
+        let coordinator = mock_coordinator_identity();
+        let coordinator_key = coordinator.public_key_hex();
+        // Node IDs by the hardware fingerprint they registered
+        let fingerprints = Arc::new(Mutex::new(HashMap::<String, u32>::new()));
+        axum::Router::new()
+            .route(
+                "/auth/register",
+                post(
+                    move |axum::Json(reg): axum::Json<identity::Registration>| async move {
+                        if let Some(fingerprint) = reg.hardware_fingerprint {
+                            let mut fingerprints = fingerprints.lock();
+                            if *fingerprints.entry(fingerprint).or_insert(reg.node_id)
+                                != reg.node_id
+                            {
+                                return Err(axum::http::StatusCode::CONFLICT);
+                            }
+                        }
+                        let expiry = 4_000_000_000;
+                        let token =
+                            AuthToken::issue(coordinator, reg.node_id, &reg.public_key, expiry);
+                        Ok(axum::Json(serde_json::json!({
+                            "token": token.to_bearer(),
+                            "coordinator_key": coordinator_key,
+                        })))
+                    },
+                ),
+            )
+            .route(
+                "/nodes/{node_id}/endpoint",
+                post(|| async { axum::http::StatusCode::CREATED }),
+            )
+            .route(
+                "/endpoints",
+                get(|| async { axum::Json(serde_json::json!({ "endpoints": {} })) }),
+            )
+    }
+
+    /// Serve `app` on a local port, returning its URL
+    async fn serve_coordinator(app: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    fn map_anonymous(len: usize) -> *mut libc::c_void {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+        base
+    }
+
+    #[test]
+    fn test_fault_threads_share_faults_and_metrics_are_scraped() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let pages = 64;
+        let len = pages * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let config = PagerConfigBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .fault_threads(4)
+            .metrics_port(0)
+            .build()
+            .unwrap();
+        let pager = runtime
+            .block_on(PagerBuilder::from_config(config).build_async())
+            .unwrap();
+        let metrics_addr = pager.metrics_addr().unwrap();
+        let handle = PagerHandle::spawn(pager).unwrap();
+
+        // Several vCPUs touching pages at once
+        let base_addr = base as usize;
+        let touchers: Vec<_> = (0..4)
+            .map(|toucher| {
+                thread::spawn(move || {
+                    for page in (toucher..pages).step_by(4) {
+                        let addr = base_addr + page * PAGE_SIZE;
+                        unsafe { std::ptr::write_volatile(addr as *mut u8, 1) };
+                    }
+                })
+            })
+            .collect();
+        for toucher in touchers {
+            toucher.join().unwrap();
+        }
+        // Counted once the guest is on its way again
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.stats().local_faults < pages as u64 {
+            assert!(Instant::now() < deadline, "faults were never counted");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.stats().local_faults, pages as u64);
+
+        let text = runtime
+            .block_on(async {
+                reqwest::get(format!("http://{}/metrics", metrics_addr))
+                    .await?
+                    .text()
+                    .await
+            })
+            .unwrap();
+        assert!(
+            text.contains(&format!(
+                "ssi_hv_pager_local_faults_total{{node=\"0\"}} {}",
+                pages
+            )),
+            "{}",
+            text
+        );
+
+        handle.stop().unwrap();
+        unsafe { libc::munmap(base, len) };
+    }
+
+    #[test]
+    fn test_pager_handle_stats_and_stop() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let handle = start_pager(
+            runtime.handle(),
+            base as *mut u8,
+            len,
+            0,
+            1,
+            &coordinator_url,
+        )
+        .unwrap();
+        assert_eq!(handle.stats().local_faults, 0);
+
+        // A first touch, resolved by the pager thread, which counts it once
+        // the guest is on its way again
+        unsafe { std::ptr::write_volatile(base as *mut u8, 1) };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while handle.stats().local_faults == 0 {
+            assert!(Instant::now() < deadline, "fault was never counted");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.stats().local_faults, 1);
+        assert_eq!(handle.directory().get_owner(0), PageOwner::Local);
+
+        assert!(!handle.is_finished());
+        handle.stop().unwrap();
+        unsafe { libc::munmap(base, len) };
+    }
+
+    #[test]
+    fn test_stopped_pager_deregisters() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let deregistered = Arc::new(Mutex::new(Vec::new()));
+        let app = mock_coordinator().route(
+            "/nodes/{node_id}",
+            axum::routing::delete({
+                let deregistered = Arc::clone(&deregistered);
+                move |axum::extract::Path(node_id): axum::extract::Path<u32>| async move {
+                    deregistered.lock().push(node_id);
+                    axum::http::StatusCode::OK
+                }
+            }),
+        );
+        let coordinator_url = runtime.block_on(serve_coordinator(app));
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let handle = start_pager(
+            runtime.handle(),
+            base as *mut u8,
+            len,
+            3,
+            4,
+            &coordinator_url,
+        )
+        .unwrap();
+        assert!(deregistered.lock().is_empty());
+
+        handle.stop().unwrap();
+        assert_eq!(*deregistered.lock(), vec![3]);
+        unsafe { libc::munmap(base, len) };
+    }
+
+    #[test]
+    fn test_blocking_build_registers_on_given_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let len = PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        // Only with a runtime to reach the coordinator on
+        assert!(PagerBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .build()
+            .is_err());
+
+        // Outside any async context, as `build` must be
+        let pager = PagerBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .runtime(runtime.handle().clone())
+            .build()
+            .unwrap();
+        fault(&pager, base as u64).unwrap();
+        assert_eq!(pager.directory().get_owner(0), PageOwner::Local);
+
+        drop(pager);
+        unsafe { libc::munmap(base, len) };
+    }
+
+    #[test]
+    fn test_management_port_binds_loopback() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let len = 4 * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let builder = PagerBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .management_port(0);
+        let pager = runtime.block_on(builder.build_async()).unwrap();
+        let addr = pager.management_addr().unwrap();
+        assert!(addr.ip().is_loopback(), "{}", addr);
+        assert_ne!(addr.port(), 0);
+
+        pager.shutdown_signal().trigger();
+        drop(pager);
+        unsafe { libc::munmap(base, len) };
+    }
+
+    #[test]
+    fn test_zero_fills_counted_against_pool() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let len = 4 * PAGE_SIZE;
+
+        // Each zero-fill gives its buffer back, so a one-page pool serves
+        // every fault; without a pool they all go to the heap
+        for (capacity, expected) in [(1, (2, 0)), (0, (0, 2))] {
+            let base = map_anonymous(len);
+            let builder = PagerBuilder::new(base as *mut u8, len)
+                .coordinator_url(&coordinator_url)
+                .pool_capacity(capacity);
+            let pager = runtime.block_on(builder.build_async()).unwrap();
+            fault(&pager, base as u64).unwrap();
+            fault(&pager, base as u64 + PAGE_SIZE as u64).unwrap();
+
+            let stats = pager.get_stats();
+            assert_eq!((stats.pool_hits, stats.pool_misses), expected);
+            assert_eq!(unsafe { *(base as *const u8).add(PAGE_SIZE) }, 0);
+            drop(pager);
+            unsafe { libc::munmap(base, len) };
+        }
+    }
+
+    #[test]
+    fn test_sequential_first_touches_prefetched() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let coordinator_url = runtime.block_on(serve_coordinator(mock_coordinator()));
+        let pages = 10;
+        let len = pages * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let builder = PagerBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .prefetch_pages(3);
+        let handle = PagerHandle::spawn(runtime.block_on(builder.build_async()).unwrap()).unwrap();
+
+        // Touch every page in order, letting the prefetcher catch up after
+        // each fault: pages 0, 4 and 8 fault, the rest are prefetched
+        let page_addr = |page: usize| base as u64 + (page * PAGE_SIZE) as u64;
+        let mut faulted = Vec::new();
+        let mut prefetched = Vec::new();
+        for page in 0..pages {
+            let present = is_resident(page_addr(page)).unwrap();
+            let start = Instant::now();
+            unsafe { std::ptr::write_volatile(page_addr(page) as *mut u8, 1) };
+            let latency = start.elapsed();
+            if present {
+                prefetched.push(latency);
+                continue;
+            }
+            faulted.push(latency);
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            for next in page + 1..pages.min(page + 4) {
+                while !is_resident(page_addr(next)).unwrap() {
+                    assert!(Instant::now() < deadline, "page {} not prefetched", next);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+
+        let stats = handle.stats();
+        assert_eq!((stats.local_faults, stats.prefetched_pages), (3, 7));
+        assert_eq!(handle.directory().local_page_count(), pages);
+        let mean =
+            |latencies: &[Duration]| latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        assert!(
+            mean(&prefetched) < mean(&faulted) / 2,
+            "prefetched {:?}, faulted {:?}",
+            prefetched,
+            faulted
+        );
 
-        // Register endpoint with coordinator
-        let local_endpoint = transport.local_endpoint();
-        Self::register_with_coordinator(coordinator_url, node_id, &local_endpoint)
-            .context("Failed to register with coordinator")?;
+        handle.stop().unwrap();
+        unsafe { libc::munmap(base, len) };
+    }
 
-        // Discover and connect to all peer nodes
-        Self::discover_and_connect_peers(coordinator_url, node_id, &mut transport)
-            .context("Failed to discover peers")?;
+    #[tokio::test]
+    async fn test_new_async() {
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
 
-        Ok(Self {
-            uffd,
-            base: base as u64,
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+
+        let pager = Pager::new_async(PagerConfig {
+            base: base as *mut u8,
             len,
-            directory: Arc::new(PageDirectory::new(node_id)),
-            stats: Arc::new(RwLock::new(PagerStats::default())),
-            node_id,
-            total_nodes,
-            transport: Arc::new(RwLock::new(transport)),
-            coordinator_url: coordinator_url.to_string(),
+            node_id: 0,
+            total_nodes: 1,
+            coordinator: CoordinatorConfig::single(&coordinator_url),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
         })
+        .await
+        .unwrap();
+        assert_eq!(pager.directory().page_count(), 0);
+
+        // The transport owns a runtime, which must not be dropped in async context
+        tokio::task::spawn_blocking(move || drop(pager))
+            .await
+            .unwrap();
+        unsafe { libc::munmap(base, len) };
     }
 
-    /// Register local endpoint with coordinator
-    fn register_with_coordinator(
-        coordinator_url: &str,
-        node_id: u32,
-        endpoint: &TransportEndpoint,
-    ) -> Result<()> {
-        let client = reqwest::blocking::Client::new();
+    #[tokio::test]
+    async fn test_second_node_on_host_rejected_by_fingerprint() {
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
 
-        let endpoint_json = match endpoint {
-            TransportEndpoint::Tcp { addr, port } => serde_json::json!({
-                "transport_type": "tcp",
-                "tcp_addr": addr,
-                "tcp_port": port,
-            }),
-            #[cfg(feature = "rdma-transport")]
-            TransportEndpoint::Rdma { qpn, lid, gid, psn } => serde_json::json!({
-                "transport_type": "rdma",
-                "rdma_qpn": qpn,
-                "rdma_lid": lid,
-                "rdma_gid": format!("0x{}", hex::encode(gid)),
-                "rdma_psn": psn,
-            }),
+        let len = 16 * PAGE_SIZE;
+        let bases = [map_anonymous(len), map_anonymous(len)];
+        let start = |node_id: u32, require_unique_fingerprint: bool| {
+            PagerBuilder::new(bases[node_id as usize] as *mut u8, len)
+                .node_id(node_id)
+                .total_nodes(2)
+                .coordinator_url(&coordinator_url)
+                .require_unique_fingerprint(require_unique_fingerprint)
+                .build_async()
         };
 
-        let url = format!("{}/nodes/{}/endpoint", coordinator_url, node_id);
-        let response = client
-            .post(&url)
-            .json(&endpoint_json)
-            .timeout(std::time::Duration::from_secs(5))
-            .send()
-            .context("Failed to send endpoint registration")?;
+        let first = start(0, true).await.unwrap();
+        let error = start(1, true).await.err().unwrap();
+        assert!(format!("{:#}", error).contains("another node ID"));
+        tokio::task::spawn_blocking(move || drop(first))
+            .await
+            .unwrap();
 
-        if !response.status().is_success() {
-            return Err(anyhow!(
-                "Failed to register endpoint: {}",
-                response.status()
-            ));
+        // Opting out registers no fingerprint
+        let second = start(1, false).await.unwrap();
+        tokio::task::spawn_blocking(move || drop(second))
+            .await
+            .unwrap();
+        for base in bases {
+            unsafe { libc::munmap(base, len) };
         }
+    }
 
-        info!("✅ Registered endpoint with coordinator: {:?}", endpoint);
-        Ok(())
+    #[tokio::test]
+    async fn test_report_load_to_coordinator() {
+        let reports = Arc::new(Mutex::new(Vec::<String>::new()));
+        let received = Arc::clone(&reports);
+        let app = mock_coordinator().route(
+            "/nodes/{node_id}/load",
+            axum::routing::put(move |body: String| async move {
+                received.lock().push(body);
+                axum::http::StatusCode::OK
+            }),
+        );
+        let coordinator_url = serve_coordinator(app).await;
+
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let pager = Pager::new_async(PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id: 0,
+            total_nodes: 1,
+            coordinator: CoordinatorConfig::single(&coordinator_url),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
+        })
+        .await
+        .unwrap();
+        for page in 0..3 {
+            pager.directory().claim_page(page);
+        }
+        pager.directory().set_owner(3, PageOwner::Remote(1));
+
+        tokio::task::spawn_blocking(move || {
+            pager.report_load_to_coordinator().unwrap();
+            drop(pager);
+        })
+        .await
+        .unwrap();
+
+        let reports = reports.lock();
+        assert_eq!(reports.len(), 1);
+        let load: metrics::LoadMetrics = serde_json::from_str(&reports[0]).unwrap();
+        assert_eq!(load.pages_owned, 3);
+        assert!(load.fault_rate_per_sec >= 0.0);
+        unsafe { libc::munmap(base, len) };
     }
 
-    /// Discover peer endpoints from coordinator and connect
-    fn discover_and_connect_peers(
-        coordinator_url: &str,
-        local_node_id: u32,
-        transport: &mut TransportManager,
-    ) -> Result<()> {
-        let client = reqwest::blocking::Client::new();
-        let url = format!("{}/endpoints", coordinator_url);
+    #[test]
+    fn test_slow_coordinator_reports_do_not_hold_up_faults() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        // Every report outlasts the pager's timeout
+        let stalled = |received: Arc<AtomicU64>| {
+            axum::routing::put(move || {
+                let received = Arc::clone(&received);
+                async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    axum::http::StatusCode::OK
+                }
+            })
+        };
+        let load_reports = Arc::new(AtomicU64::new(0));
+        let balloon_reports = Arc::new(AtomicU64::new(0));
+        let app = mock_coordinator()
+            .route("/nodes/{node_id}/load", stalled(Arc::clone(&load_reports)))
+            .route(
+                "/nodes/{node_id}/balloon_stats",
+                stalled(Arc::clone(&balloon_reports)),
+            );
+        let coordinator_url = runtime.block_on(serve_coordinator(app));
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
 
-        let response = client
-            .get(&url)
-            .timeout(std::time::Duration::from_secs(5))
-            .send()
-            .context("Failed to fetch endpoints")?;
+        let builder = PagerBuilder::new(base as *mut u8, len).coordinator_url(&coordinator_url);
+        let mut pager = runtime.block_on(builder.build_async()).unwrap();
+        pager.load_report_interval = Duration::from_millis(1);
+        pager.balloon_report_interval = Duration::from_millis(1);
+        let handle = PagerHandle::spawn(pager).unwrap();
 
-        if !response.status().is_success() {
-            return Err(anyhow!("Failed to fetch endpoints: {}", response.status()));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while load_reports.load(Ordering::SeqCst) < 2 || balloon_reports.load(Ordering::SeqCst) < 2
+        {
+            assert!(Instant::now() < deadline, "reports were never sent");
+            thread::sleep(Duration::from_millis(1));
         }
 
-        #[derive(Deserialize)]
-        struct EndpointsResponse {
-            endpoints: HashMap<String, CoordinatorEndpoint>,
+        // First touches are resolved while the coordinator is stuck
+        let start = Instant::now();
+        for page in 0..8 {
+            let addr = base as usize + page * PAGE_SIZE;
+            unsafe { std::ptr::write_volatile(addr as *mut u8, 1) };
         }
+        assert!(start.elapsed() < LOAD_REPORT_TIMEOUT);
 
-        let endpoints_resp: EndpointsResponse = response
-            .json()
-            .context("Failed to parse endpoints response")?;
+        handle.stop().unwrap();
+        unsafe { libc::munmap(base, len) };
+    }
 
-        info!(
-            "📋 Discovered {} peer nodes",
-            endpoints_resp.endpoints.len()
-        );
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_faults_on_remote_page_fetch_once() {
+        const THREADS: usize = 8;
 
-        // Connect to all peers except self
-        for (node_id_str, coord_endpoint) in endpoints_resp.endpoints {
-            let peer_node_id: u32 = node_id_str.parse().context("Invalid node ID in response")?;
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let pager = Pager::new_async(PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id: 0,
+            total_nodes: 2,
+            coordinator: CoordinatorConfig::single(&coordinator_url),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
+        })
+        .await
+        .unwrap();
+        let fault_addr = base as u64 + 5 * PAGE_SIZE as u64;
 
-            if peer_node_id == local_node_id {
-                continue; // Skip self
-            }
+        tokio::task::spawn_blocking(move || {
+            // Node 1 serves (zero) pages over TCP
+            let owner = authenticated_peer(1);
 
-            let transport_endpoint = coord_endpoint
-                .to_transport_endpoint()
-                .context("Failed to convert endpoint")?;
+            pager
+                .transport()
+                .write()
+                .connect_peer(1, loopback(owner.local_endpoint()))
+                .unwrap();
+            pager.directory().set_owner(5, PageOwner::Remote(1));
 
-            transport
-                .connect_peer(peer_node_id, transport_endpoint)
-                .context(format!("Failed to connect to node {}", peer_node_id))?;
-        }
+            let barrier = std::sync::Barrier::new(THREADS);
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    s.spawn(|| {
+                        barrier.wait();
+                        fault(&pager, fault_addr).unwrap();
+                    });
+                }
+            });
 
-        Ok(())
+            let stats = pager.get_stats();
+            assert_eq!(stats.remote_faults, THREADS as u64);
+            // One transport fetch; everyone else got the page from the cache
+            assert_eq!(stats.cache_hits, THREADS as u64 - 1);
+            assert!(pager.inflight.is_empty());
+            drop(pager);
+            drop(owner);
+        })
+        .await
+        .unwrap();
+        unsafe { libc::munmap(base, len) };
     }
 
-    /// Main fault handling loop
-    fn handle_faults(self) -> Result<()> {
-        info!(
-            "Pager: fault handling loop started on node {}",
-            self.node_id
-        );
-
-        loop {
-            // Read fault event (blocking)
-            let event = match self.uffd.read_event() {
-                Ok(Some(event)) => event,
-                Ok(None) => continue,
-                Err(e) => {
-                    warn!("Failed to read uffd event: {}", e);
-                    continue;
-                }
-            };
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stale_epoch_fetch_retries_after_requery() {
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let pager = Pager::new_async(PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id: 0,
+            total_nodes: 3,
+            coordinator: CoordinatorConfig::single(&coordinator_url),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
+        })
+        .await
+        .unwrap();
+        let fault_addr = base as u64 + 5 * PAGE_SIZE as u64;
 
-            match event {
-                Event::Pagefault { addr, .. } => {
-                    let start = std::time::Instant::now();
-                    let fault_addr = addr as u64;
+        tokio::task::spawn_blocking(move || {
+            // Node 1 has not seen any ownership change; node 2 is up to date
+            let stale = authenticated_peer(1);
+            stale.set_directory_epoch(Arc::new(AtomicU64::new(0)));
+            let current = authenticated_peer(2);
+            current.set_directory_epoch(Arc::new(AtomicU64::new(u64::MAX)));
+            for peer in [&stale, &current] {
+                pager
+                    .transport()
+                    .write()
+                    .connect_peer(peer.local_node_id(), loopback(peer.local_endpoint()))
+                    .unwrap();
+            }
+            pager.directory().set_owner(5, PageOwner::Remote(1));
+            let (owner, epoch) = pager.directory().read_with_epoch(5);
+            assert_eq!(owner, PageOwner::Remote(1));
+            assert_eq!(epoch, 1);
 
-                    if let Err(e) = self.handle_pagefault(fault_addr) {
-                        warn!("Failed to handle page fault at 0x{:x}: {}", fault_addr, e);
+            thread::scope(|s| {
+                // The page migrates to node 2 once node 1 has refused it
+                s.spawn(|| {
+                    while stale.stats().stale_epoch_rejections == 0 {
+                        thread::sleep(Duration::from_millis(1));
                     }
+                    pager.directory().set_owner(5, PageOwner::Remote(2));
+                });
+                fault(&pager, fault_addr).unwrap();
+            });
 
-                    let elapsed = start.elapsed().as_micros() as u64;
-                    self.stats.write().fault_service_time_us.push(elapsed);
-
-                    debug!(
-                        "Fault serviced: addr=0x{:x}, time={}µs",
-                        fault_addr, elapsed
-                    );
-                }
-                Event::Fork { .. } => {
-                    info!("Fork event (unhandled)");
-                }
-                Event::Remap { .. } => {
-                    info!("Remap event (unhandled)");
-                }
-                Event::Remove { .. } => {
-                    info!("Remove event (unhandled)");
-                }
-                Event::Unmap { .. } => {
-                    info!("Unmap event (unhandled)");
-                }
-            }
-        }
+            let stats = pager.get_stats();
+            assert!(stats.stale_epoch_retries >= 1);
+            assert_eq!(stats.remote_faults, 1);
+            assert_eq!(pager.directory().epoch(), 2);
+            drop(pager);
+            drop((stale, current));
+        })
+        .await
+        .unwrap();
+        unsafe { libc::munmap(base, len) };
     }
 
-    /// Handle a single page fault
-    fn handle_pagefault(&self, fault_addr: u64) -> Result<()> {
-        let page_num = (fault_addr - self.base) / PAGE_SIZE as u64;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lazy_overcommit_evicts_to_peer() {
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let pager = Pager::new_async(PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id: 0,
+            total_nodes: 2,
+            coordinator: CoordinatorConfig::single(&coordinator_url),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Lazy { max_local_pages: 2 },
+            replacement_policy: Arc::new(policy::LruPolicy::default()),
+            placement: PlacementPolicy::FirstTouch,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            require_unique_fingerprint: true,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
+        })
+        .await
+        .unwrap();
+        let base_addr = base as u64;
+        let page_addr = move |page_num: u64| base_addr + page_num * PAGE_SIZE as u64;
 
-        debug!("Page fault: addr=0x{:x}, page_num={}", fault_addr, page_num);
+        tokio::task::spawn_blocking(move || {
+            let peer = authenticated_peer(1);
+            peer.set_directory_epoch(Arc::new(AtomicU64::new(u64::MAX)));
 
-        // Check ownership
-        let owner = self.directory.get_owner(page_num);
+            pager
+                .transport()
+                .write()
+                .connect_peer(1, loopback(peer.local_endpoint()))
+                .unwrap();
 
-        match owner {
-            PageOwner::Local => {
-                // Already local, just zero-fill (shouldn't happen in normal operation)
-                self.resolve_with_zeros(fault_addr)?;
-                self.stats.write().local_faults += 1;
-            }
-            PageOwner::Remote(node) => {
-                // Fetch from remote node via RDMA
-                self.fetch_remote_page(fault_addr, node)?;
-                self.stats.write().remote_faults += 1;
-            }
-            PageOwner::Unknown => {
-                // First touch - claim ownership and zero-fill
-                self.directory.claim_page(page_num);
-                self.resolve_with_zeros(fault_addr)?;
-                self.stats.write().local_faults += 1;
-            }
-        }
+            fault(&pager, page_addr(0)).unwrap();
+            unsafe { std::ptr::write_bytes(page_addr(0) as *mut u8, 0x5a, PAGE_SIZE) };
+            fault(&pager, page_addr(1)).unwrap();
+            assert_eq!(pager.get_stats().evictions, 0);
 
-        Ok(())
-    }
+            // Page 0 was claimed first and never faulted on since
+            fault(&pager, page_addr(2)).unwrap();
+            assert_eq!(pager.get_stats().evictions, 1);
+            assert_eq!(pager.directory().local_page_count(), 2);
+            assert_eq!(pager.directory().get_owner(0), PageOwner::Remote(1));
+            let evicted = pager
+                .transport()
+                .read()
+                .fetch_page(page_addr(0), 1)
+                .unwrap();
+            assert_eq!(evicted, vec![0x5a; PAGE_SIZE]);
 
-    /// Resolve fault with zero-filled page (local allocation)
-    fn resolve_with_zeros(&self, addr: u64) -> Result<()> {
-        let zero_page = vec![0u8; PAGE_SIZE];
+            drop(pager);
+            drop(peer);
+        })
+        .await
+        .unwrap();
+        unsafe { libc::munmap(base, len) };
+    }
 
-        unsafe {
-            self.uffd
-                .copy(
-                    zero_page.as_ptr() as *const libc::c_void,
-                    addr as *mut libc::c_void,
-                    PAGE_SIZE,
-                    true,
-                )
-                .context("Failed to copy zero page")?;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_high_watermark_signals_once() {
+        struct Counter(Arc<AtomicU64>);
+        impl PressureCallback for Counter {
+            fn on_high_pressure(&self, _current: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            fn on_pressure_relieved(&self) {}
         }
 
-        debug!("Resolved with zeros: addr=0x{:x}", addr);
-        Ok(())
+        let coordinator_url = serve_coordinator(mock_coordinator()).await;
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let signals = Arc::new(AtomicU64::new(0));
+        let pager = PagerBuilder::new(base as *mut u8, len)
+            .coordinator_url(&coordinator_url)
+            .watermarks(MemoryWatermarks {
+                low_pages: 2,
+                high_pages: 4,
+            })
+            .pressure_callback(Box::new(Counter(Arc::clone(&signals))))
+            .build_async()
+            .await
+            .unwrap();
+        let base_addr = base as u64;
+
+        tokio::task::spawn_blocking(move || {
+            for page_num in 0..8 {
+                fault(&pager, base_addr + page_num * PAGE_SIZE as u64).unwrap();
+            }
+            // Several polls above the high watermark
+            thread::sleep(pressure::PRESSURE_POLL_INTERVAL * 3);
+            drop(pager);
+        })
+        .await
+        .unwrap();
+        assert_eq!(signals.load(Ordering::SeqCst), 1);
+        unsafe { libc::munmap(base, len) };
     }
 
-    /// Fetch page from remote node via transport layer
-    fn fetch_remote_page(&self, addr: u64, remote_node: u32) -> Result<()> {
-        debug!(
-            "Fetching remote page: addr=0x{:x}, from node {}",
-            addr, remote_node
-        );
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_speculative_claim_aborts_on_conflict() {
+        use axum::extract::Path;
+        use axum::routing::get;
 
-        // Use TransportManager to fetch page (works with TCP or RDMA)
-        let transport = self.transport.read();
-        let page_data = transport
-            .fetch_page(addr, remote_node)
-            .context("Failed to fetch page via transport")?;
+        // Node 1 already claimed page 3; anything else is ours for the asking
+        let app = mock_coordinator().route(
+            "/pages/{page_num}/owner",
+            get(|Path(page_num): Path<u64>| async move {
+                let owner = (page_num == 3).then_some(1);
+                axum::Json(serde_json::json!({ "page_num": page_num, "owner_node": owner }))
+            })
+            .put(|Path(page_num): Path<u64>| async move {
+                axum::Json(serde_json::json!({ "page_num": page_num, "owner_node": 0 }))
+            }),
+        );
+        let coordinator_url = serve_coordinator(app).await;
+        let len = 16 * PAGE_SIZE;
+        let base = map_anonymous(len);
+        let pager = PagerBuilder::new(base as *mut u8, len)
+            .total_nodes(2)
+            .coordinator_url(&coordinator_url)
+            .speculative_claims(true)
+            .build_async()
+            .await
+            .unwrap();
+        let base_addr = base as u64;
+        let page_addr = move |page_num: u64| base_addr + page_num * PAGE_SIZE as u64;
 
-        if page_data.len() != PAGE_SIZE {
-            return Err(anyhow!(
-                "Invalid page size: expected {}, got {}",
-                PAGE_SIZE,
-                page_data.len()
-            ));
-        }
+        tokio::task::spawn_blocking(move || {
+            let peer = authenticated_peer(1);
+            peer.set_directory_epoch(Arc::new(AtomicU64::new(u64::MAX)));
 
-        unsafe {
-            self.uffd
-                .copy(
-                    page_data.as_ptr() as *const libc::c_void,
-                    addr as *mut libc::c_void,
-                    PAGE_SIZE,
-                    true,
-                )
-                .context("Failed to copy remote page")?;
-        }
+            pager
+                .transport()
+                .write()
+                .connect_peer(1, loopback(peer.local_endpoint()))
+                .unwrap();
+            // Node 1's copy of page 3
+            pager
+                .transport()
+                .read()
+                .send_page(page_addr(3), &[0x77; PAGE_SIZE], 1)
+                .unwrap();
 
-        Ok(())
-    }
+            fault(&pager, page_addr(3)).unwrap();
+            fault(&pager, page_addr(4)).unwrap();
+            assert_eq!(pager.get_stats().local_faults, 2);
 
-    /// Get statistics for observability
-    pub fn get_stats(&self) -> PagerStats {
-        let stats = self.stats.read();
-        PagerStats {
-            local_faults: stats.local_faults,
-            remote_faults: stats.remote_faults,
-            fault_service_time_us: stats.fault_service_time_us.clone(),
-        }
-    }
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while pager.directory().get_owner(4) != PageOwner::Local
+                || pager.get_stats().speculation_aborts == 0
+            {
+                assert!(Instant::now() < deadline, "claims were never checked");
+                pager.process_control_messages();
+                thread::sleep(Duration::from_millis(10));
+            }
 
-    /// Get page directory for testing
-    pub fn directory(&self) -> &Arc<PageDirectory> {
-        &self.directory
-    }
+            assert_eq!(pager.get_stats().speculation_aborts, 1);
+            assert_eq!(pager.directory().get_owner(3), PageOwner::Remote(1));
+            let page = unsafe { std::slice::from_raw_parts(page_addr(3) as *const u8, PAGE_SIZE) };
+            assert!(page.iter().all(|&b| b == 0x77));
 
-    /// Get transport manager for testing
-    pub fn transport(&self) -> Arc<RwLock<TransportManager>> {
-        Arc::clone(&self.transport)
+            drop(pager);
+            drop(peer);
+        })
+        .await
+        .unwrap();
+        unsafe { libc::munmap(base, len) };
     }
-}
 
-/// Start pager in background thread
-///
-/// # Arguments
-/// * `base` - Base address of guest memory region
-/// * `len` - Length of memory region
-/// * `node_id` - Local node identifier
-/// * `total_nodes` - Total nodes in cluster
-/// * `coordinator_url` - Coordinator URL (e.g., "http://localhost:8000")
-pub fn start_pager(
-    base: *mut u8,
-    len: usize,
-    node_id: u32,
-    total_nodes: u32,
-    coordinator_url: &str,
-) -> Result<JoinHandle<Result<()>>> {
-    info!(
-        "Starting pager: base={:p}, len=0x{:x}, node={}/{}",
-        base, len, node_id, total_nodes
-    );
-    info!("Coordinator: {}", coordinator_url);
+    #[test]
+    fn test_peer_with_wrong_key_rejected() {
+        let coordinator = NodeIdentity::generate();
+        let impostor = NodeIdentity::generate();
+        let expiry = 4_000_000_000;
 
-    let pager = Pager::new(base, len, node_id, total_nodes, coordinator_url)?;
+        // Transport for `node_id` with a token signed by `issuer`, and the
+        // coordinator's listing of it
+        let node = |issuer: &NodeIdentity, node_id: u32| {
+            let identity = NodeIdentity::generate();
+            let public_key = identity.public_key_hex();
+            let token = AuthToken::issue(issuer, node_id, &public_key, expiry);
+            let transport = TransportManager::new(node_id).unwrap();
+            transport.set_authenticator(Arc::new(PeerAuth::new(
+                node_id,
+                identity,
+                token,
+                coordinator.verifying_key(),
+            )));
+            let endpoints = serde_json::from_value::<EndpointsResponse>(serde_json::json!({
+                "endpoints": {
+                    node_id.to_string(): CoordinatorEndpoint::from(&loopback(transport.local_endpoint())),
+                },
+                "public_keys": { node_id.to_string(): public_key },
+            }))
+            .unwrap();
+            (transport, endpoints)
+        };
+        let (mut transport, _) = node(&coordinator, 0);
 
-    let handle = thread::Builder::new()
-        .name(format!("pager-node{}", node_id))
-        .spawn(move || pager.handle_faults())
-        .context("Failed to spawn pager thread")?;
+        // Token signed by something other than the coordinator
+        let (_forged, endpoints) = node(&impostor, 1);
+        Pager::connect_peers(0, &mut transport, endpoints).unwrap();
+        assert!(transport.peers().is_empty());
 
-    Ok(handle)
-}
+        // Registered without an identity
+        let (_anonymous, mut endpoints) = node(&coordinator, 2);
+        endpoints.public_keys.clear();
+        Pager::connect_peers(0, &mut transport, endpoints).unwrap();
+        assert!(transport.peers().is_empty());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let (_genuine, endpoints) = node(&coordinator, 3);
+        Pager::connect_peers(0, &mut transport, endpoints).unwrap();
+        assert_eq!(transport.peers().len(), 1);
+    }
 
     #[test]
-    fn test_page_directory_new() {
-        let dir = PageDirectory::new(0);
-        assert_eq!(dir.local_node, 0);
-        assert_eq!(dir.page_count(), 0);
+    fn test_coordinator_endpoint_tcp() {
+        let endpoint = CoordinatorEndpointBuilder::tcp("10.0.0.1", 50051).unwrap();
+        assert_eq!(
+            endpoint.to_transport_endpoint().unwrap(),
+            TransportEndpoint::tcp("10.0.0.1:50051".parse().unwrap())
+        );
+        assert!(CoordinatorEndpointBuilder::tcp("::1", 50051).is_ok());
+
+        assert!(CoordinatorEndpointBuilder::tcp("", 50051).is_err());
+        assert!(CoordinatorEndpointBuilder::tcp("node-1.local", 50051).is_err());
+        assert!(CoordinatorEndpointBuilder::tcp("10.0.0.1", 0).is_err());
+
+        let mut missing_port = endpoint.clone();
+        missing_port.tcp_port = None;
+        assert!(missing_port.validate().is_err());
     }
 
     #[test]
-    fn test_page_directory_claim() {
-        let dir = PageDirectory::new(0);
+    fn test_coordinator_endpoint_rdma() {
+        let gid = "0xfe800000000000000002c90300a1b2c3";
+        let endpoint = CoordinatorEndpointBuilder::rdma(0x1234, 7, gid, 42).unwrap();
+        assert_eq!(endpoint.rdma_gid_bytes().unwrap()[..2], [0xfe, 0x80]);
+        assert!(CoordinatorEndpointBuilder::rdma(0x1234, 7, &gid[2..], 42).is_ok());
 
-        // Initially unknown
-        assert_eq!(dir.get_owner(0), PageOwner::Unknown);
+        assert!(CoordinatorEndpointBuilder::rdma(0, 7, gid, 42).is_err());
+        assert!(CoordinatorEndpointBuilder::rdma(0x1234, 7, "0xfe80", 42).is_err());
+        assert!(CoordinatorEndpointBuilder::rdma(0x1234, 7, "not hex", 42).is_err());
+        assert!(CoordinatorEndpointBuilder::rdma(0x1234, 7, gid, 1 << 24).is_err());
 
-        // Claim page
-        dir.claim_page(0);
-        assert_eq!(dir.get_owner(0), PageOwner::Local);
-        assert_eq!(dir.page_count(), 1);
+        let mut missing_lid = endpoint.clone();
+        missing_lid.rdma_lid = None;
+        assert!(missing_lid.validate().is_err());
     }
 
     #[test]
-    fn test_page_directory_set_owner() {
-        let dir = PageDirectory::new(0);
+    fn test_coordinator_endpoint_quic() {
+        let endpoint = CoordinatorEndpointBuilder::quic("10.0.0.1", 50051, &[0x30, 0x82]).unwrap();
+        assert_eq!(endpoint.quic_cert.as_deref(), Some("3082"));
+        assert!(CoordinatorEndpointBuilder::quic("10.0.0.1", 50051, &[]).is_err());
+        assert!(CoordinatorEndpointBuilder::quic("10.0.0.1", 0, &[0x30]).is_err());
 
-        // Set remote owner
-        dir.set_owner(0, PageOwner::Remote(1));
-        assert_eq!(dir.get_owner(0), PageOwner::Remote(1));
+        // Registrations from before QUIC have no certificate field
+        let json = r#"{"transport_type":"tcp","tcp_addr":"10.0.0.1","tcp_port":50051,
+            "rdma_qpn":null,"rdma_lid":null,"rdma_gid":null,"rdma_psn":null}"#;
+        let old: CoordinatorEndpoint = serde_json::from_str(json).unwrap();
+        assert_eq!(old.quic_cert, None);
+        old.validate().unwrap();
+    }
 
-        // Change to local
-        dir.set_owner(0, PageOwner::Local);
-        assert_eq!(dir.get_owner(0), PageOwner::Local);
+    #[test]
+    fn test_coordinator_endpoint_from_transport() {
+        let endpoint = TransportEndpoint::tcp("[fd00::2]:50052".parse().unwrap());
+        let coordinator = CoordinatorEndpoint::from(&endpoint);
+        coordinator.validate().unwrap();
+        assert_eq!(coordinator.tcp_addr.as_deref(), Some("fd00::2"));
+        assert_eq!(coordinator.to_transport_endpoint().unwrap(), endpoint);
+
+        let mut unknown = coordinator;
+        unknown.transport_type = "carrier-pigeon".to_string();
+        assert!(unknown.validate().is_err());
     }
 
     #[test]
-    fn test_page_directory_multiple_pages() {
-        let dir = PageDirectory::new(0);
+    fn test_set_realtime_priority() {
+        assert!(Pager::set_realtime_priority(0).is_err());
+        assert!(Pager::set_realtime_priority(100).is_err());
 
-        dir.claim_page(0);
-        dir.set_owner(1, PageOwner::Remote(1));
-        dir.set_owner(2, PageOwner::Remote(2));
+        // Own thread so the test harness thread keeps its scheduling
+        let (policy, priority) = thread::spawn(|| {
+            Pager::set_realtime_priority(50).unwrap();
 
-        assert_eq!(dir.get_owner(0), PageOwner::Local);
-        assert_eq!(dir.get_owner(1), PageOwner::Remote(1));
-        assert_eq!(dir.get_owner(2), PageOwner::Remote(2));
-        assert_eq!(dir.get_owner(3), PageOwner::Unknown);
-        assert_eq!(dir.page_count(), 3);
+            let mut policy = 0;
+            let mut param = libc::sched_param { sched_priority: 0 };
+            let ret = unsafe {
+                libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param)
+            };
+            assert_eq!(ret, 0);
+            (policy, param.sched_priority)
+        })
+        .join()
+        .unwrap();
+
+        // Without CAP_SYS_NICE the thread keeps the default policy
+        if policy == libc::SCHED_FIFO {
+            assert_eq!(priority, 50);
+        } else {
+            assert_eq!(policy, libc::SCHED_OTHER);
+            eprintln!("SCHED_FIFO not permitted, skipped priority check");
+        }
     }
 
     #[test]
@@ -652,11 +5818,45 @@ mod tests {
         assert_eq!(stats.p99_latency_us(), Some(500));
     }
 
+    #[test]
+    fn test_pager_stats_percentile_latency() {
+        let mut stats = PagerStats::default();
+        stats.fault_service_time_us = (1..=1000).rev().collect();
+
+        assert_eq!(stats.p95_latency_us(), Some(951));
+        assert_eq!(stats.p999_latency_us(), Some(1000));
+        assert_eq!(stats.percentile_latency_us(0.5), Some(501));
+        assert_eq!(stats.percentile_latency_us(0.0), Some(1));
+        assert_eq!(stats.percentile_latency_us(1.0), Some(1000));
+        assert_eq!(stats.percentile_latency_us(-1.0), Some(1));
+
+        stats.fault_service_time_us = vec![42];
+        assert_eq!(stats.p95_latency_us(), Some(42));
+        assert_eq!(stats.p999_latency_us(), Some(42));
+        assert_eq!(stats.percentile_latency_us(0.0), Some(42));
+    }
+
     #[test]
     fn test_pager_stats_empty_latency() {
         let stats = PagerStats::default();
         assert_eq!(stats.median_latency_us(), None);
+        assert_eq!(stats.p95_latency_us(), None);
         assert_eq!(stats.p99_latency_us(), None);
+        assert_eq!(stats.p999_latency_us(), None);
+        assert_eq!(stats.percentile_latency_us(0.5), None);
+    }
+
+    #[test]
+    fn test_pager_stats_sampled_fault_counts() {
+        let mut stats = PagerStats {
+            fault_service_sample: SamplingHistogram::new(4),
+            ..Default::default()
+        };
+        for latency in 0..40 {
+            stats.fault_service_sample.record(latency);
+        }
+        assert_eq!(stats.actual_fault_count(), 40);
+        assert_eq!(stats.sampled_fault_count(), 10);
     }
 
     #[test]
@@ -668,6 +5868,30 @@ mod tests {
         assert_ne!(PageOwner::Unknown, PageOwner::Local);
     }
 
+    #[test]
+    fn test_page_owner_json() {
+        assert_eq!(
+            serde_json::to_string(&PageOwner::Local).unwrap(),
+            "\"local\""
+        );
+        assert_eq!(
+            serde_json::to_string(&PageOwner::Remote(3)).unwrap(),
+            "{\"remote\":3}"
+        );
+        assert_eq!(
+            serde_json::to_string(&PageOwner::Unknown).unwrap(),
+            "\"unknown\""
+        );
+    }
+
+    #[test]
+    fn test_shutdown_signal() {
+        let signal = ShutdownSignal::default();
+        assert!(!signal.is_triggered());
+        signal.trigger();
+        assert!(signal.is_triggered());
+    }
+
     #[test]
     fn test_page_size_constant() {
         assert_eq!(PAGE_SIZE, 4096);
@@ -676,6 +5900,7 @@ mod tests {
     #[test]
     fn test_page_owner_clone() {
         let owner = PageOwner::Remote(5);
+        #[allow(clippy::clone_on_copy)]
         let cloned = owner.clone();
         assert_eq!(owner, cloned);
     }
@@ -692,4 +5917,48 @@ mod tests {
         assert_eq!(cloned.remote_faults, 5);
         assert_eq!(cloned.fault_service_time_us.len(), 2);
     }
+
+    #[test]
+    fn test_pager_stats_hdr_log_round_trip() {
+        use base64::engine::general_purpose::STANDARD as BASE64;
+        use base64::Engine;
+        use hdrhistogram::serialization::interval_log::{IntervalLogIterator, LogEntry};
+        use hdrhistogram::serialization::Deserializer;
+
+        let mut stats = PagerStats::default();
+        for latency_us in 1..=1000 {
+            stats.local_resolved_latency_us.record(latency_us);
+            stats.remote_fetch_latency_us.record(latency_us * 20);
+        }
+        stats.first_touch_latency_us.record(42);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("faults.hlog");
+        stats.export_hdr_log(&path, 1_700_000_000).unwrap();
+        let log = std::fs::read(&path).unwrap();
+
+        let mut p99s = HashMap::new();
+        let mut base_time = None;
+        for entry in IntervalLogIterator::new(&log) {
+            match entry.unwrap() {
+                LogEntry::BaseTime(time) => base_time = Some(time),
+                LogEntry::Interval(interval) => {
+                    let encoded = BASE64.decode(interval.encoded_histogram()).unwrap();
+                    let histogram: hdrhistogram::Histogram<u64> = Deserializer::new()
+                        .deserialize(&mut encoded.as_slice())
+                        .unwrap();
+                    p99s.insert(
+                        interval.tag().unwrap().as_str().to_string(),
+                        histogram.value_at_quantile(0.99),
+                    );
+                }
+                LogEntry::StartTime(_) => {}
+            }
+        }
+        assert_eq!(base_time, Some(Duration::from_secs(1_700_000_000)));
+        assert_eq!(p99s.len(), 3);
+        assert_eq!(Some(p99s["local"]), stats.local_resolved_p99_us());
+        assert_eq!(Some(p99s["remote"]), stats.remote_fetch_p99_us());
+        assert_eq!(Some(p99s["first_touch"]), stats.first_touch_p99_us());
+    }
 }
@@ -0,0 +1,704 @@
+//! Node load reporting for coordinator placement decisions
+//!
+//! The coordinator places new pages on lightly loaded nodes. Every
+//! `LOAD_REPORT_INTERVAL` a reporting thread samples how busy this node is and
+//! sends a `LoadMetrics` to `PUT /nodes/{id}/load`, and every
+//! `BALLOON_STATS_INTERVAL` a `BalloonStats` to `PUT /nodes/{id}/balloon_stats`,
+//! from which the coordinator decides when the cluster needs another node.
+//!
+//! Rates are averaged over the time since the previous sample, so the first
+//! sample after startup reports zero for them. Host figures come from
+//! `/proc/stat` (CPU) and `/proc/net/dev` plus `/sys/class/net/*/speed`
+//! (network).
+//!
+//! The same pager counters can be scraped from `GET /metrics` on
+//! `PagerConfig::metrics_port` (see `serve_metrics`). Nodes a Prometheus
+//! server cannot scrape (e.g. behind NAT) can instead push them to a
+//! Pushgateway every `push_interval`; see `PushGatewayConfig`.
+
+use crate::{PagerStats, ShutdownSignal};
+use anyhow::{anyhow, Context, Result};
+use hdrhistogram::Histogram;
+use log::{debug, info, warn};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt::Write;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the pager reports load to the coordinator
+pub const LOAD_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the pager reports `BalloonStats` to the coordinator
+pub const BALLOON_STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pushgateway requests give up after this long
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest latency a `LatencyHistogram` tells apart; longer ones count as this
+const MAX_LATENCY_US: u64 = 60_000_000;
+
+/// Upper bounds of the Prometheus latency buckets, in µs
+const LATENCY_BUCKETS_US: [u64; 14] = [
+    5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 100_000, 1_000_000,
+];
+
+/// Fault service times of one kind of fault, to 3 significant digits
+///
+/// Serializes as a summary (count, mean, p50, p99, max) rather than the
+/// buckets.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    histogram: Histogram<u64>,
+    /// Exact, unlike the histogram's recorded values
+    sum_us: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            histogram: Histogram::new_with_max(MAX_LATENCY_US, 3).expect("Valid histogram bounds"),
+            sum_us: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency_us: u64) {
+        self.histogram.saturating_record(latency_us);
+        self.sum_us += latency_us;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn sum_us(&self) -> u64 {
+        self.sum_us
+    }
+
+    /// Latency at `quantile` (0.0–1.0), or None if nothing was recorded
+    pub fn quantile_us(&self, quantile: f64) -> Option<u64> {
+        (!self.histogram.is_empty()).then(|| self.histogram.value_at_quantile(quantile))
+    }
+
+    pub fn p99_us(&self) -> Option<u64> {
+        self.quantile_us(0.99)
+    }
+
+    pub fn histogram(&self) -> &Histogram<u64> {
+        &self.histogram
+    }
+
+    /// Samples of at most `latency_us`, within the histogram's precision
+    pub fn count_at_most(&self, latency_us: u64) -> u64 {
+        self.histogram.count_between(0, latency_us)
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut summary = serializer.serialize_struct("LatencyHistogram", 5)?;
+        summary.serialize_field("count", &self.count())?;
+        summary.serialize_field("mean_us", &self.histogram.mean())?;
+        summary.serialize_field("p50_us", &self.quantile_us(0.5))?;
+        summary.serialize_field("p99_us", &self.p99_us())?;
+        summary.serialize_field("max_us", &self.histogram.max())?;
+        summary.end()
+    }
+}
+
+/// Prometheus Pushgateway to send pager metrics to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushGatewayConfig {
+    /// Gateway base URL (e.g. "http://pushgateway:9091")
+    pub url: String,
+    /// `job` label of the pushed metrics
+    pub job_name: String,
+    /// Time between pushes
+    pub push_interval: Duration,
+}
+
+/// Snapshot of how busy a node is serving pages
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoadMetrics {
+    /// Pages this node owns in the page directory
+    pub pages_owned: u64,
+    /// Faults (local and remote) handled per second
+    pub fault_rate_per_sec: f64,
+    /// Share of total link speed used by traffic on non-loopback interfaces
+    pub transport_bw_utilized_pct: f64,
+    /// Host CPU busy time (all CPUs)
+    pub cpu_utilization_pct: f64,
+}
+
+/// Where the guest's pages are, as one node sees it
+///
+/// `local_pages`, `remote_pages` and `unknown_pages` add up to
+/// `total_pages`. Compressed pages are among the local ones; dead-lettered
+/// pages are among the unknown ones until the guest touches them again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonStats {
+    /// Pages in the pager's region
+    pub total_pages: u64,
+    pub local_pages: u64,
+    pub remote_pages: u64,
+    /// Pages no node has claimed
+    pub unknown_pages: u64,
+    /// Local pages kept compressed (see `compressor`)
+    pub compressed_pages: u64,
+    /// Distinct pages in the dead-letter queue (see `dlq`)
+    pub dlq_pages: u64,
+}
+
+impl BalloonStats {
+    /// Share of the region held on this node, which the coordinator scales
+    /// out on
+    pub fn fill_ratio(&self) -> f64 {
+        if self.total_pages == 0 {
+            0.0
+        } else {
+            self.local_pages as f64 / self.total_pages as f64
+        }
+    }
+}
+
+/// Aggregate CPU time from the first line of `/proc/stat`, in jiffies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// Counters from the previous sample
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    faults: u64,
+    cpu: Option<CpuTimes>,
+    net_bytes: Option<u64>,
+}
+
+/// Turns cumulative counters into the rates in `LoadMetrics`
+#[derive(Debug, Default)]
+pub struct LoadSampler {
+    previous: Option<Sample>,
+}
+
+impl LoadSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load since the previous call, from `stats` and the host counters
+    pub fn sample_current(&mut self, stats: &PagerStats, pages_owned: u64) -> LoadMetrics {
+        let current = Sample {
+            at: Instant::now(),
+            faults: stats.local_faults + stats.remote_faults,
+            cpu: fs::read_to_string("/proc/stat")
+                .ok()
+                .and_then(|stat| parse_proc_stat(&stat)),
+            net_bytes: fs::read_to_string("/proc/net/dev")
+                .ok()
+                .map(|dev| parse_net_dev(&dev)),
+        };
+        let previous = self.previous.replace(current);
+
+        let mut metrics = LoadMetrics {
+            pages_owned,
+            fault_rate_per_sec: 0.0,
+            transport_bw_utilized_pct: 0.0,
+            cpu_utilization_pct: 0.0,
+        };
+        let Some(previous) = previous else {
+            return metrics;
+        };
+
+        let secs = current.at.duration_since(previous.at).as_secs_f64();
+        if secs > 0.0 {
+            metrics.fault_rate_per_sec =
+                current.faults.saturating_sub(previous.faults) as f64 / secs;
+        }
+        if let (Some(now), Some(before)) = (current.cpu, previous.cpu) {
+            metrics.cpu_utilization_pct = cpu_utilization_pct(before, now);
+        }
+        if let (Some(now), Some(before), Some(link_bps)) =
+            (current.net_bytes, previous.net_bytes, link_speed_bps())
+        {
+            let bits = now.saturating_sub(before) as f64 * 8.0;
+            if secs > 0.0 {
+                metrics.transport_bw_utilized_pct = (bits / secs / link_bps * 100.0).min(100.0);
+            }
+        }
+        metrics
+    }
+}
+
+/// Pager counters in the Prometheus text exposition format
+///
+/// Every sample carries a `node` label, so metrics from different nodes stay
+/// apart when aggregated under one job.
+pub fn prometheus_text(node_id: u32, stats: &PagerStats) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        // Writing to a String cannot fail
+        let _ = write!(
+            text,
+            "# HELP ssi_hv_pager_{name} {help}\n\
+             # TYPE ssi_hv_pager_{name} {kind}\n\
+             ssi_hv_pager_{name}{{node=\"{node_id}\"}} {value}\n"
+        );
+    };
+
+    metric(
+        "local_faults_total",
+        "counter",
+        "Faults on pages owned by this node",
+        stats.local_faults as f64,
+    );
+    metric(
+        "remote_faults_total",
+        "counter",
+        "Faults on pages owned by another node",
+        stats.remote_faults as f64,
+    );
+    metric(
+        "cache_hits_total",
+        "counter",
+        "Remote faults served from the page cache",
+        stats.cache_hits as f64,
+    );
+    metric(
+        "dedup_hits_total",
+        "counter",
+        "Remote faults served from a local copy of identical content",
+        stats.dedup_hits as f64,
+    );
+    metric(
+        "deduplicated_faults_total",
+        "counter",
+        "Remote faults that waited for another fetch of the same page",
+        stats.deduplicated_faults as f64,
+    );
+    metric(
+        "pattern_changes_total",
+        "counter",
+        "Access pattern classification changes",
+        stats.pattern_changes as f64,
+    );
+    metric(
+        "prefetch_depth",
+        "gauge",
+        "Prefetch depth chosen by access pattern detection",
+        stats.prefetch_depth as f64,
+    );
+    metric(
+        "max_shard_occupancy",
+        "gauge",
+        "Entries in the fullest page directory shard",
+        stats.max_shard_occupancy as f64,
+    );
+    if let Some(median) = stats.median_latency_us() {
+        metric(
+            "fault_service_median_seconds",
+            "gauge",
+            "Median fault service time",
+            median as f64 / 1e6,
+        );
+    }
+    if let Some(p99) = stats.p99_latency_us() {
+        metric(
+            "fault_service_p99_seconds",
+            "gauge",
+            "99th percentile fault service time",
+            p99 as f64 / 1e6,
+        );
+    }
+
+    let _ = write!(
+        text,
+        "# HELP ssi_hv_pager_fault_latency_us Fault service time by kind of fault\n\
+         # TYPE ssi_hv_pager_fault_latency_us histogram\n"
+    );
+    for (fault_type, histogram) in stats.fault_latencies() {
+        let labels = format!("node=\"{node_id}\",fault_type=\"{fault_type}\"");
+        for le in LATENCY_BUCKETS_US {
+            let _ = writeln!(
+                text,
+                "ssi_hv_pager_fault_latency_us_bucket{{{labels},le=\"{le}\"}} {}",
+                histogram.count_at_most(le)
+            );
+        }
+        let _ = write!(
+            text,
+            "ssi_hv_pager_fault_latency_us_bucket{{{labels},le=\"+Inf\"}} {count}\n\
+             ssi_hv_pager_fault_latency_us_sum{{{labels}}} {}\n\
+             ssi_hv_pager_fault_latency_us_count{{{labels}}} {count}\n",
+            histogram.sum_us(),
+            count = histogram.count()
+        );
+    }
+    text
+}
+
+/// Replace this node's metrics on the gateway with `stats`
+///
+/// Sends `PUT {url}/metrics/job/{job_name}/instance/{node_id}`.
+pub async fn push_to_gateway(
+    config: &PushGatewayConfig,
+    node_id: u32,
+    stats: &PagerStats,
+) -> Result<()> {
+    let url = format!(
+        "{}/metrics/job/{}/instance/{}",
+        config.url.trim_end_matches('/'),
+        config.job_name,
+        node_id
+    );
+    let response = reqwest::Client::new()
+        .put(&url)
+        .timeout(PUSH_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(prometheus_text(node_id, stats))
+        .send()
+        .await
+        .with_context(|| format!("Failed to push metrics to {}", url))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Pushgateway rejected metrics: {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Push `snapshot()` to the gateway every `push_interval` in a background
+/// thread, until `shutdown` is triggered
+///
+/// Failed pushes are logged and retried at the next interval.
+pub fn start_pushing(
+    config: PushGatewayConfig,
+    node_id: u32,
+    snapshot: impl Fn() -> PagerStats + Send + 'static,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<JoinHandle<()>> {
+    if config.push_interval.is_zero() {
+        return Err(anyhow!("Pushgateway push interval must be non-zero"));
+    }
+    info!(
+        "Pushing metrics to {} every {:?} (job={})",
+        config.url, config.push_interval, config.job_name
+    );
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create metrics push runtime")?;
+
+    thread::Builder::new()
+        .name("pager-metrics-push".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                loop {
+                    tokio::select! {
+                        _ = shutdown.wait() => break,
+                        _ = tokio::time::sleep(config.push_interval) => {}
+                    }
+                    match push_to_gateway(&config, node_id, &snapshot()).await {
+                        Ok(()) => debug!("Pushed metrics to {}", config.url),
+                        Err(e) => warn!("Metrics push failed: {:#}", e),
+                    }
+                }
+                info!("Metrics push stopped");
+            });
+        })
+        .context("Failed to spawn metrics push thread")
+}
+
+/// Serve `snapshot()` to Prometheus scrapes of `GET /metrics` on `addr` in
+/// a background thread, until `shutdown` is triggered
+///
+/// Returns the server thread and the address it listens on.
+pub fn serve_metrics(
+    addr: SocketAddr,
+    node_id: u32,
+    snapshot: impl Fn() -> PagerStats + Send + Sync + 'static,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<(JoinHandle<()>, SocketAddr)> {
+    let listener = std::net::TcpListener::bind(addr)
+        .with_context(|| format!("Failed to bind metrics endpoint on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let local_addr = listener.local_addr()?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create metrics server runtime")?;
+
+    info!("Serving metrics on http://{}/metrics", local_addr);
+
+    let snapshot = Arc::new(snapshot);
+    let app = axum::Router::new().route(
+        "/metrics",
+        axum::routing::get(move || {
+            let text = prometheus_text(node_id, &snapshot());
+            async move {
+                (
+                    [(
+                        axum::http::header::CONTENT_TYPE,
+                        "text/plain; version=0.0.4",
+                    )],
+                    text,
+                )
+            }
+        }),
+    );
+    let server = thread::Builder::new()
+        .name("pager-metrics".to_string())
+        .spawn(move || {
+            runtime.block_on(async move {
+                let listener = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        warn!("Failed to register metrics listener: {}", e);
+                        return;
+                    }
+                };
+                if let Err(e) = axum::serve(listener, app)
+                    .with_graceful_shutdown(async move { shutdown.wait().await })
+                    .await
+                {
+                    warn!("Metrics server error: {}", e);
+                }
+                info!("Metrics server stopped");
+            });
+        })
+        .context("Failed to spawn metrics server thread")?;
+    Ok((server, local_addr))
+}
+
+/// Busy and total jiffies from the aggregate `cpu` line of `/proc/stat`
+///
+/// Idle and iowait count as not busy.
+fn parse_proc_stat(stat: &str) -> Option<CpuTimes> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    if fields.len() < 4 {
+        return None;
+    }
+
+    // user nice system idle iowait irq softirq steal (guest time is already
+    // included in user)
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+    Some(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+fn cpu_utilization_pct(before: CpuTimes, now: CpuTimes) -> f64 {
+    let total = now.total.saturating_sub(before.total);
+    if total == 0 {
+        return 0.0;
+    }
+    now.busy.saturating_sub(before.busy) as f64 / total as f64 * 100.0
+}
+
+/// Bytes received plus sent on all non-loopback interfaces in `/proc/net/dev`
+fn parse_net_dev(dev: &str) -> u64 {
+    dev.lines()
+        .skip(2) // Two header lines
+        .filter_map(|line| {
+            let (iface, counters) = line.split_once(':')?;
+            if iface.trim() == "lo" {
+                return None;
+            }
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .filter_map(|c| c.parse().ok())
+                .collect();
+            // 8 receive counters, then 8 transmit counters; bytes come first
+            Some(counters.first()? + counters.get(8)?)
+        })
+        .sum()
+}
+
+/// Combined speed of non-loopback interfaces that report one, in bits/s
+fn link_speed_bps() -> Option<f64> {
+    let mbps: u64 = fs::read_dir("/sys/class/net")
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.file_name() != "lo")
+        .filter_map(|entry| fs::read_to_string(entry.path().join("speed")).ok())
+        // Virtual interfaces report -1
+        .filter_map(|speed| speed.trim().parse::<i64>().ok())
+        .filter(|&speed| speed > 0)
+        .map(|speed| speed as u64)
+        .sum();
+    (mbps > 0).then_some(mbps as f64 * 1e6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_STAT: &str = "\
+cpu  100 0 50 800 50 0 0 0 0 0
+cpu0 50 0 25 400 25 0 0 0 0 0
+intr 12345
+";
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 9999 10 0 0 0 0 0 0 9999 10 0 0 0 0 0 0
+  eth0: 1000 10 0 0 0 0 0 0 500 5 0 0 0 0 0 0
+  eth1: 200 2 0 0 0 0 0 0 300 3 0 0 0 0 0 0
+";
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let cpu = parse_proc_stat(PROC_STAT).unwrap();
+        assert_eq!(
+            cpu,
+            CpuTimes {
+                busy: 150,
+                total: 1000
+            }
+        );
+
+        let later = CpuTimes {
+            busy: 250,
+            total: 1200,
+        };
+        assert_eq!(cpu_utilization_pct(cpu, later), 50.0);
+        assert!(parse_proc_stat("intr 1\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_net_dev_skips_loopback() {
+        assert_eq!(parse_net_dev(NET_DEV), 2000);
+    }
+
+    #[tokio::test]
+    async fn test_push_to_gateway() {
+        let mut server = mockito::Server::new_async().await;
+        let stats = PagerStats {
+            local_faults: 42,
+            remote_faults: 7,
+            cache_hits: 3,
+            fault_service_time_us: vec![100, 200, 300],
+            ..Default::default()
+        };
+
+        let mock = server
+            .mock("PUT", "/metrics/job/ssi-hv/instance/3")
+            .match_header("content-type", "text/plain; version=0.0.4")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::Regex(
+                    "(?m)^# TYPE ssi_hv_pager_local_faults_total counter$".to_string(),
+                ),
+                mockito::Matcher::Regex(
+                    r#"(?m)^ssi_hv_pager_local_faults_total\{node="3"\} 42$"#.to_string(),
+                ),
+                mockito::Matcher::Regex(
+                    r#"(?m)^ssi_hv_pager_remote_faults_total\{node="3"\} 7$"#.to_string(),
+                ),
+                mockito::Matcher::Regex(
+                    r#"(?m)^ssi_hv_pager_cache_hits_total\{node="3"\} 3$"#.to_string(),
+                ),
+                mockito::Matcher::Regex(
+                    "(?m)^# TYPE ssi_hv_pager_prefetch_depth gauge$".to_string(),
+                ),
+                mockito::Matcher::Regex(
+                    r#"(?m)^ssi_hv_pager_fault_service_median_seconds\{node="3"\} 0.0002$"#
+                        .to_string(),
+                ),
+            ]))
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let config = PushGatewayConfig {
+            // Trailing slash must not produce "//metrics"
+            url: format!("{}/", server.url()),
+            job_name: "ssi-hv".to_string(),
+            push_interval: Duration::from_secs(15),
+        };
+        push_to_gateway(&config, 3, &stats).await.unwrap();
+        mock.assert_async().await;
+
+        server
+            .mock("PUT", "/metrics/job/ssi-hv/instance/4")
+            .with_status(500)
+            .create_async()
+            .await;
+        assert!(push_to_gateway(&config, 4, &stats).await.is_err());
+    }
+
+    #[test]
+    fn test_fault_latency_buckets_labelled_by_fault_type() {
+        let mut stats = PagerStats::default();
+        for latency_us in [3, 40, 40, 2_000] {
+            stats.remote_fetch_latency_us.record(latency_us);
+        }
+        stats.first_touch_latency_us.record(7);
+
+        let text = prometheus_text(1, &stats);
+        let remote = r#"node="1",fault_type="remote_fetch""#;
+        for line in [
+            "# TYPE ssi_hv_pager_fault_latency_us histogram".to_string(),
+            format!(r#"ssi_hv_pager_fault_latency_us_bucket{{{remote},le="5"}} 1"#),
+            format!(r#"ssi_hv_pager_fault_latency_us_bucket{{{remote},le="50"}} 3"#),
+            format!(r#"ssi_hv_pager_fault_latency_us_bucket{{{remote},le="+Inf"}} 4"#),
+            format!("ssi_hv_pager_fault_latency_us_sum{{{remote}}} 2083"),
+            format!("ssi_hv_pager_fault_latency_us_count{{{remote}}} 4"),
+            r#"ssi_hv_pager_fault_latency_us_count{node="1",fault_type="first_touch"} 1"#
+                .to_string(),
+            r#"ssi_hv_pager_fault_latency_us_count{node="1",fault_type="local_resolved"} 0"#
+                .to_string(),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line}");
+        }
+        assert_eq!(stats.remote_fetch_p99_us(), Some(2_000));
+        assert_eq!(stats.local_resolved_p99_us(), None);
+    }
+
+    #[test]
+    fn test_start_pushing_rejects_zero_interval() {
+        let config = PushGatewayConfig {
+            url: "http://127.0.0.1:9091".to_string(),
+            job_name: "ssi-hv".to_string(),
+            push_interval: Duration::ZERO,
+        };
+        let shutdown = Arc::new(ShutdownSignal::default());
+        assert!(start_pushing(config, 0, PagerStats::default, shutdown).is_err());
+    }
+
+    #[test]
+    fn test_first_sample_has_no_rates() {
+        let mut sampler = LoadSampler::new();
+        let mut stats = PagerStats {
+            local_faults: 10,
+            ..Default::default()
+        };
+
+        let first = sampler.sample_current(&stats, 7);
+        assert_eq!(first.pages_owned, 7);
+        assert_eq!(first.fault_rate_per_sec, 0.0);
+
+        stats.remote_faults = 5;
+        std::thread::sleep(Duration::from_millis(10));
+        let second = sampler.sample_current(&stats, 7);
+        assert!(second.fault_rate_per_sec > 0.0);
+        assert!((0.0..=100.0).contains(&second.cpu_utilization_pct));
+    }
+}
@@ -0,0 +1,576 @@
+//! Cancelling page fetches when a migration is aborted
+//!
+//! A page fetch runs on the transport until the owner answers, which for a
+//! slow or departed peer can take a while. When the migration that caused the
+//! fetch is cancelled, `MigrationCoordinator::cancel` makes every fetch still
+//! in flight give up with `FetchCancelled`, so its fault can be looked at
+//! again. Fetches started afterwards are not affected.
+//!
+//! Before migrating, `MigrationCoordinator::estimate_downtime` gives a dry
+//! run: how long copying this node's pages to the target would pause the
+//! guest, from the bandwidth measured to the target.
+//!
+//! `MigrationCoordinator::batch_migrate` moves many pages at once, e.g. when
+//! the coordinator rebalances the cluster. Pages are grouped by where they
+//! are and where they go, pages held by a peer are fetched from it a group
+//! at a time, and several groups are moved in parallel. Its progress is
+//! published as `MigrationEvent`s, which `MigrationCoordinator::progress`
+//! streams to any number of subscribers, and `cancel` stops it from starting
+//! further groups.
+
+use crate::{PageDirectory, PageOwner, PAGE_SIZE};
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::Stream;
+use parking_lot::{Mutex, RwLock};
+use rdma_transport::TransportManager;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+/// Downtime per millisecond of transfer, allowing for pages dirtied again
+/// while they are copied
+pub const CONVERGENCE_FACTOR: f64 = 1.5;
+
+/// Predicted cost of migrating this node's pages to another node
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MigrationEstimate {
+    /// Pages owned by this node, all of which would be copied
+    pub dirty_pages: u64,
+    /// Time to copy them at the measured bandwidth
+    pub transfer_time_ms: u64,
+    /// `transfer_time_ms` scaled by `CONVERGENCE_FACTOR`
+    pub total_downtime_estimate_ms: u64,
+}
+
+/// Pages fetched from a peer with one `fetch_pages` call during a batch
+/// migration
+const MIGRATION_FETCH_BATCH: usize = 64;
+
+/// Progress events kept for subscribers that fall behind
+const MIGRATION_PROGRESS_CAPACITY: usize = 256;
+
+/// Progress of a batch migration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationEvent {
+    /// Pages moved or given up on
+    pub pages_completed: u64,
+    pub pages_total: u64,
+    /// Contents of the pages sent so far
+    pub bytes_transferred: u64,
+    pub elapsed_ms: u64,
+    /// Time left at the rate so far; None until a page is completed
+    pub eta_ms: Option<u64>,
+}
+
+impl MigrationEvent {
+    /// Whether this is the last event of its migration
+    pub fn is_final(&self) -> bool {
+        self.pages_completed >= self.pages_total
+    }
+}
+
+/// Publishes the `MigrationEvent`s of batch migrations
+///
+/// A batch migration publishes an event when it starts, each time it has
+/// moved a group of pages, and a final one once every page is completed,
+/// including after `MigrationCoordinator::cancel`.
+#[derive(Clone)]
+pub struct MigrationProgressStream {
+    sender: broadcast::Sender<MigrationEvent>,
+}
+
+impl Default for MigrationProgressStream {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(MIGRATION_PROGRESS_CAPACITY).0,
+        }
+    }
+}
+
+impl MigrationProgressStream {
+    /// Events of the running batch migration, or the next one to start,
+    /// ending with its final event
+    ///
+    /// A subscriber that falls more than `MIGRATION_PROGRESS_CAPACITY`
+    /// events behind skips the ones it missed.
+    pub fn subscribe(&self) -> impl Stream<Item = MigrationEvent> + Send + 'static {
+        futures_util::stream::unfold(Some(self.sender.subscribe()), |receiver| async move {
+            let mut receiver = receiver?;
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, (!event.is_final()).then_some(receiver))),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    fn publish(&self, event: MigrationEvent) {
+        // No subscribers is fine
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Running totals behind a batch migration's `MigrationEvent`s
+struct MigrationProgress {
+    started: Instant,
+    pages_total: u64,
+    pages_completed: u64,
+    bytes_transferred: u64,
+}
+
+impl MigrationProgress {
+    fn event(&self) -> MigrationEvent {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let pages_left = self.pages_total - self.pages_completed;
+        MigrationEvent {
+            pages_completed: self.pages_completed,
+            pages_total: self.pages_total,
+            bytes_transferred: self.bytes_transferred,
+            elapsed_ms,
+            eta_ms: (self.pages_completed > 0)
+                .then(|| elapsed_ms * pages_left / self.pages_completed),
+        }
+    }
+}
+
+/// Outcome of a batch migration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchMigrationResult {
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Page number and reason of each failed migration
+    pub errors: Vec<(u64, String)>,
+}
+
+/// This node's view of the pages a batch migration moves
+pub trait LocalPages: Sync {
+    /// Address of page 0; pages are sent and fetched at their address
+    fn base(&self) -> u64;
+
+    /// Pages in the registered region
+    fn page_count(&self) -> u64;
+
+    /// Contents of a page this node owns
+    fn read_page(&self, page_num: u64) -> Result<Vec<u8>>;
+
+    /// A page this node owned now lives on another node
+    fn page_moved(&self, page_num: u64) -> Result<()>;
+}
+
+/// Where the pages of a batch migration come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PageSource {
+    Local,
+    Peer(u32),
+    /// Never touched; the target gets a zero page
+    Untouched,
+}
+
+/// Pages of a batch migration with the same source and target
+struct MigrationGroup {
+    source: PageSource,
+    target: u32,
+    pages: Vec<u64>,
+}
+
+/// Cancels the page fetches of an aborted migration, estimates new ones and
+/// moves batches of pages
+#[derive(Clone)]
+pub struct MigrationCoordinator {
+    /// Cancelled, and replaced, by `cancel`
+    fetches: Arc<Mutex<CancellationToken>>,
+    directory: Arc<PageDirectory>,
+    transport: Arc<RwLock<TransportManager>>,
+    /// Groups a batch migration moves at once
+    max_concurrent_migrations: usize,
+    progress: MigrationProgressStream,
+}
+
+impl MigrationCoordinator {
+    pub(crate) fn new(
+        directory: Arc<PageDirectory>,
+        transport: Arc<RwLock<TransportManager>>,
+        max_concurrent_migrations: usize,
+    ) -> Self {
+        Self {
+            fetches: Arc::default(),
+            directory,
+            transport,
+            max_concurrent_migrations,
+            progress: MigrationProgressStream::default(),
+        }
+    }
+
+    /// Progress of batch migrations
+    pub fn progress(&self) -> &MigrationProgressStream {
+        &self.progress
+    }
+
+    /// Token for a fetch about to start
+    pub(crate) fn token(&self) -> CancellationToken {
+        self.fetches.lock().clone()
+    }
+
+    /// Abandon every fetch in flight, and the groups of pages batch
+    /// migrations have yet to start
+    pub fn cancel(&self) {
+        let cancelled = std::mem::take(&mut *self.fetches.lock());
+        cancelled.cancel();
+    }
+
+    /// Dry run of migrating this node's pages to `target_node`
+    ///
+    /// Nothing is copied; the bandwidth to the target is measured with a
+    /// short probe (see `TransportManager::measure_bandwidth`).
+    pub fn estimate_downtime(&self, target_node: u32) -> Result<MigrationEstimate> {
+        if target_node == self.directory.local_node() {
+            bail!("Cannot migrate to the local node");
+        }
+        let bandwidth = self
+            .transport
+            .read()
+            .measure_bandwidth(target_node)
+            .with_context(|| format!("Failed to measure bandwidth to node {}", target_node))?;
+        if bandwidth == 0 {
+            bail!("No bandwidth to node {}", target_node);
+        }
+
+        let dirty_pages = self.directory.local_page_count() as u64;
+        let bytes = dirty_pages as u128 * PAGE_SIZE as u128;
+        let transfer_time_ms = (bytes * 1000).div_ceil(bandwidth as u128) as u64;
+        Ok(MigrationEstimate {
+            dirty_pages,
+            transfer_time_ms,
+            total_downtime_estimate_ms: (transfer_time_ms as f64 * CONVERGENCE_FACTOR).ceil()
+                as u64,
+        })
+    }
+
+    /// Move each `(page_num, target_node)` page to its target node
+    ///
+    /// Pages owned here are read from `local`, pages owned by a peer are
+    /// fetched from it, up to `MIGRATION_FETCH_BATCH` per call, and
+    /// untouched pages are zeros. The page is then sent to the target and
+    /// the directory updated. Pages already on their target count as moved;
+    /// speculatively claimed pages, pages outside the region and pages bound
+    /// for this node fail, as do the pages of groups not started before a
+    /// `cancel`. Errors only fail their own page; this only fails if a
+    /// worker thread cannot be started.
+    pub fn batch_migrate(
+        &self,
+        migrations: Vec<(u64, u32)>,
+        local: &dyn LocalPages,
+    ) -> Result<BatchMigrationResult> {
+        let cancelled = self.token();
+        let pages_total = migrations.len() as u64;
+        let mut result = BatchMigrationResult::default();
+        let mut groups: BTreeMap<(u32, PageSource), Vec<u64>> = BTreeMap::new();
+        for (page_num, target) in migrations {
+            let source = if target == self.directory.local_node() {
+                Err(anyhow!("Target node {} is the local node", target))
+            } else if page_num >= local.page_count() {
+                Err(anyhow!("Page outside registered region"))
+            } else {
+                match self.directory.get_owner(page_num) {
+                    PageOwner::Local | PageOwner::LocalHuge => Ok(PageSource::Local),
+                    PageOwner::Remote(owner) => Ok(PageSource::Peer(owner)),
+                    PageOwner::Unknown => Ok(PageSource::Untouched),
+                    PageOwner::Speculative(_) => Err(anyhow!("Page claim not yet confirmed")),
+                }
+            };
+            match source {
+                Ok(PageSource::Peer(owner)) if owner == target => result.succeeded += 1,
+                Ok(source) => groups.entry((target, source)).or_default().push(page_num),
+                Err(e) => result.record(page_num, Err(e)),
+            }
+        }
+
+        let groups: Vec<MigrationGroup> = groups
+            .into_iter()
+            .flat_map(|((target, source), pages)| {
+                pages
+                    .chunks(MIGRATION_FETCH_BATCH)
+                    .map(|pages| MigrationGroup {
+                        source,
+                        target,
+                        pages: pages.to_vec(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let progress = MigrationProgress {
+            started: Instant::now(),
+            pages_total,
+            pages_completed: result.succeeded + result.failed,
+            bytes_transferred: 0,
+        };
+        self.progress.publish(progress.event());
+
+        let next = AtomicUsize::new(0);
+        let state = Mutex::new((result, progress));
+        let spawned = thread::scope(|s| -> Result<()> {
+            for worker in 0..self.max_concurrent_migrations.min(groups.len()) {
+                thread::Builder::new()
+                    .name(format!("pager-migrate-{}", worker))
+                    .spawn_scoped(s, || {
+                        while let Some(group) = groups.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            let moved = if cancelled.is_cancelled() {
+                                group
+                                    .pages
+                                    .iter()
+                                    .map(|&page_num| {
+                                        (page_num, Err(anyhow!("Migration cancelled")))
+                                    })
+                                    .collect()
+                            } else {
+                                self.move_group(group, local)
+                            };
+                            let mut state = state.lock();
+                            let (result, progress) = &mut *state;
+                            for (page_num, outcome) in moved {
+                                if outcome.is_ok() {
+                                    progress.bytes_transferred += PAGE_SIZE as u64;
+                                }
+                                progress.pages_completed += 1;
+                                result.record(page_num, outcome);
+                            }
+                            if !progress.event().is_final() {
+                                self.progress.publish(progress.event());
+                            }
+                        }
+                    })
+                    .context("Failed to spawn migration thread")?;
+            }
+            Ok(())
+        });
+
+        // Pages of groups no worker got to count as completed too, so
+        // subscribers always see a final event
+        let (result, mut progress) = state.into_inner();
+        progress.pages_completed = progress.pages_total;
+        self.progress.publish(progress.event());
+        spawned?;
+        Ok(result)
+    }
+
+    /// Move one group's pages, returning how each went
+    fn move_group(&self, group: &MigrationGroup, local: &dyn LocalPages) -> Vec<(u64, Result<()>)> {
+        let addrs: Vec<u64> = group
+            .pages
+            .iter()
+            .map(|page_num| local.base() + page_num * PAGE_SIZE as u64)
+            .collect();
+        let contents: Vec<Result<Vec<u8>>> = match group.source {
+            PageSource::Local => group
+                .pages
+                .iter()
+                .map(|&page_num| local.read_page(page_num))
+                .collect(),
+            PageSource::Untouched => group.pages.iter().map(|_| Ok(vec![0; PAGE_SIZE])).collect(),
+            PageSource::Peer(owner) => match self.transport.read().fetch_pages(&addrs, owner) {
+                Ok(pages) => pages.into_iter().map(Ok).collect(),
+                Err(e) => {
+                    let reason = format!("Failed to fetch from node {}: {:#}", owner, e);
+                    group
+                        .pages
+                        .iter()
+                        .map(|_| Err(anyhow!("{}", reason)))
+                        .collect()
+                }
+            },
+        };
+
+        group
+            .pages
+            .iter()
+            .zip(addrs)
+            .zip(contents)
+            .map(|((&page_num, addr), data)| {
+                let outcome = data.and_then(|data| {
+                    self.transport
+                        .read()
+                        .send_page(addr, &data, group.target)
+                        .with_context(|| format!("Failed to send to node {}", group.target))?;
+                    self.directory
+                        .set_owner(page_num, PageOwner::Remote(group.target));
+                    if group.source == PageSource::Local {
+                        local.page_moved(page_num)?;
+                    }
+                    Ok(())
+                });
+                (page_num, outcome)
+            })
+            .collect()
+    }
+}
+
+impl BatchMigrationResult {
+    fn record(&mut self, page_num: u64, outcome: Result<()>) {
+        match outcome {
+            Ok(()) => self.succeeded += 1,
+            Err(e) => {
+                self.failed += 1;
+                self.errors.push((page_num, format!("{:#}", e)));
+            }
+        }
+    }
+}
+
+/// A page fetch abandoned by `MigrationCoordinator::cancel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchCancelled;
+
+impl fmt::Display for FetchCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for FetchCancelled {}
+
+/// Whether `error` is a fetch abandoned by `MigrationCoordinator::cancel`
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<FetchCancelled>().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdma_transport::transport::mock::{MockNetwork, MockTransport};
+
+    fn coordinator() -> (MigrationCoordinator, Arc<MockNetwork>) {
+        let network = MockNetwork::new();
+        let transport =
+            TransportManager::with_transport(0, Box::new(MockTransport::new(0, &network)));
+        let migration = MigrationCoordinator::new(
+            Arc::new(PageDirectory::new(0)),
+            Arc::new(RwLock::new(transport)),
+            crate::DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+        );
+        (migration, network)
+    }
+
+    /// Unmapped pages whose bytes are their page number
+    struct NumberedPages {
+        moved: Mutex<Vec<u64>>,
+    }
+
+    impl LocalPages for NumberedPages {
+        fn base(&self) -> u64 {
+            0x10_0000
+        }
+
+        fn page_count(&self) -> u64 {
+            16
+        }
+
+        fn read_page(&self, page_num: u64) -> Result<Vec<u8>> {
+            Ok(vec![page_num as u8; PAGE_SIZE])
+        }
+
+        fn page_moved(&self, page_num: u64) -> Result<()> {
+            self.moved.lock().push(page_num);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cancel_only_affects_earlier_tokens() {
+        let (migration, _network) = coordinator();
+        let before = migration.token();
+        migration.clone().cancel();
+
+        assert!(before.is_cancelled());
+        assert!(!migration.token().is_cancelled());
+    }
+
+    #[test]
+    fn test_estimate_downtime_from_measured_bandwidth() {
+        let (migration, network) = coordinator();
+        for page_num in 0..1000 {
+            migration.directory.claim_page(page_num);
+        }
+        // 4096000 bytes at 2048000 bytes/s
+        network.set_bandwidth(2_048_000);
+
+        let estimate = migration.estimate_downtime(1).unwrap();
+        assert_eq!(
+            estimate,
+            MigrationEstimate {
+                dirty_pages: 1000,
+                transfer_time_ms: 2000,
+                total_downtime_estimate_ms: 3000,
+            }
+        );
+        assert!(migration.estimate_downtime(0).is_err());
+        network.set_bandwidth(0);
+        assert!(migration.estimate_downtime(1).is_err());
+    }
+
+    #[test]
+    fn test_batch_migrate_moves_local_and_remote_pages() {
+        let (migration, network) = coordinator();
+        let local = NumberedPages {
+            moved: Mutex::new(Vec::new()),
+        };
+        for page_num in 0..3 {
+            migration.directory.claim_page(page_num);
+        }
+        migration.directory.set_owner(3, PageOwner::Remote(2));
+        migration.directory.set_owner(4, PageOwner::Remote(1));
+        migration.directory.set_owner(5, PageOwner::Speculative(0));
+        network.store_page(2, 0x10_0000 + 3 * PAGE_SIZE as u64, &[7; PAGE_SIZE]);
+
+        let result = migration
+            .batch_migrate(
+                vec![
+                    (0, 1),
+                    (1, 2),
+                    (2, 0),
+                    (3, 1),
+                    (4, 1),
+                    (5, 1),
+                    (6, 2),
+                    (16, 1),
+                ],
+                &local,
+            )
+            .unwrap();
+        assert_eq!(result.succeeded, 5);
+        assert_eq!(result.failed, 3);
+        let mut failed: Vec<u64> = result.errors.iter().map(|(page, _)| *page).collect();
+        failed.sort_unstable();
+        assert_eq!(failed, [2, 5, 16]);
+
+        let mut moved = local.moved.lock().clone();
+        moved.sort_unstable();
+        assert_eq!(moved, [0, 1]);
+        assert_eq!(migration.directory.get_owner(1), PageOwner::Remote(2));
+        assert_eq!(migration.directory.get_owner(2), PageOwner::Local);
+        assert_eq!(migration.directory.get_owner(3), PageOwner::Remote(1));
+        assert_eq!(migration.directory.get_owner(6), PageOwner::Remote(2));
+        assert_eq!(
+            network.page(1, 0x10_0000 + 3 * PAGE_SIZE as u64),
+            [7; PAGE_SIZE]
+        );
+        assert_eq!(
+            network.page(2, 0x10_0000 + PAGE_SIZE as u64),
+            [1; PAGE_SIZE]
+        );
+    }
+
+    #[test]
+    fn test_cancelled_error_recognised_through_context() {
+        let error = anyhow::Error::new(FetchCancelled).context("Failed to fetch page");
+        assert!(is_cancelled(&error));
+        assert!(!is_cancelled(&anyhow::anyhow!("Cancelled")));
+    }
+}
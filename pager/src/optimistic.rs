@@ -0,0 +1,393 @@
+//! Small values readable without taking a lock
+//!
+//! An `OptimisticLock` is a sequence lock: the value is kept in atomic
+//! words next to a version, which a writer makes odd while it stores and
+//! bumps to the next even number when done. A reader loads the version, the
+//! words and the version again, and keeps the value only if both versions
+//! are the same even number, so it writes nothing shared and never waits for
+//! a writer. A reader that keeps overlapping writes takes the writers' lock
+//! instead.
+//!
+//! Reads return the version they saw, and `compare_and_write` only stores
+//! if nothing was written since that version. `PageDirectory` fills its
+//! owner cache this way, so a fill that races a write to the same slot
+//! cannot bring back an owner read before the write.
+
+use crate::PageOwner;
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+
+#[cfg(not(pager_loom))]
+use parking_lot::Mutex;
+#[cfg(not(pager_loom))]
+use std::sync::atomic::{fence, AtomicU64};
+
+#[cfg(pager_loom)]
+use loom::sync::atomic::{fence, AtomicU64};
+#[cfg(pager_loom)]
+use loom_mutex::Mutex;
+
+/// `parking_lot::Mutex`'s interface on loom's mutex
+#[cfg(pager_loom)]
+mod loom_mutex {
+    pub struct Mutex<T>(loom::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub fn new(value: T) -> Self {
+            Self(loom::sync::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> loom::sync::MutexGuard<'_, T> {
+            self.0.lock().unwrap()
+        }
+    }
+}
+
+/// Lock-free reads tried before `read` takes the lock
+const READ_ATTEMPTS: usize = 4;
+
+/// A value stored as `N` words
+pub trait Packed<const N: usize>: Copy {
+    fn pack(self) -> [u64; N];
+    fn unpack(words: [u64; N]) -> Self;
+}
+
+impl Packed<1> for u64 {
+    fn pack(self) -> [u64; 1] {
+        [self]
+    }
+
+    fn unpack([word]: [u64; 1]) -> Self {
+        word
+    }
+}
+
+impl Packed<1> for PageOwner {
+    /// Variant in the high half, node ID in the low half
+    fn pack(self) -> [u64; 1] {
+        let (variant, node) = match self {
+            PageOwner::Local => (0, 0),
+            PageOwner::Remote(node) => (1, node),
+            PageOwner::Unknown => (2, 0),
+            PageOwner::Speculative(node) => (3, node),
+            PageOwner::LocalHuge => (4, 0),
+        };
+        [(variant << 32) | node as u64]
+    }
+
+    fn unpack([word]: [u64; 1]) -> Self {
+        let node = word as u32;
+        match word >> 32 {
+            0 => PageOwner::Local,
+            1 => PageOwner::Remote(node),
+            3 => PageOwner::Speculative(node),
+            4 => PageOwner::LocalHuge,
+            _ => PageOwner::Unknown,
+        }
+    }
+}
+
+impl<A: Packed<1>, B: Packed<1>> Packed<2> for (A, B) {
+    fn pack(self) -> [u64; 2] {
+        let ([a], [b]) = (self.0.pack(), self.1.pack());
+        [a, b]
+    }
+
+    fn unpack([a, b]: [u64; 2]) -> Self {
+        (A::unpack([a]), B::unpack([b]))
+    }
+}
+
+/// A value of `N` words with lock-free reads
+pub struct OptimisticLock<T, const N: usize> {
+    /// Odd while a writer is storing `words`
+    version: AtomicU64,
+    words: [AtomicU64; N],
+    /// Held by writers, and by readers that gave up reading lock-free
+    writer: Mutex<()>,
+    value: PhantomData<T>,
+}
+
+impl<T: Packed<N>, const N: usize> OptimisticLock<T, N> {
+    pub fn new(value: T) -> Self {
+        let words = value.pack();
+        Self {
+            version: AtomicU64::new(0),
+            words: std::array::from_fn(|i| AtomicU64::new(words[i])),
+            writer: Mutex::new(()),
+            value: PhantomData,
+        }
+    }
+
+    /// The value and the version it was read at, or `None` if a write
+    /// overlapped the read
+    pub fn try_read(&self) -> Option<(T, u64)> {
+        let version = self.version.load(Ordering::Acquire);
+        if version % 2 == 1 {
+            return None;
+        }
+        let words = self.load_words();
+        // Keeps the loads above from moving past the second version check
+        fence(Ordering::Acquire);
+        (self.version.load(Ordering::Relaxed) == version).then(|| (T::unpack(words), version))
+    }
+
+    /// The value and the version it was read at
+    pub fn read(&self) -> (T, u64) {
+        for _ in 0..READ_ATTEMPTS {
+            if let Some(read) = self.try_read() {
+                return read;
+            }
+            std::hint::spin_loop();
+        }
+        let _writer = self.writer.lock();
+        (
+            T::unpack(self.load_words()),
+            self.version.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Store `value`, returning its version
+    pub fn write(&self, value: T) -> u64 {
+        let _writer = self.writer.lock();
+        self.store(value)
+    }
+
+    /// Store `value` if nothing was written since `version` was read,
+    /// returning whether it was stored
+    pub fn compare_and_write(&self, version: u64, value: T) -> bool {
+        let _writer = self.writer.lock();
+        if self.version.load(Ordering::Relaxed) != version {
+            return false;
+        }
+        self.store(value);
+        true
+    }
+
+    fn load_words(&self) -> [u64; N] {
+        std::array::from_fn(|i| self.words[i].load(Ordering::Relaxed))
+    }
+
+    /// Caller holds `writer`
+    fn store(&self, value: T) -> u64 {
+        let version = self.version.load(Ordering::Relaxed);
+        self.version.store(version + 1, Ordering::Relaxed);
+        // Keeps the stores below from moving before the odd version
+        fence(Ordering::Release);
+        for (word, packed) in self.words.iter().zip(value.pack()) {
+            word.store(packed, Ordering::Relaxed);
+        }
+        self.version.store(version + 2, Ordering::Release);
+        version + 2
+    }
+}
+
+#[cfg(all(test, not(pager_loom)))]
+mod tests {
+    use super::*;
+    use crate::PageDirectory;
+    use parking_lot::RwLock;
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_page_owners_round_trip() {
+        for owner in [
+            PageOwner::Local,
+            PageOwner::Remote(u32::MAX),
+            PageOwner::Unknown,
+            PageOwner::Speculative(7),
+            PageOwner::LocalHuge,
+        ] {
+            assert_eq!(PageOwner::unpack(owner.pack()), owner);
+        }
+    }
+
+    #[test]
+    fn test_stale_version_not_written() {
+        let lock = OptimisticLock::new((1u64, PageOwner::Local));
+        let (_, version) = lock.read();
+        lock.write((1, PageOwner::Remote(2)));
+
+        assert!(!lock.compare_and_write(version, (1, PageOwner::Local)));
+        let (value, version) = lock.read();
+        assert_eq!(value, (1, PageOwner::Remote(2)));
+        assert!(lock.compare_and_write(version, (2, PageOwner::Remote(3))));
+        assert_eq!(lock.read().0, (2, PageOwner::Remote(3)));
+    }
+
+    #[test]
+    fn test_readers_never_see_torn_values() {
+        let lock = Arc::new(OptimisticLock::new((0u64, PageOwner::Remote(0))));
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (lock, done) = (Arc::clone(&lock), Arc::clone(&done));
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let ((page, owner), version) = lock.read();
+                        assert_eq!(owner, PageOwner::Remote(page as u32));
+                        assert_eq!(version % 2, 0);
+                    }
+                })
+            })
+            .collect();
+        for node in 1..100_000u32 {
+            lock.write((node as u64, PageOwner::Remote(node)));
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    /// Reads per second of `READERS` threads calling `get` on pages of
+    /// `REGIONS` regions while one writer calls `set` every `WRITE_INTERVAL`
+    fn reader_throughput(
+        get: impl Fn(u64) -> PageOwner + Send + Sync + 'static,
+        set: impl Fn(u64, PageOwner) + Send + Sync + 'static,
+    ) -> f64 {
+        const READERS: usize = 16;
+        const REGIONS: u64 = 64;
+        const RUN: Duration = Duration::from_millis(500);
+        const WRITE_INTERVAL: Duration = Duration::from_millis(1);
+
+        let get = Arc::new(get);
+        let stop = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicU64::new(0));
+        let readers: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let (get, stop, reads) = (Arc::clone(&get), Arc::clone(&stop), Arc::clone(&reads));
+                thread::spawn(move || {
+                    let mut count = 0u64;
+                    let mut page = reader as u64;
+                    while !stop.load(Ordering::Relaxed) {
+                        std::hint::black_box(get(page % (REGIONS * 256)));
+                        page += 7;
+                        count += 1;
+                    }
+                    reads.fetch_add(count, Ordering::Relaxed);
+                })
+            })
+            .collect();
+
+        let start = Instant::now();
+        let mut node = 0u32;
+        while start.elapsed() < RUN {
+            // Every write lands in the last region, which readers share
+            set(REGIONS * 256 - 1, PageOwner::Remote(node));
+            node += 1;
+            thread::sleep(WRITE_INTERVAL);
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        reads.load(Ordering::Relaxed) as f64 / start.elapsed().as_secs_f64()
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_rwlock_vs_optimistic_reads() {
+        let pages = 0..64 * 256;
+
+        let locked = Arc::new(RwLock::new(
+            pages
+                .clone()
+                .map(|page| (page, PageOwner::Local))
+                .collect::<HashMap<_, _>>(),
+        ));
+        let writer = Arc::clone(&locked);
+        let rwlock = reader_throughput(
+            move |page| {
+                locked
+                    .read()
+                    .get(&page)
+                    .copied()
+                    .unwrap_or(PageOwner::Unknown)
+            },
+            move |page, owner| {
+                writer.write().insert(page, owner);
+            },
+        );
+
+        let dir = Arc::new(PageDirectory::new(0));
+        for page in pages {
+            dir.set_owner(page, PageOwner::Local);
+        }
+        let writer = Arc::clone(&dir);
+        let optimistic = reader_throughput(
+            move |page| dir.get_owner(page),
+            move |page, owner| writer.set_owner(page, owner),
+        );
+
+        println!(
+            "16 readers + 1 writer: RwLock {:.1}M reads/s, optimistic {:.1}M reads/s ({:.1}x)",
+            rwlock / 1e6,
+            optimistic / 1e6,
+            optimistic / rwlock
+        );
+    }
+}
+
+#[cfg(all(test, pager_loom))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    #[test]
+    fn loom_reads_see_whole_writes() {
+        loom::model(|| {
+            let lock = Arc::new(OptimisticLock::new((1u64, PageOwner::Remote(1))));
+
+            let writer = {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    lock.write((2, PageOwner::Remote(2)));
+                })
+            };
+
+            let ((page, owner), _) = lock.read();
+            assert_eq!(owner, PageOwner::Remote(page as u32));
+
+            writer.join().unwrap();
+            assert_eq!(lock.read().0, (2, PageOwner::Remote(2)));
+        });
+    }
+
+    /// The directory's cache fill against `set_owner`, with a mutex standing
+    /// in for the region map
+    #[test]
+    fn loom_fill_never_outlives_invalidation() {
+        const EMPTY: u64 = u64::MAX;
+
+        loom::model(|| {
+            let region = Arc::new(loom::sync::Mutex::new(PageOwner::Remote(1)));
+            let cache = Arc::new(OptimisticLock::new((EMPTY, PageOwner::Unknown)));
+
+            let writer = {
+                let (region, cache) = (Arc::clone(&region), Arc::clone(&cache));
+                thread::spawn(move || {
+                    *region.lock().unwrap() = PageOwner::Remote(2);
+                    cache.write((EMPTY, PageOwner::Unknown));
+                })
+            };
+
+            let ((cached, _), version) = cache.read();
+            if cached == EMPTY {
+                let owner = *region.lock().unwrap();
+                cache.compare_and_write(version, (0, owner));
+            }
+
+            writer.join().unwrap();
+            let ((cached, owner), _) = cache.read();
+            if cached == 0 {
+                assert_eq!(owner, PageOwner::Remote(2));
+            }
+        });
+    }
+}
@@ -0,0 +1,89 @@
+//! Guest pages larger than 4 KiB
+//!
+//! The guest may back some of its memory with 2 MiB or 1 GiB pages. The
+//! page directory keeps one key per 4 KiB page whatever the guest does, so
+//! a huge page covers `PageSizeClass::pages` consecutive keys (see
+//! `PageDirectory::get_owner_huge`). Where the guest's page tables can be
+//! read, a `GuestPageWalker` says which size backs an address; otherwise
+//! the pager only knows the 2 MiB ranges it promoted itself.
+//!
+//! With the `hugepages` feature a pager can also be told the guest uses
+//! transparent huge pages throughout (`PageSizeConfig::Huge2M`): a first
+//! touch then claims and zero-fills the whole 2 MiB page with one ioctl
+//! rather than 512.
+
+use crate::{HUGE_PAGE_PAGES, PAGE_SIZE};
+use serde::{Deserialize, Serialize};
+
+/// Size of the page backing a guest address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PageSizeClass {
+    #[default]
+    Small4K,
+    Huge2M,
+    Huge1G,
+}
+
+impl PageSizeClass {
+    /// 4 KiB pages (directory keys) one page of this size covers
+    pub const fn pages(self) -> u64 {
+        match self {
+            Self::Small4K => 1,
+            Self::Huge2M => HUGE_PAGE_PAGES,
+            Self::Huge1G => HUGE_PAGE_PAGES * 512,
+        }
+    }
+
+    pub const fn bytes(self) -> usize {
+        self.pages() as usize * PAGE_SIZE
+    }
+
+    /// First 4 KiB page of the page of this size holding `page_num`
+    pub const fn align_page(self, page_num: u64) -> u64 {
+        page_num - page_num % self.pages()
+    }
+}
+
+/// Size of the pages a pager resolves first touches with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PageSizeConfig {
+    #[default]
+    Small4K,
+    /// Whole 2 MiB pages, where none of the page was touched before
+    #[cfg(feature = "hugepages")]
+    Huge2M,
+}
+
+impl PageSizeConfig {
+    pub const fn size_class(self) -> PageSizeClass {
+        match self {
+            Self::Small4K => PageSizeClass::Small4K,
+            #[cfg(feature = "hugepages")]
+            Self::Huge2M => PageSizeClass::Huge2M,
+        }
+    }
+}
+
+/// Reads the page size the guest maps an address with, e.g. from its EPT
+pub trait GuestPageWalker: Send + Sync {
+    /// Size class of the page at guest address `gpa` (an offset into the
+    /// pager's region), or `None` if the walk did not find it
+    fn page_size_class(&self, gpa: u64) -> Option<PageSizeClass>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_classes_cover_consecutive_keys() {
+        assert_eq!(PageSizeClass::Small4K.bytes(), 4096);
+        assert_eq!(PageSizeClass::Huge2M.bytes(), 2 << 20);
+        assert_eq!(PageSizeClass::Huge1G.bytes(), 1 << 30);
+        assert_eq!(PageSizeClass::Huge1G.pages(), 262_144);
+
+        assert_eq!(PageSizeClass::Small4K.align_page(513), 513);
+        assert_eq!(PageSizeClass::Huge2M.align_page(513), 512);
+        assert_eq!(PageSizeClass::Huge1G.align_page(300_000), 262_144);
+    }
+}
@@ -0,0 +1,166 @@
+//! Access pattern detection for adaptive prefetch
+//!
+//! Keeps a sliding window of recently faulted page numbers and classifies the
+//! stream as sequential, strided or random. The pager scales its prefetch
+//! depth accordingly: deep for sequential scans, shallower for strides, and
+//! off for random access where prefetched pages would just waste bandwidth.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Faulted pages remembered by the detector
+pub const PATTERN_WINDOW: usize = 32;
+
+/// Strides needed before a stream is classified as anything but random
+const MIN_STRIDES: usize = 4;
+
+/// Fraction of strides in the window that must agree (numerator/denominator)
+const AGREEMENT_NUM: usize = 3;
+const AGREEMENT_DEN: usize = 4;
+
+/// Classified access pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessPattern {
+    /// Each page follows the previous one
+    Sequential,
+    /// Regular stride (in pages, may be negative)
+    Strided(i64),
+    /// No dominant stride
+    Random,
+}
+
+/// Sliding-window access pattern classifier
+///
+/// Not thread-safe by design: each fault-handler thread owns its own.
+#[derive(Debug)]
+pub struct AccessPatternDetector {
+    window: VecDeque<u64>,
+    pattern: AccessPattern,
+}
+
+impl Default for AccessPatternDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessPatternDetector {
+    pub fn new() -> Self {
+        Self {
+            window: VecDeque::with_capacity(PATTERN_WINDOW),
+            pattern: AccessPattern::Random,
+        }
+    }
+
+    /// Current classification
+    pub fn pattern(&self) -> AccessPattern {
+        self.pattern
+    }
+
+    /// Record a faulted page and reclassify
+    ///
+    /// Returns `true` if the classification changed.
+    pub fn record(&mut self, page_num: u64) -> bool {
+        if self.window.len() == PATTERN_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(page_num);
+
+        let pattern = self.classify();
+        let changed = pattern != self.pattern;
+        self.pattern = pattern;
+        changed
+    }
+
+    /// Prefetch depth for the current pattern given the configured maximum
+    pub fn prefetch_depth(&self, max_depth: usize) -> usize {
+        match self.pattern {
+            AccessPattern::Sequential => max_depth,
+            AccessPattern::Strided(_) => max_depth / 2,
+            AccessPattern::Random => 0,
+        }
+    }
+
+    fn classify(&self) -> AccessPattern {
+        let strides: Vec<i64> = self
+            .window
+            .iter()
+            .zip(self.window.iter().skip(1))
+            .map(|(prev, next)| next.wrapping_sub(*prev) as i64)
+            .collect();
+
+        if strides.len() < MIN_STRIDES {
+            return AccessPattern::Random;
+        }
+
+        // Find the most common stride (window is small, quadratic is fine)
+        let (stride, count) = strides
+            .iter()
+            .map(|s| (*s, strides.iter().filter(|t| *t == s).count()))
+            .max_by_key(|(_, count)| *count)
+            .unwrap_or((0, 0));
+
+        if stride == 0 || count * AGREEMENT_DEN < strides.len() * AGREEMENT_NUM {
+            AccessPattern::Random
+        } else if stride == 1 {
+            AccessPattern::Sequential
+        } else {
+            AccessPattern::Strided(stride)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_detected_within_8_samples() {
+        let mut detector = AccessPatternDetector::new();
+        for page in 100..108 {
+            detector.record(page);
+        }
+        assert_eq!(detector.pattern(), AccessPattern::Sequential);
+        assert_eq!(detector.prefetch_depth(8), 8);
+    }
+
+    #[test]
+    fn test_strided_detected() {
+        let mut detector = AccessPatternDetector::new();
+        for i in 0..10 {
+            detector.record(i * 16);
+        }
+        assert_eq!(detector.pattern(), AccessPattern::Strided(16));
+        assert_eq!(detector.prefetch_depth(8), 4);
+    }
+
+    #[test]
+    fn test_random_disables_prefetch() {
+        let mut detector = AccessPatternDetector::new();
+        for page in [7, 912, 33, 4, 5120, 88, 61, 2048, 3, 777] {
+            detector.record(page);
+        }
+        assert_eq!(detector.pattern(), AccessPattern::Random);
+        assert_eq!(detector.prefetch_depth(8), 0);
+    }
+
+    #[test]
+    fn test_pattern_change_reported() {
+        let mut detector = AccessPatternDetector::new();
+        let changes: usize = (0..8).map(|p| detector.record(p) as usize).sum();
+        assert_eq!(changes, 1);
+
+        // Switch to a random stream; the window eventually flips
+        let mut flipped = false;
+        let mut seed = 42u64;
+        for _ in 0..PATTERN_WINDOW {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            flipped |= detector.record((seed >> 33) % 100_000);
+        }
+        assert!(flipped);
+        assert_eq!(detector.pattern(), AccessPattern::Random);
+    }
+}
@@ -0,0 +1,306 @@
+//! Local memory limits and page replacement
+//!
+//! By default every page a node first touches stays local. Under
+//! `OvercommitPolicy::Lazy` the node keeps at most a fixed number of local
+//! pages; a first touch beyond that evicts a local page to a peer, and a
+//! `PageReplacementPolicy` picks which one.
+//!
+//! The pager only sees faults, not accesses to pages already present, so
+//! "accessed" below means faulted on.
+
+use crate::{PageDirectory, PageOwner};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+/// How many pages a node may hold locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OvercommitPolicy {
+    /// Every first-touched page stays local
+    #[default]
+    Strict,
+    /// Keep at most `max_local_pages`; evict to a peer to make room
+    ///
+    /// The victim is copied before it is unmapped, so a write racing with
+    /// its eviction is lost; replacement policies aim for cold pages.
+    Lazy { max_local_pages: usize },
+}
+
+/// Picks local pages to evict
+///
+/// The pager reports every page it claims and every fault on a local page;
+/// the policy keeps whatever ordering it needs. Pages may stop being local
+/// without the policy hearing of it (e.g. migration), so victims are checked
+/// against the directory.
+pub trait PageReplacementPolicy: Send + Sync + fmt::Debug {
+    /// A page became local on first touch
+    fn record_claim(&self, page_num: u64);
+
+    /// A local page was faulted on again
+    fn record_access(&self, page_num: u64);
+
+    /// Choose a local page to evict and stop tracking it
+    fn select_victim(&self, directory: &PageDirectory) -> Option<u64>;
+}
+
+/// Policy the pager uses unless configured otherwise
+pub fn default_policy() -> Arc<dyn PageReplacementPolicy> {
+    Arc::new(LruPolicy::default())
+}
+
+fn is_local(directory: &PageDirectory, page_num: u64) -> bool {
+    directory.get_owner(page_num) == PageOwner::Local
+}
+
+/// Evict the page claimed longest ago
+#[derive(Debug, Default)]
+pub struct FifoPolicy {
+    inner: Mutex<FifoState>,
+}
+
+#[derive(Debug, Default)]
+struct FifoState {
+    /// Claim order, oldest first
+    queue: VecDeque<u64>,
+    queued: HashSet<u64>,
+}
+
+impl PageReplacementPolicy for FifoPolicy {
+    fn record_claim(&self, page_num: u64) {
+        let mut state = self.inner.lock();
+        if state.queued.insert(page_num) {
+            state.queue.push_back(page_num);
+        }
+    }
+
+    fn record_access(&self, _page_num: u64) {}
+
+    fn select_victim(&self, directory: &PageDirectory) -> Option<u64> {
+        let mut state = self.inner.lock();
+        while let Some(page_num) = state.queue.pop_front() {
+            state.queued.remove(&page_num);
+            if is_local(directory, page_num) {
+                return Some(page_num);
+            }
+        }
+        None
+    }
+}
+
+/// Evict the page accessed least recently
+#[derive(Debug)]
+pub struct LruPolicy {
+    pages: Mutex<LruCache<u64, ()>>,
+}
+
+impl Default for LruPolicy {
+    fn default() -> Self {
+        Self {
+            pages: Mutex::new(LruCache::unbounded()),
+        }
+    }
+}
+
+impl PageReplacementPolicy for LruPolicy {
+    fn record_claim(&self, page_num: u64) {
+        self.pages.lock().put(page_num, ());
+    }
+
+    fn record_access(&self, page_num: u64) {
+        self.pages.lock().put(page_num, ());
+    }
+
+    fn select_victim(&self, directory: &PageDirectory) -> Option<u64> {
+        let mut pages = self.pages.lock();
+        while let Some((page_num, ())) = pages.pop_lru() {
+            if is_local(directory, page_num) {
+                return Some(page_num);
+            }
+        }
+        None
+    }
+}
+
+/// Approximate LRU: pages accessed since the hand last passed get a second
+/// chance
+#[derive(Debug, Default)]
+pub struct ClockPolicy {
+    inner: Mutex<ClockState>,
+}
+
+#[derive(Debug, Default)]
+struct ClockState {
+    /// Pages in hand order; the hand points at the front
+    ring: VecDeque<u64>,
+    /// Reference bit of each page in `ring`
+    referenced: HashMap<u64, bool>,
+}
+
+impl PageReplacementPolicy for ClockPolicy {
+    fn record_claim(&self, page_num: u64) {
+        let mut state = self.inner.lock();
+        if state.referenced.insert(page_num, false).is_none() {
+            state.ring.push_back(page_num);
+        }
+    }
+
+    fn record_access(&self, page_num: u64) {
+        if let Some(bit) = self.inner.lock().referenced.get_mut(&page_num) {
+            *bit = true;
+        }
+    }
+
+    fn select_victim(&self, directory: &PageDirectory) -> Option<u64> {
+        let mut state = self.inner.lock();
+        while let Some(page_num) = state.ring.pop_front() {
+            if !is_local(directory, page_num) {
+                state.referenced.remove(&page_num);
+                continue;
+            }
+            let bit = state
+                .referenced
+                .get_mut(&page_num)
+                .expect("ring page has a bit");
+            if *bit {
+                *bit = false;
+                state.ring.push_back(page_num);
+            } else {
+                state.referenced.remove(&page_num);
+                return Some(page_num);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Outcome of replaying a fault trace
+    #[derive(Debug, Default)]
+    struct Replay {
+        /// Faults on pages that were not local
+        misses: usize,
+        victims: Vec<u64>,
+    }
+
+    /// Replay `trace` with room for `capacity` local pages
+    fn simulate(policy: &dyn PageReplacementPolicy, capacity: usize, trace: &[u64]) -> Replay {
+        let directory = PageDirectory::new(0);
+        let mut replay = Replay::default();
+        for &page_num in trace {
+            if is_local(&directory, page_num) {
+                policy.record_access(page_num);
+                continue;
+            }
+            replay.misses += 1;
+            if directory.local_page_count() >= capacity {
+                let victim = policy
+                    .select_victim(&directory)
+                    .expect("a full node has a victim");
+                assert!(is_local(&directory, victim));
+                directory.set_owner(victim, PageOwner::Remote(1));
+                replay.victims.push(victim);
+            }
+            directory.claim_page(page_num);
+            policy.record_claim(page_num);
+            assert!(directory.local_page_count() <= capacity);
+        }
+        replay
+    }
+
+    fn policies() -> Vec<Box<dyn PageReplacementPolicy>> {
+        vec![
+            Box::<FifoPolicy>::default(),
+            Box::<LruPolicy>::default(),
+            Box::<ClockPolicy>::default(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_eviction_count_bounds(
+            capacity in 1usize..8,
+            trace in prop::collection::vec(0u64..32, 0..200),
+        ) {
+            let distinct: HashSet<u64> = trace.iter().copied().collect();
+            for policy in policies() {
+                let replay = simulate(policy.as_ref(), capacity, &trace);
+                let evictions = replay.victims.len();
+                // Once full, every miss evicts exactly one page
+                prop_assert_eq!(evictions, replay.misses.saturating_sub(capacity));
+                // Worst case: every fault past the first `capacity` evicts
+                prop_assert!(evictions <= trace.len().saturating_sub(capacity));
+                // Best case: each page beyond capacity pushes one out
+                prop_assert!(evictions >= distinct.len().saturating_sub(capacity));
+            }
+        }
+
+        #[test]
+        fn prop_working_set_that_fits_never_evicts(
+            capacity in 1usize..8,
+            trace in prop::collection::vec(0u64..8, 0..200),
+        ) {
+            let trace: Vec<u64> = trace.into_iter().map(|p| p % capacity as u64).collect();
+            for policy in policies() {
+                prop_assert!(simulate(policy.as_ref(), capacity, &trace).victims.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_cyclic_scan_is_worst_case_for_all_policies() {
+        let capacity = 4;
+        let trace: Vec<u64> = (0..100).map(|i| i % (capacity as u64 + 1)).collect();
+        for policy in policies() {
+            let replay = simulate(policy.as_ref(), capacity, &trace);
+            assert_eq!(replay.victims.len(), trace.len() - capacity, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_lru_keeps_hot_page() {
+        // Page 0 is faulted on between every other page
+        let trace: Vec<u64> = (1..50).flat_map(|i| [0, i]).collect();
+
+        let lru = simulate(&LruPolicy::default(), 2, &trace);
+        assert!(!lru.victims.contains(&0));
+        let fifo = simulate(&FifoPolicy::default(), 2, &trace);
+        assert!(fifo.victims.contains(&0));
+        assert!(fifo.victims.len() > lru.victims.len());
+    }
+
+    #[test]
+    fn test_clock_gives_referenced_pages_a_second_chance() {
+        let directory = PageDirectory::new(0);
+        let clock = ClockPolicy::default();
+        for page_num in 0..3 {
+            directory.claim_page(page_num);
+            clock.record_claim(page_num);
+        }
+        clock.record_access(0);
+        assert_eq!(clock.select_victim(&directory), Some(1));
+        // Page 0's bit was cleared as the hand passed it
+        assert_eq!(clock.select_victim(&directory), Some(2));
+        assert_eq!(clock.select_victim(&directory), Some(0));
+        assert_eq!(clock.select_victim(&directory), None);
+    }
+
+    #[test]
+    fn test_victims_skip_pages_no_longer_local() {
+        for policy in policies() {
+            let directory = PageDirectory::new(0);
+            for page_num in 0..2 {
+                directory.claim_page(page_num);
+                policy.record_claim(page_num);
+            }
+            directory.set_owner(0, PageOwner::Remote(2));
+            assert_eq!(policy.select_victim(&directory), Some(1));
+            assert_eq!(policy.select_victim(&directory), None);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Installing remote pages before the guest faults on them
+//!
+//! The VMM often knows which guest memory is about to be used, e.g. a
+//! vCPU's stack and page tables right after the vCPU moved to this node.
+//! `Pager::prefault_region` installs the remote pages of a range there and
+//! then, so the guest never faults on them.
+//!
+//! A `Prefaulter` takes such hints without blocking the caller or the fault
+//! loop: its thread fetches the hinted pages into the page cache, then asks
+//! the fault loop to install them (`ControlMessage::Prefault`), which it
+//! does from the cache without a network round trip.
+
+use crate::cache::PageCache;
+use crate::reload::ControlMessage;
+use crate::{PageDirectory, PageOwner, PAGE_SIZE};
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::Sender;
+use log::{debug, warn};
+use parking_lot::RwLock;
+use rdma_transport::TransportManager;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Fetch the pages of `remote` (page number, owner) missing from `cache`
+///
+/// Owners that cannot be reached are skipped with a warning; their pages
+/// fault as usual later. Returns how many pages were fetched.
+pub(crate) fn fetch_into_cache(
+    transport: &RwLock<TransportManager>,
+    cache: &PageCache,
+    base: u64,
+    remote: &[(u64, u32)],
+) -> Result<u64> {
+    let mut by_owner: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+    for &(page_num, owner) in remote {
+        let gpa = page_num * PAGE_SIZE as u64;
+        if !cache.contains(gpa) {
+            by_owner.entry(owner).or_default().push(gpa);
+        }
+    }
+
+    let mut fetched = 0;
+    for (owner, gpas) in by_owner {
+        let addrs: Vec<u64> = gpas.iter().map(|gpa| base + gpa).collect();
+        let pages = match transport.read().fetch_pages(&addrs, owner) {
+            Ok(pages) => pages,
+            Err(e) => {
+                warn!(
+                    "Not prefaulting {} pages owned by node {}: {:#}",
+                    gpas.len(),
+                    owner,
+                    e
+                );
+                continue;
+            }
+        };
+        for (gpa, data) in gpas.into_iter().zip(pages) {
+            cache.insert(gpa, &data)?;
+            fetched += 1;
+        }
+    }
+    Ok(fetched)
+}
+
+/// What the prefaulter thread needs from the pager
+pub(crate) struct PrefaultContext {
+    pub base: u64,
+    pub len: usize,
+    pub directory: Arc<PageDirectory>,
+    pub transport: Arc<RwLock<TransportManager>>,
+    pub cache: Arc<PageCache>,
+    pub control_tx: Arc<Sender<ControlMessage>>,
+}
+
+impl PrefaultContext {
+    /// Warm the cache with the remote pages among `gpas`, then hand them to
+    /// the fault loop
+    fn prefault(&self, gpas: Vec<u64>) -> Result<()> {
+        let remote: Vec<(u64, u32)> = gpas
+            .into_iter()
+            .filter(|&gpa| {
+                let inside = gpa < self.len as u64;
+                if !inside {
+                    warn!("Ignoring prefault hint 0x{:x} outside the region", gpa);
+                }
+                inside
+            })
+            .filter_map(|gpa| {
+                let page_num = gpa / PAGE_SIZE as u64;
+                match self.directory.get_owner(page_num) {
+                    PageOwner::Remote(owner) => Some((page_num, owner)),
+                    _ => None,
+                }
+            })
+            .collect();
+        if remote.is_empty() {
+            return Ok(());
+        }
+
+        let fetched = fetch_into_cache(&self.transport, &self.cache, self.base, &remote)?;
+        debug!("Prefetched {} of {} hinted pages", fetched, remote.len());
+        let pages = remote.into_iter().map(|(page_num, _)| page_num).collect();
+        self.control_tx
+            .send(ControlMessage::Prefault(pages))
+            .map_err(|_| anyhow!("Fault loop has stopped"))
+    }
+}
+
+/// Prefaults hinted pages in the background (see `Pager::prefaulter`)
+///
+/// Dropping it finishes the hints already given, then stops its thread.
+pub struct Prefaulter {
+    hints: Option<Sender<Vec<u64>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Prefaulter {
+    pub(crate) fn start(context: PrefaultContext) -> Result<Self> {
+        let (hints, hints_rx) = crossbeam_channel::unbounded::<Vec<u64>>();
+        let thread = thread::Builder::new()
+            .name("prefaulter".to_string())
+            .spawn(move || {
+                for gpas in hints_rx {
+                    if let Err(e) = context.prefault(gpas) {
+                        warn!("Prefaulting stopped: {:#}", e);
+                        return;
+                    }
+                }
+            })
+            .context("Failed to spawn prefaulter thread")?;
+        Ok(Self {
+            hints: Some(hints),
+            thread: Some(thread),
+        })
+    }
+
+    /// Prefault the pages at guest addresses `gpas` without waiting for them
+    pub fn hint(&self, gpas: Vec<u64>) -> Result<()> {
+        self.hints
+            .as_ref()
+            .expect("hints open until drop")
+            .send(gpas)
+            .map_err(|_| anyhow!("Prefaulter thread has stopped"))
+    }
+}
+
+impl Drop for Prefaulter {
+    fn drop(&mut self) {
+        self.hints.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Zero-filling pages ahead of sequential first touches
+//!
+//! A guest going through fresh memory, e.g. zeroing a buffer or loading a
+//! file, first-touches page after page in order. With
+//! `PagerConfig::prefetch_pages` set, every first touch resolved locally
+//! queues the next pages for a background thread, which claims and
+//! zero-fills those still untouched so the guest does not fault on them.
+//!
+//! Prefetching is best effort: hints beyond `PREFETCH_QUEUE_DEPTH` are
+//! dropped, and a page the guest touches before the thread gets to it
+//! faults as usual.
+
+use crate::allocator::PageAllocator;
+use crate::policy::PageReplacementPolicy;
+use crate::{PageDirectory, PageOwner, Pager, PagerStats, PAGE_SIZE};
+use anyhow::{Context, Result};
+use crossbeam_channel::{Sender, TrySendError};
+use log::{debug, warn};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use userfaultfd::Uffd;
+
+/// Pages waiting to be prefetched before further hints are dropped
+pub const PREFETCH_QUEUE_DEPTH: usize = 256;
+
+/// What the prefetcher thread needs from the pager
+pub(crate) struct PrefetchContext {
+    pub uffd: Arc<Uffd>,
+    pub base: u64,
+    pub len: usize,
+    pub directory: Arc<PageDirectory>,
+    pub allocator: Arc<PageAllocator>,
+    pub replacement_policy: Arc<dyn PageReplacementPolicy>,
+    pub stats: Arc<RwLock<PagerStats>>,
+}
+
+impl PrefetchContext {
+    /// Claim and zero-fill `page_num` if it is still untouched
+    fn prefetch(&self, page_num: u64) -> Result<()> {
+        if page_num >= (self.len / PAGE_SIZE) as u64
+            || self.directory.get_owner(page_num) != PageOwner::Unknown
+        {
+            return Ok(());
+        }
+
+        // Claimed first, so a fault racing with the copy zero-fills it as a
+        // local page rather than claiming it again
+        self.directory.claim_page(page_num);
+        self.replacement_policy.record_claim(page_num);
+        let zero_page = self.allocator.alloc_zeroed();
+        let addr = self.base + page_num * PAGE_SIZE as u64;
+        if Pager::copy_page(&self.uffd, addr, zero_page.as_ptr())
+            .with_context(|| format!("Failed to prefetch page {}", page_num))?
+        {
+            self.stats.write().prefetched_pages += 1;
+            debug!("Prefetched page {}", page_num);
+        }
+        Ok(())
+    }
+}
+
+/// Zero-fills queued pages in the background
+///
+/// Dropping it stops its thread once the pages already queued are done.
+pub(crate) struct SequentialPrefetcher {
+    /// Pages to prefetch after each first touch
+    depth: usize,
+    queue: Option<Sender<u64>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SequentialPrefetcher {
+    pub fn start(depth: usize, context: PrefetchContext) -> Result<Self> {
+        let (queue, pages) = crossbeam_channel::bounded::<u64>(PREFETCH_QUEUE_DEPTH);
+        let thread = thread::Builder::new()
+            .name("pager-prefetch".to_string())
+            .spawn(move || {
+                for page_num in pages {
+                    if let Err(e) = context.prefetch(page_num) {
+                        warn!("{:#}", e);
+                    }
+                }
+            })
+            .context("Failed to spawn prefetch thread")?;
+        Ok(Self {
+            depth,
+            queue: Some(queue),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queue the pages following a first touch of `page_num`
+    pub fn first_touched(&self, page_num: u64) {
+        let queue = self.queue.as_ref().expect("queue open until drop");
+        for next in page_num + 1..=page_num + self.depth as u64 {
+            match queue.try_send(next) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    debug!("Prefetch queue full; dropping hints from page {}", next);
+                    return;
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+        }
+    }
+}
+
+impl Drop for SequentialPrefetcher {
+    fn drop(&mut self) {
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Local memory pressure signals
+//!
+//! The VMM has to know when local memory is running low so it can inflate
+//! the balloon or migrate pages away. The pager polls its local page count
+//! against `MemoryWatermarks` and tells a `PressureCallback` when the count
+//! rises above the high watermark, then again once it falls below the low
+//! one. Nothing is reported in between, so a count hovering around one
+//! watermark does not produce a stream of signals.
+
+use crate::ShutdownSignal;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the local page count is checked
+pub const PRESSURE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Local page counts that start and end memory pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWatermarks {
+    /// Pressure ends below this many local pages
+    pub low_pages: usize,
+    /// Pressure starts above this many local pages
+    pub high_pages: usize,
+}
+
+/// Told when local memory pressure starts and ends
+pub trait PressureCallback: Send + Sync {
+    /// More than `high_pages` pages are local
+    fn on_high_pressure(&self, current: usize);
+
+    /// Fewer than `low_pages` pages are local again
+    fn on_pressure_relieved(&self);
+}
+
+/// Callback used unless one is registered: logs the transitions
+#[derive(Debug, Default)]
+pub struct LogPressure;
+
+impl PressureCallback for LogPressure {
+    fn on_high_pressure(&self, current: usize) {
+        warn!("Local memory pressure: {} pages local", current);
+    }
+
+    fn on_pressure_relieved(&self) {
+        info!("Local memory pressure relieved");
+    }
+}
+
+/// Whether pressure is on, switching at the watermarks
+#[derive(Debug)]
+struct PressureState {
+    watermarks: MemoryWatermarks,
+    high: bool,
+}
+
+impl PressureState {
+    fn new(watermarks: MemoryWatermarks) -> Self {
+        Self {
+            watermarks,
+            high: false,
+        }
+    }
+
+    /// Tell `callback` if `current` local pages starts or ends pressure
+    fn update(&mut self, current: usize, callback: &dyn PressureCallback) {
+        if !self.high && current > self.watermarks.high_pages {
+            self.high = true;
+            callback.on_high_pressure(current);
+        } else if self.high && current < self.watermarks.low_pages {
+            self.high = false;
+            callback.on_pressure_relieved();
+        }
+    }
+}
+
+/// Poll `local_pages` every `PRESSURE_POLL_INTERVAL` on a background thread
+///
+/// Stops on shutdown, or once `local_pages` returns `None` (the pager is
+/// gone).
+pub fn start_monitor(
+    watermarks: MemoryWatermarks,
+    local_pages: impl Fn() -> Option<usize> + Send + 'static,
+    callback: Arc<RwLock<Box<dyn PressureCallback>>>,
+    shutdown: Arc<ShutdownSignal>,
+) -> Result<JoinHandle<()>> {
+    if watermarks.low_pages > watermarks.high_pages {
+        return Err(anyhow!(
+            "Low watermark {} is above high watermark {}",
+            watermarks.low_pages,
+            watermarks.high_pages
+        ));
+    }
+    info!(
+        "Watching memory pressure: high {} pages, low {} pages",
+        watermarks.high_pages, watermarks.low_pages
+    );
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create memory pressure runtime")?;
+
+    thread::Builder::new()
+        .name("pager-pressure".to_string())
+        .spawn(move || {
+            let mut state = PressureState::new(watermarks);
+            runtime.block_on(async move {
+                loop {
+                    tokio::select! {
+                        _ = shutdown.wait() => break,
+                        _ = tokio::time::sleep(PRESSURE_POLL_INTERVAL) => {}
+                    }
+                    let Some(current) = local_pages() else {
+                        break;
+                    };
+                    state.update(current, callback.read().as_ref());
+                }
+            });
+        })
+        .context("Failed to spawn memory pressure thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        high: AtomicUsize,
+        relieved: AtomicUsize,
+    }
+
+    impl PressureCallback for Counter {
+        fn on_high_pressure(&self, _current: usize) {
+            self.high.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_pressure_relieved(&self) {
+            self.relieved.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_signals_once_per_crossing() {
+        let mut state = PressureState::new(MemoryWatermarks {
+            low_pages: 4,
+            high_pages: 8,
+        });
+        let counter = Counter::default();
+        let counts = |counter: &Counter| {
+            (
+                counter.high.load(Ordering::Relaxed),
+                counter.relieved.load(Ordering::Relaxed),
+            )
+        };
+
+        for current in [2, 8, 9, 12, 9, 6, 4] {
+            state.update(current, &counter);
+        }
+        assert_eq!(counts(&counter), (1, 0));
+
+        // Below the low watermark, then back up again
+        for current in [3, 2, 6, 9] {
+            state.update(current, &counter);
+        }
+        assert_eq!(counts(&counter), (2, 1));
+    }
+
+    #[test]
+    fn test_inverted_watermarks_rejected() {
+        let callback: Arc<RwLock<Box<dyn PressureCallback>>> =
+            Arc::new(RwLock::new(Box::new(LogPressure)));
+        let result = start_monitor(
+            MemoryWatermarks {
+                low_pages: 8,
+                high_pages: 4,
+            },
+            || None,
+            callback,
+            Arc::new(ShutdownSignal::default()),
+        );
+        assert!(result.is_err());
+    }
+}
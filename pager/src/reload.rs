@@ -0,0 +1,318 @@
+//! Live configuration reload on SIGHUP
+//!
+//! The pager reads a small TOML file of settings that may change without a
+//! restart. On `SIGHUP` a `ControlMessage::ConfigReload` is delivered to every
+//! subscribed channel; the pager's main loop then re-reads the file and applies
+//! the differences.
+//!
+//! Example file:
+//! ```toml
+//! worker_threads = 4
+//! fetch_timeout_ms = 5000
+//! prefetch_depth = 2
+//! bandwidth_limit_mbps = 1000
+//! ```
+
+use crate::balancing::Rebalance;
+use anyhow::{anyhow, Context, Result};
+use crossbeam_channel::Sender;
+use log::{info, warn};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+/// Settings that can be changed on a running pager
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadableConfig {
+    /// Number of pager worker threads
+    pub worker_threads: usize,
+    /// Time a fault waits on a remote fetch before giving up on it and
+    /// retrying
+    #[serde(rename = "fetch_timeout_ms", with = "duration_ms")]
+    pub fetch_timeout: Duration,
+    /// Pages to prefetch after a remote fault, fewer unless access is
+    /// sequential (0 disables prefetch)
+    pub prefetch_depth: usize,
+    /// Cap on all transport traffic together (None = unlimited)
+    pub bandwidth_limit_mbps: Option<u64>,
+}
+
+impl Default for ReloadableConfig {
+    fn default() -> Self {
+        Self {
+            worker_threads: 2,
+            fetch_timeout: Duration::from_secs(5),
+            prefetch_depth: 0,
+            bandwidth_limit_mbps: None,
+        }
+    }
+}
+
+impl ReloadableConfig {
+    /// Parse and validate a TOML config file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.worker_threads == 0 {
+            return Err(anyhow!("worker_threads must be at least 1"));
+        }
+        if self.fetch_timeout.is_zero() {
+            return Err(anyhow!("fetch_timeout_ms must be non-zero"));
+        }
+        if self.bandwidth_limit_mbps == Some(0) {
+            return Err(anyhow!("bandwidth_limit_mbps must be non-zero when set"));
+        }
+        Ok(())
+    }
+}
+
+/// Serialize `Duration` as integer milliseconds
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_millis)
+    }
+}
+
+/// Messages delivered to the pager's main loop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Re-read the config file and apply changes
+    ConfigReload,
+    /// Another node claimed a page first (see `speculation`)
+    AbortSpeculative { page_num: u64, owner: u32 },
+    /// Migrate local pages to a less loaded node (see `balancing`)
+    Rebalance(Rebalance),
+    /// Install these pages, fetched into the page cache (see `prefault`)
+    Prefault(Vec<u64>),
+}
+
+/// Holds the live config and the file it is reloaded from
+pub struct ConfigReloader {
+    path: PathBuf,
+    current: Arc<RwLock<ReloadableConfig>>,
+}
+
+impl ConfigReloader {
+    /// Load the initial config from `path`
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let config = ReloadableConfig::from_file(&path)?;
+        Ok(Self {
+            path,
+            current: Arc::new(RwLock::new(config)),
+        })
+    }
+
+    /// Shared handle to the live config
+    pub fn config(&self) -> Arc<RwLock<ReloadableConfig>> {
+        Arc::clone(&self.current)
+    }
+
+    /// Path the config is read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-read the file and swap in the new config
+    ///
+    /// Returns the previous config so the caller can act on what changed. On
+    /// error the live config is left untouched.
+    pub fn reload(&self) -> Result<ReloadableConfig> {
+        let new = ReloadableConfig::from_file(&self.path)?;
+        let old = std::mem::replace(&mut *self.current.write(), new.clone());
+
+        if old != new {
+            info!(
+                "Reloaded {}: worker_threads {} -> {}, fetch_timeout {:?} -> {:?}, \
+                 prefetch_depth {} -> {}, bandwidth_limit_mbps {:?} -> {:?}",
+                self.path.display(),
+                old.worker_threads,
+                new.worker_threads,
+                old.fetch_timeout,
+                new.fetch_timeout,
+                old.prefetch_depth,
+                new.prefetch_depth,
+                old.bandwidth_limit_mbps,
+                new.bandwidth_limit_mbps,
+            );
+        } else {
+            info!("Reloaded {}: no changes", self.path.display());
+        }
+
+        Ok(old)
+    }
+}
+
+/// Write end of the self-pipe the signal handler pokes (-1 until installed)
+static SIGHUP_PIPE_WR: AtomicI32 = AtomicI32::new(-1);
+static SIGHUP_INSTALL: Once = Once::new();
+static SIGHUP_SUBSCRIBERS: Mutex<Vec<Arc<Sender<ControlMessage>>>> = Mutex::new(Vec::new());
+
+extern "C" fn on_sighup(_signal: libc::c_int) {
+    // Only async-signal-safe work here: wake the forwarder thread
+    let fd = SIGHUP_PIPE_WR.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 1u8;
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
+
+/// Deliver `ControlMessage::ConfigReload` to `tx` whenever SIGHUP arrives
+///
+/// The handler is installed once per process; later calls just add another
+/// subscriber. Subscribers whose receiver has been dropped are pruned.
+pub fn install_sighup_handler(tx: Arc<Sender<ControlMessage>>) -> Result<()> {
+    let mut result = Ok(());
+    SIGHUP_INSTALL.call_once(|| result = install_sighup_forwarder());
+    result?;
+
+    if SIGHUP_PIPE_WR.load(Ordering::SeqCst) < 0 {
+        return Err(anyhow!("SIGHUP handler failed to install earlier"));
+    }
+
+    SIGHUP_SUBSCRIBERS.lock().push(tx);
+    Ok(())
+}
+
+fn install_sighup_forwarder() -> Result<()> {
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(anyhow!(
+            "Failed to create SIGHUP pipe: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let [read_fd, write_fd] = fds;
+
+    thread::Builder::new()
+        .name("pager-sighup".to_string())
+        .spawn(move || forward_sighup(read_fd))
+        .context("Failed to spawn SIGHUP forwarder thread")?;
+
+    let action = SigAction::new(
+        SigHandler::Handler(on_sighup),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGHUP, &action) }.context("Failed to install SIGHUP handler")?;
+    SIGHUP_PIPE_WR.store(write_fd, Ordering::SeqCst);
+
+    info!("SIGHUP config reload handler installed");
+    Ok(())
+}
+
+fn forward_sighup(read_fd: libc::c_int) {
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            warn!("SIGHUP pipe read failed, config reload disabled");
+            return;
+        }
+
+        info!("SIGHUP received, requesting config reload");
+        SIGHUP_SUBSCRIBERS
+            .lock()
+            .retain(|tx| tx.send(ControlMessage::ConfigReload).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_config(file: &mut tempfile::NamedTempFile, contents: &str) {
+        let f = file.as_file_mut();
+        f.set_len(0).unwrap();
+        std::io::Seek::rewind(f).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+        f.sync_all().unwrap();
+    }
+
+    #[test]
+    fn test_config_defaults_for_missing_keys() {
+        let config: ReloadableConfig = toml::from_str("prefetch_depth = 3").unwrap();
+        assert_eq!(config.prefetch_depth, 3);
+        assert_eq!(config.worker_threads, 2);
+        assert_eq!(config.fetch_timeout, Duration::from_secs(5));
+        assert_eq!(config.bandwidth_limit_mbps, None);
+    }
+
+    #[test]
+    fn test_config_rejects_invalid_values() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_config(&mut file, "worker_threads = 0\n");
+        assert!(ReloadableConfig::from_file(file.path()).is_err());
+
+        write_config(&mut file, "prefetch_dpeth = 1\n");
+        assert!(ReloadableConfig::from_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_config() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_config(&mut file, "prefetch_depth = 1\n");
+        let reloader = ConfigReloader::new(file.path()).unwrap();
+
+        write_config(&mut file, "prefetch_depth = \"lots\"\n");
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.config().read().prefetch_depth, 1);
+    }
+
+    #[test]
+    fn test_sighup_reloads_prefetch_depth() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_config(
+            &mut file,
+            "worker_threads = 2\nfetch_timeout_ms = 1000\nprefetch_depth = 1\n",
+        );
+        let reloader = ConfigReloader::new(file.path()).unwrap();
+        assert_eq!(reloader.config().read().prefetch_depth, 1);
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        install_sighup_handler(Arc::new(tx)).unwrap();
+
+        write_config(
+            &mut file,
+            "worker_threads = 2\nfetch_timeout_ms = 1000\nprefetch_depth = 8\n",
+        );
+        nix::sys::signal::raise(Signal::SIGHUP).unwrap();
+
+        let msg = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg, ControlMessage::ConfigReload);
+
+        let old = reloader.reload().unwrap();
+        assert_eq!(old.prefetch_depth, 1);
+        assert_eq!(reloader.config().read().prefetch_depth, 8);
+        assert_eq!(
+            reloader.config().read().fetch_timeout,
+            Duration::from_secs(1)
+        );
+    }
+}
@@ -0,0 +1,1279 @@
+//! Multi-node clusters in one process, for tests
+//!
+//! Each node of a `SimulatedCluster` is a full `Pager` over its own mapping,
+//! reaching the others through a `MockNetwork` instead of TCP. The mappings
+//! stand for the same guest memory: `place_page` gives every node the same
+//! view of who owns a page and what it holds. Nodes never contact a
+//! coordinator; what they would learn from it (e.g. cluster load) comes from
+//! the simulation instead.
+
+use crate::affinity::PlacementPolicy;
+use crate::allocator::DEFAULT_POOL_PAGES;
+use crate::balancing::{BalancingAgent, LoadSource};
+use crate::coordinator::{CoordinatorClient, CoordinatorConfig};
+use crate::gossip::GossipConfig;
+use crate::identity::{AuthToken, NodeIdentity};
+use crate::metrics::LoadMetrics;
+use crate::page_size::{GuestPageWalker, PageSizeClass, PageSizeConfig};
+use crate::pattern::AccessPatternDetector;
+use crate::policy::{self, OvercommitPolicy};
+use crate::reload::ControlMessage;
+use crate::{
+    ClusterAuth, PageDirectory, PageOwner, Pager, PagerConfig, PagerMode,
+    DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE, DEFAULT_MAX_CONCURRENT_MIGRATIONS, PAGE_SIZE,
+};
+use anyhow::Result;
+use rdma_transport::transport::mock::{MockNetwork, MockTransport};
+use rdma_transport::{Endpoint as TransportEndpoint, TransportManager};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Token expiry far enough out for any test run
+const TOKEN_EXPIRY: u64 = 4_000_000_000;
+
+/// Pagers wired together over a `MockNetwork`
+pub struct SimulatedCluster {
+    network: Arc<MockNetwork>,
+    nodes: Vec<SimulatedNode>,
+    /// What the nodes run coordinator requests and fetch waits on; dropped
+    /// after them
+    _runtime: tokio::runtime::Runtime,
+}
+
+struct SimulatedNode {
+    pager: Pager,
+    base: u64,
+    len: usize,
+}
+
+impl SimulatedCluster {
+    /// Start `nodes` pagers, each over `pages` pages of fresh memory
+    pub fn new(nodes: usize, pages: usize) -> Self {
+        Self::with_placement(nodes, pages, PlacementPolicy::FirstTouch)
+    }
+
+    /// Like `new`, with every node placing first touches by `placement`
+    pub fn with_placement(nodes: usize, pages: usize, placement: PlacementPolicy) -> Self {
+        let network = MockNetwork::new();
+        let coordinator = NodeIdentity::generate();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let nodes = (0..nodes as u32)
+            .map(|node_id| {
+                Self::start_node(
+                    &network,
+                    runtime.handle(),
+                    &coordinator,
+                    node_id,
+                    nodes as u32,
+                    pages * PAGE_SIZE,
+                    placement,
+                )
+            })
+            .collect();
+        Self {
+            network,
+            nodes,
+            _runtime: runtime,
+        }
+    }
+
+    fn start_node(
+        network: &Arc<MockNetwork>,
+        runtime: &tokio::runtime::Handle,
+        coordinator: &NodeIdentity,
+        node_id: u32,
+        total_nodes: u32,
+        len: usize,
+        placement: PlacementPolicy,
+    ) -> SimulatedNode {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+
+        let mut transport = TransportManager::with_transport(
+            node_id,
+            Box::new(MockTransport::new(node_id, network)),
+        );
+        for peer in (0..total_nodes).filter(|&peer| peer != node_id) {
+            // Mock endpoints only need to be distinct
+            let endpoint = TransportEndpoint::tcp((Ipv4Addr::LOCALHOST, peer as u16).into());
+            transport.connect_peer(peer, endpoint).unwrap();
+        }
+
+        let identity = NodeIdentity::generate();
+        let auth = ClusterAuth {
+            token: AuthToken::issue(
+                coordinator,
+                node_id,
+                &identity.public_key_hex(),
+                TOKEN_EXPIRY,
+            ),
+            coordinator_key: coordinator.verifying_key(),
+        };
+        let config = PagerConfig {
+            base: base as *mut u8,
+            len,
+            node_id,
+            total_nodes,
+            // Never contacted
+            coordinator: CoordinatorConfig::single("http://127.0.0.1:1"),
+            identity_key_path: None,
+            push_gateway: None,
+            overcommit: OvercommitPolicy::Strict,
+            replacement_policy: policy::default_policy(),
+            placement,
+            watermarks: None,
+            auto_balance: false,
+            checkpoint_path: None,
+            checkpoint_interval: None,
+            compaction_interval: None,
+            placement_hints: false,
+            max_concurrent_fetches_per_node: DEFAULT_MAX_CONCURRENT_FETCHES_PER_NODE,
+            max_concurrent_migrations: DEFAULT_MAX_CONCURRENT_MIGRATIONS,
+            // Every node runs on this host
+            require_unique_fingerprint: false,
+            mode: PagerMode::default(),
+            page_size: PageSizeConfig::default(),
+            reconnect: None,
+            prefetch_pages: 0,
+            pool_capacity: DEFAULT_POOL_PAGES,
+            fault_threads: 1,
+            coordinator_timeout_secs: 5,
+            metrics_port: None,
+        };
+        let uffd = Pager::register_uffd(config.base, len, config.mode).unwrap();
+        let client = CoordinatorClient::new(config.coordinator.clone(), node_id).unwrap();
+        client.authorize(auth.bearer());
+        let pager =
+            Pager::from_parts(config, uffd, transport, auth, client, runtime.clone()).unwrap();
+
+        SimulatedNode {
+            pager,
+            base: base as u64,
+            len,
+        }
+    }
+
+    pub fn pager(&self, node: usize) -> &Pager {
+        &self.nodes[node].pager
+    }
+
+    /// Deliver the control messages each node's fault loop would pick up
+    pub fn process_control_messages(&mut self) {
+        for node in &mut self.nodes {
+            node.pager.process_control_messages();
+        }
+    }
+
+    /// Have `node` read its live settings from `path` (see `reload`)
+    pub fn load_config(&mut self, node: usize, path: &Path) {
+        self.nodes[node]
+            .pager
+            .enable_config_reload(path.to_path_buf())
+            .unwrap();
+    }
+
+    /// Have `node` re-read its config file, as it does on SIGHUP
+    pub fn reload_config(&mut self, node: usize) {
+        let pager = &mut self.nodes[node].pager;
+        pager.control_tx.send(ControlMessage::ConfigReload).unwrap();
+        pager.process_control_messages();
+    }
+
+    /// Balance `node`'s pages every `interval` against the cluster's page
+    /// counts (see `pages_owned`)
+    pub fn start_balancing(&mut self, node: usize, interval: Duration) {
+        let agent = BalancingAgent::new(node as u32, Box::new(self.load())).interval(interval);
+        self.nodes[node].pager.start_balancing(agent).unwrap();
+    }
+
+    /// Gossip page claims among all nodes over the multicast group in
+    /// `config`
+    pub fn start_gossip(&mut self, config: GossipConfig) {
+        for node in &mut self.nodes {
+            node.pager.start_gossip(config).unwrap();
+        }
+    }
+
+    /// Pages `node` holds, locally or as the target of a migration
+    pub fn pages_owned(&self, node: usize) -> u64 {
+        self.load().pages_owned(node)
+    }
+
+    fn load(&self) -> SimulatedLoad {
+        SimulatedLoad {
+            network: Arc::clone(&self.network),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| (Arc::clone(node.pager.directory()), node.base, node.len))
+                .collect(),
+        }
+    }
+
+    /// Address of `page_num` in `node`'s mapping
+    pub fn page_addr(&self, node: usize, page_num: u64) -> u64 {
+        self.nodes[node].base + page_num * PAGE_SIZE as u64
+    }
+
+    /// Make `owner` hold `data` at `page_num`, and every other node know it
+    pub fn place_page(&self, page_num: u64, owner: usize, data: &[u8]) {
+        self.fault(owner, page_num).unwrap();
+        // SAFETY: the page was just installed in `owner`'s mapping
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.page_addr(owner, page_num) as *mut u8,
+                PAGE_SIZE,
+            )
+        };
+
+        for node in (0..self.nodes.len()).filter(|&node| node != owner) {
+            self.pager(node)
+                .directory()
+                .set_owner(page_num, PageOwner::Remote(owner as u32));
+            // What the owner serves for this node's address of the page
+            self.network
+                .store_page(owner as u32, self.page_addr(node, page_num), data);
+        }
+    }
+
+    /// Fault on `page_num` at `node`, returning what the page then holds
+    ///
+    /// Each calling thread stands for one of `node`'s fault threads, with
+    /// its own access pattern.
+    pub fn fault(&self, node: usize, page_num: u64) -> Result<Vec<u8>> {
+        thread_local! {
+            static PATTERNS: RefCell<HashMap<usize, AccessPatternDetector>> =
+                RefCell::new(HashMap::new());
+        }
+        let addr = self.page_addr(node, page_num);
+        PATTERNS.with_borrow_mut(|patterns| {
+            self.pager(node)
+                .handle_pagefault(addr, patterns.entry(node).or_default())
+        })?;
+        // SAFETY: the fault installed the page
+        Ok(unsafe { std::slice::from_raw_parts(addr as *const u8, PAGE_SIZE) }.to_vec())
+    }
+
+    /// Most fetches between nodes that were under way at once
+    pub fn peak_fetches_in_flight(&self) -> usize {
+        self.network.peak_fetches_in_flight()
+    }
+
+    /// Make every fetch between nodes take `delay`
+    pub fn set_fetch_delay(&self, delay: Duration) {
+        self.network.set_fetch_delay(delay);
+    }
+
+    /// Make `node`'s next `count` fetches of `page_num` from `owner` fail
+    pub fn fail_fetches(&self, owner: usize, node: usize, page_num: u64, count: usize) {
+        self.network
+            .fail_fetches(owner as u32, self.page_addr(node, page_num), count);
+    }
+
+    /// Cut every link between `group_a` and `group_b` until the guard drops
+    pub fn partition(&self, group_a: &[usize], group_b: &[usize]) -> PartitionGuard {
+        let links: Vec<(u32, u32)> = group_a
+            .iter()
+            .flat_map(|&a| group_b.iter().map(move |&b| (a as u32, b as u32)))
+            .collect();
+        for &(a, b) in &links {
+            self.network.cut(a, b);
+        }
+        PartitionGuard {
+            network: Arc::clone(&self.network),
+            links,
+        }
+    }
+}
+
+impl Drop for SimulatedCluster {
+    fn drop(&mut self) {
+        for node in self.nodes.drain(..) {
+            node.pager.shutdown.trigger();
+            // Unregister the mapping before unmapping it
+            drop(node.pager);
+            unsafe { libc::munmap(node.base as *mut libc::c_void, node.len) };
+        }
+    }
+}
+
+/// Page counts of a simulated cluster, as the coordinator would report them
+struct SimulatedLoad {
+    network: Arc<MockNetwork>,
+    /// Directory, base and length of each node's mapping
+    nodes: Vec<(Arc<PageDirectory>, u64, usize)>,
+}
+
+impl SimulatedLoad {
+    fn pages_owned(&self, node: usize) -> u64 {
+        let (directory, _, len) = &self.nodes[node];
+        let mut pages: HashSet<u64> = (0..(len / PAGE_SIZE) as u64)
+            .filter(|&page_num| directory.get_owner(page_num) == PageOwner::Local)
+            .collect();
+        // Pages migrated here were sent at the sender's address of the page
+        for gpa in self.network.stored_pages(node as u32) {
+            let sender = self
+                .nodes
+                .iter()
+                .find(|&&(_, base, len)| (base..base + len as u64).contains(&gpa));
+            if let Some((_, base, _)) = sender {
+                pages.insert((gpa - base) / PAGE_SIZE as u64);
+            }
+        }
+        pages.len() as u64
+    }
+}
+
+impl LoadSource for SimulatedLoad {
+    fn cluster_load(&self) -> Result<BTreeMap<u32, LoadMetrics>> {
+        Ok((0..self.nodes.len())
+            .map(|node| {
+                let load = LoadMetrics {
+                    pages_owned: self.pages_owned(node),
+                    fault_rate_per_sec: 0.0,
+                    transport_bw_utilized_pct: 0.0,
+                    cpu_utilization_pct: 0.0,
+                };
+                (node as u32, load)
+            })
+            .collect())
+    }
+}
+
+/// A network partition; dropping it heals the partition
+pub struct PartitionGuard {
+    network: Arc<MockNetwork>,
+    links: Vec<(u32, u32)>,
+}
+
+impl Drop for PartitionGuard {
+    fn drop(&mut self) {
+        for &(a, b) in &self.links {
+            self.network.restore(a, b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_log::{AccessHandler, FaultType};
+    use crate::cache::PageCache;
+    use crate::compressor::ColdPageCompressor;
+    use crate::dlq::DEFAULT_MAX_FAULT_RETRIES;
+    use crate::metrics::BalloonStats;
+    use crate::migration;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Instant;
+    use userfaultfd::{Event, FaultKind};
+
+    #[test]
+    fn test_isolated_node_falls_back_to_zeros() {
+        let cluster = SimulatedCluster::new(2, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+
+        let _partition = cluster.partition(&[0], &[1]);
+        assert_eq!(cluster.fault(0, 3).unwrap(), vec![0; PAGE_SIZE]);
+        // Still owned by the unreachable node
+        assert_eq!(
+            cluster.pager(0).directory().get_owner(3),
+            PageOwner::Remote(1)
+        );
+    }
+
+    #[test]
+    fn test_pages_fetchable_after_partition_heals() {
+        let cluster = SimulatedCluster::new(2, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+        cluster.place_page(4, 1, &[0x43; PAGE_SIZE]);
+
+        let partition = cluster.partition(&[0], &[1]);
+        assert_eq!(cluster.fault(0, 3).unwrap(), vec![0; PAGE_SIZE]);
+        drop(partition);
+
+        assert_eq!(cluster.fault(0, 4).unwrap(), vec![0x43; PAGE_SIZE]);
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.timeout_faults, 1);
+        assert_eq!(stats.remote_faults, 1);
+    }
+
+    #[test]
+    fn test_timeout_faults_count_cross_partition_faults() {
+        let cluster = SimulatedCluster::new(3, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+        cluster.place_page(4, 2, &[0x44; PAGE_SIZE]);
+        cluster.place_page(5, 1, &[0x45; PAGE_SIZE]);
+
+        let _partition = cluster.partition(&[0, 2], &[1]);
+        cluster.fault(0, 3).unwrap();
+        assert_eq!(cluster.pager(0).get_stats().timeout_faults, 1);
+        cluster.fault(0, 5).unwrap();
+        assert_eq!(cluster.pager(0).get_stats().timeout_faults, 2);
+
+        // Node 2 is on this side of the partition
+        assert_eq!(cluster.fault(0, 4).unwrap(), vec![0x44; PAGE_SIZE]);
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.timeout_faults, 2);
+        assert_eq!(stats.remote_faults, 1);
+        // Unaffected by node 0's partition view
+        assert_eq!(cluster.pager(2).get_stats().timeout_faults, 0);
+    }
+
+    #[test]
+    fn test_cancelled_migration_abandons_slow_fetch() {
+        let cluster = SimulatedCluster::new(2, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+        cluster.set_fetch_delay(Duration::from_secs(1));
+
+        let pager = cluster.pager(0);
+        let addr = cluster.page_addr(0, 3);
+        let (_, epoch) = pager.directory().read_with_epoch(3);
+        let started = Instant::now();
+        let result = thread::scope(|scope| {
+            let fetch = scope.spawn(|| pager.fetch_remote_page(addr, 1, epoch));
+            thread::sleep(Duration::from_millis(100));
+            pager.migration_coordinator().cancel();
+            fetch.join().unwrap()
+        });
+
+        assert!(migration::is_cancelled(&result.unwrap_err()));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(pager.get_stats().cancelled_fetches, 1);
+    }
+
+    #[test]
+    fn test_repeatedly_cancelled_fault_dead_lettered() {
+        let cluster = SimulatedCluster::new(2, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+        cluster.set_fetch_delay(Duration::from_secs(1));
+
+        let pager = cluster.pager(0);
+        let done = AtomicBool::new(false);
+        let page = thread::scope(|scope| {
+            scope.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(20));
+                    pager.migration_coordinator().cancel();
+                }
+            });
+            let page = cluster.fault(0, 3).unwrap();
+            done.store(true, Ordering::SeqCst);
+            page
+        });
+
+        // Every retry was cancelled as well
+        assert_eq!(page, vec![0; PAGE_SIZE]);
+        let stats = pager.get_stats();
+        assert_eq!(
+            stats.cancelled_fetches,
+            DEFAULT_MAX_FAULT_RETRIES as u64 + 1
+        );
+        assert_eq!(stats.dead_lettered_faults, 1);
+    }
+
+    #[test]
+    fn test_reloaded_fetch_timeout_gives_up_on_slow_fetches() {
+        let mut cluster = SimulatedCluster::new(2, 8);
+        cluster.place_page(3, 1, &[0x42; PAGE_SIZE]);
+        cluster.place_page(4, 1, &[0x43; PAGE_SIZE]);
+        let config = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(config.path(), "fetch_timeout_ms = 5000\n").unwrap();
+        cluster.load_config(0, config.path());
+        cluster.set_fetch_delay(Duration::from_millis(200));
+        assert_eq!(cluster.fault(0, 3).unwrap(), vec![0x42; PAGE_SIZE]);
+
+        std::fs::write(config.path(), "fetch_timeout_ms = 10\n").unwrap();
+        cluster.reload_config(0);
+        cluster.set_fetch_delay(Duration::from_secs(1));
+        let started = Instant::now();
+        // Every retry times out as well, backoff included well before one
+        // fetch would have finished
+        assert_eq!(cluster.fault(0, 4).unwrap(), vec![0; PAGE_SIZE]);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.fetch_timeouts, DEFAULT_MAX_FAULT_RETRIES as u64 + 1);
+        assert_eq!(stats.dead_lettered_faults, 1);
+    }
+
+    #[test]
+    fn test_reloaded_prefetch_depth_prefetches_remote_pages() {
+        let mut cluster = SimulatedCluster::new(2, 16);
+        for page_num in 0..16 {
+            cluster.place_page(page_num, 1, &[page_num as u8; PAGE_SIZE]);
+        }
+        let config = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(config.path(), "prefetch_depth = 0\n").unwrap();
+        cluster.load_config(0, config.path());
+        for page_num in 0..6 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        assert_eq!(cluster.pager(0).get_stats().cache_hits, 0);
+
+        std::fs::write(config.path(), "prefetch_depth = 4\n").unwrap();
+        cluster.reload_config(0);
+        // The fault on page 6 brings in pages 7 to 10
+        for page_num in 6..11 {
+            assert_eq!(
+                cluster.fault(0, page_num).unwrap(),
+                vec![page_num as u8; PAGE_SIZE]
+            );
+        }
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.cache_hits, 4);
+        assert_eq!(stats.remote_faults, 11);
+    }
+
+    #[test]
+    fn test_reloaded_bandwidth_limit_slows_faults() {
+        let mut cluster = SimulatedCluster::new(2, 8);
+        for page_num in 0..8 {
+            cluster.place_page(page_num, 1, &[1; PAGE_SIZE]);
+        }
+        let config = tempfile::NamedTempFile::new().unwrap();
+        cluster.load_config(0, config.path());
+        let started = Instant::now();
+        for page_num in 0..4 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        std::fs::write(config.path(), "bandwidth_limit_mbps = 1\n").unwrap();
+        cluster.reload_config(0);
+        // 1 Mbps fits a page every 33 ms, after a one page burst
+        let started = Instant::now();
+        for page_num in 4..8 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn test_fault_latency_recorded_by_fault_type() {
+        let cluster = SimulatedCluster::new(2, 64);
+        for page_num in 0..3 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        cluster.pager(0).directory().set_owner(10, PageOwner::Local);
+        cluster.fault(0, 10).unwrap();
+        cluster.place_page(20, 1, &[1; PAGE_SIZE]);
+        cluster.place_page(21, 1, &[2; PAGE_SIZE]);
+        cluster.fault(0, 20).unwrap();
+        cluster.fault(0, 21).unwrap();
+
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.first_touch_latency_us.count(), 3);
+        assert_eq!(stats.local_resolved_latency_us.count(), 1);
+        assert_eq!(stats.remote_fetch_latency_us.count(), 2);
+        assert!(stats.remote_fetch_p99_us().is_some());
+        // Node 1 only claimed the pages it placed
+        let stats = cluster.pager(1).get_stats();
+        assert_eq!(stats.first_touch_latency_us.count(), 2);
+        assert_eq!(stats.remote_fetch_p99_us(), None);
+    }
+
+    #[test]
+    fn test_deflated_pages_fault_as_first_touch() {
+        let cluster = SimulatedCluster::new(2, 64);
+        for page_num in 0..4 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        cluster.place_page(8, 1, &[5; PAGE_SIZE]);
+        assert_eq!(cluster.fault(0, 8).unwrap(), vec![5; PAGE_SIZE]);
+        // SAFETY: page 1 was installed by the fault above
+        unsafe { *(cluster.page_addr(0, 1) as *mut u8) = 0xaa };
+
+        let pager = cluster.pager(0);
+        let gpas = [PAGE_SIZE as u64, 8 * PAGE_SIZE as u64];
+        pager.handle_balloon_deflate(&gpas).unwrap();
+        assert_eq!(pager.directory().page_count(), 3);
+        assert_eq!(pager.directory().get_owner(8), PageOwner::Unknown);
+        assert_eq!(pager.get_stats().gc_entries_freed, 2);
+
+        // Both come back as fresh zero pages claimed here
+        let first_touches = pager.get_stats().first_touch_latency_us.count();
+        assert_eq!(cluster.fault(0, 1).unwrap(), vec![0; PAGE_SIZE]);
+        assert_eq!(cluster.fault(0, 8).unwrap(), vec![0; PAGE_SIZE]);
+        assert_eq!(
+            pager.get_stats().first_touch_latency_us.count(),
+            first_touches + 2
+        );
+        assert!(pager.handle_balloon_deflate(&[1 << 40]).is_err());
+    }
+
+    #[test]
+    fn test_cold_pages_compressed_until_touched() {
+        let mut cluster = SimulatedCluster::new(2, 64);
+        cluster.place_page(0, 0, &[9; PAGE_SIZE]);
+        // Does not shrink
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..PAGE_SIZE)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect();
+        cluster.place_page(1, 0, &noise);
+        cluster.place_page(2, 0, &[3; PAGE_SIZE]);
+        cluster.place_page(5, 1, &[4; PAGE_SIZE]);
+
+        let stats = Arc::clone(&cluster.nodes[0].pager.stats);
+        let compressor = Arc::new(ColdPageCompressor::new(stats));
+        cluster.nodes[0].pager.compressor = Some(Arc::clone(&compressor));
+        compressor.record_access(2);
+
+        let pager = cluster.pager(0);
+        let compress_cold = || {
+            compressor
+                .compress_cold(&pager.uffd, pager.base, &pager.directory)
+                .unwrap()
+        };
+        assert_eq!(compress_cold(), 1);
+        assert!(compressor.is_compressed(0));
+        assert!(!pager.is_present(0).unwrap());
+        assert!(pager.is_present(1).unwrap());
+        let stats = pager.get_stats();
+        assert_eq!(stats.compressed_pages, 1);
+        assert!(stats.bytes_saved > PAGE_SIZE as u64 / 2);
+
+        assert_eq!(cluster.fault(0, 0).unwrap(), vec![9; PAGE_SIZE]);
+        let stats = pager.get_stats();
+        assert_eq!((stats.compressed_pages, stats.bytes_saved), (0, 0));
+
+        // Page 0 was just faulted on; page 2 has cooled down
+        assert_eq!(compress_cold(), 1);
+        pager.migrate_page(2, 1).unwrap();
+        assert_eq!(pager.directory().get_owner(2), PageOwner::Remote(1));
+        assert!(!compressor.is_compressed(2));
+        assert_eq!(pager.get_stats().compressed_pages, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "hugepages")]
+    fn test_first_touches_resolved_with_huge_pages() {
+        use crate::HUGE_PAGE_PAGES;
+
+        // 8 MiB
+        let mut cluster = SimulatedCluster::new(1, 4 * HUGE_PAGE_PAGES as usize);
+        cluster.nodes[0].pager.page_size = PageSizeConfig::Huge2M;
+
+        for huge_page in 0..4 {
+            // Anywhere in the huge page
+            let page_num = huge_page * HUGE_PAGE_PAGES + 17;
+            assert_eq!(cluster.fault(0, page_num).unwrap(), vec![0; PAGE_SIZE]);
+        }
+        let pager = cluster.pager(0);
+        assert_eq!(pager.directory().mapping_count(), 4);
+        assert_eq!(pager.directory().page_count(), 4 * HUGE_PAGE_PAGES as usize);
+        assert_eq!(
+            pager.directory().local_page_count(),
+            4 * HUGE_PAGE_PAGES as usize
+        );
+        // One fault filled each whole huge page
+        assert!(pager.is_present(0).unwrap());
+        assert!(pager.is_present(4 * HUGE_PAGE_PAGES - 1).unwrap());
+        let stats = pager.get_stats();
+        assert_eq!(stats.local_faults, 4);
+        assert_eq!(stats.huge_page_promotions, 4);
+    }
+
+    /// Whether the page at `addr` is write-protected, from its pagemap
+    /// entry's uffd-wp bit
+    fn is_write_protected(addr: u64) -> bool {
+        use std::os::unix::fs::FileExt;
+        let mut entry = [0u8; 8];
+        std::fs::File::open("/proc/self/pagemap")
+            .unwrap()
+            .read_exact_at(&mut entry, addr / PAGE_SIZE as u64 * 8)
+            .unwrap();
+        u64::from_ne_bytes(entry) & (1 << 57) != 0
+    }
+
+    #[test]
+    fn test_write_to_protected_page_unprotects_it() {
+        let cluster = SimulatedCluster::new(1, 16);
+        cluster.place_page(3, 0, &[7; PAGE_SIZE]);
+        let pager = cluster.pager(0);
+        let addr = cluster.page_addr(0, 3);
+        pager.write_protect_page(3).unwrap();
+        assert!(is_write_protected(addr));
+
+        // Blocks until the fault is resolved
+        let writer = thread::spawn(move || unsafe { *(addr as *mut u8) = 1 });
+        let Some(Event::Pagefault {
+            kind: FaultKind::WriteProtected,
+            addr: fault_addr,
+            ..
+        }) = pager.next_event(5000).unwrap()
+        else {
+            panic!("expected a write-protect fault");
+        };
+        pager.handle_write_protect_fault(fault_addr as u64).unwrap();
+        writer.join().unwrap();
+
+        assert!(!is_write_protected(addr));
+        assert_eq!(unsafe { *(addr as *const u8) }, 1);
+        assert_eq!(pager.get_stats().write_protect_faults, 1);
+        // The copy was taken before the write
+        let written = pager.take_written_pages();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[&3], vec![7; PAGE_SIZE]);
+        assert!(pager.take_written_pages().is_empty());
+
+        assert!(pager.write_protect_page(9).is_err());
+    }
+
+    #[derive(Default)]
+    struct RecordingHandler {
+        accesses: parking_lot::Mutex<Vec<(u64, FaultType)>>,
+    }
+
+    impl AccessHandler for RecordingHandler {
+        fn on_access(
+            &self,
+            gpa: u64,
+            fault_type: FaultType,
+            _timestamp: std::time::SystemTime,
+        ) -> Result<()> {
+            self.accesses.lock().push((gpa, fault_type));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_accesses_in_registered_range_logged() {
+        let cluster = SimulatedCluster::new(2, 64);
+        cluster.place_page(5, 1, &[4; PAGE_SIZE]);
+        cluster.place_page(9, 1, &[4; PAGE_SIZE]);
+
+        let handler = Arc::new(RecordingHandler::default());
+        let page = PAGE_SIZE as u64;
+        cluster
+            .pager(0)
+            .access_logger()
+            .register_range(2 * page, 6 * page, handler.clone());
+
+        cluster.fault(0, 3).unwrap();
+        cluster.fault(0, 5).unwrap();
+        // Already local, but not mapped
+        cluster.pager(0).directory().set_owner(4, PageOwner::Local);
+        cluster.fault(0, 4).unwrap();
+        // Outside the range
+        cluster.fault(0, 1).unwrap();
+        cluster.fault(0, 6).unwrap();
+        cluster.fault(0, 9).unwrap();
+        // On another node
+        cluster.fault(1, 2).unwrap();
+
+        assert_eq!(
+            *handler.accesses.lock(),
+            [
+                (3 * page, FaultType::FirstTouch),
+                (5 * page, FaultType::Remote),
+                (4 * page, FaultType::Local),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_first_touches_follow_range_affinity() {
+        let cluster =
+            SimulatedCluster::with_placement(2, 1024, PlacementPolicy::AffinityRoundRobin);
+        // Node 0's first fault in a range is placed on node 0, its second
+        // range's first on node 1
+        assert_eq!(cluster.fault(0, 0).unwrap(), vec![0; PAGE_SIZE]);
+        assert_eq!(cluster.fault(0, 512).unwrap(), vec![0; PAGE_SIZE]);
+        let pager = cluster.pager(0);
+        assert_eq!(pager.directory().get_owner(0), PageOwner::Local);
+        assert_eq!(pager.directory().get_owner(512), PageOwner::Remote(1));
+        assert_eq!(pager.get_stats().remote_placements, 1);
+        assert_eq!(pager.get_stats().remote_faults, 1);
+
+        // The rest of each range follows node 0
+        for page_num in [1, 2, 513, 514] {
+            cluster.fault(0, page_num).unwrap();
+            assert_eq!(pager.directory().get_owner(page_num), PageOwner::Local);
+        }
+        assert_eq!(pager.get_stats().remote_placements, 1);
+    }
+
+    #[test]
+    fn test_first_touches_follow_coordinator_hints() {
+        let mut server = mockito::Server::new();
+        let hinted = server
+            .mock("GET", "/pages/3/placement")
+            .with_body(r#"{"page_num": 3, "preferred_node": 1, "reason": "least loaded"}"#)
+            .expect(1)
+            .create();
+        server
+            .mock("GET", "/pages/4/placement")
+            .with_body(r#"{"page_num": 4, "preferred_node": null, "reason": "no load reported"}"#)
+            .create();
+
+        let mut cluster = SimulatedCluster::new(2, 64);
+        let pager = &mut cluster.nodes[0].pager;
+        pager.coordinator =
+            Arc::new(CoordinatorClient::new(CoordinatorConfig::single(&server.url()), 0).unwrap());
+        pager.placement_hints = true;
+
+        cluster.fault(0, 3).unwrap();
+        let pager = cluster.pager(0);
+        assert_eq!(pager.directory().get_owner(3), PageOwner::Remote(1));
+        // No preference, and no answer at all (page 5), leave it to the
+        // local policy
+        cluster.fault(0, 4).unwrap();
+        cluster.fault(0, 5).unwrap();
+        assert_eq!(pager.directory().get_owner(4), PageOwner::Local);
+        assert_eq!(pager.directory().get_owner(5), PageOwner::Local);
+        assert_eq!(pager.get_stats().coordinator_hint_hits, 1);
+
+        // Cached
+        assert_eq!(
+            pager.query_placement_hint(3).unwrap().preferred_node,
+            Some(1)
+        );
+        hinted.assert();
+    }
+
+    #[test]
+    fn test_balloon_stats_add_up() {
+        let mut cluster = SimulatedCluster::new(2, 64);
+        for page_num in 0..10 {
+            cluster.place_page(page_num, 0, &[1; PAGE_SIZE]);
+        }
+        for page_num in 10..16 {
+            cluster.place_page(page_num, 1, &[2; PAGE_SIZE]);
+        }
+        let stats = Arc::clone(&cluster.nodes[0].pager.stats);
+        let compressor = Arc::new(ColdPageCompressor::new(stats));
+        cluster.nodes[0].pager.compressor = Some(Arc::clone(&compressor));
+
+        let pager = cluster.pager(0);
+        assert_eq!(
+            compressor
+                .compress_cold(&pager.uffd, pager.base, &pager.directory)
+                .unwrap(),
+            10
+        );
+        // Given up on twice, counted once
+        for _ in 0..2 {
+            pager
+                .dead_letter(cluster.page_addr(0, 12), anyhow::anyhow!("corrupt"))
+                .unwrap();
+        }
+
+        let balloon = pager.balloon_stats();
+        assert_eq!(
+            balloon,
+            BalloonStats {
+                total_pages: 64,
+                local_pages: 10,
+                remote_pages: 5,
+                unknown_pages: 49,
+                compressed_pages: 10,
+                dlq_pages: 1,
+            }
+        );
+        assert_eq!(
+            balloon.local_pages + balloon.remote_pages + balloon.unknown_pages,
+            balloon.total_pages
+        );
+        assert!(balloon.compressed_pages <= balloon.local_pages);
+        assert!(balloon.dlq_pages <= balloon.unknown_pages);
+        assert_eq!(balloon.fill_ratio(), 10.0 / 64.0);
+    }
+
+    #[test]
+    fn test_prefaulted_pages_need_no_fault() {
+        let cluster = SimulatedCluster::new(3, 64);
+        cluster.fault(0, 0).unwrap();
+        cluster.place_page(8, 1, &[3; PAGE_SIZE]);
+        cluster.place_page(9, 2, &[4; PAGE_SIZE]);
+        cluster.place_page(20, 1, &[5; PAGE_SIZE]);
+
+        let pager = cluster.pager(0);
+        let page = PAGE_SIZE as u64;
+        // Widened to pages 0-9: page 0 is local, 1-7 untouched
+        assert_eq!(pager.prefault_region(0, 9 * page + 1).unwrap(), 2);
+        // SAFETY: both pages were installed by the prefault
+        let (page_8, page_9) = unsafe {
+            (
+                *(cluster.page_addr(0, 8) as *const u8),
+                *(cluster.page_addr(0, 9) as *const u8),
+            )
+        };
+        assert_eq!((page_8, page_9), (3, 4));
+        let stats = pager.get_stats();
+        assert_eq!((stats.proactive_installs, stats.remote_faults), (2, 0));
+
+        // Already present
+        assert_eq!(pager.prefault_region(8 * page, 10 * page).unwrap(), 0);
+        assert!(pager.prefault_region(0, 65 * page).is_err());
+
+        // Unreachable owners are left to fault later
+        let _partition = cluster.partition(&[0], &[1]);
+        assert_eq!(pager.prefault_region(20 * page, 21 * page).unwrap(), 0);
+        assert_eq!(pager.get_stats().proactive_installs, 2);
+    }
+
+    #[test]
+    fn test_prefaulter_installs_hinted_pages() {
+        let mut cluster = SimulatedCluster::new(2, 64);
+        cluster.place_page(4, 1, &[6; PAGE_SIZE]);
+        cluster.place_page(5, 1, &[7; PAGE_SIZE]);
+
+        let prefaulter = cluster.pager(0).prefaulter().unwrap();
+        let page = PAGE_SIZE as u64;
+        prefaulter.hint(vec![4 * page, 5 * page, 6 * page]).unwrap();
+        // Finishes the hint, fetching both pages into the cache
+        drop(prefaulter);
+        assert_eq!(cluster.pager(0).page_cache().len(), 2);
+
+        // Cut off: the fault loop installs from the cache
+        let _partition = cluster.partition(&[0], &[1]);
+        cluster.process_control_messages();
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!((stats.proactive_installs, stats.remote_faults), (2, 0));
+        // SAFETY: the fault loop installed the page
+        assert_eq!(unsafe { *(cluster.page_addr(0, 5) as *const u8) }, 7);
+    }
+
+    #[test]
+    fn test_prefaulter_hints_do_not_corrupt_concurrent_faults() {
+        let mut cluster = SimulatedCluster::new(2, 128);
+        // Far smaller than the pages in play, so every insert evicts
+        cluster.nodes[0].pager.cache = Arc::new(PageCache::new(4 * PAGE_SIZE).unwrap());
+        for page_num in 0..128 {
+            cluster.place_page(page_num, 1, &[page_num as u8; PAGE_SIZE]);
+        }
+
+        let prefaulter = cluster.pager(0).prefaulter().unwrap();
+        let page = PAGE_SIZE as u64;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    prefaulter
+                        .hint((64..128).map(|page_num| page_num * page).collect())
+                        .unwrap();
+                }
+            });
+            for page_num in 0..64 {
+                assert_eq!(
+                    cluster.fault(0, page_num).unwrap(),
+                    vec![page_num as u8; PAGE_SIZE]
+                );
+            }
+        });
+        drop(prefaulter);
+        cluster.process_control_messages();
+        // Whatever was still cached when the fault loop got to it
+        let addrs = (64..128).map(|page_num| cluster.page_addr(0, page_num));
+        for addr in addrs.filter(|&addr| crate::is_resident(addr).unwrap()) {
+            // SAFETY: the page is mapped
+            let byte = unsafe { *(addr as *const u8) };
+            assert_eq!(
+                byte as u64,
+                (addr - cluster.page_addr(0, 0)) / PAGE_SIZE as u64
+            );
+        }
+    }
+
+    #[test]
+    fn test_fault_threads_share_a_small_cache() {
+        let mut cluster = SimulatedCluster::new(2, 256);
+        cluster.nodes[0].pager.cache = Arc::new(PageCache::new(4 * PAGE_SIZE).unwrap());
+        for page_num in 0..256 {
+            cluster.place_page(page_num, 1, &[page_num as u8; PAGE_SIZE]);
+        }
+        // Each remote fault fills the cache with the next pages
+        let config = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(config.path(), "prefetch_depth = 4\n").unwrap();
+        cluster.load_config(0, config.path());
+
+        // As fault threads would, each with its own stream of faults
+        thread::scope(|scope| {
+            for stream in 0..4 {
+                let cluster = &cluster;
+                scope.spawn(move || {
+                    for page_num in (stream * 64..(stream + 1) * 64).step_by(2) {
+                        assert_eq!(
+                            cluster.fault(0, page_num).unwrap(),
+                            vec![page_num as u8; PAGE_SIZE]
+                        );
+                    }
+                });
+            }
+        });
+        assert!(cluster.pager(0).get_stats().cache_hits > 0);
+    }
+
+    #[test]
+    fn test_fault_threads_keep_their_own_access_patterns() {
+        let mut cluster = SimulatedCluster::new(2, 128);
+        for page_num in 0..128 {
+            cluster.place_page(page_num, 1, &[page_num as u8; PAGE_SIZE]);
+        }
+        let config = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(config.path(), "prefetch_depth = 4\n").unwrap();
+        cluster.load_config(0, config.path());
+
+        // Two sequential scans, their faults strictly interleaved
+        let barrier = std::sync::Barrier::new(2);
+        thread::scope(|scope| {
+            for stream in 0..2 {
+                let (cluster, barrier) = (&cluster, &barrier);
+                scope.spawn(move || {
+                    for page_num in stream * 64..stream * 64 + 32 {
+                        barrier.wait();
+                        assert_eq!(
+                            cluster.fault(0, page_num).unwrap(),
+                            vec![page_num as u8; PAGE_SIZE]
+                        );
+                    }
+                });
+            }
+        });
+        // Each thread saw its scan as sequential and prefetched ahead of it
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.remote_faults, 64);
+        assert!(stats.cache_hits >= 32, "{} cache hits", stats.cache_hits);
+    }
+
+    #[test]
+    fn test_concurrent_fetches_limited_per_node() {
+        let mut cluster = SimulatedCluster::new(2, 128);
+        cluster.nodes[0].pager.max_concurrent_fetches_per_node = 8;
+        for page_num in 0..64 {
+            cluster.place_page(page_num, 1, &[page_num as u8; PAGE_SIZE]);
+        }
+        cluster.set_fetch_delay(Duration::from_millis(20));
+
+        std::thread::scope(|scope| {
+            for page_num in 0..64 {
+                let cluster = &cluster;
+                scope.spawn(move || {
+                    assert_eq!(
+                        cluster.fault(0, page_num).unwrap(),
+                        vec![page_num as u8; PAGE_SIZE]
+                    );
+                });
+            }
+        });
+
+        assert!(cluster.peak_fetches_in_flight() <= 8);
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.remote_faults, 64);
+        assert!(stats.fetch_semaphore_waits > 0);
+    }
+
+    #[test]
+    fn test_persistently_failing_fault_dead_lettered() {
+        let cluster = SimulatedCluster::new(2, 64);
+        cluster.place_page(3, 1, &[8; PAGE_SIZE]);
+        cluster.place_page(4, 1, &[9; PAGE_SIZE]);
+        let pager = cluster.pager(0);
+
+        // Five retries are enough
+        cluster.fail_fetches(1, 0, 3, 5);
+        assert_eq!(cluster.fault(0, 3).unwrap(), vec![8; PAGE_SIZE]);
+        assert!(pager.dead_letter_queue().is_empty());
+
+        cluster.fail_fetches(1, 0, 4, 6);
+        assert_eq!(cluster.fault(0, 4).unwrap(), vec![0; PAGE_SIZE]);
+        let dlq = pager.dead_letter_queue();
+        assert_eq!(dlq.len(), 1);
+        assert_eq!(dlq[0].gpa, 4 * PAGE_SIZE as u64);
+        assert!(
+            dlq[0].error.contains("Injected failure"),
+            "{}",
+            dlq[0].error
+        );
+        assert_eq!(pager.directory().get_owner(4), PageOwner::Unknown);
+        let stats = pager.get_stats();
+        assert_eq!((stats.dead_lettered_faults, stats.remote_faults), (1, 1));
+    }
+
+    #[test]
+    fn test_gossiped_claims_converge() {
+        let mut cluster = SimulatedCluster::new(3, 64);
+        let port = std::net::UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        cluster.start_gossip(GossipConfig {
+            port,
+            ..Default::default()
+        });
+        for page_num in 0..8 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        cluster.fault(2, 40).unwrap();
+
+        let expected = |node: usize| {
+            (0..8)
+                .map(|page_num| (page_num, PageOwner::Remote(0)))
+                .chain([(40, PageOwner::Remote(2))])
+                .filter(move |&(_, owner)| owner != PageOwner::Remote(node as u32))
+        };
+        let converged = |cluster: &SimulatedCluster| {
+            [0, 1, 2].iter().all(|&node| {
+                expected(node).all(|(page_num, owner)| {
+                    cluster.pager(node).directory().get_owner(page_num) == owner
+                })
+            })
+        };
+        let start = Instant::now();
+        while !converged(&cluster) {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "directories did not converge"
+            );
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let stats = cluster.pager(0).get_stats();
+        assert_eq!(stats.gossip_msgs_sent, 8);
+        assert_eq!(stats.gossip_msgs_received, 1);
+        assert_eq!(cluster.pager(1).get_stats().gossip_msgs_sent, 0);
+    }
+
+    /// Guest that maps all its memory with one page size
+    struct UniformPages(PageSizeClass);
+
+    impl GuestPageWalker for UniformPages {
+        fn page_size_class(&self, _gpa: u64) -> Option<PageSizeClass> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn test_huge_guest_page_resolved_whole() {
+        let mut cluster = SimulatedCluster::new(2, 2048);
+        cluster.nodes[0].pager.page_walker = Some(Arc::new(UniformPages(PageSizeClass::Huge2M)));
+        let pager = cluster.pager(0);
+        assert_eq!(pager.page_size_class(0), PageSizeClass::Huge2M);
+        // Owned here but never mapped, e.g. left by an aborted migration
+        for page_num in 512..1536 {
+            pager.directory().set_owner(page_num, PageOwner::Local);
+        }
+        cluster.place_page(1030, 1, &[9; PAGE_SIZE]);
+
+        // One fault maps the whole 2 MiB page
+        assert_eq!(cluster.fault(0, 600).unwrap(), vec![0; PAGE_SIZE]);
+        assert!((512..1024).all(|page_num| pager.is_present(page_num).unwrap()));
+        assert!(!pager.is_present(1024).unwrap());
+
+        // Partly remote: only the faulting page
+        cluster.fault(0, 1100).unwrap();
+        assert!(pager.is_present(1100).unwrap());
+        assert!(!pager.is_present(1101).unwrap());
+        assert_eq!(pager.get_stats().local_faults, 2);
+    }
+
+    #[test]
+    fn test_page_size_class_falls_back_to_promoted_ranges() {
+        let cluster = SimulatedCluster::new(2, 1024);
+        for page_num in 0..512 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        let pager = cluster.pager(0);
+        assert_eq!(pager.page_size_class(4096), PageSizeClass::Huge2M);
+        assert_eq!(
+            pager.page_size_class(512 * PAGE_SIZE as u64),
+            PageSizeClass::Small4K
+        );
+    }
+
+    #[test]
+    fn test_fully_local_range_promoted_to_huge_page() {
+        let cluster = SimulatedCluster::new(2, 1024);
+        for page_num in 0..511 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        cluster.place_page(511, 1, &[7; PAGE_SIZE]);
+        cluster.fault(0, 511).unwrap();
+        // Remote, so the range stays small pages
+        assert_eq!(cluster.pager(0).get_stats().huge_page_promotions, 0);
+
+        for page_num in 512..1024 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        assert_eq!(cluster.pager(0).get_stats().huge_page_promotions, 1);
+        assert!(cluster.pager(0).directory().is_huge(512));
+        assert!(!cluster.pager(0).directory().is_huge(0));
+        assert!(!cluster.pager(0).try_promote_huge(512 * PAGE_SIZE as u64));
+    }
+
+    #[test]
+    fn test_batch_migration_to_two_nodes() {
+        let cluster = SimulatedCluster::new(3, 128);
+        for page_num in 0..100 {
+            cluster.place_page(page_num, 0, &[page_num as u8; PAGE_SIZE]);
+        }
+
+        let migrations = (0..100).map(|page_num| (page_num, 1 + page_num as u32 % 2));
+        let result = cluster
+            .pager(0)
+            .batch_migrate(migrations.collect())
+            .unwrap();
+        assert_eq!(result.succeeded, 100);
+        assert_eq!(result.failed, 0, "{:?}", result.errors);
+
+        let directory = cluster.pager(0).directory();
+        assert_eq!(directory.local_page_count(), 0);
+        assert_eq!(directory.get_owner(6), PageOwner::Remote(1));
+        assert_eq!(directory.get_owner(7), PageOwner::Remote(2));
+        assert_eq!(cluster.pages_owned(1), 50);
+        assert_eq!(cluster.pages_owned(2), 50);
+        // Unmapped here, so the next access fetches it from its new owner
+        assert_eq!(cluster.fault(0, 7).unwrap(), [7; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_overloaded_node_balances_its_pages() {
+        let mut cluster = SimulatedCluster::new(3, 32);
+        // Same guest memory: each node touches its own pages first
+        for page_num in 0..12 {
+            cluster.fault(0, page_num).unwrap();
+        }
+        for page_num in 12..16 {
+            cluster.fault(1, page_num).unwrap();
+        }
+        for page_num in 16..20 {
+            cluster.fault(2, page_num).unwrap();
+        }
+        for node in 0..3 {
+            cluster.start_balancing(node, Duration::from_millis(100));
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let counts = |cluster: &SimulatedCluster| -> Vec<u64> {
+            (0..3).map(|node| cluster.pages_owned(node)).collect()
+        };
+        while counts(&cluster) != [7, 7, 6] && Instant::now() < deadline {
+            cluster.process_control_messages();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        assert_eq!(counts(&cluster), [7, 7, 6]);
+        assert_eq!(cluster.pager(0).get_stats().balance_migrations, 5);
+        assert_eq!(cluster.pager(0).directory().local_page_count(), 7);
+    }
+}
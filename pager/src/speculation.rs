@@ -0,0 +1,111 @@
+//! Speculative first-touch claims
+//!
+//! Normally a node owns a page as soon as it touches it first. With
+//! speculation enabled the claim is recorded as `PageOwner::Speculative` and
+//! the fault is resolved at once; the coordinator is asked afterwards, off the
+//! fault path, whether another node got there first. If so the pager aborts
+//! the speculation (see `Pager::abort_speculative`): the local copy is dropped
+//! and the page is fetched from the true owner.
+//!
+//! The coordinator records the first claim it hears of, so every node in the
+//! cluster must claim through it (i.e. run with speculation) for conflicts to
+//! be caught.
+
+use crate::coordinator::CoordinatorClient;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct PageOwnerResponse {
+    owner_node: Option<u32>,
+}
+
+/// Node that owns `page_num` according to the coordinator
+///
+/// A page nobody has claimed yet is claimed for the coordinator client's
+/// node, so the result is that node unless another node's claim came first.
+pub async fn confirm_claim(coordinator: &CoordinatorClient, page_num: u64) -> Result<u32> {
+    let owner: PageOwnerResponse = coordinator
+        .send(|client, url| {
+            client
+                .get(format!("{}/pages/{}/owner", url, page_num))
+                .timeout(coordinator.timeout())
+        })
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid page owner response")?;
+    if let Some(owner) = owner.owner_node {
+        return Ok(owner);
+    }
+
+    let bearer = coordinator.bearer()?;
+    let claimed: PageOwnerResponse = coordinator
+        .send(|client, url| {
+            client
+                .put(format!("{}/pages/{}/owner", url, page_num))
+                .bearer_auth(bearer)
+                .json(&serde_json::json!({ "node_id": coordinator.node_id() }))
+                .timeout(coordinator.timeout())
+        })
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Invalid page claim response")?;
+    claimed
+        .owner_node
+        .ok_or_else(|| anyhow!("Coordinator recorded no owner for page {}", page_num))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinator::CoordinatorConfig;
+
+    fn coordinator(server: &mockito::Server) -> CoordinatorClient {
+        let coordinator =
+            CoordinatorClient::new(CoordinatorConfig::single(&server.url()), 3).unwrap();
+        coordinator.authorize("token".to_string());
+        coordinator
+    }
+
+    #[tokio::test]
+    async fn test_claims_unowned_page() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/pages/7/owner")
+            .with_body(r#"{"page_num": 7, "owner_node": null}"#)
+            .create_async()
+            .await;
+        let claim = server
+            .mock("PUT", "/pages/7/owner")
+            .match_header("authorization", "Bearer token")
+            .match_body(mockito::Matcher::Json(serde_json::json!({ "node_id": 3 })))
+            .with_body(r#"{"page_num": 7, "owner_node": 3}"#)
+            .create_async()
+            .await;
+
+        assert_eq!(confirm_claim(&coordinator(&server), 7).await.unwrap(), 3);
+        claim.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_reports_earlier_claim() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/pages/7/owner")
+            .with_body(r#"{"page_num": 7, "owner_node": 1}"#)
+            .create_async()
+            .await;
+        let claim = server
+            .mock("PUT", "/pages/7/owner")
+            .expect(0)
+            .create_async()
+            .await;
+
+        assert_eq!(confirm_claim(&coordinator(&server), 7).await.unwrap(), 1);
+        claim.assert_async().await;
+    }
+}
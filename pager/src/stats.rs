@@ -0,0 +1,229 @@
+//! Fault latency sampling
+//!
+//! Recording every fault's latency costs a histogram update per fault,
+//! which shows at hundreds of thousands of faults per second. A
+//! `SamplingHistogram` counts every fault but records the latency of only
+//! 1 in `sample_rate`. Which faults are sampled is decided by a
+//! `FaultSampler`, a plain counter owned by the thread handling the faults,
+//! so the fault loop checks it without an atomic or the lock around the
+//! histogram and only locks for the faults it samples.
+//!
+//! Quantiles of the sampled latencies estimate those of all faults as they
+//! are; counts are scaled up by `sample_rate`. `total_faults` and
+//! `samples_recorded` are kept apart so the sampling ratio can be checked.
+
+use crate::metrics::LatencyHistogram;
+use serde::Serialize;
+
+/// Picks 1 in `sample_rate` faults: the last of every `sample_rate`
+#[derive(Debug, Clone)]
+pub struct FaultSampler {
+    sample_rate: u32,
+    /// Faults counted since the last one sampled
+    pending: u64,
+}
+
+impl FaultSampler {
+    /// `sample_rate` is clamped to at least 1 (sample every fault)
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            pending: 0,
+        }
+    }
+
+    /// Count a fault; if it is sampled, the faults counted since the
+    /// previous sample, this one included
+    pub fn sample(&mut self) -> Option<u64> {
+        self.pending += 1;
+        if self.pending < u64::from(self.sample_rate) {
+            return None;
+        }
+        Some(std::mem::take(&mut self.pending))
+    }
+
+    /// Faults counted since the last sample, which are then forgotten
+    pub fn take_pending(&mut self) -> u64 {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Fault latencies, 1 in `sample_rate` of them recorded
+#[derive(Debug, Clone, Serialize)]
+pub struct SamplingHistogram {
+    sample_rate: u32,
+    /// Picks the faults `record` samples
+    #[serde(skip)]
+    sampler: FaultSampler,
+    /// Latencies of the sampled faults
+    sampled: LatencyHistogram,
+    samples_recorded: u64,
+    total_faults: u64,
+}
+
+impl Default for SamplingHistogram {
+    /// Records every fault
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl SamplingHistogram {
+    /// `sample_rate` is clamped to at least 1 (record every fault)
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            sampler: FaultSampler::new(sample_rate),
+            sampled: LatencyHistogram::default(),
+            samples_recorded: 0,
+            total_faults: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Count a fault that took `latency_us`, recording it if sampled
+    pub fn record(&mut self, latency_us: u64) {
+        self.total_faults += 1;
+        if self.sampler.sample().is_some() {
+            self.sampled.record(latency_us);
+            self.samples_recorded += 1;
+        }
+    }
+
+    /// Record a fault a `FaultSampler` of this rate sampled, counting the
+    /// `faults` it stood for
+    pub fn record_sampled(&mut self, latency_us: u64, faults: u64) {
+        self.total_faults += faults;
+        self.sampled.record(latency_us);
+        self.samples_recorded += 1;
+    }
+
+    /// Count faults a `FaultSampler` passed over
+    pub fn count_unsampled(&mut self, faults: u64) {
+        self.total_faults += faults;
+    }
+
+    /// Sampler picking faults at this histogram's rate
+    pub fn sampler(&self) -> FaultSampler {
+        FaultSampler::new(self.sample_rate)
+    }
+
+    /// Every fault counted
+    pub fn total_faults(&self) -> u64 {
+        self.total_faults
+    }
+
+    /// Faults whose latency was recorded
+    pub fn samples_recorded(&self) -> u64 {
+        self.samples_recorded
+    }
+
+    /// Estimated latency at `quantile` (0.0–1.0) over all faults
+    pub fn quantile_us(&self, quantile: f64) -> Option<u64> {
+        self.sampled.quantile_us(quantile)
+    }
+
+    pub fn p99_us(&self) -> Option<u64> {
+        self.quantile_us(0.99)
+    }
+
+    /// Estimated number of faults that took at most `latency_us`
+    pub fn count_at_most(&self, latency_us: u64) -> u64 {
+        self.sampled.count_at_most(latency_us) * u64::from(self.sample_rate)
+    }
+
+    /// Estimated latency summed over all faults
+    pub fn sum_us(&self) -> u64 {
+        self.sampled.sum_us() * u64::from(self.sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_records_one_in_sample_rate() {
+        let mut histogram = SamplingHistogram::new(10);
+        for latency in 0..1000 {
+            histogram.record(latency);
+        }
+        assert_eq!(histogram.total_faults(), 1000);
+        assert_eq!(histogram.samples_recorded(), 100);
+        assert_eq!(histogram.count_at_most(u64::MAX >> 20), 1000);
+
+        let mut every = SamplingHistogram::new(0);
+        every.record(5);
+        assert_eq!(every.sample_rate(), 1);
+        assert_eq!((every.total_faults(), every.samples_recorded()), (1, 1));
+    }
+
+    #[test]
+    fn test_histograms_sample_independently() {
+        // Recorded alternately on one thread, each still sees every fault
+        let (mut first, mut second) = (SamplingHistogram::new(2), SamplingHistogram::new(2));
+        for latency in 0..10 {
+            first.record(latency);
+            second.record(latency);
+        }
+        assert_eq!(first.samples_recorded(), 5);
+        assert_eq!(second.samples_recorded(), 5);
+    }
+
+    #[test]
+    fn test_sampler_counts_the_faults_it_passes_over() {
+        let mut histogram = SamplingHistogram::new(4);
+        let mut sampler = histogram.sampler();
+        for latency in 0..10 {
+            if let Some(faults) = sampler.sample() {
+                histogram.record_sampled(latency, faults);
+            }
+        }
+        assert_eq!(histogram.samples_recorded(), 2);
+        assert_eq!(histogram.total_faults(), 8);
+
+        histogram.count_unsampled(sampler.take_pending());
+        assert_eq!(histogram.total_faults(), 10);
+        assert_eq!(sampler.take_pending(), 0);
+    }
+
+    #[test]
+    fn test_p99_within_5_percent_at_1_percent_sampling() {
+        // Mostly fast local faults with a slow remote tail
+        let mut rng = StdRng::seed_from_u64(7);
+        let latencies: Vec<u64> = (0..1_000_000)
+            .map(|_| {
+                if rng.gen_bool(0.9) {
+                    rng.gen_range(5..50)
+                } else {
+                    rng.gen_range(200..2000)
+                }
+            })
+            .collect();
+
+        let mut exact = LatencyHistogram::default();
+        let mut sampled = SamplingHistogram::new(100);
+        for &latency in &latencies {
+            exact.record(latency);
+            sampled.record(latency);
+        }
+
+        assert_eq!(sampled.total_faults(), 1_000_000);
+        assert_eq!(sampled.samples_recorded(), 10_000);
+        let (exact_p99, sampled_p99) = (exact.p99_us().unwrap(), sampled.p99_us().unwrap());
+        let error = sampled_p99.abs_diff(exact_p99) as f64 / exact_p99 as f64;
+        assert!(
+            error < 0.05,
+            "sampled p99 {}µs vs exact {}µs",
+            sampled_p99,
+            exact_p99
+        );
+        let (exact_fast, sampled_fast) = (exact.count_at_most(50), sampled.count_at_most(50));
+        assert!(sampled_fast.abs_diff(exact_fast) < exact_fast / 20);
+    }
+}
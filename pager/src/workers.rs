@@ -0,0 +1,163 @@
+//! Resizable worker thread pool
+//!
+//! Workers pull boxed jobs from a shared channel. The pool can grow or shrink
+//! at runtime (e.g. on config reload); excess workers are told to exit and
+//! finish their current job first.
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use log::{debug, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often an idle worker re-checks its stop flag
+const WORKER_IDLE_POLL: Duration = Duration::from_millis(50);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+/// Pool of pager worker threads
+pub struct WorkerPool {
+    jobs_tx: Sender<Job>,
+    jobs_rx: Receiver<Job>,
+    workers: Vec<Worker>,
+    next_id: usize,
+}
+
+impl WorkerPool {
+    /// Create a pool with `size` running workers
+    pub fn new(size: usize) -> Result<Self> {
+        let (jobs_tx, jobs_rx) = crossbeam_channel::unbounded();
+        let mut pool = Self {
+            jobs_tx,
+            jobs_rx,
+            workers: Vec::new(),
+            next_id: 0,
+        };
+        pool.resize(size)?;
+        Ok(pool)
+    }
+
+    /// Number of running workers
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queue a job for the next free worker
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // Cannot fail: the pool holds a receiver for as long as it lives
+        let _ = self.jobs_tx.send(Box::new(job));
+    }
+
+    /// Grow or shrink the pool to exactly `size` workers
+    ///
+    /// If a worker cannot be started, the ones started before it keep
+    /// running and `size` reports how many there are.
+    pub fn resize(&mut self, size: usize) -> Result<()> {
+        while self.workers.len() < size {
+            self.spawn_worker()?;
+        }
+        self.shrink(size);
+        Ok(())
+    }
+
+    /// Stop workers until at most `size` are left
+    fn shrink(&mut self, size: usize) {
+        if self.workers.len() > size {
+            let excess = self.workers.split_off(size);
+            for worker in &excess {
+                worker.stop.store(true, Ordering::SeqCst);
+            }
+            for worker in excess {
+                if worker.handle.join().is_err() {
+                    warn!("Pager worker panicked while stopping");
+                }
+            }
+        }
+    }
+
+    fn spawn_worker(&mut self) -> Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = Arc::clone(&stop);
+        let jobs = self.jobs_rx.clone();
+
+        let handle = thread::Builder::new()
+            .name(format!("pager-worker{}", id))
+            .spawn(move || {
+                debug!("Pager worker {} started", id);
+                while !worker_stop.load(Ordering::SeqCst) {
+                    match jobs.recv_timeout(WORKER_IDLE_POLL) {
+                        Ok(job) => job(),
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                debug!("Pager worker {} exiting", id);
+            })
+            .context("Failed to spawn pager worker thread")?;
+
+        self.workers.push(Worker { stop, handle });
+        Ok(())
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.shrink(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_worker_pool_runs_jobs() {
+        let pool = WorkerPool::new(2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (done_tx, done_rx) = crossbeam_channel::unbounded();
+
+        for _ in 0..10 {
+            let counter = Arc::clone(&counter);
+            let done_tx = done_tx.clone();
+            pool.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                done_tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..10 {
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_worker_pool_resize() {
+        let mut pool = WorkerPool::new(1).unwrap();
+        assert_eq!(pool.size(), 1);
+
+        pool.resize(4).unwrap();
+        assert_eq!(pool.size(), 4);
+
+        pool.resize(2).unwrap();
+        assert_eq!(pool.size(), 2);
+
+        // Remaining workers still process jobs
+        let (done_tx, done_rx) = crossbeam_channel::unbounded();
+        pool.execute(move || done_tx.send(()).unwrap());
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
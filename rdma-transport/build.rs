@@ -55,6 +55,11 @@ fn main() {
         .allowlist_type("ibv_port_attr")
         .allowlist_type("ibv_device_attr")
         .allowlist_type("ibv_gid")
+        .allowlist_type("ibv_mw")
+        .allowlist_type("ibv_mw_bind_info")
+        .allowlist_type("ibv_async_event")
+        .allowlist_type("ibv_srq")
+        .allowlist_type("ibv_srq_init_attr")
         // Core functions
         .allowlist_function("ibv_get_device_list")
         .allowlist_function("ibv_free_device_list")
@@ -76,7 +81,11 @@ fn main() {
         .allowlist_function("ibv_query_qp")
         .allowlist_function("ibv_post_send")
         .allowlist_function("ibv_post_recv")
+        .allowlist_function("ibv_create_srq")
+        .allowlist_function("ibv_destroy_srq")
         .allowlist_function("ibv_poll_cq")
+        .allowlist_function("ibv_get_async_event")
+        .allowlist_function("ibv_ack_async_event")
         // Constants and enums
         .allowlist_var("IBV_QP_.*")
         .allowlist_var("IBV_ACCESS_.*")
@@ -94,6 +103,9 @@ fn main() {
         .allowlist_type("ibv_send_flags")
         .allowlist_type("ibv_qp_attr_mask")
         .allowlist_type("ibv_mtu")
+        .allowlist_type("ibv_port_state")
+        .allowlist_type("ibv_mw_type")
+        .allowlist_type("ibv_event_type")
         // Also allow _compat types
         .allowlist_type("_compat_.*")
         // Derive traits
@@ -0,0 +1,151 @@
+//! XOR delta encoding for re-sent pages
+//!
+//! When a page is migrated again after only a few bytes changed, sending the
+//! whole 4 KiB is wasteful. The delta is the XOR of the old (base) and new
+//! contents, which is zero wherever nothing changed; only its non-zero runs
+//! are kept.
+//!
+//! Encoded format, repeated per run (integers little-endian):
+//!
+//! ```text
+//! offset: u32 | length: u32 | length bytes of XOR data
+//! ```
+//!
+//! Runs separated by fewer zero bytes than a run header are merged, since
+//! splitting them would cost more than it saves.
+
+use anyhow::{anyhow, Result};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Size of a run header (offset + length)
+const RUN_HEADER: usize = 8;
+
+/// Hash of the base page a delta applies to
+pub fn base_hash(page: &[u8]) -> u64 {
+    xxh3_64(page)
+}
+
+/// Encodes and applies XOR page deltas
+pub struct DeltaEncoder;
+
+impl DeltaEncoder {
+    /// Delta turning `base` into `modified` (empty if they are equal)
+    ///
+    /// # Panics
+    /// If the two pages differ in length.
+    pub fn encode(base: &[u8], modified: &[u8]) -> Vec<u8> {
+        assert_eq!(base.len(), modified.len(), "delta pages differ in length");
+
+        let xor: Vec<u8> = base.iter().zip(modified).map(|(a, b)| a ^ b).collect();
+        let mut delta = Vec::new();
+        let mut pos = 0;
+
+        while let Some(start) = xor[pos..].iter().position(|&b| b != 0).map(|i| pos + i) {
+            // Extend the run until a zero gap long enough to pay for a header
+            let mut end = start + 1;
+            while end < xor.len() {
+                let gap = xor[end..]
+                    .iter()
+                    .take(RUN_HEADER + 1)
+                    .take_while(|&&b| b == 0)
+                    .count();
+                if gap > RUN_HEADER || end + gap == xor.len() {
+                    break;
+                }
+                end += gap.max(1);
+            }
+
+            delta.extend_from_slice(&(start as u32).to_le_bytes());
+            delta.extend_from_slice(&((end - start) as u32).to_le_bytes());
+            delta.extend_from_slice(&xor[start..end]);
+            pos = end;
+        }
+
+        delta
+    }
+
+    /// Apply a delta from `encode` to `page` in place
+    pub fn apply(page: &mut [u8], delta: &[u8]) -> Result<()> {
+        let mut rest = delta;
+        while !rest.is_empty() {
+            if rest.len() < RUN_HEADER {
+                return Err(anyhow!("Truncated delta run header"));
+            }
+            let offset = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            rest = &rest[RUN_HEADER..];
+
+            if rest.len() < len {
+                return Err(anyhow!("Truncated delta run at offset {}", offset));
+            }
+            let target = offset
+                .checked_add(len)
+                .and_then(|end| page.get_mut(offset..end))
+                .ok_or_else(|| anyhow!("Delta run {}+{} outside page", offset, len))?;
+
+            for (byte, x) in target.iter_mut().zip(&rest[..len]) {
+                *byte ^= x;
+            }
+            rest = &rest[len..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PAGE_SIZE;
+
+    fn page() -> Vec<u8> {
+        (0..PAGE_SIZE).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_identical_pages_empty_delta() {
+        let base = page();
+        assert!(DeltaEncoder::encode(&base, &base).is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let base = page();
+        let mut modified = base.clone();
+        modified[0] ^= 1;
+        modified[100..116].fill(0xee);
+        modified[105] = base[105]; // Unchanged byte inside a run
+        modified[PAGE_SIZE - 1] ^= 0x80;
+
+        let delta = DeltaEncoder::encode(&base, &modified);
+        // Three runs: [0], [100..116] and the last byte
+        assert_eq!(delta.len(), 3 * RUN_HEADER + 1 + 16 + 1);
+
+        let mut page = base.clone();
+        DeltaEncoder::apply(&mut page, &delta).unwrap();
+        assert_eq!(page, modified);
+    }
+
+    #[test]
+    fn test_close_runs_merge() {
+        let base = vec![0u8; 64];
+        let mut modified = base.clone();
+        modified[10] = 1;
+        modified[14] = 1;
+        modified[40] = 1;
+
+        let delta = DeltaEncoder::encode(&base, &modified);
+        // [10..15] merged, [40] separate
+        assert_eq!(delta.len(), 2 * RUN_HEADER + 5 + 1);
+    }
+
+    #[test]
+    fn test_apply_rejects_malformed_delta() {
+        let mut page = page();
+        assert!(DeltaEncoder::apply(&mut page, &[0; 4]).is_err());
+
+        let mut out_of_range = (PAGE_SIZE as u32 - 2).to_le_bytes().to_vec();
+        out_of_range.extend_from_slice(&4u32.to_le_bytes());
+        out_of_range.extend_from_slice(&[1; 4]);
+        assert!(DeltaEncoder::apply(&mut page, &out_of_range).is_err());
+    }
+}
@@ -5,14 +5,14 @@
 //! ## Quick Start (Zero Configuration)
 //!
 //! ```rust,no_run
-//! use rdma_transport::{TransportManager, TransportEndpoint};
+//! use rdma_transport::{Endpoint as TransportEndpoint, TransportManager};
 //!
 //! // Works on ANY hardware - automatically selects best available transport
 //! let mut transport = TransportManager::new(1).expect("Failed to create");
 //!
 //! // Connect to peer
 //! let endpoint = TransportEndpoint::Tcp {
-//!     addr: "192.168.1.100".to_string(),
+//!     addr: "192.168.1.100".parse().unwrap(),
 //!     port: 50051,
 //! };
 //! transport.connect_peer(2, endpoint).expect("Failed to connect");
@@ -26,24 +26,39 @@
 //! - **TCP** (default): Works on ANY network hardware - Ethernet, WiFi, etc.
 //!   - Latency: 200-500µs (10G), 500-2000µs (1G)
 //!   - Zero configuration required
+//!   - Optionally encrypted with TLS (see `transport::tls`)
 //!   - Perfect for development and small deployments
 //!
+//! - **QUIC** (optional): One stream per request over UDP
+//!   - No head-of-line blocking between concurrent page fetches
+//!   - Enable with `--features quic-transport`
+//!
 //! - **RDMA** (optional): High-performance mode  
 //!   - Requires InfiniBand or RoCE NICs
 //!   - Latency: <100µs median, <500µs p99
 //!   - Enable with `--features rdma-transport`
 //!
-//! The system automatically uses the best available transport.
-
+//! The system automatically uses the best available transport: RDMA, then
+//! QUIC, then TCP.
+
+pub mod delta;
+pub mod monitor;
+pub mod qos;
+pub mod reconnect;
+pub mod rediscovery;
+pub mod trace;
 pub mod transport;
 
 #[cfg(feature = "rdma-transport")]
 mod rdma;
 
-use anyhow::{anyhow, Result};
-use log::{info, warn};
-use parking_lot::RwLock;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use parking_lot::{Mutex, RwLock};
+use qos::{BandwidthCap, BandwidthLimiter, BandwidthReservation, TrafficClass};
+use reconnect::{PeerLocator, ReconnectPolicy};
 use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use std::time::Duration;
 use transport::{PageTransport, TransportEndpoint};
@@ -51,16 +66,41 @@ use transport::{PageTransport, TransportEndpoint};
 pub const PAGE_SIZE: usize = 4096;
 
 // Re-exports
-pub use transport::{TransportEndpoint as Endpoint, TransportTier};
+pub use transport::{
+    read_tsc, PeerAuthenticator, TransportEndpoint as Endpoint, TransportError, TransportStats,
+    TransportTier,
+};
 
 #[cfg(feature = "rdma-transport")]
 pub use rdma::QpEndpoint as RdmaEndpoint;
 
+#[cfg(feature = "rdma-transport")]
+pub use rdma::{list_rdma_devices, MultiRailRdmaTransport, RdmaConfig, RdmaDeviceInfo};
+
+/// Settings for a `TransportManager`
+#[derive(Debug, Clone, Default)]
+pub struct TransportConfig {
+    /// Rate of each kind of transfer (see `qos`); unlimited if unset
+    pub bandwidth: Option<BandwidthReservation>,
+    /// Reconnect to peers whose connection broke (see `reconnect`); fetches
+    /// fail straight away if unset
+    pub reconnect: Option<ReconnectPolicy>,
+}
+
 /// Transport manager - unified API for all transport types
 pub struct TransportManager {
     local_node_id: u32,
-    transport: Box<dyn PageTransport>,
+    /// Written only to reconnect a peer from behind `&self`
+    transport: RwLock<Box<dyn PageTransport>>,
     peer_endpoints: Arc<RwLock<HashMap<u32, TransportEndpoint>>>,
+    limiter: Option<BandwidthLimiter>,
+    cap: Option<BandwidthCap>,
+    reconnect: Option<ReconnectPolicy>,
+    peer_locator: Option<Arc<dyn PeerLocator>>,
+    reconnection_attempts: Mutex<HashMap<u32, u64>>,
+    /// Round-trip time to each peer, in µs, as last measured by
+    /// `refresh_latency_cache`
+    latency_cache: Arc<RwLock<HashMap<u32, u64>>>,
 }
 
 impl TransportManager {
@@ -69,9 +109,28 @@ impl TransportManager {
     /// Tries RDMA first (if compiled in), falls back to TCP.
     /// **Always works** - no special hardware required.
     pub fn new(local_node_id: u32) -> Result<Self> {
+        Self::with_config(local_node_id, TransportConfig::default())
+    }
+
+    /// Like `new`, with `config` applied
+    pub fn with_config(local_node_id: u32, config: TransportConfig) -> Result<Self> {
         info!("🚀 Initializing transport for node {}", local_node_id);
         info!("💡 Consumer-grade hardware support enabled (plug-and-play)");
 
+        #[cfg(feature = "rdma-transport")]
+        match rdma::list_rdma_devices() {
+            Ok(devices) if devices.is_empty() => info!("🔌 No RDMA devices found"),
+            Ok(devices) => {
+                for device in devices {
+                    info!(
+                        "🔌 RDMA device {}: {} port(s), firmware {}, GUID {:016x}",
+                        device.name, device.num_ports, device.fw_ver, device.node_guid
+                    );
+                }
+            }
+            Err(e) => info!("🔌 Could not list RDMA devices: {:#}", e),
+        }
+
         let transport = transport::create_transport(local_node_id)?;
         let tier = transport.performance_tier();
 
@@ -87,29 +146,75 @@ impl TransportManager {
 
         Ok(Self {
             local_node_id,
-            transport,
+            transport: RwLock::new(transport),
             peer_endpoints: Arc::new(RwLock::new(HashMap::new())),
+            limiter: config.bandwidth.map(BandwidthLimiter::new),
+            cap: None,
+            reconnect: config.reconnect,
+            peer_locator: None,
+            reconnection_attempts: Mutex::new(HashMap::new()),
+            latency_cache: Arc::new(RwLock::new(HashMap::new())),
         })
     }
 
+    /// Use `transport` instead of auto-detecting one (e.g. a
+    /// `transport::mock::MockTransport`)
+    pub fn with_transport(local_node_id: u32, transport: Box<dyn PageTransport>) -> Self {
+        Self {
+            local_node_id,
+            transport: RwLock::new(transport),
+            peer_endpoints: Arc::new(RwLock::new(HashMap::new())),
+            limiter: None,
+            cap: None,
+            reconnect: None,
+            peer_locator: None,
+            reconnection_attempts: Mutex::new(HashMap::new()),
+            latency_cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Reconnect to broken peers as `policy` says (see `reconnect`)
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    /// Limit all transfers together to `mbps` megabits per second, on top of
+    /// any `BandwidthReservation`; None lifts the limit
+    pub fn set_bandwidth_limit(&mut self, mbps: Option<u64>) {
+        self.cap = mbps.map(BandwidthCap::new);
+    }
+
+    /// Ask `locator` where to reconnect to peers, rather than reusing the
+    /// endpoint each was last reached on
+    pub fn set_peer_locator(&mut self, locator: Arc<dyn PeerLocator>) {
+        self.peer_locator = Some(locator);
+    }
+
+    /// Get the node ID this transport was created for
+    pub fn local_node_id(&self) -> u32 {
+        self.local_node_id
+    }
+
     /// Get local endpoint to share with peers
     pub fn local_endpoint(&self) -> TransportEndpoint {
-        self.transport.local_endpoint()
+        self.transport.read().local_endpoint()
     }
 
     /// Connect to a peer node
     ///
     /// Endpoint can be:
-    /// - TCP: "192.168.1.100:50051" or TransportEndpoint::Tcp
+    /// - TCP: TransportEndpoint::Tcp (IPv4 or IPv6 address and port)
     /// - RDMA: QP endpoint info as TransportEndpoint::Rdma
     pub fn connect_peer(&mut self, remote_node_id: u32, endpoint: TransportEndpoint) -> Result<()> {
         info!("🔗 Connecting to node {}", remote_node_id);
 
-        self.transport.connect(remote_node_id, endpoint.clone())?;
+        self.transport
+            .get_mut()
+            .connect(remote_node_id, endpoint.clone())?;
         self.peer_endpoints.write().insert(remote_node_id, endpoint);
 
         // Measure latency
-        if let Ok(latency) = self.transport.measure_latency(remote_node_id) {
+        if let Ok(latency) = self.transport.read().measure_latency(remote_node_id) {
             info!(
                 "✅ Connected to node {} (latency: {}µs)",
                 remote_node_id,
@@ -120,6 +225,106 @@ impl TransportManager {
         Ok(())
     }
 
+    /// List connected peers and the endpoints they were reached on
+    pub fn peers(&self) -> Vec<(u32, TransportEndpoint)> {
+        let mut peers: Vec<_> = self
+            .peer_endpoints
+            .read()
+            .iter()
+            .map(|(id, ep)| (*id, ep.clone()))
+            .collect();
+        peers.sort_by_key(|(id, _)| *id);
+        peers
+    }
+
+    /// Measure round-trip latency to a connected peer
+    pub fn measure_latency(&self, remote_node_id: u32) -> Result<Duration> {
+        self.transport.read().measure_latency(remote_node_id)
+    }
+
+    /// Measure round-trip latency to every connected peer at once
+    pub fn measure_all_peers(&self) -> HashMap<u32, Result<Duration>> {
+        let peers: Vec<u32> = self.peer_endpoints.read().keys().copied().collect();
+        let transport = self.transport.read();
+        let transport = &**transport;
+        std::thread::scope(|s| {
+            let pings: Vec<_> = peers
+                .into_iter()
+                .map(|peer| (peer, s.spawn(move || transport.measure_latency(peer))))
+                .collect();
+            pings
+                .into_iter()
+                .map(|(peer, ping)| {
+                    let latency = ping
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow!("Latency probe panicked")));
+                    (peer, latency)
+                })
+                .collect()
+        })
+    }
+
+    /// Measure every connected peer again for `latency_matrix_us`
+    ///
+    /// Peers that cannot be measured drop out of the matrix, and are
+    /// reported in the error.
+    pub fn refresh_latency_cache(&self) -> Result<()> {
+        let mut measured = HashMap::new();
+        let mut failed = Vec::new();
+        for (peer, latency) in self.measure_all_peers() {
+            match latency {
+                Ok(latency) => {
+                    measured.insert(peer, latency.as_micros() as u64);
+                }
+                Err(e) => {
+                    debug!("Failed to measure latency to node {}: {:#}", peer, e);
+                    failed.push(peer);
+                }
+            }
+        }
+        *self.latency_cache.write() = measured;
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+        failed.sort_unstable();
+        Err(anyhow!("Failed to measure latency to nodes {:?}", failed))
+    }
+
+    /// Round-trip times in µs between nodes, indexed by node ID, as of the
+    /// last `refresh_latency_cache`
+    ///
+    /// Only this node's row is known: it holds 0 for the node itself and
+    /// the latency to each measured peer. Every other entry is `None`.
+    pub fn latency_matrix_us(&self) -> Vec<Vec<Option<u64>>> {
+        let cache = self.latency_cache.read();
+        let nodes = cache
+            .keys()
+            .copied()
+            .chain([self.local_node_id])
+            .max()
+            .map_or(0, |highest| highest as usize + 1);
+
+        let mut matrix = vec![vec![None; nodes]; nodes];
+        let row = &mut matrix[self.local_node_id as usize];
+        row[self.local_node_id as usize] = Some(0);
+        for (&peer, &latency_us) in cache.iter() {
+            row[peer as usize] = Some(latency_us);
+        }
+        matrix
+    }
+
+    /// Bytes per second a peer's pages arrive at (see
+    /// `transport::PageTransport::measure_bandwidth`)
+    pub fn measure_bandwidth(&self, remote_node_id: u32) -> Result<u64> {
+        self.transport.read().measure_bandwidth(remote_node_id)
+    }
+
+    /// Probe a peer's TSC (see `transport::PageTransport::probe_tsc`)
+    pub fn probe_tsc(&self, remote_node_id: u32, local_tsc: u64) -> Result<u64> {
+        self.transport.read().probe_tsc(remote_node_id, local_tsc)
+    }
+
     /// Fetch a page from remote node
     ///
     /// # Arguments
@@ -129,17 +334,240 @@ impl TransportManager {
     /// # Returns
     /// Page data (4KB)
     pub fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>> {
-        self.transport.fetch_page(gpa, remote_node_id)
+        self.reserve(TrafficClass::Fault, PAGE_SIZE);
+        self.with_reconnect(remote_node_id, || {
+            self.transport.read().fetch_page(gpa, remote_node_id)
+        })
+    }
+
+    /// Fetch pages ahead of use, at prefetch priority
+    pub fn fetch_pages(&self, gpas: &[u64], remote_node_id: u32) -> Result<Vec<Vec<u8>>> {
+        gpas.iter()
+            .map(|&gpa| {
+                self.reserve(TrafficClass::Prefetch, PAGE_SIZE);
+                self.transport.read().fetch_page(gpa, remote_node_id)
+            })
+            .collect()
+    }
+
+    /// Fetch pages from any number of nodes, one batch per node
+    ///
+    /// `requests` are `(gpa, remote_node_id)` pairs. The nodes are fetched
+    /// from in parallel, each with a single exchange where the transport
+    /// supports it (see `PageTransport::fetch_pages_batch`). Results are in
+    /// request order, and one page failing does not fail the others.
+    pub fn fetch_pages_batch(&self, requests: &[(u64, u32)]) -> Vec<Result<Vec<u8>>> {
+        let mut by_node: HashMap<u32, (Vec<usize>, Vec<u64>)> = HashMap::new();
+        for (i, &(gpa, remote_node_id)) in requests.iter().enumerate() {
+            let (indices, gpas) = by_node.entry(remote_node_id).or_default();
+            indices.push(i);
+            gpas.push(gpa);
+        }
+        self.reserve(TrafficClass::Fault, requests.len() * PAGE_SIZE);
+
+        let fetched: Vec<_> = if by_node.len() == 1 {
+            by_node
+                .into_iter()
+                .map(|(remote_node_id, (indices, gpas))| {
+                    (
+                        indices,
+                        self.transport
+                            .read()
+                            .fetch_pages_batch(&gpas, remote_node_id),
+                    )
+                })
+                .collect()
+        } else {
+            std::thread::scope(|s| {
+                let batches: Vec<_> = by_node
+                    .into_iter()
+                    .map(|(remote_node_id, (indices, gpas))| {
+                        let batch = s.spawn(move || {
+                            self.transport
+                                .read()
+                                .fetch_pages_batch(&gpas, remote_node_id)
+                        });
+                        (indices, batch)
+                    })
+                    .collect();
+                batches
+                    .into_iter()
+                    .map(|(indices, batch)| {
+                        let pages = batch.join().unwrap_or_else(|_| {
+                            indices
+                                .iter()
+                                .map(|_| Err(anyhow!("Batch fetch panicked")))
+                                .collect()
+                        });
+                        (indices, pages)
+                    })
+                    .collect()
+            })
+        };
+
+        let mut results: Vec<Result<Vec<u8>>> = requests
+            .iter()
+            .map(|_| Err(anyhow!("Page not fetched")))
+            .collect();
+        for (indices, pages) in fetched {
+            for (i, page) in indices.into_iter().zip(pages) {
+                results[i] = page;
+            }
+        }
+        results
+    }
+
+    /// Fetch a page as of local page directory `epoch`
+    ///
+    /// Fails with `TransportError::StaleEpoch` if the owner's directory is
+    /// older; see `transport::PageTransport::fetch_page_at_epoch`.
+    pub fn fetch_page_at_epoch(
+        &self,
+        gpa: u64,
+        remote_node_id: u32,
+        epoch: u64,
+    ) -> Result<Vec<u8>> {
+        self.reserve(TrafficClass::Fault, PAGE_SIZE);
+        self.with_reconnect(remote_node_id, || {
+            self.transport
+                .read()
+                .fetch_page_at_epoch(gpa, remote_node_id, epoch)
+        })
+    }
+
+    /// xxHash3-128 of the owner's current copy of a page, as of local page
+    /// directory `epoch`; see `transport::PageTransport::fetch_page_hash`
+    pub fn fetch_page_hash(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<u128> {
+        self.transport
+            .read()
+            .fetch_page_hash(gpa, remote_node_id, epoch)
+    }
+
+    /// Run `fetch`, reconnecting to `remote_node_id` and running it again
+    /// while it fails to reach the peer, as the `ReconnectPolicy` allows
+    fn with_reconnect(
+        &self,
+        remote_node_id: u32,
+        mut fetch: impl FnMut() -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let Some(policy) = self.reconnect else {
+            return fetch();
+        };
+        let mut attempt = 0;
+        loop {
+            match fetch() {
+                Err(e) if reconnect::is_connection_error(&e) && attempt < policy.max_retries => {
+                    let delay = policy.delay(attempt);
+                    attempt += 1;
+                    debug!(
+                        "Node {} unreachable, reconnecting in {:?} (attempt {}): {:#}",
+                        remote_node_id, delay, attempt, e
+                    );
+                    std::thread::sleep(delay);
+                    if let Err(e) = self.reconnect_peer(remote_node_id) {
+                        warn!("Failed to reconnect to node {}: {:#}", remote_node_id, e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connect to `remote_node_id` again, at the endpoint the `PeerLocator`
+    /// reports or else the one it was last reached on
+    fn reconnect_peer(&self, remote_node_id: u32) -> Result<()> {
+        *self
+            .reconnection_attempts
+            .lock()
+            .entry(remote_node_id)
+            .or_default() += 1;
+        let endpoint = match &self.peer_locator {
+            Some(locator) => locator
+                .endpoint(remote_node_id)
+                .context("Failed to look up peer endpoint")?,
+            None => self
+                .peer_endpoints
+                .read()
+                .get(&remote_node_id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Node {} was never connected", remote_node_id))?,
+        };
+
+        self.transport
+            .write()
+            .connect(remote_node_id, endpoint.clone())?;
+        self.peer_endpoints.write().insert(remote_node_id, endpoint);
+        info!("Reconnected to node {}", remote_node_id);
+        Ok(())
+    }
+
+    /// Refuse page requests from nodes whose directory epoch is ahead of
+    /// this counter
+    pub fn set_directory_epoch(&self, epoch: Arc<AtomicU64>) {
+        self.transport.read().set_directory_epoch(epoch)
+    }
+
+    /// Exchange credentials with peers on every new connection; see
+    /// `PeerAuthenticator`
+    pub fn set_authenticator(&self, authenticator: Arc<dyn PeerAuthenticator>) {
+        self.transport.read().set_authenticator(authenticator)
     }
 
     /// Send a page to remote node (for migration)
     pub fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()> {
-        self.transport.send_page(gpa, data, remote_node_id)
+        self.reserve(TrafficClass::Migration, data.len());
+        self.transport.read().send_page(gpa, data, remote_node_id)
+    }
+
+    /// Page a peer sent to this node with `send_page`, if one has arrived
+    pub fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        self.transport.read().received_page(gpa)
+    }
+
+    /// Send a page the remote node already holds as `base`, as a delta
+    ///
+    /// Falls back to a full page if the remote copy no longer matches `base`.
+    pub fn send_page_delta(
+        &self,
+        gpa: u64,
+        base: &[u8],
+        data: &[u8],
+        remote_node_id: u32,
+    ) -> Result<()> {
+        // Charged as a full page; the delta's size is not known here
+        self.reserve(TrafficClass::Migration, data.len());
+        self.transport
+            .read()
+            .send_page_delta(gpa, base, data, remote_node_id)
+    }
+
+    /// Wait for `class`'s bandwidth reservation to allow `bytes`
+    fn reserve(&self, class: TrafficClass, bytes: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(class, bytes);
+        }
+        if let Some(cap) = &self.cap {
+            cap.acquire(bytes);
+        }
+    }
+
+    /// Transfer counters
+    pub fn stats(&self) -> TransportStats {
+        let mut stats = self.transport.read().stats();
+        stats.reconnection_attempts = self.reconnection_attempts.lock().clone();
+        stats
     }
 
     /// Get current performance tier
     pub fn performance_tier(&self) -> TransportTier {
-        self.transport.performance_tier()
+        self.transport.read().performance_tier()
+    }
+
+    /// Stop serving peers once in-flight requests are answered
+    ///
+    /// See `transport::PageTransport::shutdown_gracefully`.
+    pub fn shutdown_gracefully(&self, timeout: Duration) -> Result<()> {
+        self.transport.read().shutdown_gracefully(timeout)
     }
 
     /// Register memory region (for zero-copy if supported)
@@ -148,7 +576,7 @@ impl TransportManager {
         addr: *mut u8,
         length: usize,
     ) -> Result<Box<dyn transport::MemoryRegion>> {
-        self.transport.register_memory(addr, length)
+        self.transport.read().register_memory(addr, length)
     }
 }
 
@@ -161,11 +589,12 @@ pub fn init_transport(local_node_id: u32) -> Result<()> {
     let _lock = INIT_LOCK.lock();
 
     unsafe {
-        if GLOBAL_TRANSPORT.is_some() {
+        let global = &mut *std::ptr::addr_of_mut!(GLOBAL_TRANSPORT);
+        if global.is_some() {
             return Err(anyhow!("Transport already initialized"));
         }
 
-        GLOBAL_TRANSPORT = Some(TransportManager::new(local_node_id)?);
+        *global = Some(TransportManager::new(local_node_id)?);
     }
 
     Ok(())
@@ -174,7 +603,7 @@ pub fn init_transport(local_node_id: u32) -> Result<()> {
 /// Get global transport manager
 pub fn get_transport() -> Result<&'static mut TransportManager> {
     unsafe {
-        GLOBAL_TRANSPORT
+        (*std::ptr::addr_of_mut!(GLOBAL_TRANSPORT))
             .as_mut()
             .ok_or_else(|| anyhow!("Transport not initialized. Call init_transport() first."))
     }
@@ -216,6 +645,152 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_peers_empty_on_creation() {
+        let transport = TransportManager::new(2).unwrap();
+        assert!(transport.peers().is_empty());
+        assert_eq!(transport.local_node_id(), 2);
+    }
+
+    /// p99 fetch latency from a node sending pages to the same peer from
+    /// four threads as fast as its migration class allows
+    fn fetch_p99_while_migrating(config: TransportConfig) -> Duration {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Instant;
+
+        let server = TransportManager::new(2).unwrap();
+        let mut client = TransportManager::with_config(1, config).unwrap();
+        client.connect_peer(2, server.local_endpoint()).unwrap();
+        let client = Arc::new(client);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let migrators: Vec<_> = (0..4u64)
+            .map(|thread| {
+                let (client, stop) = (Arc::clone(&client), Arc::clone(&stop));
+                std::thread::spawn(move || {
+                    let mut gpa = thread << 32;
+                    while !stop.load(Ordering::Relaxed) {
+                        client.send_page(gpa, &[7; PAGE_SIZE], 2).unwrap();
+                        gpa += PAGE_SIZE as u64;
+                    }
+                })
+            })
+            .collect();
+
+        let mut latencies: Vec<Duration> = (0..100)
+            .map(|page| {
+                let start = Instant::now();
+                client.fetch_page(page * PAGE_SIZE as u64, 2).unwrap();
+                let latency = start.elapsed();
+                std::thread::sleep(Duration::from_millis(1));
+                latency
+            })
+            .collect();
+        stop.store(true, Ordering::Relaxed);
+        for migrator in migrators {
+            migrator.join().unwrap();
+        }
+        latencies.sort_unstable();
+        latencies[98]
+    }
+
+    #[test]
+    fn test_fault_latency_held_under_saturating_migration() {
+        let reserved = TransportConfig {
+            bandwidth: Some(BandwidthReservation {
+                fault_mbps: 1000,
+                prefetch_mbps: 100,
+                migration_mbps: 20,
+            }),
+            ..TransportConfig::default()
+        };
+        let p99 = fetch_p99_while_migrating(reserved);
+        // Loose enough for a loaded single-core debug build
+        assert!(p99 < Duration::from_millis(20), "fault p99 {:?}", p99);
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_fault_latency_with_and_without_reservation() {
+        let unlimited = fetch_p99_while_migrating(TransportConfig::default());
+        let reserved = fetch_p99_while_migrating(TransportConfig {
+            bandwidth: Some(BandwidthReservation {
+                fault_mbps: 1000,
+                prefetch_mbps: 100,
+                migration_mbps: 20,
+            }),
+            ..TransportConfig::default()
+        });
+        println!(
+            "Fault p99 while migrating: {:?} unlimited, {:?} with migration at 20 Mbps",
+            unlimited, reserved
+        );
+    }
+
+    #[test]
+    fn test_fetch_pages_batch_keeps_request_order() {
+        let mut client = TransportManager::new(1).unwrap();
+        let servers: Vec<_> = [2, 3]
+            .map(|node| TransportManager::new(node).unwrap())
+            .into_iter()
+            .collect();
+        for server in &servers {
+            let node = server.local_node_id();
+            client.connect_peer(node, server.local_endpoint()).unwrap();
+            // Servers serve back the pages they were sent
+            client
+                .send_page(0x2000, &[node as u8 + 10; PAGE_SIZE], node)
+                .unwrap();
+        }
+
+        let results =
+            client.fetch_pages_batch(&[(0x2000, 3), (0x2000, 2), (0x3000, 4), (0x3000, 2)]);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().unwrap(), &vec![13u8; PAGE_SIZE]);
+        assert_eq!(results[1].as_ref().unwrap(), &vec![12u8; PAGE_SIZE]);
+        // Node 4 is not connected; the rest of the batch still succeeds
+        assert!(results[2].is_err());
+        assert_eq!(results[3].as_ref().unwrap(), &vec![0u8; PAGE_SIZE]);
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_batch_vs_sequential_fetch() {
+        use std::time::Instant;
+
+        const PAGES: u64 = 16;
+        const ROUNDS: u32 = 50;
+
+        let server = TransportManager::new(2).unwrap();
+        let mut client = TransportManager::new(1).unwrap();
+        client.connect_peer(2, server.local_endpoint()).unwrap();
+        let requests: Vec<(u64, u32)> = (0..PAGES).map(|page| (page << 12, 2)).collect();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for &(gpa, node) in &requests {
+                client.fetch_page(gpa, node).unwrap();
+            }
+        }
+        let sequential = start.elapsed() / ROUNDS;
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            for page in client.fetch_pages_batch(&requests) {
+                page.unwrap();
+            }
+        }
+        let batched = start.elapsed() / ROUNDS;
+
+        let speedup = sequential.as_secs_f64() / batched.as_secs_f64();
+        println!(
+            "{} pages: {:?} sequential, {:?} batched ({:.1}x)",
+            PAGES, sequential, batched, speedup
+        );
+        // Debug builds spend most of a batch encoding it; measure with --release
+        assert!(speedup >= 4.0, "batch only {:.1}x faster", speedup);
+    }
+
     #[test]
     fn test_page_size_constant() {
         assert_eq!(PAGE_SIZE, 4096);
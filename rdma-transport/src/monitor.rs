@@ -0,0 +1,227 @@
+//! TCP congestion detection from kernel network counters
+//!
+//! Retransmitted segments are the kernel's sign that the network is
+//! dropping packets, and every retransmit delays a page transfer by at least
+//! one RTO. `TcpCongestionMonitor` samples `RetransSegs` and `OutSegs` from
+//! `/proc/net/snmp` and reports the share of segments sent since the previous
+//! sample that were retransmissions. The TCP transport throttles its sends
+//! while that share is above `CONGESTION_THRESHOLD`.
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Retransmit share above which the network counts as congested
+pub const CONGESTION_THRESHOLD: f64 = 0.05;
+
+/// Where the monitor reads kernel network counters
+pub trait NetStatsSource: Send {
+    /// Contents of `/proc/net/snmp`
+    fn snmp(&self) -> Result<String>;
+    /// Contents of `/proc/net/dev`
+    fn dev(&self) -> Result<String>;
+}
+
+/// The running kernel's counters
+pub struct ProcNetStats;
+
+impl NetStatsSource for ProcNetStats {
+    fn snmp(&self) -> Result<String> {
+        fs::read_to_string("/proc/net/snmp").context("Failed to read /proc/net/snmp")
+    }
+
+    fn dev(&self) -> Result<String> {
+        fs::read_to_string("/proc/net/dev").context("Failed to read /proc/net/dev")
+    }
+}
+
+/// Traffic counters of one network interface (from `/proc/net/dev`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterfaceStats {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+/// Tracks the TCP retransmit rate between samples
+pub struct TcpCongestionMonitor {
+    source: Box<dyn NetStatsSource>,
+    /// `(OutSegs, RetransSegs)` at the previous sample
+    last: Option<(u64, u64)>,
+}
+
+impl TcpCongestionMonitor {
+    pub fn new(source: Box<dyn NetStatsSource>) -> Self {
+        Self { source, last: None }
+    }
+
+    /// Share of TCP segments sent since the last call that were
+    /// retransmissions (0.0–1.0), or since boot on the first call
+    ///
+    /// Unreadable counters count as no congestion.
+    pub fn congestion_level(&mut self) -> f64 {
+        let (out, retrans) = match self.source.snmp().and_then(|snmp| tcp_segments(&snmp)) {
+            Ok(segments) => segments,
+            Err(e) => {
+                debug!("No TCP counters: {:#}", e);
+                return 0.0;
+            }
+        };
+        let (last_out, last_retrans) = self.last.replace((out, retrans)).unwrap_or((0, 0));
+        // Counters only go back on wrap-around or a namespace change
+        let sent = out.saturating_sub(last_out);
+        let resent = retrans.saturating_sub(last_retrans);
+        if sent == 0 {
+            return 0.0;
+        }
+        (resent as f64 / sent as f64).min(1.0)
+    }
+
+    /// Counters of every network interface
+    pub fn interfaces(&self) -> Result<Vec<InterfaceStats>> {
+        parse_dev(&self.source.dev()?)
+    }
+}
+
+/// Counters of this host's network interfaces
+pub fn interface_stats() -> Result<Vec<InterfaceStats>> {
+    parse_dev(&ProcNetStats.dev()?)
+}
+
+/// `(OutSegs, RetransSegs)` from the `Tcp:` lines of `/proc/net/snmp`
+///
+/// The file pairs a header line of field names with a line of values.
+fn tcp_segments(snmp: &str) -> Result<(u64, u64)> {
+    let mut tcp = snmp.lines().filter(|line| line.starts_with("Tcp:"));
+    let (Some(names), Some(values)) = (tcp.next(), tcp.next()) else {
+        return Err(anyhow!("No Tcp section in /proc/net/snmp"));
+    };
+    let field = |wanted: &str| -> Result<u64> {
+        names
+            .split_whitespace()
+            .zip(values.split_whitespace())
+            .find(|(name, _)| *name == wanted)
+            .ok_or_else(|| anyhow!("No {} in /proc/net/snmp", wanted))?
+            .1
+            .parse()
+            .with_context(|| format!("Bad {} in /proc/net/snmp", wanted))
+    };
+    Ok((field("OutSegs")?, field("RetransSegs")?))
+}
+
+/// Interfaces listed in `/proc/net/dev`, after its two header lines
+fn parse_dev(dev: &str) -> Result<Vec<InterfaceStats>> {
+    dev.lines()
+        .skip(2)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, counters) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Bad /proc/net/dev line: {}", line))?;
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("Bad counters for {}", name.trim()))?;
+            if counters.len() < 12 {
+                return Err(anyhow!("Too few counters for {}", name.trim()));
+            }
+            // rx: bytes packets errs drop fifo frame compressed multicast,
+            // then tx: bytes packets errs drop ...
+            Ok(InterfaceStats {
+                name: name.trim().to_string(),
+                rx_bytes: counters[0],
+                rx_packets: counters[1],
+                rx_errors: counters[2],
+                rx_dropped: counters[3],
+                tx_bytes: counters[8],
+                tx_packets: counters[9],
+                tx_errors: counters[10],
+                tx_dropped: counters[11],
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Counters set by the test
+    #[derive(Clone, Default)]
+    pub(crate) struct MockNetStats {
+        /// `(OutSegs, RetransSegs)`
+        pub(crate) segments: Arc<Mutex<(u64, u64)>>,
+    }
+
+    impl MockNetStats {
+        pub(crate) fn set(&self, out: u64, retrans: u64) {
+            *self.segments.lock() = (out, retrans);
+        }
+    }
+
+    impl NetStatsSource for MockNetStats {
+        fn snmp(&self) -> Result<String> {
+            let (out, retrans) = *self.segments.lock();
+            Ok(format!(
+                "Ip: Forwarding DefaultTTL\n\
+                 Ip: 1 64\n\
+                 Tcp: RtoAlgorithm RtoMin OutSegs RetransSegs InErrs\n\
+                 Tcp: 1 200 {} {} 0\n",
+                out, retrans
+            ))
+        }
+
+        fn dev(&self) -> Result<String> {
+            Ok("Inter-|   Receive                            |  Transmit\n \
+                face |bytes packets errs drop fifo frame compressed multicast|bytes packets errs drop fifo colls carrier compressed\n  \
+                eth0: 1000 10 1 2 0 0 0 0 2000 20 3 4 0 0 0 0\n"
+                .to_string())
+        }
+    }
+
+    #[test]
+    fn test_congestion_level_is_retransmit_share_between_samples() {
+        let stats = MockNetStats::default();
+        let mut monitor = TcpCongestionMonitor::new(Box::new(stats.clone()));
+        stats.set(1000, 10);
+        assert_eq!(monitor.congestion_level(), 0.01);
+
+        stats.set(2000, 110);
+        assert_eq!(monitor.congestion_level(), 0.1);
+        // Nothing sent since
+        assert_eq!(monitor.congestion_level(), 0.0);
+    }
+
+    #[test]
+    fn test_interface_counters_parsed() {
+        let monitor = TcpCongestionMonitor::new(Box::new(MockNetStats::default()));
+        let interfaces = monitor.interfaces().unwrap();
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces[0].name, "eth0");
+        assert_eq!(
+            (
+                interfaces[0].rx_dropped,
+                interfaces[0].tx_bytes,
+                interfaces[0].tx_dropped
+            ),
+            (2, 2000, 4)
+        );
+    }
+
+    #[test]
+    fn test_real_proc_counters_readable() {
+        let mut monitor = TcpCongestionMonitor::new(Box::new(ProcNetStats));
+        let level = monitor.congestion_level();
+        assert!((0.0..=1.0).contains(&level));
+    }
+}
@@ -0,0 +1,316 @@
+//! Bandwidth reserved per kind of transfer
+//!
+//! A fault waits on one page, while prefetch and migration move many pages
+//! nobody is blocked on yet. Left to share the link, a migration burst
+//! queues ahead of the fault. `BandwidthLimiter` gives each `TrafficClass`
+//! its own token bucket, refilled at the rate `BandwidthReservation` sets
+//! for it, and a transfer waits until its bucket holds enough tokens.
+//!
+//! Faults drain first: while a fault waits for tokens, prefetch and
+//! migration transfers wait as well, whatever their own buckets hold.
+//!
+//! A `BandwidthCap` limits all classes together, on top of their
+//! reservations.
+
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Burst a bucket can save up, as time at its rate
+const BURST: Duration = Duration::from_millis(10);
+
+/// Longest a waiting transfer sleeps before checking its bucket again
+const MAX_WAIT: Duration = Duration::from_millis(10);
+
+/// Rate of each traffic class in megabits per second; 0 leaves a class
+/// unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthReservation {
+    pub fault_mbps: u32,
+    pub prefetch_mbps: u32,
+    pub migration_mbps: u32,
+}
+
+/// What a transfer is for, highest priority first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficClass {
+    /// A page a vCPU is waiting on
+    Fault,
+    /// Pages fetched ahead of use
+    Prefetch,
+    /// Pages sent to another node
+    Migration,
+}
+
+impl TrafficClass {
+    fn index(self) -> usize {
+        match self {
+            Self::Fault => 0,
+            Self::Prefetch => 1,
+            Self::Migration => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Bytes per second; 0 for unlimited
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(mbps: u64, now: Instant) -> Self {
+        let rate = mbps as f64 * 1e6 / 8.0;
+        // Room for at least one page, or a slow class could never send
+        let burst = (rate * BURST.as_secs_f64()).max(crate::PAGE_SIZE as f64);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+    }
+
+    /// Take `bytes`, or say how long until they are there
+    ///
+    /// A transfer larger than the burst goes through once the bucket is
+    /// full and leaves it in debt.
+    fn take(&mut self, bytes: usize) -> Result<(), Duration> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+        let needed = (bytes as f64).min(self.burst);
+        if self.tokens >= needed {
+            self.tokens -= bytes as f64;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((needed - self.tokens) / self.rate))
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    buckets: [TokenBucket; 3],
+    faults_waiting: usize,
+}
+
+/// Token buckets for the three traffic classes
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    state: Mutex<LimiterState>,
+    faults_done: Condvar,
+}
+
+impl BandwidthLimiter {
+    pub fn new(reservation: BandwidthReservation) -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(LimiterState {
+                buckets: [
+                    TokenBucket::new(reservation.fault_mbps.into(), now),
+                    TokenBucket::new(reservation.prefetch_mbps.into(), now),
+                    TokenBucket::new(reservation.migration_mbps.into(), now),
+                ],
+                faults_waiting: 0,
+            }),
+            faults_done: Condvar::new(),
+        }
+    }
+
+    /// Block until `class` may transfer `bytes`
+    pub fn acquire(&self, class: TrafficClass, bytes: usize) {
+        let mut state = self.state.lock();
+        let fault = class == TrafficClass::Fault;
+        if fault {
+            state.faults_waiting += 1;
+        }
+        loop {
+            if !fault && state.faults_waiting > 0 {
+                self.faults_done.wait_for(&mut state, MAX_WAIT);
+                continue;
+            }
+            let bucket = &mut state.buckets[class.index()];
+            bucket.refill(Instant::now());
+            match bucket.take(bytes) {
+                Ok(()) => break,
+                Err(wait) => {
+                    // Sleep without the lock so other classes can go
+                    let wait = wait.min(MAX_WAIT);
+                    if fault {
+                        parking_lot::MutexGuard::unlocked(&mut state, || std::thread::sleep(wait));
+                    } else {
+                        self.faults_done.wait_for(&mut state, wait);
+                    }
+                }
+            }
+        }
+        if fault {
+            state.faults_waiting -= 1;
+            if state.faults_waiting == 0 {
+                self.faults_done.notify_all();
+            }
+        }
+    }
+}
+
+/// Rate of all transfers together
+#[derive(Debug)]
+pub struct BandwidthCap {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl BandwidthCap {
+    /// Cap at `mbps` megabits per second
+    pub fn new(mbps: u64) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(mbps, Instant::now())),
+        }
+    }
+
+    /// Block until `bytes` fit under the cap
+    pub fn acquire(&self, bytes: usize) {
+        loop {
+            let mut bucket = self.bucket.lock();
+            bucket.refill(Instant::now());
+            match bucket.take(bytes) {
+                Ok(()) => return,
+                Err(wait) => {
+                    drop(bucket);
+                    std::thread::sleep(wait.min(MAX_WAIT));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PAGE_SIZE;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn reservation(
+        fault_mbps: u32,
+        prefetch_mbps: u32,
+        migration_mbps: u32,
+    ) -> BandwidthReservation {
+        BandwidthReservation {
+            fault_mbps,
+            prefetch_mbps,
+            migration_mbps,
+        }
+    }
+
+    #[test]
+    fn test_class_limited_to_its_rate() {
+        // 8 Mbps is 1 MB/s, 10 KB of burst
+        let limiter = BandwidthLimiter::new(reservation(0, 0, 8));
+        let start = Instant::now();
+        for _ in 0..50 {
+            limiter.acquire(TrafficClass::Migration, 1000);
+        }
+        // 40 KB beyond the burst takes 40 ms
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(35), "{:?}", elapsed);
+
+        // Other classes are not held back
+        let start = Instant::now();
+        for _ in 0..50 {
+            limiter.acquire(TrafficClass::Prefetch, 1000);
+            limiter.acquire(TrafficClass::Fault, 1000);
+        }
+        assert!(start.elapsed() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_cap_shared_by_all_classes() {
+        // 8 Mbps is 1 MB/s, 10 KB of burst
+        let cap = BandwidthCap::new(8);
+        let start = Instant::now();
+        for _ in 0..12 {
+            cap.acquire(PAGE_SIZE);
+        }
+        // 39 KB beyond the burst takes 39 ms
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(35), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_oversized_transfer_goes_through_in_debt() {
+        let limiter = BandwidthLimiter::new(reservation(8, 0, 0));
+        limiter.acquire(TrafficClass::Fault, 100 * PAGE_SIZE);
+        let start = Instant::now();
+        limiter.acquire(TrafficClass::Fault, 1);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_waiting_fault_holds_back_migration() {
+        let limiter = Arc::new(BandwidthLimiter::new(reservation(8, 0, 0)));
+        // Drain the fault bucket so the next fault waits ~40 ms
+        limiter.acquire(TrafficClass::Fault, 50_000);
+        let fault = {
+            let limiter = Arc::clone(&limiter);
+            thread::spawn(move || limiter.acquire(TrafficClass::Fault, 1000))
+        };
+        while limiter.state.lock().faults_waiting == 0 {
+            thread::yield_now();
+        }
+        let start = Instant::now();
+        limiter.acquire(TrafficClass::Migration, 1000);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        fault.join().unwrap();
+    }
+
+    #[test]
+    fn test_fault_latency_bounded_under_migration_load() {
+        let limiter = Arc::new(BandwidthLimiter::new(reservation(1000, 100, 100)));
+        let stop = Arc::new(AtomicBool::new(false));
+        let migrated = Arc::new(AtomicU64::new(0));
+        let migrators: Vec<_> = (0..4)
+            .map(|_| {
+                let (limiter, stop, migrated) = (
+                    Arc::clone(&limiter),
+                    Arc::clone(&stop),
+                    Arc::clone(&migrated),
+                );
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        limiter.acquire(TrafficClass::Migration, PAGE_SIZE);
+                        migrated.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        let start = Instant::now();
+        let mut worst = Duration::ZERO;
+        for _ in 0..200 {
+            let fault = Instant::now();
+            limiter.acquire(TrafficClass::Fault, PAGE_SIZE);
+            worst = worst.max(fault.elapsed());
+            thread::sleep(Duration::from_micros(500));
+        }
+        stop.store(true, Ordering::Relaxed);
+        for migrator in migrators {
+            migrator.join().unwrap();
+        }
+
+        // 1000 Mbps fits a page every 33 µs, far above the fault rate
+        assert!(worst < Duration::from_millis(5), "{:?}", worst);
+        // 100 Mbps is ~3050 pages/s, plus the burst
+        let rate = migrated.load(Ordering::Relaxed) as f64 / start.elapsed().as_secs_f64();
+        assert!(rate < 3500.0, "{} pages/s", rate);
+    }
+}
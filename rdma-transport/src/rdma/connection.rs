@@ -2,11 +2,16 @@
 //!
 //! Implements RDMA Reliable Connection (RC) queue pairs for page transfers.
 
-use super::device::{RdmaDevice, RdmaMemoryRegion};
+use super::device::{RdmaDevice, RdmaMemoryRegion, RdmaMemoryWindow, RdmaSharedRecvQueue};
 use anyhow::{anyhow, Result};
 use log::{debug, info, warn};
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "stub-rdma"))]
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
 use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -24,30 +29,131 @@ pub struct QpEndpoint {
     pub psn: u32,      // Packet Sequence Number (for flow control)
 }
 
+impl QpEndpoint {
+    /// Endpoint with its GID given as an IPv6 address
+    ///
+    /// RoCE v2 GIDs are IPv6 (or IPv4-mapped) addresses, so this is the
+    /// natural form when they come from configuration.
+    pub fn from_ipv6_gid(gid: Ipv6Addr, qpn: u32, lid: u16, psn: u32) -> Self {
+        Self {
+            qpn,
+            lid,
+            gid: gid.octets(),
+            psn,
+        }
+    }
+
+    /// GID in IPv6 notation (e.g. `fe80::...` or `::ffff:10.0.0.1`)
+    pub fn gid_as_ipv6(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.gid)
+    }
+}
+
+/// Default HCA port for connections
+pub const DEFAULT_PORT_NUM: u8 = 1;
+
+/// Inline data capacity requested for each QP
+const MAX_INLINE_DATA: u32 = 64;
+
+/// RDMA connection settings
+#[derive(Debug, Clone)]
+pub struct RdmaConfig {
+    /// RDMA device name (e.g., "mlx5_0", "rxe0"); the fastest device if
+    /// unset (see `RdmaDevice::open_best`)
+    pub device_name: Option<String>,
+    /// HCA port the queue pair is bound to (1-based)
+    pub port_num: u8,
+    /// Completion queue depth (number of outstanding operations)
+    pub cq_depth: u32,
+}
+
+impl Default for RdmaConfig {
+    fn default() -> Self {
+        Self {
+            device_name: None,
+            port_num: DEFAULT_PORT_NUM,
+            cq_depth: 128,
+        }
+    }
+}
+
+impl RdmaConfig {
+    /// Open the configured device, or the fastest one
+    pub fn open_device(&self) -> Result<Arc<RdmaDevice>> {
+        match &self.device_name {
+            Some(name) => RdmaDevice::open(name),
+            None => RdmaDevice::open_best(),
+        }
+    }
+}
+
 /// RDMA connection with RC queue pair
 pub struct RdmaConnection {
     device: Arc<RdmaDevice>,
+    port_num: u8,
     #[cfg(not(feature = "stub-rdma"))]
     qp: *mut ibv_qp,
     #[cfg(not(feature = "stub-rdma"))]
     cq_send: *mut ibv_cq,
     #[cfg(not(feature = "stub-rdma"))]
     cq_recv: *mut ibv_cq,
-    local_endpoint: QpEndpoint,
+    /// Shared with `SmMonitor`, which rewrites the LID when the SM changes it
+    local_endpoint: Arc<RwLock<QpEndpoint>>,
     remote_endpoint: Option<QpEndpoint>,
     pub remote_node_id: u32,
+    /// Largest payload the QP accepts inline (as granted by the driver)
+    max_inline_data: usize,
+    /// Writes posted with `IBV_SEND_INLINE`
+    inline_sends: AtomicU64,
+    /// Local memory the peer may reach through `window`, GPA 0 at its start
+    exposed_memory: Option<Arc<RdmaMemoryRegion>>,
+    /// This connection's window over its authorized GPA range
+    window: Option<RdmaMemoryWindow>,
+    /// Shared queue the QP takes its receives from, instead of its own
+    srq: Option<Arc<RdmaSharedRecvQueue>>,
 }
 
 unsafe impl Send for RdmaConnection {}
 unsafe impl Sync for RdmaConnection {}
 
 impl RdmaConnection {
-    /// Create new RDMA connection (QP in RESET state)
+    /// Create new RDMA connection on the default port (QP in RESET state)
+    ///
+    /// # Arguments
+    /// * `device` - RDMA device handle
+    /// * `cq_depth` - Completion queue depth (number of outstanding operations)
+    /// * `srq` - Shared receive queue on `device` to take receives from, if
+    ///   any; otherwise the QP has its own
+    pub fn create(
+        device: Arc<RdmaDevice>,
+        cq_depth: u32,
+        srq: Option<Arc<RdmaSharedRecvQueue>>,
+    ) -> Result<Self> {
+        Self::create_on_port(device, cq_depth, DEFAULT_PORT_NUM, srq)
+    }
+
+    /// Create new RDMA connection using `config.port_num` and `config.cq_depth`
+    pub fn with_config(
+        device: Arc<RdmaDevice>,
+        config: &RdmaConfig,
+        srq: Option<Arc<RdmaSharedRecvQueue>>,
+    ) -> Result<Self> {
+        Self::create_on_port(device, config.cq_depth, config.port_num, srq)
+    }
+
+    /// Create new RDMA connection bound to a specific HCA port
     ///
     /// # Arguments
     /// * `device` - RDMA device handle
     /// * `cq_depth` - Completion queue depth (number of outstanding operations)
-    pub fn create(device: Arc<RdmaDevice>, cq_depth: u32) -> Result<Self> {
+    /// * `port_num` - HCA port (1-based)
+    /// * `srq` - Shared receive queue, as for `create`
+    pub fn create_on_port(
+        device: Arc<RdmaDevice>,
+        cq_depth: u32,
+        port_num: u8,
+        srq: Option<Arc<RdmaSharedRecvQueue>>,
+    ) -> Result<Self> {
         #[cfg(feature = "stub-rdma")]
         {
             return Err(anyhow!("RDMA not available (stub mode)"));
@@ -55,7 +161,10 @@ impl RdmaConnection {
 
         #[cfg(not(feature = "stub-rdma"))]
         {
-            info!("Creating RDMA connection, CQ depth={}", cq_depth);
+            info!(
+                "Creating RDMA connection, port={}, CQ depth={}",
+                port_num, cq_depth
+            );
 
             // Create completion queues
             let cq_send = unsafe {
@@ -95,10 +204,14 @@ impl RdmaConnection {
             qp_init_attr.recv_cq = cq_recv;
             qp_init_attr.qp_type = ibv_qp_type_IBV_QPT_RC;
             qp_init_attr.cap.max_send_wr = cq_depth;
-            qp_init_attr.cap.max_recv_wr = cq_depth;
+            match &srq {
+                // Receives are posted to the SRQ, not the QP
+                Some(srq) => qp_init_attr.srq = srq.as_ptr(),
+                None => qp_init_attr.cap.max_recv_wr = cq_depth,
+            }
             qp_init_attr.cap.max_send_sge = 1;
             qp_init_attr.cap.max_recv_sge = 1;
-            qp_init_attr.cap.max_inline_data = 64; // Small inline data support
+            qp_init_attr.cap.max_inline_data = MAX_INLINE_DATA; // Small inline data support
 
             let qp = unsafe { ibv_create_qp(device.pd(), &mut qp_init_attr) };
 
@@ -111,10 +224,15 @@ impl RdmaConnection {
             }
 
             let qpn = unsafe { (*qp).qp_num };
-            debug!("Created QP: qpn={}", qpn);
+            // ibv_create_qp writes back the capabilities actually granted
+            let max_inline_data = qp_init_attr.cap.max_inline_data as usize;
+            debug!(
+                "Created QP: qpn={}, max_inline_data={}",
+                qpn, max_inline_data
+            );
 
             // Query port to get LID and GID
-            let port = device.query_port(1)?;
+            let port = device.query_port(port_num)?;
 
             let local_endpoint = QpEndpoint {
                 qpn,
@@ -130,21 +248,37 @@ impl RdmaConnection {
 
             Ok(Self {
                 device,
+                port_num,
                 qp,
                 cq_send,
                 cq_recv,
-                local_endpoint,
+                local_endpoint: Arc::new(RwLock::new(local_endpoint)),
                 remote_endpoint: None,
                 remote_node_id: 0,
+                max_inline_data,
+                inline_sends: AtomicU64::new(0),
+                exposed_memory: None,
+                window: None,
+                srq,
             })
         }
     }
 
     /// Get local endpoint info for exchange
-    pub fn local_endpoint(&self) -> &QpEndpoint {
+    pub fn local_endpoint(&self) -> QpEndpoint {
+        self.local_endpoint.read().clone()
+    }
+
+    /// Local endpoint as shared with `SmMonitor::track`
+    pub(crate) fn shared_local_endpoint(&self) -> &Arc<RwLock<QpEndpoint>> {
         &self.local_endpoint
     }
 
+    /// HCA port this connection's queue pair is bound to
+    pub fn port_num(&self) -> u8 {
+        self.port_num
+    }
+
     /// Connect to remote node using exchanged endpoint
     ///
     /// Transitions QP: RESET → INIT → RTR → RTS
@@ -183,7 +317,7 @@ impl RdmaConnection {
         let mut attr: ibv_qp_attr = unsafe { std::mem::zeroed() };
         attr.qp_state = ibv_qp_state_IBV_QPS_INIT;
         attr.pkey_index = 0;
-        attr.port_num = 1;
+        attr.port_num = self.port_num;
         attr.qp_access_flags = (ibv_access_flags_IBV_ACCESS_REMOTE_READ as u32
             | ibv_access_flags_IBV_ACCESS_REMOTE_WRITE as u32
             | ibv_access_flags_IBV_ACCESS_LOCAL_WRITE as u32) as u32;
@@ -215,7 +349,7 @@ impl RdmaConnection {
         attr.ah_attr.dlid = remote_ep.lid;
         attr.ah_attr.sl = 0;
         attr.ah_attr.src_path_bits = 0;
-        attr.ah_attr.port_num = 1;
+        attr.ah_attr.port_num = self.port_num;
 
         // Set GID if available (for RoCE)
         if remote_ep.gid != [0u8; 16] {
@@ -247,7 +381,7 @@ impl RdmaConnection {
     fn qp_to_rts(&self) -> Result<()> {
         let mut attr: ibv_qp_attr = unsafe { std::mem::zeroed() };
         attr.qp_state = ibv_qp_state_IBV_QPS_RTS;
-        attr.sq_psn = self.local_endpoint.psn;
+        attr.sq_psn = self.local_endpoint.read().psn;
         attr.timeout = 14; // ~67ms
         attr.retry_cnt = 7;
         attr.rnr_retry = 7;
@@ -334,6 +468,93 @@ impl RdmaConnection {
         }
     }
 
+    /// RDMA READ of several regions of `length` bytes at once
+    ///
+    /// Every read is posted before any completion is polled, so the batch
+    /// costs about one round trip. `reads` are `(local_offset, remote_addr)`
+    /// pairs; the results are in the same order, and one read failing does
+    /// not fail the others. Reads beyond the send queue's depth (the CQ depth
+    /// the connection was created with) fail to post.
+    pub fn rdma_read_batch(
+        &self,
+        local_mr: &RdmaMemoryRegion,
+        reads: &[(usize, u64)],
+        remote_rkey: u32,
+        length: usize,
+    ) -> Vec<Result<()>> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            let _ = (local_mr, remote_rkey, length);
+            return reads
+                .iter()
+                .map(|_| Err(anyhow!("RDMA not available (stub mode)")))
+                .collect();
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            let mut results: Vec<Result<()>> = reads
+                .iter()
+                .map(|_| Err(anyhow!("RDMA READ was not completed")))
+                .collect();
+            if reads.is_empty() {
+                return results;
+            }
+
+            let mut sges: Vec<ibv_sge> = reads
+                .iter()
+                .map(|&(local_offset, _)| ibv_sge {
+                    addr: (local_mr.addr as u64) + (local_offset as u64),
+                    length: length as u32,
+                    lkey: local_mr.lkey,
+                })
+                .collect();
+            let mut wrs: Vec<ibv_send_wr> = reads
+                .iter()
+                .map(|_| unsafe { std::mem::zeroed() })
+                .collect();
+            let mut pending = HashMap::with_capacity(reads.len());
+            for (i, &(_, remote_addr)) in reads.iter().enumerate() {
+                let wr_id = self.generate_wr_id();
+                pending.insert(wr_id, i);
+                let wr = &mut wrs[i];
+                wr.wr_id = wr_id;
+                wr.sg_list = &mut sges[i];
+                wr.num_sge = 1;
+                wr.opcode = ibv_wr_opcode_IBV_WR_RDMA_READ;
+                wr.send_flags = ibv_send_flags_IBV_SEND_SIGNALED as u32;
+                wr.wr.rdma.remote_addr = remote_addr;
+                wr.wr.rdma.rkey = remote_rkey;
+            }
+            // Chain the work requests so they are posted with one call
+            for i in 1..wrs.len() {
+                let next: *mut ibv_send_wr = &mut wrs[i];
+                wrs[i - 1].next = next;
+            }
+
+            let mut bad_wr: *mut ibv_send_wr = ptr::null_mut();
+            let ctx = unsafe { (*self.qp).context };
+            let post_send_fn = unsafe { (*ctx).ops.post_send.unwrap() };
+            let ret = unsafe { post_send_fn(self.qp, wrs.as_mut_ptr(), &mut bad_wr) };
+
+            if ret != 0 {
+                // Requests from `bad_wr` on were not posted
+                let posted = if bad_wr.is_null() {
+                    0
+                } else {
+                    unsafe { bad_wr.offset_from(wrs.as_ptr()) as usize }
+                };
+                for (wr, result) in wrs[posted..].iter().zip(&mut results[posted..]) {
+                    pending.remove(&wr.wr_id);
+                    *result = Err(anyhow!("Failed to post RDMA READ"));
+                }
+            }
+
+            self.poll_send_completions(&mut pending, &mut results);
+            results
+        }
+    }
+
     /// Perform RDMA WRITE operation
     pub fn rdma_write(
         &self,
@@ -384,6 +605,164 @@ impl RdmaConnection {
         }
     }
 
+    /// RDMA WRITE of a small buffer without a memory region
+    ///
+    /// Payloads up to the QP's `max_inline_data` (64 bytes) are copied into
+    /// the work request with `IBV_SEND_INLINE`, so `data` needs no MR and can
+    /// live on the stack. Larger payloads fall back to `rdma_write` through a
+    /// temporary MR.
+    pub fn send_inline(&self, data: &[u8], remote_addr: u64, remote_rkey: u32) -> Result<()> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            if data.len() > self.max_inline_data {
+                let mut buf = data.to_vec();
+                let mr = self.device.register_memory(buf.as_mut_ptr(), buf.len())?;
+                self.rdma_write(&mr, 0, remote_addr, remote_rkey, buf.len())?;
+                return Ok(());
+            }
+
+            let wr_id = self.generate_wr_id();
+
+            // The HCA copies inline data at post time; lkey is ignored
+            let mut sge = ibv_sge {
+                addr: data.as_ptr() as u64,
+                length: data.len() as u32,
+                lkey: 0,
+            };
+
+            let mut wr: ibv_send_wr = unsafe { std::mem::zeroed() };
+            wr.wr_id = wr_id;
+            wr.sg_list = &mut sge;
+            wr.num_sge = 1;
+            wr.opcode = ibv_wr_opcode_IBV_WR_RDMA_WRITE;
+            wr.send_flags =
+                (ibv_send_flags_IBV_SEND_SIGNALED | ibv_send_flags_IBV_SEND_INLINE) as u32;
+            wr.wr.rdma.remote_addr = remote_addr;
+            wr.wr.rdma.rkey = remote_rkey;
+
+            let mut bad_wr: *mut ibv_send_wr = ptr::null_mut();
+            let ctx = unsafe { (*self.qp).context };
+            let post_send_fn = unsafe { (*ctx).ops.post_send.unwrap() };
+            let ret = unsafe { post_send_fn(self.qp, &mut wr, &mut bad_wr) };
+
+            if ret != 0 {
+                return Err(anyhow!("Failed to post inline RDMA WRITE"));
+            }
+
+            self.poll_send_completion(wr_id)?;
+            self.inline_sends.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Post a buffer for a message from the peer
+    ///
+    /// With a shared receive queue the buffer goes to it, and a message from
+    /// any connection sharing it may fill the buffer. Arguments as for
+    /// `RdmaSharedRecvQueue::post_recv`.
+    pub fn post_recv(
+        &self,
+        mr: &RdmaMemoryRegion,
+        local_offset: usize,
+        length: usize,
+        wr_id: u64,
+    ) -> Result<()> {
+        if let Some(srq) = &self.srq {
+            return srq.post_recv(mr, local_offset, length, wr_id);
+        }
+
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            let mut sge = ibv_sge {
+                addr: (mr.addr as u64) + (local_offset as u64),
+                length: length as u32,
+                lkey: mr.lkey,
+            };
+
+            let mut wr: ibv_recv_wr = unsafe { std::mem::zeroed() };
+            wr.wr_id = wr_id;
+            wr.sg_list = &mut sge;
+            wr.num_sge = 1;
+
+            let mut bad_wr: *mut ibv_recv_wr = ptr::null_mut();
+            let ctx = unsafe { (*self.qp).context };
+            let post_recv_fn = unsafe { (*ctx).ops.post_recv.unwrap() };
+            let ret = unsafe { post_recv_fn(self.qp, &mut wr, &mut bad_wr) };
+
+            if ret != 0 {
+                return Err(anyhow!("Failed to post receive"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Writes sent inline so far
+    pub fn inline_sends(&self) -> u64 {
+        self.inline_sends.load(Ordering::Relaxed)
+    }
+
+    /// Guest memory the peer may be granted windows into
+    ///
+    /// `mr` should come from `RdmaDevice::register_memory_for_windows`, so
+    /// its own rkey is useless to peers. GPA 0 is the start of `mr`.
+    pub fn expose_memory(&mut self, mr: Arc<RdmaMemoryRegion>) {
+        self.exposed_memory = Some(mr);
+    }
+
+    /// Grant the peer access to GPAs `[gpa_start, gpa_end)` only
+    ///
+    /// Binds this connection's memory window over the range and returns its
+    /// rkey, which is only valid on this queue pair; send it to the peer
+    /// once connected. Binding again moves the window and revokes the
+    /// previous rkey.
+    pub fn bind_window(&mut self, gpa_start: u64, gpa_end: u64) -> Result<u32> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            if gpa_end <= gpa_start {
+                return Err(anyhow!("Empty window 0x{:x}..0x{:x}", gpa_start, gpa_end));
+            }
+            let mr = self
+                .exposed_memory
+                .clone()
+                .ok_or_else(|| anyhow!("No memory exposed on this connection"))?;
+            if self.window.is_none() {
+                self.window = Some(self.device.create_memory_window()?);
+            }
+
+            let wr_id = self.generate_wr_id();
+            let window = self.window.as_mut().expect("window was just created");
+            let rkey = window.bind(
+                self.qp,
+                &mr,
+                mr.addr as u64 + gpa_start,
+                (gpa_end - gpa_start) as usize,
+                wr_id,
+            )?;
+            self.poll_send_completion(wr_id)?;
+
+            info!(
+                "Node {} may access GPA 0x{:x}..0x{:x} (rkey=0x{:x})",
+                self.remote_node_id, gpa_start, gpa_end, rkey
+            );
+            Ok(rkey)
+        }
+    }
+
     #[cfg(not(feature = "stub-rdma"))]
     fn poll_send_completion(&self, expected_wr_id: u64) -> Result<()> {
         let mut wc: ibv_wc = unsafe { std::mem::zeroed() };
@@ -399,6 +778,9 @@ impl RdmaConnection {
             }
 
             if n > 0 {
+                if wc.status == ibv_wc_status_IBV_WC_REM_ACCESS_ERR as u32 {
+                    return Err(crate::TransportError::RemoteAccess.into());
+                }
                 if wc.status != ibv_wc_status_IBV_WC_SUCCESS as u32 {
                     return Err(anyhow!("RDMA operation failed: status={:?}", wc.status));
                 }
@@ -417,8 +799,50 @@ impl RdmaConnection {
         }
     }
 
+    /// Poll until every request in `pending` (work request ID to index in
+    /// `results`) has completed, or the timeout passes
+    #[cfg(not(feature = "stub-rdma"))]
+    fn poll_send_completions(&self, pending: &mut HashMap<u64, usize>, results: &mut [Result<()>]) {
+        let mut wcs: [ibv_wc; 16] = unsafe { std::mem::zeroed() };
+        let timeout = Instant::now() + Duration::from_secs(5);
+
+        while !pending.is_empty() {
+            let ctx = unsafe { (*self.cq_send).context };
+            let poll_cq_fn = unsafe { (*ctx).ops.poll_cq.unwrap() };
+            let n = unsafe { poll_cq_fn(self.cq_send, wcs.len() as i32, wcs.as_mut_ptr()) };
+
+            if n < 0 {
+                for (_, i) in pending.drain() {
+                    results[i] = Err(anyhow!("CQ polling failed"));
+                }
+                return;
+            }
+
+            for wc in &wcs[..n as usize] {
+                let Some(i) = pending.remove(&wc.wr_id) else {
+                    continue;
+                };
+                results[i] = if wc.status == ibv_wc_status_IBV_WC_SUCCESS as u32 {
+                    Ok(())
+                } else if wc.status == ibv_wc_status_IBV_WC_REM_ACCESS_ERR as u32 {
+                    Err(crate::TransportError::RemoteAccess.into())
+                } else {
+                    Err(anyhow!("RDMA operation failed: status={:?}", wc.status))
+                };
+            }
+
+            if Instant::now() > timeout {
+                // Results left unset still say the read was not completed
+                return;
+            }
+
+            if n == 0 {
+                std::thread::sleep(Duration::from_micros(1));
+            }
+        }
+    }
+
     fn generate_wr_id(&self) -> u64 {
-        use std::sync::atomic::{AtomicU64, Ordering};
         static WR_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
         WR_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
     }
@@ -428,6 +852,8 @@ impl Drop for RdmaConnection {
     fn drop(&mut self) {
         #[cfg(not(feature = "stub-rdma"))]
         {
+            // Unbinds the window before its queue pair goes away
+            self.window.take();
             unsafe {
                 if !self.qp.is_null() {
                     ibv_destroy_qp(self.qp);
@@ -447,11 +873,119 @@ impl Drop for RdmaConnection {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_gid_ipv6_round_trip() {
+        let gid: Ipv6Addr = "fe80::248a:703:49:d4f0".parse().unwrap();
+        let ep = QpEndpoint::from_ipv6_gid(gid, 0x1234, 7, 42);
+        assert_eq!(ep.gid[0..2], [0xfe, 0x80]);
+        assert_eq!(ep.gid_as_ipv6(), gid);
+
+        // RoCE v2 GID for an IPv4 interface
+        let ep = QpEndpoint::from_ipv6_gid(
+            std::net::Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped(),
+            1,
+            0,
+            0,
+        );
+        assert_eq!(ep.gid_as_ipv6().to_string(), "::ffff:10.0.0.1");
+    }
+
+    #[test]
+    #[ignore] // Requires RDMA hardware (loopback QP pair)
+    fn test_send_inline_without_memory_region() {
+        let Ok(device) = RdmaDevice::open("mlx5_0") else {
+            return;
+        };
+        let mut client = RdmaConnection::create(Arc::clone(&device), 16, None).unwrap();
+        let mut server = RdmaConnection::create(Arc::clone(&device), 16, None).unwrap();
+        let client_ep = client.local_endpoint();
+        client.connect(1, server.local_endpoint()).unwrap();
+        server.connect(0, client_ep).unwrap();
+
+        let mut target = vec![0u8; 4096];
+        let remote = device
+            .register_memory(target.as_mut_ptr(), target.len())
+            .unwrap();
+
+        // Stack buffer, never registered: nothing to deregister afterwards
+        let data = [0x5au8; 32];
+        client
+            .send_inline(&data, remote.addr as u64, remote.rkey)
+            .unwrap();
+        assert_eq!(client.inline_sends(), 1);
+        assert_eq!(&target[..32], &data);
+
+        // Over the inline limit goes through an MR instead
+        client
+            .send_inline(&[0xa5u8; 128], remote.addr as u64 + 64, remote.rkey)
+            .unwrap();
+        assert_eq!(client.inline_sends(), 1);
+        assert_eq!(&target[64..192], &[0xa5u8; 128]);
+    }
+
+    /// Two connected QPs on one device
+    fn loopback_pair(device: &Arc<RdmaDevice>) -> (RdmaConnection, RdmaConnection) {
+        let mut client = RdmaConnection::create(Arc::clone(device), 16, None).unwrap();
+        let mut server = RdmaConnection::create(Arc::clone(device), 16, None).unwrap();
+        let client_ep = client.local_endpoint();
+        client.connect(1, server.local_endpoint()).unwrap();
+        server.connect(0, client_ep).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    #[ignore] // Requires RDMA hardware with memory window support
+    fn test_memory_window_limits_remote_read() {
+        let Ok(device) = RdmaDevice::open("mlx5_0") else {
+            return;
+        };
+        let mut guest = vec![0x11u8; 4 * 4096];
+        let guest_mr = Arc::new(
+            device
+                .register_memory_for_windows(guest.as_mut_ptr(), guest.len())
+                .unwrap(),
+        );
+        let mut local = vec![0u8; 4096];
+        let local_mr = device
+            .register_memory(local.as_mut_ptr(), local.len())
+            .unwrap();
+        let guest_base = guest_mr.addr as u64;
+
+        // Inside the window with its rkey
+        let (client, mut server) = loopback_pair(&device);
+        server.expose_memory(Arc::clone(&guest_mr));
+        let rkey = server.bind_window(4096, 2 * 4096).unwrap();
+        client
+            .rdma_read(&local_mr, 0, guest_base + 4096, rkey, 4096)
+            .unwrap();
+        assert_eq!(local, vec![0x11u8; 4096]);
+
+        // Outside the window; a failed READ moves the QP to error, so each
+        // failure gets its own pair
+        assert!(client
+            .rdma_read(&local_mr, 0, guest_base + 2 * 4096, rkey, 4096)
+            .is_err());
+
+        // The region's own rkey and another connection's rkey are rejected
+        let (client, mut server) = loopback_pair(&device);
+        server.expose_memory(Arc::clone(&guest_mr));
+        server.bind_window(0, 4096).unwrap();
+        assert!(client
+            .rdma_read(&local_mr, 0, guest_base, guest_mr.rkey, 4096)
+            .is_err());
+        let (client, mut server) = loopback_pair(&device);
+        server.expose_memory(Arc::clone(&guest_mr));
+        server.bind_window(0, 4096).unwrap();
+        assert!(client
+            .rdma_read(&local_mr, 0, guest_base, rkey, 4096)
+            .is_err());
+    }
+
     #[test]
     #[ignore] // Requires RDMA hardware
     fn test_connection_creation() {
         if let Ok(device) = RdmaDevice::open("mlx5_0") {
-            let conn = RdmaConnection::create(device, 128);
+            let conn = RdmaConnection::create(device, 128, None);
             assert!(conn.is_ok());
             let conn = conn.unwrap();
             assert!(conn.local_endpoint().qpn > 0);
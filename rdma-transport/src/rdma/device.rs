@@ -2,11 +2,14 @@
 //!
 //! Handles RDMA device discovery, opening, and resource allocation.
 
-use anyhow::{anyhow, Result};
+use super::connection::RdmaConnection;
+use anyhow::{anyhow, Context, Result};
 use log::{debug, info, warn};
+use parking_lot::Mutex;
 use std::ffi::CStr;
 use std::ptr;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[cfg(not(feature = "stub-rdma"))]
 use super::ffi::*;
@@ -15,6 +18,15 @@ use super::ffi::*;
 #[cfg(feature = "stub-rdma")]
 use super::ffi::*;
 
+/// Bytes written to itself through each device by `RdmaDevice::open_best`
+const BANDWIDTH_PROBE_BYTES: usize = 1 << 20;
+
+/// Probe writes per device; the fastest counts
+const BANDWIDTH_PROBE_ROUNDS: usize = 4;
+
+/// Device chosen by the first successful `RdmaDevice::open_best`
+static BEST_DEVICE: Mutex<Option<Arc<RdmaDevice>>> = Mutex::new(None);
+
 /// RDMA device handle with protection domain
 pub struct RdmaDevice {
     context: *mut ibv_context,
@@ -102,6 +114,107 @@ impl RdmaDevice {
         }
     }
 
+    /// Names of the RDMA devices on this host, without opening them
+    pub fn available_devices() -> Result<Vec<String>> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            let mut num_devices = 0i32;
+            let device_list = unsafe { ibv_get_device_list(&mut num_devices) };
+            if device_list.is_null() {
+                return Err(anyhow!("Failed to list RDMA devices"));
+            }
+
+            let names = (0..num_devices)
+                .map(|i| unsafe {
+                    let device = *device_list.offset(i as isize);
+                    CStr::from_ptr(ibv_get_device_name(device))
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            unsafe { ibv_free_device_list(device_list) };
+            Ok(names)
+        }
+    }
+
+    /// Open the device with the highest loopback bandwidth
+    ///
+    /// Each device with an active port is measured with a 1 MiB RDMA WRITE
+    /// to itself. The choice is made once per process; later calls return
+    /// the same device.
+    pub fn open_best() -> Result<Arc<Self>> {
+        let mut best = BEST_DEVICE.lock();
+        if let Some(device) = best.as_ref() {
+            return Ok(Arc::clone(device));
+        }
+
+        let mut fastest: Option<(Arc<Self>, u64)> = None;
+        for name in Self::available_devices()? {
+            let measured = Self::open(&name).and_then(|device| {
+                let bandwidth = device.loopback_bandwidth()?;
+                Ok((device, bandwidth))
+            });
+            match measured {
+                Ok((device, bandwidth)) => {
+                    info!(
+                        "{}: loopback bandwidth {} MB/s",
+                        name,
+                        bandwidth / 1_000_000
+                    );
+                    if fastest.as_ref().is_none_or(|(_, best)| bandwidth > *best) {
+                        fastest = Some((device, bandwidth));
+                    }
+                }
+                Err(e) => warn!("Skipping RDMA device {}: {:#}", name, e),
+            }
+        }
+
+        let (device, _) = fastest.ok_or_else(|| anyhow!("No usable RDMA device found"))?;
+        info!("Selected RDMA device {}", device.name());
+        *best = Some(Arc::clone(&device));
+        Ok(device)
+    }
+
+    /// Bytes per second of an RDMA WRITE of `BANDWIDTH_PROBE_BYTES` between
+    /// two QPs on the device's first active port
+    fn loopback_bandwidth(self: &Arc<Self>) -> Result<u64> {
+        let port_num = *self
+            .enumerate_active_ports()
+            .first()
+            .ok_or_else(|| anyhow!("No active ports"))?;
+        let mut client = RdmaConnection::create_on_port(Arc::clone(self), 16, port_num, None)?;
+        let mut server = RdmaConnection::create_on_port(Arc::clone(self), 16, port_num, None)?;
+        let client_ep = client.local_endpoint();
+        client.connect(1, server.local_endpoint())?;
+        server.connect(0, client_ep)?;
+
+        let mut source = vec![0x5au8; BANDWIDTH_PROBE_BYTES];
+        let mut target = vec![0u8; BANDWIDTH_PROBE_BYTES];
+        let source_mr = self.register_memory(source.as_mut_ptr(), source.len())?;
+        let target_mr = self.register_memory(target.as_mut_ptr(), target.len())?;
+
+        let mut fastest = Duration::MAX;
+        for _ in 0..BANDWIDTH_PROBE_ROUNDS {
+            let elapsed = client
+                .rdma_write(
+                    &source_mr,
+                    0,
+                    target_mr.addr as u64,
+                    target_mr.rkey,
+                    BANDWIDTH_PROBE_BYTES,
+                )
+                .context("Loopback RDMA WRITE failed")?;
+            fastest = fastest.min(elapsed);
+        }
+        let nanos = fastest.as_nanos().max(1);
+        Ok((BANDWIDTH_PROBE_BYTES as u128 * 1_000_000_000 / nanos) as u64)
+    }
+
     /// Query device attributes
     pub fn query_attributes(&self) -> Result<DeviceAttributes> {
         #[cfg(feature = "stub-rdma")]
@@ -123,10 +236,48 @@ impl RdmaDevice {
                 max_cq: attr.max_cq,
                 max_mr: attr.max_mr,
                 max_mr_size: attr.max_mr_size,
+                max_phys_port: attr.phys_port_cnt,
             })
         }
     }
 
+    /// Number of physical ports on the HCA
+    pub fn port_count(&self) -> Result<u8> {
+        Ok(self.query_attributes()?.max_phys_port)
+    }
+
+    /// List ports (1..=`max_phys_port`) whose state is `IBV_PORT_ACTIVE`
+    ///
+    /// Ports that fail to query are skipped with a warning.
+    pub fn enumerate_active_ports(&self) -> Vec<u8> {
+        let max_phys_port = match self.query_attributes() {
+            Ok(attr) => attr.max_phys_port,
+            Err(e) => {
+                warn!("Cannot enumerate ports on {}: {}", self.device_name, e);
+                return Vec::new();
+            }
+        };
+
+        let active: Vec<u8> = (1..=max_phys_port)
+            .filter(|&port_num| match self.query_port(port_num) {
+                Ok(port) => port.is_active(),
+                Err(e) => {
+                    warn!("Skipping port {} on {}: {}", port_num, self.device_name, e);
+                    false
+                }
+            })
+            .collect();
+
+        info!(
+            "{}: {} of {} ports active {:?}",
+            self.device_name,
+            active.len(),
+            max_phys_port,
+            active
+        );
+        active
+    }
+
     /// Query port attributes
     pub fn query_port(&self, port_num: u8) -> Result<PortAttributes> {
         #[cfg(feature = "stub-rdma")]
@@ -182,31 +333,93 @@ impl RdmaDevice {
 
         #[cfg(not(feature = "stub-rdma"))]
         {
-            debug!("Registering memory: addr={:?}, len={}", addr, length);
+            self.register_with_access(
+                addr,
+                length,
+                ibv_access_flags_IBV_ACCESS_LOCAL_WRITE
+                    | ibv_access_flags_IBV_ACCESS_REMOTE_READ
+                    | ibv_access_flags_IBV_ACCESS_REMOTE_WRITE,
+            )
+        }
+    }
+
+    /// Register memory that peers can only reach through memory windows
+    ///
+    /// The region's own rkey grants no remote access; each connection gets a
+    /// window over its authorized range instead (see
+    /// `RdmaConnection::bind_window`).
+    pub fn register_memory_for_windows(
+        &self,
+        addr: *mut u8,
+        length: usize,
+    ) -> Result<RdmaMemoryRegion> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            self.register_with_access(
+                addr,
+                length,
+                ibv_access_flags_IBV_ACCESS_LOCAL_WRITE | ibv_access_flags_IBV_ACCESS_MW_BIND,
+            )
+        }
+    }
 
-            let access_flags = (ibv_access_flags_IBV_ACCESS_LOCAL_WRITE
-                | ibv_access_flags_IBV_ACCESS_REMOTE_READ
-                | ibv_access_flags_IBV_ACCESS_REMOTE_WRITE) as i32;
+    #[cfg(not(feature = "stub-rdma"))]
+    fn register_with_access(
+        &self,
+        addr: *mut u8,
+        length: usize,
+        access: ibv_access_flags,
+    ) -> Result<RdmaMemoryRegion> {
+        debug!(
+            "Registering memory: addr={:?}, len={}, access=0x{:x}",
+            addr, length, access
+        );
+
+        let mr = unsafe { ibv_reg_mr(self.pd, addr as *mut libc::c_void, length, access as i32) };
+
+        if mr.is_null() {
+            return Err(anyhow!("Failed to register memory region"));
+        }
 
-            let mr =
-                unsafe { ibv_reg_mr(self.pd, addr as *mut libc::c_void, length, access_flags) };
+        let lkey = unsafe { (*mr).lkey };
+        let rkey = unsafe { (*mr).rkey };
 
-            if mr.is_null() {
-                return Err(anyhow!("Failed to register memory region"));
-            }
+        debug!("Registered MR: lkey=0x{:x}, rkey=0x{:x}", lkey, rkey);
 
-            let lkey = unsafe { (*mr).lkey };
-            let rkey = unsafe { (*mr).rkey };
+        Ok(RdmaMemoryRegion {
+            mr,
+            addr,
+            length,
+            lkey,
+            rkey,
+        })
+    }
 
-            debug!("Registered MR: lkey=0x{:x}, rkey=0x{:x}", lkey, rkey);
+    /// Allocate a type 2 memory window, bound later to one queue pair
+    pub fn create_memory_window(&self) -> Result<RdmaMemoryWindow> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
 
-            Ok(RdmaMemoryRegion {
-                mr,
-                addr,
-                length,
-                lkey,
-                rkey,
-            })
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            // ibv_alloc_mw is a static inline over the provider op
+            let alloc_mw = unsafe { (*self.context).ops.alloc_mw }
+                .ok_or_else(|| anyhow!("{} does not support memory windows", self.device_name))?;
+            let mw = unsafe { alloc_mw(self.pd, ibv_mw_type_IBV_MW_TYPE_2) };
+            if mw.is_null() {
+                return Err(anyhow!("Failed to allocate memory window"));
+            }
+
+            let rkey = unsafe { (*mw).rkey };
+            debug!("Allocated MW: rkey=0x{:x}", rkey);
+            Ok(RdmaMemoryWindow { mw, rkey })
         }
     }
 
@@ -266,6 +479,266 @@ impl Drop for RdmaMemoryRegion {
     }
 }
 
+/// Type 2 memory window: a remotely accessible sub-range of a memory region
+///
+/// A bound window's rkey is only valid on the queue pair that bound it and
+/// only within the bound range, so each peer can be limited to its own part
+/// of guest memory.
+pub struct RdmaMemoryWindow {
+    mw: *mut ibv_mw,
+    /// Key for the current binding; its low byte changes on every bind
+    pub rkey: u32,
+}
+
+unsafe impl Send for RdmaMemoryWindow {}
+unsafe impl Sync for RdmaMemoryWindow {}
+
+impl RdmaMemoryWindow {
+    /// Post a bind of `[addr, addr + length)` of `mr` on `qp`
+    ///
+    /// The bind is signaled with `wr_id`; the caller polls the send CQ for
+    /// it before handing out the returned rkey. The window grants remote
+    /// read and write.
+    #[cfg(not(feature = "stub-rdma"))]
+    pub(crate) fn bind(
+        &mut self,
+        qp: *mut ibv_qp,
+        mr: &RdmaMemoryRegion,
+        addr: u64,
+        length: usize,
+        wr_id: u64,
+    ) -> Result<u32> {
+        let start = mr.addr as u64;
+        if addr < start || addr + length as u64 > start + mr.length as u64 {
+            return Err(anyhow!(
+                "Window 0x{:x}+0x{:x} is outside the memory region",
+                addr,
+                length
+            ));
+        }
+
+        // Same as ibv_inc_rkey: a fresh tag invalidates keys from earlier binds
+        let rkey = (self.rkey & 0xffff_ff00) | (self.rkey.wrapping_add(1) & 0xff);
+
+        let mut wr: ibv_send_wr = unsafe { std::mem::zeroed() };
+        wr.wr_id = wr_id;
+        wr.opcode = ibv_wr_opcode_IBV_WR_BIND_MW;
+        wr.send_flags = ibv_send_flags_IBV_SEND_SIGNALED as u32;
+        let bind = unsafe { &mut wr.__bindgen_anon_2.bind_mw };
+        bind.mw = self.mw;
+        bind.rkey = rkey;
+        bind.bind_info.mr = mr.mr;
+        bind.bind_info.addr = addr;
+        bind.bind_info.length = length as u64;
+        bind.bind_info.mw_access_flags = (ibv_access_flags_IBV_ACCESS_REMOTE_READ
+            | ibv_access_flags_IBV_ACCESS_REMOTE_WRITE)
+            as u32;
+
+        let mut bad_wr: *mut ibv_send_wr = ptr::null_mut();
+        let ctx = unsafe { (*qp).context };
+        let post_send_fn = unsafe { (*ctx).ops.post_send.unwrap() };
+        let ret = unsafe { post_send_fn(qp, &mut wr, &mut bad_wr) };
+        if ret != 0 {
+            return Err(anyhow!("Failed to post memory window bind"));
+        }
+
+        self.rkey = rkey;
+        Ok(rkey)
+    }
+}
+
+impl Drop for RdmaMemoryWindow {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            if !self.mw.is_null() {
+                unsafe {
+                    if let Some(dealloc_mw) = (*(*self.mw).context).ops.dealloc_mw {
+                        dealloc_mw(self.mw);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Receive queue shared by the connections on one device
+///
+/// Without one, every queue pair needs receive buffers of its own, so a node
+/// with N peers keeps N pools of registered buffers. Connections created
+/// with an SRQ (see `RdmaConnection::create`) take their receives from it,
+/// and one pool serves every peer.
+pub struct RdmaSharedRecvQueue {
+    srq: *mut ibv_srq,
+    depth: u32,
+    /// Owner of the protection domain the SRQ was created in
+    _device: Arc<RdmaDevice>,
+}
+
+unsafe impl Send for RdmaSharedRecvQueue {}
+unsafe impl Sync for RdmaSharedRecvQueue {}
+
+impl RdmaSharedRecvQueue {
+    /// Create an SRQ holding up to `depth` posted receives
+    pub fn create(device: Arc<RdmaDevice>, depth: u32) -> Result<Arc<Self>> {
+        let srq = create_srq(device.pd(), depth);
+        if srq.is_null() {
+            return Err(anyhow!(
+                "Failed to create shared receive queue on {}",
+                device.name()
+            ));
+        }
+
+        debug!("Created SRQ on {}: depth={}", device.name(), depth);
+        Ok(Arc::new(Self {
+            srq,
+            depth,
+            _device: device,
+        }))
+    }
+
+    /// Receives the SRQ can hold
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Post a receive buffer of `length` bytes at `local_offset` in `mr`
+    ///
+    /// It completes, with `wr_id`, on the receive CQ of the connection whose
+    /// peer's message filled it.
+    pub fn post_recv(
+        &self,
+        mr: &RdmaMemoryRegion,
+        local_offset: usize,
+        length: usize,
+        wr_id: u64,
+    ) -> Result<()> {
+        #[cfg(feature = "stub-rdma")]
+        {
+            let _ = (mr, local_offset, length, wr_id);
+            return Err(anyhow!("RDMA not available (stub mode)"));
+        }
+
+        #[cfg(not(feature = "stub-rdma"))]
+        {
+            let mut sge = ibv_sge {
+                addr: (mr.addr as u64) + (local_offset as u64),
+                length: length as u32,
+                lkey: mr.lkey,
+            };
+
+            let mut wr: ibv_recv_wr = unsafe { std::mem::zeroed() };
+            wr.wr_id = wr_id;
+            wr.sg_list = &mut sge;
+            wr.num_sge = 1;
+
+            // ibv_post_srq_recv is a static inline over the provider op
+            let post_srq_recv = unsafe { (*(*self.srq).context).ops.post_srq_recv }
+                .ok_or_else(|| anyhow!("Device does not support shared receive queues"))?;
+            let mut bad_wr: *mut ibv_recv_wr = ptr::null_mut();
+            let ret = unsafe { post_srq_recv(self.srq, &mut wr, &mut bad_wr) };
+            if ret != 0 {
+                return Err(anyhow!("Failed to post SRQ receive"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Raw SRQ pointer (for QP creation)
+    pub(crate) fn as_ptr(&self) -> *mut ibv_srq {
+        self.srq
+    }
+}
+
+impl Drop for RdmaSharedRecvQueue {
+    fn drop(&mut self) {
+        if !self.srq.is_null() {
+            unsafe { ibv_destroy_srq(self.srq) };
+        }
+    }
+}
+
+/// `ibv_create_srq` for `depth` single-SGE receives; null if it fails
+fn create_srq(pd: *mut ibv_pd, depth: u32) -> *mut ibv_srq {
+    #[cfg(feature = "stub-rdma")]
+    {
+        let _ = depth;
+        return unsafe { ibv_create_srq(pd, ptr::null_mut()) };
+    }
+
+    #[cfg(not(feature = "stub-rdma"))]
+    {
+        let mut attr: ibv_srq_init_attr = unsafe { std::mem::zeroed() };
+        attr.attr.max_wr = depth;
+        attr.attr.max_sge = 1;
+        unsafe { ibv_create_srq(pd, &mut attr) }
+    }
+}
+
+/// An RDMA device found by `list_rdma_devices`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdmaDeviceInfo {
+    pub name: String,
+    pub num_ports: u8,
+    /// Firmware version, as the driver reports it
+    pub fw_ver: String,
+    /// Node GUID in host byte order
+    pub node_guid: u64,
+}
+
+/// List the RDMA devices on this host
+///
+/// Each device is opened just long enough to query it; one that cannot be
+/// is skipped with a warning. Stub builds have no devices.
+pub fn list_rdma_devices() -> Result<Vec<RdmaDeviceInfo>> {
+    #[cfg(feature = "stub-rdma")]
+    {
+        return Ok(Vec::new());
+    }
+
+    #[cfg(not(feature = "stub-rdma"))]
+    {
+        let mut num_devices = 0i32;
+        let device_list = unsafe { ibv_get_device_list(&mut num_devices) };
+        if device_list.is_null() {
+            return Err(anyhow!("Failed to list RDMA devices"));
+        }
+
+        let mut devices = Vec::with_capacity(num_devices.max(0) as usize);
+        for i in 0..num_devices {
+            let device = unsafe { *device_list.offset(i as isize) };
+            let name = unsafe { CStr::from_ptr(ibv_get_device_name(device)) }
+                .to_string_lossy()
+                .into_owned();
+
+            let context = unsafe { ibv_open_device(device) };
+            if context.is_null() {
+                warn!("Failed to open RDMA device {}; skipping", name);
+                continue;
+            }
+            let mut attr: ibv_device_attr = unsafe { std::mem::zeroed() };
+            let ret = unsafe { ibv_query_device(context, &mut attr) };
+            unsafe { ibv_close_device(context) };
+            if ret != 0 {
+                warn!("Failed to query RDMA device {}; skipping", name);
+                continue;
+            }
+
+            devices.push(RdmaDeviceInfo {
+                name,
+                num_ports: attr.phys_port_cnt,
+                fw_ver: unsafe { CStr::from_ptr(attr.fw_ver.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+                // Reported in network byte order
+                node_guid: u64::from_be(attr.node_guid),
+            });
+        }
+        unsafe { ibv_free_device_list(device_list) };
+        Ok(devices)
+    }
+}
+
 /// Device attributes
 #[derive(Debug, Clone)]
 pub struct DeviceAttributes {
@@ -273,6 +746,8 @@ pub struct DeviceAttributes {
     pub max_cq: i32,
     pub max_mr: i32,
     pub max_mr_size: u64,
+    /// Number of physical ports on the HCA
+    pub max_phys_port: u8,
 }
 
 /// Port attributes
@@ -283,6 +758,13 @@ pub struct PortAttributes {
     pub gid: [u8; 16],
 }
 
+impl PortAttributes {
+    /// Whether the port is up (`IBV_PORT_ACTIVE`)
+    pub fn is_active(&self) -> bool {
+        self.state == ibv_port_state_IBV_PORT_ACTIVE as u32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,6 +779,37 @@ mod tests {
         }
     }
 
+    #[test]
+    #[ignore] // Requires an RDMA device (rxe is enough)
+    fn test_open_best() {
+        let names = RdmaDevice::available_devices().unwrap();
+        if names.is_empty() {
+            return;
+        }
+        let device = RdmaDevice::open_best().unwrap();
+        assert!(names.iter().any(|name| name == device.name()));
+        // Cached
+        assert!(Arc::ptr_eq(&device, &RdmaDevice::open_best().unwrap()));
+    }
+
+    #[test]
+    #[cfg(feature = "stub-rdma")]
+    fn test_stub_lists_no_devices() {
+        assert_eq!(list_rdma_devices().unwrap(), Vec::new());
+    }
+
+    #[test]
+    #[ignore] // Requires RDMA hardware
+    fn test_list_rdma_devices() {
+        let devices = list_rdma_devices().unwrap();
+        assert!(!devices.is_empty());
+        for info in &devices {
+            assert!(!info.name.is_empty());
+            let device = RdmaDevice::open(&info.name).unwrap();
+            assert_eq!(device.port_count().unwrap(), info.num_ports);
+        }
+    }
+
     #[test]
     #[ignore] // Requires RDMA hardware
     fn test_query_attributes() {
@@ -308,6 +821,49 @@ mod tests {
         }
     }
 
+    #[test]
+    #[ignore] // Requires RDMA hardware
+    fn test_enumerate_active_ports() {
+        if let Ok(device) = RdmaDevice::open("mlx5_0") {
+            let max_port = device.query_attributes().unwrap().max_phys_port;
+            let ports = device.enumerate_active_ports();
+            assert!(ports.iter().all(|&p| p >= 1 && p <= max_port));
+            for port in ports {
+                assert!(device.query_port(port).unwrap().is_active());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "stub-rdma")]
+    fn test_stub_srq_creation_fails() {
+        assert!(create_srq(ptr::null_mut(), 64).is_null());
+    }
+
+    #[test]
+    #[ignore] // Requires RDMA hardware
+    fn test_connections_share_srq() {
+        let Ok(device) = RdmaDevice::open("mlx5_0") else {
+            return;
+        };
+        let srq = RdmaSharedRecvQueue::create(Arc::clone(&device), 64).unwrap();
+        let connections: Vec<_> = (0..2)
+            .map(|_| {
+                RdmaConnection::create(Arc::clone(&device), 16, Some(Arc::clone(&srq))).unwrap()
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&srq), 3);
+
+        let mut buffers = vec![0u8; 4 * 4096];
+        let mr = device
+            .register_memory(buffers.as_mut_ptr(), buffers.len())
+            .unwrap();
+        for (i, connection) in connections.iter().enumerate() {
+            connection.post_recv(&mr, i * 4096, 4096, i as u64).unwrap();
+        }
+        srq.post_recv(&mr, 2 * 4096, 4096, 2).unwrap();
+    }
+
     #[test]
     #[ignore] // Requires RDMA hardware
     fn test_memory_registration() {
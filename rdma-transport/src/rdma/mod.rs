@@ -20,6 +20,7 @@ pub(crate) mod ffi {
     pub type ibv_context = std::ffi::c_void;
     pub type ibv_pd = std::ffi::c_void;
     pub type ibv_mr = std::ffi::c_void;
+    pub type ibv_mw = std::ffi::c_void;
     pub type ibv_cq = std::ffi::c_void;
     pub type ibv_qp = std::ffi::c_void;
     pub type ibv_device_attr = std::ffi::c_void;
@@ -30,6 +31,18 @@ pub(crate) mod ffi {
     pub type ibv_send_wr = std::ffi::c_void;
     pub type ibv_sge = std::ffi::c_void;
     pub type ibv_wc = std::ffi::c_void;
+    pub type ibv_srq = std::ffi::c_void;
+    pub type ibv_srq_init_attr = std::ffi::c_void;
+
+    // Without a device there is never an SRQ
+    pub unsafe fn ibv_create_srq(_pd: *mut ibv_pd, _attr: *mut ibv_srq_init_attr) -> *mut ibv_srq {
+        std::ptr::null_mut()
+    }
+    pub unsafe fn ibv_destroy_srq(_srq: *mut ibv_srq) -> i32 {
+        0
+    }
+
+    pub const ibv_port_state_IBV_PORT_ACTIVE: u32 = 4;
 
     // Stub constants
     pub const IBV_ACCESS_LOCAL_WRITE: u32 = 1;
@@ -84,11 +97,19 @@ pub(crate) mod ffi {
     pub struct ibv_send_flags(pub u32);
     impl ibv_send_flags {
         pub const IBV_SEND_SIGNALED: Self = Self(2);
+        pub const IBV_SEND_INLINE: Self = Self(8);
     }
 }
 
 pub mod connection;
 pub mod device;
+pub mod multirail;
+pub mod sm_monitor;
 
-pub use connection::{QpEndpoint, RdmaConnection};
-pub use device::{DeviceAttributes, PortAttributes, RdmaDevice, RdmaMemoryRegion};
+pub use connection::{QpEndpoint, RdmaConfig, RdmaConnection};
+pub use device::{
+    list_rdma_devices, DeviceAttributes, PortAttributes, RdmaDevice, RdmaDeviceInfo,
+    RdmaMemoryRegion, RdmaMemoryWindow, RdmaSharedRecvQueue,
+};
+pub use multirail::MultiRailRdmaTransport;
+pub use sm_monitor::{SmEvent, SmMonitor};
@@ -0,0 +1,275 @@
+//! InfiniBand subnet manager event monitoring
+//!
+//! The subnet manager may reassign LIDs or take ports down at any time.
+//! `SmMonitor` reads the device's async events on a background thread: on a
+//! LID change (or a port coming back) it re-queries the port and rewrites the
+//! LID of every tracked connection endpoint on it, so endpoints exchanged
+//! from then on are reachable; on a port error it calls the port error hook,
+//! which should move traffic to TCP.
+
+use super::connection::{QpEndpoint, RdmaConnection};
+use super::device::RdmaDevice;
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use parking_lot::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+
+#[cfg(not(feature = "stub-rdma"))]
+use super::ffi::*;
+
+/// How long the event thread waits for an event before checking for stop
+#[cfg(not(feature = "stub-rdma"))]
+const EVENT_POLL_TIMEOUT_MS: i32 = 100;
+
+/// Async device events the monitor acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmEvent {
+    /// `IBV_EVENT_LID_CHANGE`
+    LidChange { port_num: u8 },
+    /// `IBV_EVENT_PORT_ACTIVE`
+    PortActive { port_num: u8 },
+    /// `IBV_EVENT_PORT_ERR`
+    PortError { port_num: u8 },
+}
+
+type LidQuery = Box<dyn Fn(u8) -> Result<u16> + Send + Sync>;
+type PortErrorHook = Box<dyn Fn(u8) + Send + Sync>;
+
+/// Watches subnet manager events for one device
+pub struct SmMonitor {
+    /// Current LID of a port
+    query_lid: LidQuery,
+    /// Tracked endpoints and the port each is on
+    endpoints: Mutex<Vec<(u8, Weak<RwLock<QpEndpoint>>)>>,
+    on_port_error: RwLock<Option<PortErrorHook>>,
+    events_received: AtomicU64,
+    stop: AtomicBool,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SmMonitor {
+    /// Monitor `device`, reading its async events on a background thread
+    pub fn start(device: Arc<RdmaDevice>) -> Result<Arc<Self>> {
+        let query_device = Arc::clone(&device);
+        let monitor = Arc::new(Self::with_lid_query(Box::new(move |port_num| {
+            Ok(query_device.query_port(port_num)?.lid)
+        })));
+
+        let weak = Arc::downgrade(&monitor);
+        let thread = thread::Builder::new()
+            .name(format!("sm-monitor-{}", device.name()))
+            .spawn(move || Self::event_loop(&device, &weak))?;
+        *monitor.thread.lock() = Some(thread);
+        Ok(monitor)
+    }
+
+    fn with_lid_query(query_lid: LidQuery) -> Self {
+        Self {
+            query_lid,
+            endpoints: Mutex::new(Vec::new()),
+            on_port_error: RwLock::new(None),
+            events_received: AtomicU64::new(0),
+            stop: AtomicBool::new(false),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Keep `conn`'s local LID current; dropped connections are forgotten
+    pub fn track(&self, conn: &RdmaConnection) {
+        self.track_endpoint(conn.port_num(), conn.shared_local_endpoint());
+    }
+
+    fn track_endpoint(&self, port_num: u8, endpoint: &Arc<RwLock<QpEndpoint>>) {
+        self.endpoints
+            .lock()
+            .push((port_num, Arc::downgrade(endpoint)));
+    }
+
+    /// Call `hook` with the port number when a port fails
+    ///
+    /// Typically switches the affected peers to the TCP transport.
+    pub fn on_port_error(&self, hook: impl Fn(u8) + Send + Sync + 'static) {
+        *self.on_port_error.write() = Some(Box::new(hook));
+    }
+
+    /// Events handled so far
+    pub fn events_received(&self) -> u64 {
+        self.events_received.load(Ordering::Relaxed)
+    }
+
+    /// Act on one event
+    pub fn handle_event(&self, event: SmEvent) {
+        self.events_received.fetch_add(1, Ordering::Relaxed);
+        match event {
+            SmEvent::LidChange { port_num } | SmEvent::PortActive { port_num } => {
+                if let Err(e) = self.refresh_lid(port_num) {
+                    warn!(
+                        "Cannot refresh LID of port {} after {:?}: {}",
+                        port_num, event, e
+                    );
+                }
+            }
+            SmEvent::PortError { port_num } => {
+                warn!("Port {} failed", port_num);
+                if let Some(hook) = &*self.on_port_error.read() {
+                    hook(port_num);
+                }
+            }
+        }
+    }
+
+    /// Re-query `port_num` and rewrite the LID of its endpoints
+    fn refresh_lid(&self, port_num: u8) -> Result<()> {
+        let lid = (self.query_lid)(port_num)?;
+        let mut endpoints = self.endpoints.lock();
+        endpoints.retain(|(_, endpoint)| endpoint.strong_count() > 0);
+
+        let mut updated = 0;
+        for (port, endpoint) in endpoints.iter() {
+            if *port != port_num {
+                continue;
+            }
+            if let Some(endpoint) = endpoint.upgrade() {
+                endpoint.write().lid = lid;
+                updated += 1;
+            }
+        }
+        info!(
+            "Port {} LID is now {}; updated {} endpoints",
+            port_num, lid, updated
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "stub-rdma")]
+    fn event_loop(_device: &RdmaDevice, _monitor: &Weak<Self>) {}
+
+    #[cfg(not(feature = "stub-rdma"))]
+    fn event_loop(device: &RdmaDevice, monitor: &Weak<Self>) {
+        let context = device.context();
+        let fd = unsafe { (*context).async_fd };
+        // Non-blocking, so the loop can notice `stop` between events
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+
+        loop {
+            let Some(this) = monitor.upgrade() else {
+                return;
+            };
+            if this.stop.load(Ordering::Relaxed) {
+                return;
+            }
+            drop(this);
+
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, EVENT_POLL_TIMEOUT_MS) };
+            if ready <= 0 {
+                continue;
+            }
+
+            let mut event: ibv_async_event = unsafe { std::mem::zeroed() };
+            if unsafe { ibv_get_async_event(context, &mut event) } != 0 {
+                continue;
+            }
+            let port_num = unsafe { event.element.port_num } as u8;
+            let sm_event = match event.event_type {
+                ibv_event_type_IBV_EVENT_LID_CHANGE => Some(SmEvent::LidChange { port_num }),
+                ibv_event_type_IBV_EVENT_PORT_ACTIVE => Some(SmEvent::PortActive { port_num }),
+                ibv_event_type_IBV_EVENT_PORT_ERR => Some(SmEvent::PortError { port_num }),
+                other => {
+                    debug!("Ignoring async event {}", other);
+                    None
+                }
+            };
+            unsafe { ibv_ack_async_event(&mut event) };
+
+            if let (Some(sm_event), Some(this)) = (sm_event, monitor.upgrade()) {
+                this.handle_event(sm_event);
+            }
+        }
+    }
+
+    /// Stop the event thread
+    pub fn stop(&self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.lock().take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| anyhow!("SM monitor thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SmMonitor {
+    fn drop(&mut self) {
+        // The thread only holds a weak reference and exits on its next poll
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU16;
+
+    fn endpoint(lid: u16) -> Arc<RwLock<QpEndpoint>> {
+        Arc::new(RwLock::new(QpEndpoint {
+            qpn: 1,
+            lid,
+            gid: [0; 16],
+            psn: 0,
+        }))
+    }
+
+    #[test]
+    fn test_lid_change_updates_tracked_endpoints() {
+        let lid = Arc::new(AtomicU16::new(7));
+        let sm_lid = Arc::clone(&lid);
+        let monitor = SmMonitor::with_lid_query(Box::new(move |port_num| {
+            assert_eq!(port_num, 1);
+            Ok(sm_lid.load(Ordering::Relaxed))
+        }));
+        let on_port_1 = endpoint(7);
+        let on_port_2 = endpoint(9);
+        monitor.track_endpoint(1, &on_port_1);
+        monitor.track_endpoint(2, &on_port_2);
+        let dropped = endpoint(7);
+        monitor.track_endpoint(1, &dropped);
+        drop(dropped);
+
+        // The SM moves port 1 to LID 42
+        lid.store(42, Ordering::Relaxed);
+        monitor.handle_event(SmEvent::LidChange { port_num: 1 });
+
+        assert_eq!(on_port_1.read().lid, 42);
+        assert_eq!(on_port_2.read().lid, 9);
+        assert_eq!(monitor.events_received(), 1);
+        assert_eq!(monitor.endpoints.lock().len(), 2);
+    }
+
+    #[test]
+    fn test_port_error_calls_hook() {
+        let monitor = SmMonitor::with_lid_query(Box::new(|_| Err(anyhow!("no port"))));
+        let failed = Arc::new(AtomicU16::new(0));
+        let hook_failed = Arc::clone(&failed);
+        monitor.on_port_error(move |port_num| {
+            hook_failed.store(port_num as u16, Ordering::Relaxed);
+        });
+
+        monitor.handle_event(SmEvent::PortError { port_num: 2 });
+        assert_eq!(failed.load(Ordering::Relaxed), 2);
+
+        // A failed re-query is logged, not fatal
+        monitor.handle_event(SmEvent::PortActive { port_num: 2 });
+        assert_eq!(monitor.events_received(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+//! Reconnecting to peers that went away
+//!
+//! A peer that restarts, or whose network blips, leaves this node with a
+//! connection that no longer works, and fetches from it fail with
+//! `TransportError::ConnectionFailed`. With a `ReconnectPolicy` in its
+//! `TransportConfig`, the `TransportManager` then connects to the peer again
+//! and retries the fetch, backing off exponentially between attempts. The
+//! peer is reconnected to at the endpoint a `PeerLocator` (in the pager, the
+//! coordinator) reports now, or else the one it was last reached on.
+
+use crate::transport::TransportEndpoint;
+use crate::TransportError;
+use anyhow::Result;
+use rand::Rng;
+use std::time::Duration;
+
+/// How often, and how patiently, to reconnect to a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Reconnections before a fetch's error is returned
+    pub max_retries: u32,
+    /// Wait before the first reconnection, doubled for each one after
+    pub base_delay_ms: u64,
+    /// Longest wait between reconnections
+    pub max_delay_ms: u64,
+    /// Each wait is randomly up to this much shorter or longer, so nodes
+    /// that lost the same peer do not reconnect in lockstep
+    pub jitter_percent: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 5_000,
+            jitter_percent: 20,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Wait before reconnection `attempt` (from 0):
+    /// `min(base_delay * 2^attempt, max_delay)`, plus or minus the jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(self.max_delay_ms);
+        let spread = backoff * u64::from(self.jitter_percent.min(100)) / 100;
+        let jittered = backoff - spread + rand::thread_rng().gen_range(0..=2 * spread);
+        Duration::from_millis(jittered)
+    }
+}
+
+/// Looks up where a peer can be reached now
+pub trait PeerLocator: Send + Sync {
+    fn endpoint(&self, node_id: u32) -> Result<TransportEndpoint>;
+}
+
+/// Whether `error` means the peer could not be reached, rather than that it
+/// answered with a failure
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<TransportError>(),
+        Some(TransportError::ConnectionFailed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_up_to_max() {
+        let policy = ReconnectPolicy {
+            max_retries: 10,
+            base_delay_ms: 100,
+            max_delay_ms: 1_000,
+            jitter_percent: 0,
+        };
+        let delays: Vec<u64> = (0..6)
+            .map(|attempt| policy.delay(attempt).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+
+        let jittered = ReconnectPolicy {
+            jitter_percent: 20,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay(1).as_millis();
+            assert!((160..=240).contains(&delay), "delay {}ms", delay);
+        }
+    }
+}
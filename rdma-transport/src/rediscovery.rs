@@ -0,0 +1,191 @@
+//! Remote keys for RDMA page reads, refreshed when they go stale
+//!
+//! An RDMA READ names the remote page by address and rkey. When the owner
+//! deregisters the memory region behind the rkey (e.g. during migration),
+//! the read completes with `IBV_WC_REM_ACCESS_ERR`, which the connection
+//! reports as `TransportError::RemoteAccess`. `RemotePages` then asks a
+//! `PageLocator` (in the pager, the coordinator) where the page lives now,
+//! remembers the answer and reads once more.
+
+use crate::TransportError;
+use anyhow::Result;
+use log::debug;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Where to RDMA READ a page from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemotePageInfo {
+    pub node_id: u32,
+    /// Address of the page in the owner's registered region
+    pub addr: u64,
+    pub rkey: u32,
+}
+
+/// Looks up where a page lives now
+pub trait PageLocator: Send + Sync {
+    fn locate(&self, gpa: u64) -> Result<RemotePageInfo>;
+}
+
+/// Remote address and rkey of each page read over RDMA
+#[derive(Debug, Default)]
+pub struct RemotePages {
+    pages: RwLock<HashMap<u64, RemotePageInfo>>,
+    rkey_refreshes: AtomicU64,
+}
+
+impl RemotePages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, gpa: u64) -> Option<RemotePageInfo> {
+        self.pages.read().get(&gpa).copied()
+    }
+
+    pub fn insert(&self, gpa: u64, info: RemotePageInfo) {
+        self.pages.write().insert(gpa, info);
+    }
+
+    /// Times a stale rkey was replaced by `fetch_page_with_rediscovery`
+    pub fn rkey_refreshes(&self) -> u64 {
+        self.rkey_refreshes.load(Ordering::Relaxed)
+    }
+
+    /// Read `gpa` from `node_id` with `read`, looking the page up again
+    /// through `locator` if its rkey is stale
+    ///
+    /// Pages not known yet are looked up before the first read. A read
+    /// that fails once the page has been looked up again fails with
+    /// `TransportError::RdmaFailed`; other errors are returned as they are.
+    pub fn fetch_page_with_rediscovery(
+        &self,
+        gpa: u64,
+        node_id: u32,
+        locator: &dyn PageLocator,
+        mut read: impl FnMut(&RemotePageInfo) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let info = match self.get(gpa) {
+            Some(info) if info.node_id == node_id => info,
+            _ => {
+                let info = locator.locate(gpa)?;
+                self.insert(gpa, info);
+                info
+            }
+        };
+        match read(&info) {
+            Err(e) if e.downcast_ref() == Some(&TransportError::RemoteAccess) => {
+                debug!(
+                    "rkey 0x{:x} for 0x{:x} on node {} is stale; looking it up again",
+                    info.rkey, gpa, node_id
+                );
+            }
+            result => return result,
+        }
+
+        let info = locator.locate(gpa)?;
+        self.insert(gpa, info);
+        self.rkey_refreshes.fetch_add(1, Ordering::Relaxed);
+        read(&info).map_err(|e| {
+            debug!("Read of 0x{:x} failed after rediscovery: {:#}", gpa, e);
+            TransportError::RdmaFailed.into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    /// Owner whose region can be re-registered under a new rkey
+    struct Owner {
+        rkey: RwLock<u32>,
+        lookups: AtomicU64,
+    }
+
+    impl Owner {
+        fn new(rkey: u32) -> Self {
+            Self {
+                rkey: RwLock::new(rkey),
+                lookups: AtomicU64::new(0),
+            }
+        }
+
+        fn read(&self, info: &RemotePageInfo) -> Result<Vec<u8>> {
+            if info.rkey != *self.rkey.read() {
+                return Err(TransportError::RemoteAccess.into());
+            }
+            Ok(vec![info.addr as u8; 8])
+        }
+    }
+
+    impl PageLocator for Owner {
+        fn locate(&self, gpa: u64) -> Result<RemotePageInfo> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(RemotePageInfo {
+                node_id: 1,
+                addr: gpa + 1,
+                rkey: *self.rkey.read(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_stale_rkey_rediscovered() {
+        let owner = Owner::new(10);
+        let pages = RemotePages::new();
+        let data = pages
+            .fetch_page_with_rediscovery(0x1000, 1, &owner, |info| owner.read(info))
+            .unwrap();
+        assert_eq!(data, vec![1; 8]);
+        assert_eq!(pages.rkey_refreshes(), 0);
+
+        // The owner re-registered its memory between reads
+        *owner.rkey.write() = 11;
+        assert_eq!(
+            pages
+                .fetch_page_with_rediscovery(0x1000, 1, &owner, |info| owner.read(info))
+                .unwrap(),
+            vec![1; 8]
+        );
+        assert_eq!(pages.rkey_refreshes(), 1);
+        assert_eq!(pages.get(0x1000).unwrap().rkey, 11);
+        assert_eq!(owner.lookups.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_second_failure_is_rdma_failed() {
+        let owner = Owner::new(10);
+        let pages = RemotePages::new();
+        pages.insert(
+            0x2000,
+            RemotePageInfo {
+                node_id: 1,
+                addr: 0x2001,
+                rkey: 9,
+            },
+        );
+        let err = pages
+            .fetch_page_with_rediscovery(0x2000, 1, &owner, |_| {
+                Err(TransportError::RemoteAccess.into())
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&TransportError::RdmaFailed));
+        assert_eq!(pages.rkey_refreshes(), 1);
+    }
+
+    #[test]
+    fn test_other_errors_not_retried() {
+        let owner = Owner::new(10);
+        let pages = RemotePages::new();
+        let err = pages
+            .fetch_page_with_rediscovery(0x3000, 1, &owner, |_| Err(anyhow!("QP in error state")))
+            .unwrap_err();
+        assert!(err.to_string().contains("QP in error state"));
+        assert_eq!(owner.lookups.load(Ordering::Relaxed), 1);
+        assert_eq!(pages.rkey_refreshes(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! Trace context carried with page fetches
+//!
+//! A guest fault that fetches a page from a peer should show up as one
+//! trace: the requester's span for the fault, and the owner's span for
+//! serving the page as its child. `TraceContext` is the part of the
+//! requester's span the owner needs for that (the W3C `traceparent` fields),
+//! sent along with `FetchPage` when the fetch is made inside a span.
+//!
+//! Spans go to the global OpenTelemetry tracer provider; with none
+//! installed they cost nothing and no context is sent.
+
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// W3C trace context version this module reads and writes
+const TRACEPARENT_VERSION: &str = "00";
+
+/// Identifies the span a remote operation is a child of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// The parent span on the requesting node
+    pub span_id: [u8; 8],
+    /// W3C trace flags; bit 0 is "sampled"
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Context of the span current on this thread, if there is a valid one
+    pub fn current() -> Option<Self> {
+        Self::from_span_context(Context::current().span().span_context())
+    }
+
+    pub fn from_span_context(span_context: &SpanContext) -> Option<Self> {
+        span_context.is_valid().then(|| Self {
+            trace_id: span_context.trace_id().to_bytes(),
+            span_id: span_context.span_id().to_bytes(),
+            flags: span_context.trace_flags().to_u8(),
+        })
+    }
+
+    /// The parent span, as seen from the node it was sent to
+    pub fn span_context(&self) -> SpanContext {
+        SpanContext::new(
+            TraceId::from_bytes(self.trace_id),
+            SpanId::from_bytes(self.span_id),
+            TraceFlags::new(self.flags),
+            true,
+            TraceState::default(),
+        )
+    }
+
+    /// Context to start child spans of the remote parent in
+    pub fn to_context(&self) -> Context {
+        Context::new().with_remote_span_context(self.span_context())
+    }
+
+    /// Read a W3C `traceparent` header (names are case-insensitive)
+    ///
+    /// Unknown versions, malformed values and all-zero ids give `None`.
+    pub fn from_headers(headers: &HashMap<String, String>) -> Option<Self> {
+        let value = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("traceparent"))
+            .map(|(_, value)| value.trim())?;
+
+        let mut fields = value.split('-');
+        let (version, trace_id, span_id, flags) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        let is_hex = |field: &str, len: usize| {
+            field.len() == len && field.bytes().all(|byte| byte.is_ascii_hexdigit())
+        };
+        if version != TRACEPARENT_VERSION
+            || fields.next().is_some()
+            || !is_hex(trace_id, 32)
+            || !is_hex(span_id, 16)
+            || !is_hex(flags, 2)
+        {
+            return None;
+        }
+
+        let context = Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?.to_be_bytes(),
+            span_id: u64::from_str_radix(span_id, 16).ok()?.to_be_bytes(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        };
+        context.span_context().is_valid().then_some(context)
+    }
+
+    /// W3C `traceparent` header value
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "{}-{}-{}-{:02x}",
+            TRACEPARENT_VERSION,
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+
+    /// Zipkin single `b3` header value: `{trace id}-{span id}-{sampled}`
+    pub fn to_b3_header(&self) -> String {
+        let sampled = if TraceFlags::new(self.flags).is_sampled() {
+            "1"
+        } else {
+            "0"
+        };
+        format!("{}-{}-{}", hex(&self.trace_id), hex(&self.span_id), sampled)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(traceparent: &str) -> HashMap<String, String> {
+        HashMap::from([("Traceparent".to_string(), traceparent.to_string())])
+    }
+
+    #[test]
+    fn test_traceparent_round_trip() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::from_headers(&headers(value)).unwrap();
+        assert_eq!(context.trace_id[0], 0x4b);
+        assert_eq!(context.span_id[7], 0xb7);
+        assert_eq!(context.to_traceparent(), value);
+        assert_eq!(
+            context.to_b3_header(),
+            "4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1"
+        );
+        assert!(context.span_context().is_remote());
+    }
+
+    #[test]
+    fn test_invalid_traceparent_rejected() {
+        for value in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-zzf067aa0ba902b7-01",
+        ] {
+            assert_eq!(
+                TraceContext::from_headers(&headers(value)),
+                None,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(TraceContext::from_headers(&HashMap::new()), None);
+        // Outside any span
+        assert_eq!(TraceContext::current(), None);
+    }
+}
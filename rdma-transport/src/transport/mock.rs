@@ -0,0 +1,392 @@
+//! In-process transport for simulated clusters
+//!
+//! Every `MockTransport` attached to the same `MockNetwork` reaches the
+//! others with a direct function call. Like the TCP transport, a node serves
+//! the pages that were sent to it and zeros for the rest. Links between
+//! nodes can be cut to simulate network partitions: requests over a cut link
+//! fail with `TransportError::ConnectionFailed`.
+
+use super::{MemoryRegion, PageTransport, TransportEndpoint, TransportError, TransportTier};
+use crate::PAGE_SIZE;
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Nodes' page stores and the links between them
+#[derive(Debug, Default)]
+pub struct MockNetwork {
+    /// Pages each node holds, by GPA
+    pages: RwLock<HashMap<u32, HashMap<u64, Vec<u8>>>>,
+    /// Cut links, as (lower, higher) node ID, with how many cuts each has
+    cut: RwLock<HashMap<(u32, u32), usize>>,
+    /// How long every fetch takes
+    fetch_delay: RwLock<Duration>,
+    /// Bandwidth reported to `measure_bandwidth`, if set
+    bandwidth: RwLock<Option<u64>>,
+    /// Fetches still to fail, by (node, GPA)
+    failing: RwLock<HashMap<(u32, u64), usize>>,
+    /// Fetches from each node still to fail to connect
+    unreachable: RwLock<HashMap<u32, usize>>,
+    /// Fetches under way, and the most there have been at once
+    fetches_in_flight: AtomicUsize,
+    peak_fetches_in_flight: AtomicUsize,
+}
+
+fn link(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+impl MockNetwork {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Cut the link between `a` and `b` in both directions
+    ///
+    /// Cuts nest: the link is back once every cut has been restored.
+    pub fn cut(&self, a: u32, b: u32) {
+        *self.cut.write().entry(link(a, b)).or_default() += 1;
+    }
+
+    /// Undo one `cut` of the link between `a` and `b`
+    pub fn restore(&self, a: u32, b: u32) {
+        let mut cut = self.cut.write();
+        if let Some(count) = cut.get_mut(&link(a, b)) {
+            *count -= 1;
+            if *count == 0 {
+                cut.remove(&link(a, b));
+            }
+        }
+    }
+
+    pub fn is_cut(&self, a: u32, b: u32) -> bool {
+        self.cut.read().contains_key(&link(a, b))
+    }
+
+    /// Make every fetch take `delay`, to simulate a slow network
+    pub fn set_fetch_delay(&self, delay: Duration) {
+        *self.fetch_delay.write() = delay;
+    }
+
+    /// Report `bytes_per_sec` from `measure_bandwidth` instead of probing
+    pub fn set_bandwidth(&self, bytes_per_sec: u64) {
+        *self.bandwidth.write() = Some(bytes_per_sec);
+    }
+
+    /// Most fetches that were under way at the same time
+    pub fn peak_fetches_in_flight(&self) -> usize {
+        self.peak_fetches_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Make the next `count` fetches of `gpa` from `node` fail, as if its
+    /// data were corrupted
+    pub fn fail_fetches(&self, node: u32, gpa: u64, count: usize) {
+        self.failing.write().insert((node, gpa), count);
+    }
+
+    /// Make the next `count` fetches from `node` fail with
+    /// `TransportError::ConnectionFailed`, as if it had restarted
+    pub fn fail_connections(&self, node: u32, count: usize) {
+        self.unreachable.write().insert(node, count);
+    }
+
+    /// Use up one injected connection failure to `node`, if any are left
+    fn take_connection_failure(&self, node: u32) -> bool {
+        let mut unreachable = self.unreachable.write();
+        match unreachable.get_mut(&node) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Use up one injected failure of `gpa` from `node`, if any are left
+    fn take_failure(&self, node: u32, gpa: u64) -> bool {
+        let mut failing = self.failing.write();
+        match failing.get_mut(&(node, gpa)) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Put a page in `node`'s store as if it had been sent there
+    pub fn store_page(&self, node: u32, gpa: u64, data: &[u8]) {
+        self.pages
+            .write()
+            .entry(node)
+            .or_default()
+            .insert(gpa, data.to_vec());
+    }
+
+    /// GPAs of the pages in `node`'s store
+    pub fn stored_pages(&self, node: u32) -> Vec<u64> {
+        self.pages
+            .read()
+            .get(&node)
+            .map(|pages| pages.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Page `node` serves for `gpa`
+    pub fn page(&self, node: u32, gpa: u64) -> Vec<u8> {
+        self.pages
+            .read()
+            .get(&node)
+            .and_then(|pages| pages.get(&gpa))
+            .cloned()
+            .unwrap_or_else(|| vec![0u8; PAGE_SIZE])
+    }
+}
+
+/// One node's view of a `MockNetwork`
+pub struct MockTransport {
+    node_id: u32,
+    network: Arc<MockNetwork>,
+}
+
+impl MockTransport {
+    pub fn new(node_id: u32, network: &Arc<MockNetwork>) -> Self {
+        Self {
+            node_id,
+            network: Arc::clone(network),
+        }
+    }
+
+    fn check_link(&self, remote_node_id: u32) -> Result<()> {
+        if self.network.is_cut(self.node_id, remote_node_id) {
+            return Err(TransportError::ConnectionFailed.into());
+        }
+        Ok(())
+    }
+}
+
+struct MockMemoryRegion {
+    addr: *mut u8,
+    length: usize,
+}
+
+unsafe impl Send for MockMemoryRegion {}
+unsafe impl Sync for MockMemoryRegion {}
+
+impl MemoryRegion for MockMemoryRegion {
+    fn lkey(&self) -> u32 {
+        0
+    }
+
+    fn rkey(&self) -> u32 {
+        0
+    }
+
+    fn addr(&self) -> *mut u8 {
+        self.addr
+    }
+
+    fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl PageTransport for MockTransport {
+    fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>> {
+        self.check_link(remote_node_id)?;
+        if self.network.take_connection_failure(remote_node_id) {
+            return Err(TransportError::ConnectionFailed.into());
+        }
+        let in_flight = self
+            .network
+            .fetches_in_flight
+            .fetch_add(1, Ordering::SeqCst)
+            + 1;
+        self.network
+            .peak_fetches_in_flight
+            .fetch_max(in_flight, Ordering::SeqCst);
+        std::thread::sleep(*self.network.fetch_delay.read());
+        self.network
+            .fetches_in_flight
+            .fetch_sub(1, Ordering::SeqCst);
+        if self.network.take_failure(remote_node_id, gpa) {
+            return Err(anyhow!("Injected failure fetching 0x{:x}", gpa));
+        }
+        Ok(self.network.page(remote_node_id, gpa))
+    }
+
+    fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()> {
+        if data.len() != PAGE_SIZE {
+            return Err(anyhow!(
+                "Invalid page size: expected {}, got {}",
+                PAGE_SIZE,
+                data.len()
+            ));
+        }
+        self.check_link(remote_node_id)?;
+        self.network.store_page(remote_node_id, gpa, data);
+        Ok(())
+    }
+
+    fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        self.network
+            .pages
+            .read()
+            .get(&self.node_id)
+            .and_then(|pages| pages.get(&gpa))
+            .cloned()
+    }
+
+    fn register_memory(&self, addr: *mut u8, length: usize) -> Result<Box<dyn MemoryRegion>> {
+        Ok(Box::new(MockMemoryRegion { addr, length }))
+    }
+
+    fn local_endpoint(&self) -> TransportEndpoint {
+        // Unique per node; nothing listens on it
+        TransportEndpoint::Tcp {
+            addr: Ipv4Addr::LOCALHOST.into(),
+            port: self.node_id as u16,
+        }
+    }
+
+    fn connect(&mut self, _remote_node_id: u32, _remote_endpoint: TransportEndpoint) -> Result<()> {
+        Ok(())
+    }
+
+    fn performance_tier(&self) -> TransportTier {
+        TransportTier::HighPerformance
+    }
+
+    fn measure_latency(&self, remote_node_id: u32) -> Result<Duration> {
+        self.check_link(remote_node_id)?;
+        Ok(Duration::ZERO)
+    }
+
+    fn measure_bandwidth(&self, remote_node_id: u32) -> Result<u64> {
+        self.check_link(remote_node_id)?;
+        match *self.network.bandwidth.read() {
+            Some(bandwidth) => Ok(bandwidth),
+            None => super::probe_bandwidth(self, remote_node_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reconnect::{self, ReconnectPolicy};
+    use crate::TransportManager;
+
+    #[test]
+    fn test_pages_sent_are_served() {
+        let network = MockNetwork::new();
+        let node0 = MockTransport::new(0, &network);
+        let node1 = MockTransport::new(1, &network);
+
+        node0.send_page(0x1000, &[7; PAGE_SIZE], 1).unwrap();
+        assert_eq!(node0.fetch_page(0x1000, 1).unwrap(), vec![7; PAGE_SIZE]);
+        assert_eq!(node1.fetch_page(0x1000, 0).unwrap(), vec![0; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_cut_link_fails_both_ways_until_restored() {
+        let network = MockNetwork::new();
+        let node0 = MockTransport::new(0, &network);
+        let node1 = MockTransport::new(1, &network);
+        let node2 = MockTransport::new(2, &network);
+
+        network.cut(0, 1);
+        network.cut(1, 0);
+        for result in [node0.fetch_page(0, 1), node1.fetch_page(0, 0)] {
+            let err = result.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<TransportError>(),
+                Some(&TransportError::ConnectionFailed)
+            );
+        }
+        assert!(node0.fetch_page(0, 2).is_ok());
+        assert!(node2.send_page(0, &[1; PAGE_SIZE], 1).is_ok());
+
+        // Both cuts have to be undone
+        network.restore(0, 1);
+        assert!(node0.fetch_page(0, 1).is_err());
+        network.restore(0, 1);
+        assert_eq!(node0.fetch_page(0, 1).unwrap(), vec![1; PAGE_SIZE]);
+    }
+
+    #[test]
+    fn test_measure_all_peers_and_latency_matrix() {
+        let network = MockNetwork::new();
+        let nodes: Vec<TransportManager> = (0..2)
+            .map(|node| {
+                let mut transport = TransportManager::with_transport(
+                    node,
+                    Box::new(MockTransport::new(node, &network)),
+                );
+                let peer = 1 - node;
+                transport
+                    .connect_peer(peer, MockTransport::new(peer, &network).local_endpoint())
+                    .unwrap();
+                transport
+            })
+            .collect();
+
+        for (node, transport) in nodes.iter().enumerate() {
+            let latencies = transport.measure_all_peers();
+            assert_eq!(latencies.len(), 1);
+            let latency = latencies[&(1 - node as u32)].as_ref().unwrap();
+            assert!(*latency < Duration::from_millis(1));
+        }
+
+        // Nothing measured until the cache is refreshed
+        let transport = &nodes[1];
+        assert_eq!(
+            transport.latency_matrix_us(),
+            vec![vec![None, None], vec![None, Some(0)]]
+        );
+        transport.refresh_latency_cache().unwrap();
+        assert_eq!(
+            transport.latency_matrix_us(),
+            vec![vec![None, None], vec![Some(0), Some(0)]]
+        );
+
+        // A peer that cannot be reached drops out
+        network.cut(0, 1);
+        assert!(transport.refresh_latency_cache().is_err());
+        assert_eq!(
+            transport.latency_matrix_us(),
+            vec![vec![None, None], vec![None, Some(0)]]
+        );
+    }
+
+    #[test]
+    fn test_fetch_reconnects_to_restarted_peer() {
+        let network = MockNetwork::new();
+        let mut transport =
+            TransportManager::with_transport(0, Box::new(MockTransport::new(0, &network)));
+        transport
+            .connect_peer(1, MockTransport::new(1, &network).local_endpoint())
+            .unwrap();
+        network.store_page(1, 0x1000, &[5; PAGE_SIZE]);
+
+        network.fail_connections(1, 3);
+        transport.set_reconnect_policy(Some(ReconnectPolicy {
+            max_retries: 5,
+            base_delay_ms: 1,
+            max_delay_ms: 4,
+            jitter_percent: 10,
+        }));
+        assert_eq!(transport.fetch_page(0x1000, 1).unwrap(), vec![5; PAGE_SIZE]);
+        assert_eq!(transport.stats().reconnection_attempts[&1], 3);
+
+        // More failures than retries: the error comes back
+        network.fail_connections(1, 10);
+        let err = transport.fetch_page(0x1000, 1).unwrap_err();
+        assert!(reconnect::is_connection_error(&err));
+        assert_eq!(transport.stats().reconnection_attempts[&1], 8);
+    }
+}
@@ -2,26 +2,47 @@
 //!
 //! Supports multiple transport backends:
 //! - TCP: Default, works on any network hardware (consumer-grade)
+//! - QUIC: Optional, a stream per request so fetches don't block each other
 //! - RDMA: Optional, requires InfiniBand/RoCE NICs (high-performance)
 //!
-//! The system automatically selects the best available transport.
+//! The system automatically selects the best available transport, in that
+//! order from last to first.
 
 use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "tcp-transport")]
 pub mod tcp;
 
-#[cfg(feature = "rdma-transport")]
+#[cfg(feature = "tcp-transport")]
+pub mod tls;
+
+#[cfg(feature = "quic-transport")]
+pub mod quic;
+
+// Stub builds have no device to run it on
+#[cfg(all(feature = "rdma-transport", not(feature = "stub-rdma")))]
 pub mod rdma;
 
+#[cfg(feature = "mock")]
+pub mod mock;
+
+/// Pages fetched by `PageTransport::measure_bandwidth`'s default probe
+pub const BANDWIDTH_PROBE_PAGES: usize = 16;
+
 /// Transport-agnostic endpoint information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransportEndpoint {
-    /// TCP endpoint (IP:port)
-    Tcp { addr: String, port: u16 },
+    /// TCP endpoint, serialized as a socket address string
+    /// (`"10.0.0.1:50051"`, `"[::1]:50051"`)
+    #[serde(serialize_with = "serialize_tcp", deserialize_with = "deserialize_tcp")]
+    Tcp { addr: IpAddr, port: u16 },
     /// RDMA endpoint (QP info)
     #[cfg(feature = "rdma-transport")]
     Rdma {
@@ -30,6 +51,53 @@ pub enum TransportEndpoint {
         gid: [u8; 16],
         psn: u32,
     },
+    /// QUIC endpoint, with the node's self-signed certificate (DER), which
+    /// connecting peers trust
+    #[cfg(feature = "quic-transport")]
+    Quic {
+        addr: IpAddr,
+        port: u16,
+        cert: Vec<u8>,
+    },
+}
+
+impl TransportEndpoint {
+    /// TCP endpoint for a socket address
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self::Tcp {
+            addr: addr.ip(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl fmt::Display for TransportEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { addr, port } => write!(f, "tcp://{}", SocketAddr::new(*addr, *port)),
+            #[cfg(feature = "rdma-transport")]
+            Self::Rdma { qpn, lid, .. } => write!(f, "rdma://lid {} qpn {}", lid, qpn),
+            #[cfg(feature = "quic-transport")]
+            Self::Quic { addr, port, .. } => write!(f, "quic://{}", SocketAddr::new(*addr, *port)),
+        }
+    }
+}
+
+fn serialize_tcp<S: Serializer>(
+    addr: &IpAddr,
+    port: &u16,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    // SocketAddr brackets IPv6 addresses, keeping the port unambiguous
+    serializer.collect_str(&SocketAddr::new(*addr, *port))
+}
+
+fn deserialize_tcp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(IpAddr, u16), D::Error> {
+    let s = String::deserialize(deserializer)?;
+    let addr: SocketAddr = s
+        .parse()
+        .map_err(|e| serde::de::Error::custom(format!("invalid TCP endpoint {:?}: {}", s, e)))?;
+    Ok((addr.ip(), addr.port()))
 }
 
 /// Transport performance characteristics
@@ -71,6 +139,69 @@ impl fmt::Display for TransportTier {
     }
 }
 
+/// Transfer counters of a transport
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportStats {
+    /// Bytes not sent because pages went out as deltas
+    pub delta_bytes_saved: u64,
+    /// Page requests this node refused because its directory was older
+    /// than the requester's
+    #[serde(default)]
+    pub stale_epoch_rejections: u64,
+    /// Times sends were throttled because the network was congested
+    #[serde(default)]
+    pub congestion_events: u64,
+    /// Sends are throttled now
+    #[serde(default)]
+    pub throttled: bool,
+    /// Stale rkeys replaced after a remote access error (see `rediscovery`)
+    #[serde(default)]
+    pub rkey_refreshes: u64,
+    /// Reconnections to each peer after its connection broke (see
+    /// `reconnect`)
+    #[serde(default)]
+    pub reconnection_attempts: HashMap<u32, u64>,
+}
+
+/// Transport failures callers can act on (returned inside `anyhow::Error`)
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TransportError {
+    /// The serving node's page directory is older than the requester's, so
+    /// the page may be mid-migration; look the owner up again and retry
+    #[error("stale directory epoch: requested {requested}, serving node at {current}")]
+    StaleEpoch { requested: u64, current: u64 },
+    /// The remote node could not be reached
+    #[error("connection to remote node failed")]
+    ConnectionFailed,
+    /// The peer's credentials were missing or invalid, or it turned ours
+    /// away (see `PeerAuthenticator`)
+    #[error("peer authentication failed")]
+    AuthenticationFailed,
+    /// The remote node no longer accepts the rkey an RDMA operation used
+    /// (`IBV_WC_REM_ACCESS_ERR`), e.g. because it deregistered the region
+    #[error("remote access error: rkey no longer valid")]
+    RemoteAccess,
+    /// An RDMA read failed even after its page was looked up again (see
+    /// `rediscovery`)
+    #[error("RDMA operation failed")]
+    RdmaFailed,
+}
+
+/// Proves this node's identity to peers, and checks theirs, as connections
+/// open
+///
+/// Credentials are opaque to the transport. Once set (see
+/// `PageTransport::set_authenticator`), an inbound connection is served only
+/// after the connecting node presents valid credentials, and an outbound one
+/// is used only after the accepting node answers with its own.
+pub trait PeerAuthenticator: Send + Sync {
+    /// Credentials this node presents to `remote_node_id`
+    fn credentials(&self, remote_node_id: u32) -> Result<Vec<u8>>;
+
+    /// Node that presented `credentials`, if they are valid
+    fn verify(&self, credentials: &[u8]) -> Result<u32>;
+}
+
 /// Page transport abstraction
 ///
 /// Implementations handle the network-specific details of fetching/sending pages.
@@ -86,6 +217,51 @@ pub trait PageTransport: Send + Sync {
     /// Page data (4KB or 2MB)
     fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>>;
 
+    /// Fetch several pages from one remote node
+    ///
+    /// Results are in the order of `gpas`, and one page failing does not fail
+    /// the others. Transports without batching fetch the pages one by one.
+    fn fetch_pages_batch(&self, gpas: &[u64], remote_node_id: u32) -> Vec<Result<Vec<u8>>> {
+        gpas.iter()
+            .map(|&gpa| self.fetch_page(gpa, remote_node_id))
+            .collect()
+    }
+
+    /// Fetch a page, as of page directory `epoch` on this node
+    ///
+    /// Fails with `TransportError::StaleEpoch` if the remote node's directory
+    /// epoch is older. Transports without epoch checks ignore `epoch`.
+    fn fetch_page_at_epoch(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<Vec<u8>> {
+        let _ = epoch;
+        self.fetch_page(gpa, remote_node_id)
+    }
+
+    /// xxHash3-128 of a remote node's current copy of a page, checked
+    /// against `epoch` as `fetch_page_at_epoch` does
+    ///
+    /// Lets a node confirm a copy it already holds without transferring the
+    /// page. Transports that cannot ask for a hash fail.
+    fn fetch_page_hash(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<u128> {
+        let _ = (gpa, remote_node_id, epoch);
+        Err(anyhow::anyhow!(
+            "Page hashes are not supported by this transport"
+        ))
+    }
+
+    /// Serve fetches only at or below this node's directory `epoch`
+    ///
+    /// Until called, every fetch is served.
+    fn set_directory_epoch(&self, epoch: Arc<AtomicU64>) {
+        let _ = epoch;
+    }
+
+    /// Authenticate every connection opened from now on with `authenticator`
+    ///
+    /// Transports that cannot exchange credentials ignore it.
+    fn set_authenticator(&self, authenticator: Arc<dyn PeerAuthenticator>) {
+        let _ = authenticator;
+    }
+
     /// Send a page to a remote node
     ///
     /// # Arguments
@@ -94,6 +270,33 @@ pub trait PageTransport: Send + Sync {
     /// * `remote_node_id` - ID of the destination node
     fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()>;
 
+    /// Page a peer sent to this node for `gpa`, if one has arrived
+    ///
+    /// Transports that keep no received pages return None.
+    fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        let _ = gpa;
+        None
+    }
+
+    /// Send a page as a delta against `base`, the remote node's current copy
+    ///
+    /// Transports without delta support send the full page.
+    fn send_page_delta(
+        &self,
+        gpa: u64,
+        base: &[u8],
+        data: &[u8],
+        remote_node_id: u32,
+    ) -> Result<()> {
+        let _ = base;
+        self.send_page(gpa, data, remote_node_id)
+    }
+
+    /// Transfer counters
+    fn stats(&self) -> TransportStats {
+        TransportStats::default()
+    }
+
     /// Register a memory region for efficient transfers
     ///
     /// # Arguments
@@ -115,6 +318,68 @@ pub trait PageTransport: Send + Sync {
 
     /// Measure actual round-trip latency to a peer
     fn measure_latency(&self, remote_node_id: u32) -> Result<Duration>;
+
+    /// Bytes per second a peer's pages arrive at when fetched one after
+    /// another
+    ///
+    /// Defaults to `probe_bandwidth`.
+    fn measure_bandwidth(&self, remote_node_id: u32) -> Result<u64> {
+        probe_bandwidth(self, remote_node_id)
+    }
+
+    /// Send a TSC probe and return the remote node's TSC when it received it
+    ///
+    /// # Arguments
+    /// * `remote_node_id` - Node to probe
+    /// * `local_tsc` - Local TSC at send time (echoed back for matching)
+    fn probe_tsc(&self, remote_node_id: u32, local_tsc: u64) -> Result<u64> {
+        let _ = (remote_node_id, local_tsc);
+        anyhow::bail!("TSC probes not supported by this transport")
+    }
+
+    /// Stop serving peers, waiting up to `timeout` for requests already
+    /// received to be answered
+    ///
+    /// Transports without a server side have nothing to drain.
+    fn shutdown_gracefully(&self, timeout: Duration) -> Result<()> {
+        let _ = timeout;
+        Ok(())
+    }
+}
+
+/// Time `BANDWIDTH_PROBE_PAGES` fetches of a peer's first page, returning
+/// bytes per second
+///
+/// Round trips count against the bandwidth as they do when pages are
+/// copied one by one.
+pub fn probe_bandwidth<T: PageTransport + ?Sized>(
+    transport: &T,
+    remote_node_id: u32,
+) -> Result<u64> {
+    let start = Instant::now();
+    for _ in 0..BANDWIDTH_PROBE_PAGES {
+        transport.fetch_page(0, remote_node_id)?;
+    }
+    let elapsed = start.elapsed().as_secs_f64().max(f64::MIN_POSITIVE);
+    Ok(((BANDWIDTH_PROBE_PAGES * crate::PAGE_SIZE) as f64 / elapsed) as u64)
+}
+
+/// Read the host time stamp counter
+///
+/// Falls back to monotonic nanoseconds on architectures without RDTSC.
+pub fn read_tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SAFETY: RDTSC is available on every x86-64 CPU
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        use std::sync::OnceLock;
+        use std::time::Instant;
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
 }
 
 /// Memory region handle for zero-copy transfers
@@ -133,9 +398,10 @@ pub trait MemoryRegion: Send + Sync {
 }
 
 /// Auto-detect and create the best available transport
+#[allow(clippy::needless_return)]
 pub fn create_transport(local_node_id: u32) -> Result<Box<dyn PageTransport>> {
     // Try RDMA first if compiled in
-    #[cfg(feature = "rdma-transport")]
+    #[cfg(all(feature = "rdma-transport", not(feature = "stub-rdma")))]
     {
         if let Ok(transport) = rdma::RdmaTransport::new(local_node_id) {
             log::info!("🚀 Using RDMA transport (high-performance mode)");
@@ -144,6 +410,17 @@ pub fn create_transport(local_node_id: u32) -> Result<Box<dyn PageTransport>> {
         log::warn!("RDMA not available, falling back to TCP");
     }
 
+    #[cfg(feature = "quic-transport")]
+    {
+        match quic::QuicTransport::new(local_node_id) {
+            Ok(transport) => {
+                log::info!("📡 Using QUIC transport (stream per request)");
+                return Ok(Box::new(transport));
+            }
+            Err(e) => log::warn!("QUIC not available ({:#}), falling back to TCP", e),
+        }
+    }
+
     // Fall back to TCP (always available)
     #[cfg(feature = "tcp-transport")]
     {
@@ -179,6 +456,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tcp_endpoint_ipv4_round_trip() {
+        let endpoint = TransportEndpoint::tcp("192.168.1.100:50051".parse().unwrap());
+
+        let json = serde_json::to_string(&endpoint).unwrap();
+        assert_eq!(json, r#"{"Tcp":"192.168.1.100:50051"}"#);
+        assert_eq!(
+            serde_json::from_str::<TransportEndpoint>(&json).unwrap(),
+            endpoint
+        );
+
+        let bytes = bincode::serialize(&endpoint).unwrap();
+        assert_eq!(
+            bincode::deserialize::<TransportEndpoint>(&bytes).unwrap(),
+            endpoint
+        );
+    }
+
+    #[test]
+    fn test_tcp_endpoint_ipv6_uses_brackets() {
+        let endpoint = TransportEndpoint::Tcp {
+            addr: "::1".parse().unwrap(),
+            port: 50051,
+        };
+
+        let json = serde_json::to_string(&endpoint).unwrap();
+        assert_eq!(json, r#"{"Tcp":"[::1]:50051"}"#);
+        assert_eq!(
+            serde_json::from_str::<TransportEndpoint>(&json).unwrap(),
+            endpoint
+        );
+        assert_eq!(endpoint.to_string(), "tcp://[::1]:50051");
+
+        let bytes = bincode::serialize(&endpoint).unwrap();
+        assert_eq!(
+            bincode::deserialize::<TransportEndpoint>(&bytes).unwrap(),
+            endpoint
+        );
+    }
+
+    #[test]
+    fn test_tcp_endpoint_rejects_ambiguous_ipv6() {
+        assert!(serde_json::from_str::<TransportEndpoint>(r#"{"Tcp":"::1:50051"}"#).is_err());
+        assert!(serde_json::from_str::<TransportEndpoint>(r#"{"Tcp":"10.0.0.1"}"#).is_err());
+    }
+
     #[test]
     fn test_create_transport() {
         // Should create TCP transport by default
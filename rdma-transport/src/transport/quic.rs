@@ -0,0 +1,622 @@
+//! QUIC page transport: one stream per request
+//!
+//! Page requests sharing a TCP connection are delivered in order, so a lost
+//! segment holds up every fetch behind it. QUIC multiplexes independent
+//! streams over one connection per peer and a loss only stalls the stream
+//! it hit. Each request here opens its own bidirectional stream carrying one
+//! length-prefixed `Message` each way, framed and served as over TCP.
+//!
+//! Every transport generates a self-signed certificate at startup and
+//! advertises it in its `TransportEndpoint::Quic`; peers trust exactly that
+//! certificate when connecting.
+
+use super::tcp::{
+    Message, ServerState, TcpMemoryRegion, TcpTransport, TcpTransportConfig, MAX_BATCH_PAGES,
+};
+use super::{
+    MemoryRegion, PageTransport, PeerAuthenticator, TransportEndpoint, TransportError,
+    TransportTier,
+};
+use crate::delta::{base_hash, DeltaEncoder};
+use crate::trace::TraceContext;
+use crate::TransportStats;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use parking_lot::RwLock;
+use quinn::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use quinn::rustls::RootCertStore;
+use quinn::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, ServerConfig};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// UDP ports, so they do not collide with the TCP transport's
+const PORT_RANGE_START: u16 = 50051;
+const PORT_RANGE_END: u16 = 50100;
+
+/// Name in every node's certificate, checked by connecting peers
+const SERVER_NAME: &str = "ssi-hv-node";
+
+/// Streams a peer may have open to this node at once
+const MAX_CONCURRENT_STREAMS: u32 = 1024;
+
+/// QUIC transport implementation
+pub struct QuicTransport {
+    local_node_id: u32,
+    local_addr: SocketAddr,
+    /// This node's certificate, advertised in `local_endpoint`
+    cert: CertificateDer<'static>,
+    endpoint: Endpoint,
+    /// One connection per peer; requests open streams on it
+    connections: RwLock<HashMap<u32, Connection>>,
+    runtime: Arc<Runtime>,
+    measured_tier: RwLock<Option<TransportTier>>,
+    server: ServerState,
+    /// Request streams being served
+    open_streams: Arc<AtomicU32>,
+    delta_bytes_saved: AtomicU64,
+}
+
+impl QuicTransport {
+    pub fn new(local_node_id: u32) -> Result<Self> {
+        let runtime = Arc::new(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(4)
+                .enable_all()
+                .build()
+                .context("Failed to create Tokio runtime")?,
+        );
+
+        let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+            .context("Failed to generate QUIC certificate")?;
+        let cert = certified.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+        let mut server_config = ServerConfig::with_single_cert(vec![cert.clone()], key.into())
+            .context("Failed to configure QUIC server")?;
+        Arc::get_mut(&mut server_config.transport)
+            .ok_or_else(|| anyhow!("QUIC transport config is shared"))?
+            .max_concurrent_bidi_streams(MAX_CONCURRENT_STREAMS.into());
+
+        // Endpoints register their socket with the runtime they are made in
+        let _runtime = runtime.enter();
+        let endpoint = (PORT_RANGE_START..=PORT_RANGE_END)
+            .find_map(|port| {
+                match Endpoint::server(server_config.clone(), ([0, 0, 0, 0], port).into()) {
+                    Ok(endpoint) => Some(Ok(endpoint)),
+                    Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => None,
+                    Err(e) => Some(Err(anyhow!("Failed to bind QUIC endpoint: {}", e))),
+                }
+            })
+            .unwrap_or_else(|| {
+                Err(anyhow!(
+                    "No available UDP ports in range {}-{}",
+                    PORT_RANGE_START,
+                    PORT_RANGE_END
+                ))
+            })?;
+        let local_addr = endpoint
+            .local_addr()
+            .context("Failed to get local address")?;
+
+        info!(
+            "QUIC transport initialized on {} (node_id={})",
+            local_addr, local_node_id
+        );
+
+        let server = ServerState::new(TcpTransportConfig::default());
+        let open_streams = Arc::new(AtomicU32::new(0));
+        runtime.spawn(Self::accept_task(
+            endpoint.clone(),
+            server.clone(),
+            Arc::clone(&open_streams),
+        ));
+
+        Ok(Self {
+            local_node_id,
+            local_addr,
+            cert,
+            endpoint,
+            connections: RwLock::new(HashMap::new()),
+            runtime: Arc::clone(&runtime),
+            measured_tier: RwLock::new(None),
+            server,
+            open_streams,
+            delta_bytes_saved: AtomicU64::new(0),
+        })
+    }
+
+    /// Accept connections until the endpoint closes
+    async fn accept_task(endpoint: Endpoint, server: ServerState, open_streams: Arc<AtomicU32>) {
+        while let Some(incoming) = endpoint.accept().await {
+            let server = server.clone();
+            let open_streams = Arc::clone(&open_streams);
+            tokio::spawn(async move {
+                let peer_addr = incoming.remote_address();
+                match incoming.await {
+                    Ok(connection) => {
+                        debug!("Accepted QUIC connection from {}", peer_addr);
+                        Self::handle_connection(connection, server, open_streams).await;
+                    }
+                    Err(e) => warn!("QUIC handshake with {} failed: {}", peer_addr, e),
+                }
+            });
+        }
+        debug!("QUIC endpoint closed");
+    }
+
+    /// Serve every stream the peer opens, each in its own task
+    ///
+    /// With an authenticator set, the first stream must carry the peer's
+    /// `Hello`; otherwise the connection is closed.
+    async fn handle_connection(
+        connection: Connection,
+        server: ServerState,
+        open_streams: Arc<AtomicU32>,
+    ) {
+        if let Some(authenticator) = server.authenticator() {
+            if let Err(e) = Self::answer_hello(&connection, authenticator.as_ref()).await {
+                warn!(
+                    "Closing QUIC connection from {}: {:#}",
+                    connection.remote_address(),
+                    e
+                );
+                connection.close(0u32.into(), b"authentication failed");
+                return;
+            }
+        }
+        loop {
+            let (send, recv) = match connection.accept_bi().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!(
+                        "QUIC connection from {} ended: {}",
+                        connection.remote_address(),
+                        e
+                    );
+                    return;
+                }
+            };
+            let server = server.clone();
+            let open_streams = Arc::clone(&open_streams);
+            open_streams.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                if let Err(e) = Self::handle_stream(send, recv, &server).await {
+                    warn!("QUIC stream error: {:#}", e);
+                }
+                open_streams.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    /// Answer the one request on a stream
+    async fn handle_stream(
+        mut send: SendStream,
+        mut recv: RecvStream,
+        server: &ServerState,
+    ) -> Result<()> {
+        let Some(msg) = TcpTransport::read_message(&mut recv).await? else {
+            return Ok(());
+        };
+        if let Some(response) = TcpTransport::handle_message(msg, server) {
+            TcpTransport::write_message(&mut send, &response).await?;
+        }
+        send.finish()?;
+        // Return once the peer has the response, so shutdown can wait for it
+        let _ = send.stopped().await;
+        Ok(())
+    }
+
+    /// Check the peer's `Hello` on the connection's first stream and answer
+    /// with ours
+    async fn answer_hello(
+        connection: &Connection,
+        authenticator: &dyn PeerAuthenticator,
+    ) -> Result<()> {
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let hello = TcpTransport::read_message(&mut recv).await?;
+        let answer = TcpTransport::answer_hello(authenticator, hello);
+        let response = match &answer {
+            Ok(response) => response,
+            Err(_) => &Message::Error {
+                message: "Authentication failed".to_string(),
+            },
+        };
+        TcpTransport::write_message(&mut send, response).await?;
+        send.finish()?;
+        let _ = send.stopped().await;
+        answer.map(|_| ())
+    }
+
+    /// Send `msg` to `remote_node_id` on a new stream and wait for the reply
+    fn request(&self, remote_node_id: u32, msg: &Message) -> Result<Message> {
+        let connection = self
+            .connections
+            .read()
+            .get(&remote_node_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Node {} not connected", remote_node_id))?;
+
+        self.runtime.block_on(Self::request_on(&connection, msg))
+    }
+
+    /// Send `msg` on a new stream of `connection` and wait for the reply
+    async fn request_on(connection: &Connection, msg: &Message) -> Result<Message> {
+        let (mut send, mut recv) = connection
+            .open_bi()
+            .await
+            .context(TransportError::ConnectionFailed)?;
+        TcpTransport::write_message(&mut send, msg).await?;
+        send.finish()?;
+        TcpTransport::read_message(&mut recv)
+            .await?
+            .ok_or_else(|| anyhow!("Stream closed before response"))
+    }
+}
+
+impl PageTransport for QuicTransport {
+    fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>> {
+        self.fetch_page_at_epoch(gpa, remote_node_id, 0)
+    }
+
+    fn fetch_page_at_epoch(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<Vec<u8>> {
+        let msg = Message::FetchPage {
+            gpa,
+            epoch,
+            trace_context: TraceContext::current(),
+        };
+        let response = self.request(remote_node_id, &msg)?;
+        TcpTransport::page_from_response(response, epoch)
+    }
+
+    fn fetch_page_hash(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<u128> {
+        let response = self.request(remote_node_id, &Message::FetchPageHash { gpa, epoch })?;
+        TcpTransport::hash_from_response(response, epoch)
+    }
+
+    fn fetch_pages_batch(&self, gpas: &[u64], remote_node_id: u32) -> Vec<Result<Vec<u8>>> {
+        gpas.chunks(MAX_BATCH_PAGES)
+            .flat_map(|chunk| {
+                let msg = Message::FetchPageBatch {
+                    gpas: chunk.to_vec(),
+                };
+                TcpTransport::pages_from_batch_response(self.request(remote_node_id, &msg), chunk)
+            })
+            .collect()
+    }
+
+    fn set_directory_epoch(&self, epoch: Arc<AtomicU64>) {
+        self.server.set_directory_epoch(epoch);
+    }
+
+    fn set_authenticator(&self, authenticator: Arc<dyn PeerAuthenticator>) {
+        self.server.set_authenticator(authenticator);
+    }
+
+    fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()> {
+        let msg = Message::SendPage {
+            gpa,
+            data: data.to_vec(),
+        };
+        match self.request(remote_node_id, &msg)? {
+            Message::Ack => Ok(()),
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response type")),
+        }
+    }
+
+    fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        self.server.received_page(gpa)
+    }
+
+    fn send_page_delta(
+        &self,
+        gpa: u64,
+        base: &[u8],
+        data: &[u8],
+        remote_node_id: u32,
+    ) -> Result<()> {
+        let delta = DeltaEncoder::encode(base, data);
+        if delta.len() >= data.len() {
+            return self.send_page(gpa, data, remote_node_id);
+        }
+
+        let saved = (data.len() - delta.len()) as u64;
+        let msg = Message::DeltaPage {
+            gpa,
+            delta,
+            base_hash: base_hash(base),
+        };
+        match self.request(remote_node_id, &msg)? {
+            Message::Ack => {
+                self.delta_bytes_saved.fetch_add(saved, Ordering::Relaxed);
+                Ok(())
+            }
+            Message::NeedFullPage { .. } => self.send_page(gpa, data, remote_node_id),
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response type")),
+        }
+    }
+
+    fn stats(&self) -> TransportStats {
+        TransportStats {
+            delta_bytes_saved: self.delta_bytes_saved.load(Ordering::Relaxed),
+            stale_epoch_rejections: self.server.stale_epoch_rejections(),
+            ..Default::default()
+        }
+    }
+
+    /// Stop serving peers, answering the requests already received
+    ///
+    /// New connections are refused at once. The endpoint closes when no
+    /// request stream is left or `timeout` expires, whichever is first; in
+    /// the latter case an error is returned.
+    fn shutdown_gracefully(&self, timeout: Duration) -> Result<()> {
+        self.endpoint.set_server_config(None);
+        let deadline = Instant::now() + timeout;
+        while self.open_streams.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let open = self.open_streams.load(Ordering::SeqCst);
+        self.endpoint.close(0u32.into(), b"shutdown");
+        if open > 0 {
+            return Err(anyhow!(
+                "{} QUIC requests still unanswered after {:?}",
+                open,
+                timeout
+            ));
+        }
+        info!(
+            "QUIC transport shut down gracefully (node_id={})",
+            self.local_node_id
+        );
+        Ok(())
+    }
+
+    fn register_memory(&self, addr: *mut u8, length: usize) -> Result<Box<dyn MemoryRegion>> {
+        // Like TCP, nothing to register
+        Ok(Box::new(TcpMemoryRegion { addr, length }))
+    }
+
+    fn local_endpoint(&self) -> TransportEndpoint {
+        let addr = if self.local_addr.ip().is_unspecified() {
+            local_ip_address::local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        } else {
+            self.local_addr.ip()
+        };
+        TransportEndpoint::Quic {
+            addr,
+            port: self.local_addr.port(),
+            cert: self.cert.to_vec(),
+        }
+    }
+
+    fn connect(&mut self, remote_node_id: u32, remote_endpoint: TransportEndpoint) -> Result<()> {
+        let TransportEndpoint::Quic { addr, port, cert } = remote_endpoint else {
+            return Err(anyhow!(
+                "Cannot connect to {} with QUIC transport",
+                remote_endpoint
+            ));
+        };
+        let peer_addr = SocketAddr::new(addr, port);
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(cert))
+            .context("Invalid peer certificate")?;
+        let client_config = ClientConfig::with_root_certificates(Arc::new(roots))
+            .context("Failed to configure QUIC client")?;
+        let connection = self.runtime.block_on(async {
+            self.endpoint
+                .connect_with(client_config, peer_addr, SERVER_NAME)?
+                .await
+                .context(TransportError::ConnectionFailed)
+        })?;
+        if let Some(authenticator) = self.server.authenticator() {
+            let authenticated = TcpTransport::hello(authenticator.as_ref(), remote_node_id)
+                .and_then(|hello| self.runtime.block_on(Self::request_on(&connection, &hello)))
+                .and_then(|response| {
+                    TcpTransport::check_hello(authenticator.as_ref(), remote_node_id, response)
+                });
+            if let Err(e) = authenticated {
+                connection.close(0u32.into(), b"authentication failed");
+                return Err(e.context(TransportError::AuthenticationFailed));
+            }
+        }
+        self.connections.write().insert(remote_node_id, connection);
+        info!(
+            "Connected to node {} at {} (QUIC)",
+            remote_node_id, peer_addr
+        );
+
+        if let Ok(latency) = self.measure_latency(remote_node_id) {
+            let tier = TcpTransport::detect_tier(latency);
+            *self.measured_tier.write() = Some(tier);
+            info!(
+                "Network performance: {} (~{}µs latency)",
+                tier,
+                latency.as_micros()
+            );
+        }
+        Ok(())
+    }
+
+    fn performance_tier(&self) -> TransportTier {
+        self.measured_tier.read().unwrap_or(TransportTier::Standard)
+    }
+
+    fn measure_latency(&self, remote_node_id: u32) -> Result<Duration> {
+        let start = Instant::now();
+        match self.request(remote_node_id, &Message::Ping { timestamp: 0 })? {
+            Message::Pong { .. } => Ok(start.elapsed()),
+            _ => Err(anyhow!("Unexpected response to ping")),
+        }
+    }
+
+    fn probe_tsc(&self, remote_node_id: u32, local_tsc: u64) -> Result<u64> {
+        let msg = Message::TscProbe {
+            sender_tsc: local_tsc,
+        };
+        match self.request(remote_node_id, &msg)? {
+            Message::TscEcho {
+                sender_tsc,
+                receiver_tsc,
+            } if sender_tsc == local_tsc => Ok(receiver_tsc),
+            Message::TscEcho { .. } => Err(anyhow!("TSC echo does not match probe")),
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response to TSC probe")),
+        }
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        debug!(
+            "Shutting down QUIC transport (node_id={})",
+            self.local_node_id
+        );
+        self.endpoint.close(0u32.into(), b"dropped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PAGE_SIZE;
+    use std::thread;
+
+    /// `endpoint` with the loopback address, for connecting in tests
+    fn loopback(endpoint: TransportEndpoint) -> TransportEndpoint {
+        match endpoint {
+            TransportEndpoint::Quic { port, cert, .. } => TransportEndpoint::Quic {
+                addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port,
+                cert,
+            },
+            TransportEndpoint::Tcp { port, .. } => {
+                TransportEndpoint::tcp((Ipv4Addr::LOCALHOST, port).into())
+            }
+            #[cfg(feature = "rdma-transport")]
+            other => other,
+        }
+    }
+
+    fn connected_pair() -> (QuicTransport, QuicTransport) {
+        let mut sender = QuicTransport::new(1).unwrap();
+        let receiver = QuicTransport::new(2).unwrap();
+        sender
+            .connect(2, loopback(receiver.local_endpoint()))
+            .unwrap();
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_pages_round_trip_over_quic() {
+        let (sender, receiver) = connected_pair();
+        sender.send_page(0x3000, &vec![9u8; PAGE_SIZE], 2).unwrap();
+        assert_eq!(receiver.received_page(0x3000), Some(vec![9u8; PAGE_SIZE]));
+        assert_eq!(sender.fetch_page(0x3000, 2).unwrap(), vec![9u8; PAGE_SIZE]);
+        // Pages never sent are zeros
+        assert_eq!(sender.fetch_page(0x4000, 2).unwrap(), vec![0u8; PAGE_SIZE]);
+        assert!(sender.measure_latency(2).is_ok());
+    }
+
+    #[test]
+    fn test_stale_epoch_rejected_over_quic() {
+        let (sender, receiver) = connected_pair();
+        receiver.set_directory_epoch(Arc::new(AtomicU64::new(3)));
+        let err = sender.fetch_page_at_epoch(0x1000, 2, 5).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransportError>(),
+            Some(&TransportError::StaleEpoch {
+                requested: 5,
+                current: 3
+            })
+        );
+        assert_eq!(receiver.stats().stale_epoch_rejections, 1);
+    }
+
+    #[test]
+    fn test_unknown_certificate_refused() {
+        let mut sender = QuicTransport::new(1).unwrap();
+        let receiver = QuicTransport::new(2).unwrap();
+        let impostor = QuicTransport::new(3).unwrap();
+        let TransportEndpoint::Quic { port, .. } = receiver.local_endpoint() else {
+            unreachable!()
+        };
+        let TransportEndpoint::Quic { cert, .. } = impostor.local_endpoint() else {
+            unreachable!()
+        };
+        let endpoint = TransportEndpoint::Quic {
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+            cert,
+        };
+        assert!(sender.connect(2, endpoint).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_round_trip() {
+        let transport = QuicTransport::new(1).unwrap();
+        let endpoint = transport.local_endpoint();
+        let bytes = bincode::serialize(&endpoint).unwrap();
+        assert_eq!(
+            bincode::deserialize::<TransportEndpoint>(&bytes).unwrap(),
+            endpoint
+        );
+        assert!(endpoint.to_string().starts_with("quic://"));
+    }
+
+    /// p99 latency of `fetch` run by `THREADS` threads at once
+    fn concurrent_fetch_p99(fetch: impl Fn(u64) + Sync) -> Duration {
+        const THREADS: u64 = 16;
+        const FETCHES: u64 = 200;
+
+        let mut latencies: Vec<Duration> = thread::scope(|scope| {
+            let fetch = &fetch;
+            let threads: Vec<_> = (0..THREADS)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        (0..FETCHES)
+                            .map(|i| {
+                                let start = Instant::now();
+                                fetch((thread * FETCHES + i) * PAGE_SIZE as u64);
+                                start.elapsed()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect()
+        });
+        latencies.sort_unstable();
+        latencies[latencies.len() * 99 / 100]
+    }
+
+    #[test]
+    #[ignore] // Benchmark: run with --ignored --nocapture
+    fn bench_quic_vs_tcp_concurrent_fetch_p99() {
+        let mut tcp = TcpTransport::new(1).unwrap();
+        let tcp_server = TcpTransport::new(2).unwrap();
+        tcp.connect(2, loopback(tcp_server.local_endpoint()))
+            .unwrap();
+        let tcp_p99 = concurrent_fetch_p99(|gpa| {
+            tcp.fetch_page(gpa, 2).unwrap();
+        });
+
+        let (quic, _quic_server) = connected_pair();
+        let quic_p99 = concurrent_fetch_p99(|gpa| {
+            quic.fetch_page(gpa, 2).unwrap();
+        });
+
+        println!(
+            "16 concurrent fetches: TCP p99 {:?}, QUIC p99 {:?}",
+            tcp_p99, quic_p99
+        );
+    }
+}
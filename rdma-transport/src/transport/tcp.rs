@@ -8,39 +8,375 @@
 //! - 10 Gbps Ethernet: 200-500µs per page (Standard)
 //! - Tuned 10G: 100-300µs per page (Good enough for many workloads)
 
-use super::{MemoryRegion, PageTransport, TransportEndpoint, TransportTier};
+use super::tls::TlsConfig;
+use super::{
+    read_tsc, MemoryRegion, PageTransport, PeerAuthenticator, TransportEndpoint, TransportError,
+    TransportStats, TransportTier,
+};
+use crate::delta::{base_hash, DeltaEncoder};
+use crate::monitor::{ProcNetStats, TcpCongestionMonitor, CONGESTION_THRESHOLD};
+use crate::trace::TraceContext;
 use anyhow::{anyhow, Context, Result};
 use bincode::{deserialize, serialize};
 use log::{debug, info, warn};
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::trace::{SpanKind, Tracer};
+use opentelemetry::KeyValue;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use xxhash_rust::xxh3::xxh3_128;
 
-const DEFAULT_PORT: u16 = 50051;
 const PORT_RANGE_START: u16 = 50051;
 const PORT_RANGE_END: u16 = 50100;
 const PAGE_SIZE: usize = 4096;
-const PING_SIZE: usize = 8;
+const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Most pages asked for in one `FetchPageBatch`, keeping the response well
+/// under `MAX_MESSAGE_SIZE`
+pub(super) const MAX_BATCH_PAGES: usize = 256;
+
+/// Buffered responses are flushed once they reach this size, even mid-batch
+const COALESCE_FLUSH_BYTES: usize = 64 * 1024;
+
+/// How often the kernel's retransmit counters are checked for congestion
+pub const CONGESTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pages received from peers, by GPA (served back on fetch)
+type ReceivedPages = Arc<RwLock<HashMap<u64, Vec<u8>>>>;
+
+/// TCP transport tuning
+#[derive(Debug, Clone)]
+pub struct TcpTransportConfig {
+    /// How long to hold a page response before flushing, so responses to
+    /// requests arriving in the meantime go out in the same write
+    pub nagle_buffer_us: u64,
+    /// Flush every response immediately
+    pub disable_nagle_coalescing: bool,
+    /// Least time between the starts of consecutive page sends; doubled in
+    /// throttle mode
+    pub send_backoff_us: u64,
+    /// Throttle sends while the host's TCP retransmit rate shows congestion,
+    /// checking this often (see `monitor`)
+    pub congestion_check: Option<Duration>,
+    /// Idle connections kept open to each peer for later requests
+    pub pool_size: usize,
+    /// Encrypt connections to and from peers (see `tls`)
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for TcpTransportConfig {
+    fn default() -> Self {
+        Self {
+            nagle_buffer_us: 50,
+            disable_nagle_coalescing: false,
+            send_backoff_us: 25,
+            congestion_check: Some(CONGESTION_CHECK_INTERVAL),
+            pool_size: 4,
+            tls: None,
+        }
+    }
+}
+
+/// State shared by the server side's listener and connection handlers
+///
+/// The QUIC transport serves requests with the same state.
+#[derive(Clone)]
+pub(super) struct ServerState {
+    config: TcpTransportConfig,
+    /// Socket writes issued by the server side (coalescing observability)
+    response_writes: Arc<AtomicU64>,
+    pages: ReceivedPages,
+    /// Cleared on shutdown; connections accepted afterwards are closed
+    accepting: Arc<AtomicBool>,
+    /// `FetchPage` requests received whose `PageData` is not yet sent
+    in_flight_count: Arc<AtomicU32>,
+    /// Epoch of the local page directory, once the pager has shared it
+    directory_epoch: Arc<RwLock<Option<Arc<AtomicU64>>>>,
+    /// `FetchPage` requests answered with `StaleEpoch`
+    stale_epoch_rejections: Arc<AtomicU64>,
+    /// Connections accepted from peers
+    connections_accepted: Arc<AtomicU64>,
+    /// Checks connecting peers, once set
+    authenticator: Arc<RwLock<Option<Arc<dyn PeerAuthenticator>>>>,
+}
+
+impl ServerState {
+    pub(super) fn new(config: TcpTransportConfig) -> Self {
+        Self {
+            config,
+            response_writes: Arc::new(AtomicU64::new(0)),
+            pages: ReceivedPages::default(),
+            accepting: Arc::new(AtomicBool::new(true)),
+            in_flight_count: Arc::new(AtomicU32::new(0)),
+            directory_epoch: Arc::new(RwLock::new(None)),
+            stale_epoch_rejections: Arc::new(AtomicU64::new(0)),
+            connections_accepted: Arc::new(AtomicU64::new(0)),
+            authenticator: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub(super) fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        self.pages.read().get(&gpa).cloned()
+    }
+
+    /// Page served for `gpa`: for now, the received page or zeros
+    fn page(&self, gpa: u64) -> Vec<u8> {
+        self.received_page(gpa)
+            .unwrap_or_else(|| vec![0u8; PAGE_SIZE])
+    }
+
+    pub(super) fn set_directory_epoch(&self, epoch: Arc<AtomicU64>) {
+        *self.directory_epoch.write() = Some(epoch);
+    }
+
+    pub(super) fn set_authenticator(&self, authenticator: Arc<dyn PeerAuthenticator>) {
+        *self.authenticator.write() = Some(authenticator);
+    }
+
+    pub(super) fn authenticator(&self) -> Option<Arc<dyn PeerAuthenticator>> {
+        self.authenticator.read().clone()
+    }
+
+    pub(super) fn stale_epoch_rejections(&self) -> u64 {
+        self.stale_epoch_rejections.load(Ordering::Relaxed)
+    }
+
+    /// Why a fetch at `epoch` must be refused, if it must
+    ///
+    /// A requester whose directory is newer than ours may know of a
+    /// migration we have not applied yet, so our copy could be stale.
+    fn check_epoch(&self, epoch: u64) -> Option<u64> {
+        let current = self.directory_epoch.read().as_ref()?.load(Ordering::SeqCst);
+        (epoch > current).then_some(current)
+    }
+}
 
 /// TCP transport implementation
 pub struct TcpTransport {
     local_node_id: u32,
     local_addr: SocketAddr,
-    peers: Arc<RwLock<HashMap<u32, SocketAddr>>>,
+    peers: Arc<RwLock<HashMap<u32, Arc<ConnectionPool>>>>,
     runtime: Arc<Runtime>,
     measured_tier: Arc<RwLock<Option<TransportTier>>>,
+    server: ServerState,
+    delta_bytes_saved: AtomicU64,
+    throttle: Arc<SendThrottle>,
+    /// Tells the listener and connection handlers to stop
+    stop: watch::Sender<bool>,
+    /// Listener task; yields the connection handlers once it stops
+    listener: parking_lot::Mutex<Option<JoinHandle<JoinSet<()>>>>,
+}
+
+/// A byte stream to a peer: a plain TCP connection or TLS over one
+trait PeerStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> PeerStream for S {}
+
+/// Open connections to one peer, reused across requests
+///
+/// A request takes an idle connection if there is one and opens a new one
+/// otherwise. The connection goes back to the pool afterwards, unless the
+/// request failed or `max_idle` connections are already idle.
+struct ConnectionPool {
+    addr: SocketAddr,
+    max_idle: usize,
+    idle: parking_lot::Mutex<VecDeque<Box<dyn PeerStream>>>,
+    /// Connections carrying a request
+    active: AtomicUsize,
+    /// Set if connections run over TLS
+    tls: Option<TlsConnector>,
+    /// Node the pool connects to
+    node_id: u32,
+    /// Set if each new connection opens with a `Hello` exchange
+    authenticator: Option<Arc<dyn PeerAuthenticator>>,
+}
+
+impl ConnectionPool {
+    fn new(
+        addr: SocketAddr,
+        max_idle: usize,
+        tls: Option<TlsConnector>,
+        node_id: u32,
+        authenticator: Option<Arc<dyn PeerAuthenticator>>,
+    ) -> Self {
+        Self {
+            addr,
+            max_idle,
+            idle: parking_lot::Mutex::new(VecDeque::new()),
+            active: AtomicUsize::new(0),
+            tls,
+            node_id,
+            authenticator,
+        }
+    }
+
+    /// `(active, idle)` connection counts
+    fn stats(&self) -> (usize, usize) {
+        (self.active.load(Ordering::SeqCst), self.idle.lock().len())
+    }
+
+    async fn connect(&self) -> Result<Box<dyn PeerStream>> {
+        let socket = TcpStream::connect(self.addr)
+            .await
+            .context(TransportError::ConnectionFailed)?;
+        // Set TCP_NODELAY for lower latency
+        socket.set_nodelay(true)?;
+
+        let mut stream: Box<dyn PeerStream> = match &self.tls {
+            Some(connector) => {
+                let name = ServerName::IpAddress(self.addr.ip().into());
+                let stream = connector
+                    .connect(name, socket)
+                    .await
+                    .with_context(|| format!("TLS handshake with {} failed", self.addr))?;
+                Box::new(stream)
+            }
+            None => Box::new(socket),
+        };
+
+        if let Some(authenticator) = &self.authenticator {
+            let hello = TcpTransport::hello(authenticator.as_ref(), self.node_id)?;
+            let response = TcpTransport::exchange(&mut stream, &hello).await?;
+            TcpTransport::check_hello(authenticator.as_ref(), self.node_id, response)
+                .context(TransportError::AuthenticationFailed)?;
+        }
+        Ok(stream)
+    }
+
+    /// Send a message and wait for response
+    ///
+    /// If an idle connection fails (the peer may have closed it while it sat
+    /// in the pool), it is dropped and the request retried once on a new one.
+    async fn send_and_receive(&self, msg: &Message) -> Result<Message> {
+        // The most recently used connection is the least likely to be closed
+        let idle = self.idle.lock().pop_back();
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let result = match idle {
+            Some(mut socket) => match TcpTransport::exchange(&mut socket, msg).await {
+                Ok(response) => Ok((socket, response)),
+                Err(e) => {
+                    debug!("Idle connection to {} failed: {:#}", self.addr, e);
+                    self.exchange_on_new(msg).await
+                }
+            },
+            None => self.exchange_on_new(msg).await,
+        };
+        self.active.fetch_sub(1, Ordering::SeqCst);
+
+        let (socket, response) = result?;
+        let mut idle = self.idle.lock();
+        if idle.len() < self.max_idle {
+            idle.push_back(socket);
+        }
+        Ok(response)
+    }
+
+    async fn exchange_on_new(&self, msg: &Message) -> Result<(Box<dyn PeerStream>, Message)> {
+        let mut socket = self.connect().await?;
+        let response = TcpTransport::exchange(&mut socket, msg).await?;
+        Ok((socket, response))
+    }
+}
+
+/// Paces page sends, more slowly while the network is congested
+struct SendThrottle {
+    backoff: Duration,
+    throttled: AtomicBool,
+    /// Times throttle mode was entered
+    congestion_events: AtomicU64,
+    /// Earliest start of the next send
+    next_send: parking_lot::Mutex<Instant>,
+}
+
+impl SendThrottle {
+    fn new(backoff: Duration) -> Self {
+        Self {
+            backoff,
+            throttled: AtomicBool::new(false),
+            congestion_events: AtomicU64::new(0),
+            next_send: parking_lot::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn set(&self, throttled: bool) {
+        if self.throttled.swap(throttled, Ordering::Relaxed) != throttled {
+            if throttled {
+                self.congestion_events.fetch_add(1, Ordering::Relaxed);
+                warn!("Network congested; doubling the backoff between page sends");
+            } else {
+                info!("Network congestion cleared; page sends back to full rate");
+            }
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        match self.throttled.load(Ordering::Relaxed) {
+            true => self.backoff * 2,
+            false => self.backoff,
+        }
+    }
+
+    /// Wait for this send's turn
+    fn pace(&self) {
+        let start = {
+            let mut next_send = self.next_send.lock();
+            let start = (*next_send).max(Instant::now());
+            *next_send = start + self.backoff();
+            start
+        };
+        let wait = start.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Page requests read on one connection whose responses are not yet sent
+///
+/// Whatever is still counted when the connection ends (e.g. on a write
+/// error) is released on drop, so the transport's in-flight count cannot
+/// leak.
+struct UnsentPages {
+    in_flight: Arc<AtomicU32>,
+    count: u32,
+}
+
+impl UnsentPages {
+    fn received(&mut self) {
+        self.count += 1;
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn sent(&mut self) {
+        self.in_flight.fetch_sub(self.count, Ordering::SeqCst);
+        self.count = 0;
+    }
+}
+
+impl Drop for UnsentPages {
+    fn drop(&mut self) {
+        self.sent();
+    }
 }
 
 /// TCP memory region (just tracks address, no special registration)
-struct TcpMemoryRegion {
-    addr: *mut u8,
-    length: usize,
+pub(super) struct TcpMemoryRegion {
+    pub(super) addr: *mut u8,
+    pub(super) length: usize,
 }
 
 unsafe impl Send for TcpMemoryRegion {}
@@ -66,11 +402,20 @@ impl MemoryRegion for TcpMemoryRegion {
 
 /// Wire protocol messages
 #[derive(Debug, Serialize, Deserialize)]
-enum Message {
-    /// Fetch a page
-    FetchPage { gpa: u64 },
+pub(super) enum Message {
+    /// Fetch a page; `epoch` is the requester's page directory epoch, and
+    /// `trace_context` the span the fetch was made in, if any
+    FetchPage {
+        gpa: u64,
+        epoch: u64,
+        trace_context: Option<TraceContext>,
+    },
     /// Page data response
     PageData { gpa: u64, data: Vec<u8> },
+    /// Fetch several pages in one exchange
+    FetchPageBatch { gpas: Vec<u64> },
+    /// Pages answering a `FetchPageBatch`
+    PageDataBatch { pages: Vec<(u64, Vec<u8>)> },
     /// Send a page (for migration)
     SendPage { gpa: u64, data: Vec<u8> },
     /// Acknowledgment
@@ -79,13 +424,51 @@ enum Message {
     Ping { timestamp: u64 },
     /// Pong response
     Pong { timestamp: u64 },
+    /// TSC probe carrying the sender's TSC
+    TscProbe { sender_tsc: u64 },
+    /// Probe echo with the receiver's TSC at time of receipt
+    TscEcho { sender_tsc: u64, receiver_tsc: u64 },
     /// Error response
     Error { message: String },
+    /// Page sent as an XOR delta against the receiver's copy
+    DeltaPage {
+        gpa: u64,
+        delta: Vec<u8>,
+        base_hash: u64,
+    },
+    /// Delta rejected: the receiver's copy differs from the base
+    NeedFullPage { gpa: u64 },
+    /// Fetch rejected: the server's directory is at `current`, older than
+    /// the requester's
+    StaleEpoch { gpa: u64, current: u64 },
+    /// Ask for the xxHash3-128 of a page rather than its data; `epoch` as
+    /// for `FetchPage`
+    FetchPageHash { gpa: u64, epoch: u64 },
+    /// Hash answering a `FetchPageHash`
+    PageHash { gpa: u64, hash: u128 },
+    /// First message each way on an authenticated connection (see
+    /// `PeerAuthenticator`)
+    Hello { credentials: Vec<u8> },
+}
+
+/// Span for serving `gpa`, a child of the requester's span `parent`
+fn serve_page_span(gpa: u64, parent: &TraceContext) -> BoxedSpan {
+    let tracer = global::tracer("rdma-transport");
+    tracer
+        .span_builder("serve_page")
+        .with_kind(SpanKind::Server)
+        .with_attributes([KeyValue::new("gpa", gpa as i64)])
+        .start_with_context(&tracer, &parent.to_context())
 }
 
 impl TcpTransport {
-    /// Create a new TCP transport
+    /// Create a new TCP transport with default tuning
     pub fn new(local_node_id: u32) -> Result<Self> {
+        Self::with_config(local_node_id, TcpTransportConfig::default())
+    }
+
+    /// Create a new TCP transport
+    pub fn with_config(local_node_id: u32, config: TcpTransportConfig) -> Result<Self> {
         let runtime = Arc::new(
             tokio::runtime::Builder::new_multi_thread()
                 .worker_threads(4)
@@ -95,15 +478,15 @@ impl TcpTransport {
         );
 
         // Try to bind to a port in the range (handle multiple instances)
-        let local_addr = runtime.block_on(async {
+        let (listener, local_addr) = runtime.block_on(async {
             for port in PORT_RANGE_START..=PORT_RANGE_END {
                 match TcpListener::bind(("0.0.0.0", port)).await {
                     Ok(listener) => {
                         let addr = listener
                             .local_addr()
                             .map_err(|e| anyhow!("Failed to get local address: {}", e))?;
-                        // Listener will close here, we'll recreate in the task
-                        return Ok(addr);
+                        // Keep the listener so no other instance can take this port
+                        return Ok((listener, addr));
                     }
                     Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
                     Err(e) => return Err(anyhow!("Failed to bind TCP listener: {}", e)),
@@ -124,13 +507,23 @@ impl TcpTransport {
 
         let peers = Arc::new(RwLock::new(HashMap::new()));
         let measured_tier = Arc::new(RwLock::new(None));
+        let (stop, stop_rx) = watch::channel(false);
+        let throttle = Arc::new(SendThrottle::new(Duration::from_micros(
+            config.send_backoff_us,
+        )));
+        if let Some(interval) = config.congestion_check {
+            let monitor = TcpCongestionMonitor::new(Box::new(ProcNetStats));
+            runtime.spawn(Self::congestion_task(
+                monitor,
+                interval,
+                Arc::clone(&throttle),
+                stop_rx.clone(),
+            ));
+        }
 
         // Start listener task
-        let peers_clone = Arc::clone(&peers);
-        let runtime_clone = Arc::clone(&runtime);
-        runtime.spawn(async move {
-            Self::listener_task(peers_clone, runtime_clone).await;
-        });
+        let server = ServerState::new(config);
+        let listener = runtime.spawn(Self::listener_task(listener, server.clone(), stop_rx));
 
         Ok(Self {
             local_node_id,
@@ -138,179 +531,408 @@ impl TcpTransport {
             peers,
             runtime,
             measured_tier,
+            server,
+            delta_bytes_saved: AtomicU64::new(0),
+            throttle,
+            stop,
+            listener: parking_lot::Mutex::new(Some(listener)),
         })
     }
 
     /// Background task to accept incoming connections
-    async fn listener_task(_peers: Arc<RwLock<HashMap<u32, SocketAddr>>>, _runtime: Arc<Runtime>) {
-        // Try to bind to any available port in range
-        let (listener, port) = 'bind: loop {
-            for port in PORT_RANGE_START..=PORT_RANGE_END {
-                match TcpListener::bind(("0.0.0.0", port)).await {
-                    Ok(l) => {
-                        info!("TCP listener bound to port {}", port);
-                        break 'bind (l, port);
+    ///
+    /// Returns the still-running connection handlers once `stop` is set,
+    /// after closing the listening socket.
+    async fn listener_task(
+        listener: TcpListener,
+        server: ServerState,
+        mut stop: watch::Receiver<bool>,
+    ) -> JoinSet<()> {
+        let port = listener.local_addr().map(|a| a.port()).unwrap_or_default();
+        info!("Listening for TCP connections on port {}", port);
+
+        let mut handlers = JoinSet::new();
+        let handler_stop = stop.clone();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((_, peer_addr)) if !server.accepting.load(Ordering::SeqCst) => {
+                        debug!("Shutting down, refusing connection from {}", peer_addr);
+                    }
+                    Ok((socket, peer_addr)) => {
+                        debug!("Accepted connection from {}", peer_addr);
+                        server.connections_accepted.fetch_add(1, Ordering::Relaxed);
+                        let server = server.clone();
+                        let stop = handler_stop.clone();
+                        handlers.spawn(async move {
+                            if let Err(e) = Self::serve_connection(socket, server, stop).await {
+                                warn!("Connection error from {}: {}", peer_addr, e);
+                            }
+                        });
                     }
-                    Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
                     Err(e) => {
-                        warn!("Failed to start TCP listener: {}", e);
-                        return;
+                        warn!("Accept error: {}", e);
                     }
-                }
+                },
+                // Reap finished handlers so the set only holds live ones
+                Some(_) = handlers.join_next(), if !handlers.is_empty() => {}
+                _ = stop.wait_for(|&stop| stop) => break,
             }
-            warn!(
-                "No ports available in range {}-{}",
-                PORT_RANGE_START, PORT_RANGE_END
-            );
-            return;
-        };
+        }
 
-        info!("Listening for TCP connections on port {}", port);
+        drop(listener);
+        info!("Stopped listening on port {}", port);
+        handlers
+    }
 
-        loop {
-            match listener.accept().await {
-                Ok((socket, peer_addr)) => {
-                    debug!("Accepted connection from {}", peer_addr);
-                    tokio::spawn(async move {
-                        if let Err(e) = Self::handle_connection(socket).await {
-                            warn!("Connection error from {}: {}", peer_addr, e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    warn!("Accept error: {}", e);
-                }
+    /// Serve an accepted connection, over TLS if configured
+    async fn serve_connection(
+        socket: TcpStream,
+        server: ServerState,
+        stop: watch::Receiver<bool>,
+    ) -> Result<()> {
+        // Set TCP_NODELAY for lower latency
+        socket.set_nodelay(true)?;
+
+        match server.config.tls.as_ref().map(TlsConfig::acceptor) {
+            Some(acceptor) => {
+                let stream = acceptor
+                    .accept(socket)
+                    .await
+                    .context("TLS handshake failed")?;
+                Self::handle_connection(stream, server, stop).await
             }
+            None => Self::handle_connection(socket, server, stop).await,
         }
     }
 
     /// Handle an incoming connection
-    async fn handle_connection(mut socket: TcpStream) -> Result<()> {
-        // Set TCP_NODELAY for lower latency
-        socket.set_nodelay(true)?;
+    ///
+    /// Responses go through a write buffer. Unless coalescing is disabled, a
+    /// page response is held for up to `nagle_buffer_us`; if another request
+    /// arrives meanwhile its response joins the same buffer, so a burst of
+    /// page requests is answered with a few large writes instead of many
+    /// 4 KiB segments.
+    ///
+    /// Once `stop` is set the connection is closed, but only after answering
+    /// every request that has already arrived.
+    async fn handle_connection<S: AsyncRead + AsyncWrite>(
+        socket: S,
+        server: ServerState,
+        mut stop: watch::Receiver<bool>,
+    ) -> Result<()> {
+        let (reader, writer) = tokio::io::split(socket);
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::with_capacity(COALESCE_FLUSH_BYTES, writer);
+        let hold = Duration::from_micros(server.config.nagle_buffer_us);
+        let coalesce = !server.config.disable_nagle_coalescing && !hold.is_zero();
+        let mut unsent = UnsentPages {
+            in_flight: Arc::clone(&server.in_flight_count),
+            count: 0,
+        };
+
+        // An unauthenticated peer is told why, then disconnected
+        if let Some(authenticator) = server.authenticator() {
+            let hello = Self::read_message(&mut reader).await?;
+            let answer = Self::answer_hello(authenticator.as_ref(), hello);
+            let response = match &answer {
+                Ok(response) => response,
+                Err(_) => &Message::Error {
+                    message: "Authentication failed".to_string(),
+                },
+            };
+            Self::send_message(&mut writer, response).await?;
+            answer.context("Peer failed authentication")?;
+        }
 
         loop {
-            // Read message length (4 bytes)
-            let mut len_buf = [0u8; 4];
-            match socket.read_exact(&mut len_buf).await {
-                Ok(_) => {}
-                Err(_) => break, // Connection closed
+            let msg = tokio::select! {
+                // Buffered requests complete without waiting, so they are
+                // always read before a stop is noticed
+                biased;
+                msg = Self::read_message(&mut reader) => msg?,
+                _ = stop.wait_for(|&stop| stop) => None,
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+
+            if matches!(
+                msg,
+                Message::FetchPage { .. } | Message::FetchPageBatch { .. }
+            ) {
+                unsent.received();
             }
-            let msg_len = u32::from_be_bytes(len_buf) as usize;
-
-            if msg_len > 10 * 1024 * 1024 {
-                // 10MB max
-                return Err(anyhow!("Message too large: {}", msg_len));
+            if let Some(response) = Self::handle_message(msg, &server) {
+                let is_page = matches!(response, Message::PageData { .. });
+                Self::write_message(&mut writer, &response).await?;
+
+                if coalesce
+                    && is_page
+                    && writer.buffer().len() < COALESCE_FLUSH_BYTES
+                    && Self::more_requests_within(&mut reader, hold).await
+                {
+                    continue;
+                }
             }
 
-            // Read message data
-            let mut msg_buf = vec![0u8; msg_len];
-            socket.read_exact(&mut msg_buf).await?;
-
-            let msg: Message = deserialize(&msg_buf)?;
+            if !writer.buffer().is_empty() {
+                server.response_writes.fetch_add(1, Ordering::Relaxed);
+                writer.flush().await?;
+            }
+            unsent.sent();
+        }
 
-            // Handle message
-            match msg {
-                Message::FetchPage { gpa } => {
-                    // In real implementation, look up page from local memory
-                    debug!("Received FetchPage request for GPA 0x{:x}", gpa);
+        if !writer.buffer().is_empty() {
+            server.response_writes.fetch_add(1, Ordering::Relaxed);
+            writer.flush().await?;
+        }
+        unsent.sent();
+        Ok(())
+    }
 
-                    // For now, return zeros (stub implementation)
-                    let response = Message::PageData {
-                        gpa,
-                        data: vec![0u8; PAGE_SIZE],
-                    };
+    /// Wait up to `hold` for the peer to send another request
+    ///
+    /// Whatever arrives stays in `reader`'s buffer for the next read.
+    async fn more_requests_within<R: AsyncRead + Unpin>(
+        reader: &mut BufReader<R>,
+        hold: Duration,
+    ) -> bool {
+        matches!(
+            tokio::time::timeout(hold, reader.fill_buf()).await,
+            Ok(Ok(buf)) if !buf.is_empty()
+        )
+    }
 
-                    Self::send_message(&mut socket, &response).await?;
-                }
-                Message::SendPage { gpa, data } => {
+    /// Build the response to a request (`None` if it needs no reply)
+    pub(super) fn handle_message(msg: Message, server: &ServerState) -> Option<Message> {
+        let pages = &server.pages;
+        match msg {
+            Message::FetchPage {
+                gpa,
+                epoch,
+                trace_context,
+            } => {
+                // In real implementation, look up page from local memory
+                debug!("Received FetchPage request for GPA 0x{:x}", gpa);
+                // Ends, and is exported, once the response is built
+                let _span = trace_context.map(|parent| serve_page_span(gpa, &parent));
+
+                if let Some(current) = server.check_epoch(epoch) {
                     debug!(
-                        "Received SendPage for GPA 0x{:x} ({} bytes)",
-                        gpa,
-                        data.len()
+                        "Rejecting fetch of GPA 0x{:x} at epoch {} (directory at {})",
+                        gpa, epoch, current
                     );
+                    server
+                        .stale_epoch_rejections
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Some(Message::StaleEpoch { gpa, current });
+                }
 
-                    // In real implementation, copy to local memory
-                    // For now, just acknowledge
-                    let response = Message::Ack;
-                    Self::send_message(&mut socket, &response).await?;
+                Some(Message::PageData {
+                    gpa,
+                    data: server.page(gpa),
+                })
+            }
+            Message::FetchPageHash { gpa, epoch } => {
+                if let Some(current) = server.check_epoch(epoch) {
+                    server
+                        .stale_epoch_rejections
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Some(Message::StaleEpoch { gpa, current });
                 }
-                Message::Ping { timestamp } => {
-                    let response = Message::Pong { timestamp };
-                    Self::send_message(&mut socket, &response).await?;
+                Some(Message::PageHash {
+                    gpa,
+                    hash: xxh3_128(&server.page(gpa)),
+                })
+            }
+            Message::FetchPageBatch { gpas } => {
+                debug!("Received FetchPageBatch request for {} pages", gpas.len());
+                let pages = gpas
+                    .into_iter()
+                    .map(|gpa| (gpa, server.page(gpa)))
+                    .collect();
+                Some(Message::PageDataBatch { pages })
+            }
+            Message::SendPage { gpa, data } => {
+                debug!(
+                    "Received SendPage for GPA 0x{:x} ({} bytes)",
+                    gpa,
+                    data.len()
+                );
+
+                // In real implementation, copy to local memory
+                pages.write().insert(gpa, data);
+                Some(Message::Ack)
+            }
+            Message::DeltaPage {
+                gpa,
+                delta,
+                base_hash: expected,
+            } => {
+                debug!(
+                    "Received DeltaPage for GPA 0x{:x} ({} bytes)",
+                    gpa,
+                    delta.len()
+                );
+
+                let mut pages = pages.write();
+                let page = pages.entry(gpa).or_insert_with(|| vec![0u8; PAGE_SIZE]);
+                if base_hash(page) != expected {
+                    debug!("Delta base mismatch for GPA 0x{:x}", gpa);
+                    return Some(Message::NeedFullPage { gpa });
                 }
-                _ => {
-                    warn!("Unexpected message type in server handler");
+                match DeltaEncoder::apply(page, &delta) {
+                    Ok(()) => Some(Message::Ack),
+                    Err(e) => Some(Message::Error {
+                        message: format!("Bad delta for GPA 0x{:x}: {}", gpa, e),
+                    }),
                 }
             }
+            Message::Hello { .. } => Some(Message::Error {
+                message: "Credentials are only accepted as a connection opens".to_string(),
+            }),
+            Message::Ping { timestamp } => Some(Message::Pong { timestamp }),
+            Message::TscProbe { sender_tsc } => Some(Message::TscEcho {
+                sender_tsc,
+                receiver_tsc: read_tsc(),
+            }),
+            _ => {
+                warn!("Unexpected message type in server handler");
+                None
+            }
         }
+    }
 
-        Ok(())
+    /// Read one length-prefixed message (`None` when the peer closed)
+    pub(super) async fn read_message<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<Option<Message>> {
+        // Read message length (4 bytes)
+        let mut len_buf = [0u8; 4];
+        if reader.read_exact(&mut len_buf).await.is_err() {
+            return Ok(None); // Connection closed
+        }
+        let msg_len = u32::from_be_bytes(len_buf) as usize;
+
+        if msg_len > MAX_MESSAGE_SIZE {
+            return Err(anyhow!("Message too large: {}", msg_len));
+        }
+
+        // Read message data
+        let mut msg_buf = vec![0u8; msg_len];
+        reader.read_exact(&mut msg_buf).await?;
+
+        Ok(Some(deserialize(&msg_buf)?))
     }
 
-    /// Send a message over TCP
-    async fn send_message(socket: &mut TcpStream, msg: &Message) -> Result<()> {
+    /// Append a length-prefixed message to a writer without flushing
+    pub(super) async fn write_message<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        msg: &Message,
+    ) -> Result<()> {
         let msg_data = serialize(msg)?;
         let len = (msg_data.len() as u32).to_be_bytes();
 
-        socket.write_all(&len).await?;
-        socket.write_all(&msg_data).await?;
-        socket.flush().await?;
+        writer.write_all(&len).await?;
+        writer.write_all(&msg_data).await?;
 
         Ok(())
     }
 
-    /// Send a message and wait for response
-    async fn send_and_receive(peer_addr: SocketAddr, msg: &Message) -> Result<Message> {
-        let mut socket = TcpStream::connect(peer_addr)
-            .await
-            .context("Failed to connect to peer")?;
-
-        socket.set_nodelay(true)?;
+    /// Send a message over TCP
+    async fn send_message<S: AsyncWrite + Unpin>(socket: &mut S, msg: &Message) -> Result<()> {
+        Self::write_message(socket, msg).await?;
+        socket.flush().await?;
 
-        // Send request
-        Self::send_message(&mut socket, msg).await?;
+        Ok(())
+    }
 
-        // Read response length
-        let mut len_buf = [0u8; 4];
-        socket.read_exact(&mut len_buf).await?;
-        let msg_len = u32::from_be_bytes(len_buf) as usize;
+    /// Send a message on an open connection and wait for response
+    async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+        socket: &mut S,
+        msg: &Message,
+    ) -> Result<Message> {
+        Self::send_message(socket, msg).await?;
 
-        // Read response data
-        let mut msg_buf = vec![0u8; msg_len];
-        socket.read_exact(&mut msg_buf).await?;
+        Self::read_message(socket)
+            .await?
+            .ok_or_else(|| anyhow!("Connection closed before response"))
+    }
 
-        let response: Message = deserialize(&msg_buf)?;
-        Ok(response)
+    /// `Hello` presenting this node to `remote_node_id`
+    pub(super) fn hello(
+        authenticator: &dyn PeerAuthenticator,
+        remote_node_id: u32,
+    ) -> Result<Message> {
+        Ok(Message::Hello {
+            credentials: authenticator.credentials(remote_node_id)?,
+        })
     }
 
-    /// Detect network tier based on measured latency
-    fn detect_tier(&self, latency: Duration) -> TransportTier {
-        if latency < Duration::from_micros(150) {
-            TransportTier::MediumPerformance // Unlikely on TCP, but possible with tuning
-        } else if latency < Duration::from_micros(500) {
-            TransportTier::Standard // 10G Ethernet
-        } else {
-            TransportTier::Basic // 1G Ethernet
+    /// Check `response` to our `Hello` came from `remote_node_id`
+    pub(super) fn check_hello(
+        authenticator: &dyn PeerAuthenticator,
+        remote_node_id: u32,
+        response: Message,
+    ) -> Result<()> {
+        match response {
+            Message::Hello { credentials } => {
+                let node_id = authenticator
+                    .verify(&credentials)
+                    .with_context(|| format!("Node {} failed authentication", remote_node_id))?;
+                if node_id != remote_node_id {
+                    return Err(anyhow!(
+                        "Node {} answered as node {}",
+                        remote_node_id,
+                        node_id
+                    ));
+                }
+                Ok(())
+            }
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Node {} did not authenticate", remote_node_id)),
         }
     }
-}
 
-impl PageTransport for TcpTransport {
-    fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>> {
-        let peer_addr = {
-            let peers = self.peers.read();
-            *peers
-                .get(&remote_node_id)
-                .ok_or_else(|| anyhow!("Node {} not connected", remote_node_id))?
+    /// Our `Hello` answering `hello`, the first message on an inbound
+    /// connection, if it carries valid credentials
+    pub(super) fn answer_hello(
+        authenticator: &dyn PeerAuthenticator,
+        hello: Option<Message>,
+    ) -> Result<Message> {
+        let Some(Message::Hello { credentials }) = hello else {
+            return Err(anyhow!("Connection did not open with credentials"));
         };
+        let node_id = authenticator.verify(&credentials)?;
+        Self::hello(authenticator, node_id)
+    }
 
-        let msg = Message::FetchPage { gpa };
+    /// Connections to `remote_node_id`
+    fn pool(&self, remote_node_id: u32) -> Result<Arc<ConnectionPool>> {
+        self.peers
+            .read()
+            .get(&remote_node_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Node {} not connected", remote_node_id))
+    }
 
-        let response = self
-            .runtime
-            .block_on(Self::send_and_receive(peer_addr, &msg))?;
+    /// `(active, idle)` connections to `peer`: those carrying a request
+    /// right now, and those kept open for the next ones
+    pub fn pool_stats(&self, peer: u32) -> (usize, usize) {
+        self.peers
+            .read()
+            .get(&peer)
+            .map_or((0, 0), |pool| pool.stats())
+    }
+
+    /// Connections the server side has accepted so far
+    pub fn connections_accepted(&self) -> u64 {
+        self.server.connections_accepted.load(Ordering::Relaxed)
+    }
 
+    /// Page data from the response to a `FetchPage` sent at `epoch`
+    pub(super) fn page_from_response(response: Message, epoch: u64) -> Result<Vec<u8>> {
         match response {
             Message::PageData { data, .. } => {
                 if data.len() != PAGE_SIZE {
@@ -322,27 +944,188 @@ impl PageTransport for TcpTransport {
                 }
                 Ok(data)
             }
+            Message::StaleEpoch { current, .. } => Err(TransportError::StaleEpoch {
+                requested: epoch,
+                current,
+            }
+            .into()),
             Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
             _ => Err(anyhow!("Unexpected response type")),
         }
     }
 
-    fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()> {
-        let peer_addr = {
-            let peers = self.peers.read();
-            *peers
-                .get(&remote_node_id)
-                .ok_or_else(|| anyhow!("Node {} not connected", remote_node_id))?
+    /// Page hash from the response to a `FetchPageHash` made at `epoch`
+    pub(super) fn hash_from_response(response: Message, epoch: u64) -> Result<u128> {
+        match response {
+            Message::PageHash { hash, .. } => Ok(hash),
+            Message::StaleEpoch { current, .. } => Err(TransportError::StaleEpoch {
+                requested: epoch,
+                current,
+            }
+            .into()),
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response type")),
+        }
+    }
+
+    /// Pages for `gpas`, in order, from the response to a `FetchPageBatch`
+    ///
+    /// A page missing from the response, or of the wrong size, fails on its
+    /// own; a failed exchange fails every page.
+    pub(super) fn pages_from_batch_response(
+        response: Result<Message>,
+        gpas: &[u64],
+    ) -> Vec<Result<Vec<u8>>> {
+        let pages: HashMap<u64, Vec<u8>> = match response {
+            Ok(Message::PageDataBatch { pages }) => pages.into_iter().collect(),
+            Ok(Message::Error { message }) => {
+                return gpas
+                    .iter()
+                    .map(|_| Err(anyhow!("Remote error: {}", message)))
+                    .collect();
+            }
+            Ok(_) => {
+                return gpas
+                    .iter()
+                    .map(|_| Err(anyhow!("Unexpected response type")))
+                    .collect();
+            }
+            Err(e) => {
+                return gpas
+                    .iter()
+                    .map(|_| Err(anyhow!("Batch fetch failed: {:#}", e)))
+                    .collect();
+            }
         };
 
-        let msg = Message::SendPage {
+        gpas.iter()
+            .map(|gpa| match pages.get(gpa) {
+                Some(data) if data.len() == PAGE_SIZE => Ok(data.clone()),
+                Some(data) => Err(anyhow!(
+                    "Invalid page size: expected {}, got {}",
+                    PAGE_SIZE,
+                    data.len()
+                )),
+                None => Err(anyhow!("GPA 0x{:x} missing from batch response", gpa)),
+            })
+            .collect()
+    }
+
+    /// Double the backoff between page sends, or go back to full rate
+    pub fn throttle_mode(&self, enabled: bool) {
+        self.throttle.set(enabled);
+    }
+
+    /// Throttle sends while `monitor` reports congestion, checking every
+    /// `interval` until the transport stops
+    pub fn monitor_congestion(&self, monitor: TcpCongestionMonitor, interval: Duration) {
+        self.runtime.spawn(Self::congestion_task(
+            monitor,
+            interval,
+            Arc::clone(&self.throttle),
+            self.stop.subscribe(),
+        ));
+    }
+
+    async fn congestion_task(
+        mut monitor: TcpCongestionMonitor,
+        interval: Duration,
+        throttle: Arc<SendThrottle>,
+        mut stop: watch::Receiver<bool>,
+    ) {
+        loop {
+            tokio::select! {
+                _ = stop.changed() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+            let level = monitor.congestion_level();
+            debug!("TCP retransmit rate {:.3}", level);
+            throttle.set(level > CONGESTION_THRESHOLD);
+        }
+    }
+
+    /// Socket writes the server side has used for responses so far
+    pub fn response_writes(&self) -> u64 {
+        self.server.response_writes.load(Ordering::Relaxed)
+    }
+
+    /// Detect network tier based on measured latency
+    pub(super) fn detect_tier(latency: Duration) -> TransportTier {
+        if latency < Duration::from_micros(150) {
+            TransportTier::MediumPerformance // Unlikely on TCP, but possible with tuning
+        } else if latency < Duration::from_micros(500) {
+            TransportTier::Standard // 10G Ethernet
+        } else {
+            TransportTier::Basic // 1G Ethernet
+        }
+    }
+}
+
+impl PageTransport for TcpTransport {
+    fn fetch_page(&self, gpa: u64, remote_node_id: u32) -> Result<Vec<u8>> {
+        self.fetch_page_at_epoch(gpa, remote_node_id, 0)
+    }
+
+    fn fetch_page_at_epoch(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<Vec<u8>> {
+        let pool = self.pool(remote_node_id)?;
+
+        let msg = Message::FetchPage {
             gpa,
-            data: data.to_vec(),
+            epoch,
+            trace_context: TraceContext::current(),
         };
 
+        let response = self.runtime.block_on(pool.send_and_receive(&msg))?;
+        Self::page_from_response(response, epoch)
+    }
+
+    fn fetch_page_hash(&self, gpa: u64, remote_node_id: u32, epoch: u64) -> Result<u128> {
+        let pool = self.pool(remote_node_id)?;
         let response = self
             .runtime
-            .block_on(Self::send_and_receive(peer_addr, &msg))?;
+            .block_on(pool.send_and_receive(&Message::FetchPageHash { gpa, epoch }))?;
+        Self::hash_from_response(response, epoch)
+    }
+
+    /// Fetch up to `MAX_BATCH_PAGES` pages per exchange
+    fn fetch_pages_batch(&self, gpas: &[u64], remote_node_id: u32) -> Vec<Result<Vec<u8>>> {
+        let pool = self.peers.read().get(&remote_node_id).cloned();
+        let Some(pool) = pool else {
+            return gpas
+                .iter()
+                .map(|_| Err(anyhow!("Node {} not connected", remote_node_id)))
+                .collect();
+        };
+
+        gpas.chunks(MAX_BATCH_PAGES)
+            .flat_map(|chunk| {
+                let msg = Message::FetchPageBatch {
+                    gpas: chunk.to_vec(),
+                };
+                let response = self.runtime.block_on(pool.send_and_receive(&msg));
+                Self::pages_from_batch_response(response, chunk)
+            })
+            .collect()
+    }
+
+    fn set_directory_epoch(&self, epoch: Arc<AtomicU64>) {
+        self.server.set_directory_epoch(epoch);
+    }
+
+    fn set_authenticator(&self, authenticator: Arc<dyn PeerAuthenticator>) {
+        self.server.set_authenticator(authenticator);
+    }
+
+    fn send_page(&self, gpa: u64, data: &[u8], remote_node_id: u32) -> Result<()> {
+        let pool = self.pool(remote_node_id)?;
+
+        let msg = Message::SendPage {
+            gpa,
+            data: data.to_vec(),
+        };
+
+        self.throttle.pace();
+        let response = self.runtime.block_on(pool.send_and_receive(&msg))?;
 
         match response {
             Message::Ack => Ok(()),
@@ -351,6 +1134,110 @@ impl PageTransport for TcpTransport {
         }
     }
 
+    fn received_page(&self, gpa: u64) -> Option<Vec<u8>> {
+        self.server.received_page(gpa)
+    }
+
+    fn send_page_delta(
+        &self,
+        gpa: u64,
+        base: &[u8],
+        data: &[u8],
+        remote_node_id: u32,
+    ) -> Result<()> {
+        let delta = DeltaEncoder::encode(base, data);
+        if delta.len() >= data.len() {
+            return self.send_page(gpa, data, remote_node_id);
+        }
+
+        let pool = self.pool(remote_node_id)?;
+
+        let saved = (data.len() - delta.len()) as u64;
+        let msg = Message::DeltaPage {
+            gpa,
+            delta,
+            base_hash: base_hash(base),
+        };
+
+        self.throttle.pace();
+        let response = self.runtime.block_on(pool.send_and_receive(&msg))?;
+
+        match response {
+            Message::Ack => {
+                self.delta_bytes_saved.fetch_add(saved, Ordering::Relaxed);
+                Ok(())
+            }
+            Message::NeedFullPage { .. } => {
+                debug!(
+                    "Node {} changed GPA 0x{:x}, resending full page",
+                    remote_node_id, gpa
+                );
+                self.send_page(gpa, data, remote_node_id)
+            }
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response type")),
+        }
+    }
+
+    fn stats(&self) -> TransportStats {
+        TransportStats {
+            delta_bytes_saved: self.delta_bytes_saved.load(Ordering::Relaxed),
+            stale_epoch_rejections: self.server.stale_epoch_rejections(),
+            congestion_events: self.throttle.congestion_events.load(Ordering::Relaxed),
+            throttled: self.throttle.throttled.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+
+    /// Stop serving peers, answering the page requests already received
+    ///
+    /// New connections are refused first. Once no `FetchPage` is awaiting its
+    /// `PageData`, the listening socket is closed and connection handlers
+    /// finish the requests they have read. Handlers still running when
+    /// `timeout` expires are aborted and an error is returned. Later calls
+    /// return at once.
+    fn shutdown_gracefully(&self, timeout: Duration) -> Result<()> {
+        let Some(listener) = self.listener.lock().take() else {
+            return Ok(());
+        };
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.server.accepting.store(false, Ordering::SeqCst);
+
+        self.runtime.block_on(async {
+            while self.server.in_flight_count.load(Ordering::SeqCst) > 0
+                && tokio::time::Instant::now() < deadline
+            {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            self.stop.send_replace(true);
+            let mut handlers = listener
+                .await
+                .map_err(|e| anyhow!("TCP listener task failed: {}", e))?;
+
+            let drained = tokio::time::timeout_at(deadline, async {
+                while handlers.join_next().await.is_some() {}
+            })
+            .await;
+            if drained.is_err() {
+                let open = handlers.len();
+                handlers.abort_all();
+                return Err(anyhow!(
+                    "{} TCP connections still open after {:?} ({} page requests unanswered)",
+                    open,
+                    timeout,
+                    self.server.in_flight_count.load(Ordering::SeqCst)
+                ));
+            }
+
+            info!(
+                "TCP transport shut down gracefully (node_id={})",
+                self.local_node_id
+            );
+            Ok(())
+        })
+    }
+
     fn register_memory(&self, addr: *mut u8, length: usize) -> Result<Box<dyn MemoryRegion>> {
         // TCP doesn't require special registration
         Ok(Box::new(TcpMemoryRegion { addr, length }))
@@ -362,9 +1249,8 @@ impl PageTransport for TcpTransport {
             // Use first non-loopback interface
             local_ip_address::local_ip()
                 .unwrap_or(IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)))
-                .to_string()
         } else {
-            self.local_addr.ip().to_string()
+            self.local_addr.ip()
         };
 
         TransportEndpoint::Tcp {
@@ -376,20 +1262,36 @@ impl PageTransport for TcpTransport {
     fn connect(&mut self, remote_node_id: u32, remote_endpoint: TransportEndpoint) -> Result<()> {
         match remote_endpoint {
             TransportEndpoint::Tcp { addr, port } => {
-                let socket_addr = format!("{}:{}", addr, port)
-                    .parse::<SocketAddr>()
-                    .context("Invalid socket address")?;
-
-                self.peers.write().insert(remote_node_id, socket_addr);
-
+                let socket_addr = SocketAddr::new(addr, port);
+
+                let tls = self.server.config.tls.as_ref().map(TlsConfig::connector);
+                let authenticator = self.server.authenticator();
+                let authenticated = authenticator.is_some();
+                let pool = ConnectionPool::new(
+                    socket_addr,
+                    self.server.config.pool_size,
+                    tls,
+                    remote_node_id,
+                    authenticator,
+                );
+                self.peers.write().insert(remote_node_id, Arc::new(pool));
+
+                // Measure latency on connect; with authentication, this also
+                // checks the peer is who it should be
+                let latency = match self.measure_latency(remote_node_id) {
+                    Err(e) if authenticated => {
+                        self.peers.write().remove(&remote_node_id);
+                        return Err(e);
+                    }
+                    latency => latency,
+                };
                 info!(
                     "Connected to node {} at {} (TCP)",
                     remote_node_id, socket_addr
                 );
 
-                // Measure latency on connect
-                if let Ok(latency) = self.measure_latency(remote_node_id) {
-                    let tier = self.detect_tier(latency);
+                if let Ok(latency) = latency {
+                    let tier = Self::detect_tier(latency);
                     *self.measured_tier.write() = Some(tier);
                     info!(
                         "Network performance: {} (~{}µs latency)",
@@ -411,6 +1313,10 @@ impl PageTransport for TcpTransport {
             TransportEndpoint::Rdma { .. } => Err(anyhow!(
                 "Cannot connect to RDMA endpoint with TCP transport"
             )),
+            #[cfg(feature = "quic-transport")]
+            TransportEndpoint::Quic { .. } => Err(anyhow!(
+                "Cannot connect to QUIC endpoint with TCP transport"
+            )),
         }
     }
 
@@ -419,21 +1325,14 @@ impl PageTransport for TcpTransport {
     }
 
     fn measure_latency(&self, remote_node_id: u32) -> Result<Duration> {
-        let peer_addr = {
-            let peers = self.peers.read();
-            *peers
-                .get(&remote_node_id)
-                .ok_or_else(|| anyhow!("Node {} not connected", remote_node_id))?
-        };
+        let pool = self.pool(remote_node_id)?;
 
         let start = Instant::now();
         let timestamp = start.elapsed().as_nanos() as u64;
 
         let msg = Message::Ping { timestamp };
 
-        let response = self
-            .runtime
-            .block_on(Self::send_and_receive(peer_addr, &msg))?;
+        let response = self.runtime.block_on(pool.send_and_receive(&msg))?;
 
         let elapsed = start.elapsed();
 
@@ -442,11 +1341,34 @@ impl PageTransport for TcpTransport {
             _ => Err(anyhow!("Unexpected response to ping")),
         }
     }
+
+    fn probe_tsc(&self, remote_node_id: u32, local_tsc: u64) -> Result<u64> {
+        let pool = self.pool(remote_node_id)?;
+
+        let msg = Message::TscProbe {
+            sender_tsc: local_tsc,
+        };
+
+        let response = self.runtime.block_on(pool.send_and_receive(&msg))?;
+
+        match response {
+            Message::TscEcho {
+                sender_tsc,
+                receiver_tsc,
+            } if sender_tsc == local_tsc => Ok(receiver_tsc),
+            Message::TscEcho { .. } => Err(anyhow!("TSC echo does not match probe")),
+            Message::Error { message } => Err(anyhow!("Remote error: {}", message)),
+            _ => Err(anyhow!("Unexpected response to TSC probe")),
+        }
+    }
 }
 
 impl Drop for TcpTransport {
     fn drop(&mut self) {
-        debug!("Shutting down TCP transport");
+        debug!(
+            "Shutting down TCP transport (node_id={})",
+            self.local_node_id
+        );
     }
 }
 
@@ -460,6 +1382,428 @@ mod tests {
         assert!(transport.is_ok());
     }
 
+    /// Pipeline `count` page requests on one connection and return how many
+    /// socket writes the server used to answer them
+    fn pipelined_response_writes(config: TcpTransportConfig, count: u64) -> u64 {
+        let transport = TcpTransport::with_config(1, config).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], transport.local_addr.port()));
+
+        transport.runtime.block_on(async {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            socket.set_nodelay(true).unwrap();
+
+            // All requests in one write so they arrive back-to-back
+            let mut batch = Vec::new();
+            for gpa in 0..count {
+                TcpTransport::write_message(
+                    &mut batch,
+                    &Message::FetchPage {
+                        gpa: gpa << 12,
+                        epoch: 0,
+                        trace_context: None,
+                    },
+                )
+                .await
+                .unwrap();
+            }
+            socket.write_all(&batch).await.unwrap();
+
+            for gpa in 0..count {
+                match TcpTransport::read_message(&mut socket).await.unwrap() {
+                    Some(Message::PageData { gpa: got, data }) => {
+                        assert_eq!(got, gpa << 12);
+                        assert_eq!(data.len(), PAGE_SIZE);
+                    }
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+        });
+
+        transport.response_writes()
+    }
+
+    #[test]
+    fn test_page_responses_coalesce() {
+        let writes = pipelined_response_writes(TcpTransportConfig::default(), 8);
+        assert!(writes < 8, "8 page responses took {} writes", writes);
+    }
+
+    #[test]
+    fn test_coalescing_disabled_flushes_each_response() {
+        let config = TcpTransportConfig {
+            disable_nagle_coalescing: true,
+            ..Default::default()
+        };
+        assert_eq!(pipelined_response_writes(config, 8), 8);
+    }
+
+    /// Two transports, the first connected to the second as node 2
+    fn connected_pair() -> (TcpTransport, TcpTransport) {
+        let mut sender = TcpTransport::new(1).unwrap();
+        let receiver = TcpTransport::new(2).unwrap();
+        let endpoint = TransportEndpoint::tcp(SocketAddr::from((
+            [127, 0, 0, 1],
+            receiver.local_addr.port(),
+        )));
+        sender.connect(2, endpoint).unwrap();
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_sequential_fetches_reuse_pooled_connections() {
+        let (sender, receiver) = connected_pair();
+        for page in 0..100u64 {
+            sender.fetch_page(page << 12, 2).unwrap();
+        }
+
+        let accepted = receiver.connections_accepted();
+        assert!(accepted < 10, "100 fetches opened {} connections", accepted);
+        assert_eq!(sender.pool_stats(2), (0, 1));
+        assert_eq!(sender.pool_stats(3), (0, 0));
+    }
+
+    /// TLS settings presenting, and trusting only, a new self-signed
+    /// certificate for 127.0.0.1
+    fn self_signed_tls(dir: &std::path::Path, name: &str) -> TlsConfig {
+        let certified = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let cert = dir.join(format!("{}.crt", name));
+        let key = dir.join(format!("{}.key", name));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        TlsConfig::from_pem_files(&cert, &key, &cert).unwrap()
+    }
+
+    /// Node 1 connected to node 2, each with its own TLS settings
+    fn tls_pair(sender_tls: TlsConfig, receiver_tls: TlsConfig) -> (TcpTransport, TcpTransport) {
+        let with_tls = |tls| TcpTransportConfig {
+            tls: Some(tls),
+            ..Default::default()
+        };
+        let mut sender = TcpTransport::with_config(1, with_tls(sender_tls)).unwrap();
+        let receiver = TcpTransport::with_config(2, with_tls(receiver_tls)).unwrap();
+        let endpoint = TransportEndpoint::tcp(SocketAddr::from((
+            [127, 0, 0, 1],
+            receiver.local_addr.port(),
+        )));
+        sender.connect(2, endpoint).unwrap();
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_pages_round_trip_over_tls() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls = self_signed_tls(dir.path(), "node");
+        let (sender, receiver) = tls_pair(tls.clone(), tls);
+
+        let page: Vec<u8> = (0..PAGE_SIZE).map(|i| (i * 7) as u8).collect();
+        sender.send_page(0x8000, &page, 2).unwrap();
+        assert_eq!(receiver.received_page(0x8000), Some(page.clone()));
+        assert_eq!(sender.fetch_page(0x8000, 2).unwrap(), page);
+    }
+
+    #[test]
+    fn test_tls_peer_with_untrusted_certificate_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, _receiver) = tls_pair(
+            self_signed_tls(dir.path(), "sender"),
+            self_signed_tls(dir.path(), "receiver"),
+        );
+        assert!(sender.fetch_page(0x8000, 2).is_err());
+    }
+
+    #[test]
+    fn test_delta_transfer_of_one_percent_change() {
+        let (sender, _receiver) = connected_pair();
+        let gpa = 0x5000;
+        let base: Vec<u8> = (0..PAGE_SIZE).map(|i| i as u8).collect();
+        sender.send_page(gpa, &base, 2).unwrap();
+
+        // Change 1% of the page (41 bytes)
+        let mut modified = base.clone();
+        for byte in &mut modified[1000..1041] {
+            *byte = !*byte;
+        }
+
+        let full = serialize(&Message::SendPage {
+            gpa,
+            data: modified.clone(),
+        })
+        .unwrap()
+        .len();
+        let delta = serialize(&Message::DeltaPage {
+            gpa,
+            delta: DeltaEncoder::encode(&base, &modified),
+            base_hash: base_hash(&base),
+        })
+        .unwrap()
+        .len();
+        println!("1% change: full {} bytes, delta {} bytes", full, delta);
+        assert!(delta * 40 < full);
+
+        sender.send_page_delta(gpa, &base, &modified, 2).unwrap();
+        assert_eq!(sender.fetch_page(gpa, 2).unwrap(), modified);
+        assert_eq!(
+            sender.stats().delta_bytes_saved,
+            (PAGE_SIZE - DeltaEncoder::encode(&base, &modified).len()) as u64
+        );
+    }
+
+    #[test]
+    fn test_congestion_activates_throttle() {
+        use crate::monitor::tests::MockNetStats;
+
+        let transport = TcpTransport::with_config(
+            1,
+            TcpTransportConfig {
+                congestion_check: None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let backoff = transport.throttle.backoff();
+        let stats = MockNetStats::default();
+        // 10% of segments retransmitted
+        stats.set(1000, 100);
+        transport.monitor_congestion(
+            TcpCongestionMonitor::new(Box::new(stats.clone())),
+            Duration::from_millis(10),
+        );
+
+        let wait_for = |throttled: bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while transport.stats().throttled != throttled && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(transport.stats().throttled, throttled);
+        };
+        wait_for(true);
+        assert_eq!(transport.throttle.backoff(), backoff * 2);
+
+        // 1% since the last sample
+        stats.set(101_000, 1100);
+        wait_for(false);
+        assert_eq!(transport.throttle.backoff(), backoff);
+        assert_eq!(transport.stats().congestion_events, 1);
+    }
+
+    #[test]
+    fn test_sent_pages_readable_by_receiver() {
+        let (sender, receiver) = connected_pair();
+        assert_eq!(receiver.received_page(0x7000), None);
+        sender.send_page(0x7000, &vec![3u8; PAGE_SIZE], 2).unwrap();
+        assert_eq!(receiver.received_page(0x7000), Some(vec![3u8; PAGE_SIZE]));
+    }
+
+    #[test]
+    fn test_delta_base_mismatch_sends_full_page() {
+        let (sender, _receiver) = connected_pair();
+        let gpa = 0x6000;
+        let stale = vec![0u8; PAGE_SIZE];
+        sender.send_page(gpa, &vec![1u8; PAGE_SIZE], 2).unwrap();
+
+        let mut modified = stale.clone();
+        modified[0] = 0xff;
+        sender.send_page_delta(gpa, &stale, &modified, 2).unwrap();
+
+        assert_eq!(sender.fetch_page(gpa, 2).unwrap(), modified);
+        assert_eq!(sender.stats().delta_bytes_saved, 0);
+    }
+
+    #[test]
+    fn test_shutdown_gracefully_answers_received_requests() {
+        const REQUESTS: u64 = 10;
+
+        let transport = TcpTransport::new(1).unwrap();
+        let addr = SocketAddr::from(([127, 0, 0, 1], transport.local_addr.port()));
+
+        let mut socket = transport.runtime.block_on(async {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            let mut batch = Vec::new();
+            for gpa in 0..REQUESTS {
+                TcpTransport::write_message(
+                    &mut batch,
+                    &Message::FetchPage {
+                        gpa: gpa << 12,
+                        epoch: 0,
+                        trace_context: None,
+                    },
+                )
+                .await
+                .unwrap();
+            }
+            socket.write_all(&batch).await.unwrap();
+
+            // The server has started on the batch; the rest is buffered
+            let first = TcpTransport::read_message(&mut socket).await.unwrap();
+            assert!(matches!(first, Some(Message::PageData { gpa: 0, .. })));
+            socket
+        });
+
+        transport
+            .shutdown_gracefully(Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(transport.server.in_flight_count.load(Ordering::SeqCst), 0);
+
+        transport.runtime.block_on(async {
+            for gpa in 1..REQUESTS {
+                match TcpTransport::read_message(&mut socket).await.unwrap() {
+                    Some(Message::PageData { gpa: got, .. }) => assert_eq!(got, gpa << 12),
+                    other => panic!("unexpected response: {:?}", other),
+                }
+            }
+            // The server closed the connection after the last response
+            assert!(TcpTransport::read_message(&mut socket)
+                .await
+                .unwrap()
+                .is_none());
+        });
+
+        // Shutting down again is a no-op
+        transport
+            .shutdown_gracefully(Duration::from_secs(2))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_fetch_span_parents_remote_serve_span() {
+        use opentelemetry::trace::{Span, TraceContextExt, TracerProvider};
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        // The receiver's spans go to the global provider
+        global::set_tracer_provider(provider.clone());
+
+        let (sender, _receiver) = connected_pair();
+        let fault = provider.tracer("pager").start("handle_pagefault");
+        let parent = fault.span_context().clone();
+        {
+            let _fault = opentelemetry::Context::current_with_span(fault).attach();
+            sender.fetch_page(0x3000, 2).unwrap();
+        }
+        // Outside the span nothing is propagated
+        sender.fetch_page(0x4000, 2).unwrap();
+
+        let served: Vec<_> = exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .filter(|span| span.name == "serve_page")
+            .collect();
+        assert_eq!(served.len(), 1);
+        let served = &served[0];
+        assert_eq!(served.span_context.trace_id(), parent.trace_id());
+        assert_eq!(served.parent_span_id, parent.span_id());
+        assert!(served.parent_span_is_remote);
+        assert!(served
+            .attributes
+            .contains(&KeyValue::new("gpa", 0x3000_i64)));
+        let _ = provider.shutdown();
+    }
+
+    #[test]
+    fn test_stale_epoch_rejected_until_directory_catches_up() {
+        let (sender, receiver) = connected_pair();
+        let receiver_epoch = Arc::new(AtomicU64::new(3));
+        receiver.set_directory_epoch(Arc::clone(&receiver_epoch));
+
+        // Requests at or below the receiver's epoch are served
+        assert_eq!(
+            sender.fetch_page_at_epoch(0x1000, 2, 3).unwrap().len(),
+            PAGE_SIZE
+        );
+
+        let err = sender.fetch_page_at_epoch(0x1000, 2, 5).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransportError>(),
+            Some(&TransportError::StaleEpoch {
+                requested: 5,
+                current: 3
+            })
+        );
+        assert_eq!(receiver.stats().stale_epoch_rejections, 1);
+
+        // The receiver applies the migration; the retry goes through
+        receiver_epoch.store(5, Ordering::SeqCst);
+        assert_eq!(
+            sender.fetch_page_at_epoch(0x1000, 2, 5).unwrap().len(),
+            PAGE_SIZE
+        );
+    }
+
+    /// Credentials are the node ID behind a secret shared by the cluster
+    struct SharedSecret {
+        node_id: u32,
+        secret: u8,
+    }
+
+    impl PeerAuthenticator for SharedSecret {
+        fn credentials(&self, _remote_node_id: u32) -> Result<Vec<u8>> {
+            let mut credentials = vec![self.secret];
+            credentials.extend(self.node_id.to_le_bytes());
+            Ok(credentials)
+        }
+
+        fn verify(&self, credentials: &[u8]) -> Result<u32> {
+            match credentials {
+                [secret, node_id @ ..] if *secret == self.secret => {
+                    Ok(u32::from_le_bytes(node_id.try_into()?))
+                }
+                _ => Err(anyhow!("Wrong secret")),
+            }
+        }
+    }
+
+    /// Transport for `node_id` that authenticates with `secret`, if any
+    fn authenticated(node_id: u32, secret: Option<u8>) -> TcpTransport {
+        let transport = TcpTransport::new(node_id).unwrap();
+        if let Some(secret) = secret {
+            transport.set_authenticator(Arc::new(SharedSecret { node_id, secret }));
+        }
+        transport
+    }
+
+    fn endpoint_of(transport: &TcpTransport) -> TransportEndpoint {
+        TransportEndpoint::tcp(SocketAddr::from((
+            [127, 0, 0, 1],
+            transport.local_addr.port(),
+        )))
+    }
+
+    #[test]
+    fn test_authenticated_peers_connect() {
+        let receiver = authenticated(2, Some(7));
+        let mut sender = authenticated(1, Some(7));
+        sender.connect(2, endpoint_of(&receiver)).unwrap();
+        assert_eq!(sender.fetch_page(0x1000, 2).unwrap().len(), PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_unauthenticated_peer_disconnected() {
+        let receiver = authenticated(2, Some(7));
+
+        // Connecting without credentials is not checked until a request
+        let mut anonymous = authenticated(1, None);
+        anonymous.connect(2, endpoint_of(&receiver)).unwrap();
+        let err = anonymous.fetch_page(0x1000, 2).unwrap_err();
+        assert!(format!("{:#}", err).contains("Authentication failed"));
+
+        let mut impostor = authenticated(3, Some(8));
+        let err = impostor.connect(2, endpoint_of(&receiver)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TransportError>(),
+            Some(&TransportError::AuthenticationFailed)
+        );
+        assert!(impostor.peers.read().is_empty());
+
+        // Nor is a node that can't authenticate itself trusted
+        let unauthenticated = authenticated(4, None);
+        let mut sender = authenticated(1, Some(7));
+        assert!(sender.connect(4, endpoint_of(&unauthenticated)).is_err());
+    }
+
     #[test]
     fn test_memory_registration() {
         let transport = TcpTransport::new(1).unwrap();
@@ -0,0 +1,87 @@
+//! TLS between TCP transport peers
+//!
+//! Pages cross the network holding guest RAM, which may contain secrets.
+//! With a `TlsConfig` in its `TcpTransportConfig`, the TCP transport runs
+//! every connection over TLS. Messages and their length-prefixed framing are
+//! unchanged; only the byte stream under them is encrypted.
+//!
+//! A node presents the same certificate when serving and when connecting,
+//! and accepts peers whose certificates chain to the configured CA, so both
+//! ends of a connection are authenticated. Peers are connected to by IP
+//! address, which their certificates must name.
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Certificates and keys for TLS between peers
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Load this node's certificate chain and private key, and the CA
+    /// certificates peers must chain to, from PEM files
+    pub fn from_pem_files(cert: &Path, key: &Path, ca: &Path) -> Result<Self> {
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+        if certs.is_empty() {
+            bail!("No certificates in {}", cert.display());
+        }
+        let key = PrivateKeyDer::from_pem_file(key)
+            .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+
+        let mut roots = RootCertStore::empty();
+        for ca_cert in CertificateDer::pem_file_iter(ca)
+            .with_context(|| format!("Failed to read CA certificates from {}", ca.display()))?
+        {
+            let ca_cert = ca_cert
+                .with_context(|| format!("Failed to read CA certificates from {}", ca.display()))?;
+            roots.add(ca_cert).context("Invalid CA certificate")?;
+        }
+        if roots.is_empty() {
+            bail!("No CA certificates in {}", ca.display());
+        }
+        let roots = Arc::new(roots);
+
+        let provider = Arc::new(ring::default_provider());
+        let client_verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::clone(&roots), Arc::clone(&provider))
+                .build()
+                .context("Failed to configure peer certificate checks")?;
+        let server = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(client_verifier)
+            .with_single_cert(certs.clone(), key.clone_key())
+            .context("Failed to configure TLS server")?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .context("Failed to configure TLS client")?;
+
+        Ok(Self {
+            server: Arc::new(server),
+            client: Arc::new(client),
+        })
+    }
+
+    /// Wraps accepted connections
+    pub(super) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(Arc::clone(&self.server))
+    }
+
+    /// Wraps connections to peers
+    pub(super) fn connector(&self) -> TlsConnector {
+        TlsConnector::from(Arc::clone(&self.client))
+    }
+}
@@ -0,0 +1,112 @@
+//! Typed addresses for the VMM's address spaces
+//!
+//! Guest physical addresses (what the guest and KVM slots see) and host
+//! virtual addresses (where the VMM has guest memory mapped) are both plain
+//! 64-bit numbers, which makes them easy to mix up. Keeping them in distinct
+//! types lets the compiler catch it; `GuestMemoryExt` converts between them.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+/// Guest physical address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GuestPhysAddr(pub u64);
+
+impl From<GuestAddress> for GuestPhysAddr {
+    fn from(addr: GuestAddress) -> Self {
+        Self(addr.raw_value())
+    }
+}
+
+impl fmt::Display for GuestPhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GPA 0x{:x}", self.0)
+    }
+}
+
+/// Host virtual address in the VMM process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HostVirtAddr(pub u64);
+
+impl HostVirtAddr {
+    /// Pointer for APIs that take raw host memory (KVM, userfaultfd)
+    pub fn as_mut_ptr(self) -> *mut u8 {
+        self.0 as *mut u8
+    }
+}
+
+impl fmt::Display for HostVirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HVA 0x{:x}", self.0)
+    }
+}
+
+/// Conversions between guest physical and host virtual addresses
+pub trait GuestMemoryExt {
+    /// Host address backing `gpa`
+    fn gpa_to_hva(&self, gpa: GuestPhysAddr) -> Result<HostVirtAddr>;
+
+    /// Guest physical address mapped at `hva`
+    #[allow(dead_code)] // For mapping pager (HVA) faults back to guest addresses
+    fn hva_to_gpa(&self, hva: HostVirtAddr) -> Result<GuestPhysAddr>;
+}
+
+impl GuestMemoryExt for GuestMemoryMmap<()> {
+    fn gpa_to_hva(&self, gpa: GuestPhysAddr) -> Result<HostVirtAddr> {
+        self.iter()
+            .find_map(|region| {
+                let start = region.start_addr().raw_value();
+                let offset = gpa.0.checked_sub(start)?;
+                (offset < region.len()).then(|| HostVirtAddr(region.as_ptr() as u64 + offset))
+            })
+            .ok_or_else(|| anyhow!("{} is not backed by guest memory", gpa))
+    }
+
+    fn hva_to_gpa(&self, hva: HostVirtAddr) -> Result<GuestPhysAddr> {
+        self.iter()
+            .find_map(|region| {
+                let offset = hva.0.checked_sub(region.as_ptr() as u64)?;
+                (offset < region.len())
+                    .then(|| GuestPhysAddr(region.start_addr().raw_value() + offset))
+            })
+            .ok_or_else(|| anyhow!("{} is not inside guest memory", hva))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guest_memory() -> GuestMemoryMmap<()> {
+        GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 1 << 20),
+            (GuestAddress(0x1000_0000), 1 << 20),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gpa_hva_round_trip() {
+        let mem = guest_memory();
+
+        for gpa in [GuestPhysAddr(0x1234), GuestPhysAddr(0x1000_5678)] {
+            let hva = mem.gpa_to_hva(gpa).unwrap();
+            assert_eq!(mem.hva_to_gpa(hva).unwrap(), gpa);
+        }
+
+        // Offsets within a region are preserved on the host side
+        let base = mem.gpa_to_hva(GuestPhysAddr(0x1000_0000)).unwrap();
+        let hva = mem.gpa_to_hva(GuestPhysAddr(0x1000_5678)).unwrap();
+        assert_eq!(hva.0 - base.0, 0x5678);
+    }
+
+    #[test]
+    fn test_unmapped_addresses_rejected() {
+        let mem = guest_memory();
+
+        // Hole between the two regions
+        assert!(mem.gpa_to_hva(GuestPhysAddr(0x0800_0000)).is_err());
+        assert!(mem.hva_to_gpa(HostVirtAddr(0)).is_err());
+    }
+}
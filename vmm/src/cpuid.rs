@@ -0,0 +1,269 @@
+//! Guest CPU topology through CPUID
+//!
+//! The guest kernel learns how CPUs are grouped from CPUID leaf 0x0B
+//! (Extended Topology) and matches APIC IDs against the ACPI SRAT to put
+//! CPUs in NUMA nodes. `CpuidTopologyEmulator` presents each cluster node as
+//! a package whose cores are the node's vCPUs, with one thread per core:
+//!
+//! ```text
+//! APIC ID = node_id << core_shift | (cpu - cpu_start)
+//! ```
+//!
+//! where `core_shift` is wide enough for the node with the most CPUs. vCPUs
+//! must be created with their APIC ID as KVM vCPU ID, since KVM derives the
+//! local APIC ID from it.
+
+use anyhow::{anyhow, Result};
+use kvm_bindings::{kvm_cpuid_entry2, CpuId, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+use serde::Deserialize;
+
+/// Extended Topology leaf
+const TOPOLOGY_LEAF: u32 = 0x0b;
+
+/// Level types in leaf 0x0B ECX[15:8]
+const LEVEL_INVALID: u32 = 0;
+const LEVEL_SMT: u32 = 1;
+const LEVEL_CORE: u32 = 2;
+
+/// Guest CPUs by NUMA node, as in the topology file given to acpi-gen
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClusterTopology {
+    pub nodes: Vec<NodeConfig>,
+}
+
+/// CPUs of one NUMA node; other fields of the topology file are ignored
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeConfig {
+    pub node_id: u32,
+    /// First guest CPU on this node
+    pub cpu_start: u32,
+    pub cpu_count: u32,
+}
+
+/// Rewrites vCPU CPUID so the guest sees the cluster's NUMA nodes as packages
+#[derive(Debug, Clone)]
+pub struct CpuidTopologyEmulator {
+    nodes: Vec<NodeConfig>,
+    /// APIC ID bits below the package ID
+    core_shift: u32,
+}
+
+impl CpuidTopologyEmulator {
+    pub fn new(topology: &ClusterTopology) -> Result<Self> {
+        let mut nodes = topology.nodes.clone();
+        if nodes.is_empty() {
+            return Err(anyhow!("Topology has no nodes"));
+        }
+        nodes.sort_by_key(|node| node.cpu_start);
+        for node in &nodes {
+            if node.cpu_count == 0 {
+                return Err(anyhow!("Node {} has no CPUs", node.node_id));
+            }
+        }
+        for pair in nodes.windows(2) {
+            if pair[0].cpu_start + pair[0].cpu_count > pair[1].cpu_start {
+                return Err(anyhow!(
+                    "CPUs of nodes {} and {} overlap",
+                    pair[0].node_id,
+                    pair[1].node_id
+                ));
+            }
+        }
+
+        let widest = nodes.iter().map(|node| node.cpu_count).max().unwrap_or(1);
+        Ok(Self {
+            nodes,
+            core_shift: widest.next_power_of_two().trailing_zeros(),
+        })
+    }
+
+    /// Node guest CPU `cpu` is on
+    fn node_of(&self, cpu: u32) -> Result<&NodeConfig> {
+        self.nodes
+            .iter()
+            .find(|node| (node.cpu_start..node.cpu_start + node.cpu_count).contains(&cpu))
+            .ok_or_else(|| anyhow!("CPU {} is not on any node", cpu))
+    }
+
+    /// APIC ID of guest CPU `cpu`
+    pub fn apic_id(&self, cpu: u32) -> Result<u32> {
+        let node = self.node_of(cpu)?;
+        Ok(node.node_id << self.core_shift | (cpu - node.cpu_start))
+    }
+
+    /// Make `cpuid` describe guest CPU `cpu`'s place in the topology
+    pub fn apply(&self, cpu: u32, cpuid: &mut CpuId) -> Result<()> {
+        let node = self.node_of(cpu)?;
+        let apic_id = self.apic_id(cpu)?;
+
+        for entry in cpuid.as_mut_slice() {
+            match entry.function {
+                // The topology leaf must be in the basic range
+                0 => entry.eax = entry.eax.max(TOPOLOGY_LEAF),
+                1 => {
+                    // EBX[31:24] initial APIC ID, EBX[23:16] IDs per package
+                    let per_package = (1u32 << self.core_shift).min(0xff);
+                    entry.ebx = (entry.ebx & 0xffff) | per_package << 16 | (apic_id & 0xff) << 24;
+                }
+                _ => {}
+            }
+        }
+
+        // (shift to the next level's ID, logical CPUs at this level, type)
+        let levels = [
+            (0, 1, LEVEL_SMT),
+            (self.core_shift, node.cpu_count, LEVEL_CORE),
+            (0, 0, LEVEL_INVALID),
+        ];
+        cpuid.retain(|entry| entry.function != TOPOLOGY_LEAF);
+        for (index, (shift, count, level)) in levels.into_iter().enumerate() {
+            cpuid
+                .push(kvm_cpuid_entry2 {
+                    function: TOPOLOGY_LEAF,
+                    index: index as u32,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    eax: shift,
+                    ebx: count,
+                    ecx: level << 8 | index as u32,
+                    edx: apic_id,
+                    ..Default::default()
+                })
+                .map_err(|e| anyhow!("Failed to add CPUID topology entry: {:?}", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvm_ioctls::{Kvm, VcpuExit};
+
+    fn topology() -> ClusterTopology {
+        serde_json::from_str(
+            r#"{"nodes": [
+                {"node_id": 0, "cpu_start": 0, "cpu_count": 3, "mem_size": 1073741824},
+                {"node_id": 1, "cpu_start": 3, "cpu_count": 2, "mem_size": 1073741824}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apic_ids_group_cpus_by_node() {
+        let emulator = CpuidTopologyEmulator::new(&topology()).unwrap();
+        let ids: Vec<u32> = (0..5).map(|cpu| emulator.apic_id(cpu).unwrap()).collect();
+        // Three CPUs on node 0 need two core bits
+        assert_eq!(ids, [0, 1, 2, 4, 5]);
+        assert!(emulator.apic_id(5).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_nodes_rejected() {
+        let mut topology = topology();
+        topology.nodes[1].cpu_start = 2;
+        assert!(CpuidTopologyEmulator::new(&topology).is_err());
+    }
+
+    /// EDX of CPUID leaf 0x0B subleaf 0 as a guest on CPU `cpu` sees it;
+    /// None without a usable /dev/kvm
+    fn guest_x2apic_id(emulator: &CpuidTopologyEmulator, cpu: u32) -> Option<u32> {
+        const MEM_SIZE: usize = 0x10000;
+        const CODE_GPA: u64 = 0x1000;
+        const DATA_GPA: usize = 0x2000;
+
+        let kvm = Kvm::new().ok()?;
+        let vm = kvm.create_vm().ok()?;
+        let hva = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                MEM_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(hva, libc::MAP_FAILED);
+        // mov eax, 0xb; xor ecx, ecx; cpuid; mov [0x2000], edx; hlt
+        let code = [
+            0x66, 0xb8, 0x0b, 0x00, 0x00, 0x00, 0x66, 0x31, 0xc9, 0x0f, 0xa2, 0x66, 0x89, 0x16,
+            0x00, 0x20, 0xf4,
+        ];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                code.as_ptr(),
+                (hva as *mut u8).add(CODE_GPA as usize),
+                code.len(),
+            );
+            vm.set_user_memory_region(kvm_bindings::kvm_userspace_memory_region {
+                slot: 0,
+                guest_phys_addr: 0,
+                memory_size: MEM_SIZE as u64,
+                userspace_addr: hva as u64,
+                flags: 0,
+            })
+            .unwrap();
+        }
+
+        let mut vcpu = vm
+            .create_vcpu(emulator.apic_id(cpu).unwrap() as u64)
+            .unwrap();
+        let mut cpuid = kvm.get_supported_cpuid(256).unwrap();
+        emulator.apply(cpu, &mut cpuid).unwrap();
+        vcpu.set_cpuid2(&cpuid).unwrap();
+
+        let mut sregs = vcpu.get_sregs().unwrap();
+        sregs.cs.base = 0;
+        sregs.cs.selector = 0;
+        vcpu.set_sregs(&sregs).unwrap();
+        let mut regs = vcpu.get_regs().unwrap();
+        regs.rip = CODE_GPA;
+        regs.rflags = 0x2;
+        vcpu.set_regs(&regs).unwrap();
+        match vcpu.run().unwrap() {
+            VcpuExit::Hlt => {}
+            exit => panic!("Unexpected vCPU exit: {:?}", exit),
+        }
+
+        let edx =
+            unsafe { std::ptr::read_unaligned((hva as *const u8).add(DATA_GPA) as *const u32) };
+        unsafe { libc::munmap(hva, MEM_SIZE) };
+        Some(edx)
+    }
+
+    #[test]
+    fn test_guest_cpuid_reports_topology_apic_ids() {
+        let emulator = CpuidTopologyEmulator::new(&topology()).unwrap();
+        for (cpu, expected) in [(0, 0), (2, 2), (3, 4), (4, 5)] {
+            // Needs /dev/kvm
+            let Some(apic_id) = guest_x2apic_id(&emulator, cpu) else {
+                return;
+            };
+            assert_eq!(apic_id, expected, "APIC ID of CPU {}", cpu);
+        }
+    }
+
+    #[test]
+    fn test_topology_levels() {
+        let Ok(kvm) = Kvm::new() else {
+            return;
+        };
+        let emulator = CpuidTopologyEmulator::new(&topology()).unwrap();
+        let mut cpuid = kvm.get_supported_cpuid(256).unwrap();
+        emulator.apply(4, &mut cpuid).unwrap();
+
+        let leaf = |index: u32| {
+            *cpuid
+                .as_slice()
+                .iter()
+                .find(|entry| entry.function == TOPOLOGY_LEAF && entry.index == index)
+                .unwrap()
+        };
+        let (smt, core, end) = (leaf(0), leaf(1), leaf(2));
+        assert_eq!((smt.eax, smt.ebx, smt.ecx), (0, 1, LEVEL_SMT << 8));
+        assert_eq!((core.eax, core.ebx, core.ecx), (2, 2, LEVEL_CORE << 8 | 1));
+        assert_eq!(end.ecx >> 8 & 0xff, LEVEL_INVALID);
+        assert!([smt, core, end].iter().all(|entry| entry.edx == 5));
+    }
+}
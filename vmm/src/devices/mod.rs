@@ -0,0 +1,85 @@
+//! Port I/O and MMIO that vCPUs exit to the VMM for
+//!
+//! `VcpuManager::run` passes each `KVM_EXIT_IO` and `KVM_EXIT_MMIO` to a
+//! `DeviceManager`, which all of the VM's vCPU threads share.
+
+mod serial;
+
+pub use serial::SerialDevice;
+
+/// Emulates the devices behind port I/O and MMIO exits
+///
+/// Values are little-endian and `len` bytes wide (1, 2 or 4 for ports, up
+/// to 8 for MMIO); only the low `len` bytes of a read are used.
+pub trait DeviceManager: Send {
+    fn handle_io_in(&mut self, port: u16) -> u32;
+    fn handle_io_out(&mut self, port: u16, data: u32, len: usize);
+    fn handle_mmio_read(&mut self, addr: u64, len: usize) -> u64;
+    fn handle_mmio_write(&mut self, addr: u64, data: u64, len: usize);
+}
+
+/// The devices every guest gets: the COM1 console on stdout
+///
+/// Reads from anything else see a floating bus (all ones) and writes are
+/// dropped, as on hardware with nothing decoding the address.
+#[derive(Default)]
+pub struct LegacyDevices {
+    serial: SerialDevice,
+}
+
+impl LegacyDevices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(dead_code)] // For tests and diagnostics
+    pub fn serial(&self) -> &SerialDevice {
+        &self.serial
+    }
+}
+
+fn is_com1(port: u16) -> bool {
+    (serial::COM1_BASE..serial::COM1_BASE + serial::COM1_PORTS).contains(&port)
+}
+
+impl DeviceManager for LegacyDevices {
+    fn handle_io_in(&mut self, port: u16) -> u32 {
+        if is_com1(port) {
+            return self.serial.handle_io_in(port);
+        }
+        u32::MAX
+    }
+
+    fn handle_io_out(&mut self, port: u16, data: u32, len: usize) {
+        if is_com1(port) {
+            self.serial.handle_io_out(port, data, len);
+        }
+    }
+
+    fn handle_mmio_read(&mut self, _addr: u64, _len: usize) -> u64 {
+        u64::MAX
+    }
+
+    fn handle_mmio_write(&mut self, _addr: u64, _data: u64, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_devices_console_and_floating_bus() {
+        let mut devices = LegacyDevices {
+            serial: SerialDevice::with_output(Box::new(std::io::sink())),
+        };
+        // COM1's line status: ready to transmit
+        assert_eq!(devices.handle_io_in(0x3fd) & 0x60, 0x60);
+        assert_eq!(devices.handle_io_in(0x80), u32::MAX);
+        assert_eq!(devices.handle_mmio_read(0xfed0_0000, 4), u64::MAX);
+
+        devices.handle_io_out(0x80, 0x12, 1);
+        assert!(devices.serial().output_bytes().is_empty());
+        devices.handle_io_out(0x3f8, u32::from(b'\n'), 1);
+        assert_eq!(devices.serial().output_bytes(), b"\n");
+    }
+}
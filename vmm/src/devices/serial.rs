@@ -0,0 +1,230 @@
+//! 8250/16550 UART on COM1
+//!
+//! Enough of the register map for a guest kernel's early console and
+//! 8250 driver to write to it: transmitted bytes go to stdout a line at a
+//! time, the transmitter is always empty and no interrupts are raised.
+//! Nothing is ever received.
+
+use super::DeviceManager;
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// COM1's I/O ports
+pub const COM1_BASE: u16 = 0x3f8;
+pub const COM1_PORTS: u16 = 8;
+
+/// Register offsets from the base port
+const THR: u16 = 0; // RBR on reads
+const IER: u16 = 1;
+const IIR: u16 = 2; // FCR on writes
+const LCR: u16 = 3;
+const MCR: u16 = 4;
+const LSR: u16 = 5;
+const MSR: u16 = 6;
+const SCR: u16 = 7;
+
+/// IIR: no interrupt pending
+const IIR_NO_INTERRUPT: u8 = 0x01;
+/// LCR: offsets 0 and 1 are the divisor latch
+const LCR_DLAB: u8 = 0x80;
+/// LSR: transmitter holding register and shift register empty
+const LSR_THR_EMPTY: u8 = 0x60;
+/// MSR: clear to send, data set ready and carrier detect
+const MSR_CONNECTED: u8 = 0xb0;
+
+/// A line longer than this goes to stdout without waiting for its newline
+const MAX_LINE: usize = 4096;
+/// How much recent output `output_bytes` keeps
+const OUTPUT_HISTORY: usize = 64 << 10;
+
+/// COM1, printing what the guest transmits
+pub struct SerialDevice {
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    /// Divisor latch, low and high bytes
+    divisor: [u8; 2],
+    /// Transmitted bytes since the last newline
+    line: Vec<u8>,
+    /// The most recent transmitted bytes
+    output: VecDeque<u8>,
+    out: Box<dyn Write + Send>,
+}
+
+impl SerialDevice {
+    /// Print to stdout
+    pub fn new() -> Self {
+        Self::with_output(Box::new(std::io::stdout()))
+    }
+
+    pub fn with_output(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            ier: 0,
+            // 8 data bits, no parity, 1 stop bit
+            lcr: 0x03,
+            mcr: 0,
+            scr: 0,
+            // 115200 baud
+            divisor: [1, 0],
+            line: Vec::new(),
+            output: VecDeque::new(),
+            out,
+        }
+    }
+
+    /// What the guest has transmitted, up to the last 64 KiB
+    #[allow(dead_code)] // For tests and diagnostics
+    pub fn output_bytes(&self) -> Vec<u8> {
+        self.output.iter().copied().collect()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.output.len() == OUTPUT_HISTORY {
+            self.output.pop_front();
+        }
+        self.output.push_back(byte);
+        self.line.push(byte);
+        if byte == b'\n' || self.line.len() >= MAX_LINE {
+            self.flush_line();
+        }
+    }
+
+    fn flush_line(&mut self) {
+        // The guest cannot be told the console failed; drop the line
+        let _ = self.out.write_all(&self.line);
+        let _ = self.out.flush();
+        self.line.clear();
+    }
+
+    fn read(&self, offset: u16) -> u8 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            THR if dlab => self.divisor[0],
+            IER if dlab => self.divisor[1],
+            // Nothing is ever received
+            THR => 0,
+            IER => self.ier,
+            IIR => IIR_NO_INTERRUPT,
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => LSR_THR_EMPTY,
+            MSR => MSR_CONNECTED,
+            SCR => self.scr,
+            _ => 0xff,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            THR if dlab => self.divisor[0] = value,
+            IER if dlab => self.divisor[1] = value,
+            THR => self.transmit(value),
+            // Only the 4 interrupt enables exist
+            IER => self.ier = value & 0x0f,
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1f,
+            SCR => self.scr = value,
+            // FIFO control and the read-only status registers
+            _ => {}
+        }
+    }
+}
+
+impl Default for SerialDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SerialDevice {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.flush_line();
+        }
+    }
+}
+
+impl DeviceManager for SerialDevice {
+    fn handle_io_in(&mut self, port: u16) -> u32 {
+        match port.checked_sub(COM1_BASE) {
+            Some(offset) if offset < COM1_PORTS => u32::from(self.read(offset)),
+            _ => u32::MAX,
+        }
+    }
+
+    fn handle_io_out(&mut self, port: u16, data: u32, _len: usize) {
+        if let Some(offset) = port.checked_sub(COM1_BASE).filter(|&o| o < COM1_PORTS) {
+            self.write(offset, data as u8);
+        }
+    }
+
+    fn handle_mmio_read(&mut self, _addr: u64, _len: usize) -> u64 {
+        u64::MAX
+    }
+
+    fn handle_mmio_write(&mut self, _addr: u64, _data: u64, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /// Collects what the device writes out
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_serial_output_flushed_per_line() {
+        let sink = Sink::default();
+        let mut serial = SerialDevice::with_output(Box::new(sink.clone()));
+        for &byte in b"Linux version" {
+            serial.handle_io_out(COM1_BASE + THR, u32::from(byte), 1);
+        }
+        assert!(sink.0.lock().is_empty());
+        serial.handle_io_out(COM1_BASE + THR, u32::from(b'\n'), 1);
+
+        assert_eq!(serial.output_bytes(), b"Linux version\n");
+        assert_eq!(&*sink.0.lock(), b"Linux version\n");
+    }
+
+    #[test]
+    fn test_serial_registers() {
+        let mut serial = SerialDevice::with_output(Box::new(Sink::default()));
+        let read = |serial: &mut SerialDevice, offset| serial.handle_io_in(COM1_BASE + offset);
+        assert_eq!(read(&mut serial, IIR), u32::from(IIR_NO_INTERRUPT));
+        assert_eq!(read(&mut serial, LSR), u32::from(LSR_THR_EMPTY));
+
+        serial.handle_io_out(COM1_BASE + LCR, 0x03, 1);
+        serial.handle_io_out(COM1_BASE + IER, 0x01, 1);
+        serial.handle_io_out(COM1_BASE + SCR, 0x5a, 1);
+        assert_eq!(read(&mut serial, LCR), 0x03);
+        assert_eq!(read(&mut serial, IER), 0x01);
+        assert_eq!(read(&mut serial, SCR), 0x5a);
+
+        // With DLAB set, offsets 0 and 1 are the divisor and nothing is sent
+        serial.handle_io_out(COM1_BASE + LCR, u32::from(LCR_DLAB | 0x03), 1);
+        serial.handle_io_out(COM1_BASE + THR, 0x0c, 1);
+        serial.handle_io_out(COM1_BASE + IER, 0x00, 1);
+        assert_eq!(read(&mut serial, THR), 0x0c);
+        serial.handle_io_out(COM1_BASE + LCR, 0x03, 1);
+        assert_eq!(read(&mut serial, IER), 0x01);
+        assert!(serial.output_bytes().is_empty());
+
+        assert_eq!(serial.handle_io_in(0x2f8), u32::MAX);
+    }
+}
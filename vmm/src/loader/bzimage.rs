@@ -0,0 +1,374 @@
+//! Linux bzImage loading, per the x86 boot protocol
+//!
+//! (Documentation/arch/x86/boot.rst.) A bzImage is a real-mode setup
+//! program, whose first sector holds the setup header, followed by the
+//! protected-mode kernel. The setup code goes to `SETUP_GPA`, the kernel to
+//! `KERNEL_GPA`, and the zero page (`boot_params`) the kernel reads at entry
+//! is built at `BOOT_PARAMS_GPA` from the image's setup header.
+//! `write_boot_params` then adds what the VMM knows: the command line and
+//! the E820 map of guest memory.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use vm_memory::{Address, Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
+
+/// Where the real-mode setup code is loaded
+pub const SETUP_GPA: u64 = 0x1_0000;
+/// Where the protected-mode kernel is loaded, and its 32-bit entry point
+pub const KERNEL_GPA: u64 = 0x10_0000;
+/// Where the zero page is built
+pub const BOOT_PARAMS_GPA: u64 = 0x7000;
+pub const BOOT_PARAMS_LEN: usize = 4096;
+/// Where the kernel command line is put
+pub const CMDLINE_GPA: u64 = 0x2_0000;
+/// Longest command line, NUL included, older kernels accept
+pub const CMDLINE_MAX: usize = 2048;
+
+const SECTOR: usize = 512;
+/// `setup_sects` of 0 means 4, for very old images
+const DEFAULT_SETUP_SECTS: usize = 4;
+
+/// Setup header fields, at the same offsets in the image and the zero page
+const E820_ENTRIES: usize = 0x1e8;
+const SETUP_HEADER: usize = 0x1f1;
+const SETUP_SECTS: usize = 0x1f1;
+const VID_MODE: usize = 0x1fa;
+const BOOT_FLAG: usize = 0x1fe;
+/// Offset, from 0x202, of the end of the header
+const HEADER_JUMP: usize = 0x201;
+const HEADER_MAGIC: usize = 0x202;
+const TYPE_OF_LOADER: usize = 0x210;
+const LOADFLAGS: usize = 0x211;
+const CODE32_START: usize = 0x214;
+const EXT_LOADER_TYPE: usize = 0x227;
+const CMD_LINE_PTR: usize = 0x228;
+/// Where the zero page's copy of the header must end
+const SETUP_HEADER_MAX_END: usize = 0x290;
+/// `e820_table`: up to 128 entries of 20 bytes
+const E820_TABLE: usize = 0x2d0;
+const E820_MAX_ENTRIES: usize = 128;
+const E820_ENTRY_LEN: usize = 20;
+
+/// E820 range types
+pub const E820_RAM: u32 = 1;
+pub const E820_RESERVED: u32 = 2;
+
+/// End of conventional memory, less the EBDA the kernel expects below it
+const LOW_RAM_END: u64 = 0x9_fc00;
+/// The VGA and BIOS ROM hole
+const LEGACY_HOLE: u64 = 0xa_0000;
+const HIGH_RAM_START: u64 = 0x10_0000;
+
+const BOOT_FLAG_MAGIC: u16 = 0xaa55;
+const HDRS: &[u8; 4] = b"HdrS";
+/// `vid_mode`: normal VGA text mode
+const VID_MODE_NORMAL: u16 = 0xffff;
+/// `type_of_loader`: a boot loader without an assigned ID
+const LOADER_UNDEFINED: u8 = 0xff;
+/// `loadflags`: the protected-mode kernel is loaded at 1 MiB
+const LOADED_HIGH: u8 = 0x01;
+
+/// Load the bzImage at `path` into `guest_mem`, returning the kernel's
+/// 32-bit entry point
+pub fn load_bzimage(guest_mem: &GuestMemoryMmap, path: &Path) -> Result<GuestAddress> {
+    let image =
+        std::fs::read(path).with_context(|| format!("Failed to read kernel {}", path.display()))?;
+    load_bzimage_bytes(guest_mem, &image)
+        .with_context(|| format!("Failed to load kernel {}", path.display()))
+}
+
+fn load_bzimage_bytes(guest_mem: &GuestMemoryMmap, image: &[u8]) -> Result<GuestAddress> {
+    if image.len() < SECTOR
+        || u16::from_le_bytes([image[BOOT_FLAG], image[BOOT_FLAG + 1]]) != BOOT_FLAG_MAGIC
+    {
+        return Err(anyhow!("Not a bzImage: no 0xAA55 boot flag"));
+    }
+    if image.get(HEADER_MAGIC..HEADER_MAGIC + 4) != Some(HDRS) {
+        return Err(anyhow!("Not a bzImage: no HdrS setup header"));
+    }
+
+    let setup_sects = match image[SETUP_SECTS] as usize {
+        0 => DEFAULT_SETUP_SECTS,
+        sects => sects,
+    };
+    // The boot sector is not counted in setup_sects
+    let setup_len = (setup_sects + 1) * SECTOR;
+    if image.len() <= setup_len {
+        return Err(anyhow!(
+            "bzImage of {} bytes ends within its {} setup bytes",
+            image.len(),
+            setup_len
+        ));
+    }
+    let (setup, kernel) = image.split_at(setup_len);
+
+    guest_mem
+        .write_slice(setup, GuestAddress(SETUP_GPA))
+        .context("Setup code does not fit in guest memory")?;
+    guest_mem
+        .write_slice(kernel, GuestAddress(KERNEL_GPA))
+        .context("Kernel does not fit in guest memory")?;
+
+    let mut boot_params = [0u8; BOOT_PARAMS_LEN];
+    let header_end = (HEADER_MAGIC + image[HEADER_JUMP] as usize).min(SETUP_HEADER_MAX_END);
+    boot_params[SETUP_HEADER..header_end].copy_from_slice(&image[SETUP_HEADER..header_end]);
+    boot_params[VID_MODE..VID_MODE + 2].copy_from_slice(&VID_MODE_NORMAL.to_le_bytes());
+    boot_params[TYPE_OF_LOADER] = LOADER_UNDEFINED;
+    boot_params[LOADFLAGS] |= LOADED_HIGH;
+    boot_params[EXT_LOADER_TYPE] = 0;
+    guest_mem
+        .write_slice(&boot_params, GuestAddress(BOOT_PARAMS_GPA))
+        .context("boot_params do not fit in guest memory")?;
+
+    Ok(GuestAddress(KERNEL_GPA))
+}
+
+/// One range of the E820 memory map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootE820Entry {
+    pub addr: u64,
+    pub size: u64,
+    pub kind: u32,
+}
+
+impl BootE820Entry {
+    fn to_bytes(self) -> [u8; E820_ENTRY_LEN] {
+        let mut bytes = [0; E820_ENTRY_LEN];
+        bytes[..8].copy_from_slice(&self.addr.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.size.to_le_bytes());
+        bytes[16..].copy_from_slice(&self.kind.to_le_bytes());
+        bytes
+    }
+}
+
+/// E820 map of `mem_size` bytes of guest memory from address 0
+///
+/// The legacy hole at 640 KiB-1 MiB is reserved even though guest memory
+/// backs it, as firmware would report it.
+pub fn build_e820_map(mem_size: usize) -> Vec<BootE820Entry> {
+    let mem_end = mem_size as u64;
+    let mut map = vec![BootE820Entry {
+        addr: 0,
+        size: LOW_RAM_END.min(mem_end),
+        kind: E820_RAM,
+    }];
+    if mem_end > LEGACY_HOLE {
+        map.push(BootE820Entry {
+            addr: LEGACY_HOLE,
+            size: HIGH_RAM_START - LEGACY_HOLE,
+            kind: E820_RESERVED,
+        });
+    }
+    if mem_end > HIGH_RAM_START {
+        map.push(BootE820Entry {
+            addr: HIGH_RAM_START,
+            size: mem_end - HIGH_RAM_START,
+            kind: E820_RAM,
+        });
+    }
+    map
+}
+
+/// Put `cmdline` at `CMDLINE_GPA`, NUL-terminated, returning its address
+pub fn load_cmdline(guest_mem: &GuestMemoryMmap, cmdline: &str) -> Result<u64> {
+    if cmdline.len() >= CMDLINE_MAX || cmdline.contains('\0') {
+        return Err(anyhow!(
+            "Kernel command line must be under {} bytes without NULs",
+            CMDLINE_MAX
+        ));
+    }
+    let mut bytes = cmdline.as_bytes().to_vec();
+    bytes.push(0);
+    guest_mem
+        .write_slice(&bytes, GuestAddress(CMDLINE_GPA))
+        .context("Command line does not fit in guest memory")?;
+    Ok(CMDLINE_GPA)
+}
+
+/// Complete the zero page `load_bzimage` built at `params_gpa` with the
+/// kernel's entry, the command line at `cmdline_gpa` and the E820 map of
+/// `guest_mem`
+pub fn write_boot_params(
+    guest_mem: &GuestMemoryMmap,
+    params_gpa: u64,
+    kernel_entry: GuestAddress,
+    cmdline_gpa: u64,
+) -> Result<()> {
+    let params = GuestAddress(params_gpa);
+    let mut boot_params = [0u8; BOOT_PARAMS_LEN];
+    guest_mem
+        .read_slice(&mut boot_params, params)
+        .context("boot_params are not in guest memory")?;
+
+    let code32_start = u32::try_from(kernel_entry.raw_value()).map_err(|_| {
+        anyhow!(
+            "Kernel entry {:#x} is above 4 GiB",
+            kernel_entry.raw_value()
+        )
+    })?;
+    let cmd_line_ptr = u32::try_from(cmdline_gpa)
+        .map_err(|_| anyhow!("Command line at {:#x} is above 4 GiB", cmdline_gpa))?;
+    boot_params[CODE32_START..CODE32_START + 4].copy_from_slice(&code32_start.to_le_bytes());
+    boot_params[CMD_LINE_PTR..CMD_LINE_PTR + 4].copy_from_slice(&cmd_line_ptr.to_le_bytes());
+
+    let mem_size = guest_mem.last_addr().raw_value() as usize + 1;
+    let map = build_e820_map(mem_size);
+    if map.len() > E820_MAX_ENTRIES {
+        return Err(anyhow!(
+            "{} E820 entries do not fit in boot_params",
+            map.len()
+        ));
+    }
+    for (i, entry) in map.iter().enumerate() {
+        let offset = E820_TABLE + i * E820_ENTRY_LEN;
+        boot_params[offset..offset + E820_ENTRY_LEN].copy_from_slice(&entry.to_bytes());
+    }
+    boot_params[E820_ENTRIES] = map.len() as u8;
+
+    guest_mem
+        .write_slice(&boot_params, params)
+        .context("Failed to write boot_params")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bzImage with one setup sector after the boot sector and a 4 KiB
+    /// kernel, each filled with a distinct byte
+    fn synthetic_image() -> Vec<u8> {
+        let mut image = vec![0xb0; 2 * SECTOR];
+        image[SECTOR..].fill(0x5e);
+        image[SETUP_SECTS] = 1;
+        image[VID_MODE..VID_MODE + 2].copy_from_slice(&0u16.to_le_bytes());
+        image[BOOT_FLAG..BOOT_FLAG + 2].copy_from_slice(&BOOT_FLAG_MAGIC.to_le_bytes());
+        // jmp short over a header ending at 0x264, as boot protocol 2.15 has
+        image[0x200] = 0xeb;
+        image[HEADER_JUMP] = 0x62;
+        image[HEADER_MAGIC..HEADER_MAGIC + 4].copy_from_slice(HDRS);
+        image[TYPE_OF_LOADER] = 0;
+        image[LOADFLAGS] = 0;
+        image[EXT_LOADER_TYPE] = 0x12;
+        image.extend_from_slice(&[0x4b; 4096]);
+        image
+    }
+
+    #[test]
+    fn test_load_synthetic_bzimage() {
+        let guest_mem = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 4 << 20)]).unwrap();
+        let image = synthetic_image();
+        let path = std::env::temp_dir().join(format!("bzimage-test-{}", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+        let entry = load_bzimage(&guest_mem, &path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entry.unwrap(), GuestAddress(KERNEL_GPA));
+
+        let mut setup = vec![0; 2 * SECTOR];
+        guest_mem
+            .read_slice(&mut setup, GuestAddress(SETUP_GPA))
+            .unwrap();
+        assert_eq!(setup, image[..2 * SECTOR]);
+        let mut kernel = vec![0; 4096];
+        guest_mem
+            .read_slice(&mut kernel, GuestAddress(KERNEL_GPA))
+            .unwrap();
+        assert!(kernel.iter().all(|&byte| byte == 0x4b));
+
+        let mut boot_params = [0; BOOT_PARAMS_LEN];
+        guest_mem
+            .read_slice(&mut boot_params, GuestAddress(BOOT_PARAMS_GPA))
+            .unwrap();
+        assert_eq!(boot_params[SETUP_SECTS], 1);
+        assert_eq!(&boot_params[HEADER_MAGIC..HEADER_MAGIC + 4], HDRS);
+        assert_eq!(&boot_params[VID_MODE..VID_MODE + 2], &[0xff, 0xff]);
+        assert_eq!(boot_params[TYPE_OF_LOADER], 0xff);
+        assert_eq!(boot_params[LOADFLAGS], 0x01);
+        assert_eq!(boot_params[EXT_LOADER_TYPE], 0);
+        // Nothing outside the setup header comes from the image
+        assert!(boot_params[..SETUP_HEADER].iter().all(|&byte| byte == 0));
+        assert!(boot_params[0x264..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_e820_map_of_1gib() {
+        let map = build_e820_map(1 << 30);
+        assert_eq!(
+            map,
+            [
+                BootE820Entry {
+                    addr: 0,
+                    size: 0x9_fc00,
+                    kind: E820_RAM
+                },
+                BootE820Entry {
+                    addr: 0xa_0000,
+                    size: 0x6_0000,
+                    kind: E820_RESERVED
+                },
+                BootE820Entry {
+                    addr: 0x10_0000,
+                    size: (1 << 30) - 0x10_0000,
+                    kind: E820_RAM
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_boot_params_adds_cmdline_and_e820() {
+        let guest_mem = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 4 << 20)]).unwrap();
+        let entry = load_bzimage_bytes(&guest_mem, &synthetic_image()).unwrap();
+        let cmdline = load_cmdline(&guest_mem, "console=ttyS0").unwrap();
+        write_boot_params(&guest_mem, BOOT_PARAMS_GPA, entry, cmdline).unwrap();
+
+        let mut boot_params = [0; BOOT_PARAMS_LEN];
+        guest_mem
+            .read_slice(&mut boot_params, GuestAddress(BOOT_PARAMS_GPA))
+            .unwrap();
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(boot_params[offset..offset + 4].try_into().unwrap());
+        assert_eq!(read_u32(CODE32_START), KERNEL_GPA as u32);
+        assert_eq!(read_u32(CMD_LINE_PTR), CMDLINE_GPA as u32);
+        // What load_bzimage set is kept
+        assert_eq!(boot_params[TYPE_OF_LOADER], 0xff);
+
+        assert_eq!(boot_params[E820_ENTRIES], 3);
+        let high = &boot_params[E820_TABLE + 2 * E820_ENTRY_LEN..][..E820_ENTRY_LEN];
+        assert_eq!(
+            high,
+            BootE820Entry {
+                addr: HIGH_RAM_START,
+                size: (4 << 20) - HIGH_RAM_START,
+                kind: E820_RAM,
+            }
+            .to_bytes()
+        );
+
+        let mut stored = [0; 14];
+        guest_mem
+            .read_slice(&mut stored, GuestAddress(CMDLINE_GPA))
+            .unwrap();
+        assert_eq!(&stored, b"console=ttyS0\0");
+        assert!(load_cmdline(&guest_mem, &"x".repeat(CMDLINE_MAX)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_images_without_setup_header() {
+        let guest_mem = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 4 << 20)]).unwrap();
+        let mut image = synthetic_image();
+        image[HEADER_MAGIC] = b'X';
+        assert!(load_bzimage_bytes(&guest_mem, &image).is_err());
+
+        let mut image = synthetic_image();
+        image[BOOT_FLAG] = 0;
+        assert!(load_bzimage_bytes(&guest_mem, &image).is_err());
+
+        // Nothing after the setup code
+        let image = &synthetic_image()[..2 * SECTOR];
+        assert!(load_bzimage_bytes(&guest_mem, image).is_err());
+
+        // A kernel past the end of guest memory
+        let small = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 1 << 20)]).unwrap();
+        assert!(load_bzimage_bytes(&small, &synthetic_image()).is_err());
+    }
+}
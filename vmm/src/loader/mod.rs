@@ -0,0 +1,3 @@
+//! Loading guest software into guest memory
+
+pub mod bzimage;
@@ -1,12 +1,34 @@
-use anyhow::{Context, Result};
-use kvm_bindings::kvm_userspace_memory_region;
+use addr::{GuestMemoryExt, GuestPhysAddr, HostVirtAddr};
+use anyhow::{anyhow, Context, Result};
+use devices::LegacyDevices;
 use kvm_ioctls::{Kvm, VcpuFd, VmFd};
-use log::{info, warn};
+use log::info;
+use memslots::{MemorySlot, MemorySlots};
+use parking_lot::{Mutex, RwLock};
+use rdma_transport::TransportManager;
 use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
-use vm_memory::{Address, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
-
+use vcpu::{VcpuExitStats, VcpuGate, VcpuManager};
+use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion, GuestRegionMmap};
+
+mod addr;
+mod cpuid;
+mod devices;
+mod dirty;
+mod loader;
+mod memslots;
+mod migration;
+mod page_walk;
+#[cfg(feature = "sev")]
+mod sev;
+mod tsc;
 mod vcpu;
+mod vfio;
+
+/// Node whose host TSC all guest vCPUs are synchronized to
+const TSC_REFERENCE_NODE: u32 = 0;
 
 /// SSI-HV VMM Configuration
 #[derive(Debug)]
@@ -21,6 +43,21 @@ struct VmmConfig {
     total_nodes: u32,
     /// Coordinator URL (e.g., http://100.119.10.82:8000)
     coordinator_url: String,
+    /// Synchronize vCPU TSCs to the reference node
+    tsc_sync: bool,
+    /// Log guest writes for live migration (see `dirty`)
+    dirty_tracking: bool,
+    /// Show the guest its vCPUs grouped in these NUMA nodes (see `cpuid`)
+    topology: Option<cpuid::ClusterTopology>,
+    /// Encrypt guest memory with AMD SEV (single-node only)
+    #[cfg(feature = "sev")]
+    sev: Option<sev::SevConfig>,
+    /// PCI devices passed through to the guest
+    vfio_devices: Vec<vfio::VfioDeviceConfig>,
+    /// Linux bzImage to load into guest memory
+    kernel: Option<PathBuf>,
+    /// The kernel's command line
+    cmdline: String,
 }
 
 impl Default for VmmConfig {
@@ -31,6 +68,14 @@ impl Default for VmmConfig {
             node_id: 0,
             total_nodes: 1,
             coordinator_url: "http://127.0.0.1:8000".to_string(),
+            tsc_sync: false,
+            dirty_tracking: false,
+            topology: None,
+            #[cfg(feature = "sev")]
+            sev: None,
+            vfio_devices: Vec::new(),
+            kernel: None,
+            cmdline: "console=ttyS0".to_string(),
         }
     }
 }
@@ -41,6 +86,19 @@ struct SsiVmm {
     vm: VmFd,
     guest_memory: GuestMemoryMmap<()>,
     config: VmmConfig,
+    transport: Option<Arc<RwLock<TransportManager>>>,
+    /// What the pager reaches the coordinator on, for as long as it runs
+    pager_runtime: Option<tokio::runtime::Runtime>,
+    vcpus: Vec<VcpuFd>,
+    vcpu_gate: VcpuGate,
+    /// Running vCPUs, which own their `VcpuFd`s once started
+    vcpu_threads: Vec<thread::JoinHandle<Result<VcpuExitStats>>>,
+    memory_slots: MemorySlots,
+    dirty: Option<dirty::DirtyTracker>,
+    #[cfg(feature = "sev")]
+    sev: Option<sev::SevGuest>,
+    vfio_container: Option<vfio::VfioContainer>,
+    vfio_devices: Vec<vfio::VfioDevice>,
 }
 
 impl SsiVmm {
@@ -57,6 +115,25 @@ impl SsiVmm {
             config.total_nodes
         );
 
+        #[cfg(feature = "sev")]
+        let sev = match &config.sev {
+            Some(sev_config) => {
+                if !sev::supported(&vm) {
+                    return Err(anyhow!(
+                        "SEV requested but KVM on this host does not support it"
+                    ));
+                }
+                if config.total_nodes > 1 {
+                    return Err(anyhow!(
+                        "SEV cannot be used with the distributed pager ({} nodes)",
+                        config.total_nodes
+                    ));
+                }
+                Some(sev::SevGuest::launch(&vm, sev_config).context("Failed to launch SEV guest")?)
+            }
+            None => None,
+        };
+
         // Create guest memory
         let guest_memory = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), config.mem_size)])
             .context("Failed to create guest memory")?;
@@ -66,6 +143,17 @@ impl SsiVmm {
             vm,
             guest_memory,
             config,
+            transport: None,
+            pager_runtime: None,
+            vcpus: Vec::new(),
+            vcpu_gate: VcpuGate::default(),
+            vcpu_threads: Vec::new(),
+            memory_slots: MemorySlots::new(),
+            dirty: None,
+            #[cfg(feature = "sev")]
+            sev,
+            vfio_container: None,
+            vfio_devices: Vec::new(),
         })
     }
 
@@ -73,32 +161,115 @@ impl SsiVmm {
     fn setup_memory(&mut self) -> Result<()> {
         info!("Setting up KVM memory slots");
 
-        for (slot, region) in self.guest_memory.iter().enumerate() {
-            let mem_region = kvm_userspace_memory_region {
-                slot: slot as u32,
-                flags: 0,
-                guest_phys_addr: region.start_addr().raw_value(),
-                memory_size: region.len() as u64,
-                userspace_addr: region.as_ptr() as u64,
-            };
+        let regions: Vec<_> = self
+            .guest_memory
+            .iter()
+            .map(|region| {
+                (
+                    GuestPhysAddr::from(region.start_addr()),
+                    region.len() as usize,
+                )
+            })
+            .collect();
+        for (gpa, size) in regions {
+            self.map_memory_slot(gpa, size)?;
+        }
 
-            unsafe {
-                self.vm
-                    .set_user_memory_region(mem_region)
-                    .context("Failed to set KVM memory region")?;
-            }
+        Ok(())
+    }
+
+    /// Register the guest memory at `gpa..gpa+size` with KVM in a new slot
+    fn map_memory_slot(&mut self, gpa: GuestPhysAddr, size: usize) -> Result<u32> {
+        let slot = MemorySlot {
+            gpa,
+            hva: self.guest_memory.gpa_to_hva(gpa)?,
+            size,
+        };
+        #[cfg(feature = "sev")]
+        if let Some(sev) = &self.sev {
+            sev.register_memory(&self.vm, slot.hva.0, size)?;
+        }
+        let id = self.add_memory_slot(slot)?;
+        if let Some(dirty) = &mut self.dirty {
+            dirty.track_slot(&self.vm, id, slot)?;
+        }
+        Ok(id)
+    }
+
+    /// Register an already mapped host range with KVM in a new slot
+    fn add_memory_slot(&mut self, slot: MemorySlot) -> Result<u32> {
+        let id = self.memory_slots.insert(slot)?;
+
+        // SAFETY: the host mapping is owned by `guest_memory` and outlives the slot
+        if let Err(e) = unsafe { self.vm.set_user_memory_region(slot.kvm_region(id)) } {
+            self.memory_slots.remove(id)?;
+            return Err(anyhow!("Failed to set KVM memory region: {}", e));
+        }
+
+        info!(
+            "Mapped slot {}: {} -> {}, size 0x{:x}",
+            id, slot.gpa, slot.hva, slot.size
+        );
+        Ok(id)
+    }
+
+    /// Number of KVM memory slots in use
+    fn slot_count(&self) -> usize {
+        self.memory_slots.len()
+    }
 
+    /// Open passthrough devices, map their BARs and give them DMA access
+    fn setup_vfio(&mut self) -> Result<()> {
+        if self.config.vfio_devices.is_empty() {
+            return Ok(());
+        }
+        info!("Setting up {} VFIO devices", self.config.vfio_devices.len());
+
+        let mut container = vfio::VfioContainer::open()?;
+        let mut devices = Vec::new();
+        for config in &self.config.vfio_devices {
+            let device = vfio::VfioDevice::open(&mut container, config)
+                .with_context(|| format!("Failed to open VFIO device {}", config.pci_addr))?;
+            devices.push(device);
+        }
+
+        // BARs of all devices share one MMIO window
+        let bars: Vec<_> = devices
+            .iter()
+            .flat_map(|device| device.bars().iter().map(move |bar| (device, bar)))
+            .collect();
+        let sizes: Vec<usize> = bars.iter().map(|(_, bar)| bar.size).collect();
+        let gpas = vfio::place_bars(vfio::VFIO_MMIO_BASE, &sizes);
+        for ((device, bar), gpa) in bars.into_iter().zip(gpas) {
+            self.add_memory_slot(MemorySlot {
+                gpa: GuestPhysAddr(gpa),
+                hva: HostVirtAddr(bar.hva),
+                size: bar.size,
+            })?;
             info!(
-                "Mapped slot {}: GPA 0x{:x}, size 0x{:x}",
-                slot, mem_region.guest_phys_addr, mem_region.memory_size
+                "{} BAR{} at GPA 0x{:x}",
+                device.config().pci_addr,
+                bar.index,
+                gpa
             );
         }
 
+        // Identity IOVA = GPA, so the guest driver can program guest addresses
+        for region in self.guest_memory.iter() {
+            container.map_dma(
+                region.start_addr().0,
+                region.as_ptr() as u64,
+                region.len() as usize,
+            )?;
+        }
+
+        self.vfio_container = Some(container);
+        self.vfio_devices = devices;
         Ok(())
     }
 
     /// Initialize userfaultfd pager for distributed memory
-    fn setup_pager(&self) -> Result<()> {
+    fn setup_pager(&mut self) -> Result<()> {
         info!("Initializing userfaultfd pager");
 
         // Get the first memory region
@@ -108,31 +279,50 @@ impl SsiVmm {
             .next()
             .context("No memory regions available")?;
 
-        let base = region.as_ptr();
+        let base = self
+            .guest_memory
+            .gpa_to_hva(GuestPhysAddr::from(region.start_addr()))?;
         let len = region.len() as usize;
 
-        // Start pager with coordinator URL
-        pager::start_pager(
-            base,
-            len,
-            self.config.node_id,
-            self.config.total_nodes,
-            &self.config.coordinator_url,
-        )
-        .context("Failed to start pager")?;
+        let runtime = tokio::runtime::Runtime::new().context("Failed to create pager runtime")?;
 
-        info!("Pager registered: base={:p}, len=0x{:x}", base, len);
+        // Start pager with coordinator URL
+        let pager = pager::PagerBuilder::new(base.as_mut_ptr(), len)
+            .node_id(self.config.node_id)
+            .total_nodes(self.config.total_nodes)
+            .coordinator_url(&self.config.coordinator_url)
+            .runtime(runtime.handle().clone())
+            .build()
+            .context("Failed to start pager")?;
+        self.transport = Some(pager.transport());
+        self.pager_runtime = Some(runtime);
+        pager.spawn().context("Failed to start pager")?;
+
+        info!("Pager registered: base={}, len=0x{:x}", base, len);
         Ok(())
     }
 
     /// Create and configure vCPUs
+    ///
+    /// With a topology, each vCPU is created with its guest CPU's APIC ID as
+    /// vCPU ID; KVM gives the local APIC that ID.
     fn create_vcpus(&self) -> Result<Vec<VcpuFd>> {
         let mut vcpus = Vec::new();
+        let topology = self
+            .config
+            .topology
+            .as_ref()
+            .map(cpuid::CpuidTopologyEmulator::new)
+            .transpose()?;
 
         for i in 0..self.config.num_vcpus {
+            let id = match &topology {
+                Some(topology) => topology.apic_id(i)?,
+                None => i,
+            };
             let vcpu = self
                 .vm
-                .create_vcpu(i as u64)
+                .create_vcpu(id as u64)
                 .context(format!("Failed to create vCPU {}", i))?;
 
             // Setup CPUID (use reasonable default of 256 entries)
@@ -149,29 +339,291 @@ impl SsiVmm {
         Ok(vcpus)
     }
 
+    /// Show the guest each vCPU as a CPU of its NUMA node in `topology`
+    ///
+    /// vCPU `i` is guest CPU `i` of the topology.
+    fn configure_cpuid_topology(&mut self, topology: &cpuid::ClusterTopology) -> Result<()> {
+        let emulator = cpuid::CpuidTopologyEmulator::new(topology)?;
+        for (cpu, vcpu) in self.vcpus.iter().enumerate() {
+            let mut cpuid = self
+                .kvm
+                .get_supported_cpuid(256)
+                .context("Failed to get supported CPUID")?;
+            emulator.apply(cpu as u32, &mut cpuid)?;
+            vcpu.set_cpuid2(&cpuid)
+                .with_context(|| format!("Failed to set CPUID topology of vCPU {}", cpu))?;
+        }
+        info!(
+            "Presented {} vCPUs in {} NUMA nodes",
+            self.vcpus.len(),
+            topology.nodes.len()
+        );
+        Ok(())
+    }
+
+    /// Align vCPU TSCs with the reference node's host TSC
+    fn sync_tsc(&self, vcpus: &[VcpuFd]) -> Result<()> {
+        let sync = tsc::TscSync::new(TSC_REFERENCE_NODE);
+
+        let measurement = if self.config.node_id == TSC_REFERENCE_NODE {
+            let khz = match vcpus.first() {
+                Some(vcpu) => vcpu
+                    .get_tsc_khz()
+                    .context("Failed to read host TSC frequency")?,
+                None => return Ok(()),
+            };
+            tsc::TscMeasurement::identity(khz)
+        } else {
+            let transport = self
+                .transport
+                .as_ref()
+                .context("TSC sync requires the pager transport")?;
+            sync.measure(&transport.read())?
+        };
+
+        sync.apply(vcpus, &measurement)
+    }
+
+    /// Measure the initial guest memory and seal the SEV launch
+    ///
+    /// Must run after the guest image is in memory, since the measurement
+    /// covers it. The measurement is what a guest owner checks before
+    /// provisioning secrets.
+    #[cfg(feature = "sev")]
+    fn finalize_sev_launch(&mut self) -> Result<sev::SevMeasurement> {
+        let guest = self
+            .sev
+            .as_mut()
+            .context("SEV is not enabled for this VM")?;
+        let measurement = guest.measure(&self.vm)?;
+        guest.finish(&self.vm)?;
+        Ok(measurement)
+    }
+
+    /// Translate a guest virtual address using a vCPU's current page tables
+    #[allow(dead_code)] // Debugging aid, e.g. to name the GVA behind a fault
+    fn gva_to_gpa(&self, vcpu_id: u32, gva: u64) -> Result<GuestPhysAddr> {
+        const CR0_PG: u64 = 1 << 31;
+        const EFER_LMA: u64 = 1 << 10;
+
+        let vcpu = self
+            .vcpus
+            .get(vcpu_id as usize)
+            .ok_or_else(|| anyhow!("No vCPU {}", vcpu_id))?;
+        let sregs = vcpu.get_sregs().context("Failed to get vCPU sregs")?;
+
+        if sregs.cr0 & CR0_PG == 0 {
+            // Paging disabled: virtual == physical
+            return Ok(GuestPhysAddr(gva));
+        }
+        if sregs.efer & EFER_LMA == 0 {
+            return Err(anyhow!(
+                "vCPU {} is not in long mode; only 4-level paging is supported",
+                vcpu_id
+            ));
+        }
+
+        page_walk::GuestPageWalker::new(&self.guest_memory).translate(sregs.cr3, gva)
+    }
+
     fn run(&mut self) -> Result<()> {
+        // Dirty rings must be enabled before vCPUs exist
+        if self.config.dirty_tracking {
+            self.dirty = Some(dirty::DirtyTracker::new(
+                &self.vm,
+                dirty::DEFAULT_RING_ENTRIES,
+            )?);
+        }
+
         // Setup memory slots in KVM
         self.setup_memory()?;
 
         // Initialize pager for distributed memory
         self.setup_pager()?;
 
+        self.setup_vfio().context("VFIO passthrough setup failed")?;
+
+        if let Some(kernel) = &self.config.kernel {
+            let entry = loader::bzimage::load_bzimage(&self.guest_memory, kernel)?;
+            let cmdline = loader::bzimage::load_cmdline(&self.guest_memory, &self.config.cmdline)?;
+            loader::bzimage::write_boot_params(
+                &self.guest_memory,
+                loader::bzimage::BOOT_PARAMS_GPA,
+                entry,
+                cmdline,
+            )?;
+            info!(
+                "Loaded {}: entry {}",
+                kernel.display(),
+                GuestPhysAddr::from(entry)
+            );
+        }
+
         // Create vCPUs
-        let vcpus = self.create_vcpus()?;
+        self.vcpus = self.create_vcpus()?;
+        if let Some(topology) = self.config.topology.clone() {
+            self.configure_cpuid_topology(&topology)?;
+        }
+        if let Some(dirty) = &mut self.dirty {
+            for vcpu in &self.vcpus {
+                dirty.track_vcpu(vcpu)?;
+            }
+        }
+
+        if self.config.tsc_sync {
+            self.sync_tsc(&self.vcpus)
+                .context("TSC synchronization failed")?;
+        }
+
+        #[cfg(feature = "sev")]
+        if self.sev.is_some() {
+            let measurement = self.finalize_sev_launch()?;
+            let digest: String = measurement
+                .digest
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            info!("SEV launch measurement: {}", digest);
+        }
 
         info!("SSI-HV VMM initialized successfully");
         info!(
-            "VM fd={}, vCPUs={}, memory={}MB",
+            "VM fd={}, vCPUs={}, memory={}MB in {} slots",
             self.vm.as_raw_fd(),
-            vcpus.len(),
-            self.config.mem_size >> 20
+            self.vcpus.len(),
+            self.config.mem_size >> 20,
+            self.slot_count()
         );
 
-        // TODO: Setup serial console, load OVMF, start vCPU run loops
-        warn!("vCPU run loops not yet implemented - VM created but not running");
+        // TODO: Load OVMF
+        self.start_vcpus()
+    }
+
+    /// Run each vCPU on its own thread, sharing the VM's devices
+    fn start_vcpus(&mut self) -> Result<()> {
+        let devices = Arc::new(Mutex::new(LegacyDevices::new()));
+        for (id, vcpu) in std::mem::take(&mut self.vcpus).into_iter().enumerate() {
+            let mut manager = VcpuManager::new(vcpu, id as u32);
+            let gate = self.vcpu_gate.clone();
+            let devices = Arc::clone(&devices);
+            let handle = thread::Builder::new()
+                .name(format!("vcpu{}", id))
+                .spawn(move || {
+                    manager.run(&gate, &*devices)?;
+                    Ok(manager.exit_stats())
+                })
+                .with_context(|| format!("Failed to start vCPU {} thread", id))?;
+            self.vcpu_threads.push(handle);
+        }
+        info!("Started {} vCPU threads", self.vcpu_threads.len());
+        Ok(())
+    }
+
+    /// Wait for every vCPU to halt or shut down
+    fn join_vcpus(&mut self) -> Result<()> {
+        for (id, handle) in self.vcpu_threads.drain(..).enumerate() {
+            let stats = handle
+                .join()
+                .map_err(|_| anyhow!("vCPU {} thread panicked", id))??;
+            info!("vCPU {} stopped: {:?}", id, stats);
+        }
+        Ok(())
+    }
+}
+
+/// Memory hotplug
+#[allow(dead_code)] // Not yet driven by a control interface
+impl SsiVmm {
+    /// Add `size` bytes of guest memory at `gpa`, returning its slot
+    fn hotplug_memory(&mut self, gpa: GuestPhysAddr, size: usize) -> Result<u32> {
+        let region = GuestRegionMmap::from_range(GuestAddress(gpa.0), size, None)
+            .with_context(|| format!("Failed to allocate 0x{:x} bytes at {}", size, gpa))?;
+        let guest_memory = self
+            .guest_memory
+            .insert_region(Arc::new(region))
+            .with_context(|| format!("Cannot hotplug memory at {}", gpa))?;
+        let previous = std::mem::replace(&mut self.guest_memory, guest_memory);
+
+        self.map_memory_slot(gpa, size).inspect_err(|_| {
+            self.guest_memory = previous;
+        })
+    }
+
+    /// Remove a hotplugged memory slot and free its host memory
+    fn unplug_memory(&mut self, slot: u32) -> Result<()> {
+        let mapping = *self
+            .memory_slots
+            .get(slot)
+            .ok_or_else(|| anyhow!("No memory slot {}", slot))?;
+
+        unsafe {
+            self.vm
+                .set_user_memory_region(mapping.kvm_delete(slot))
+                .with_context(|| format!("Failed to delete KVM memory slot {}", slot))?;
+        }
+        self.memory_slots.remove(slot)?;
+        if let Some(dirty) = &mut self.dirty {
+            dirty.untrack_slot(slot);
+        }
+
+        let (guest_memory, _region) = self
+            .guest_memory
+            .remove_region(GuestAddress(mapping.gpa.0), mapping.size as u64)
+            .with_context(|| format!("Failed to remove guest memory at {}", mapping.gpa))?;
+        self.guest_memory = guest_memory;
 
+        info!(
+            "Unplugged slot {}: {}, size 0x{:x}",
+            slot, mapping.gpa, mapping.size
+        );
         Ok(())
     }
+
+    /// Renumber memory slots to close the holes left by `unplug_memory`
+    ///
+    /// vCPUs are held out of the guest while slots are moved; each move
+    /// deletes the old slot and re-creates the same mapping under a lower
+    /// number. Returns the number of slots moved.
+    fn compact_memory_slots(&mut self) -> Result<usize> {
+        let gate = self.vcpu_gate.clone();
+        let _paused = gate.pause();
+
+        let plan = self.memory_slots.compaction_plan();
+        for &(from, to) in &plan {
+            let mapping = *self
+                .memory_slots
+                .get(from)
+                .ok_or_else(|| anyhow!("No memory slot {}", from))?;
+
+            // SAFETY: vCPUs are paused, and the mapping is unchanged apart
+            // from its slot number
+            unsafe {
+                self.vm
+                    .set_user_memory_region(mapping.kvm_delete(from))
+                    .with_context(|| format!("Failed to delete KVM memory slot {}", from))?;
+                self.vm
+                    .set_user_memory_region(mapping.kvm_region(to))
+                    .with_context(|| format!("Failed to move memory slot {} to {}", from, to))?;
+            }
+            self.memory_slots.renumber(from, to)?;
+            // The re-created slot starts without dirty logging
+            if let Some(dirty) = &mut self.dirty {
+                if let Some(mapping) = dirty.untrack_slot(from) {
+                    dirty.track_slot(&self.vm, to, mapping)?;
+                }
+            }
+        }
+        self.memory_slots.reset_next_slot();
+
+        if !plan.is_empty() {
+            info!(
+                "Compacted memory slots: moved {}, {} in use",
+                plan.len(),
+                self.slot_count()
+            );
+        }
+        Ok(plan.len())
+    }
 }
 
 fn main() -> Result<()> {
@@ -187,9 +639,8 @@ fn main() -> Result<()> {
 
     info!("VMM initialization complete");
 
-    // Keep running for testing
-    info!("Press Ctrl+C to exit");
-    thread::park();
+    vmm.join_vcpus()?;
+    info!("All vCPUs stopped");
 
     Ok(())
 }
@@ -205,6 +656,7 @@ mod tests {
         assert_eq!(config.num_vcpus, 2);
         assert_eq!(config.node_id, 0);
         assert_eq!(config.total_nodes, 1);
+        assert!(!config.tsc_sync);
     }
 
     #[test]
@@ -215,6 +667,14 @@ mod tests {
             node_id: 1,
             total_nodes: 2,
             coordinator_url: "http://test:8000".to_string(),
+            tsc_sync: true,
+            dirty_tracking: false,
+            topology: None,
+            #[cfg(feature = "sev")]
+            sev: None,
+            vfio_devices: Vec::new(),
+            kernel: None,
+            cmdline: String::new(),
         };
         assert_eq!(config.mem_size, 2 << 30);
         assert_eq!(config.num_vcpus, 4);
@@ -222,6 +682,75 @@ mod tests {
         assert_eq!(config.total_nodes, 2);
     }
 
+    #[test]
+    fn test_configure_cpuid_topology() {
+        let topology: cpuid::ClusterTopology = serde_json::from_str(
+            r#"{"nodes": [
+                {"node_id": 0, "cpu_start": 0, "cpu_count": 2},
+                {"node_id": 1, "cpu_start": 2, "cpu_count": 1}
+            ]}"#,
+        )
+        .unwrap();
+        // Needs /dev/kvm
+        let Ok(mut vmm) = SsiVmm::new(VmmConfig {
+            mem_size: 1 << 20,
+            num_vcpus: 3,
+            topology: Some(topology.clone()),
+            ..Default::default()
+        }) else {
+            return;
+        };
+        vmm.vcpus = vmm.create_vcpus().unwrap();
+        vmm.configure_cpuid_topology(&topology).unwrap();
+
+        let apic_ids: Vec<u32> = vmm
+            .vcpus
+            .iter()
+            .map(|vcpu| {
+                let cpuid = vcpu.get_cpuid2(256).unwrap();
+                let smt = cpuid
+                    .as_slice()
+                    .iter()
+                    .find(|entry| entry.function == 0x0b && entry.index == 0)
+                    .unwrap();
+                smt.edx
+            })
+            .collect();
+        assert_eq!(apic_ids, [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_compact_memory_slots() {
+        // Needs /dev/kvm
+        let Ok(mut vmm) = SsiVmm::new(VmmConfig {
+            mem_size: 1 << 20,
+            ..Default::default()
+        }) else {
+            return;
+        };
+
+        let slots: Vec<u32> = (1..=5)
+            .map(|i| vmm.hotplug_memory(GuestPhysAddr(i << 30), 1 << 20).unwrap())
+            .collect();
+        assert_eq!(slots, vec![0, 1, 2, 3, 4]);
+
+        for slot in [0, 1, 3] {
+            vmm.unplug_memory(slot).unwrap();
+        }
+        assert_eq!(vmm.slot_count(), 2);
+
+        assert_eq!(vmm.compact_memory_slots().unwrap(), 2);
+        assert_eq!(vmm.slot_count(), 2);
+        assert_eq!(vmm.memory_slots.get(0).unwrap().gpa, GuestPhysAddr(3 << 30));
+        assert_eq!(vmm.memory_slots.get(1).unwrap().gpa, GuestPhysAddr(5 << 30));
+
+        // Freed numbers are handed out again
+        assert_eq!(
+            vmm.hotplug_memory(GuestPhysAddr(6 << 30), 1 << 20).unwrap(),
+            2
+        );
+    }
+
     #[test]
     fn test_vmm_config_memory_sizes() {
         let config_1gb = VmmConfig {
@@ -0,0 +1,200 @@
+//! KVM memory slot bookkeeping
+//!
+//! KVM identifies each guest memory mapping by a slot number, and a VM only
+//! has a limited number of them. Hotplug hands out slot numbers in increasing
+//! order, so after regions are removed the slot space is left full of holes.
+//! `MemorySlots` tracks the live slots and plans a compaction that renumbers
+//! them into the lowest free slot numbers, leaving the guest memory layout
+//! untouched.
+
+use crate::addr::{GuestPhysAddr, HostVirtAddr};
+use anyhow::{anyhow, Result};
+use kvm_bindings::kvm_userspace_memory_region;
+use std::collections::HashMap;
+
+/// Memory slots KVM allows per VM (`KVM_USER_MEM_SLOTS` on x86-64)
+pub const MAX_MEMORY_SLOTS: u32 = 512;
+
+/// One guest memory region registered with KVM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemorySlot {
+    pub gpa: GuestPhysAddr,
+    pub hva: HostVirtAddr,
+    pub size: usize,
+}
+
+impl MemorySlot {
+    /// `KVM_SET_USER_MEMORY_REGION` argument mapping this slot as `slot`
+    pub fn kvm_region(&self, slot: u32) -> kvm_userspace_memory_region {
+        kvm_userspace_memory_region {
+            slot,
+            flags: 0,
+            guest_phys_addr: self.gpa.0,
+            memory_size: self.size as u64,
+            userspace_addr: self.hva.0,
+        }
+    }
+
+    /// `KVM_SET_USER_MEMORY_REGION` argument deleting `slot`
+    pub fn kvm_delete(&self, slot: u32) -> kvm_userspace_memory_region {
+        kvm_userspace_memory_region {
+            memory_size: 0,
+            ..self.kvm_region(slot)
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.gpa.0 + self.size as u64
+    }
+}
+
+/// Live KVM memory slots, by slot number
+#[derive(Debug, Default)]
+pub struct MemorySlots {
+    slots: HashMap<u32, MemorySlot>,
+    /// Next slot number handed out; never reuses freed numbers
+    next_slot: u32,
+}
+
+impl MemorySlots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of live slots
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&MemorySlot> {
+        self.slots.get(&slot)
+    }
+
+    /// Reserve the next slot number for `slot`
+    ///
+    /// Fails if the region overlaps a live slot or the slot numbers are
+    /// used up (`SsiVmm::compact_memory_slots` may free some).
+    pub fn insert(&mut self, slot: MemorySlot) -> Result<u32> {
+        if let Some((id, other)) = self
+            .slots
+            .iter()
+            .find(|(_, other)| slot.gpa.0 < other.end() && other.gpa.0 < slot.end())
+        {
+            return Err(anyhow!(
+                "{} (size 0x{:x}) overlaps slot {} at {}",
+                slot.gpa,
+                slot.size,
+                id,
+                other.gpa
+            ));
+        }
+        if self.next_slot >= MAX_MEMORY_SLOTS {
+            return Err(anyhow!(
+                "Out of KVM memory slot numbers ({} live of {})",
+                self.slots.len(),
+                MAX_MEMORY_SLOTS
+            ));
+        }
+
+        let id = self.next_slot;
+        self.next_slot += 1;
+        self.slots.insert(id, slot);
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, slot: u32) -> Result<MemorySlot> {
+        self.slots
+            .remove(&slot)
+            .ok_or_else(|| anyhow!("No memory slot {}", slot))
+    }
+
+    /// Moves `(from, to)` that pack the live slots into `0..len()`
+    ///
+    /// Slots numbered at or above `len()` are moved, lowest GPA first, into
+    /// the free numbers below it. Targets are always free, so the moves can be
+    /// applied in order.
+    pub fn compaction_plan(&self) -> Vec<(u32, u32)> {
+        let live = self.slots.len() as u32;
+        let gaps = (0..live).filter(|id| !self.slots.contains_key(id));
+
+        let mut movers: Vec<(u32, GuestPhysAddr)> = self
+            .slots
+            .iter()
+            .filter(|(id, _)| **id >= live)
+            .map(|(id, slot)| (*id, slot.gpa))
+            .collect();
+        movers.sort_by_key(|(_, gpa)| *gpa);
+
+        movers.into_iter().map(|(id, _)| id).zip(gaps).collect()
+    }
+
+    /// Record a slot's new number after KVM has been updated
+    pub fn renumber(&mut self, from: u32, to: u32) -> Result<()> {
+        if self.slots.contains_key(&to) {
+            return Err(anyhow!("Memory slot {} is in use", to));
+        }
+        let slot = self.remove(from)?;
+        self.slots.insert(to, slot);
+        Ok(())
+    }
+
+    /// Hand out numbers right after the highest live slot again
+    pub fn reset_next_slot(&mut self) {
+        self.next_slot = self.slots.keys().max().map_or(0, |id| id + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(gpa: u64) -> MemorySlot {
+        MemorySlot {
+            gpa: GuestPhysAddr(gpa),
+            hva: HostVirtAddr(0x7f00_0000_0000 + gpa),
+            size: 0x10_0000,
+        }
+    }
+
+    #[test]
+    fn test_insert_rejects_overlap() {
+        let mut slots = MemorySlots::new();
+        assert_eq!(slots.insert(slot(0)).unwrap(), 0);
+        assert!(slots.insert(slot(0x8_0000)).is_err());
+        assert_eq!(slots.insert(slot(0x10_0000)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_freed_numbers_not_reused_until_compaction() {
+        let mut slots = MemorySlots::new();
+        for i in 0..3 {
+            slots.insert(slot(i << 20)).unwrap();
+        }
+        slots.remove(0).unwrap();
+        assert_eq!(slots.insert(slot(3 << 20)).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_compaction_plan_fills_gaps_by_gpa() {
+        let mut slots = MemorySlots::new();
+        for i in 0..5 {
+            slots.insert(slot(i << 20)).unwrap();
+        }
+        for id in [0, 1, 3] {
+            slots.remove(id).unwrap();
+        }
+
+        // Live: 2 (GPA 2M) and 4 (GPA 4M) move into the gaps at 0 and 1
+        let plan = slots.compaction_plan();
+        assert_eq!(plan, vec![(2, 0), (4, 1)]);
+
+        for (from, to) in plan {
+            slots.renumber(from, to).unwrap();
+        }
+        slots.reset_next_slot();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots.get(0).unwrap().gpa, GuestPhysAddr(2 << 20));
+        assert_eq!(slots.get(1).unwrap().gpa, GuestPhysAddr(4 << 20));
+        assert_eq!(slots.insert(slot(5 << 20)).unwrap(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+//! Moving running vCPUs between nodes
+//!
+//! `VcpuMigrator::migrate_vcpu` pauses a vCPU, snapshots it, and sends the
+//! bincode-encoded snapshot to the target node as pages in a per-vCPU
+//! mailbox: a window of guest-physical addresses far above any guest RAM.
+//! The mailbox's first page is a header, sent last, so its arrival tells the
+//! target the snapshot is complete. The target's `receive_vcpu` then loads
+//! it into its own `VcpuManager` and lets the vCPU run.
+//!
+//! ```text
+//! mailbox(id) = MAILBOX_BASE + id * MAILBOX_PAGES * PAGE_SIZE
+//! header:     b"VCPUSNAP" | sequence: u64 | snapshot bytes: u64
+//! page 1..n:  snapshot, zero padded
+//! ```
+
+use crate::vcpu::{VcpuManager, VcpuSnapshot};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use rdma_transport::TransportManager;
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Start of the mailboxes, beyond the 52-bit guest physical address space
+const MAILBOX_BASE: u64 = 1 << 52;
+
+/// Pages per vCPU mailbox, header included
+const MAILBOX_PAGES: u64 = 16;
+
+const HEADER_MAGIC: &[u8; 8] = b"VCPUSNAP";
+
+/// Totals over the migrations a `VcpuMigrator` sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VcpuMigratorStats {
+    pub vcpus_migrated: u64,
+    /// Time vCPUs spent paused before their snapshot was delivered, summed
+    pub downtime_us: u64,
+}
+
+/// Sends vCPUs to other nodes and restores the ones sent here
+#[derive(Debug, Default)]
+#[allow(dead_code)] // Not yet driven by live migration
+pub struct VcpuMigrator {
+    stats: VcpuMigratorStats,
+    /// Sequence number of the last snapshot restored, by vCPU ID
+    restored: HashMap<u32, u64>,
+}
+
+#[allow(dead_code)]
+impl VcpuMigrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> VcpuMigratorStats {
+        self.stats
+    }
+
+    /// Stop `vcpu` and hand its state to `target_node`
+    ///
+    /// The vCPU stays paused here; the target resumes it once restored.
+    pub fn migrate_vcpu(
+        &mut self,
+        vcpu: &mut VcpuManager,
+        target_node: u32,
+        transport: &TransportManager,
+    ) -> Result<()> {
+        let paused = Instant::now();
+        vcpu.pause();
+        let snapshot = vcpu.snapshot()?;
+        let bytes = bincode::serialize(&snapshot).context("Failed to encode vCPU snapshot")?;
+        let pages = bytes.len().div_ceil(PAGE_SIZE) as u64;
+        if pages >= MAILBOX_PAGES {
+            return Err(anyhow!(
+                "vCPU {} snapshot of {} bytes does not fit its mailbox",
+                vcpu.id(),
+                bytes.len()
+            ));
+        }
+
+        let mailbox = mailbox(vcpu.id());
+        for (index, chunk) in bytes.chunks(PAGE_SIZE).enumerate() {
+            let mut page = [0u8; PAGE_SIZE];
+            page[..chunk.len()].copy_from_slice(chunk);
+            transport
+                .send_page(
+                    mailbox + (index as u64 + 1) * PAGE_SIZE as u64,
+                    &page,
+                    target_node,
+                )
+                .with_context(|| format!("Failed to send vCPU {} snapshot", vcpu.id()))?;
+        }
+
+        // Only unique per vCPU; the target restores each sequence once
+        let sequence = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut header = [0u8; PAGE_SIZE];
+        header[..8].copy_from_slice(HEADER_MAGIC);
+        header[8..16].copy_from_slice(&sequence.to_le_bytes());
+        header[16..24].copy_from_slice(&(bytes.len() as u64).to_le_bytes());
+        transport
+            .send_page(mailbox, &header, target_node)
+            .with_context(|| {
+                format!(
+                    "Failed to signal vCPU {} to node {}",
+                    vcpu.id(),
+                    target_node
+                )
+            })?;
+
+        let downtime = paused.elapsed();
+        self.stats.vcpus_migrated += 1;
+        self.stats.downtime_us += downtime.as_micros() as u64;
+        info!(
+            "Migrated vCPU {} to node {} ({} bytes, {:?} down)",
+            vcpu.id(),
+            target_node,
+            bytes.len(),
+            downtime
+        );
+        Ok(())
+    }
+
+    /// Restore and resume `vcpu` from a snapshot another node sent here
+    ///
+    /// Returns false if no snapshot for it arrived since the last restore.
+    pub fn receive_vcpu(
+        &mut self,
+        vcpu: &mut VcpuManager,
+        transport: &TransportManager,
+    ) -> Result<bool> {
+        let mailbox = mailbox(vcpu.id());
+        let Some(header) = transport.received_page(mailbox) else {
+            return Ok(false);
+        };
+        if &header[..8] != HEADER_MAGIC {
+            return Err(anyhow!("Bad snapshot header for vCPU {}", vcpu.id()));
+        }
+        let sequence = u64::from_le_bytes(header[8..16].try_into()?);
+        if self.restored.get(&vcpu.id()) == Some(&sequence) {
+            return Ok(false);
+        }
+        let len = u64::from_le_bytes(header[16..24].try_into()?) as usize;
+        let pages = len.div_ceil(PAGE_SIZE) as u64;
+        if pages >= MAILBOX_PAGES {
+            return Err(anyhow!(
+                "vCPU {} snapshot of {} bytes is too large",
+                vcpu.id(),
+                len
+            ));
+        }
+
+        let mut bytes = Vec::with_capacity(pages as usize * PAGE_SIZE);
+        for index in 1..=pages {
+            let page = transport
+                .received_page(mailbox + index * PAGE_SIZE as u64)
+                .ok_or_else(|| anyhow!("vCPU {} snapshot page {} missing", vcpu.id(), index))?;
+            bytes.extend_from_slice(&page);
+        }
+        let snapshot: VcpuSnapshot =
+            bincode::deserialize(&bytes[..len]).context("Failed to decode vCPU snapshot")?;
+        if snapshot.id != vcpu.id() {
+            return Err(anyhow!(
+                "Snapshot of vCPU {} in the mailbox of vCPU {}",
+                snapshot.id,
+                vcpu.id()
+            ));
+        }
+
+        vcpu.restore(&snapshot)?;
+        vcpu.resume();
+        self.restored.insert(vcpu.id(), sequence);
+        debug!("Restored vCPU {} from snapshot {}", vcpu.id(), sequence);
+        Ok(true)
+    }
+}
+
+/// First page of vCPU `id`'s mailbox
+fn mailbox(id: u32) -> u64 {
+    MAILBOX_BASE + id as u64 * MAILBOX_PAGES * PAGE_SIZE as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvm_ioctls::Kvm;
+    use rdma_transport::transport::mock::{MockNetwork, MockTransport};
+
+    /// A vCPU `id` of its own VM, with the host's CPUID and a local APIC
+    fn vcpu(kvm: &Kvm, id: u32) -> VcpuManager {
+        let vm = kvm.create_vm().unwrap();
+        vm.create_irq_chip().unwrap();
+        let vcpu = vm.create_vcpu(id as u64).unwrap();
+        vcpu.set_cpuid2(&kvm.get_supported_cpuid(256).unwrap())
+            .unwrap();
+        // The vCPU holds its own reference to the VM
+        VcpuManager::new(vcpu, id)
+    }
+
+    #[test]
+    fn test_migrate_stopped_vcpu_between_managers() {
+        // Needs /dev/kvm
+        let Ok(kvm) = Kvm::new() else {
+            return;
+        };
+        let network = MockNetwork::new();
+        let source_transport =
+            TransportManager::with_transport(0, Box::new(MockTransport::new(0, &network)));
+        let target_transport =
+            TransportManager::with_transport(1, Box::new(MockTransport::new(1, &network)));
+
+        let mut source = vcpu(&kvm, 2);
+        let mut before = source.snapshot().unwrap();
+        before.regs.rip = 0xfff0_1234;
+        before.regs.rax = 0xdead_beef;
+        before.regs.rflags = 0x246;
+        before.sregs.cr0 |= 1; // Protected mode
+        source.restore(&before).unwrap();
+
+        let mut sender = VcpuMigrator::new();
+        sender
+            .migrate_vcpu(&mut source, 1, &source_transport)
+            .unwrap();
+        assert_eq!(sender.stats().vcpus_migrated, 1);
+
+        let mut target = vcpu(&kvm, 2);
+        let mut receiver = VcpuMigrator::new();
+        assert!(receiver
+            .receive_vcpu(&mut target, &target_transport)
+            .unwrap());
+        // Each snapshot is restored once
+        assert!(!receiver
+            .receive_vcpu(&mut target, &target_transport)
+            .unwrap());
+
+        let after = target.snapshot().unwrap();
+        assert_eq!(after.regs.rip, 0xfff0_1234);
+        assert_eq!(after.regs.rax, 0xdead_beef);
+        assert_eq!(after.regs.rflags, 0x246);
+        assert_eq!(after.sregs.cr0, before.sregs.cr0);
+        assert_eq!(
+            after.lapic.unwrap().regs[..],
+            before.lapic.unwrap().regs[..]
+        );
+        assert_eq!(after.msrs.len(), before.msrs.len());
+
+        // Nothing was sent for other vCPUs
+        let mut other = vcpu(&kvm, 3);
+        assert!(!receiver
+            .receive_vcpu(&mut other, &target_transport)
+            .unwrap());
+    }
+}
@@ -0,0 +1,222 @@
+//! Guest page table walk (GVA -> GPA) for debugging
+//!
+//! The pager only sees host virtual addresses. To tell which guest virtual
+//! address caused a fault we walk the guest's x86-64 4-level page tables
+//! (PML4 -> PDPT -> PD -> PT) directly out of guest memory, starting from the
+//! vCPU's CR3. 2 MiB and 1 GiB large pages are supported.
+
+use crate::addr::GuestPhysAddr;
+use anyhow::{anyhow, Result};
+use vm_memory::{Bytes, GuestAddress, GuestMemory};
+
+/// Entry maps a page / points to the next table
+const PTE_PRESENT: u64 = 1 << 0;
+/// Entry maps a large page (PDPT: 1 GiB, PD: 2 MiB)
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+/// Physical address bits 51:12 of a table entry or CR3
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const PAGE_SHIFT_4K: u32 = 12;
+const PAGE_SHIFT_2M: u32 = 21;
+const PAGE_SHIFT_1G: u32 = 30;
+
+/// Paging levels, outermost first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageLevel {
+    Pml4,
+    Pdpt,
+    Pd,
+    Pt,
+}
+
+impl PageLevel {
+    /// Bit position of this level's 9-bit index in the virtual address
+    fn index_shift(self) -> u32 {
+        match self {
+            Self::Pml4 => 39,
+            Self::Pdpt => PAGE_SHIFT_1G,
+            Self::Pd => PAGE_SHIFT_2M,
+            Self::Pt => PAGE_SHIFT_4K,
+        }
+    }
+
+    fn next(self) -> Option<Self> {
+        match self {
+            Self::Pml4 => Some(Self::Pdpt),
+            Self::Pdpt => Some(Self::Pd),
+            Self::Pd => Some(Self::Pt),
+            Self::Pt => None,
+        }
+    }
+
+    /// Whether a PS entry at this level maps a large page
+    fn allows_large_page(self) -> bool {
+        matches!(self, Self::Pdpt | Self::Pd)
+    }
+}
+
+/// Walks guest page tables in guest memory
+pub struct GuestPageWalker<'a, M: GuestMemory> {
+    guest_mem: &'a M,
+}
+
+impl<'a, M: GuestMemory> GuestPageWalker<'a, M> {
+    pub fn new(guest_mem: &'a M) -> Self {
+        Self { guest_mem }
+    }
+
+    /// Translate `gva` using the page tables rooted at `cr3`
+    pub fn translate(&self, cr3: u64, gva: u64) -> Result<GuestPhysAddr> {
+        if !is_canonical(gva) {
+            return Err(anyhow!("GVA 0x{:x} is not canonical", gva));
+        }
+
+        let mut table = cr3 & PTE_ADDR_MASK;
+        let mut level = PageLevel::Pml4;
+
+        loop {
+            let shift = level.index_shift();
+            let index = (gva >> shift) & 0x1ff;
+            let entry_addr = table + index * 8;
+            let entry: u64 = self
+                .guest_mem
+                .read_obj(GuestAddress(entry_addr))
+                .map_err(|e| {
+                    anyhow!(
+                        "Failed to read {:?} entry at GPA 0x{:x}: {}",
+                        level,
+                        entry_addr,
+                        e
+                    )
+                })?;
+
+            if entry & PTE_PRESENT == 0 {
+                return Err(anyhow!(
+                    "GVA 0x{:x} not mapped: {:?} entry {} not present",
+                    gva,
+                    level,
+                    index
+                ));
+            }
+
+            let is_leaf =
+                level == PageLevel::Pt || (level.allows_large_page() && entry & PTE_PAGE_SIZE != 0);
+            if is_leaf {
+                let offset_mask = (1u64 << shift) - 1;
+                let frame = entry & PTE_ADDR_MASK & !offset_mask;
+                return Ok(GuestPhysAddr(frame | (gva & offset_mask)));
+            }
+
+            table = entry & PTE_ADDR_MASK;
+            level = level
+                .next()
+                .ok_or_else(|| anyhow!("Page walk ran past PT level"))?;
+        }
+    }
+}
+
+/// Bits 63:48 must be copies of bit 47
+fn is_canonical(gva: u64) -> bool {
+    let upper = gva >> 47;
+    upper == 0 || upper == 0x1_ffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm_memory::GuestMemoryMmap;
+
+    const PML4: u64 = 0x1000;
+    const PDPT: u64 = 0x2000;
+    const PD: u64 = 0x3000;
+    const PT: u64 = 0x4000;
+
+    fn index(gva: u64, level: PageLevel) -> u64 {
+        (gva >> level.index_shift()) & 0x1ff
+    }
+
+    fn write_entry(mem: &GuestMemoryMmap<()>, table: u64, index: u64, entry: u64) {
+        mem.write_obj(entry, GuestAddress(table + index * 8))
+            .unwrap();
+    }
+
+    fn guest_memory() -> GuestMemoryMmap<()> {
+        GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 16 << 20)]).unwrap()
+    }
+
+    #[test]
+    fn test_translate_4k_page() {
+        let mem = guest_memory();
+        let gva = 0x0000_7f12_3456_7abc;
+        let frame = 0x0080_0000;
+
+        write_entry(&mem, PML4, index(gva, PageLevel::Pml4), PDPT | PTE_PRESENT);
+        write_entry(&mem, PDPT, index(gva, PageLevel::Pdpt), PD | PTE_PRESENT);
+        write_entry(&mem, PD, index(gva, PageLevel::Pd), PT | PTE_PRESENT);
+        write_entry(&mem, PT, index(gva, PageLevel::Pt), frame | PTE_PRESENT);
+
+        let walker = GuestPageWalker::new(&mem);
+        let gpa = walker.translate(PML4, gva).unwrap();
+        assert_eq!(gpa, GuestPhysAddr(frame | 0xabc));
+    }
+
+    #[test]
+    fn test_translate_2m_page() {
+        let mem = guest_memory();
+        let gva = 0x0000_0040_0012_3456;
+        let frame = 0x0060_0000;
+
+        write_entry(&mem, PML4, index(gva, PageLevel::Pml4), PDPT | PTE_PRESENT);
+        write_entry(&mem, PDPT, index(gva, PageLevel::Pdpt), PD | PTE_PRESENT);
+        write_entry(
+            &mem,
+            PD,
+            index(gva, PageLevel::Pd),
+            frame | PTE_PRESENT | PTE_PAGE_SIZE,
+        );
+
+        let walker = GuestPageWalker::new(&mem);
+        let gpa = walker.translate(PML4, gva).unwrap();
+        assert_eq!(gpa, GuestPhysAddr(frame | 0x12_3456));
+    }
+
+    #[test]
+    fn test_translate_1g_page() {
+        // Higher-half kernel address
+        let gva = 0xffff_ffff_8123_4567;
+        let mem = guest_memory();
+        let frame = 0x4000_0000;
+
+        write_entry(&mem, PML4, index(gva, PageLevel::Pml4), PDPT | PTE_PRESENT);
+        write_entry(
+            &mem,
+            PDPT,
+            index(gva, PageLevel::Pdpt),
+            frame | PTE_PRESENT | PTE_PAGE_SIZE,
+        );
+
+        let walker = GuestPageWalker::new(&mem);
+        let gpa = walker.translate(PML4, gva).unwrap();
+        assert_eq!(gpa, GuestPhysAddr(frame | 0x0123_4567));
+    }
+
+    #[test]
+    fn test_translate_not_present() {
+        let mem = guest_memory();
+        let gva = 0x0000_0000_0040_1000;
+
+        write_entry(&mem, PML4, index(gva, PageLevel::Pml4), PDPT | PTE_PRESENT);
+        // PDPT entry left zero
+
+        let walker = GuestPageWalker::new(&mem);
+        let err = walker.translate(PML4, gva).unwrap_err();
+        assert!(err.to_string().contains("Pdpt"), "{}", err);
+    }
+
+    #[test]
+    fn test_translate_non_canonical() {
+        let mem = guest_memory();
+        let walker = GuestPageWalker::new(&mem);
+        assert!(walker.translate(PML4, 0x0000_8000_0000_0000).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! vCPU TSC synchronization across cluster nodes
+//!
+//! Each host's TSC runs at its own frequency from its own epoch, so guest
+//! vCPUs placed on different hosts would otherwise see unrelated counters.
+//! `TscSync` measures the local TSC against a reference node with a
+//! Cristian-style probe over the transport, then programs every local vCPU
+//! with the reference frequency (`KVM_SET_TSC_KHZ`) and a TSC offset so the
+//! guest counter tracks the reference host's counter.
+
+use anyhow::{anyhow, Context, Result};
+use kvm_bindings::{kvm_device_attr, KVM_VCPU_TSC_CTRL, KVM_VCPU_TSC_OFFSET};
+use kvm_ioctls::VcpuFd;
+use log::{debug, info};
+use rdma_transport::{read_tsc, TransportManager};
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Probes per batch; the one with the shortest round trip is kept
+const DEFAULT_PROBES: usize = 16;
+
+/// `_IOW(KVMIO, 0xe1, struct kvm_device_attr)`
+const KVM_SET_DEVICE_ATTR: libc::c_ulong = 0x4018_aee1;
+
+/// Gap between the two probe batches used to estimate TSC frequencies
+const RATE_WINDOW: Duration = Duration::from_millis(50);
+
+/// Result of probing a reference node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TscMeasurement {
+    /// Local TSC at the sample point
+    pub local_tsc: u64,
+    /// Reference node TSC at the same instant
+    pub reference_tsc: u64,
+    /// Local host TSC frequency
+    pub local_khz: u32,
+    /// Reference host TSC frequency (programmed into every vCPU)
+    pub reference_khz: u32,
+    /// Round trip of the sample probe in local cycles (bounds the error)
+    pub round_trip_cycles: u64,
+}
+
+impl TscMeasurement {
+    /// Measurement for the reference node itself: guest TSC equals host TSC
+    pub fn identity(khz: u32) -> Self {
+        let now = read_tsc();
+        Self {
+            local_tsc: now,
+            reference_tsc: now,
+            local_khz: khz,
+            reference_khz: khz,
+            round_trip_cycles: 0,
+        }
+    }
+
+    /// Offset to program into KVM so that, with TSC scaling to
+    /// `reference_khz`, the guest TSC equals the reference TSC
+    pub fn kvm_offset(&self) -> i64 {
+        let scaled = self.local_tsc as i128 * self.reference_khz as i128 / self.local_khz as i128;
+        (self.reference_tsc as i128 - scaled) as i64
+    }
+
+    /// Guest TSC a vCPU programmed with this measurement reads at `host_tsc`
+    pub fn guest_tsc(&self, host_tsc: u64) -> u64 {
+        let scaled = host_tsc as i128 * self.reference_khz as i128 / self.local_khz as i128;
+        (scaled + self.kvm_offset() as i128) as u64
+    }
+}
+
+/// One probe: local TSC at the midpoint of the round trip vs. remote TSC
+struct Sample {
+    local_mid: u64,
+    remote: u64,
+    round_trip: u64,
+    at: Instant,
+}
+
+/// Synchronizes local vCPU TSCs to a reference node
+pub struct TscSync {
+    reference_node: u32,
+    probes: usize,
+}
+
+impl TscSync {
+    pub fn new(reference_node: u32) -> Self {
+        Self {
+            reference_node,
+            probes: DEFAULT_PROBES,
+        }
+    }
+
+    /// Measure the local TSC against the reference node
+    pub fn measure(&self, transport: &TransportManager) -> Result<TscMeasurement> {
+        if transport.local_node_id() == self.reference_node {
+            return Ok(TscMeasurement::identity(calibrate_tsc_khz()));
+        }
+
+        let first = self.best_sample(transport)?;
+        thread::sleep(RATE_WINDOW);
+        let second = self.best_sample(transport)?;
+
+        let elapsed_ns = second.at.duration_since(first.at).as_nanos().max(1);
+        let khz = |cycles: u64| (cycles as u128 * 1_000_000 / elapsed_ns) as u32;
+        let local_khz = khz(second.local_mid.wrapping_sub(first.local_mid));
+        let reference_khz = khz(second.remote.wrapping_sub(first.remote));
+        if local_khz == 0 || reference_khz == 0 {
+            return Err(anyhow!("TSC did not advance during measurement"));
+        }
+
+        let measurement = TscMeasurement {
+            local_tsc: second.local_mid,
+            reference_tsc: second.remote,
+            local_khz,
+            reference_khz,
+            round_trip_cycles: second.round_trip,
+        };
+
+        info!(
+            "TSC offset to node {}: {} cycles (rtt {} cycles, local {} kHz, reference {} kHz)",
+            self.reference_node,
+            measurement.reference_tsc as i128 - measurement.local_tsc as i128,
+            measurement.round_trip_cycles,
+            local_khz,
+            reference_khz
+        );
+        Ok(measurement)
+    }
+
+    fn best_sample(&self, transport: &TransportManager) -> Result<Sample> {
+        let mut best: Option<Sample> = None;
+
+        for _ in 0..self.probes {
+            let start = Instant::now();
+            let sent = read_tsc();
+            let remote = transport
+                .probe_tsc(self.reference_node, sent)
+                .with_context(|| format!("TSC probe to node {} failed", self.reference_node))?;
+            let received = read_tsc();
+            let round_trip = received.wrapping_sub(sent);
+
+            if best.as_ref().is_none_or(|b| round_trip < b.round_trip) {
+                best = Some(Sample {
+                    local_mid: sent + round_trip / 2,
+                    remote,
+                    round_trip,
+                    at: start + start.elapsed() / 2,
+                });
+            }
+        }
+
+        best.ok_or_else(|| anyhow!("No TSC probes sent"))
+    }
+
+    /// Program every vCPU with the reference frequency and offset
+    pub fn apply(&self, vcpus: &[VcpuFd], measurement: &TscMeasurement) -> Result<()> {
+        let offset = measurement.kvm_offset();
+
+        for (id, vcpu) in vcpus.iter().enumerate() {
+            vcpu.set_tsc_khz(measurement.reference_khz)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vCPU {} TSC to {} kHz",
+                        id, measurement.reference_khz
+                    )
+                })?;
+            set_tsc_offset(vcpu, offset).with_context(|| format!("vCPU {}", id))?;
+        }
+
+        debug!(
+            "Guest TSC now ~{} (reference node {})",
+            measurement.guest_tsc(read_tsc()),
+            self.reference_node
+        );
+        info!(
+            "Synchronized {} vCPU TSCs to node {} ({} kHz, offset {})",
+            vcpus.len(),
+            self.reference_node,
+            measurement.reference_khz,
+            offset
+        );
+        Ok(())
+    }
+}
+
+/// Set the per-vCPU TSC offset (`KVM_VCPU_TSC_OFFSET` attribute)
+///
+/// kvm-ioctls only exposes `set_device_attr` for vCPUs on aarch64, so issue
+/// `KVM_SET_DEVICE_ATTR` directly.
+fn set_tsc_offset(vcpu: &VcpuFd, offset: i64) -> Result<()> {
+    let value = offset as u64;
+    let attr = kvm_device_attr {
+        group: KVM_VCPU_TSC_CTRL,
+        attr: KVM_VCPU_TSC_OFFSET as u64,
+        addr: &value as *const u64 as u64,
+        flags: 0,
+    };
+    // SAFETY: valid vCPU fd, `attr` and `value` outlive the call
+    let ret = unsafe { libc::ioctl(vcpu.as_raw_fd(), KVM_SET_DEVICE_ATTR, &attr) };
+    if ret != 0 {
+        return Err(anyhow!(
+            "Failed to set TSC offset: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Estimate the local TSC frequency against the monotonic clock
+pub fn calibrate_tsc_khz() -> u32 {
+    let start = Instant::now();
+    let tsc_start = read_tsc();
+    thread::sleep(Duration::from_millis(10));
+    let cycles = read_tsc().wrapping_sub(tsc_start);
+    let elapsed_ns = start.elapsed().as_nanos().max(1);
+    (cycles as u128 * 1_000_000 / elapsed_ns) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdma_transport::Endpoint;
+
+    #[test]
+    fn test_kvm_offset_with_matching_frequencies() {
+        let m = TscMeasurement {
+            local_tsc: 1_000,
+            reference_tsc: 5_000,
+            local_khz: 2_000_000,
+            reference_khz: 2_000_000,
+            round_trip_cycles: 10,
+        };
+        assert_eq!(m.kvm_offset(), 4_000);
+        assert_eq!(m.guest_tsc(1_000), 5_000);
+        assert_eq!(m.guest_tsc(2_000), 6_000);
+    }
+
+    #[test]
+    fn test_kvm_offset_with_scaling() {
+        // Local host runs at half the reference frequency
+        let m = TscMeasurement {
+            local_tsc: 1_000,
+            reference_tsc: 10_000,
+            local_khz: 1_000_000,
+            reference_khz: 2_000_000,
+            round_trip_cycles: 0,
+        };
+        assert_eq!(m.guest_tsc(1_000), 10_000);
+        // 100 local cycles later the reference has advanced 200 cycles
+        assert_eq!(m.guest_tsc(1_100), 10_200);
+    }
+
+    #[test]
+    fn test_vcpus_on_two_hosts_agree_within_1ms() {
+        let reference = TransportManager::new(0).unwrap();
+        let mut local = TransportManager::new(1).unwrap();
+
+        let port = match reference.local_endpoint() {
+            Endpoint::Tcp { port, .. } => port,
+            // Reachable only with another transport compiled in
+            #[allow(unreachable_patterns)]
+            other => panic!("{} is not a TCP endpoint", other),
+        };
+        local
+            .connect_peer(
+                0,
+                Endpoint::Tcp {
+                    addr: "127.0.0.1".parse().unwrap(),
+                    port,
+                },
+            )
+            .unwrap();
+
+        let sync = TscSync::new(0);
+        let reference_vcpu = sync.measure(&reference).unwrap();
+        let local_vcpu = sync.measure(&local).unwrap();
+
+        // A vCPU on each "host" reads its TSC at the same instant
+        let host_tsc = read_tsc();
+        let a = reference_vcpu.guest_tsc(host_tsc);
+        let b = local_vcpu.guest_tsc(host_tsc);
+
+        let one_ms = reference_vcpu.reference_khz as u64;
+        assert!(
+            a.abs_diff(b) < one_ms,
+            "vCPU TSCs differ by {} cycles (1 ms = {} cycles)",
+            a.abs_diff(b),
+            one_ms
+        );
+    }
+}
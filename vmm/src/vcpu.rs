@@ -1,40 +1,669 @@
+use crate::devices::DeviceManager;
 /// vCPU management module for SSI-HV
-use anyhow::Result;
-use kvm_ioctls::VcpuFd;
-use log::info;
+use anyhow::{anyhow, Context, Result};
+use kvm_bindings::{
+    kvm_debugregs, kvm_fpu, kvm_lapic_state, kvm_mp_state, kvm_msr_entry, kvm_regs, kvm_sregs,
+    kvm_vcpu_events, kvm_xcrs, kvm_xsave, Msrs,
+};
+use kvm_ioctls::{VcpuExit, VcpuFd};
+use log::{debug, info};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// MSRs carried in a snapshot; the rest of the MSR state the guest sees is
+/// in `kvm_sregs` (EFER, FS/GS base) or set up by the VMM
+const SNAPSHOT_MSRS: [u32; 11] = [
+    0x10,        // IA32_TSC
+    0x174,       // IA32_SYSENTER_CS
+    0x175,       // IA32_SYSENTER_ESP
+    0x176,       // IA32_SYSENTER_EIP
+    0x1a0,       // IA32_MISC_ENABLE
+    0x277,       // IA32_PAT
+    0xc000_0081, // STAR
+    0xc000_0082, // LSTAR
+    0xc000_0083, // CSTAR
+    0xc000_0084, // SYSCALL_MASK
+    0xc000_0102, // KERNEL_GS_BASE
+];
+
+/// XMM0-15 in the XSAVE legacy area, as `kvm_xsave::region` words
+const XSAVE_XMM_WORDS: Range<usize> = 160 / 4..416 / 4;
+
+/// Upper halves of YMM0-15 in the standard-format XSAVE area, as words
+const XSAVE_YMM_HI_WORDS: Range<usize> = 576 / 4..832 / 4;
+
+/// IA32_TSC_DEADLINE
+const MSR_IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+/// Offset of the LVT timer register in the local APIC page
+const APIC_LVTT: usize = 0x320;
+
+/// LVT timer mode field (bits 17-18)
+const APIC_LVTT_MODE_SHIFT: u32 = 17;
+const APIC_LVTT_MODE_MASK: u32 = 0b11 << APIC_LVTT_MODE_SHIFT;
+
+/// How the guest's local APIC timer fires
+///
+/// A periodic timer exits to the host on every tick whether or not the guest
+/// has work for it; in TSC-deadline mode the timer only fires, and the vCPU
+/// only exits, at the deadline the guest last asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LapicTimerMode {
+    /// Fires once when the initial count runs down
+    #[default]
+    Oneshot,
+    /// Reloads the initial count and fires every time it runs down
+    Periodic,
+    /// Fires when the TSC reaches IA32_TSC_DEADLINE
+    Deadline,
+}
+
+impl LapicTimerMode {
+    /// Value of the LVT timer mode field
+    fn lvtt_bits(self) -> u32 {
+        let mode = match self {
+            LapicTimerMode::Oneshot => 0b00,
+            LapicTimerMode::Periodic => 0b01,
+            LapicTimerMode::Deadline => 0b10,
+        };
+        mode << APIC_LVTT_MODE_SHIFT
+    }
+
+    fn from_lvtt(lvtt: u32) -> Option<Self> {
+        match (lvtt & APIC_LVTT_MODE_MASK) >> APIC_LVTT_MODE_SHIFT {
+            0b00 => Some(LapicTimerMode::Oneshot),
+            0b01 => Some(LapicTimerMode::Periodic),
+            0b10 => Some(LapicTimerMode::Deadline),
+            _ => None,
+        }
+    }
+}
+
+/// Read the 32-bit APIC register at `offset`
+fn apic_register(lapic: &kvm_lapic_state, offset: usize) -> u32 {
+    let bytes = &lapic.regs[offset..offset + 4];
+    u32::from_le_bytes([
+        bytes[0] as u8,
+        bytes[1] as u8,
+        bytes[2] as u8,
+        bytes[3] as u8,
+    ])
+}
+
+fn set_apic_register(lapic: &mut kvm_lapic_state, offset: usize, value: u32) {
+    for (reg, byte) in lapic.regs[offset..offset + 4]
+        .iter_mut()
+        .zip(value.to_le_bytes())
+    {
+        *reg = byte as _;
+    }
+}
+
+/// Lets the VMM hold all vCPUs out of the guest
+///
+/// vCPU threads hold `enter()` across `KVM_RUN`; `pause()` waits for them to
+/// exit and keeps them out until the returned guard is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct VcpuGate(Arc<RwLock<()>>);
+
+impl VcpuGate {
+    pub fn enter(&self) -> RwLockReadGuard<'_, ()> {
+        self.0.read()
+    }
+
+    pub fn pause(&self) -> RwLockWriteGuard<'_, ()> {
+        self.0.write()
+    }
+}
+
+/// Serde for `kvm_xsave` as an LZ4-compressed blob
+///
+/// The 4 KiB area is mostly zeros unless the guest uses wide vector state,
+/// and it is most of what a snapshot sends over the wire.
+mod compressed_xsave {
+    use kvm_bindings::{__IncompleteArrayField, kvm_xsave};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(xsave: &kvm_xsave, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = xsave
+            .region
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        serializer.serialize_bytes(&lz4_flex::compress_prepend_size(&bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<kvm_xsave, D::Error> {
+        let compressed = Vec::<u8>::deserialize(deserializer)?;
+        let bytes = lz4_flex::decompress_size_prepended(&compressed).map_err(D::Error::custom)?;
+        let mut xsave = kvm_xsave {
+            region: [0; 1024],
+            extra: __IncompleteArrayField::new(),
+        };
+        if bytes.len() != std::mem::size_of_val(&xsave.region) {
+            return Err(D::Error::custom(format!(
+                "XSAVE area of {} bytes",
+                bytes.len()
+            )));
+        }
+        for (word, chunk) in xsave.region.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Ok(xsave)
+    }
+}
+
+/// Serde for `kvm_fpu`, which kvm-bindings leaves out of its serde support
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "kvm_fpu")]
+struct KvmFpuDef {
+    fpr: [[u8; 16]; 8],
+    fcw: u16,
+    fsw: u16,
+    ftwx: u8,
+    pad1: u8,
+    last_opcode: u16,
+    last_ip: u64,
+    last_dp: u64,
+    xmm: [[u8; 16]; 16],
+    mxcsr: u32,
+    pad2: u32,
+}
+
+/// Architectural state of a stopped vCPU
+#[derive(Serialize, Deserialize)]
+pub struct VcpuSnapshot {
+    pub id: u32,
+    pub regs: kvm_regs,
+    pub sregs: kvm_sregs,
+    /// x87 and SSE registers as KVM_GET_FPU reports them
+    ///
+    /// Usually the same as the legacy part of `xsave`, but state loaded
+    /// with KVM_SET_FPU is not marked present in the XSAVE header, so it
+    /// only shows up here.
+    #[serde(with = "KvmFpuDef")]
+    pub fpu: kvm_fpu,
+    /// FPU and vector registers
+    #[serde(with = "compressed_xsave")]
+    pub xsave: kvm_xsave,
+    pub xcrs: kvm_xcrs,
+    pub debug_regs: kvm_debugregs,
+    /// None without an in-kernel irqchip
+    pub lapic: Option<kvm_lapic_state>,
+    pub mp_state: kvm_mp_state,
+    /// Pending exceptions, interrupts and NMIs
+    pub vcpu_events: kvm_vcpu_events,
+    pub msrs: Vec<kvm_msr_entry>,
+}
+
+impl VcpuSnapshot {
+    /// Whether any XMM or YMM register is non-zero, i.e. the guest has
+    /// vector state a target must be able to load
+    #[allow(dead_code)] // Not yet checked by VcpuMigrator
+    pub fn requires_xsave(&self) -> bool {
+        self.fpu.xmm.iter().flatten().any(|&byte| byte != 0)
+            || XSAVE_XMM_WORDS
+                .chain(XSAVE_YMM_HI_WORDS)
+                .any(|word| self.xsave.region[word] != 0)
+    }
+}
+
+/// How often each kind of exit has returned from `KVM_RUN`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VcpuExitStats {
+    pub hlt: u64,
+    pub io_in: u64,
+    pub io_out: u64,
+    pub mmio_read: u64,
+    pub mmio_write: u64,
+    pub shutdown: u64,
+    /// Everything else, including `KVM_RUN` interrupted by a signal
+    pub other: u64,
+}
+
+/// Little-endian value of an exit's data bytes
+fn exit_value(data: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    let len = data.len().min(8);
+    bytes[..len].copy_from_slice(&data[..len]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Fill an exit's data bytes from a device's little-endian value
+fn set_exit_value(data: &mut [u8], value: u64) {
+    let len = data.len().min(8);
+    data[..len].copy_from_slice(&value.to_le_bytes()[..len]);
+}
 
 /// Manages vCPU lifecycle and execution
 pub struct VcpuManager {
     vcpu: VcpuFd,
     id: u32,
+    exit_stats: VcpuExitStats,
 }
 
+#[allow(dead_code)]
 impl VcpuManager {
     pub fn new(vcpu: VcpuFd, id: u32) -> Self {
-        Self { vcpu, id }
+        Self {
+            vcpu,
+            id,
+            exit_stats: VcpuExitStats::default(),
+        }
     }
 
-    /// Run the vCPU in a loop (to be implemented)
-    pub fn run(&mut self) -> Result<()> {
-        info!("vCPU {} run loop starting", self.id);
-        // TODO: Implement KVM_RUN loop with exit handling
-        // - KVM_EXIT_IO for serial console
-        // - KVM_EXIT_MMIO for device access
-        // - KVM_EXIT_HLT
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Keep the vCPU out of the guest: `KVM_RUN` returns at once until
+    /// `resume`
+    pub fn pause(&mut self) {
+        self.vcpu.set_kvm_immediate_exit(1);
+    }
+
+    pub fn resume(&mut self) {
+        self.vcpu.set_kvm_immediate_exit(0);
+    }
+
+    /// Capture the vCPU's state; it must not be running
+    pub fn snapshot(&self) -> Result<VcpuSnapshot> {
+        let vcpu = &self.vcpu;
+        let mut msrs = Msrs::from_entries(&SNAPSHOT_MSRS.map(|index| kvm_msr_entry {
+            index,
+            ..Default::default()
+        }))
+        .map_err(|e| anyhow!("Failed to build MSR list: {:?}", e))?;
+        // Stops at the first MSR KVM does not know
+        let read = vcpu.get_msrs(&mut msrs).context("Failed to get MSRs")?;
+
+        Ok(VcpuSnapshot {
+            id: self.id,
+            regs: vcpu.get_regs().context("Failed to get registers")?,
+            sregs: vcpu
+                .get_sregs()
+                .context("Failed to get special registers")?,
+            fpu: vcpu.get_fpu().context("Failed to get FPU state")?,
+            xsave: vcpu.get_xsave().context("Failed to get XSAVE state")?,
+            xcrs: vcpu.get_xcrs().context("Failed to get XCRs")?,
+            debug_regs: vcpu
+                .get_debug_regs()
+                .context("Failed to get debug registers")?,
+            // Only fails when the VM has no in-kernel local APIC
+            lapic: vcpu.get_lapic().ok(),
+            mp_state: vcpu.get_mp_state().context("Failed to get MP state")?,
+            vcpu_events: vcpu
+                .get_vcpu_events()
+                .context("Failed to get vCPU events")?,
+            msrs: msrs.as_slice()[..read].to_vec(),
+        })
+    }
+
+    /// Load state captured by `snapshot`, possibly on another host
+    ///
+    /// CPUID must already be set. The vCPU is left paused if it was.
+    pub fn restore(&mut self, snapshot: &VcpuSnapshot) -> Result<()> {
+        let vcpu = &self.vcpu;
+        vcpu.set_mp_state(snapshot.mp_state)
+            .context("Failed to set MP state")?;
+        vcpu.set_regs(&snapshot.regs)
+            .context("Failed to set registers")?;
+        vcpu.set_sregs(&snapshot.sregs)
+            .context("Failed to set special registers")?;
+        // SAFETY: a legacy `kvm_xsave` as returned by KVM_GET_XSAVE, which
+        // KVM_SET_XSAVE reads in full
+        unsafe { vcpu.set_xsave(&snapshot.xsave) }.context("Failed to set XSAVE state")?;
+        // After XSAVE, which would reset x87 and SSE state its header does
+        // not mark present (see `VcpuSnapshot::fpu`)
+        vcpu.set_fpu(&snapshot.fpu)
+            .context("Failed to set FPU state")?;
+        vcpu.set_xcrs(&snapshot.xcrs)
+            .context("Failed to set XCRs")?;
+        vcpu.set_debug_regs(&snapshot.debug_regs)
+            .context("Failed to set debug registers")?;
+        if let Some(lapic) = &snapshot.lapic {
+            vcpu.set_lapic(lapic).context("Failed to set local APIC")?;
+        }
+        let msrs = Msrs::from_entries(&snapshot.msrs)
+            .map_err(|e| anyhow!("Failed to build MSR list: {:?}", e))?;
+        let written = vcpu.set_msrs(&msrs).context("Failed to set MSRs")?;
+        if written != snapshot.msrs.len() {
+            return Err(anyhow!(
+                "Only {} of {} MSRs restored",
+                written,
+                snapshot.msrs.len()
+            ));
+        }
+        vcpu.set_vcpu_events(&snapshot.vcpu_events)
+            .context("Failed to set vCPU events")?;
+        Ok(())
+    }
+
+    /// Switch the local APIC timer to `mode`, keeping its vector and mask
+    ///
+    /// `Deadline` cuts timer exits to the ones the guest asks for (see
+    /// `LapicTimerMode`); it needs an in-kernel irqchip and CPUID
+    /// advertising the TSC-deadline timer.
+    pub fn set_interrupt_coalescing(&mut self, lapic_timer_mode: LapicTimerMode) -> Result<()> {
+        let mut lapic = self.vcpu.get_lapic().context("Failed to get local APIC")?;
+        let lvtt = apic_register(&lapic, APIC_LVTT);
+        set_apic_register(
+            &mut lapic,
+            APIC_LVTT,
+            (lvtt & !APIC_LVTT_MODE_MASK) | lapic_timer_mode.lvtt_bits(),
+        );
+        self.vcpu
+            .set_lapic(&lapic)
+            .context("Failed to set local APIC")?;
+        info!(
+            "vCPU {} local APIC timer in {:?} mode",
+            self.id, lapic_timer_mode
+        );
+        Ok(())
+    }
+
+    /// Current local APIC timer mode
+    pub fn lapic_timer_mode(&self) -> Result<LapicTimerMode> {
+        let lapic = self.vcpu.get_lapic().context("Failed to get local APIC")?;
+        let lvtt = apic_register(&lapic, APIC_LVTT);
+        LapicTimerMode::from_lvtt(lvtt)
+            .ok_or_else(|| anyhow!("Reserved LVT timer mode in 0x{:x}", lvtt))
+    }
+
+    /// Arm the TSC-deadline timer to fire at guest TSC `deadline_tsc`
+    /// (0 disarms it)
+    ///
+    /// Only takes effect in `LapicTimerMode::Deadline`.
+    pub fn set_tsc_deadline_timer(&mut self, deadline_tsc: u64) -> Result<()> {
+        let msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: MSR_IA32_TSC_DEADLINE,
+            data: deadline_tsc,
+            ..Default::default()
+        }])
+        .map_err(|e| anyhow!("Failed to build MSR list: {:?}", e))?;
+        let written = self
+            .vcpu
+            .set_msrs(&msrs)
+            .context("Failed to set IA32_TSC_DEADLINE")?;
+        if written != 1 {
+            return Err(anyhow!("KVM rejected IA32_TSC_DEADLINE"));
+        }
         Ok(())
     }
+
+    pub fn exit_stats(&self) -> VcpuExitStats {
+        self.exit_stats
+    }
+
+    /// Run the guest until it halts or shuts down
+    ///
+    /// Port I/O and MMIO exits go to `devices`. `gate` is held while in
+    /// `KVM_RUN` and handling its exit, so `VcpuGate::pause` keeps this vCPU
+    /// out of the guest.
+    pub fn run(&mut self, gate: &VcpuGate, devices: &Mutex<dyn DeviceManager>) -> Result<()> {
+        info!("vCPU {} run loop starting", self.id);
+        let stats = &mut self.exit_stats;
+        loop {
+            let in_guest = gate.enter();
+            let exit = match self.vcpu.run() {
+                Ok(exit) => exit,
+                // A signal, or `pause`: come back once the caller is done
+                Err(e) if e.errno() == libc::EINTR || e.errno() == libc::EAGAIN => {
+                    stats.other += 1;
+                    drop(in_guest);
+                    std::thread::yield_now();
+                    continue;
+                }
+                Err(e) => return Err(e).with_context(|| format!("vCPU {} KVM_RUN", self.id)),
+            };
+            match exit {
+                VcpuExit::Hlt => {
+                    stats.hlt += 1;
+                    info!("vCPU {} halted", self.id);
+                    return Ok(());
+                }
+                VcpuExit::Shutdown => {
+                    stats.shutdown += 1;
+                    info!("vCPU {} shut down", self.id);
+                    return Ok(());
+                }
+                VcpuExit::IoIn(port, data) => {
+                    stats.io_in += 1;
+                    let value = devices.lock().handle_io_in(port);
+                    set_exit_value(data, u64::from(value));
+                }
+                VcpuExit::IoOut(port, data) => {
+                    stats.io_out += 1;
+                    devices
+                        .lock()
+                        .handle_io_out(port, exit_value(data) as u32, data.len());
+                }
+                VcpuExit::MmioRead(addr, data) => {
+                    stats.mmio_read += 1;
+                    let value = devices.lock().handle_mmio_read(addr, data.len());
+                    set_exit_value(data, value);
+                }
+                VcpuExit::MmioWrite(addr, data) => {
+                    stats.mmio_write += 1;
+                    devices
+                        .lock()
+                        .handle_mmio_write(addr, exit_value(data), data.len());
+                }
+                VcpuExit::FailEntry(reason, cpu) => {
+                    stats.other += 1;
+                    return Err(anyhow!(
+                        "vCPU {} failed to enter the guest: reason 0x{:x} on host CPU {}",
+                        self.id,
+                        reason,
+                        cpu
+                    ));
+                }
+                VcpuExit::InternalError => {
+                    stats.other += 1;
+                    return Err(anyhow!("vCPU {}: KVM internal error", self.id));
+                }
+                exit => {
+                    stats.other += 1;
+                    debug!("vCPU {} unhandled exit: {:?}", self.id, exit);
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     // Note: VcpuManager tests require actual KVM file descriptor
     // These are integration-level tests that need KVM access
+    use super::*;
+    use kvm_ioctls::Kvm;
 
     #[test]
     fn test_vcpu_manager_id() {
         // This test verifies the structure exists and can be compiled
         // Actual instantiation requires KVM
     }
+
+    #[test]
+    fn test_lapic_timer_mode_set() {
+        // Needs /dev/kvm
+        let Ok(kvm) = Kvm::new() else {
+            return;
+        };
+        let vm = kvm.create_vm().unwrap();
+        vm.create_irq_chip().unwrap();
+        let vcpu = vm.create_vcpu(0).unwrap();
+        vcpu.set_cpuid2(&kvm.get_supported_cpuid(256).unwrap())
+            .unwrap();
+        let mut manager = VcpuManager::new(vcpu, 0);
+        assert_eq!(manager.lapic_timer_mode().unwrap(), LapicTimerMode::Oneshot);
+        let lvtt = apic_register(&manager.vcpu.get_lapic().unwrap(), APIC_LVTT);
+
+        manager
+            .set_interrupt_coalescing(LapicTimerMode::Periodic)
+            .unwrap();
+        assert_eq!(
+            manager.lapic_timer_mode().unwrap(),
+            LapicTimerMode::Periodic
+        );
+
+        manager
+            .set_interrupt_coalescing(LapicTimerMode::Deadline)
+            .unwrap();
+        let lapic = manager.vcpu.get_lapic().unwrap();
+        let deadline_lvtt = apic_register(&lapic, APIC_LVTT);
+        assert_eq!(deadline_lvtt & APIC_LVTT_MODE_MASK, 0b10 << 17);
+        // Vector and mask are kept
+        assert_eq!(deadline_lvtt & !APIC_LVTT_MODE_MASK, lvtt);
+
+        let deadline = 0x1234_5678_9abc;
+        manager.set_tsc_deadline_timer(deadline).unwrap();
+        let mut msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: MSR_IA32_TSC_DEADLINE,
+            ..Default::default()
+        }])
+        .unwrap();
+        assert_eq!(manager.vcpu.get_msrs(&mut msrs).unwrap(), 1);
+        assert_eq!(msrs.as_slice()[0].data, deadline);
+    }
+
+    /// Remembers what the guest did; port reads return `0x5a`, MMIO reads
+    /// `0x1234`
+    #[derive(Default)]
+    struct RecordingDevices {
+        io_out: Vec<(u16, u32, usize)>,
+        mmio_write: Vec<(u64, u64, usize)>,
+    }
+
+    impl DeviceManager for RecordingDevices {
+        fn handle_io_in(&mut self, _port: u16) -> u32 {
+            0x5a
+        }
+
+        fn handle_io_out(&mut self, port: u16, data: u32, len: usize) {
+            self.io_out.push((port, data, len));
+        }
+
+        fn handle_mmio_read(&mut self, _addr: u64, _len: usize) -> u64 {
+            0x1234
+        }
+
+        fn handle_mmio_write(&mut self, addr: u64, data: u64, len: usize) {
+            self.mmio_write.push((addr, data, len));
+        }
+    }
+
+    #[test]
+    fn test_run_dispatches_exits_until_hlt() {
+        const CODE_GPA: u64 = 0x1000;
+        const MEM_SIZE: usize = 0x1000;
+        // Needs /dev/kvm
+        let Ok(kvm) = Kvm::new() else {
+            return;
+        };
+        let vm = kvm.create_vm().unwrap();
+        let hva = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                MEM_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(hva, libc::MAP_FAILED);
+        // mov dx, 0x3f8; mov al, 'A'; out dx, al; in al, dx; out dx, al;
+        // mov ax, [0x8000]; mov [0x8002], ax; hlt
+        // Only the code page is memory, so 0x8000 is MMIO
+        let code = [
+            0xba, 0xf8, 0x03, 0xb0, 0x41, 0xee, 0xec, 0xee, 0xa1, 0x00, 0x80, 0xa3, 0x02, 0x80,
+            0xf4,
+        ];
+        unsafe {
+            std::ptr::copy_nonoverlapping(code.as_ptr(), hva as *mut u8, code.len());
+            vm.set_user_memory_region(kvm_bindings::kvm_userspace_memory_region {
+                slot: 0,
+                guest_phys_addr: CODE_GPA,
+                memory_size: MEM_SIZE as u64,
+                userspace_addr: hva as u64,
+                flags: 0,
+            })
+            .unwrap();
+        }
+
+        let vcpu = vm.create_vcpu(0).unwrap();
+        let mut sregs = vcpu.get_sregs().unwrap();
+        sregs.cs.base = 0;
+        sregs.cs.selector = 0;
+        vcpu.set_sregs(&sregs).unwrap();
+        let mut regs = vcpu.get_regs().unwrap();
+        regs.rip = CODE_GPA;
+        regs.rflags = 0x2;
+        vcpu.set_regs(&regs).unwrap();
+
+        let mut manager = VcpuManager::new(vcpu, 0);
+        let devices = Mutex::new(RecordingDevices::default());
+        manager.run(&VcpuGate::default(), &devices).unwrap();
+        unsafe { libc::munmap(hva, MEM_SIZE) };
+
+        let devices = devices.into_inner();
+        assert_eq!(devices.io_out, [(0x3f8, 0x41, 1), (0x3f8, 0x5a, 1)]);
+        assert_eq!(devices.mmio_write, [(0x8002, 0x1234, 2)]);
+        assert_eq!(
+            manager.exit_stats(),
+            VcpuExitStats {
+                hlt: 1,
+                io_in: 1,
+                io_out: 2,
+                mmio_read: 1,
+                mmio_write: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_fpu_state_round_trip() {
+        // Needs /dev/kvm
+        let Ok(kvm) = Kvm::new() else {
+            return;
+        };
+        let cpuid = kvm.get_supported_cpuid(256).unwrap();
+        let new_vcpu = |vm: &kvm_ioctls::VmFd| {
+            vm.create_irq_chip().unwrap();
+            let vcpu = vm.create_vcpu(0).unwrap();
+            vcpu.set_cpuid2(&cpuid).unwrap();
+            VcpuManager::new(vcpu, 0)
+        };
+        let source_vm = kvm.create_vm().unwrap();
+        let source = new_vcpu(&source_vm);
+        assert!(!source.snapshot().unwrap().requires_xsave());
+
+        let xmm0: [u8; 16] = std::array::from_fn(|i| 0xa0 + i as u8);
+        let mut fpu = source.vcpu.get_fpu().unwrap();
+        fpu.xmm[0] = xmm0;
+        source.vcpu.set_fpu(&fpu).unwrap();
+        let snapshot = source.snapshot().unwrap();
+        assert_eq!(snapshot.fpu.xmm[0], xmm0);
+        assert!(snapshot.requires_xsave());
+
+        let bytes = bincode::serialize(&snapshot).unwrap();
+        // The compressed XSAVE area leaves the snapshot smaller than it
+        assert!(bytes.len() < std::mem::size_of::<kvm_xsave>());
+        let received: VcpuSnapshot = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(received.xsave.region, snapshot.xsave.region);
+
+        let target_vm = kvm.create_vm().unwrap();
+        let mut target = new_vcpu(&target_vm);
+        target.restore(&received).unwrap();
+        assert_eq!(target.vcpu.get_fpu().unwrap().xmm[0], xmm0);
+        assert_eq!(
+            target.vcpu.get_xsave().unwrap().region,
+            snapshot.xsave.region
+        );
+    }
 }
@@ -0,0 +1,423 @@
+//! VFIO PCI device passthrough
+//!
+//! VFIO hands a physical PCI device (typically a NIC) to userspace. The device
+//! belongs to an IOMMU group (`/dev/vfio/{group}`), which is attached to a
+//! container (`/dev/vfio/vfio`) holding the IOMMU page tables. From the group
+//! the VMM obtains a device fd, whose regions are the device's BARs.
+//!
+//! Passthrough has two halves:
+//! - guest -> device: mappable BARs are `mmap`ed from the device fd and
+//!   installed as KVM memory slots, so guest MMIO goes straight to hardware
+//! - device -> guest: guest RAM is mapped into the IOMMU with IOVA = GPA
+//!   (`VFIO_IOMMU_MAP_DMA`), so the guest driver can program guest physical
+//!   addresses into the device
+//!
+//! DMA mapping pins all of guest memory, which faults it in and defeats the
+//! pager's demand paging for that VM. BARs that cannot be mapped (e.g. the
+//! MSI-X table on some devices) would need trapped MMIO emulation, which the
+//! VMM does not have yet; they are skipped.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::Mode;
+use std::ffi::CString;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd};
+
+/// Guest physical address where passthrough BARs are placed
+///
+/// Well above any guest RAM the VMM hands out, so BARs never collide with
+/// memory slots.
+pub const VFIO_MMIO_BASE: u64 = 1 << 40;
+
+const VFIO_CONTAINER: &str = "/dev/vfio/vfio";
+const VFIO_API_VERSION: i32 = 0;
+const VFIO_TYPE1V2_IOMMU: libc::c_ulong = 3;
+
+/// `_IO(VFIO_TYPE, VFIO_BASE + n)` with `VFIO_TYPE = ';'`, `VFIO_BASE = 100`
+const fn vfio_ioctl(n: libc::c_ulong) -> libc::c_ulong {
+    (b';' as libc::c_ulong) << 8 | (100 + n)
+}
+
+const VFIO_GET_API_VERSION: libc::c_ulong = vfio_ioctl(0);
+const VFIO_CHECK_EXTENSION: libc::c_ulong = vfio_ioctl(1);
+const VFIO_SET_IOMMU: libc::c_ulong = vfio_ioctl(2);
+const VFIO_GROUP_GET_STATUS: libc::c_ulong = vfio_ioctl(3);
+const VFIO_GROUP_SET_CONTAINER: libc::c_ulong = vfio_ioctl(4);
+const VFIO_GROUP_GET_DEVICE_FD: libc::c_ulong = vfio_ioctl(6);
+const VFIO_DEVICE_GET_INFO: libc::c_ulong = vfio_ioctl(7);
+const VFIO_DEVICE_GET_REGION_INFO: libc::c_ulong = vfio_ioctl(8);
+const VFIO_IOMMU_MAP_DMA: libc::c_ulong = vfio_ioctl(13);
+
+const VFIO_GROUP_FLAGS_VIABLE: u32 = 1 << 0;
+const VFIO_DEVICE_FLAGS_PCI: u32 = 1 << 1;
+const VFIO_REGION_INFO_FLAG_MMAP: u32 = 1 << 2;
+const VFIO_DMA_MAP_FLAG_READ: u32 = 1 << 0;
+const VFIO_DMA_MAP_FLAG_WRITE: u32 = 1 << 1;
+
+/// Region indices 0-5 of a PCI device are BAR0-BAR5
+const PCI_BAR_COUNT: u32 = 6;
+
+#[repr(C)]
+#[derive(Default)]
+struct VfioGroupStatus {
+    argsz: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VfioDeviceInfo {
+    argsz: u32,
+    flags: u32,
+    num_regions: u32,
+    num_irqs: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VfioRegionInfo {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    cap_offset: u32,
+    size: u64,
+    offset: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct VfioDmaMap {
+    argsz: u32,
+    flags: u32,
+    vaddr: u64,
+    iova: u64,
+    size: u64,
+}
+
+/// A PCI device to pass through to the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfioDeviceConfig {
+    /// IOMMU group number (`/dev/vfio/{group}`)
+    pub group: u32,
+    /// PCI address, e.g. `0000:01:00.0`
+    pub pci_addr: String,
+}
+
+fn ioctl_ptr<T>(file: &File, request: libc::c_ulong, arg: &mut T) -> std::io::Result<i32> {
+    // SAFETY: `arg` is a live, correctly sized VFIO argument struct
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request, arg as *mut T) };
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn ioctl_val(file: &File, request: libc::c_ulong, arg: libc::c_ulong) -> std::io::Result<i32> {
+    // SAFETY: these VFIO ioctls take a plain integer argument
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request, arg) };
+    if ret < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn open_rdwr(path: &str) -> Result<File> {
+    let fd = fcntl::open(path, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())
+        .with_context(|| format!("Failed to open {}", path))?;
+    // SAFETY: `fd` was just opened and is owned by nothing else
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// VFIO container: the IOMMU context shared by all passthrough devices
+#[derive(Debug)]
+pub struct VfioContainer {
+    file: File,
+    iommu_set: bool,
+}
+
+impl VfioContainer {
+    pub fn open() -> Result<Self> {
+        let file = open_rdwr(VFIO_CONTAINER)?;
+
+        let version =
+            ioctl_val(&file, VFIO_GET_API_VERSION, 0).context("VFIO_GET_API_VERSION failed")?;
+        if version != VFIO_API_VERSION {
+            return Err(anyhow!("Unsupported VFIO API version {}", version));
+        }
+        if ioctl_val(&file, VFIO_CHECK_EXTENSION, VFIO_TYPE1V2_IOMMU).unwrap_or(0) != 1 {
+            return Err(anyhow!("VFIO type1v2 IOMMU not supported"));
+        }
+
+        Ok(Self {
+            file,
+            iommu_set: false,
+        })
+    }
+
+    /// Attach an IOMMU group; the first one also selects the IOMMU model
+    fn attach(&mut self, group: &File) -> Result<()> {
+        let mut container_fd = self.file.as_raw_fd();
+        ioctl_ptr(group, VFIO_GROUP_SET_CONTAINER, &mut container_fd)
+            .context("VFIO_GROUP_SET_CONTAINER failed")?;
+
+        if !self.iommu_set {
+            ioctl_val(&self.file, VFIO_SET_IOMMU, VFIO_TYPE1V2_IOMMU)
+                .context("VFIO_SET_IOMMU failed")?;
+            self.iommu_set = true;
+        }
+        Ok(())
+    }
+
+    /// Let devices DMA to `iova..iova+size`, backed by host memory at `vaddr`
+    ///
+    /// Only valid once a group is attached.
+    pub fn map_dma(&self, iova: u64, vaddr: u64, size: usize) -> Result<()> {
+        if !self.iommu_set {
+            return Err(anyhow!("No VFIO group attached to the container"));
+        }
+        let mut map = VfioDmaMap {
+            argsz: std::mem::size_of::<VfioDmaMap>() as u32,
+            flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+            vaddr,
+            iova,
+            size: size as u64,
+        };
+        ioctl_ptr(&self.file, VFIO_IOMMU_MAP_DMA, &mut map)
+            .with_context(|| format!("VFIO_IOMMU_MAP_DMA of IOVA 0x{:x} failed", iova))?;
+        Ok(())
+    }
+}
+
+/// A BAR mapped into the VMM; unmapped when the device is dropped
+#[derive(Debug)]
+pub struct VfioBar {
+    /// BAR number (0-5)
+    pub index: u32,
+    /// Host address of the mapping
+    pub hva: u64,
+    pub size: usize,
+}
+
+/// An opened VFIO PCI device with its mappable BARs
+#[derive(Debug)]
+pub struct VfioDevice {
+    config: VfioDeviceConfig,
+    // Held open so the group stays attached to the container
+    _group: File,
+    device: File,
+    bars: Vec<VfioBar>,
+}
+
+impl VfioDevice {
+    /// Open `config.pci_addr` from its group and map its BARs
+    pub fn open(container: &mut VfioContainer, config: &VfioDeviceConfig) -> Result<Self> {
+        let group = open_rdwr(&format!("/dev/vfio/{}", config.group))?;
+
+        let mut status = VfioGroupStatus {
+            argsz: std::mem::size_of::<VfioGroupStatus>() as u32,
+            ..Default::default()
+        };
+        ioctl_ptr(&group, VFIO_GROUP_GET_STATUS, &mut status)
+            .context("VFIO_GROUP_GET_STATUS failed")?;
+        if status.flags & VFIO_GROUP_FLAGS_VIABLE == 0 {
+            return Err(anyhow!(
+                "VFIO group {} is not viable (are all its devices bound to vfio-pci?)",
+                config.group
+            ));
+        }
+        container.attach(&group)?;
+
+        let name = CString::new(config.pci_addr.as_str())
+            .with_context(|| format!("Invalid PCI address {:?}", config.pci_addr))?;
+        // SAFETY: `name` is a NUL-terminated string that outlives the call
+        let fd = unsafe { libc::ioctl(group.as_raw_fd(), VFIO_GROUP_GET_DEVICE_FD, name.as_ptr()) };
+        if fd < 0 {
+            return Err(anyhow!(
+                "Failed to get VFIO device {} from group {}: {}",
+                config.pci_addr,
+                config.group,
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: the kernel returned a new fd we now own
+        let device = unsafe { File::from_raw_fd(fd) };
+
+        let mut info = VfioDeviceInfo {
+            argsz: std::mem::size_of::<VfioDeviceInfo>() as u32,
+            ..Default::default()
+        };
+        ioctl_ptr(&device, VFIO_DEVICE_GET_INFO, &mut info)
+            .context("VFIO_DEVICE_GET_INFO failed")?;
+        if info.flags & VFIO_DEVICE_FLAGS_PCI == 0 {
+            return Err(anyhow!("{} is not a PCI device", config.pci_addr));
+        }
+
+        let mut vfio = Self {
+            config: config.clone(),
+            _group: group,
+            device,
+            bars: Vec::new(),
+        };
+        for index in 0..info.num_regions.min(PCI_BAR_COUNT) {
+            vfio.map_bar(index)?;
+        }
+
+        info!(
+            "Opened VFIO device {} (group {}): {} regions, {} IRQs, {} mapped BARs",
+            config.pci_addr,
+            config.group,
+            info.num_regions,
+            info.num_irqs,
+            vfio.bars.len()
+        );
+        Ok(vfio)
+    }
+
+    pub fn config(&self) -> &VfioDeviceConfig {
+        &self.config
+    }
+
+    /// Mapped BARs, in BAR order
+    pub fn bars(&self) -> &[VfioBar] {
+        &self.bars
+    }
+
+    fn map_bar(&mut self, index: u32) -> Result<()> {
+        let mut region = VfioRegionInfo {
+            argsz: std::mem::size_of::<VfioRegionInfo>() as u32,
+            index,
+            ..Default::default()
+        };
+        ioctl_ptr(&self.device, VFIO_DEVICE_GET_REGION_INFO, &mut region)
+            .with_context(|| format!("VFIO_DEVICE_GET_REGION_INFO for BAR{} failed", index))?;
+
+        if region.size == 0 {
+            return Ok(());
+        }
+        if region.flags & VFIO_REGION_INFO_FLAG_MMAP == 0 {
+            warn!(
+                "{} BAR{} is not mappable; guest accesses to it are not handled",
+                self.config.pci_addr, index
+            );
+            return Ok(());
+        }
+
+        let size = region.size as usize;
+        // SAFETY: maps `size` bytes of the device fd at the offset VFIO gave us
+        let hva = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.device.as_raw_fd(),
+                region.offset as libc::off_t,
+            )
+        };
+        if hva == libc::MAP_FAILED {
+            return Err(anyhow!(
+                "Failed to mmap {} BAR{}: {}",
+                self.config.pci_addr,
+                index,
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        self.bars.push(VfioBar {
+            index,
+            hva: hva as u64,
+            size,
+        });
+        Ok(())
+    }
+}
+
+impl Drop for VfioDevice {
+    fn drop(&mut self) {
+        for bar in &self.bars {
+            // SAFETY: we mapped this range in `map_bar`
+            unsafe { libc::munmap(bar.hva as *mut libc::c_void, bar.size) };
+        }
+    }
+}
+
+/// Guest addresses for BARs of the given sizes, packed upward from `base`
+///
+/// BAR sizes are powers of two and each BAR is naturally aligned, as PCI
+/// requires.
+pub fn place_bars(base: u64, sizes: &[usize]) -> Vec<u64> {
+    let mut next = base;
+    sizes
+        .iter()
+        .map(|&size| {
+            let gpa = next.next_multiple_of(size as u64);
+            next = gpa + size as u64;
+            gpa
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ioctl_numbers() {
+        // From <linux/vfio.h>
+        assert_eq!(VFIO_GET_API_VERSION, 0x3b64);
+        assert_eq!(VFIO_GROUP_GET_DEVICE_FD, 0x3b6a);
+        assert_eq!(VFIO_DEVICE_GET_INFO, 0x3b6b);
+        assert_eq!(VFIO_IOMMU_MAP_DMA, 0x3b71);
+        assert_eq!(std::mem::size_of::<VfioRegionInfo>(), 32);
+        assert_eq!(std::mem::size_of::<VfioDmaMap>(), 32);
+    }
+
+    #[test]
+    fn test_place_bars_aligns_naturally() {
+        let gpas = place_bars(VFIO_MMIO_BASE, &[0x4000, 0x100_0000, 0x1000]);
+        assert_eq!(
+            gpas,
+            vec![
+                VFIO_MMIO_BASE,
+                VFIO_MMIO_BASE + 0x100_0000,
+                VFIO_MMIO_BASE + 0x200_0000
+            ]
+        );
+    }
+
+    /// First `(group, pci_addr)` bound to vfio-pci on this host, if any
+    fn find_vfio_device() -> Option<VfioDeviceConfig> {
+        for entry in std::fs::read_dir("/dev/vfio").ok()?.flatten() {
+            let Ok(group) = entry.file_name().to_string_lossy().parse::<u32>() else {
+                continue;
+            };
+            let devices = format!("/sys/kernel/iommu_groups/{}/devices", group);
+            if let Some(device) = std::fs::read_dir(devices).ok()?.flatten().next() {
+                return Some(VfioDeviceConfig {
+                    group,
+                    pci_addr: device.file_name().to_string_lossy().into_owned(),
+                });
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_open_device() {
+        // Needs a device bound to vfio-pci
+        let Some(config) = find_vfio_device() else {
+            return;
+        };
+        let Ok(mut container) = VfioContainer::open() else {
+            return;
+        };
+
+        let device = VfioDevice::open(&mut container, &config).unwrap();
+        assert_eq!(device.config(), &config);
+        assert!(device.bars().iter().all(|bar| bar.index < PCI_BAR_COUNT));
+    }
+}